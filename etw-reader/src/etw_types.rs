@@ -21,6 +21,14 @@ impl Deref for EventRecord {
 }
 
 impl EventRecord {
+    /// A copy of the record which claims to come from `provider`, for looking up
+    /// the schema of an event whose provider is an alias of another provider.
+    pub(crate) fn with_provider_id(&self, provider: GUID) -> EventRecord {
+        let mut record = self.0;
+        record.EventHeader.ProviderId = provider;
+        EventRecord(record)
+    }
+
     pub(crate) fn user_buffer(&self) -> &[u8] {
         if self.UserData == std::ptr::null_mut() {
             return &[];
@@ -18,6 +18,7 @@ extern crate memoffset;
 
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt::Write as _;
 use std::hash::BuildHasherDefault;
 use std::mem;
 use std::path::Path;
@@ -326,9 +327,40 @@ pub fn event_properties_to_string(
     s: &schema::TypedEvent,
     parser: &mut Parser,
     skip_properties: Option<&[&str]>,
+) -> String {
+    event_properties_to_string_with_limits(s, parser, skip_properties, &PropertyStringLimits::NONE)
+}
+
+/// Size limits for [`event_properties_to_string_with_limits`], in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PropertyStringLimits {
+    /// Longer property values are cut off, followed by e.g. "…[+1234 bytes]".
+    pub max_value_len: Option<usize>,
+    /// Once the text is this long, the remaining properties are left out and
+    /// replaced with e.g. "…[+3 properties]".
+    pub max_total_len: Option<usize>,
+}
+
+impl PropertyStringLimits {
+    pub const NONE: Self = Self {
+        max_value_len: None,
+        max_total_len: None,
+    };
+}
+
+/// Like [`event_properties_to_string`], but long values and long property
+/// lists are truncated, so that chatty providers with large payloads don't
+/// blow up the size of the profile.
+pub fn event_properties_to_string_with_limits(
+    s: &schema::TypedEvent,
+    parser: &mut Parser,
+    skip_properties: Option<&[&str]>,
+    limits: &PropertyStringLimits,
 ) -> String {
     let mut text = String::new();
-    for i in 0..s.property_count() {
+    let mut property_text = String::new();
+    let property_count = s.property_count();
+    for i in 0..property_count {
         let property = s.property(i);
         if let Some(propfilter) = skip_properties {
             if propfilter.iter().any(|&s| s == property.name) {
@@ -336,13 +368,42 @@ pub fn event_properties_to_string(
             }
         }
 
-        write_property(&mut text, parser, &property, false);
+        if let Some(max_total_len) = limits.max_total_len {
+            if text.len() >= max_total_len {
+                write!(text, "…[+{} properties]", property_count - i).unwrap();
+                break;
+            }
+        }
+
+        property_text.clear();
+        write_property(&mut property_text, parser, &property, false);
+        if let Some(max_value_len) = limits.max_value_len {
+            // write_property writes "  {name}= " before the value.
+            let value_start = property.name.len() + 4;
+            truncate_with_note(&mut property_text, value_start + max_value_len);
+        }
+        text += &property_text;
         text += ", "
     }
 
     text
 }
 
+/// Cuts `text` off after `max_len` bytes, at a char boundary, and appends a
+/// note with the number of bytes which were removed.
+fn truncate_with_note(text: &mut String, max_len: usize) {
+    if text.len() <= max_len {
+        return;
+    }
+    let mut end = max_len;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let removed_len = text.len() - end;
+    text.truncate(end);
+    write!(text, "…[+{removed_len} bytes]").unwrap();
+}
+
 pub fn write_property(
     output: &mut dyn std::fmt::Write,
     parser: &mut Parser,
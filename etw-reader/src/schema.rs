@@ -116,6 +116,7 @@ impl SchemaKey {
 pub struct SchemaLocator {
     schemas: FastHashMap<SchemaKey, Rc<Schema>>,
     tracelogging_providers: FastHashMap<GUID, TraceLoggingProviderIds>,
+    provider_aliases: FastHashMap<GUID, GUID>,
 }
 
 pub trait EventSchema {
@@ -152,9 +153,19 @@ impl SchemaLocator {
         SchemaLocator {
             schemas: FastHashMap::default(),
             tracelogging_providers: FastHashMap::default(),
+            provider_aliases: FastHashMap::default(),
         }
     }
 
+    /// Decodes the events of `provider` with the schemas of `canonical_provider`.
+    ///
+    /// Some collectors log the events of a well-known provider, e.g. the kernel's
+    /// PerfInfo events, under a GUID of their own. With an alias, these events get
+    /// the same schema, and therefore the same name, as the original events.
+    pub fn add_provider_alias(&mut self, provider: GUID, canonical_provider: GUID) {
+        self.provider_aliases.insert(provider, canonical_provider);
+    }
+
     pub fn add_custom_schema(&mut self, schema: Box<dyn EventSchema>) {
         let key = SchemaKey {
             provider: schema.provider_guid(),
@@ -185,11 +196,19 @@ impl SchemaLocator {
     /// };
     /// ```
     pub fn event_schema<'a>(&mut self, event: &'a EventRecord) -> SchemaResult<TypedEvent<'a>> {
-        let key = SchemaKey::new(event, self);
+        let aliased_record;
+        let schema_record = match self.provider_aliases.get(&event.EventHeader.ProviderId) {
+            Some(&canonical_provider) => {
+                aliased_record = event.with_provider_id(canonical_provider);
+                &aliased_record
+            }
+            None => event,
+        };
+        let key = SchemaKey::new(schema_record, self);
         let info = match self.schemas.entry(key) {
             Entry::Occupied(entry) => entry.into_mut(),
             Entry::Vacant(entry) => {
-                let info = Box::new(tdh::schema_from_tdh(event)?);
+                let info = Box::new(tdh::schema_from_tdh(schema_record)?);
                 // dbg!(info.provider_guid(), info.provider_name(), info.decoding_source());
                 // TODO: Cloning for now, should be a reference at some point...
                 entry.insert(Rc::new(Schema::new(info)))
@@ -0,0 +1,214 @@
+#!/usr/bin/env python3
+"""Writes perf.data, a minimal simpleperf recording of an app with JIT code.
+
+The file has the layout of `simpleperf record` output on an arm64 device:
+the arch and META_INFO feature sections, a FILE2 section with the symbols
+of the JIT symfiles, and PERF_RECORD_MMAP2 records with simpleperf's
+PROT_JIT_SYMFILE_MAP bit for the JIT code. See README.md.
+"""
+
+import struct
+import sys
+
+PID = 1234
+
+APP_JIT_PATH = "/data/local/tmp/perf.data_jit_app_cache:4096-4608"
+ZYGOTE_JIT_PATH = "/data/local/tmp/perf.data_jit_zygote_cache:8192-8448"
+
+# The app cache symfile has two methods in one mapping.
+APP_JIT_START = 0x7100001000
+APP_JIT_SYMBOLS = [
+    (0x7100001000, 0x100, "int com.example.jit.Fib.fib(int)"),
+    (0x7100001100, 0x100, "void com.example.jit.Fib.run()"),
+]
+ZYGOTE_JIT_START = 0x7200002000
+ZYGOTE_JIT_SYMBOLS = [
+    (0x7200002000, 0x100, "void android.os.Looper.loop()"),
+]
+
+META_INFO = [
+    ("simpleperf_version", "1.build.11240384"),
+    ("system_wide_collection", "false"),
+    ("trace_offcpu", "false"),
+    ("event_type_info", "cpu-clock,1,0"),
+    ("product_props", "Google:Pixel 6:oriole"),
+    ("android_version", "14"),
+    ("android_build_fingerprint", "google/oriole/oriole:14/UQ1A.240205.002/11224170:user/release-keys"),
+    ("app_package_name", "com.example.jit"),
+    ("app_type", "debuggable"),
+    ("clockid", "monotonic"),
+    ("timestamp", "1700000000"),
+    ("kernel_version", "5.10.189-android13-4"),
+]
+
+PERF_TYPE_SOFTWARE = 1
+PERF_COUNT_SW_CPU_CLOCK = 0
+PERF_SAMPLE_IP = 1 << 0
+PERF_SAMPLE_TID = 1 << 1
+PERF_SAMPLE_TIME = 1 << 2
+PERF_SAMPLE_CALLCHAIN = 1 << 5
+ATTR_FLAG_MMAP = 1 << 8
+ATTR_FLAG_COMM = 1 << 9
+ATTR_FLAG_SAMPLE_ID_ALL = 1 << 18
+ATTR_FLAG_MMAP2 = 1 << 23
+
+PERF_RECORD_COMM = 3
+PERF_RECORD_SAMPLE = 9
+PERF_RECORD_MMAP2 = 10
+PERF_RECORD_MISC_USER = 2
+PERF_CONTEXT_USER = (1 << 64) - 512
+
+PROT_READ = 0x1
+PROT_EXEC = 0x4
+PROT_JIT_SYMFILE_MAP = 0x4000
+MAP_PRIVATE = 0x2
+
+HEADER_ARCH = 6
+FEAT_SIMPLEPERF_META_INFO = 129
+FEAT_SIMPLEPERF_FILE2 = 132
+
+DSO_ELF_FILE = 2
+
+
+def pad8(data):
+    return data + b"\0" * (-len(data) % 8)
+
+
+def record(record_type, misc, body):
+    return struct.pack("<IHH", record_type, misc, 8 + len(body)) + body
+
+
+def sample_id(time):
+    return struct.pack("<IIQ", PID, PID, time)
+
+
+def comm_record(name, time):
+    body = struct.pack("<II", PID, PID) + pad8(name.encode() + b"\0") + sample_id(time)
+    return record(PERF_RECORD_COMM, 0, body)
+
+
+def jit_mmap2_record(path, start, size, time):
+    body = struct.pack("<IIQQQIIQQII", PID, PID, start, size, 0, 0, 0, 0, 0,
+                       PROT_READ | PROT_EXEC | PROT_JIT_SYMFILE_MAP, MAP_PRIVATE)
+    body += pad8(path.encode() + b"\0") + sample_id(time)
+    return record(PERF_RECORD_MMAP2, PERF_RECORD_MISC_USER, body)
+
+
+def sample_record(callchain, time):
+    ips = [PERF_CONTEXT_USER] + callchain
+    body = struct.pack("<QIIQQ", callchain[0], PID, PID, time, len(ips))
+    body += b"".join(struct.pack("<Q", ip) for ip in ips)
+    return record(PERF_RECORD_SAMPLE, PERF_RECORD_MISC_USER, body)
+
+
+def varint(value):
+    out = b""
+    while True:
+        byte = value & 0x7f
+        value >>= 7
+        if value:
+            out += bytes([byte | 0x80])
+        else:
+            return out + bytes([byte])
+
+
+def proto_varint(field, value):
+    return varint(field << 3) + varint(value)
+
+
+def proto_bytes(field, data):
+    return varint(field << 3 | 2) + varint(len(data)) + data
+
+
+def file_feature(path, symbols):
+    # simpleperf's proto::FileFeature
+    msg = proto_bytes(1, path.encode())
+    msg += proto_varint(2, DSO_ELF_FILE)
+    for vaddr, size, name in symbols:
+        symbol = proto_varint(1, vaddr) + proto_varint(2, size) + proto_bytes(3, name.encode())
+        msg += proto_bytes(4, symbol)
+    msg += proto_bytes(6, b"")  # An ElfFile with file_offset_of_min_vaddr 0.
+    return struct.pack("<I", len(msg)) + msg
+
+
+def perf_header_string(s):
+    data = s.encode() + b"\0"
+    data += b"\0" * (-len(data) % 64)
+    return struct.pack("<I", len(data)) + data
+
+
+def perf_event_attr():
+    attr = struct.pack(
+        "<IIQQQQQIIQQQQIiQIHH",
+        PERF_TYPE_SOFTWARE,
+        112,  # PERF_ATTR_SIZE_VER5
+        PERF_COUNT_SW_CPU_CLOCK,
+        1_000_000,  # sample_period: 1ms
+        PERF_SAMPLE_IP | PERF_SAMPLE_TID | PERF_SAMPLE_TIME | PERF_SAMPLE_CALLCHAIN,
+        0,  # read_format
+        ATTR_FLAG_MMAP | ATTR_FLAG_COMM | ATTR_FLAG_SAMPLE_ID_ALL | ATTR_FLAG_MMAP2,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    )
+    assert len(attr) == 112
+    return attr
+
+
+def main(output_path):
+    fib = APP_JIT_SYMBOLS[0][0] + 0x10
+    run = APP_JIT_SYMBOLS[1][0] + 0x20
+    loop = ZYGOTE_JIT_SYMBOLS[0][0] + 0x40
+    # The addresses of the calls in the callers. simpleperf writes adjusted
+    # return addresses, which point into the call instruction.
+    run_call = APP_JIT_SYMBOLS[1][0] + 0x30
+    loop_call = ZYGOTE_JIT_SYMBOLS[0][0] + 0x50
+
+    records = [
+        comm_record("com.example.jit", 1_000_000),
+        jit_mmap2_record(APP_JIT_PATH, APP_JIT_START, 0x200, 1_000_000),
+        jit_mmap2_record(ZYGOTE_JIT_PATH, ZYGOTE_JIT_START, 0x100, 1_000_000),
+    ]
+    stacks = [[fib, run_call, loop_call]] * 3 + [[run, loop_call]] * 2 + [[loop]]
+    for i, stack in enumerate(stacks):
+        records.append(sample_record(stack, 2_000_000 + i * 1_000_000))
+    data = b"".join(records)
+
+    meta_info = b"".join(k.encode() + b"\0" + v.encode() + b"\0" for k, v in META_INFO)
+    file2 = file_feature(APP_JIT_PATH, APP_JIT_SYMBOLS)
+    file2 += file_feature(ZYGOTE_JIT_PATH, ZYGOTE_JIT_SYMBOLS)
+    features = [
+        (HEADER_ARCH, perf_header_string("arm64")),
+        (FEAT_SIMPLEPERF_META_INFO, meta_info),
+        (FEAT_SIMPLEPERF_FILE2, file2),
+    ]
+
+    header_size = 104
+    attr = perf_event_attr() + struct.pack("<QQ", 0, 0)  # no event ids
+    attrs_offset = header_size
+    data_offset = attrs_offset + len(attr)
+    feature_table_offset = data_offset + len(data)
+    feature_offset = feature_table_offset + 16 * len(features)
+    feature_table = b""
+    feature_contents = b""
+    feature_bits = [0, 0, 0, 0]
+    for feature, content in features:
+        feature_table += struct.pack("<QQ", feature_offset + len(feature_contents), len(content))
+        feature_contents += content
+        feature_bits[feature // 64] |= 1 << (feature % 64)
+
+    header = b"PERFILE2" + struct.pack(
+        "<QQQQQQQQ4Q",
+        header_size,
+        len(attr),
+        attrs_offset, len(attr),
+        data_offset, len(data),
+        0, 0,  # event_types
+        *feature_bits,
+    )
+    assert len(header) == header_size
+
+    with open(output_path, "wb") as f:
+        f.write(header + attr + data + feature_table + feature_contents)
+
+
+if __name__ == "__main__":
+    main(sys.argv[1] if len(sys.argv) > 1 else "perf.data")
@@ -22,6 +22,12 @@ impl From<Duration> for CpuDelta {
     }
 }
 
+impl From<CpuDelta> for Duration {
+    fn from(cpu_delta: CpuDelta) -> Self {
+        Duration::from_micros(cpu_delta.micros)
+    }
+}
+
 impl CpuDelta {
     /// A CPU delta of zero.
     pub const ZERO: Self = Self { micros: 0 };
@@ -51,6 +57,16 @@ impl CpuDelta {
     }
 }
 
+impl std::ops::Add for CpuDelta {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            micros: self.micros + other.micros,
+        }
+    }
+}
+
 impl Serialize for CpuDelta {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         // CPU deltas are serialized as float microseconds, because
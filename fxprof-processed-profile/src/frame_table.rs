@@ -100,6 +100,25 @@ impl FrameTable {
             })
     }
 
+    /// Returns the location of each frame, indexed by frame index.
+    pub fn frame_locations(&self) -> Vec<&InternalFrameLocation> {
+        let mut locations = vec![None; self.addresses.len()];
+        for (frame, frame_index) in &self.internal_frame_to_frame_index {
+            locations[*frame_index] = Some(&frame.location);
+        }
+        locations.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Changes the category of the frame at `frame_index`.
+    pub fn set_frame_category(&mut self, frame_index: usize, category_pair: CategoryPairHandle) {
+        let CategoryPairHandle(category, subcategory_index) = category_pair;
+        self.categories[frame_index] = category;
+        self.subcategories[frame_index] = match subcategory_index {
+            Some(index) => Subcategory::Normal(index),
+            None => Subcategory::Other(category),
+        };
+    }
+
     pub fn as_serializable<'a>(&'a self, categories: &'a [Category]) -> impl Serialize + 'a {
         SerializableFrameTable {
             table: self,
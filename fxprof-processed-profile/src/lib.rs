@@ -54,7 +54,9 @@ mod sample_table;
 mod serialization_helpers;
 mod stack_table;
 mod string_table;
+mod summary;
 mod thread;
+mod thread_order;
 mod thread_string_table;
 mod timestamp;
 
@@ -74,5 +76,7 @@ pub use markers::{
 pub use process::ThreadHandle;
 pub use profile::{Profile, SamplingInterval, StringHandle};
 pub use reference_timestamp::ReferenceTimestamp;
+pub use summary::{HotFrame, HotFrameLocation, ProcessSummary, ProfileSummary};
 pub use thread::ProcessHandle;
+pub use thread_order::ThreadOrder;
 pub use timestamp::*;
@@ -1,6 +1,7 @@
 use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
 
 use crate::markers::{InternalMarkerSchema, MarkerFieldFormatKind};
+use crate::sample_table::map_stack_indexes;
 use crate::serialization_helpers::SerializableOptionalTimestampColumn;
 use crate::string_table::{GlobalStringIndex, GlobalStringTable, StringIndex};
 use crate::thread_string_table::{ThreadInternalStringIndex, ThreadStringTable};
@@ -43,6 +44,10 @@ pub struct MarkerTable {
     /// https://github.com/firefox-devtools/profiler/issues/5022 tracks supporting string indexes
     /// for the other string format variants.
     marker_field_string_values: Vec<StringIndex>,
+    /// Whether the field values have been dropped with [`MarkerTable::clear_marker_fields`].
+    /// Once this is set, no field values are stored for any marker, including markers
+    /// which are added afterwards.
+    marker_fields_cleared: bool,
 }
 
 impl MarkerTable {
@@ -50,6 +55,10 @@ impl MarkerTable {
         Default::default()
     }
 
+    pub fn len(&self) -> usize {
+        self.marker_name_string_indexes.len()
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn add_marker<T: Marker>(
         &mut self,
@@ -75,6 +84,9 @@ impl MarkerTable {
         self.marker_phases.push(phase);
         self.marker_type_handles.push(marker_type_handle);
         self.marker_stacks.push(None);
+        if self.marker_fields_cleared {
+            return MarkerHandle(self.marker_categories.len() - 1);
+        }
         for (field_index, field) in schema.fields().iter().enumerate() {
             match field.format.kind() {
                 MarkerFieldFormatKind::String => {
@@ -104,6 +116,23 @@ impl MarkerTable {
         self.marker_stacks[marker.0] = stack_index;
     }
 
+    pub fn clear_marker_stacks(&mut self) {
+        self.marker_stacks.fill(None);
+    }
+
+    /// Drops the field values of all markers. The markers keep their name, timing,
+    /// category and type, and are serialized with only their `type` in `data`.
+    pub fn clear_marker_fields(&mut self) {
+        self.marker_field_number_values = Vec::new();
+        self.marker_field_string_values = Vec::new();
+        self.marker_fields_cleared = true;
+    }
+
+    /// Replaces the stack of each marker with its entry in `stack_map`.
+    pub fn map_stacks(&mut self, stack_map: &[Option<usize>]) {
+        map_stack_indexes(&mut self.marker_stacks, stack_map);
+    }
+
     pub fn as_serializable<'a>(
         &'a self,
         schemas: &'a [InternalMarkerSchema],
@@ -160,18 +189,22 @@ impl<'a> Serialize for SerializableMarkerTableDataColumn<'a> {
             let marker_type_handle = marker_table.marker_type_handles[i];
             let stack_index = marker_table.marker_stacks[i];
             let schema = &schemas[marker_type_handle.0];
-            let string_fields;
-            let number_fields;
-            (string_fields, remaining_string_fields) =
-                remaining_string_fields.split_at(schema.string_field_count());
-            (number_fields, remaining_number_fields) =
-                remaining_number_fields.split_at(schema.number_field_count());
+            let fields = if marker_table.marker_fields_cleared {
+                None
+            } else {
+                let string_fields;
+                let number_fields;
+                (string_fields, remaining_string_fields) =
+                    remaining_string_fields.split_at(schema.string_field_count());
+                (number_fields, remaining_number_fields) =
+                    remaining_number_fields.split_at(schema.number_field_count());
+                Some((string_fields, number_fields))
+            };
             seq.serialize_element(&SerializableMarkerDataElement {
                 global_string_table,
                 stack_index,
                 schema,
-                string_fields,
-                number_fields,
+                fields,
             })?;
         }
         seq.end()
@@ -182,8 +215,8 @@ struct SerializableMarkerDataElement<'a> {
     global_string_table: &'a GlobalStringTable,
     stack_index: Option<usize>,
     schema: &'a InternalMarkerSchema,
-    string_fields: &'a [StringIndex],
-    number_fields: &'a [f64],
+    /// The string and number field values, or `None` if the fields have been cleared.
+    fields: Option<(&'a [StringIndex], &'a [f64])>,
 }
 
 impl<'a> Serialize for SerializableMarkerDataElement<'a> {
@@ -192,14 +225,16 @@ impl<'a> Serialize for SerializableMarkerDataElement<'a> {
             global_string_table,
             stack_index,
             schema,
-            mut string_fields,
-            mut number_fields,
+            fields,
         } = self;
         let mut map = serializer.serialize_map(None)?;
         map.serialize_entry("type", &schema.type_name())?;
         if let Some(stack_index) = stack_index {
             map.serialize_entry("cause", &SerializableMarkerCause(*stack_index))?;
         }
+        let Some((mut string_fields, mut number_fields)) = *fields else {
+            return map.end();
+        };
         for field in schema.fields() {
             match field.format.kind() {
                 MarkerFieldFormatKind::String => {
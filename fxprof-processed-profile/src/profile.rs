@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
 use std::sync::Arc;
 use std::time::Duration;
@@ -5,7 +6,7 @@ use std::time::Duration;
 use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
 use serde_json::json;
 
-use crate::category::{Category, CategoryHandle, CategoryPairHandle};
+use crate::category::{Category, CategoryHandle, CategoryPairHandle, SubcategoryIndex};
 use crate::category_color::CategoryColor;
 use crate::counters::{Counter, CounterHandle};
 use crate::cpu_delta::CpuDelta;
@@ -23,6 +24,7 @@ use crate::process::{Process, ThreadHandle};
 use crate::reference_timestamp::ReferenceTimestamp;
 use crate::string_table::{GlobalStringIndex, GlobalStringTable};
 use crate::thread::{ProcessHandle, Thread};
+use crate::thread_order::{ThreadActivity, ThreadOrder};
 use crate::timestamp::Timestamp;
 
 /// The sampling interval used during profile recording.
@@ -107,6 +109,8 @@ pub struct StringHandle(pub(crate) GlobalStringIndex);
 pub struct Profile {
     pub(crate) product: String,
     pub(crate) os_name: Option<String>,
+    pub(crate) extra_meta_info: Vec<(String, Vec<(String, String)>)>,
+    pub(crate) cpu_core_types: Vec<Option<String>>,
     pub(crate) interval: SamplingInterval,
     pub(crate) global_libs: GlobalLibTable,
     pub(crate) kernel_libs: LibMappings<LibraryHandle>,
@@ -115,11 +119,14 @@ pub struct Profile {
     pub(crate) counters: Vec<Counter>,
     pub(crate) threads: Vec<Thread>, // append-only for stable ThreadHandles
     pub(crate) reference_timestamp: ReferenceTimestamp,
+    pub(crate) profiling_start_time: Option<Timestamp>,
     pub(crate) string_table: GlobalStringTable,
     pub(crate) marker_schemas: Vec<InternalMarkerSchema>,
     static_schema_marker_types: FastHashMap<&'static str, MarkerTypeHandle>,
     used_pids: FastHashMap<u32, u32>,
     used_tids: FastHashMap<u32, u32>,
+    thread_order: ThreadOrder,
+    hide_idle_threads: bool,
 }
 
 impl Profile {
@@ -138,10 +145,13 @@ impl Profile {
             interval,
             product: product.to_string(),
             os_name: None,
+            extra_meta_info: Vec::new(),
+            cpu_core_types: Vec::new(),
             threads: Vec::new(),
             global_libs: GlobalLibTable::new(),
             kernel_libs: LibMappings::new(),
             reference_timestamp,
+            profiling_start_time: None,
             processes: Vec::new(),
             string_table: GlobalStringTable::new(),
             marker_schemas: Vec::new(),
@@ -154,6 +164,8 @@ impl Profile {
             used_pids: FastHashMap::default(),
             used_tids: FastHashMap::default(),
             counters: Vec::new(),
+            thread_order: ThreadOrder::Default,
+            hide_idle_threads: false,
         }
     }
 
@@ -167,6 +179,15 @@ impl Profile {
         self.reference_timestamp = reference_timestamp;
     }
 
+    /// Set the time at which the recording started.
+    ///
+    /// Together with the reference timestamp, this lets the Firefox Profiler
+    /// map timestamps in the profile to absolute wall-clock times. If this is
+    /// not set, the recording is assumed to start at the first sample or marker.
+    pub fn set_profiling_start_time(&mut self, start_time: Timestamp) {
+        self.profiling_start_time = Some(start_time);
+    }
+
     /// Change the product name.
     pub fn set_product(&mut self, product: &str) {
         self.product = product.to_string();
@@ -177,6 +198,51 @@ impl Profile {
         self.os_name = Some(os_name.to_string());
     }
 
+    /// Set the order in which processes and threads are listed in the profile.
+    pub fn set_thread_order(&mut self, thread_order: ThreadOrder) {
+        self.thread_order = thread_order;
+    }
+
+    /// Hide idle threads when the profile is opened in the Firefox Profiler.
+    /// A thread is idle if it used less than 1% of the CPU time of the busiest
+    /// thread. The main thread of a process stays visible if any other thread
+    /// of the process is visible. The threads can still be shown in the UI.
+    pub fn set_hide_idle_threads(&mut self, hide_idle_threads: bool) {
+        self.hide_idle_threads = hide_idle_threads;
+    }
+
+    /// Add a label / value pair to the profile metadata. The Firefox Profiler
+    /// displays these in the profile info panel, grouped by `section`.
+    ///
+    /// This can be used for information which doesn't have a dedicated field in
+    /// the profile metadata, for example the clock that the timestamps were
+    /// taken from.
+    pub fn add_extra_meta_info(&mut self, section: &str, label: &str, value: &str) {
+        let entry = (label.to_string(), value.to_string());
+        match self
+            .extra_meta_info
+            .iter_mut()
+            .find(|(section_label, _)| section_label == section)
+        {
+            Some((_, entries)) => entries.push(entry),
+            None => self
+                .extra_meta_info
+                .push((section.to_string(), vec![entry])),
+        }
+    }
+
+    /// Set the type of the core with the CPU number `cpu`, for example
+    /// "Performance" or "Efficiency" on machines with different kinds of
+    /// cores. This is stored in the profile metadata next to the CPU numbers
+    /// of the samples from [`Profile::add_sample_on_cpu`].
+    pub fn set_cpu_core_type(&mut self, cpu: u32, core_type: &str) {
+        let index = cpu as usize;
+        if self.cpu_core_types.len() <= index {
+            self.cpu_core_types.resize(index + 1, None);
+        }
+        self.cpu_core_types[index] = Some(core_type.to_string());
+    }
+
     /// Add a category and return its handle.
     ///
     /// Categories are used for stack frames and markers, as part of a "category pair".
@@ -190,6 +256,103 @@ impl Profile {
         handle
     }
 
+    /// Assign categories to stack frames based on their function names, for example
+    /// to group frames by the crate or namespace they belong to.
+    ///
+    /// `category_for_name` is called with the name of each frame which has one, and
+    /// returns the name and color of the frame's new category, or `None` to keep the
+    /// frame's current category. Categories are looked up by name, and added if they
+    /// don't exist yet. Stacks take the category of their frame.
+    ///
+    /// Frames with a code address only have a name if their library has a symbol
+    /// table, see [`Profile::set_lib_symbol_table`]. The symbol table can be set
+    /// after the frames were added.
+    pub fn categorize_frames_by_name(
+        &mut self,
+        mut category_for_name: impl FnMut(&str) -> Option<(String, CategoryColor)>,
+    ) {
+        self.categorize_frames(|_lib_name, function_name| category_for_name(function_name?));
+    }
+
+    /// Like [`Profile::categorize_frames_by_name`], but `category_for_frame` is
+    /// called with the name of the frame's library and with the frame's function
+    /// name, for each frame which has at least one of them. This allows
+    /// categorizing the frames of libraries without a symbol table.
+    pub fn categorize_frames(
+        &mut self,
+        mut category_for_frame: impl FnMut(
+            Option<&str>,
+            Option<&str>,
+        ) -> Option<(String, CategoryColor)>,
+    ) {
+        let categories = &mut self.categories;
+        for thread in &mut self.threads {
+            thread.categorize_frames(&self.global_libs, |lib_name, function_name| {
+                let (category_name, color) = category_for_frame(lib_name, function_name)?;
+                Some(find_or_add_category(categories, category_name, color).into())
+            });
+        }
+    }
+
+    /// Like [`Profile::categorize_frames`], but the category also applies to the
+    /// stacks below each categorized frame, towards the leaf, unless a frame further
+    /// down has a category of its own. For example, the kernel frames below a
+    /// syscall entry point can be attributed to the syscall.
+    ///
+    /// `category_for_frame` returns the name and color of the category, and
+    /// optionally the name of a subcategory. Subcategories are looked up by name
+    /// too, and added if they don't exist yet. Once a category has 255
+    /// subcategories, frames for new subcategories get the category's "Other"
+    /// subcategory.
+    pub fn categorize_stacks_below_frames(
+        &mut self,
+        mut category_for_frame: impl FnMut(
+            Option<&str>,
+            Option<&str>,
+        ) -> Option<(String, CategoryColor, Option<String>)>,
+    ) {
+        let categories = &mut self.categories;
+        for thread in &mut self.threads {
+            thread.categorize_stacks_below_frames(&self.global_libs, |lib_name, function_name| {
+                let (category_name, color, subcategory_name) =
+                    category_for_frame(lib_name, function_name)?;
+                let category_handle = find_or_add_category(categories, category_name, color);
+                let Some(subcategory_name) = subcategory_name else {
+                    return Some(category_handle.into());
+                };
+                let category = &mut categories[category_handle.0 as usize];
+                let subcategory = match category
+                    .subcategories
+                    .iter()
+                    .position(|name| *name == subcategory_name)
+                {
+                    Some(index) => Some(SubcategoryIndex(index as u8)),
+                    None if category.subcategories.len() < u8::MAX as usize => {
+                        Some(category.add_subcategory(subcategory_name))
+                    }
+                    None => None,
+                };
+                Some(CategoryPairHandle(category_handle, subcategory))
+            });
+        }
+    }
+
+    /// Remove stack frames from the leaf end of stacks, for example frames of the
+    /// profiler itself which ended up in the sampled stacks.
+    ///
+    /// `should_remove` is called like the callback of [`Profile::categorize_frames`],
+    /// and returns whether the frame should be removed. Only a run of removed
+    /// frames at the leaf end of a stack is removed; the same frames further up
+    /// the stack, towards the root, are kept.
+    pub fn remove_leaf_frames(
+        &mut self,
+        mut should_remove: impl FnMut(Option<&str>, Option<&str>) -> bool,
+    ) {
+        for thread in &mut self.threads {
+            thread.remove_leaf_frames(&self.global_libs, &mut should_remove);
+        }
+    }
+
     /// Add a subcategory for a category, and return the "category pair" handle.
     pub fn add_subcategory(&mut self, category: CategoryHandle, name: &str) -> CategoryPairHandle {
         let subcategory = self.categories[category.0 as usize].add_subcategory(name.into());
@@ -445,7 +608,27 @@ impl Profile {
         weight: i32,
     ) {
         let stack_index = self.stack_index_for_frames(thread, frames);
-        self.threads[thread.0].add_sample(timestamp, stack_index, cpu_delta, weight);
+        self.threads[thread.0].add_sample(timestamp, stack_index, cpu_delta, weight, None);
+    }
+
+    /// Add a sample which was taken while the thread was running on the CPU
+    /// with the number `cpu`. Otherwise this is the same as
+    /// [`Profile::add_sample`].
+    ///
+    /// The CPU numbers end up in a `cpu` column of the thread's samples table.
+    /// If only some of a thread's samples have a CPU, the column is `null` for
+    /// the others.
+    pub fn add_sample_on_cpu(
+        &mut self,
+        thread: ThreadHandle,
+        timestamp: Timestamp,
+        frames: impl Iterator<Item = FrameInfo>,
+        cpu_delta: CpuDelta,
+        weight: i32,
+        cpu: u32,
+    ) {
+        let stack_index = self.stack_index_for_frames(thread, frames);
+        self.threads[thread.0].add_sample(timestamp, stack_index, cpu_delta, weight, Some(cpu));
     }
 
     /// Add a sample with a CPU delta of zero. Internally, multiple consecutive
@@ -657,6 +840,69 @@ impl Profile {
         self.threads[thread.0].set_marker_stack(marker, stack_index);
     }
 
+    /// Sets a marker's stack to the most common stack among the thread's samples
+    /// between `start` and `end`, weighted by the sample weights. This gives
+    /// markers for long operations, e.g. hangs, the stack which took up most of
+    /// their time. The stack stays unset if there are no samples in that range.
+    pub fn set_marker_stack_from_samples(
+        &mut self,
+        thread: ThreadHandle,
+        marker: MarkerHandle,
+        start: Timestamp,
+        end: Timestamp,
+    ) {
+        let thread = &mut self.threads[thread.0];
+        if let Some(stack_index) = thread.most_common_sample_stack(start, end) {
+            thread.set_marker_stack(marker, Some(stack_index));
+        }
+    }
+
+    /// Remove the stacks from all markers on all threads.
+    ///
+    /// Marker stacks can make up a large part of a profile with many markers. This can
+    /// be used to reduce the size of the serialized profile, at the cost of losing the
+    /// marker stack information.
+    pub fn clear_marker_stacks(&mut self) {
+        for thread in &mut self.threads {
+            thread.clear_marker_stacks();
+        }
+    }
+
+    /// Remove the field values from all markers on all threads, including markers
+    /// which are added after this call.
+    ///
+    /// The markers keep their name, category, timing and type, but the fields from
+    /// their schema (for example URLs, byte counts or durations) will be missing in
+    /// the front-end. This can be used to reduce the size of the serialized profile
+    /// further than [`Profile::clear_marker_stacks`] does.
+    pub fn clear_marker_fields(&mut self) {
+        for thread in &mut self.threads {
+            thread.clear_marker_fields();
+        }
+    }
+
+    /// The total number of samples across all threads.
+    pub fn sample_count(&self) -> usize {
+        self.threads
+            .iter()
+            .map(|thread| thread.sample_count())
+            .sum()
+    }
+
+    /// Reduce the number of samples on every thread by a factor of `factor`, by only
+    /// keeping every `factor`-th sample.
+    ///
+    /// Each kept sample absorbs the weight and CPU delta of the samples that were dropped
+    /// before it, so the call tree totals and the CPU graphs stay approximately the same.
+    /// Allocation samples are not affected.
+    ///
+    /// This can be used to limit the size of the serialized profile.
+    pub fn downsample(&mut self, factor: usize) {
+        for thread in &mut self.threads {
+            thread.downsample(factor);
+        }
+    }
+
     /// Add a data point to a counter. For a memory counter, `value_delta` is the number
     /// of bytes that have been allocated / deallocated since the previous counter sample, and
     /// `number_of_operations` is the number of `malloc` / `free` calls since the previous
@@ -745,11 +991,36 @@ impl Profile {
         let mut sorted_threads = Vec::with_capacity(self.threads.len());
         let mut first_thread_index_per_process = vec![0; self.processes.len()];
 
+        let thread_activities: Vec<ThreadActivity> = match self.thread_order {
+            ThreadOrder::Default | ThreadOrder::Name => Vec::new(),
+            ThreadOrder::CpuUsage | ThreadOrder::FirstSampleTime => {
+                self.threads.iter().map(ThreadActivity::of_thread).collect()
+            }
+        };
+        let mut process_activities = vec![ThreadActivity::default(); self.processes.len()];
+        for (thread, activity) in self.threads.iter().zip(&thread_activities) {
+            let process_activity = &mut process_activities[thread.process().0];
+            *process_activity = process_activity.merge(*activity);
+        }
+        let cmp_activities = |a: &ThreadActivity, b: &ThreadActivity| match self.thread_order {
+            ThreadOrder::CpuUsage => a.cmp_busiest_first(b),
+            ThreadOrder::FirstSampleTime => a.cmp_first_sample_time(b),
+            ThreadOrder::Default | ThreadOrder::Name => Ordering::Equal,
+        };
+
         let mut sorted_processes: Vec<_> = (0..self.processes.len()).map(ProcessHandle).collect();
         sorted_processes.sort_by(|a_handle, b_handle| {
             let a = &self.processes[a_handle.0];
             let b = &self.processes[b_handle.0];
-            a.cmp_for_json_order(b)
+            let ordering = match self.thread_order {
+                ThreadOrder::Default => Ordering::Equal,
+                ThreadOrder::Name => a.name().cmp(b.name()),
+                ThreadOrder::CpuUsage | ThreadOrder::FirstSampleTime => cmp_activities(
+                    &process_activities[a_handle.0],
+                    &process_activities[b_handle.0],
+                ),
+            };
+            ordering.then_with(|| a.cmp_for_json_order(b))
         });
 
         for process in sorted_processes {
@@ -761,13 +1032,60 @@ impl Profile {
             sorted_threads_for_this_process.sort_by(|a_handle, b_handle| {
                 let a = &self.threads[a_handle.0];
                 let b = &self.threads[b_handle.0];
-                a.cmp_for_json_order(b)
+                let ordering = match self.thread_order {
+                    ThreadOrder::Default => Ordering::Equal,
+                    ThreadOrder::Name => a.name().cmp(&b.name()),
+                    ThreadOrder::CpuUsage | ThreadOrder::FirstSampleTime => cmp_activities(
+                        &thread_activities[a_handle.0],
+                        &thread_activities[b_handle.0],
+                    ),
+                };
+                (!a.is_main())
+                    .cmp(&!b.is_main())
+                    .then(ordering)
+                    .then_with(|| a.cmp_for_json_order(b))
             });
         }
 
         (sorted_threads, first_thread_index_per_process)
     }
 
+    /// Returns the indexes, in `sorted_threads`, of the threads which aren't
+    /// idle, or `None` if no thread should be hidden.
+    fn initial_visible_threads(&self, sorted_threads: &[ThreadHandle]) -> Option<Vec<usize>> {
+        let activities: Vec<ThreadActivity> = sorted_threads
+            .iter()
+            .map(|thread| ThreadActivity::of_thread(&self.threads[thread.0]))
+            .collect();
+        let max_cpu_time = activities.iter().map(ThreadActivity::cpu_time).max()?;
+        let max_sample_count = activities.iter().map(ThreadActivity::sample_count).max()?;
+
+        let mut process_is_visible = vec![false; self.processes.len()];
+        for (thread, activity) in sorted_threads.iter().zip(&activities) {
+            if !activity.is_idle(max_cpu_time, max_sample_count) {
+                process_is_visible[self.threads[thread.0].process().0] = true;
+            }
+        }
+        let visible_threads: Vec<usize> = sorted_threads
+            .iter()
+            .zip(&activities)
+            .enumerate()
+            .filter(|(_, (thread, activity))| {
+                let thread = &self.threads[thread.0];
+                if thread.is_main() {
+                    process_is_visible[thread.process().0]
+                } else {
+                    !activity.is_idle(max_cpu_time, max_sample_count)
+                }
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if visible_threads.is_empty() || visible_threads.len() == sorted_threads.len() {
+            return None;
+        }
+        Some(visible_threads)
+    }
+
     fn serializable_threads<'a>(
         &'a self,
         sorted_threads: &'a [ThreadHandle],
@@ -801,11 +1119,31 @@ impl Profile {
     }
 }
 
+/// Returns the category with this name, and adds it if it doesn't exist yet.
+fn find_or_add_category(
+    categories: &mut Vec<Category>,
+    name: String,
+    color: CategoryColor,
+) -> CategoryHandle {
+    let index = match categories.iter().position(|c| c.name == name) {
+        Some(index) => index,
+        None => {
+            categories.push(Category {
+                name,
+                color,
+                subcategories: Vec::new(),
+            });
+            categories.len() - 1
+        }
+    };
+    CategoryHandle(index as u16)
+}
+
 impl Serialize for Profile {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         let (sorted_threads, first_thread_index_per_process) = self.sorted_threads();
         let mut map = serializer.serialize_map(None)?;
-        map.serialize_entry("meta", &SerializableProfileMeta(self))?;
+        map.serialize_entry("meta", &SerializableProfileMeta(self, &sorted_threads))?;
         map.serialize_entry("libs", &self.global_libs)?;
         map.serialize_entry("threads", &self.serializable_threads(&sorted_threads))?;
         map.serialize_entry("pages", &[] as &[()])?;
@@ -818,7 +1156,7 @@ impl Serialize for Profile {
     }
 }
 
-struct SerializableProfileMeta<'a>(&'a Profile);
+struct SerializableProfileMeta<'a>(&'a Profile, &'a [ThreadHandle]);
 
 impl<'a> Serialize for SerializableProfileMeta<'a> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
@@ -850,12 +1188,50 @@ impl<'a> Serialize for SerializableProfileMeta<'a> {
             }),
         )?;
         map.serialize_entry("startTime", &self.0.reference_timestamp)?;
+        if let Some(profiling_start_time) = &self.0.profiling_start_time {
+            map.serialize_entry("profilingStartTime", profiling_start_time)?;
+        }
+        if !self.0.extra_meta_info.is_empty() {
+            let extra: Vec<_> = self
+                .0
+                .extra_meta_info
+                .iter()
+                .map(|(section_label, entries)| {
+                    let entries: Vec<_> = entries
+                        .iter()
+                        .map(|(label, value)| {
+                            json!({
+                                "label": label,
+                                "format": "string",
+                                "value": value,
+                            })
+                        })
+                        .collect();
+                    json!({
+                        "label": section_label,
+                        "entries": entries,
+                    })
+                })
+                .collect();
+            map.serialize_entry("extra", &extra)?;
+        }
+        if !self.0.cpu_core_types.is_empty() {
+            map.serialize_entry("cpuCoreTypes", &self.0.cpu_core_types)?;
+        }
         map.serialize_entry("symbolicated", &false)?;
         map.serialize_entry("pausedRanges", &[] as &[()])?;
         map.serialize_entry("version", &24)?;
         map.serialize_entry("usesOnlyOneStackType", &(!self.0.contains_js_function()))?;
         map.serialize_entry("doesNotUseFrameImplementation", &true)?;
         map.serialize_entry("sourceCodeIsNotOnSearchfox", &true)?;
+        if self.0.thread_order != ThreadOrder::Default {
+            map.serialize_entry("keepProfileThreadOrder", &true)?;
+        }
+        if self.0.hide_idle_threads {
+            if let Some(visible_threads) = self.0.initial_visible_threads(self.1) {
+                map.serialize_entry("initialVisibleThreads", &visible_threads)?;
+            }
+        }
 
         let mut marker_schemas: Vec<InternalMarkerSchema> = self.0.marker_schemas.clone();
         marker_schemas.sort_by(|a, b| a.type_name().cmp(b.type_name()));
@@ -20,6 +20,9 @@ pub struct SampleTable {
     sample_stack_indexes: Vec<Option<usize>>,
     /// CPU usage delta since the previous sample for this thread, for each sample.
     sample_cpu_deltas: Vec<CpuDelta>,
+    /// The CPU which each sample was taken on. This is only allocated once the
+    /// first sample with a known CPU is added.
+    sample_cpus: Option<Vec<Option<u32>>>,
     sorted_by_time: bool,
     last_sample_timestamp: Timestamp,
 }
@@ -112,6 +115,7 @@ impl SampleTable {
             sample_timestamps: Vec::new(),
             sample_stack_indexes: Vec::new(),
             sample_cpu_deltas: Vec::new(),
+            sample_cpus: None,
             sorted_by_time: true,
             last_sample_timestamp: Timestamp::from_nanos_since_reference(0),
         }
@@ -123,7 +127,17 @@ impl SampleTable {
         stack_index: Option<usize>,
         cpu_delta: CpuDelta,
         weight: i32,
+        cpu: Option<u32>,
     ) {
+        match (&mut self.sample_cpus, cpu) {
+            (Some(sample_cpus), cpu) => sample_cpus.push(cpu),
+            (None, Some(cpu)) => {
+                let mut sample_cpus = vec![None; self.sample_timestamps.len()];
+                sample_cpus.push(Some(cpu));
+                self.sample_cpus = Some(sample_cpus);
+            }
+            (None, None) => {}
+        }
         self.sample_weights.push(weight);
         self.sample_timestamps.push(timestamp);
         self.sample_stack_indexes.push(stack_index);
@@ -134,10 +148,74 @@ impl SampleTable {
         self.last_sample_timestamp = timestamp;
     }
 
+    /// Replaces the stack of each sample with its entry in `stack_map`.
+    pub fn map_stacks(&mut self, stack_map: &[Option<usize>]) {
+        map_stack_indexes(&mut self.sample_stack_indexes, stack_map);
+    }
+
     pub fn modify_last_sample(&mut self, timestamp: Timestamp, weight: i32) {
         *self.sample_weights.last_mut().unwrap() += weight;
         *self.sample_timestamps.last_mut().unwrap() = timestamp;
     }
+
+    pub fn len(&self) -> usize {
+        self.sample_timestamps.len()
+    }
+
+    /// Iterates over the timestamp, stack index, CPU delta and weight of each sample.
+    pub fn iter(&self) -> impl Iterator<Item = (Timestamp, Option<usize>, CpuDelta, i32)> + '_ {
+        (0..self.len()).map(|i| {
+            (
+                self.sample_timestamps[i],
+                self.sample_stack_indexes[i],
+                self.sample_cpu_deltas[i],
+                self.sample_weights[i],
+            )
+        })
+    }
+
+    /// Reduce the number of samples by a factor of `factor`.
+    ///
+    /// The samples are grouped into runs of `factor` consecutive samples (in timestamp
+    /// order), and each run is replaced with its last sample. The kept sample receives
+    /// the accumulated weight and CPU delta of the entire run, so that the call tree
+    /// totals and the CPU graph stay approximately the same.
+    pub fn downsample(&mut self, factor: usize) {
+        if factor <= 1 || self.sample_timestamps.is_empty() {
+            return;
+        }
+
+        let mut indexes: Vec<usize> = (0..self.sample_timestamps.len()).collect();
+        if !self.sorted_by_time {
+            indexes.sort_by_key(|index| self.sample_timestamps[*index]);
+        }
+
+        let mut downsampled = SampleTable {
+            sample_type: self.sample_type.clone(),
+            ..SampleTable::new()
+        };
+        for run in indexes.chunks(factor) {
+            let last = *run.last().unwrap();
+            let weight = run.iter().map(|i| self.sample_weights[*i]).sum();
+            let cpu_delta = run
+                .iter()
+                .map(|i| self.sample_cpu_deltas[*i])
+                .fold(CpuDelta::ZERO, |acc, delta| acc + delta);
+            downsampled.add_sample(
+                self.sample_timestamps[last],
+                self.sample_stack_indexes[last],
+                cpu_delta,
+                weight,
+                self.sample_cpu(last),
+            );
+        }
+        *self = downsampled;
+    }
+
+    /// The CPU which the sample with the given index was taken on, if known.
+    pub fn sample_cpu(&self, index: usize) -> Option<u32> {
+        self.sample_cpus.as_ref().and_then(|cpus| cpus[index])
+    }
 }
 
 impl Serialize for SampleTable {
@@ -152,6 +230,9 @@ impl Serialize for SampleTable {
             map.serialize_entry("time", &self.sample_timestamps)?;
             map.serialize_entry("weight", &self.sample_weights)?;
             map.serialize_entry("threadCPUDelta", &self.sample_cpu_deltas)?;
+            if let Some(sample_cpus) = &self.sample_cpus {
+                map.serialize_entry("cpu", sample_cpus)?;
+            }
         } else {
             let mut indexes: Vec<usize> = (0..self.sample_timestamps.len()).collect();
             indexes.sort_unstable_by_key(|index| self.sample_timestamps[*index]);
@@ -171,6 +252,9 @@ impl Serialize for SampleTable {
                 "threadCPUDelta",
                 &SliceWithPermutation(&self.sample_cpu_deltas, &indexes),
             )?;
+            if let Some(sample_cpus) = &self.sample_cpus {
+                map.serialize_entry("cpu", &SliceWithPermutation(sample_cpus, &indexes))?;
+            }
         }
         map.end()
     }
@@ -242,6 +326,17 @@ impl NativeAllocationsTable {
         self.allocation_address.push(allocation_address);
         self.allocation_size.push(allocation_size);
     }
+
+    /// Replaces the stack of each sample with its entry in `stack_map`.
+    pub fn map_stacks(&mut self, stack_map: &[Option<usize>]) {
+        map_stack_indexes(&mut self.stack, stack_map);
+    }
+}
+
+pub(crate) fn map_stack_indexes(stack_indexes: &mut [Option<usize>], stack_map: &[Option<usize>]) {
+    for stack_index in stack_indexes {
+        *stack_index = stack_index.and_then(|stack_index| stack_map[stack_index]);
+    }
 }
 
 impl Serialize for NativeAllocationsTable {
@@ -366,4 +461,59 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn test_downsample() {
+        let mut sample_table = SampleTable::new();
+        for i in 0..5 {
+            sample_table.add_sample(
+                Timestamp::from_millis_since_reference(i as f64),
+                Some(i),
+                CpuDelta::from_micros(100),
+                1,
+                None,
+            );
+        }
+        sample_table.downsample(2);
+
+        assert_json_eq!(
+            sample_table,
+            json!({
+              "length": 3,
+              "weightType": "samples",
+              "stack": [1, 3, 4],
+              "time": [1.0, 3.0, 4.0],
+              "weight": [2, 2, 1],
+              "threadCPUDelta": [200, 200, 100]
+            })
+        );
+    }
+    #[test]
+    fn test_downsample_with_cpus() {
+        let mut sample_table = SampleTable::new();
+        for i in 0..4 {
+            let cpu = (i != 1).then_some(i as u32);
+            sample_table.add_sample(
+                Timestamp::from_millis_since_reference(i as f64),
+                Some(i),
+                CpuDelta::from_micros(100),
+                1,
+                cpu,
+            );
+        }
+        sample_table.downsample(3);
+
+        assert_json_eq!(
+            sample_table,
+            json!({
+              "length": 2,
+              "weightType": "samples",
+              "stack": [2, 3],
+              "time": [2.0, 3.0],
+              "weight": [3, 1],
+              "threadCPUDelta": [300, 100],
+              "cpu": [2, 3]
+            })
+        );
+    }
 }
@@ -86,6 +86,65 @@ impl StackTable {
         }
     }
 
+    pub fn frame_for_stack(&self, stack: usize) -> usize {
+        self.stack_frames[stack]
+    }
+
+    /// Sets the category of each stack whose frame has an entry in
+    /// `frame_categories`, which is indexed by frame index. The other stacks keep
+    /// their category.
+    pub fn set_categories_for_frames(&mut self, frame_categories: &[Option<CategoryPairHandle>]) {
+        for stack in 0..self.stack_frames.len() {
+            if let Some(category_pair) = frame_categories[self.stack_frames[stack]] {
+                self.set_stack_category(stack, category_pair);
+            }
+        }
+    }
+
+    /// Like [`StackTable::set_categories_for_frames`], but stacks whose frame has
+    /// no entry take the category of their prefix, if the prefix got one from
+    /// this call.
+    pub fn set_categories_below_frames(&mut self, frame_categories: &[Option<CategoryPairHandle>]) {
+        let mut stack_categories: Vec<Option<CategoryPairHandle>> =
+            Vec::with_capacity(self.stack_frames.len());
+        for stack in 0..self.stack_frames.len() {
+            // A prefix always has a lower index than its stacks.
+            let category_pair = frame_categories[self.stack_frames[stack]]
+                .or_else(|| self.stack_prefixes[stack].and_then(|prefix| stack_categories[prefix]));
+            stack_categories.push(category_pair);
+            if let Some(category_pair) = category_pair {
+                self.set_stack_category(stack, category_pair);
+            }
+        }
+    }
+
+    fn set_stack_category(&mut self, stack: usize, category_pair: CategoryPairHandle) {
+        let CategoryPairHandle(category, subcategory_index) = category_pair;
+        self.stack_categories[stack] = category;
+        self.stack_subcategories[stack] = match subcategory_index {
+            Some(index) => Subcategory::Normal(index),
+            None => Subcategory::Other(category),
+        };
+    }
+
+    /// Returns, for each stack, the stack which is left after removing the
+    /// frames in `remove_frame` (indexed by frame index) from its leaf end.
+    /// Only a run of removed frames at the leaf end is removed; the same frames
+    /// further up the stack are kept. `None` means the empty stack.
+    pub fn stacks_without_leaf_frames(&self, remove_frame: &[bool]) -> Vec<Option<usize>> {
+        let mut stack_map: Vec<Option<usize>> = Vec::with_capacity(self.stack_frames.len());
+        for (stack, frame) in self.stack_frames.iter().enumerate() {
+            // A prefix always has a lower index than its stacks.
+            let mapped = if remove_frame[*frame] {
+                self.stack_prefixes[stack].and_then(|prefix| stack_map[prefix])
+            } else {
+                Some(stack)
+            };
+            stack_map.push(mapped);
+        }
+        stack_map
+    }
+
     pub fn serialize_with_categories<'a>(
         &'a self,
         categories: &'a [Category],
@@ -0,0 +1,237 @@
+use std::time::Duration;
+
+use crate::fast_hash_map::FastHashMap;
+use crate::frame_table::InternalFrameLocation;
+use crate::global_lib_table::GlobalLibIndex;
+use crate::library_info::LibraryInfo;
+use crate::profile::Profile;
+use crate::thread::{ProcessHandle, Thread};
+use crate::timestamp::Timestamp;
+
+/// An overview of the contents of a profile, for example for printing a short
+/// summary in the terminal. Created with [`Profile::summary`].
+#[derive(Debug, Clone)]
+pub struct ProfileSummary {
+    /// The time between the first and the last sample in the profile.
+    pub sampled_duration: Duration,
+    /// The total number of samples across all threads.
+    pub sample_count: usize,
+    /// The total number of markers across all threads.
+    pub marker_count: usize,
+    /// One entry per process which has at least one sample, sorted by CPU time
+    /// (or by sample count, for processes without CPU deltas), in descending order.
+    pub processes: Vec<ProcessSummary>,
+    /// The leaf frames with the highest total sample weight across all threads,
+    /// in descending order.
+    pub hottest_frames: Vec<HotFrame>,
+}
+
+/// The activity of one process. See [`ProfileSummary::processes`].
+#[derive(Debug, Clone)]
+pub struct ProcessSummary {
+    pub name: String,
+    pub pid: String,
+    /// The sum of the CPU deltas of all samples on all threads of this process.
+    pub cpu_time: Duration,
+    /// The number of samples on all threads of this process.
+    pub sample_count: usize,
+    /// The CPU time of this process, bucketed into equally-sized time ranges which
+    /// together span [`ProfileSummary::sampled_duration`].
+    pub cpu_time_per_bucket: Vec<Duration>,
+    /// Like `cpu_time_per_bucket`, but with sample counts.
+    pub samples_per_bucket: Vec<u64>,
+}
+
+/// A leaf frame and its total sample weight. See [`ProfileSummary::hottest_frames`].
+#[derive(Debug, Clone)]
+pub struct HotFrame {
+    pub location: HotFrameLocation,
+    pub self_weight: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotFrameLocation {
+    /// A frame with a string label.
+    Label(String),
+    /// A code address which didn't belong to any known library.
+    UnknownAddress(u64),
+    /// A code address in a library. `symbol_name` is set if the library's
+    /// symbol table was provided with [`Profile::set_lib_symbol_table`].
+    AddressInLib {
+        lib: LibraryInfo,
+        relative_address: u32,
+        symbol_name: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FrameKey {
+    Label(String),
+    UnknownAddress(u64),
+    AddressInLib(u32, GlobalLibIndex),
+}
+
+impl Profile {
+    /// Compute a [`ProfileSummary`] with the `hot_frame_count` hottest leaf frames,
+    /// and with the per-process activity split into `bucket_count` time buckets.
+    pub fn summary(&self, hot_frame_count: usize, bucket_count: usize) -> ProfileSummary {
+        let (start_nanos, end_nanos) = match self.sample_time_range() {
+            Some((start, end)) => (start.nanos_since_reference(), end.nanos_since_reference()),
+            None => (0, 0),
+        };
+        let bucket_count = bucket_count.max(1);
+        let bucket_for_timestamp = |timestamp: Timestamp| -> usize {
+            let duration = end_nanos - start_nanos;
+            if duration == 0 {
+                return 0;
+            }
+            let offset = timestamp.nanos_since_reference() - start_nanos;
+            ((offset as u128 * bucket_count as u128 / duration as u128) as usize)
+                .min(bucket_count - 1)
+        };
+
+        let mut processes: Vec<ProcessSummary> = self
+            .processes
+            .iter()
+            .map(|process| ProcessSummary {
+                name: process.name().to_owned(),
+                pid: process.pid().to_owned(),
+                cpu_time: Duration::ZERO,
+                sample_count: 0,
+                cpu_time_per_bucket: vec![Duration::ZERO; bucket_count],
+                samples_per_bucket: vec![0; bucket_count],
+            })
+            .collect();
+        let mut frame_weights: FastHashMap<FrameKey, i64> = FastHashMap::default();
+        let mut marker_count = 0;
+
+        for thread in &self.threads {
+            marker_count += thread.marker_count();
+            let process = &mut processes[thread.process().0];
+            thread.for_each_sample_with_leaf_frame(|timestamp, cpu_delta, weight, leaf_frame| {
+                let cpu_delta = Duration::from(cpu_delta);
+                let bucket = bucket_for_timestamp(timestamp);
+                process.cpu_time += cpu_delta;
+                process.sample_count += 1;
+                process.cpu_time_per_bucket[bucket] += cpu_delta;
+                process.samples_per_bucket[bucket] += 1;
+                if let Some(key) = leaf_frame.and_then(|location| frame_key(thread, location)) {
+                    *frame_weights.entry(key).or_default() += i64::from(weight);
+                }
+            });
+        }
+
+        processes.retain(|process| process.sample_count != 0);
+        processes.sort_by(|a, b| {
+            b.cpu_time
+                .cmp(&a.cpu_time)
+                .then(b.sample_count.cmp(&a.sample_count))
+        });
+
+        let mut frame_weights: Vec<(FrameKey, i64)> = frame_weights.into_iter().collect();
+        frame_weights.sort_by(|(_, a), (_, b)| b.cmp(a));
+        let hottest_frames = frame_weights
+            .into_iter()
+            .take(hot_frame_count)
+            .map(|(key, self_weight)| HotFrame {
+                location: self.hot_frame_location(key),
+                self_weight,
+            })
+            .collect();
+
+        ProfileSummary {
+            sampled_duration: Duration::from_nanos(end_nanos - start_nanos),
+            sample_count: self.sample_count(),
+            marker_count,
+            processes,
+            hottest_frames,
+        }
+    }
+
+    /// The timestamps of the first and of the last sample across all threads,
+    /// or `None` if the profile has no samples.
+    pub fn sample_time_range(&self) -> Option<(Timestamp, Timestamp)> {
+        self.threads
+            .iter()
+            .filter_map(Thread::sample_time_range)
+            .reduce(|(start1, end1), (start2, end2)| (start1.min(start2), end1.max(end2)))
+    }
+
+    /// The combined CPU time of all threads of `processes`, bucketed into equally
+    /// sized time ranges which span [`Profile::sample_time_range`]. The buckets are
+    /// `min_bucket_duration` long, or longer if there would be more than
+    /// `max_bucket_count` buckets otherwise. Returns the start time and the CPU
+    /// time of each bucket.
+    pub fn cpu_time_per_bucket(
+        &self,
+        processes: &[ProcessHandle],
+        min_bucket_duration: Duration,
+        max_bucket_count: usize,
+    ) -> Vec<(Timestamp, Duration)> {
+        let Some((start, end)) = self.sample_time_range() else {
+            return Vec::new();
+        };
+        let start_nanos = start.nanos_since_reference();
+        let duration_nanos = end.nanos_since_reference() - start_nanos;
+        let max_bucket_count = max_bucket_count.max(1) as u64;
+        let bucket_nanos = (min_bucket_duration.as_nanos() as u64)
+            .max(duration_nanos / max_bucket_count + 1)
+            .max(1);
+        let bucket_count = (duration_nanos / bucket_nanos + 1) as usize;
+
+        let mut cpu_time_per_bucket = vec![Duration::ZERO; bucket_count];
+        for thread in &self.threads {
+            if !processes.contains(&thread.process()) {
+                continue;
+            }
+            thread.for_each_sample_with_leaf_frame(|timestamp, cpu_delta, _weight, _leaf| {
+                let offset = timestamp.nanos_since_reference() - start_nanos;
+                let bucket = ((offset / bucket_nanos) as usize).min(bucket_count - 1);
+                cpu_time_per_bucket[bucket] += Duration::from(cpu_delta);
+            });
+        }
+        cpu_time_per_bucket
+            .into_iter()
+            .enumerate()
+            .map(|(i, cpu_time)| {
+                let bucket_start = start_nanos + i as u64 * bucket_nanos;
+                (
+                    Timestamp::from_nanos_since_reference(bucket_start),
+                    cpu_time,
+                )
+            })
+            .collect()
+    }
+
+    fn hot_frame_location(&self, key: FrameKey) -> HotFrameLocation {
+        match key {
+            FrameKey::Label(label) => HotFrameLocation::Label(label),
+            FrameKey::UnknownAddress(address) => HotFrameLocation::UnknownAddress(address),
+            FrameKey::AddressInLib(relative_address, lib_index) => {
+                let lib = self.global_libs.get_lib(lib_index).unwrap();
+                let symbol_name = lib
+                    .symbol_table
+                    .as_deref()
+                    .and_then(|symbol_table| symbol_table.lookup(relative_address))
+                    .map(|symbol| symbol.name.clone());
+                HotFrameLocation::AddressInLib {
+                    lib: lib.clone(),
+                    relative_address,
+                    symbol_name,
+                }
+            }
+        }
+    }
+}
+
+fn frame_key(thread: &Thread, location: &InternalFrameLocation) -> Option<FrameKey> {
+    Some(match location {
+        InternalFrameLocation::UnknownAddress(address) => FrameKey::UnknownAddress(*address),
+        InternalFrameLocation::AddressInLib(address, lib_index) => {
+            FrameKey::AddressInLib(*address, *lib_index)
+        }
+        InternalFrameLocation::Label(string_index) => {
+            FrameKey::Label(thread.get_string(*string_index)?.to_owned())
+        }
+    })
+}
@@ -1,11 +1,12 @@
 use std::borrow::Cow;
-use std::cmp::Ordering;
+use std::cmp::{Ordering, Reverse};
 
 use serde::ser::{SerializeMap, Serializer};
 
 use crate::category::{Category, CategoryPairHandle};
 use crate::cpu_delta::CpuDelta;
-use crate::frame_table::{FrameTable, InternalFrame};
+use crate::fast_hash_map::FastHashMap;
+use crate::frame_table::{FrameTable, InternalFrame, InternalFrameLocation};
 use crate::func_table::FuncTable;
 use crate::global_lib_table::GlobalLibTable;
 use crate::marker_table::MarkerTable;
@@ -86,6 +87,14 @@ impl Thread {
         self.process
     }
 
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn is_main(&self) -> bool {
+        self.is_main
+    }
+
     pub fn convert_string_index(
         &mut self,
         global_table: &GlobalStringTable,
@@ -126,9 +135,10 @@ impl Thread {
         stack_index: Option<usize>,
         cpu_delta: CpuDelta,
         weight: i32,
+        cpu: Option<u32>,
     ) {
         self.samples
-            .add_sample(timestamp, stack_index, cpu_delta, weight);
+            .add_sample(timestamp, stack_index, cpu_delta, weight, cpu);
         self.last_sample_stack = stack_index;
         self.last_sample_was_zero_cpu = cpu_delta == CpuDelta::ZERO;
     }
@@ -153,7 +163,7 @@ impl Thread {
         } else {
             let stack_index = self.last_sample_stack;
             self.samples
-                .add_sample(timestamp, stack_index, CpuDelta::ZERO, weight);
+                .add_sample(timestamp, stack_index, CpuDelta::ZERO, weight, None);
             self.last_sample_was_zero_cpu = true;
         }
     }
@@ -185,6 +195,175 @@ impl Thread {
         self.markers.set_marker_stack(marker, stack_index);
     }
 
+    pub fn clear_marker_stacks(&mut self) {
+        self.markers.clear_marker_stacks();
+    }
+
+    pub fn clear_marker_fields(&mut self) {
+        self.markers.clear_marker_fields();
+    }
+
+    /// The stack with the highest total sample weight among the samples in the
+    /// time range, including both ends. Ties go to the lower stack index.
+    pub fn most_common_sample_stack(&self, start: Timestamp, end: Timestamp) -> Option<usize> {
+        let mut stack_weights: FastHashMap<usize, i64> = FastHashMap::default();
+        for (timestamp, stack_index, _cpu_delta, weight) in self.samples.iter() {
+            match stack_index {
+                Some(stack_index) if start <= timestamp && timestamp <= end => {
+                    *stack_weights.entry(stack_index).or_default() += i64::from(weight);
+                }
+                _ => {}
+            }
+        }
+        stack_weights
+            .into_iter()
+            .max_by_key(|&(stack_index, weight)| (weight, Reverse(stack_index)))
+            .map(|(stack_index, _weight)| stack_index)
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn marker_count(&self) -> usize {
+        self.markers.len()
+    }
+
+    pub fn get_string(&self, index: ThreadInternalStringIndex) -> Option<&str> {
+        self.string_table.get_string(index)
+    }
+
+    /// The timestamps of the earliest and the latest sample, if there are any samples.
+    pub fn sample_time_range(&self) -> Option<(Timestamp, Timestamp)> {
+        let mut timestamps = self.samples.iter().map(|(timestamp, ..)| timestamp);
+        let first = timestamps.next()?;
+        Some(timestamps.fold((first, first), |(start, end), timestamp| {
+            (start.min(timestamp), end.max(timestamp))
+        }))
+    }
+
+    /// Calls `f` for each sample, with the sample's timestamp, CPU delta, weight,
+    /// and the location of the sample's leaf frame. The leaf frame is `None` for
+    /// samples with an empty stack.
+    pub fn for_each_sample_with_leaf_frame(
+        &self,
+        mut f: impl FnMut(Timestamp, CpuDelta, i32, Option<&InternalFrameLocation>),
+    ) {
+        let frame_locations = self.frame_table.frame_locations();
+        for (timestamp, stack_index, cpu_delta, weight) in self.samples.iter() {
+            let leaf_frame = stack_index
+                .map(|stack_index| frame_locations[self.stack_table.frame_for_stack(stack_index)]);
+            f(timestamp, cpu_delta, weight, leaf_frame);
+        }
+    }
+
+    /// Calls `f` with the library name and the function name of each frame, if
+    /// it has at least one of them, and returns the results, indexed by frame
+    /// index. Frames without either get the default value. Frames in libraries
+    /// only have a function name if the library has a symbol table.
+    fn map_frame_names<T: Default>(
+        &self,
+        global_libs: &GlobalLibTable,
+        mut f: impl FnMut(Option<&str>, Option<&str>) -> T,
+    ) -> Vec<T> {
+        self.frame_table
+            .frame_locations()
+            .into_iter()
+            .map(|location| {
+                let (lib_name, function_name) = match location {
+                    InternalFrameLocation::UnknownAddress(_) => (None, None),
+                    InternalFrameLocation::AddressInLib(address, lib_index) => {
+                        match global_libs.get_lib(*lib_index) {
+                            Some(lib) => (
+                                Some(lib.name.as_str()),
+                                lib.symbol_table
+                                    .as_deref()
+                                    .and_then(|symbol_table| symbol_table.lookup(*address))
+                                    .map(|symbol| symbol.name.as_str()),
+                            ),
+                            None => (None, None),
+                        }
+                    }
+                    InternalFrameLocation::Label(string_index) => {
+                        (None, self.string_table.get_string(*string_index))
+                    }
+                };
+                if lib_name.is_none() && function_name.is_none() {
+                    return T::default();
+                }
+                f(lib_name, function_name)
+            })
+            .collect()
+    }
+
+    /// Calls `category_for_frame` with the library name and the function name
+    /// of each frame, if it has them, and gives the frame and its stacks the
+    /// returned category. Frames in libraries only have a function name if the
+    /// library has a symbol table.
+    pub fn categorize_frames(
+        &mut self,
+        global_libs: &GlobalLibTable,
+        category_for_frame: impl FnMut(Option<&str>, Option<&str>) -> Option<CategoryPairHandle>,
+    ) {
+        let frame_categories = self.map_frame_names(global_libs, category_for_frame);
+        for (frame_index, category) in frame_categories.iter().enumerate() {
+            if let Some(category) = category {
+                self.frame_table.set_frame_category(frame_index, *category);
+            }
+        }
+        self.stack_table
+            .set_categories_for_frames(&frame_categories);
+    }
+
+    /// Like [`Thread::categorize_frames`], but stacks without a categorized frame
+    /// take the category of their prefix stack if it got one, so that the category
+    /// applies to all stacks below a categorized frame.
+    pub fn categorize_stacks_below_frames(
+        &mut self,
+        global_libs: &GlobalLibTable,
+        category_for_frame: impl FnMut(Option<&str>, Option<&str>) -> Option<CategoryPairHandle>,
+    ) {
+        let frame_categories = self.map_frame_names(global_libs, category_for_frame);
+        for (frame_index, category) in frame_categories.iter().enumerate() {
+            if let Some(category) = category {
+                self.frame_table.set_frame_category(frame_index, *category);
+            }
+        }
+        self.stack_table
+            .set_categories_below_frames(&frame_categories);
+    }
+
+    /// Removes the frames for which `should_remove` returns true from the leaf
+    /// end of the stacks of the samples, allocations and markers. It's called
+    /// like the callback of [`Thread::categorize_frames`].
+    pub fn remove_leaf_frames(
+        &mut self,
+        global_libs: &GlobalLibTable,
+        mut should_remove: impl FnMut(Option<&str>, Option<&str>) -> bool,
+    ) {
+        let remove_frame = self.map_frame_names(global_libs, &mut should_remove);
+        if !remove_frame.contains(&true) {
+            return;
+        }
+        let stack_map = self.stack_table.stacks_without_leaf_frames(&remove_frame);
+        self.samples.map_stacks(&stack_map);
+        if let Some(allocations) = &mut self.native_allocations {
+            allocations.map_stacks(&stack_map);
+        }
+        self.markers.map_stacks(&stack_map);
+        self.last_sample_stack = self.last_sample_stack.and_then(|stack| stack_map[stack]);
+        // The last sample's stack may have changed, so don't let
+        // add_sample_same_stack_zero_cpu modify it.
+        self.last_sample_was_zero_cpu = false;
+    }
+
+    pub fn downsample(&mut self, factor: usize) {
+        self.samples.downsample(factor);
+        // The last sample may have been merged into a different sample, so
+        // don't let add_sample_same_stack_zero_cpu modify it.
+        self.last_sample_was_zero_cpu = false;
+    }
+
     pub fn contains_js_function(&self) -> bool {
         self.func_table.contains_js_function()
     }
@@ -0,0 +1,101 @@
+use std::cmp::Ordering;
+use std::time::Duration;
+
+use crate::thread::Thread;
+use crate::timestamp::Timestamp;
+
+/// The order of the processes and threads in the profile, set with
+/// [`Profile::set_thread_order`](crate::Profile::set_thread_order).
+///
+/// Any order other than [`ThreadOrder::Default`] asks the Firefox Profiler UI to
+/// keep the order from the profile. Within each process, the main thread always
+/// comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThreadOrder {
+    /// Processes and threads by start time. The Firefox Profiler UI may reorder them.
+    #[default]
+    Default,
+    /// The processes and threads with the most CPU time first, or with the most
+    /// samples, if the samples don't have CPU deltas.
+    CpuUsage,
+    /// Processes and threads by name.
+    Name,
+    /// Processes and threads by the time of their first sample. The ones without
+    /// samples come last.
+    FirstSampleTime,
+}
+
+/// The numbers which the thread order and the idle thread hints are based on.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ThreadActivity {
+    cpu_time: Duration,
+    sample_count: usize,
+    first_sample_time: Option<Timestamp>,
+}
+
+impl ThreadActivity {
+    pub fn of_thread(thread: &Thread) -> Self {
+        let mut activity = Self::default();
+        thread.for_each_sample_with_leaf_frame(|timestamp, cpu_delta, _weight, _leaf| {
+            activity.cpu_time += Duration::from(cpu_delta);
+            activity.sample_count += 1;
+            activity.first_sample_time = Some(match activity.first_sample_time {
+                Some(first_sample_time) => first_sample_time.min(timestamp),
+                None => timestamp,
+            });
+        });
+        activity
+    }
+
+    /// Combines the activity of several threads, e.g. of all threads of a process.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            cpu_time: self.cpu_time + other.cpu_time,
+            sample_count: self.sample_count + other.sample_count,
+            first_sample_time: match (self.first_sample_time, other.first_sample_time) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+
+    /// Orders the busier activity first.
+    pub fn cmp_busiest_first(&self, other: &Self) -> Ordering {
+        other
+            .cpu_time
+            .cmp(&self.cpu_time)
+            .then(other.sample_count.cmp(&self.sample_count))
+    }
+
+    /// Orders the earlier first sample first, and activity without samples last.
+    pub fn cmp_first_sample_time(&self, other: &Self) -> Ordering {
+        match (self.first_sample_time, other.first_sample_time) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+
+    /// Whether this thread used less than 1% of the CPU time of the busiest
+    /// thread. If none of the samples have CPU deltas, sample counts are
+    /// compared instead. Threads without samples are always idle.
+    pub fn is_idle(&self, max_cpu_time: Duration, max_sample_count: usize) -> bool {
+        if self.sample_count == 0 {
+            return true;
+        }
+        if !max_cpu_time.is_zero() {
+            self.cpu_time * 100 < max_cpu_time
+        } else {
+            self.sample_count * 100 < max_sample_count
+        }
+    }
+
+    pub fn cpu_time(&self) -> Duration {
+        self.cpu_time
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.sample_count
+    }
+}
@@ -4,10 +4,10 @@ use std::time::Duration;
 use assert_json_diff::assert_json_eq;
 use debugid::DebugId;
 use fxprof_processed_profile::{
-    CategoryColor, CategoryHandle, CpuDelta, Frame, FrameFlags, FrameInfo, LibraryInfo,
-    MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema, MarkerStaticField,
-    MarkerTiming, Profile, ReferenceTimestamp, SamplingInterval, StaticSchemaMarker, StringHandle,
-    Symbol, SymbolTable, Timestamp,
+    CategoryColor, CategoryHandle, CpuDelta, Frame, FrameFlags, FrameInfo, HotFrameLocation,
+    LibraryInfo, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, MarkerTiming, Profile, ReferenceTimestamp, SamplingInterval,
+    StaticSchemaMarker, StringHandle, Symbol, SymbolTable, ThreadOrder, Timestamp,
 };
 use serde_json::json;
 
@@ -1586,3 +1586,627 @@ fn profile_counters_with_sorted_processes() {
         )
     )
 }
+
+#[test]
+fn profile_summary() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    let process0 = profile.add_process("busy", 123, Timestamp::from_millis_since_reference(0.0));
+    let process1 = profile.add_process("idle", 456, Timestamp::from_millis_since_reference(0.0));
+    let thread0 = profile.add_thread(
+        process0,
+        123,
+        Timestamp::from_millis_since_reference(0.0),
+        true,
+    );
+    let thread1 = profile.add_thread(
+        process1,
+        456,
+        Timestamp::from_millis_since_reference(0.0),
+        true,
+    );
+    let category = profile.add_category("Regular", CategoryColor::Blue);
+    let hot = profile.intern_string("hot");
+    let cold = profile.intern_string("cold");
+    let frame = |name| FrameInfo {
+        frame: Frame::Label(name),
+        category_pair: category.into(),
+        flags: FrameFlags::empty(),
+    };
+
+    for i in 0..4 {
+        let leaf = if i == 3 { cold } else { hot };
+        profile.add_sample(
+            thread0,
+            Timestamp::from_millis_since_reference(i as f64),
+            vec![frame(cold), frame(leaf)].into_iter(),
+            CpuDelta::from_millis(1.0),
+            1,
+        );
+    }
+    profile.add_sample(
+        thread1,
+        Timestamp::from_millis_since_reference(4.0),
+        vec![frame(cold)].into_iter(),
+        CpuDelta::ZERO,
+        1,
+    );
+    let marker = TextMarker {
+        name: profile.intern_string("Experimental"),
+        text: profile.intern_string("Hello world!"),
+    };
+    profile.add_marker(
+        thread1,
+        MarkerTiming::Instant(Timestamp::from_millis_since_reference(4.0)),
+        marker,
+    );
+
+    let summary = profile.summary(1, 2);
+    assert_eq!(summary.sampled_duration, Duration::from_millis(4));
+    assert_eq!(summary.sample_count, 5);
+    assert_eq!(summary.marker_count, 1);
+
+    assert_eq!(summary.processes.len(), 2);
+    assert_eq!(summary.processes[0].name, "busy");
+    assert_eq!(summary.processes[0].cpu_time, Duration::from_millis(4));
+    assert_eq!(summary.processes[0].sample_count, 4);
+    assert_eq!(summary.processes[0].samples_per_bucket, vec![2, 2]);
+    assert_eq!(summary.processes[1].name, "idle");
+    assert_eq!(summary.processes[1].cpu_time, Duration::ZERO);
+    assert_eq!(summary.processes[1].samples_per_bucket, vec![0, 1]);
+
+    assert_eq!(summary.hottest_frames.len(), 1);
+    assert_eq!(
+        summary.hottest_frames[0].location,
+        HotFrameLocation::Label("hot".to_string())
+    );
+    assert_eq!(summary.hottest_frames[0].self_weight, 3);
+
+    let ms = Timestamp::from_millis_since_reference;
+    assert_eq!(profile.sample_time_range(), Some((ms(0.0), ms(4.0))));
+    assert_eq!(
+        profile.cpu_time_per_bucket(&[process0, process1], Duration::from_millis(2), 100),
+        vec![
+            (ms(0.0), Duration::from_millis(2)),
+            (ms(2.0), Duration::from_millis(2)),
+            (ms(4.0), Duration::ZERO),
+        ]
+    );
+    assert_eq!(
+        profile.cpu_time_per_bucket(&[process0], Duration::ZERO, 1),
+        vec![(ms(0.0), Duration::from_millis(4))]
+    );
+}
+
+#[test]
+fn profile_extra_meta_info() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    profile.add_extra_meta_info("Clock", "Timestamp clock", "CLOCK_MONOTONIC");
+    profile.add_extra_meta_info("Machine", "CPU", "Example CPU");
+    profile.add_extra_meta_info("Clock", "Clock zero", "2021-11-06T01:30:32Z");
+
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    assert_json_eq!(
+        profile_json["meta"]["extra"],
+        json!([
+            {
+                "label": "Clock",
+                "entries": [
+                    { "label": "Timestamp clock", "format": "string", "value": "CLOCK_MONOTONIC" },
+                    { "label": "Clock zero", "format": "string", "value": "2021-11-06T01:30:32Z" },
+                ]
+            },
+            {
+                "label": "Machine",
+                "entries": [
+                    { "label": "CPU", "format": "string", "value": "Example CPU" },
+                ]
+            },
+        ])
+    );
+}
+
+#[test]
+fn profile_profiling_start_time() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    assert_eq!(profile_json["meta"].get("profilingStartTime"), None);
+
+    profile.set_profiling_start_time(Timestamp::from_millis_since_reference(1500.0));
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    assert_eq!(profile_json["meta"]["startTime"], json!(1636162232627.0));
+    assert_eq!(profile_json["meta"]["profilingStartTime"], json!(1500.0));
+}
+
+#[test]
+fn profile_sample_cpus() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    let process = profile.add_process("test", 123, Timestamp::from_millis_since_reference(0.0));
+    let thread = profile.add_thread(
+        process,
+        12345,
+        Timestamp::from_millis_since_reference(0.0),
+        true,
+    );
+    profile.add_sample(
+        thread,
+        Timestamp::from_millis_since_reference(1.0),
+        vec![].into_iter(),
+        CpuDelta::ZERO,
+        1,
+    );
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    assert_eq!(profile_json["threads"][0]["samples"].get("cpu"), None);
+    assert_eq!(profile_json["meta"].get("cpuCoreTypes"), None);
+
+    // Out of order, so that the cpu column is permuted together with the others.
+    profile.add_sample_on_cpu(
+        thread,
+        Timestamp::from_millis_since_reference(3.0),
+        vec![].into_iter(),
+        CpuDelta::ZERO,
+        1,
+        2,
+    );
+    profile.add_sample_on_cpu(
+        thread,
+        Timestamp::from_millis_since_reference(2.0),
+        vec![].into_iter(),
+        CpuDelta::ZERO,
+        1,
+        0,
+    );
+    profile.set_cpu_core_type(0, "Performance");
+    profile.set_cpu_core_type(2, "Efficiency");
+
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    assert_eq!(
+        profile_json["threads"][0]["samples"]["time"],
+        json!([1.0, 2.0, 3.0])
+    );
+    assert_eq!(
+        profile_json["threads"][0]["samples"]["cpu"],
+        json!([null, 0, 2])
+    );
+    assert_eq!(
+        profile_json["meta"]["cpuCoreTypes"],
+        json!(["Performance", null, "Efficiency"])
+    );
+}
+
+#[test]
+fn profile_categorize_frames_by_name() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+    let thread = profile.add_thread(
+        process,
+        123,
+        Timestamp::from_millis_since_reference(0.0),
+        true,
+    );
+    let category = profile.add_category("Regular", CategoryColor::Blue);
+    let main = profile.intern_string("mycrate::main");
+    let helper = profile.intern_string("helper");
+    let push = profile.intern_string("alloc::vec::Vec<T>::push");
+    let frame = |name| FrameInfo {
+        frame: Frame::Label(name),
+        category_pair: category.into(),
+        flags: FrameFlags::empty(),
+    };
+    profile.add_sample(
+        thread,
+        Timestamp::from_millis_since_reference(0.0),
+        vec![frame(main), frame(helper), frame(push)].into_iter(),
+        CpuDelta::ZERO,
+        1,
+    );
+
+    profile.categorize_frames_by_name(|name| match name.split_once("::") {
+        Some((namespace, _)) => Some((namespace.to_string(), CategoryColor::Green)),
+        None if name == "helper" => Some(("Regular".to_string(), CategoryColor::Red)),
+        None => None,
+    });
+
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    let category_names: Vec<&str> = profile_json["meta"]["categories"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|category| category["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(category_names, vec!["Other", "Regular", "mycrate", "alloc"]);
+    let thread_json = &profile_json["threads"][0];
+    assert_eq!(thread_json["frameTable"]["category"], json!([2, 1, 3]));
+    assert_eq!(thread_json["stackTable"]["category"], json!([2, 1, 3]));
+}
+
+#[test]
+fn profile_thread_order_and_idle_threads() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    let ms = Timestamp::from_millis_since_reference;
+    let early_process = profile.add_process("early", 1, ms(0.0));
+    let early_main = profile.add_thread(early_process, 1, ms(0.0), true);
+    let early_worker = profile.add_thread(early_process, 11, ms(0.0), false);
+    profile.set_thread_name(early_worker, "b-worker");
+    let busy_process = profile.add_process("busy", 2, ms(1.0));
+    let busy_main = profile.add_thread(busy_process, 2, ms(1.0), true);
+    let busy_worker = profile.add_thread(busy_process, 21, ms(1.0), false);
+    profile.set_thread_name(busy_worker, "a-worker");
+
+    let category = profile.add_category("Regular", CategoryColor::Blue);
+    let frame = FrameInfo {
+        frame: Frame::Label(profile.intern_string("work")),
+        category_pair: category.into(),
+        flags: FrameFlags::empty(),
+    };
+    for (thread, time, cpu_ms) in [
+        (early_main, 1.0, 1.0),
+        (busy_main, 2.0, 0.0),
+        (busy_worker, 3.0, 10.0),
+    ] {
+        profile.add_sample(
+            thread,
+            ms(time),
+            vec![frame.clone()].into_iter(),
+            CpuDelta::from_millis(cpu_ms),
+            1,
+        );
+    }
+
+    let thread_names = |profile: &Profile| -> Vec<String> {
+        let profile_json = serde_json::to_value(profile).unwrap();
+        profile_json["threads"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|thread| thread["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    assert_eq!(profile_json["meta"].get("keepProfileThreadOrder"), None);
+    assert_eq!(profile_json["meta"].get("initialVisibleThreads"), None);
+    assert_eq!(
+        thread_names(&profile),
+        vec!["early", "b-worker", "busy", "a-worker"]
+    );
+
+    profile.set_thread_order(ThreadOrder::CpuUsage);
+    assert_eq!(
+        thread_names(&profile),
+        vec!["busy", "a-worker", "early", "b-worker"]
+    );
+    profile.set_thread_order(ThreadOrder::Name);
+    assert_eq!(
+        thread_names(&profile),
+        vec!["busy", "a-worker", "early", "b-worker"]
+    );
+    profile.set_thread_order(ThreadOrder::FirstSampleTime);
+    assert_eq!(
+        thread_names(&profile),
+        vec!["early", "b-worker", "busy", "a-worker"]
+    );
+
+    // The idle main thread of "busy" stays visible because of its busy worker.
+    profile.set_thread_order(ThreadOrder::CpuUsage);
+    profile.set_hide_idle_threads(true);
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    assert_eq!(profile_json["meta"]["keepProfileThreadOrder"], json!(true));
+    assert_eq!(
+        profile_json["meta"]["initialVisibleThreads"],
+        json!([0, 1, 2])
+    );
+}
+
+#[test]
+fn profile_categorize_frames_by_lib_name() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+    let thread = profile.add_thread(
+        process,
+        123,
+        Timestamp::from_millis_since_reference(0.0),
+        true,
+    );
+    let category = profile.add_category("Regular", CategoryColor::Blue);
+    let libc = profile.add_lib(LibraryInfo {
+        name: "libc.so.6".to_string(),
+        debug_name: "libc.so.6".to_string(),
+        path: "/usr/lib/libc.so.6".to_string(),
+        code_id: None,
+        debug_path: "/usr/lib/libc.so.6".to_string(),
+        debug_id: DebugId::nil(),
+        arch: None,
+        symbol_table: None,
+    });
+    let main = profile.intern_string("main");
+    let frames = vec![
+        FrameInfo {
+            frame: Frame::Label(main),
+            category_pair: category.into(),
+            flags: FrameFlags::empty(),
+        },
+        FrameInfo {
+            frame: Frame::RelativeAddressFromInstructionPointer(libc, 0x1234),
+            category_pair: category.into(),
+            flags: FrameFlags::empty(),
+        },
+    ];
+    profile.add_sample(
+        thread,
+        Timestamp::from_millis_since_reference(0.0),
+        frames.into_iter(),
+        CpuDelta::ZERO,
+        1,
+    );
+
+    let mut calls = Vec::new();
+    profile.categorize_frames(|lib_name, function_name| {
+        calls.push((
+            lib_name.map(str::to_owned),
+            function_name.map(str::to_owned),
+        ));
+        match lib_name {
+            Some("libc.so.6") => Some(("libc".to_string(), CategoryColor::Orange)),
+            _ => None,
+        }
+    });
+    assert_eq!(
+        calls,
+        vec![
+            (None, Some("main".to_string())),
+            (Some("libc.so.6".to_string()), None)
+        ]
+    );
+
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    assert_eq!(profile_json["meta"]["categories"][2]["name"], json!("libc"));
+    let thread_json = &profile_json["threads"][0];
+    assert_eq!(thread_json["frameTable"]["category"], json!([1, 2]));
+    assert_eq!(thread_json["stackTable"]["category"], json!([1, 2]));
+}
+
+#[test]
+fn profile_remove_leaf_frames() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+    let thread = profile.add_thread(
+        process,
+        123,
+        Timestamp::from_millis_since_reference(0.0),
+        true,
+    );
+    let category = profile.add_category("Regular", CategoryColor::Blue);
+    let label_frames = |profile: &mut Profile, names: &[&str]| -> Vec<FrameInfo> {
+        names
+            .iter()
+            .map(|name| FrameInfo {
+                frame: Frame::Label(profile.intern_string(name)),
+                category_pair: category.into(),
+                flags: FrameFlags::empty(),
+            })
+            .collect()
+    };
+    // Root first. The first sample has a signal trampoline in the middle of
+    // the stack, which must be kept, and two at the leaf end.
+    for (time, names) in [
+        (
+            0.0,
+            &["main", "_sigtramp", "handler", "_sigtramp", "_sigtramp"][..],
+        ),
+        (1.0, &["main", "work"][..]),
+    ] {
+        let frames = label_frames(&mut profile, names);
+        profile.add_sample(
+            thread,
+            Timestamp::from_millis_since_reference(time),
+            frames.into_iter(),
+            CpuDelta::ZERO,
+            1,
+        );
+    }
+
+    profile.remove_leaf_frames(|_lib_name, function_name| function_name == Some("_sigtramp"));
+
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    let thread_json = &profile_json["threads"][0];
+    // Stacks: 0 main, 1 _sigtramp, 2 handler, 3 _sigtramp, 4 _sigtramp, 5 work
+    assert_eq!(
+        thread_json["stackTable"]["prefix"],
+        json!([null, 0, 1, 2, 3, 0])
+    );
+    assert_eq!(thread_json["samples"]["stack"], json!([2, 5]));
+}
+
+#[test]
+fn profile_categorize_stacks_below_frames() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+    let thread = profile.add_thread(
+        process,
+        123,
+        Timestamp::from_millis_since_reference(0.0),
+        true,
+    );
+    let category = profile.add_category("Regular", CategoryColor::Blue);
+    for (time, names) in [
+        (0.0, &["main", "__x64_sys_read", "vfs_read"][..]),
+        (1.0, &["main", "compute"][..]),
+    ] {
+        let frames: Vec<FrameInfo> = names
+            .iter()
+            .map(|name| FrameInfo {
+                frame: Frame::Label(profile.intern_string(name)),
+                category_pair: category.into(),
+                flags: FrameFlags::empty(),
+            })
+            .collect();
+        profile.add_sample(
+            thread,
+            Timestamp::from_millis_since_reference(time),
+            frames.into_iter(),
+            CpuDelta::ZERO,
+            1,
+        );
+    }
+
+    profile.categorize_stacks_below_frames(|_lib_name, function_name| {
+        let syscall = function_name?.strip_prefix("__x64_sys_")?;
+        Some((
+            "Syscall".to_string(),
+            CategoryColor::Red,
+            Some(syscall.to_string()),
+        ))
+    });
+
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    assert_eq!(
+        profile_json["meta"]["categories"][2]["name"],
+        json!("Syscall")
+    );
+    assert_eq!(
+        profile_json["meta"]["categories"][2]["subcategories"],
+        json!(["read", "Other"])
+    );
+    let thread_json = &profile_json["threads"][0];
+    // Stacks: 0 main, 1 __x64_sys_read, 2 vfs_read, 3 compute
+    assert_eq!(thread_json["stackTable"]["category"], json!([1, 2, 2, 1]));
+    assert_eq!(
+        thread_json["stackTable"]["subcategory"],
+        json!([0, 0, 0, 0])
+    );
+    assert_eq!(thread_json["frameTable"]["category"], json!([1, 2, 1, 1]));
+}
+
+#[test]
+fn profile_set_marker_stack_from_samples() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+    let thread = profile.add_thread(
+        process,
+        123,
+        Timestamp::from_millis_since_reference(0.0),
+        true,
+    );
+    let category = profile.add_category("Regular", CategoryColor::Blue);
+    for (time, names, weight) in [
+        (0.0, &["main", "wait"][..], 5),
+        (1.0, &["main", "compute"][..], 2),
+        (2.0, &["main", "layout"][..], 3),
+        (3.0, &["main", "compute"][..], 2),
+        (4.0, &["main", "paint"][..], 1),
+    ] {
+        let frames: Vec<FrameInfo> = names
+            .iter()
+            .map(|name| FrameInfo {
+                frame: Frame::Label(profile.intern_string(name)),
+                category_pair: category.into(),
+                flags: FrameFlags::empty(),
+            })
+            .collect();
+        profile.add_sample(
+            thread,
+            Timestamp::from_millis_since_reference(time),
+            frames.into_iter(),
+            CpuDelta::ZERO,
+            weight,
+        );
+    }
+
+    let add_marker = |profile: &mut Profile, start: f64, end: f64| {
+        let marker = TextMarker {
+            name: profile.intern_string("Hang"),
+            text: profile.intern_string(""),
+        };
+        let start = Timestamp::from_millis_since_reference(start);
+        let end = Timestamp::from_millis_since_reference(end);
+        let handle = profile.add_marker(thread, MarkerTiming::Interval(start, end), marker);
+        profile.set_marker_stack_from_samples(thread, handle, start, end);
+    };
+    // "layout" has a higher weight than each "compute" sample, but "compute"
+    // has the higher total.
+    add_marker(&mut profile, 1.0, 4.0);
+    add_marker(&mut profile, 10.0, 20.0);
+
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    let thread_json = &profile_json["threads"][0];
+    // Stacks: 0 main, 1 wait, 2 compute, 3 layout, 4 paint
+    assert_eq!(
+        thread_json["markers"]["data"][0]["cause"]["stack"],
+        json!(2)
+    );
+    assert_eq!(thread_json["markers"]["data"][1]["cause"], json!(null));
+}
+
+#[test]
+fn profile_clear_marker_fields() {
+    let mut profile = Profile::new(
+        "test",
+        ReferenceTimestamp::from_millis_since_unix_epoch(1636162232627.0),
+        SamplingInterval::from_millis(1),
+    );
+    let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+    let thread = profile.add_thread(
+        process,
+        123,
+        Timestamp::from_millis_since_reference(0.0),
+        true,
+    );
+    let add_marker = |profile: &mut Profile, time: f64, text: &str| {
+        let marker = TextMarker {
+            name: profile.intern_string("Log"),
+            text: profile.intern_string(text),
+        };
+        let time = Timestamp::from_millis_since_reference(time);
+        profile.add_marker(thread, MarkerTiming::Instant(time), marker);
+    };
+    add_marker(&mut profile, 1.0, "before");
+    profile.clear_marker_fields();
+    add_marker(&mut profile, 2.0, "after");
+
+    let profile_json = serde_json::to_value(&profile).unwrap();
+    let markers_json = &profile_json["threads"][0]["markers"];
+    assert_eq!(markers_json["length"], json!(2));
+    assert_eq!(markers_json["data"][0], json!({ "type": "Text" }));
+    assert_eq!(markers_json["data"][1], json!({ "type": "Text" }));
+    assert_eq!(markers_json["startTime"], json!([1.0, 2.0]));
+}
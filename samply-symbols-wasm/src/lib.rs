@@ -0,0 +1,236 @@
+//! WebAssembly bindings for `samply-symbols`, so that the profiler front end
+//! can symbolicate profiles in the browser, from binaries and debug files that
+//! the user opened locally, without running a native symbol server.
+//!
+//! The file access is done by a JavaScript object, see the `FileAndPathHelper`
+//! interface in the README. Files are read into memory as a whole; there's no
+//! memory mapping in the browser.
+//!
+//! Build with `wasm-pack build --target web samply-symbols-wasm`.
+
+use std::fmt::Display;
+use std::pin::Pin;
+
+use js_sys::{Array, Promise, Uint32Array, Uint8Array};
+use samply_api::samply_symbols::{
+    self, CandidatePathInfo, CompactSymbolTable, FileAndPathHelperResult, FileLocation,
+    LibraryInfo, OptionallySendFuture, SymbolManager,
+};
+use samply_api::Api;
+use serde_json::json;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+
+#[wasm_bindgen(typescript_custom_section)]
+const TYPESCRIPT_TYPES: &str = r#"
+export interface LibraryInfo {
+  debugName?: string;
+  breakpadId?: string;
+  debugPath?: string;
+  name?: string;
+  codeId?: string;
+  path?: string;
+  arch?: string;
+}
+
+export interface FileAndPathHelper {
+  getCandidatePathsForDebugFile(info: LibraryInfo): string[];
+  getCandidatePathsForBinary(info: LibraryInfo): string[];
+  readFile(path: string): Promise<Uint8Array>;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    /// The JavaScript object which finds and reads the files.
+    #[wasm_bindgen(typescript_type = "FileAndPathHelper")]
+    pub type FileAndPathHelper;
+
+    #[wasm_bindgen(catch, method, js_name = getCandidatePathsForDebugFile)]
+    fn get_candidate_paths_for_debug_file(
+        this: &FileAndPathHelper,
+        info: JsValue,
+    ) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(catch, method, js_name = getCandidatePathsForBinary)]
+    fn get_candidate_paths_for_binary(
+        this: &FileAndPathHelper,
+        info: JsValue,
+    ) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(catch, method, js_name = readFile)]
+    fn read_file(this: &FileAndPathHelper, path: &str) -> Result<Promise, JsValue>;
+}
+
+/// Runs a query against the symbolication API of `samply-api`, for example
+/// `/symbolicate/v5`. The returned promise resolves to the JSON response.
+#[wasm_bindgen(js_name = queryAPI)]
+pub fn query_api(url: String, request_json: String, helper: FileAndPathHelper) -> Promise {
+    future_to_promise(async move {
+        let symbol_manager = SymbolManager::with_helper(JsHelper { helper });
+        let response = Api::new(&symbol_manager)
+            .query_api(&url, &request_json)
+            .await;
+        Ok(response.into())
+    })
+}
+
+/// Gets the symbol table of a library in the "SymbolTableAsTuple" format of
+/// the profiler: `[addrs: Uint32Array, index: Uint32Array, buffer: Uint8Array]`.
+#[wasm_bindgen(js_name = getCompactSymbolTable)]
+pub fn get_compact_symbol_table(
+    debug_name: String,
+    breakpad_id: String,
+    helper: FileAndPathHelper,
+) -> Promise {
+    future_to_promise(async move {
+        let debug_id = samply_symbols::debugid::DebugId::from_breakpad(&breakpad_id)
+            .map_err(|_| JsError::new(&format!("Invalid breakpad ID {breakpad_id}")))?;
+        let info = LibraryInfo {
+            debug_name: Some(debug_name),
+            debug_id: Some(debug_id),
+            ..Default::default()
+        };
+        let symbol_manager = SymbolManager::with_helper(JsHelper { helper });
+        let symbol_map = symbol_manager
+            .load_symbol_map(&info)
+            .await
+            .map_err(|e| JsError::new(&e.to_string()))?;
+        let table = CompactSymbolTable::from_symbol_map(&symbol_map);
+        let tuple = Array::of3(
+            &Uint32Array::from(&table.addr[..]),
+            &Uint32Array::from(&table.index[..]),
+            &Uint8Array::from(&table.buffer[..]),
+        );
+        Ok(tuple.into())
+    })
+}
+
+struct JsHelper {
+    helper: FileAndPathHelper,
+}
+
+impl JsHelper {
+    fn candidate_paths(
+        &self,
+        info: &LibraryInfo,
+        get_paths: fn(&FileAndPathHelper, JsValue) -> Result<JsValue, JsValue>,
+    ) -> FileAndPathHelperResult<Vec<CandidatePathInfo<WasmFileLocation>>> {
+        let paths = get_paths(&self.helper, library_info_to_js(info)).map_err(js_error)?;
+        let paths = Array::from(&paths)
+            .iter()
+            .filter_map(|path| path.as_string())
+            .map(|path| CandidatePathInfo::SingleFile(WasmFileLocation(path)))
+            .collect();
+        Ok(paths)
+    }
+}
+
+impl samply_symbols::FileAndPathHelper for JsHelper {
+    type F = Vec<u8>;
+    type FL = WasmFileLocation;
+
+    fn get_candidate_paths_for_debug_file(
+        &self,
+        info: &LibraryInfo,
+    ) -> FileAndPathHelperResult<Vec<CandidatePathInfo<WasmFileLocation>>> {
+        self.candidate_paths(info, FileAndPathHelper::get_candidate_paths_for_debug_file)
+    }
+
+    fn get_candidate_paths_for_binary(
+        &self,
+        info: &LibraryInfo,
+    ) -> FileAndPathHelperResult<Vec<CandidatePathInfo<WasmFileLocation>>> {
+        self.candidate_paths(info, FileAndPathHelper::get_candidate_paths_for_binary)
+    }
+
+    fn get_dyld_shared_cache_paths(
+        &self,
+        _arch: Option<&str>,
+    ) -> FileAndPathHelperResult<Vec<WasmFileLocation>> {
+        Ok(Vec::new())
+    }
+
+    fn load_file(
+        &self,
+        location: WasmFileLocation,
+    ) -> Pin<Box<dyn OptionallySendFuture<Output = FileAndPathHelperResult<Self::F>> + '_>> {
+        Box::pin(async move {
+            let promise = self.helper.read_file(&location.0).map_err(js_error)?;
+            let contents = JsFuture::from(promise).await.map_err(js_error)?;
+            let contents: Uint8Array = contents
+                .dyn_into()
+                .map_err(|_| format!("readFile({location}) didn't return a Uint8Array"))?;
+            Ok(contents.to_vec())
+        })
+    }
+}
+
+fn library_info_to_js(info: &LibraryInfo) -> JsValue {
+    let info = json!({
+        "debugName": info.debug_name,
+        "breakpadId": info.debug_id.map(|debug_id| debug_id.breakpad().to_string()),
+        "debugPath": info.debug_path,
+        "name": info.name,
+        "codeId": info.code_id.as_ref().map(ToString::to_string),
+        "path": info.path,
+        "arch": info.arch,
+    });
+    js_sys::JSON::parse(&info.to_string()).unwrap_or(JsValue::NULL)
+}
+
+/// Turns a JavaScript exception into an error which can cross threads, by
+/// only keeping its message.
+fn js_error(value: JsValue) -> samply_symbols::FileAndPathHelperError {
+    let message = match value.dyn_ref::<js_sys::Error>() {
+        Some(error) => String::from(error.message()),
+        None => value.as_string().unwrap_or_else(|| format!("{value:?}")),
+    };
+    message.into()
+}
+
+/// A path, or any other string which the JavaScript helper understands.
+#[derive(Clone, Debug)]
+pub struct WasmFileLocation(String);
+
+impl Display for WasmFileLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FileLocation for WasmFileLocation {
+    fn location_for_dyld_subcache(&self, suffix: &str) -> Option<Self> {
+        Some(Self(format!("{}{suffix}", self.0)))
+    }
+
+    fn location_for_external_object_file(&self, object_file: &str) -> Option<Self> {
+        Some(Self(object_file.into()))
+    }
+
+    fn location_for_pdb_from_binary(&self, pdb_path_in_binary: &str) -> Option<Self> {
+        Some(Self(pdb_path_in_binary.into()))
+    }
+
+    fn location_for_source_file(&self, source_file_path: &str) -> Option<Self> {
+        Some(Self(source_file_path.into()))
+    }
+
+    fn location_for_breakpad_symindex(&self) -> Option<Self> {
+        let file_name_start = self.0.rfind(['/', '\\']).map_or(0, |i| i + 1);
+        let stem = match self.0[file_name_start..].rfind('.') {
+            Some(i) => &self.0[..file_name_start + i],
+            None => &self.0,
+        };
+        Some(Self(format!("{stem}.symindex")))
+    }
+
+    fn location_for_dwo(&self, _comp_dir: &str, _path: &str) -> Option<Self> {
+        None // TODO
+    }
+
+    fn location_for_dwp(&self) -> Option<Self> {
+        Some(Self(format!("{}.dwp", self.0)))
+    }
+}
@@ -3,7 +3,10 @@ use std::marker::PhantomData;
 use addr2line::{fallible_iterator, gimli};
 use elsa::sync::FrozenVec;
 use fallible_iterator::FallibleIterator;
-use gimli::{DwarfPackage, EndianSlice, Reader, RunTimeEndian, SectionId};
+use gimli::{
+    AttributeValue, DebuggingInformationEntry, DwarfPackage, EndianSlice, Reader, RunTimeEndian,
+    SectionId, UnitOffset, UnitRef,
+};
 use object::read::ReadRef;
 use object::CompressionFormat;
 
@@ -15,17 +18,38 @@ pub fn get_frames<R: Reader>(
     address: u64,
     context: Option<&addr2line::Context<R>>,
     path_mapper: &mut PathMapper<()>,
+    full_signatures: bool,
 ) -> Option<Vec<FrameDebugInfo>> {
-    let frame_iter = context?.find_frames(address).skip_all_loads().ok()?;
-    convert_frames(frame_iter, path_mapper)
+    let context = context?;
+    let frame_iter = context.find_frames(address).skip_all_loads().ok()?;
+    let signature_unit = signature_unit(context, address, full_signatures);
+    convert_frames(frame_iter, path_mapper, signature_unit)
+}
+
+/// The unit whose DIEs the frames at `address` refer to, if we want to add
+/// parameter types to the function names. Split DWARF units are skipped: the
+/// frames' DIE offsets refer to the DWO unit, which this wouldn't load.
+pub fn signature_unit<R: Reader>(
+    context: &addr2line::Context<R>,
+    address: u64,
+    full_signatures: bool,
+) -> Option<UnitRef<'_, R>> {
+    if !full_signatures {
+        return None;
+    }
+    match context.find_dwarf_and_unit(address) {
+        addr2line::LookupResult::Output(unit) => unit,
+        addr2line::LookupResult::Load { .. } => None,
+    }
 }
 
 pub fn convert_frames<'a, R: gimli::Reader>(
     frame_iter: impl FallibleIterator<Item = addr2line::Frame<'a, R>>,
     path_mapper: &mut PathMapper<()>,
+    signature_unit: Option<UnitRef<R>>,
 ) -> Option<Vec<FrameDebugInfo>> {
     let frames: Vec<_> = frame_iter
-        .map(|f| Ok(convert_stack_frame(f, &mut *path_mapper)))
+        .map(|f| Ok(convert_stack_frame(f, &mut *path_mapper, signature_unit)))
         .collect()
         .ok()?;
 
@@ -36,14 +60,21 @@ pub fn convert_frames<'a, R: gimli::Reader>(
     }
 }
 
+/// If `signature_unit` is given, function names without a parameter list get
+/// the parameter types from the debug info.
 pub fn convert_stack_frame<R: gimli::Reader>(
     frame: addr2line::Frame<R>,
     path_mapper: &mut PathMapper<()>,
+    signature_unit: Option<UnitRef<R>>,
 ) -> FrameDebugInfo {
     let function = match frame.function {
         Some(function_name) => {
             if let Ok(name) = function_name.raw_name() {
-                Some(demangle::demangle_any(&name))
+                let mut name = demangle::demangle_any(&name);
+                if let (Some(unit), Some(offset)) = (signature_unit, frame.dw_die_offset) {
+                    add_parameter_types(&mut name, function_name.language, unit, offset);
+                }
+                Some(name)
             } else {
                 None
             }
@@ -62,6 +93,194 @@ pub fn convert_stack_frame<R: gimli::Reader>(
     }
 }
 
+/// Appends the parameter types to a function name which doesn't have them,
+/// e.g. `add` becomes `add(int, long)`. DWARF names only lack the parameter
+/// list if the function has no linkage name, which is the case for C functions
+/// and for `extern "C"` functions in C++. The types are written the way the
+/// C++ demangler writes them, so this is only done for C-family languages.
+fn add_parameter_types<R: Reader>(
+    name: &mut String,
+    language: Option<gimli::DwLang>,
+    unit: UnitRef<R>,
+    offset: UnitOffset<R::Offset>,
+) {
+    if name.contains('(') || !language.is_some_and(is_c_family_language) {
+        return;
+    }
+    if let Some(parameter_list) = parameter_list(unit, offset, 0) {
+        name.push_str(&parameter_list);
+    }
+}
+
+fn is_c_family_language(language: gimli::DwLang) -> bool {
+    matches!(
+        language,
+        gimli::DW_LANG_C89
+            | gimli::DW_LANG_C
+            | gimli::DW_LANG_C99
+            | gimli::DW_LANG_C11
+            | gimli::DW_LANG_C17
+            | gimli::DW_LANG_C_plus_plus
+            | gimli::DW_LANG_C_plus_plus_03
+            | gimli::DW_LANG_C_plus_plus_11
+            | gimli::DW_LANG_C_plus_plus_14
+            | gimli::DW_LANG_C_plus_plus_17
+            | gimli::DW_LANG_C_plus_plus_20
+            | gimli::DW_LANG_ObjC
+            | gimli::DW_LANG_ObjC_plus_plus
+    )
+}
+
+/// Type references can form cycles in broken debug info.
+const MAX_TYPE_DEPTH: usize = 32;
+
+/// Returns the parameter list of the function (or function type) at `offset`,
+/// e.g. `(int, char const*)`. Returns `None` if the debug info doesn't describe
+/// the parameters, e.g. with `-gline-tables-only`, or if it uses references
+/// to other units.
+fn parameter_list<R: Reader>(
+    unit: UnitRef<R>,
+    offset: UnitOffset<R::Offset>,
+    depth: usize,
+) -> Option<String> {
+    if depth > MAX_TYPE_DEPTH {
+        return None;
+    }
+    let mut tree = unit.entries_tree(Some(offset)).ok()?;
+    let root = tree.root().ok()?;
+    let entry = root.entry();
+    // Inlined calls and concrete instances of inline functions can leave out
+    // parameters which were optimized away. The abstract instance has all of them.
+    if let Some(origin) = unit_ref_attr(entry, gimli::DW_AT_abstract_origin) {
+        return parameter_list(unit, origin, depth + 1);
+    }
+    let specification = unit_ref_attr(entry, gimli::DW_AT_specification);
+    let is_prototyped = matches!(
+        entry.attr_value(gimli::DW_AT_prototyped),
+        Ok(Some(AttributeValue::Flag(true)))
+    );
+
+    let mut types = Vec::new();
+    let mut has_parameters = false;
+    let mut children = root.children();
+    while let Some(child) = children.next().ok()? {
+        let child = child.entry();
+        match child.tag() {
+            gimli::DW_TAG_formal_parameter => {
+                has_parameters = true;
+                if let Ok(Some(AttributeValue::Flag(true))) =
+                    child.attr_value(gimli::DW_AT_artificial)
+                {
+                    // The implicit `this` parameter.
+                    continue;
+                }
+                types.push(type_name_of(unit, child, depth + 1)?);
+            }
+            gimli::DW_TAG_unspecified_parameters => {
+                has_parameters = true;
+                types.push("...".to_string());
+            }
+            _ => {}
+        }
+    }
+
+    if has_parameters {
+        Some(format!("({})", types.join(", ")))
+    } else if let Some(specification) = specification {
+        // An out-of-line definition of a member function, whose declaration
+        // has the parameters.
+        parameter_list(unit, specification, depth + 1)
+    } else if is_prototyped {
+        Some("()".to_string())
+    } else {
+        // Without DW_AT_prototyped, we can't tell a function without parameters
+        // from a function whose parameters aren't described.
+        None
+    }
+}
+
+/// The name of the type of a parameter or of a type modifier, or `void` if it
+/// has no type.
+fn type_name_of<R: Reader>(
+    unit: UnitRef<R>,
+    entry: &DebuggingInformationEntry<R>,
+    depth: usize,
+) -> Option<String> {
+    match entry.attr_value(gimli::DW_AT_type).ok()? {
+        Some(AttributeValue::UnitRef(offset)) => type_name(unit, offset, depth),
+        Some(_) => None,
+        None => match unit_ref_attr(entry, gimli::DW_AT_abstract_origin) {
+            Some(origin) => type_name_of(unit, &unit.entry(origin).ok()?, depth + 1),
+            None => Some("void".to_string()),
+        },
+    }
+}
+
+/// Formats a type like the C++ demangler does, e.g. `char const*`.
+fn type_name<R: Reader>(
+    unit: UnitRef<R>,
+    offset: UnitOffset<R::Offset>,
+    depth: usize,
+) -> Option<String> {
+    if depth > MAX_TYPE_DEPTH {
+        return None;
+    }
+    let entry = unit.entry(offset).ok()?;
+    let name = match entry.tag() {
+        gimli::DW_TAG_base_type
+        | gimli::DW_TAG_typedef
+        | gimli::DW_TAG_structure_type
+        | gimli::DW_TAG_class_type
+        | gimli::DW_TAG_union_type
+        | gimli::DW_TAG_enumeration_type
+        | gimli::DW_TAG_unspecified_type => match entry.attr_value(gimli::DW_AT_name).ok()? {
+            Some(name) => unit
+                .attr_string(name)
+                .ok()?
+                .to_string_lossy()
+                .ok()?
+                .into_owned(),
+            None => "(anonymous)".to_string(),
+        },
+        gimli::DW_TAG_pointer_type => {
+            if let Some(pointee) = unit_ref_attr(&entry, gimli::DW_AT_type) {
+                if unit.entry(pointee).ok()?.tag() == gimli::DW_TAG_subroutine_type {
+                    // A function pointer, e.g. `void (*)(int)`.
+                    let pointee = unit.entry(pointee).ok()?;
+                    let return_type = type_name_of(unit, &pointee, depth + 1)?;
+                    let parameters = parameter_list(unit, pointee.offset(), depth + 1)?;
+                    return Some(format!("{return_type} (*){parameters}"));
+                }
+            }
+            format!("{}*", type_name_of(unit, &entry, depth + 1)?)
+        }
+        gimli::DW_TAG_reference_type => format!("{}&", type_name_of(unit, &entry, depth + 1)?),
+        gimli::DW_TAG_rvalue_reference_type => {
+            format!("{}&&", type_name_of(unit, &entry, depth + 1)?)
+        }
+        gimli::DW_TAG_const_type => format!("{} const", type_name_of(unit, &entry, depth + 1)?),
+        gimli::DW_TAG_volatile_type => {
+            format!("{} volatile", type_name_of(unit, &entry, depth + 1)?)
+        }
+        gimli::DW_TAG_array_type => format!("{}[]", type_name_of(unit, &entry, depth + 1)?),
+        gimli::DW_TAG_restrict_type | gimli::DW_TAG_atomic_type => {
+            type_name_of(unit, &entry, depth + 1)?
+        }
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn unit_ref_attr<R: Reader>(
+    entry: &DebuggingInformationEntry<R>,
+    attr: gimli::DwAt,
+) -> Option<UnitOffset<R::Offset>> {
+    match entry.attr_value(attr).ok()? {
+        Some(AttributeValue::UnitRef(offset)) => Some(offset),
+        _ => None,
+    }
+}
+
 pub enum SingleSectionData<'data, T: ReadRef<'data>> {
     View {
         data: T,
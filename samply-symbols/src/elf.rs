@@ -413,6 +413,7 @@ impl<'data, T: FileContents + 'static> ElfObjectsTrait<T> for ElfObjects<'data,
             self.make_addr2line_context().ok(),
             self.make_dwp_package().ok().flatten(),
             debug_id,
+            &[],
             function_starts.as_deref(),
             function_ends.as_deref(),
             self,
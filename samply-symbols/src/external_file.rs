@@ -139,6 +139,7 @@ trait ExternalFileInnerTrait {
     fn lookup(
         &self,
         external_file_address: &ExternalFileAddressInFileRef,
+        full_signatures: bool,
     ) -> Option<Vec<FrameDebugInfo>>;
 }
 
@@ -152,6 +153,7 @@ impl<'a, F: FileContents> ExternalFileInnerTrait for ExternalFileInner<'a, F> {
     fn lookup(
         &self,
         external_file_address: &ExternalFileAddressInFileRef,
+        full_signatures: bool,
     ) -> Option<Vec<FrameDebugInfo>> {
         let mut path_mapper = self.path_mapper.lock().unwrap();
         match (&self.member_contexts, external_file_address) {
@@ -161,7 +163,12 @@ impl<'a, F: FileContents> ExternalFileInnerTrait for ExternalFileInner<'a, F> {
                     symbol_name,
                     offset_from_symbol,
                 },
-            ) => context.lookup(symbol_name, *offset_from_symbol, &mut path_mapper),
+            ) => context.lookup(
+                symbol_name,
+                *offset_from_symbol,
+                &mut path_mapper,
+                full_signatures,
+            ),
             (
                 ExternalFileMemberContexts::Archive {
                     member_ranges,
@@ -175,9 +182,12 @@ impl<'a, F: FileContents> ExternalFileInnerTrait for ExternalFileInner<'a, F> {
             ) => {
                 let mut member_contexts = contexts.lock().unwrap();
                 match member_contexts.get(name_in_archive) {
-                    Some(member_context) => {
-                        member_context.lookup(symbol_name, *offset_from_symbol, &mut path_mapper)
-                    }
+                    Some(member_context) => member_context.lookup(
+                        symbol_name,
+                        *offset_from_symbol,
+                        &mut path_mapper,
+                        full_signatures,
+                    ),
                     None => {
                         let range = *member_ranges.get(name_in_archive.as_bytes())?;
                         // .ok_or_else(|| Error::FileNotInArchive(name_in_archive.to_owned()))?;
@@ -186,6 +196,7 @@ impl<'a, F: FileContents> ExternalFileInnerTrait for ExternalFileInner<'a, F> {
                             symbol_name,
                             *offset_from_symbol,
                             &mut path_mapper,
+                            full_signatures,
                         );
                         member_contexts.insert(name_in_archive.to_string(), member_context);
                         res
@@ -216,10 +227,11 @@ impl<'a> ExternalFileMemberContext<'a> {
         symbol_name: &[u8],
         offset_from_symbol: u32,
         path_mapper: &mut PathMapper<()>,
+        full_signatures: bool,
     ) -> Option<Vec<FrameDebugInfo>> {
         let symbol_address = self.symbol_addresses.get(symbol_name)?;
         let address = symbol_address + offset_from_symbol as u64;
-        get_frames(address, self.context.as_ref(), path_mapper)
+        get_frames(address, self.context.as_ref(), path_mapper, full_signatures)
     }
 }
 
@@ -251,6 +263,19 @@ impl<F: FileContents + 'static> ExternalFileSymbolMap<F> {
         &self,
         external_file_address: &ExternalFileAddressInFileRef,
     ) -> Option<Vec<FrameDebugInfo>> {
-        self.0.get().0.lookup(external_file_address)
+        self.0.get().0.lookup(external_file_address, false)
+    }
+
+    /// Like [`lookup`](Self::lookup), but can add the parameter types to
+    /// function names, see [`FileAndPathHelper::full_function_signatures`](crate::FileAndPathHelper::full_function_signatures).
+    pub(crate) fn lookup_with_signatures(
+        &self,
+        external_file_address: &ExternalFileAddressInFileRef,
+        full_signatures: bool,
+    ) -> Option<Vec<FrameDebugInfo>> {
+        self.0
+            .get()
+            .0
+            .lookup(external_file_address, full_signatures)
     }
 }
@@ -0,0 +1,136 @@
+use std::borrow::Cow;
+
+use crate::shared::FrameDebugInfo;
+
+/// Qualifiers which can follow the parameter list of a C++ member function.
+const TRAILING_QUALIFIERS: &[&str] = &["const", "volatile", "noexcept", "&&", "&"];
+
+/// Removes the parameter list from a demangled function name, e.g.
+/// `mozilla::dom::Foo::Bar(int, char const*) const` becomes
+/// `mozilla::dom::Foo::Bar`. Suffixes like `[clone .cold]` are kept.
+///
+/// Names which don't end in a parameter list, e.g. Rust names or plain C
+/// symbols, are returned unchanged.
+pub fn strip_function_parameters(name: &str) -> Cow<'_, str> {
+    let (body, suffix) = match name.find(" [clone ") {
+        Some(suffix_start) => name.split_at(suffix_start),
+        None => (name, ""),
+    };
+    let body = strip_trailing_qualifiers(body);
+    let Some(params_start) = find_parameter_list_start(body) else {
+        return Cow::Borrowed(name);
+    };
+    let prefix = &body[..params_start];
+    // `operator()` is a function name, not a parameter list. So are the
+    // parentheses of a name which doesn't have anything in front of them.
+    if prefix.trim_end().is_empty() || prefix.ends_with("operator") {
+        return Cow::Borrowed(name);
+    }
+    Cow::Owned(format!("{prefix}{suffix}"))
+}
+
+/// Removes the parameter lists from the function names of the frames.
+pub fn strip_function_parameters_from_frames(frames: &mut [FrameDebugInfo]) {
+    for frame in frames {
+        if let Some(function) = &mut frame.function {
+            strip_function_parameters_in_place(function);
+        }
+    }
+}
+
+/// Like [`strip_function_parameters`], but replaces the name.
+pub fn strip_function_parameters_in_place(name: &mut String) {
+    if let Cow::Owned(stripped) = strip_function_parameters(name) {
+        *name = stripped;
+    }
+}
+
+fn strip_trailing_qualifiers(mut body: &str) -> &str {
+    loop {
+        let trimmed = body.trim_end();
+        let stripped = TRAILING_QUALIFIERS.iter().find_map(|qualifier| {
+            let rest = trimmed.strip_suffix(qualifier)?;
+            // Only strip whole words. `&` and `&&` may directly follow a
+            // `)` or another qualifier.
+            (qualifier.starts_with('&') || rest.ends_with([' ', ')'])).then_some(rest)
+        });
+        match stripped {
+            Some(rest) => body = rest,
+            None => return trimmed,
+        }
+    }
+}
+
+/// Returns the byte index of the `(` which opens the parameter list at the
+/// end of `body`, if `body` ends with a balanced parameter list.
+fn find_parameter_list_start(body: &str) -> Option<usize> {
+    if !body.ends_with(')') {
+        return None;
+    }
+    let mut depth = 0usize;
+    for (index, c) in body.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strips_cpp_parameters() {
+        assert_eq!(
+            strip_function_parameters("mozilla::dom::Foo::Bar(int, char const*) const"),
+            "mozilla::dom::Foo::Bar"
+        );
+        assert_eq!(
+            strip_function_parameters("Foo::Bar(void (*)(int), std::vector<int> const&) &&"),
+            "Foo::Bar"
+        );
+        assert_eq!(strip_function_parameters("Foo::Get() const&"), "Foo::Get");
+        assert_eq!(
+            strip_function_parameters("Foo::operator()(int) const"),
+            "Foo::operator()"
+        );
+        assert_eq!(
+            strip_function_parameters("Foo::Bar(int)::{lambda(int)#1}::operator()(int) const"),
+            "Foo::Bar(int)::{lambda(int)#1}::operator()"
+        );
+        assert_eq!(
+            strip_function_parameters("Foo::Bar(int) [clone .cold]"),
+            "Foo::Bar [clone .cold]"
+        );
+        assert_eq!(
+            strip_function_parameters("Foo::Constant(int) constexpr_thing"),
+            "Foo::Constant(int) constexpr_thing"
+        );
+    }
+
+    #[test]
+    fn keeps_names_without_parameters() {
+        assert!(matches!(
+            strip_function_parameters("core::ptr::drop_in_place<alloc::vec::Vec<u8>>"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(strip_function_parameters("malloc"), "malloc");
+        assert_eq!(
+            strip_function_parameters("Foo::operator()"),
+            "Foo::operator()"
+        );
+        assert_eq!(
+            strip_function_parameters("<fn(u8) as Foo>::call"),
+            "<fn(u8) as Foo>::call"
+        );
+        assert_eq!(strip_function_parameters("Foo::Bar(int"), "Foo::Bar(int");
+    }
+}
@@ -226,11 +226,13 @@ mod dwarf;
 mod elf;
 mod error;
 mod external_file;
+mod function_signature;
 mod jitdump;
 mod macho;
 mod mapped_path;
 mod path_mapper;
 mod shared;
+mod source_link;
 mod symbol_map;
 mod symbol_map_object;
 mod windows;
@@ -248,6 +250,7 @@ pub use crate::external_file::{load_external_file, ExternalFileSymbolMap};
 pub use crate::jitdump::debug_id_and_code_id_for_jitdump;
 pub use crate::macho::FatArchiveMember;
 pub use crate::mapped_path::MappedPath;
+pub use crate::path_mapper::SourcePathSubstitution;
 pub use crate::shared::{
     relative_address_base, AddressInfo, CandidatePathInfo, CodeId, ElfBuildId,
     ExternalFileAddressInFileRef, ExternalFileAddressRef, ExternalFileRef, FileAndPathHelper,
@@ -280,14 +283,32 @@ where
         self.helper.clone()
     }
 
+    /// Reads a source file, from the raw path if possible, and otherwise from the
+    /// URL of its mapped path, e.g. for binaries which were built on CI.
     pub async fn load_source_file(
         &self,
         debug_file_location: &H::FL,
         source_file_path: &SourceFilePath,
     ) -> Result<String, Error> {
-        let source_file_location = debug_file_location
-            .location_for_source_file(source_file_path.raw_path())
-            .ok_or(Error::FileLocationRefusedSourceFileLocation)?;
+        let result = match debug_file_location.location_for_source_file(source_file_path.raw_path())
+        {
+            Some(location) => self.load_source_file_at(location).await,
+            None => Err(Error::FileLocationRefusedSourceFileLocation),
+        };
+        if result.is_ok() {
+            return result;
+        }
+        match source_file_path
+            .mapped_path()
+            .and_then(MappedPath::source_url)
+            .and_then(|url| debug_file_location.location_for_source_url(&url))
+        {
+            Some(location) => self.load_source_file_at(location).await,
+            None => result,
+        }
+    }
+
+    async fn load_source_file_at(&self, source_file_location: H::FL) -> Result<String, Error> {
         let file_contents = self
             .helper
             .load_file(source_file_location.clone())
@@ -309,7 +330,8 @@ where
             .as_ref()
             .get_symbol_map_for_library(library_info)
         {
-            return Ok(SymbolMap::with_symbol_map_trait(fl, symbol_map));
+            let symbol_map = SymbolMap::with_symbol_map_trait(fl, symbol_map);
+            return Ok(self.with_helper_rewrites(symbol_map));
         }
 
         let debug_id = match library_info.debug_id {
@@ -351,7 +373,9 @@ where
             };
 
             match symbol_map {
-                Ok(symbol_map) if symbol_map.debug_id() == debug_id => return Ok(symbol_map),
+                Ok(symbol_map) if symbol_map.debug_id() == debug_id => {
+                    return Ok(self.with_helper_rewrites(symbol_map))
+                }
                 Ok(symbol_map) => {
                     all_errors.push(Error::UnmatchedDebugId(symbol_map.debug_id(), debug_id));
                 }
@@ -530,14 +554,14 @@ where
             match (&multi_arch_disambiguator, symbol_map_res) {
                 (Some(MultiArchDisambiguator::DebugId(expected_debug_id)), Ok(symbol_map)) => {
                     if &symbol_map.debug_id() == expected_debug_id {
-                        return Ok(symbol_map);
+                        return Ok(self.with_helper_rewrites(symbol_map));
                     }
                     err = Some(Error::UnmatchedDebugId(
                         symbol_map.debug_id(),
                         *expected_debug_id,
                     ));
                 }
-                (_, Ok(symbol_map)) => return Ok(symbol_map),
+                (_, Ok(symbol_map)) => return Ok(self.with_helper_rewrites(symbol_map)),
                 (_, Err(e)) => err = Some(e),
             }
         }
@@ -548,6 +572,27 @@ where
         &self,
         file_location: FL,
         multi_arch_disambiguator: Option<MultiArchDisambiguator>,
+    ) -> Result<SymbolMap<H>, Error> {
+        let symbol_map = self
+            .load_symbol_map_from_location_with_raw_paths(file_location, multi_arch_disambiguator)
+            .await?;
+        Ok(self.with_helper_rewrites(symbol_map))
+    }
+
+    /// Makes the symbol map apply the helper's source path substitutions to
+    /// the file paths in its lookup results, and strip or add the parameter
+    /// lists of function names if the helper asks for it.
+    fn with_helper_rewrites(&self, mut symbol_map: SymbolMap<H>) -> SymbolMap<H> {
+        symbol_map.set_source_path_substitutions(self.helper.source_path_substitutions().to_vec());
+        symbol_map.set_strip_function_parameters(self.helper.strip_function_parameters());
+        symbol_map.set_full_function_signatures(self.helper.full_function_signatures());
+        symbol_map
+    }
+
+    async fn load_symbol_map_from_location_with_raw_paths(
+        &self,
+        file_location: FL,
+        multi_arch_disambiguator: Option<MultiArchDisambiguator>,
     ) -> Result<SymbolMap<H>, Error> {
         let file_contents = self
             .helper
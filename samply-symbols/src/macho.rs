@@ -3,12 +3,15 @@ use std::sync::Arc;
 
 use debugid::DebugId;
 use macho_unwind_info::UnwindInfo;
-use object::macho::{self, LinkeditDataCommand, MachHeader32, MachHeader64};
+use object::macho::{
+    self, DyldCacheHeader, LinkeditDataCommand, MachHeader32, MachHeader64, Nlist32, Nlist64,
+};
 use object::read::macho::{
-    FatArch, LoadCommandIterator, MachHeader, MachOFatFile32, MachOFatFile64,
+    DyldSubCacheSlice, FatArch, LoadCommandIterator, MachHeader, MachOFatFile32, MachOFatFile64,
+    Nlist,
 };
-use object::read::{File, Object, ObjectSection};
-use object::{Endianness, FileKind, ReadRef};
+use object::read::{File, Object, ObjectSection, SectionIndex, StringTable};
+use object::{Endianness, FileKind, ReadRef, SectionKind, U32, U64};
 use uuid::Uuid;
 use yoke::Yoke;
 use yoke_derive::Yokeable;
@@ -18,8 +21,8 @@ use crate::debugid_util::debug_id_for_object;
 use crate::dwarf::Addr2lineContextData;
 use crate::error::Error;
 use crate::shared::{
-    FileAndPathHelper, FileContents, FileContentsWrapper, FileLocation, MultiArchDisambiguator,
-    RangeReadRef,
+    relative_address_base, FileAndPathHelper, FileContents, FileContentsWrapper, FileLocation,
+    MultiArchDisambiguator, RangeReadRef,
 };
 use crate::symbol_map::SymbolMap;
 use crate::symbol_map_object::{
@@ -250,32 +253,62 @@ where
     let dcl = DyldCacheLoader::new(helper, &dyld_cache_path);
     let root_contents = dcl.load_cache().await?;
     let root_contents = FileContentsWrapper::new(root_contents);
+    let (subcache_suffixes, has_symbols_subcache) = dyld_subcache_suffixes(&root_contents)?;
 
-    let mut subcache_contents = Vec::new();
-    for subcache_index in 1.. {
-        // Find the subcache at dyld_shared_cache_arm64e.1 or dyld_shared_cache_arm64e.01
-        let suffix = format!(".{subcache_index}");
-        let suffix2 = format!(".{subcache_index:02}");
-        let subcache = match dcl.load_subcache(&suffix).await {
-            Ok(subcache) => subcache,
-            Err(_) => match dcl.load_subcache(&suffix2).await {
-                Ok(subcache) => subcache,
-                Err(_) => break,
-            },
-        };
+    let mut subcache_contents = Vec::with_capacity(subcache_suffixes.len());
+    for suffix in subcache_suffixes {
+        let subcache = dcl.load_subcache(&suffix).await?;
         subcache_contents.push(FileContentsWrapper::new(subcache));
     }
-    if let Ok(subcache) = dcl.load_subcache(".symbols").await {
-        subcache_contents.push(FileContentsWrapper::new(subcache));
+    let symbols_subcache_contents = if has_symbols_subcache {
+        let subcache = dcl.load_subcache(".symbols").await?;
+        Some(FileContentsWrapper::new(subcache))
+    } else {
+        None
     };
 
     Ok(DyldCacheFileData::new(
         root_contents,
         subcache_contents,
+        symbols_subcache_contents,
         dylib_path,
     ))
 }
 
+/// Returns the file name suffixes of the subcaches, in the order in which they
+/// are listed in the header of the main cache file, and whether there is a
+/// `.symbols` subcache.
+///
+/// Since macOS 13 / iOS 16, the header has the suffix of each subcache, e.g.
+/// `.01` or `.25.data`. Before that, the subcaches were numbered `.1`, `.2`, etc.
+fn dyld_subcache_suffixes<T: FileContents>(
+    root_contents: &FileContentsWrapper<T>,
+) -> Result<(Vec<String>, bool), Error> {
+    let data = root_contents.full_range();
+    let header = DyldCacheHeader::<Endianness>::parse(data).map_err(Error::DyldCacheParseError)?;
+    let (_arch, endian) = header.parse_magic().map_err(Error::DyldCacheParseError)?;
+    let suffixes = match header
+        .subcaches(endian, data)
+        .map_err(Error::DyldCacheParseError)?
+    {
+        Some(DyldSubCacheSlice::V2(subcaches)) => subcaches
+            .iter()
+            .map(|subcache| {
+                let suffix = &subcache.file_suffix;
+                let len = suffix.iter().position(|&b| b == 0).unwrap_or(suffix.len());
+                String::from_utf8_lossy(&suffix[..len]).into_owned()
+            })
+            .collect(),
+        Some(DyldSubCacheSlice::V1(subcaches)) => (1..=subcaches.len())
+            .map(|index| format!(".{index}"))
+            .collect(),
+        // The slice type is non-exhaustive.
+        Some(_) | None => Vec::new(),
+    };
+    let has_symbols_subcache = header.symbols_subcache_uuid(endian).is_some();
+    Ok((suffixes, has_symbols_subcache))
+}
+
 pub async fn load_symbol_map_for_dyld_cache<H>(
     dyld_cache_path: H::FL,
     dylib_path: String,
@@ -296,6 +329,7 @@ where
 {
     root_file_data: FileContentsWrapper<T>,
     subcache_file_data: Vec<FileContentsWrapper<T>>,
+    symbols_subcache_file_data: Option<FileContentsWrapper<T>>,
     dylib_path: String,
 }
 
@@ -306,6 +340,9 @@ pub struct ObjectAndMachOData<'data, T: FileContents + 'static> {
     object: File<'data, FileContentsRange<'data, T>>,
     macho_data: MachOData<'data, FileContentsRange<'data, T>>,
     addr2line_context: Addr2lineContextData,
+    /// The local symbols of a dyld shared cache image, which are kept outside
+    /// of the image's symbol table, as (address, name) pairs.
+    local_symbols: Vec<(u64, &'data [u8])>,
 }
 
 impl<'data, T: FileContents + 'static> ObjectAndMachOData<'data, T> {
@@ -317,6 +354,7 @@ impl<'data, T: FileContents + 'static> ObjectAndMachOData<'data, T> {
             object,
             macho_data,
             addr2line_context: Addr2lineContextData::new(),
+            local_symbols: Vec::new(),
         }
     }
 
@@ -339,21 +377,29 @@ impl<T: FileContents + 'static> DyldCacheFileData<T> {
     pub fn new(
         root_file_data: FileContentsWrapper<T>,
         subcache_file_data: Vec<FileContentsWrapper<T>>,
+        symbols_subcache_file_data: Option<FileContentsWrapper<T>>,
         dylib_path: String,
     ) -> Self {
         Self {
             root_file_data,
             subcache_file_data,
+            symbols_subcache_file_data,
             dylib_path,
         }
     }
 
     pub fn make_object(&self) -> Result<ObjectAndMachOData<'_, T>, Error> {
         let rootcache_range = self.root_file_data.full_range();
+        let symbols_subcache_range = self
+            .symbols_subcache_file_data
+            .as_ref()
+            .map(FileContentsWrapper::full_range);
+        // The .symbols subcache has to come last.
         let subcache_ranges: Vec<_> = self
             .subcache_file_data
             .iter()
             .map(FileContentsWrapper::full_range)
+            .chain(symbols_subcache_range)
             .collect();
         let cache = object::read::macho::DyldCache::<Endianness, _>::parse(
             rootcache_range,
@@ -372,9 +418,144 @@ impl<T: FileContents + 'static> DyldCacheFileData<T> {
         let (data, header_offset) = image
             .image_data_and_offset()
             .map_err(Error::MachOHeaderParseError)?;
+
+        // Older caches have the local symbols in the main cache file.
+        let local_symbols_data = symbols_subcache_range.unwrap_or(rootcache_range);
+        let local_symbols = read_dyld_cache_local_symbols(
+            local_symbols_data,
+            rootcache_range,
+            &object,
+            header_offset,
+        )
+        .unwrap_or_default();
+
         let macho_data = MachOData::new(data, header_offset, object.is_64());
-        Ok(ObjectAndMachOData::new(object, macho_data))
+        let mut object_and_macho_data = ObjectAndMachOData::new(object, macho_data);
+        object_and_macho_data.local_symbols = local_symbols;
+        Ok(object_and_macho_data)
+    }
+}
+
+/// The dyld cache header size from which on the local symbol entries have a
+/// 64-bit VM offset rather than a 32-bit file offset. This is the offset of the
+/// `symbolFileUUID` field.
+const DYLD_CACHE_HEADER_SIZE_LOCAL_SYMBOLS_ENTRY_64: u32 = 0x190;
+
+/// Reads the local symbols of a dyld shared cache image from the local symbol
+/// table of the cache, as (address, name) pairs. The cache builder moves the
+/// local symbols out of the images' own symbol tables; caches which have a
+/// `.symbols` subcache keep them there, older caches in the main cache file.
+///
+/// `image_file_offset` is the file offset of the image's Mach-O header. It's
+/// only used for caches without subcaches, where this offset is in the main
+/// cache file. Returns `None` if the cache has no local symbols for the image.
+fn read_dyld_cache_local_symbols<'data, R: ReadRef<'data>>(
+    data: R,
+    rootcache_data: R,
+    object: &File<'data, R>,
+    image_file_offset: u64,
+) -> Option<Vec<(u64, &'data [u8])>> {
+    let header = DyldCacheHeader::<Endianness>::parse(data).ok()?;
+    let (_arch, endian) = header.parse_magic().ok()?;
+    let info_offset = header.local_symbols_offset.get(endian);
+    if info_offset == 0 {
+        return None;
+    }
+
+    // struct dyld_cache_local_symbols_info
+    let info = data.read_slice_at::<U32<Endianness>>(info_offset, 6).ok()?;
+    let [nlist_offset, nlist_count, strings_offset, strings_size, entries_offset, entries_count] =
+        [0, 1, 2, 3, 4, 5].map(|i| u64::from(info[i].get(endian)));
+
+    // Find the image's range of entries in the nlist array.
+    let mut image_nlist_range = None;
+    let entries_start = info_offset + entries_offset;
+    if header.mapping_offset.get(endian) >= DYLD_CACHE_HEADER_SIZE_LOCAL_SYMBOLS_ENTRY_64 {
+        // struct dyld_cache_local_symbols_entry_64, with the image's VM offset
+        // from the cache base address.
+        let rootcache_header = DyldCacheHeader::<Endianness>::parse(rootcache_data).ok()?;
+        let cache_base_address = rootcache_header
+            .mappings(endian, rootcache_data)
+            .ok()?
+            .first()?
+            .address
+            .get(endian);
+        let image_vm_offset = relative_address_base(object).checked_sub(cache_base_address)?;
+        for index in 0..entries_count {
+            let entry_offset = entries_start + index * 16;
+            let dylib_offset = data.read_at::<U64<Endianness>>(entry_offset).ok()?;
+            if dylib_offset.get(endian) == image_vm_offset {
+                image_nlist_range = Some(entry_offset + 8);
+                break;
+            }
+        }
+    } else {
+        // struct dyld_cache_local_symbols_entry, with the file offset of the
+        // image's Mach-O header.
+        for index in 0..entries_count {
+            let entry_offset = entries_start + index * 12;
+            let dylib_offset = data.read_at::<U32<Endianness>>(entry_offset).ok()?;
+            if u64::from(dylib_offset.get(endian)) == image_file_offset {
+                image_nlist_range = Some(entry_offset + 4);
+                break;
+            }
+        }
     }
+    let range = data
+        .read_slice_at::<U32<Endianness>>(image_nlist_range?, 2)
+        .ok()?;
+    let nlist_start_index = u64::from(range[0].get(endian));
+    let image_nlist_count = range[1].get(endian) as usize;
+    if nlist_start_index + image_nlist_count as u64 > nlist_count {
+        return None;
+    }
+
+    let strings_start = info_offset + strings_offset;
+    let strings = StringTable::new(data, strings_start, strings_start + strings_size);
+    let nlist_start = info_offset + nlist_offset;
+    if object.is_64() {
+        let nlists = data
+            .read_slice_at::<Nlist64<Endianness>>(
+                nlist_start + nlist_start_index * 16,
+                image_nlist_count,
+            )
+            .ok()?;
+        Some(text_symbols_from_nlists(nlists, endian, strings, object))
+    } else {
+        let nlists = data
+            .read_slice_at::<Nlist32<Endianness>>(
+                nlist_start + nlist_start_index * 12,
+                image_nlist_count,
+            )
+            .ok()?;
+        Some(text_symbols_from_nlists(nlists, endian, strings, object))
+    }
+}
+
+/// Returns the (address, name) pairs of the symbols which are defined in a
+/// text section of `object`.
+fn text_symbols_from_nlists<'data, R: ReadRef<'data>, N: Nlist<Endian = Endianness>>(
+    nlists: &[N],
+    endian: Endianness,
+    strings: StringTable<'data, R>,
+    object: &File<'data, R>,
+) -> Vec<(u64, &'data [u8])> {
+    nlists
+        .iter()
+        .filter(|nlist| {
+            !nlist.is_stab()
+                && nlist.n_type() & macho::N_TYPE == macho::N_SECT
+                && object
+                    .section_by_index(SectionIndex(nlist.n_sect().into()))
+                    .is_ok_and(|section| section.kind() == SectionKind::Text)
+        })
+        .filter_map(|nlist| {
+            Some((
+                nlist.n_value(endian).into(),
+                nlist.name(endian, strings).ok()?,
+            ))
+        })
+        .collect()
 }
 
 impl<T: FileContents + 'static> MakeMachObject<T> for DyldCacheFileData<T> {
@@ -403,6 +584,7 @@ impl<T: FileContents + 'static> ObjectSymbolMapOuter<T> for FileDataAndObject<T>
             object,
             macho_data,
             addr2line_context,
+            local_symbols,
         } = self.0.get();
         let (function_starts, function_ends) = compute_function_addresses_macho(macho_data, object);
         let debug_id = debug_id_for_object(object)
@@ -414,6 +596,7 @@ impl<T: FileContents + 'static> ObjectSymbolMapOuter<T> for FileDataAndObject<T>
                 .ok(),
             None,
             debug_id,
+            local_symbols,
             function_starts.as_deref(),
             function_ends.as_deref(),
             &(),
@@ -95,6 +95,36 @@ impl MappedPath {
         }
     }
 
+    /// The URL of the raw contents of this file, if the host serves them.
+    /// Gitiles hosts, such as googlesource.com, only serve base64-encoded
+    /// files, and files in cargo packages are inside the `.crate` archive,
+    /// so there's no URL for those.
+    pub fn source_url(&self) -> Option<String> {
+        match self {
+            MappedPath::Git { repo, path, rev } => {
+                if let Some(repo) = repo.strip_prefix("github.com/") {
+                    Some(format!(
+                        "https://raw.githubusercontent.com/{repo}/{rev}/{path}"
+                    ))
+                } else if let Some(repo) = repo.strip_prefix("dev.azure.com/") {
+                    let (org_and_project, repo_name) = repo.split_once("/_git/")?;
+                    Some(format!("https://dev.azure.com/{org_and_project}/_apis/git/repositories/{repo_name}/items?api-version=1.0&versionType=commit&version={rev}&path=/{path}"))
+                } else {
+                    None
+                }
+            }
+            MappedPath::Hg { repo, path, rev } => {
+                Some(format!("https://{repo}/raw-file/{rev}/{path}"))
+            }
+            MappedPath::S3 {
+                bucket,
+                digest,
+                path,
+            } => Some(format!("https://{bucket}.s3.amazonaws.com/{digest}/{path}")),
+            MappedPath::Cargo { .. } => None,
+        }
+    }
+
     /// Create a short, display-friendly form of this path.
     pub fn display_path(&self) -> String {
         match self {
@@ -222,6 +252,35 @@ fn hg_url(input: &str) -> IResult<&str, (String, String, String)> {
     ))
 }
 
+fn azure_devops_url(input: &str) -> IResult<&str, (String, String, String)> {
+    // Example: "https://dev.azure.com/contoso/engine/_apis/git/repositories/renderer/items?api-version=1.0&versionType=commit&version=2f1a8e4b9c0d7e6f5a4b3c2d1e0f9a8b7c6d5e4f&path=/src/gfx/blit.cpp"
+    let (input, _) = tag("https://dev.azure.com/")(input)?;
+    let (input, org) = terminated(take_until1("/"), tag("/"))(input)?;
+    let (input, project) = terminated(take_until1("/"), tag("/"))(input)?;
+    let (input, _) = tag("_apis/git/repositories/")(input)?;
+    let (query, repo_name) = terminated(take_until1("/items?"), tag("/items?"))(input)?;
+    let mut rev = None;
+    let mut path = None;
+    for param in query.split('&') {
+        match param.split_once('=') {
+            Some(("version", value)) => rev = Some(value),
+            Some(("path", value)) => path = Some(value.trim_start_matches('/')),
+            _ => {}
+        }
+    }
+    match (rev, path) {
+        (Some(rev), Some(path)) if !rev.is_empty() && !path.is_empty() => Ok((
+            "",
+            (
+                format!("dev.azure.com/{org}/{project}/_git/{repo_name}"),
+                path.to_owned(),
+                rev.to_owned(),
+            ),
+        )),
+        _ => Err(Err::Error(nom::error::Error::new(query, ErrorKind::Tag))),
+    }
+}
+
 fn s3_url(input: &str) -> IResult<&str, (String, String, String)> {
     // Example: "https://gecko-generated-sources.s3.amazonaws.com/7a1db5dfd0061d0e0bcca227effb419a20439aef4f6c4e9cd391a9f136c6283e89043d62e63e7edbd63ad81c339c401092bcfeff80f74f9cae8217e072f0c6f3/x86_64-pc-windows-msvc/release/build/swgl-59e3a0e09f56f4ea/out/brush_solid_DEBUG_OVERDRAW.h"
     let (input, _) = tag("https://")(input)?;
@@ -239,6 +298,11 @@ fn parse_url(input: &str) -> IResult<&str, MappedPath> {
             path,
             rev,
         }),
+        map(azure_devops_url, |(repo, path, rev)| MappedPath::Git {
+            repo,
+            path,
+            rev,
+        }),
         map(hg_url, |(repo, path, rev)| MappedPath::Hg {
             repo,
             path,
@@ -324,6 +388,49 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_azure_devops_urls() {
+        let url = "https://dev.azure.com/contoso/engine/_apis/git/repositories/renderer/items?api-version=1.0&versionType=commit&version=2f1a8e4b9c0d7e6f5a4b3c2d1e0f9a8b7c6d5e4f&path=/src/gfx/blit.cpp";
+        let mapped_path = MappedPath::from_url(url).unwrap();
+        assert_eq!(
+            mapped_path,
+            MappedPath::Git {
+                repo: "dev.azure.com/contoso/engine/_git/renderer".to_string(),
+                path: "src/gfx/blit.cpp".to_string(),
+                rev: "2f1a8e4b9c0d7e6f5a4b3c2d1e0f9a8b7c6d5e4f".to_string(),
+            }
+        );
+        assert_eq!(mapped_path.source_url().as_deref(), Some(url));
+        assert_eq!(
+            MappedPath::from_url(
+                "https://dev.azure.com/contoso/engine/_apis/git/repositories/renderer/items?api-version=1.0&path=/src/gfx/blit.cpp"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn source_urls() {
+        let url = "https://raw.githubusercontent.com/baldurk/renderdoc/v1.15/renderdoc/data/glsl/gl_texsample.h";
+        assert_eq!(
+            MappedPath::from_url(url).unwrap().source_url().as_deref(),
+            Some(url)
+        );
+        let url = "https://hg.mozilla.org/mozilla-central/raw-file/1706d4d54ec68fae1280305b70a02cb24c16ff68/mozglue/baseprofiler/core/ProfilerBacktrace.cpp";
+        assert_eq!(
+            MappedPath::from_url(url).unwrap().source_url().as_deref(),
+            Some(url)
+        );
+        assert_eq!(
+            MappedPath::from_special_path_str(
+                "git:chromium.googlesource.com/chromium/src:content/gpu/gpu_main.cc:4dac2548d4812df2aa4a90ac1fc8912363f4d59c"
+            )
+            .unwrap()
+            .source_url(),
+            None
+        );
+    }
+
     #[test]
     fn parse_s3_paths() {
         assert_eq!(
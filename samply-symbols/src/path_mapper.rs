@@ -8,6 +8,7 @@ use nom::sequence::{delimited, preceded, terminated, tuple};
 use nom::Err;
 
 use crate::mapped_path::MappedPath;
+use crate::shared::{FrameDebugInfo, SourceFilePath};
 
 pub trait ExtraPathMapper {
     fn map_path(&mut self, path: &str) -> Option<MappedPath>;
@@ -60,6 +61,78 @@ impl<E: ExtraPathMapper> PathMapper<E> {
     }
 }
 
+/// A rule which replaces a path prefix in the raw paths of source files, for
+/// example to map paths on the machine which built a binary to a local checkout
+/// of the same sources:
+///
+/// ```
+/// use samply_symbols::SourcePathSubstitution;
+///
+/// let rule = SourcePathSubstitution::new("/builds/worker/checkouts/gecko", "/home/me/src/gecko");
+/// assert_eq!(
+///     rule.apply("/builds/worker/checkouts/gecko/xpcom/base/nsCOMPtr.h").as_deref(),
+///     Some("/home/me/src/gecko/xpcom/base/nsCOMPtr.h")
+/// );
+/// assert_eq!(rule.apply("/builds/worker/checkouts/gecko-dev/README"), None);
+/// ```
+///
+/// These rules are supplied by [`FileAndPathHelper::source_path_substitutions`](crate::FileAndPathHelper::source_path_substitutions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcePathSubstitution {
+    from: String,
+    to: String,
+}
+
+impl SourcePathSubstitution {
+    /// Create a rule which replaces the `from` prefix with `to`. The prefix only
+    /// matches whole path components.
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            from: trim_trailing_separators(from.into()),
+            to: trim_trailing_separators(to.into()),
+        }
+    }
+
+    /// Returns the substituted path, or `None` if the rule doesn't apply to `raw_path`.
+    pub fn apply(&self, raw_path: &str) -> Option<String> {
+        let rest = raw_path.strip_prefix(&self.from)?;
+        if !rest.is_empty() && !rest.starts_with(['/', '\\']) {
+            return None;
+        }
+        Some(format!("{}{rest}", self.to))
+    }
+}
+
+fn trim_trailing_separators(mut path: String) -> String {
+    while path.len() > 1 && path.ends_with(['/', '\\']) {
+        path.pop();
+    }
+    path
+}
+
+/// Applies the first matching substitution to the file path of each frame.
+/// A substituted path refers to a local file, so its mapped path is dropped;
+/// otherwise the mapped path would be preferred when the file is displayed.
+pub fn apply_source_path_substitutions(
+    substitutions: &[SourcePathSubstitution],
+    frames: &mut [FrameDebugInfo],
+) {
+    if substitutions.is_empty() {
+        return;
+    }
+    for frame in frames {
+        let Some(file_path) = &mut frame.file_path else {
+            continue;
+        };
+        if let Some(local_path) = substitutions
+            .iter()
+            .find_map(|substitution| substitution.apply(file_path.raw_path()))
+        {
+            *file_path = SourceFilePath::new(local_path, None);
+        }
+    }
+}
+
 fn map_rustc_path(input: &str) -> Result<MappedPath, nom::Err<nom::error::Error<&str>>> {
     // /rustc/c79419af0721c614d050f09b95f076da09d37b0d/library/std/src/rt.rs
     // /rustc/e1884a8e3c3e813aada8254edfa120e85bf5ffca\/library\std\src\rt.rs
@@ -150,6 +223,56 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_source_path_substitution() {
+        let rule = SourcePathSubstitution::new("/builds/worker/checkouts/", "/home/me/src");
+        assert_eq!(
+            rule.apply("/builds/worker/checkouts/widget/nsWindow.cpp"),
+            Some("/home/me/src/widget/nsWindow.cpp".into())
+        );
+        assert_eq!(
+            rule.apply("/builds/worker/checkouts"),
+            Some("/home/me/src".into())
+        );
+        assert_eq!(rule.apply("/builds/worker/checkouts2/a.cpp"), None);
+        assert_eq!(rule.apply("/other/path.cpp"), None);
+
+        let rule = SourcePathSubstitution::new(r"D:\agent\_work\2\s", "/home/me/src");
+        assert_eq!(
+            rule.apply(r"D:\agent\_work\2\s\src\main.cpp"),
+            Some(r"/home/me/src\src\main.cpp".into())
+        );
+
+        let mut frames = vec![FrameDebugInfo {
+            function: None,
+            file_path: Some(SourceFilePath::new(
+                "/rustc/c79419af0721c614d050f09b95f076da09d37b0d/library/std/src/rt.rs".into(),
+                map_rustc_path(
+                    "/rustc/c79419af0721c614d050f09b95f076da09d37b0d/library/std/src/rt.rs",
+                )
+                .ok(),
+            )),
+            line_number: Some(1),
+        }];
+        apply_source_path_substitutions(
+            &[
+                rule,
+                SourcePathSubstitution::new(
+                    "/rustc/c79419af0721c614d050f09b95f076da09d37b0d",
+                    "/home/me/rust",
+                ),
+            ],
+            &mut frames,
+        );
+        assert_eq!(
+            frames[0].file_path,
+            Some(SourceFilePath::new(
+                "/home/me/rust/library/std/src/rt.rs".into(),
+                None
+            ))
+        );
+    }
+
     #[test]
     fn test_map_cargo_dep_path() {
         assert_eq!(
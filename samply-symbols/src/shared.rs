@@ -15,6 +15,7 @@ use object::FileFlags;
 use uuid::Uuid;
 
 use crate::mapped_path::MappedPath;
+use crate::path_mapper::SourcePathSubstitution;
 use crate::symbol_map::SymbolMapTrait;
 
 pub type FileAndPathHelperError = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -416,6 +417,29 @@ pub trait FileAndPathHelper {
     ) -> Option<(Self::FL, Arc<dyn SymbolMapTrait + Send + Sync>)> {
         None
     }
+
+    /// Rules for rewriting the raw paths of source files in lookup results, e.g.
+    /// to point paths from a build machine at a local checkout. The first
+    /// matching rule is applied.
+    fn source_path_substitutions(&self) -> &[SourcePathSubstitution] {
+        &[]
+    }
+
+    /// Whether to remove the parameter lists from demangled function names,
+    /// e.g. to turn `Foo::Bar(int) const` into `Foo::Bar`. By default, names
+    /// include the parameter types from the symbol or the debug info, which
+    /// distinguishes overloads.
+    fn strip_function_parameters(&self) -> bool {
+        false
+    }
+
+    /// Whether to add the parameter types from the DWARF debug info to function
+    /// names which don't have them, e.g. to turn the C function `add` into
+    /// `add(int, long)`. Names from mangled symbols and from PDBs already have
+    /// the parameter types.
+    fn full_function_signatures(&self) -> bool {
+        false
+    }
 }
 
 /// Provides synchronous access to the raw bytes of a file.
@@ -501,6 +525,16 @@ pub trait FileLocation: Clone + Display {
     /// those relative paths relative to the current working directory.
     fn location_for_source_file(&self, source_file_path: &str) -> Option<Self>;
 
+    /// Called on the location of a debug file in order to create a location for
+    /// a source file which is hosted on the web, e.g. at the revision that the
+    /// debug file's srcsrv or SourceLink information refers to. This is tried
+    /// if the file at the raw path can't be loaded.
+    ///
+    /// The default implementation refuses all URLs.
+    fn location_for_source_url(&self, _url: &str) -> Option<Self> {
+        None
+    }
+
     /// Called on the location of a Breakpad sym file, to get a location for its
     /// corresponding symindex file.
     fn location_for_breakpad_symindex(&self) -> Option<Self>;
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use crate::mapped_path::MappedPath;
+use crate::path_mapper::ExtraPathMapper;
+
+/// Maps raw file paths to URLs, using the SourceLink stream of a PDB. SourceLink
+/// is what the MSVC and .NET toolchains emit for builds on CI, instead of srcsrv.
+/// The stream is JSON with a map from path patterns to URL patterns:
+///
+/// ```json
+/// {"documents": {"D:\\a\\repo\\*": "https://raw.githubusercontent.com/org/repo/<rev>/*"}}
+/// ```
+///
+/// A pattern either matches a path exactly, or it ends with a `*`, which matches
+/// the rest of the path and is substituted for the `*` in the URL. Patterns are
+/// matched case-insensitively, and the longest matching pattern wins.
+///
+/// See <https://github.com/dotnet/designs/blob/main/accepted/2020/diagnostics/source-link.md>.
+pub struct SourceLinkPathMapper {
+    /// (lowercase path pattern without the `*`, URL pattern, whether it ended with `*`),
+    /// longest pattern first.
+    documents: Vec<(String, String, bool)>,
+    cache: HashMap<String, Option<MappedPath>>,
+}
+
+impl SourceLinkPathMapper {
+    /// Parses the contents of a SourceLink stream. Returns `None` if it isn't
+    /// valid SourceLink JSON.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let json: serde_json::Value = serde_json::from_slice(data).ok()?;
+        let mut documents: Vec<(String, String, bool)> = json
+            .get("documents")?
+            .as_object()?
+            .iter()
+            .filter_map(|(path_pattern, url_pattern)| {
+                let url_pattern = url_pattern.as_str()?;
+                let (path_prefix, is_wildcard) = match path_pattern.strip_suffix('*') {
+                    Some(path_prefix) => (path_prefix, true),
+                    None => (path_pattern.as_str(), false),
+                };
+                Some((
+                    path_prefix.to_lowercase(),
+                    url_pattern.to_string(),
+                    is_wildcard,
+                ))
+            })
+            .collect();
+        documents.sort_by_key(|(path_prefix, _, _)| std::cmp::Reverse(path_prefix.len()));
+        Some(SourceLinkPathMapper {
+            documents,
+            cache: HashMap::new(),
+        })
+    }
+
+    /// The URL for the file at `path`, if a pattern matches it.
+    pub fn url_for_path(&self, path: &str) -> Option<String> {
+        let path_lower = path.to_lowercase();
+        self.documents
+            .iter()
+            .find_map(|(path_prefix, url_pattern, is_wildcard)| {
+                if !is_wildcard {
+                    return (path_lower == *path_prefix).then(|| url_pattern.clone());
+                }
+                if !path_lower.starts_with(path_prefix.as_str()) {
+                    return None;
+                }
+                // Lowercasing can change the byte length of some characters, so
+                // the rest of the path is only taken from the original path if
+                // it didn't.
+                let rest = match path.get(path_prefix.len()..) {
+                    Some(rest) if path.len() == path_lower.len() => rest,
+                    _ => return None,
+                };
+                Some(url_pattern.replacen('*', &rest.replace('\\', "/"), 1))
+            })
+    }
+}
+
+impl ExtraPathMapper for SourceLinkPathMapper {
+    fn map_path(&mut self, path: &str) -> Option<MappedPath> {
+        if let Some(value) = self.cache.get(path) {
+            return value.clone();
+        }
+
+        let value = self
+            .url_for_path(path)
+            .and_then(|url| MappedPath::from_url(&url));
+        self.cache.insert(path.to_string(), value.clone());
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_source_link_mapping() {
+        let json = br#"{
+            "documents": {
+                "D:\\a\\engine\\*": "https://raw.githubusercontent.com/contoso/engine/0123abcd/*",
+                "D:\\a\\engine\\external\\zlib\\*": "https://raw.githubusercontent.com/madler/zlib/51b7f2ab/*",
+                "D:\\a\\engine\\build\\version.h": "https://example.com/version.h"
+            }
+        }"#;
+        let mut mapper = SourceLinkPathMapper::parse(json).unwrap();
+        assert_eq!(
+            mapper.url_for_path(r"d:\a\engine\src\Main.cpp").as_deref(),
+            Some("https://raw.githubusercontent.com/contoso/engine/0123abcd/src/Main.cpp")
+        );
+        assert_eq!(
+            mapper.map_path(r"D:\a\engine\external\zlib\inflate.c"),
+            Some(MappedPath::Git {
+                repo: "github.com/madler/zlib".to_string(),
+                path: "inflate.c".to_string(),
+                rev: "51b7f2ab".to_string(),
+            })
+        );
+        assert_eq!(
+            mapper
+                .url_for_path(r"D:\a\engine\build\version.h")
+                .as_deref(),
+            Some("https://example.com/version.h")
+        );
+        assert_eq!(mapper.url_for_path(r"C:\Program Files\foo.h"), None);
+
+        assert!(SourceLinkPathMapper::parse(b"not json").is_none());
+    }
+}
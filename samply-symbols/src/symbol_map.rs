@@ -3,6 +3,11 @@ use std::sync::Arc;
 
 use debugid::DebugId;
 
+use crate::function_signature::{
+    strip_function_parameters, strip_function_parameters_from_frames,
+    strip_function_parameters_in_place,
+};
+use crate::path_mapper::{apply_source_path_substitutions, SourcePathSubstitution};
 use crate::shared::LookupAddress;
 use crate::{
     AddressInfo, ExternalFileAddressRef, ExternalFileRef, FileAndPathHelper, FileLocation,
@@ -17,6 +22,10 @@ pub trait SymbolMapTrait {
     fn iter_symbols(&self) -> Box<dyn Iterator<Item = (u32, Cow<'_, str>)> + '_>;
 
     fn lookup_sync(&self, address: LookupAddress) -> Option<SyncAddressInfo>;
+
+    /// See [`FileAndPathHelper::full_function_signatures`]. Only symbol maps
+    /// with DWARF debug info do something with this.
+    fn set_full_function_signatures(&self, _full_signatures: bool) {}
 }
 
 pub trait SymbolMapTraitWithExternalFileSupport<FC>: SymbolMapTrait {
@@ -49,6 +58,8 @@ pub struct SymbolMap<H: FileAndPathHelper> {
     debug_file_location: H::FL,
     inner: InnerSymbolMap<H::F>,
     helper: Option<Arc<H>>,
+    source_path_substitutions: Vec<SourcePathSubstitution>,
+    strip_function_parameters: bool,
 }
 
 impl<H: FileAndPathHelper> SymbolMap<H> {
@@ -60,6 +71,8 @@ impl<H: FileAndPathHelper> SymbolMap<H> {
             debug_file_location,
             inner: InnerSymbolMap::WithoutAddFile(inner),
             helper: None,
+            source_path_substitutions: Vec::new(),
+            strip_function_parameters: false,
         }
     }
 
@@ -72,6 +85,8 @@ impl<H: FileAndPathHelper> SymbolMap<H> {
             debug_file_location,
             inner: InnerSymbolMap::WithAddFile(inner),
             helper: Some(helper),
+            source_path_substitutions: Vec::new(),
+            strip_function_parameters: false,
         }
     }
 
@@ -83,6 +98,38 @@ impl<H: FileAndPathHelper> SymbolMap<H> {
             debug_file_location,
             inner: InnerSymbolMap::Direct(inner),
             helper: None,
+            source_path_substitutions: Vec::new(),
+            strip_function_parameters: false,
+        }
+    }
+
+    pub(crate) fn set_source_path_substitutions(
+        &mut self,
+        source_path_substitutions: Vec<SourcePathSubstitution>,
+    ) {
+        self.source_path_substitutions = source_path_substitutions;
+    }
+
+    pub(crate) fn set_strip_function_parameters(&mut self, strip_function_parameters: bool) {
+        self.strip_function_parameters = strip_function_parameters;
+    }
+
+    pub(crate) fn set_full_function_signatures(&self, full_signatures: bool) {
+        self.inner().set_full_function_signatures(full_signatures);
+    }
+
+    /// Applies the source path substitutions and, if enabled, removes the
+    /// parameter lists from the function names.
+    fn rewrite_frames(&self, frames: &mut [FrameDebugInfo]) {
+        apply_source_path_substitutions(&self.source_path_substitutions, frames);
+        if self.strip_function_parameters {
+            strip_function_parameters_from_frames(frames);
+        }
+    }
+
+    fn rewrite_symbol_name(&self, name: &mut String) {
+        if self.strip_function_parameters {
+            strip_function_parameters_in_place(name);
         }
     }
 
@@ -107,14 +154,41 @@ impl<H: FileAndPathHelper> SymbolMap<H> {
     }
 
     pub fn iter_symbols(&self) -> Box<dyn Iterator<Item = (u32, Cow<'_, str>)> + '_> {
-        self.inner().iter_symbols()
+        let symbols = self.inner().iter_symbols();
+        if !self.strip_function_parameters {
+            return symbols;
+        }
+        Box::new(symbols.map(|(address, name)| {
+            let name = match name {
+                Cow::Borrowed(name) => strip_function_parameters(name),
+                Cow::Owned(mut name) => {
+                    strip_function_parameters_in_place(&mut name);
+                    Cow::Owned(name)
+                }
+            };
+            (address, name)
+        }))
     }
 
     pub fn lookup_sync(&self, address: LookupAddress) -> Option<SyncAddressInfo> {
-        self.inner().lookup_sync(address)
+        let mut address_info = self.inner().lookup_sync(address)?;
+        self.rewrite_symbol_name(&mut address_info.symbol.name);
+        if let Some(FramesLookupResult::Available(frames)) = &mut address_info.frames {
+            self.rewrite_frames(frames);
+        }
+        Some(address_info)
     }
 
     pub async fn lookup(&self, address: LookupAddress) -> Option<AddressInfo> {
+        let mut address_info = self.lookup_with_raw_paths(address).await?;
+        self.rewrite_symbol_name(&mut address_info.symbol.name);
+        if let Some(frames) = &mut address_info.frames {
+            self.rewrite_frames(frames);
+        }
+        Some(address_info)
+    }
+
+    async fn lookup_with_raw_paths(&self, address: LookupAddress) -> Option<AddressInfo> {
         let address_info = self.inner().lookup_sync(address)?;
         let symbol = address_info.symbol;
         let (mut external, inner) = match (address_info.frames, &self.inner) {
@@ -178,6 +252,15 @@ impl<H: FileAndPathHelper> SymbolMap<H> {
     pub async fn lookup_external(
         &self,
         external: &ExternalFileAddressRef,
+    ) -> Option<Vec<FrameDebugInfo>> {
+        let mut frames = self.lookup_external_with_raw_paths(external).await?;
+        self.rewrite_frames(&mut frames);
+        Some(frames)
+    }
+
+    async fn lookup_external_with_raw_paths(
+        &self,
+        external: &ExternalFileAddressRef,
     ) -> Option<Vec<FrameDebugInfo>> {
         let helper = self.helper.as_deref()?;
         let inner = match &self.inner {
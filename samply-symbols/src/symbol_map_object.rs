@@ -1,6 +1,7 @@
 use std::borrow::Cow;
 use std::marker::PhantomData;
 use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use addr2line::{LookupResult, SplitDwarfLoad};
@@ -12,7 +13,7 @@ use object::{
 use yoke::Yoke;
 use yoke_derive::Yokeable;
 
-use crate::dwarf::convert_frames;
+use crate::dwarf::{convert_frames, signature_unit};
 use crate::path_mapper::PathMapper;
 use crate::shared::{
     relative_address_base, ExternalFileAddressInFileRef, ExternalFileAddressRef, ExternalFileRef,
@@ -31,6 +32,9 @@ enum FullSymbolListEntry<'a, Symbol> {
     /// A synthesized symbol for the entry point of the object.
     SynthesizedEntryPoint,
     Symbol(Symbol),
+    /// A symbol which isn't in the object's symbol table, e.g. a local symbol
+    /// of a dyld shared cache image.
+    ExtraSymbol(&'a [u8]),
     Export(object::Export<'a>),
     EndAddress,
 }
@@ -44,6 +48,10 @@ impl<'a, Symbol: object::ObjectSymbol<'a>> std::fmt::Debug for FullSymbolListEnt
                 .debug_tuple("Symbol")
                 .field(&arg0.name().unwrap())
                 .finish(),
+            Self::ExtraSymbol(arg0) => f
+                .debug_tuple("ExtraSymbol")
+                .field(&String::from_utf8_lossy(arg0))
+                .finish(),
             Self::Export(arg0) => f
                 .debug_tuple("Export")
                 .field(&std::str::from_utf8(arg0.name()).unwrap())
@@ -62,6 +70,7 @@ impl<'a, Symbol: object::ObjectSymbol<'a>> FullSymbolListEntry<'a, Symbol> {
             FullSymbolListEntry::Symbol(symbol) => {
                 String::from_utf8_lossy(symbol.name_bytes().ok()?)
             }
+            FullSymbolListEntry::ExtraSymbol(name) => String::from_utf8_lossy(name),
             FullSymbolListEntry::Export(export) => String::from_utf8_lossy(export.name()),
         };
         Some(name)
@@ -69,7 +78,9 @@ impl<'a, Symbol: object::ObjectSymbol<'a>> FullSymbolListEntry<'a, Symbol> {
 
     fn counts_as_proper_symbol(&self) -> bool {
         match self {
-            FullSymbolListEntry::Symbol(_) | FullSymbolListEntry::Export(_) => true,
+            FullSymbolListEntry::Symbol(_)
+            | FullSymbolListEntry::ExtraSymbol(_)
+            | FullSymbolListEntry::Export(_) => true,
             FullSymbolListEntry::EndAddress
             | FullSymbolListEntry::Synthesized
             | FullSymbolListEntry::SynthesizedEntryPoint => false,
@@ -85,6 +96,7 @@ impl<'a, Symbol: object::ObjectSymbol<'a> + 'a> SymbolList<'a, Symbol> {
     pub fn new<'file, O>(
         object_file: &'file O,
         base_address: u64,
+        extra_symbols: &[(u64, &'a [u8])],
         function_start_addresses: Option<&[u32]>,
         function_end_addresses: Option<&[u32]>,
     ) -> Self
@@ -127,6 +139,12 @@ impl<'a, Symbol: object::ObjectSymbol<'a> + 'a> SymbolList<'a, Symbol> {
                         return false;
                     }
 
+                    // Filter out the placeholders which the dyld shared cache builder
+                    // leaves in place of the local symbols it moves out of the images.
+                    if symbol.name_bytes() == Ok(b"<redacted>") {
+                        return false;
+                    }
+
                     // Filter out non-Text symbols which don't have a symbol size.
                     match symbol.kind() {
                         SymbolKind::Text => {
@@ -157,7 +175,15 @@ impl<'a, Symbol: object::ObjectSymbol<'a> + 'a> SymbolList<'a, Symbol> {
                 }),
         );
 
-        // 3. Exports (only used by exe / dll objects)
+        // 3. Extra symbols from outside the symbol table (only used by dyld shared cache images)
+        entries.extend(extra_symbols.iter().filter_map(|(address, name)| {
+            Some((
+                u32::try_from(address.checked_sub(base_address)?).ok()?,
+                FullSymbolListEntry::ExtraSymbol(name),
+            ))
+        }));
+
+        // 4. Exports (only used by exe / dll objects)
         if let Ok(exports) = object_file.exports() {
             for export in exports {
                 entries.push((
@@ -167,7 +193,7 @@ impl<'a, Symbol: object::ObjectSymbol<'a> + 'a> SymbolList<'a, Symbol> {
             }
         }
 
-        // 4. Placeholder symbols based on function start addresses
+        // 5. Placeholder symbols based on function start addresses
         if let Some(function_start_addresses) = function_start_addresses {
             // Use function start addresses with synthesized symbols of the form fun_abcdef
             // as the ultimate fallback.
@@ -181,7 +207,7 @@ impl<'a, Symbol: object::ObjectSymbol<'a> + 'a> SymbolList<'a, Symbol> {
             );
         }
 
-        // 5. A placeholder symbol for the entry point.
+        // 6. A placeholder symbol for the entry point.
         if let Some(entry_point) = object_file.entry().checked_sub(base_address) {
             entries.push((
                 entry_point as u32,
@@ -189,7 +215,7 @@ impl<'a, Symbol: object::ObjectSymbol<'a> + 'a> SymbolList<'a, Symbol> {
             ));
         }
 
-        // 6. End addresses from text section ends
+        // 7. End addresses from text section ends
         // These entries serve to "terminate" the last function of each section,
         // so that addresses in the following section are not considered
         // to be part of the last function of that previous section.
@@ -205,7 +231,7 @@ impl<'a, Symbol: object::ObjectSymbol<'a> + 'a> SymbolList<'a, Symbol> {
                 }),
         );
 
-        // 7. End addresses for sized symbols
+        // 8. End addresses for sized symbols
         // These addresses serve to "terminate" functions symbols.
         entries.extend(
             object_file
@@ -227,7 +253,7 @@ impl<'a, Symbol: object::ObjectSymbol<'a> + 'a> SymbolList<'a, Symbol> {
                 }),
         );
 
-        // 8. End addresses for known functions ends
+        // 9. End addresses for known functions ends
         // These addresses serve to "terminate" functions from function_start_addresses.
         // They come from .eh_frame or .pdata info, which has the function size.
         if let Some(function_end_addresses) = function_end_addresses {
@@ -362,6 +388,7 @@ pub struct ObjectSymbolMapInner<'a, Symbol, FC: FileContents + 'static, DDM> {
     image_base_address: u64,
     dwo_dwarf_maker: &'a DDM,
     cached_external_file: Mutex<Option<ExternalFileSymbolMap<FC>>>,
+    full_signatures: AtomicBool,
     _phantom: PhantomData<FC>,
 }
 
@@ -416,7 +443,10 @@ where
                     match &*cached_external_file {
                         Some(external_file) if external_file.file_path() == file_path => {
                             return external_file
-                                .lookup(&external.address_in_file)
+                                .lookup_with_signatures(
+                                    &external.address_in_file,
+                                    self.full_signatures.load(Ordering::Relaxed),
+                                )
                                 .map(FramesLookupResult::Available);
                         }
                         _ => {}
@@ -432,7 +462,10 @@ where
                 };
                 let external_file = ExternalFileSymbolMap::new(file_path, file_contents).ok()?;
                 let lookup_result = external_file
-                    .lookup(&external.address_in_file)
+                    .lookup_with_signatures(
+                        &external.address_in_file,
+                        self.full_signatures.load(Ordering::Relaxed),
+                    )
                     .map(FramesLookupResult::Available);
 
                 *self.cached_external_file.lock().unwrap() = Some(external_file);
@@ -480,8 +513,10 @@ where
                             continue;
                         }
                         LookupResult::Output(Ok(frame_iter)) => {
+                            // The frames come from the DWO, so there's no unit
+                            // for adding parameter types.
                             let mut path_mapper = self.path_mapper.lock().unwrap();
-                            convert_frames(frame_iter, &mut path_mapper)
+                            convert_frames(frame_iter, &mut path_mapper, None)
                                 .map(FramesLookupResult::Available)
                         }
                         LookupResult::Output(Err(_)) => None,
@@ -502,6 +537,11 @@ where
         self.debug_id
     }
 
+    fn set_full_function_signatures(&self, full_signatures: bool) {
+        self.full_signatures
+            .store(full_signatures, Ordering::Relaxed);
+    }
+
     fn symbol_count(&self) -> usize {
         let iter = self.list.entries.iter();
         iter.filter(|&(_, entry)| entry.counts_as_proper_symbol())
@@ -564,8 +604,13 @@ where
                         ))
                     }
                     LookupResult::Output(Ok(frame_iter)) => {
+                        let unit = signature_unit(
+                            &*context,
+                            svma,
+                            self.full_signatures.load(Ordering::Relaxed),
+                        );
                         let mut path_mapper = self.path_mapper.lock().unwrap();
-                        convert_frames(frame_iter, &mut path_mapper)
+                        convert_frames(frame_iter, &mut path_mapper, unit)
                             .map(FramesLookupResult::Available)
                     }
                     LookupResult::Output(Err(_)) => {
@@ -650,6 +695,7 @@ impl<'a, FC: FileContents + 'static> ObjectSymbolMapInnerWrapper<'a, FC> {
         addr2line_context: Option<addr2line::Context<EndianSlice<'a, RunTimeEndian>>>,
         dwp_package: Option<addr2line::gimli::DwarfPackage<EndianSlice<'a, RunTimeEndian>>>,
         debug_id: DebugId,
+        extra_symbols: &[(u64, &'a [u8])],
         function_start_addresses: Option<&[u32]>,
         function_end_addresses: Option<&[u32]>,
         dwo_dwarf_maker: &'a DDM,
@@ -664,6 +710,7 @@ impl<'a, FC: FileContents + 'static> ObjectSymbolMapInnerWrapper<'a, FC> {
         let list = SymbolList::new(
             object_file,
             base_address,
+            extra_symbols,
             function_start_addresses,
             function_end_addresses,
         );
@@ -679,6 +726,7 @@ impl<'a, FC: FileContents + 'static> ObjectSymbolMapInnerWrapper<'a, FC> {
             svma_file_ranges: SvmaFileRanges::from_object(object_file),
             dwo_dwarf_maker,
             cached_external_file: Mutex::new(None),
+            full_signatures: AtomicBool::new(false),
             _phantom: PhantomData,
         };
         Self(Box::new(inner))
@@ -22,6 +22,7 @@ use crate::shared::{
     FileAndPathHelper, FileContents, FileContentsWrapper, FileLocation, FrameDebugInfo,
     FramesLookupResult, LookupAddress, SourceFilePath, SymbolInfo,
 };
+use crate::source_link::SourceLinkPathMapper;
 use crate::symbol_map::{GetInnerSymbolMap, SymbolMap, SymbolMapTrait};
 use crate::symbol_map_object::{
     ObjectSymbolMap, ObjectSymbolMapInnerWrapper, ObjectSymbolMapOuter,
@@ -132,6 +133,7 @@ impl<T: FileContents + 'static> ObjectSymbolMapOuter<T> for PeSymbolMapDataAndOb
                 .ok(),
             None,
             debug_id,
+            &[],
             function_starts.as_deref(),
             function_ends.as_deref(),
             &(),
@@ -165,6 +167,7 @@ struct PdbObject<'data, FC: FileContents + 'static> {
     context_data: pdb_addr2line::ContextPdbData<'data, 'data, &'data FileContentsWrapper<FC>>,
     debug_id: DebugId,
     srcsrv_stream: Option<Box<dyn Deref<Target = [u8]> + Send + 'data>>,
+    sourcelink_stream: Option<Box<dyn Deref<Target = [u8]> + Send + 'data>>,
 }
 
 trait PdbObjectTrait {
@@ -178,12 +181,24 @@ impl<'data, FC: FileContents + 'static> PdbObjectTrait for PdbObject<'data, FC>
     fn make_pdb_symbol_map(&self) -> Result<PdbSymbolMapInner<'_>, Error> {
         let context = self.make_context()?;
 
-        let path_mapper = match &self.srcsrv_stream {
+        let srcsrv = match &self.srcsrv_stream {
             Some(srcsrv_stream) => Some(SrcSrvPathMapper::new(srcsrv::SrcSrvStream::parse(
                 srcsrv_stream.deref(),
             )?)),
             None => None,
         };
+        // A broken SourceLink stream only costs us the source URLs, so it's not an error.
+        let source_link = self
+            .sourcelink_stream
+            .as_ref()
+            .and_then(|stream| SourceLinkPathMapper::parse(stream.deref()));
+        let path_mapper = match (srcsrv, source_link) {
+            (None, None) => None,
+            (srcsrv, source_link) => Some(PdbSourcePathMapper {
+                srcsrv,
+                source_link,
+            }),
+        };
         let path_mapper = PathMapper::new_with_maybe_extra_mapper(path_mapper);
 
         let symbol_map = PdbSymbolMapInner {
@@ -236,7 +251,7 @@ pub struct PdbSymbolMapInnerWrapper<'data>(Box<dyn SymbolMapTrait + Send + 'data
 struct PdbSymbolMapInner<'object> {
     context: Box<dyn PdbAddr2lineContextTrait + Send + 'object>,
     debug_id: DebugId,
-    path_mapper: Mutex<PathMapper<SrcSrvPathMapper<'object>>>,
+    path_mapper: Mutex<PathMapper<PdbSourcePathMapper<'object>>>,
 }
 
 impl<'object> SymbolMapTrait for PdbSymbolMapInner<'object> {
@@ -273,15 +288,21 @@ impl<'object> SymbolMapTrait for PdbSymbolMapInner<'object> {
                 return None;
             }
         };
+        // pdb-addr2line translates the PDB's addresses through the OMAP tables, if
+        // the binary was rearranged after linking (e.g. the PGO-optimized Windows
+        // system DLLs), so the rva can be looked up directly.
         let function_frames = self.context.find_frames(rva).ok()??;
         let symbol_address = function_frames.start_rva;
         let symbol_name = match &function_frames.frames.last().unwrap().function {
             Some(name) => demangle::demangle_any(name),
             None => "unknown".to_string(),
         };
+        // With OMAP, the blocks of a function can be moved apart, and its end, which
+        // is translated separately from its start, can even end up in front of it.
+        // Then we don't know the size.
         let function_size = function_frames
             .end_rva
-            .map(|end_rva| end_rva - function_frames.start_rva);
+            .and_then(|end_rva| end_rva.checked_sub(function_frames.start_rva));
 
         let symbol = SymbolInfo {
             address: symbol_address,
@@ -339,6 +360,11 @@ impl<T: FileContents + 'static> PdbObjectWithFileData<T> {
                 Err(pdb::Error::StreamNameNotFound | pdb::Error::StreamNotFound(_)) => None,
                 Err(e) => return Err(Error::PdbError("pdb.named_stream(srcsrv)", e)),
             };
+            let sourcelink_stream = match pdb.named_stream(b"sourcelink") {
+                Ok(stream) => Some(box_stream(stream)),
+                Err(pdb::Error::StreamNameNotFound | pdb::Error::StreamNotFound(_)) => None,
+                Err(e) => return Err(Error::PdbError("pdb.named_stream(sourcelink)", e)),
+            };
 
             let context_data = pdb_addr2line::ContextPdbData::try_from_pdb(pdb)
                 .context("ContextConstructionData::try_from_pdb")?;
@@ -347,6 +373,7 @@ impl<T: FileContents + 'static> PdbObjectWithFileData<T> {
                 context_data,
                 debug_id,
                 srcsrv_stream,
+                sourcelink_stream,
             };
 
             Ok(PdbObjectWrapper(Box::new(pdb_object)))
@@ -423,6 +450,22 @@ pub fn get_symbol_map_for_pdb<H: FileAndPathHelper>(
     ))
 }
 
+/// Map raw file paths to special "permalink" paths, using the srcsrv stream or,
+/// for files which it doesn't cover, the SourceLink stream.
+struct PdbSourcePathMapper<'a> {
+    srcsrv: Option<SrcSrvPathMapper<'a>>,
+    source_link: Option<SourceLinkPathMapper>,
+}
+
+impl<'a> ExtraPathMapper for PdbSourcePathMapper<'a> {
+    fn map_path(&mut self, path: &str) -> Option<MappedPath> {
+        if let Some(mapped_path) = self.srcsrv.as_mut().and_then(|m| m.map_path(path)) {
+            return Some(mapped_path);
+        }
+        self.source_link.as_mut()?.map_path(path)
+    }
+}
+
 /// Map raw file paths to special "permalink" paths, using the srcsrv stream.
 /// This allows finding source code for applications that were not compiled on this
 /// machine, for example when using PDBs that were downloaded from a symbol server.
@@ -638,4 +681,54 @@ mod test {
             Err(nom::Err::Error(nom::error::Error::new("otherstuff", nom::error::ErrorKind::Eof)))
         );
     }
+
+    /// Returns a function whose end was moved in front of its start by OMAP.
+    struct RearrangedFunctionContext;
+
+    impl PdbAddr2lineContextTrait for RearrangedFunctionContext {
+        fn find_frames(
+            &self,
+            probe: u32,
+        ) -> Result<Option<pdb_addr2line::FunctionFrames>, pdb_addr2line::Error> {
+            if !(0x2000..0x2100).contains(&probe) {
+                return Ok(None);
+            }
+            Ok(Some(pdb_addr2line::FunctionFrames {
+                start_rva: 0x2000,
+                end_rva: Some(0x1800),
+                frames: vec![pdb_addr2line::Frame {
+                    function: Some("RtlpMovedFunction".to_string()),
+                    file: None,
+                    line: None,
+                }],
+            }))
+        }
+
+        fn function_count(&self) -> usize {
+            1
+        }
+
+        fn functions(&self) -> Box<dyn Iterator<Item = pdb_addr2line::Function> + '_> {
+            Box::new(std::iter::empty())
+        }
+    }
+
+    #[test]
+    fn test_lookup_omap_rearranged_function() {
+        let symbol_map = PdbSymbolMapInner {
+            context: Box::new(RearrangedFunctionContext),
+            debug_id: DebugId::nil(),
+            path_mapper: Mutex::new(PathMapper::new_with_maybe_extra_mapper(None)),
+        };
+        let info = symbol_map
+            .lookup_sync(LookupAddress::Relative(0x2010))
+            .unwrap();
+        assert_eq!(info.symbol.address, 0x2000);
+        assert_eq!(info.symbol.size, None);
+        assert_eq!(info.symbol.name, "RtlpMovedFunction");
+        assert!(info.frames.is_none());
+        assert!(symbol_map
+            .lookup_sync(LookupAddress::Relative(0x1900))
+            .is_none());
+    }
 }
@@ -0,0 +1,395 @@
+//! Adds markers for external events, e.g. deploys or the phases of a load
+//! test, to an existing profile, for `samply annotate-profile`.
+//!
+//! The markers are added to the main thread of the first process, in the
+//! processed profile format. Their times are given either as wall-clock times,
+//! which are converted with the profile's `meta.startTime`, or relative to the
+//! start of the profile.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+const ANNOTATION_MARKER_TYPE: &str = "Annotation";
+const ANNOTATION_CATEGORY: &str = "Annotation";
+
+#[derive(thiserror::Error, Debug)]
+pub enum AnnotateError {
+    #[error("Unexpected profile format: {0}")]
+    UnexpectedFormat(&'static str),
+
+    #[error("The marker {0:?} ends before it starts")]
+    EndsBeforeStart(String),
+}
+
+/// A marker to add, as given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub name: String,
+    pub start: AnnotationTime,
+    /// Set for interval markers.
+    pub end: Option<AnnotationTime>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnnotationTime {
+    /// A full RFC 3339 timestamp, e.g. `2024-05-01T12:03:05Z`.
+    WallClock(SystemTime),
+    /// A UTC time of day, e.g. `12:03:05Z`, on the day closest to the start of
+    /// the profile.
+    TimeOfDay(String),
+    /// A duration since the start of the profile, e.g. `+90s` or `1m30s`.
+    Relative(Duration),
+}
+
+/// Parses `NAME@TIME` or `NAME@START..END`.
+pub fn parse_annotation(s: &str) -> Result<Annotation, String> {
+    let (name, times) = s
+        .rsplit_once('@')
+        .ok_or_else(|| format!("Expected NAME@TIME or NAME@START..END, got {s:?}"))?;
+    if name.is_empty() {
+        return Err(format!("Missing the marker name in {s:?}"));
+    }
+    let (start, end) = match times.split_once("..") {
+        Some((start, end)) => (parse_time(start)?, Some(parse_time(end)?)),
+        None => (parse_time(times)?, None),
+    };
+    Ok(Annotation {
+        name: name.to_string(),
+        start,
+        end,
+    })
+}
+
+/// Parses a single time, like the times in [`parse_annotation`].
+pub fn parse_time(s: &str) -> Result<AnnotationTime, String> {
+    let s = s.trim();
+    if let Some(relative) = s.strip_prefix('+') {
+        return parse_relative(relative);
+    }
+    if !s.contains(':') {
+        return parse_relative(s);
+    }
+    if s.contains(['T', ' ']) {
+        let time = humantime::parse_rfc3339_weak(s)
+            .map_err(|err| format!("Invalid timestamp {s:?}: {err}"))?;
+        Ok(AnnotationTime::WallClock(time))
+    } else {
+        let time_of_day = s.strip_suffix('Z').unwrap_or(s);
+        // Validate it now, so that mistakes are reported by the argument parser.
+        humantime::parse_rfc3339_weak(&format!("2000-01-01T{time_of_day}"))
+            .map_err(|err| format!("Invalid time of day {s:?}: {err}"))?;
+        Ok(AnnotationTime::TimeOfDay(time_of_day.to_string()))
+    }
+}
+
+fn parse_relative(s: &str) -> Result<AnnotationTime, String> {
+    let duration = match s.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 => Duration::from_secs_f64(seconds),
+        _ => humantime::parse_duration(s).map_err(|err| format!("Invalid time {s:?}: {err}"))?,
+    };
+    Ok(AnnotationTime::Relative(duration))
+}
+
+impl AnnotationTime {
+    /// Returns the time in milliseconds since the start of the profile, which
+    /// started at `start_time_ms` milliseconds since the Unix epoch.
+    pub(crate) fn resolve(&self, start_time_ms: f64) -> Result<f64, AnnotateError> {
+        let wall_clock_ms = match self {
+            AnnotationTime::Relative(duration) => return Ok(duration.as_secs_f64() * 1000.0),
+            AnnotationTime::WallClock(time) => ms_since_epoch(*time),
+            AnnotationTime::TimeOfDay(time_of_day) => {
+                const DAY_MS: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+                let start = UNIX_EPOCH + Duration::from_secs_f64(start_time_ms / 1000.0);
+                let start_date = &humantime::format_rfc3339(start).to_string()[..10];
+                let time = humantime::parse_rfc3339_weak(&format!("{start_date}T{time_of_day}"))
+                    .map_err(|_| AnnotateError::UnexpectedFormat("invalid meta.startTime"))?;
+                // Pick the day which puts the time closest to the start of the
+                // profile, so that profiles around midnight work.
+                let time_ms = ms_since_epoch(time);
+                [time_ms - DAY_MS, time_ms, time_ms + DAY_MS]
+                    .into_iter()
+                    .min_by(|a, b| {
+                        (a - start_time_ms)
+                            .abs()
+                            .total_cmp(&(b - start_time_ms).abs())
+                    })
+                    .unwrap()
+            }
+        };
+        Ok(wall_clock_ms - start_time_ms)
+    }
+}
+
+pub(crate) fn ms_since_epoch(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs_f64() * 1000.0,
+        Err(err) => -err.duration().as_secs_f64() * 1000.0,
+    }
+}
+
+/// Adds the annotations as markers to the main thread of the first process,
+/// or to the first thread if no thread is marked as a main thread.
+pub fn annotate_profile(
+    profile: &mut Value,
+    annotations: &[Annotation],
+) -> Result<(), AnnotateError> {
+    let start_time_ms = profile["meta"]["startTime"]
+        .as_f64()
+        .ok_or(AnnotateError::UnexpectedFormat("missing meta.startTime"))?;
+    let category = add_annotation_meta(profile)?;
+
+    let threads = profile
+        .get_mut("threads")
+        .and_then(Value::as_array_mut)
+        .filter(|threads| !threads.is_empty())
+        .ok_or(AnnotateError::UnexpectedFormat(
+            "the profile has no threads",
+        ))?;
+    let thread_index = threads
+        .iter()
+        .position(|thread| thread["isMainThread"] == json!(true))
+        .unwrap_or(0);
+    let thread = &mut threads[thread_index];
+
+    for annotation in annotations {
+        let start = annotation.start.resolve(start_time_ms)?;
+        let end = match &annotation.end {
+            Some(end) => Some(end.resolve(start_time_ms)?),
+            None => None,
+        };
+        if end.is_some_and(|end| end < start) {
+            return Err(AnnotateError::EndsBeforeStart(annotation.name.clone()));
+        }
+        let name_index = intern_string(thread, &annotation.name)?;
+        let data = json!({ "type": ANNOTATION_MARKER_TYPE });
+        add_marker(thread, name_index, category, start, end, data)?;
+    }
+    Ok(())
+}
+
+/// Adds the annotation category and marker schema to `meta`, if they're not
+/// there yet, and returns the index of the category.
+fn add_annotation_meta(profile: &mut Value) -> Result<u64, AnnotateError> {
+    add_marker_schema(
+        profile,
+        json!({
+            "name": ANNOTATION_MARKER_TYPE,
+            "display": ["marker-chart", "marker-table", "timeline-overview"],
+            "chartLabel": "{marker.name}",
+            "tooltipLabel": "{marker.name}",
+            "tableLabel": "{marker.name}",
+            "data": [],
+        }),
+    )?;
+    find_or_add_category(profile, ANNOTATION_CATEGORY, "blue")
+}
+
+fn profile_meta(profile: &mut Value) -> Result<&mut Map<String, Value>, AnnotateError> {
+    profile
+        .get_mut("meta")
+        .and_then(Value::as_object_mut)
+        .ok_or(AnnotateError::UnexpectedFormat("missing meta"))
+}
+
+/// Adds the marker schema to `meta`, unless there already is a schema with
+/// its name.
+pub(crate) fn add_marker_schema(profile: &mut Value, schema: Value) -> Result<(), AnnotateError> {
+    let schemas = profile_meta(profile)?
+        .entry("markerSchema")
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or(AnnotateError::UnexpectedFormat(
+            "expected meta.markerSchema to be an array",
+        ))?;
+    if !schemas
+        .iter()
+        .any(|existing| existing["name"] == schema["name"])
+    {
+        schemas.push(schema);
+    }
+    Ok(())
+}
+
+/// Returns the index of the category with this name, adding it if needed.
+pub(crate) fn find_or_add_category(
+    profile: &mut Value,
+    name: &str,
+    color: &str,
+) -> Result<u64, AnnotateError> {
+    let categories = profile_meta(profile)?
+        .entry("categories")
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or(AnnotateError::UnexpectedFormat(
+            "expected meta.categories to be an array",
+        ))?;
+    let index = match categories
+        .iter()
+        .position(|category| category["name"] == name)
+    {
+        Some(index) => index,
+        None => {
+            categories.push(json!({
+                "name": name,
+                "color": color,
+                "subcategories": ["Other"],
+            }));
+            categories.len() - 1
+        }
+    };
+    Ok(index as u64)
+}
+
+/// Returns the index of `s` in the thread's string table, adding it if needed.
+pub(crate) fn intern_string(thread: &mut Value, s: &str) -> Result<u64, AnnotateError> {
+    let strings = thread
+        .as_object_mut()
+        .ok_or(AnnotateError::UnexpectedFormat("expected a thread object"))?
+        .entry("stringArray")
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or(AnnotateError::UnexpectedFormat(
+            "expected stringArray to be an array",
+        ))?;
+    let index = match strings.iter().position(|string| string == s) {
+        Some(index) => index,
+        None => {
+            strings.push(json!(s));
+            strings.len() - 1
+        }
+    };
+    Ok(index as u64)
+}
+
+/// Adds a marker with the given `data` to the thread. It's an interval marker
+/// if `end` is set, and an instant marker otherwise.
+pub(crate) fn add_marker(
+    thread: &mut Value,
+    name_index: u64,
+    category: u64,
+    start: f64,
+    end: Option<f64>,
+    data: Value,
+) -> Result<(), AnnotateError> {
+    let markers = thread
+        .as_object_mut()
+        .ok_or(AnnotateError::UnexpectedFormat("expected a thread object"))?
+        .entry("markers")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or(AnnotateError::UnexpectedFormat(
+            "expected markers to be an object",
+        ))?;
+    // Instant markers have phase 0, interval markers phase 1.
+    let phase = if end.is_some() { 1 } else { 0 };
+    let columns = [
+        ("category", json!(category)),
+        ("data", data),
+        ("endTime", json!(end)),
+        ("name", json!(name_index)),
+        ("phase", json!(phase)),
+        ("startTime", json!(start)),
+    ];
+    for (key, value) in columns {
+        markers
+            .entry(key)
+            .or_insert_with(|| Value::Array(Vec::new()))
+            .as_array_mut()
+            .ok_or(AnnotateError::UnexpectedFormat(
+                "expected the marker columns to be arrays",
+            ))?
+            .push(value);
+    }
+    let length = markers.get("length").and_then(Value::as_u64).unwrap_or(0);
+    markers.insert("length".to_string(), json!(length + 1));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{Profile, ReferenceTimestamp, SamplingInterval, Timestamp};
+
+    use super::*;
+
+    #[test]
+    fn parses_annotations() {
+        assert_eq!(
+            parse_annotation("Deploy v1.2@12:03:05Z").unwrap(),
+            Annotation {
+                name: "Deploy v1.2".to_string(),
+                start: AnnotationTime::TimeOfDay("12:03:05".to_string()),
+                end: None,
+            }
+        );
+        assert_eq!(
+            parse_annotation("Warmup@+0s..1m30s").unwrap(),
+            Annotation {
+                name: "Warmup".to_string(),
+                start: AnnotationTime::Relative(Duration::ZERO),
+                end: Some(AnnotationTime::Relative(Duration::from_secs(90))),
+            }
+        );
+        assert_eq!(
+            parse_annotation("a@b@2.5").unwrap().start,
+            AnnotationTime::Relative(Duration::from_millis(2500))
+        );
+        assert!(matches!(
+            parse_annotation("x@2024-05-01T12:03:05Z").unwrap().start,
+            AnnotationTime::WallClock(_)
+        ));
+        assert!(parse_annotation("Deploy").is_err());
+        assert!(parse_annotation("@5s").is_err());
+        assert!(parse_annotation("Deploy@25:00:00").is_err());
+    }
+
+    #[test]
+    fn adds_markers_to_the_main_thread() {
+        // 2024-05-01T12:00:00Z
+        let start_time_ms = 1_714_564_800_000.0;
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(start_time_ms),
+            SamplingInterval::from_millis(1),
+        );
+        let process = profile.add_process("test", 42, Timestamp::from_millis_since_reference(0.0));
+        profile.add_thread(
+            process,
+            43,
+            Timestamp::from_millis_since_reference(0.0),
+            false,
+        );
+        profile.add_thread(
+            process,
+            42,
+            Timestamp::from_millis_since_reference(0.0),
+            true,
+        );
+        let mut profile = serde_json::to_value(&profile).unwrap();
+
+        let annotations = [
+            parse_annotation("Deploy v1.2@12:03:05Z").unwrap(),
+            parse_annotation("Load test@+10s..20s").unwrap(),
+        ];
+        annotate_profile(&mut profile, &annotations).unwrap();
+
+        let threads = profile["threads"].as_array().unwrap();
+        let (main_threads, other_threads): (Vec<_>, Vec<_>) = threads
+            .iter()
+            .partition(|thread| thread["isMainThread"] == json!(true));
+        let thread = main_threads[0];
+        let markers = &thread["markers"];
+        assert_eq!(markers["length"], json!(2));
+        assert_eq!(markers["startTime"], json!([185_000.0, 10_000.0]));
+        assert_eq!(markers["endTime"], json!([null, 20_000.0]));
+        assert_eq!(markers["phase"], json!([0, 1]));
+        let name_index = markers["name"][0].as_u64().unwrap() as usize;
+        assert_eq!(thread["stringArray"][name_index], json!("Deploy v1.2"));
+        let category = markers["category"][0].as_u64().unwrap() as usize;
+        assert_eq!(
+            profile["meta"]["categories"][category]["name"],
+            json!(ANNOTATION_CATEGORY)
+        );
+        assert_eq!(other_threads[0]["markers"]["length"], json!(0));
+    }
+}
@@ -0,0 +1,556 @@
+//! Adds externally collected time series, e.g. the request rate of a server
+//! or the events of a load generator, to an existing profile, for
+//! `samply attach-data`. Each row of the data becomes a counter sample or a
+//! marker.
+//!
+//! The data is a CSV file with a header row, or a JSON array of objects or of
+//! `[time, value]` pairs. Timestamps are either RFC 3339 wall-clock times, or
+//! numbers, which are Unix times unless an anchor says which time of the
+//! profile one of them corresponds to.
+
+use std::time::SystemTime;
+
+use serde_json::{json, Value};
+
+use crate::annotate::{self, AnnotateError, AnnotationTime};
+
+const EXTERNAL_DATA_MARKER_TYPE: &str = "ExternalData";
+const EXTERNAL_DATA_CATEGORY: &str = "External data";
+
+/// The column names which are recognized as the time column, in lower case.
+const TIME_COLUMN_NAMES: &[&str] = &["time", "timestamp", "ts"];
+const END_COLUMN_NAME: &str = "end";
+
+#[derive(thiserror::Error, Debug)]
+pub enum AttachDataError {
+    #[error("Line {0}: {1}")]
+    Parse(usize, String),
+
+    #[error("Row {0}: {1}")]
+    InvalidRow(usize, String),
+
+    #[error("The data has no column {0:?}")]
+    NoColumn(String),
+
+    #[error("The data has no value column")]
+    NoValueColumn,
+
+    #[error("Row {row}: the value {value:?} is not a number")]
+    NotANumber { row: usize, value: String },
+
+    #[error("The profile has no process with pid {0}")]
+    NoProcess(String),
+
+    #[error("{0}")]
+    Annotate(#[from] AnnotateError),
+}
+
+/// Whether each row becomes a counter sample or a marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataKind {
+    Counter,
+    Marker,
+}
+
+/// A timestamp in the data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataTime {
+    WallClock(SystemTime),
+    /// In the time unit of [`TimeAlignment`].
+    Number(f64),
+}
+
+/// One row of the data. `end` is set for markers from data with an "end"
+/// column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataRow {
+    pub time: DataTime,
+    pub end: Option<DataTime>,
+    pub value: String,
+}
+
+/// Says that the numeric data timestamp `data_time` was at `time`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeAnchor {
+    pub data_time: f64,
+    pub time: AnnotationTime,
+}
+
+/// How the data timestamps map to the times of the profile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeAlignment {
+    /// The length of one unit of the numeric timestamps, in milliseconds.
+    pub unit_ms: f64,
+    /// Without an anchor, numeric timestamps are Unix times.
+    pub anchor: Option<TimeAnchor>,
+    /// Added to all times, e.g. to correct for a clock which was ahead.
+    pub offset_ms: f64,
+}
+
+/// Parses `VALUE=TIME`, where TIME is in the format of the times of
+/// `annotate-profile --marker`.
+pub fn parse_anchor(s: &str) -> Result<TimeAnchor, String> {
+    let (data_time, time) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected VALUE=TIME, got {s:?}"))?;
+    let data_time = data_time
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid data timestamp {data_time:?}"))?;
+    Ok(TimeAnchor {
+        data_time,
+        time: annotate::parse_time(time)?,
+    })
+}
+
+impl TimeAlignment {
+    /// Returns the time in milliseconds since the start of the profile, which
+    /// started at `start_time_ms` milliseconds since the Unix epoch.
+    fn resolve(&self, time: &DataTime, start_time_ms: f64) -> Result<f64, AttachDataError> {
+        let time_ms = match (time, &self.anchor) {
+            (DataTime::WallClock(time), _) => annotate::ms_since_epoch(*time) - start_time_ms,
+            (DataTime::Number(number), None) => number * self.unit_ms - start_time_ms,
+            (DataTime::Number(number), Some(anchor)) => {
+                anchor.time.resolve(start_time_ms)? + (number - anchor.data_time) * self.unit_ms
+            }
+        };
+        Ok(time_ms + self.offset_ms)
+    }
+}
+
+/// Parses CSV or JSON data, which is recognized by its first character. The
+/// values are taken from `value_column`, or from the first column which isn't
+/// the time or end column.
+pub fn parse_data(text: &str, value_column: Option<&str>) -> Result<Vec<DataRow>, AttachDataError> {
+    if text.trim_start().starts_with('[') {
+        parse_json(text, value_column)
+    } else {
+        parse_csv(text, value_column)
+    }
+}
+
+fn parse_csv(text: &str, value_column: Option<&str>) -> Result<Vec<DataRow>, AttachDataError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim_end_matches('\r')))
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'));
+    let Some((_, header)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    let columns = split_csv_line(header);
+    let time_index = columns
+        .iter()
+        .position(|column| is_time_column(column))
+        .unwrap_or(0);
+    let end_index = columns
+        .iter()
+        .position(|column| column.eq_ignore_ascii_case(END_COLUMN_NAME));
+    let value_index = match value_column {
+        Some(name) => columns
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| AttachDataError::NoColumn(name.to_string()))?,
+        None => (0..columns.len())
+            .find(|index| *index != time_index && Some(*index) != end_index)
+            .ok_or(AttachDataError::NoValueColumn)?,
+    };
+
+    let mut rows = Vec::new();
+    for (line_number, line) in lines {
+        let fields = split_csv_line(line);
+        let field = |index: usize| fields.get(index).map(String::as_str).unwrap_or_default();
+        let time = parse_data_time(field(time_index))
+            .map_err(|message| AttachDataError::Parse(line_number, message))?;
+        let end = match end_index.map(field).filter(|end| !end.is_empty()) {
+            Some(end) => Some(
+                parse_data_time(end)
+                    .map_err(|message| AttachDataError::Parse(line_number, message))?,
+            ),
+            None => None,
+        };
+        rows.push(DataRow {
+            time,
+            end,
+            value: field(value_index).to_string(),
+        });
+    }
+    Ok(rows)
+}
+
+/// Splits a CSV line into its fields. Fields can be quoted with double
+/// quotes, with `""` for a quote inside a quoted field.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+fn parse_json(text: &str, value_column: Option<&str>) -> Result<Vec<DataRow>, AttachDataError> {
+    let json: Vec<Value> = serde_json::from_str(text)
+        .map_err(|err| AttachDataError::Parse(err.line(), err.to_string()))?;
+    let mut rows = Vec::with_capacity(json.len());
+    for (index, row) in json.iter().enumerate() {
+        let row_number = index + 1;
+        let parse_error = |message: String| AttachDataError::InvalidRow(row_number, message);
+        let (time, end, value) = match row {
+            Value::Array(pair) if pair.len() >= 2 => (&pair[0], None, &pair[1]),
+            Value::Object(object) => {
+                let time = object
+                    .iter()
+                    .find(|(key, _)| is_time_column(key))
+                    .map(|(_, time)| time)
+                    .ok_or_else(|| parse_error("missing the time".to_string()))?;
+                let value_key = value_column.unwrap_or("value");
+                let value = object
+                    .get(value_key)
+                    .ok_or_else(|| AttachDataError::NoColumn(value_key.to_string()))?;
+                (time, object.get(END_COLUMN_NAME), value)
+            }
+            _ => {
+                return Err(parse_error(
+                    "expected an object or a [time, value] pair".to_string(),
+                ))
+            }
+        };
+        let end = match end {
+            Some(end) => Some(parse_data_time(&json_string(end)).map_err(parse_error)?),
+            None => None,
+        };
+        rows.push(DataRow {
+            time: parse_data_time(&json_string(time)).map_err(parse_error)?,
+            end,
+            value: json_string(value),
+        });
+    }
+    Ok(rows)
+}
+
+fn json_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn is_time_column(name: &str) -> bool {
+    TIME_COLUMN_NAMES
+        .iter()
+        .any(|time_name| name.eq_ignore_ascii_case(time_name))
+}
+
+fn parse_data_time(s: &str) -> Result<DataTime, String> {
+    let s = s.trim();
+    if let Ok(number) = s.parse::<f64>() {
+        return Ok(DataTime::Number(number));
+    }
+    humantime::parse_rfc3339_weak(s)
+        .map(DataTime::WallClock)
+        .map_err(|err| format!("Invalid timestamp {s:?}: {err}"))
+}
+
+/// Adds the rows to the process with the pid `pid`, or to the first process,
+/// as a counter or as markers on its main thread. Returns the number of added
+/// counter samples or markers.
+pub fn attach_data(
+    profile: &mut Value,
+    rows: &[DataRow],
+    kind: DataKind,
+    name: &str,
+    alignment: &TimeAlignment,
+    pid: Option<&str>,
+) -> Result<usize, AttachDataError> {
+    let start_time_ms = profile["meta"]["startTime"]
+        .as_f64()
+        .ok_or(AnnotateError::UnexpectedFormat("missing meta.startTime"))?;
+    let thread_index = find_main_thread(profile, pid)?;
+    match kind {
+        DataKind::Counter => {
+            let mut samples = Vec::with_capacity(rows.len());
+            for (index, row) in rows.iter().enumerate() {
+                let value = row
+                    .value
+                    .parse::<f64>()
+                    .map_err(|_| AttachDataError::NotANumber {
+                        row: index + 1,
+                        value: row.value.clone(),
+                    })?;
+                samples.push((alignment.resolve(&row.time, start_time_ms)?, value));
+            }
+            add_counter(profile, name, thread_index, samples)?;
+        }
+        DataKind::Marker => {
+            annotate::add_marker_schema(
+                profile,
+                json!({
+                    "name": EXTERNAL_DATA_MARKER_TYPE,
+                    "display": ["marker-chart", "marker-table"],
+                    "chartLabel": "{marker.data.value}",
+                    "tooltipLabel": "{marker.name}: {marker.data.value}",
+                    "tableLabel": "{marker.data.value}",
+                    "data": [
+                        {
+                            "key": "value",
+                            "label": "Value",
+                            "format": "unique-string",
+                            "searchable": true,
+                        },
+                    ],
+                }),
+            )?;
+            let category =
+                annotate::find_or_add_category(profile, EXTERNAL_DATA_CATEGORY, "green")?;
+            let thread = &mut profile["threads"][thread_index];
+            let name_index = annotate::intern_string(thread, name)?;
+            for row in rows {
+                let start = alignment.resolve(&row.time, start_time_ms)?;
+                let end = match &row.end {
+                    Some(end) => Some(alignment.resolve(end, start_time_ms)?.max(start)),
+                    None => None,
+                };
+                let data = json!({
+                    "type": EXTERNAL_DATA_MARKER_TYPE,
+                    "value": annotate::intern_string(thread, &row.value)?,
+                });
+                annotate::add_marker(thread, name_index, category, start, end, data)?;
+            }
+        }
+    }
+    Ok(rows.len())
+}
+
+/// Finds the main thread of the process, or its first thread if none of its
+/// threads is marked as the main thread.
+fn find_main_thread(profile: &Value, pid: Option<&str>) -> Result<usize, AttachDataError> {
+    let threads = profile["threads"]
+        .as_array()
+        .filter(|threads| !threads.is_empty())
+        .ok_or(AnnotateError::UnexpectedFormat(
+            "the profile has no threads",
+        ))?;
+    let pid = match pid {
+        Some(pid) => pid.to_string(),
+        None => json_string(&threads[0]["pid"]),
+    };
+    let process_threads: Vec<usize> = (0..threads.len())
+        .filter(|index| json_string(&threads[*index]["pid"]) == pid)
+        .collect();
+    process_threads
+        .iter()
+        .copied()
+        .find(|index| threads[*index]["isMainThread"] == json!(true))
+        .or(process_threads.first().copied())
+        .ok_or(AttachDataError::NoProcess(pid))
+}
+
+/// Adds a counter with the values at the given times. Counter samples store
+/// the change from the previous sample.
+fn add_counter(
+    profile: &mut Value,
+    name: &str,
+    thread_index: usize,
+    mut samples: Vec<(f64, f64)>,
+) -> Result<(), AttachDataError> {
+    samples.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    let mut previous_value = 0.0;
+    let mut time = Vec::with_capacity(samples.len());
+    let mut count = Vec::with_capacity(samples.len());
+    for (sample_time, value) in samples {
+        time.push(json!(sample_time));
+        count.push(json!(value - previous_value));
+        previous_value = value;
+    }
+    let pid = profile["threads"][thread_index]["pid"].clone();
+    let counter = json!({
+        "category": EXTERNAL_DATA_CATEGORY,
+        "name": name,
+        "description": name,
+        "mainThreadIndex": thread_index,
+        "pid": pid,
+        "samples": {
+            "length": time.len(),
+            "count": count,
+            "number": vec![1; time.len()],
+            "time": time,
+        },
+    });
+    profile
+        .as_object_mut()
+        .ok_or(AnnotateError::UnexpectedFormat(
+            "the profile is not an object",
+        ))?
+        .entry("counters")
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or(AnnotateError::UnexpectedFormat(
+            "expected counters to be an array",
+        ))?
+        .push(counter);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use fxprof_processed_profile::{Profile, ReferenceTimestamp, SamplingInterval, Timestamp};
+
+    use super::*;
+
+    fn test_profile() -> Value {
+        // 2024-05-01T12:00:00Z
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(1_714_564_800_000.0),
+            SamplingInterval::from_millis(1),
+        );
+        let at = Timestamp::from_millis_since_reference;
+        let first = profile.add_process("first", 10, at(0.0));
+        profile.add_thread(first, 10, at(0.0), true);
+        let second = profile.add_process("second", 20, at(0.0));
+        profile.add_thread(second, 21, at(0.0), false);
+        profile.add_thread(second, 20, at(0.0), true);
+        serde_json::to_value(&profile).unwrap()
+    }
+
+    #[test]
+    fn parses_csv_and_json() {
+        let rows = parse_data(
+            "# requests\nrequests,Timestamp\n\"1,5\",2024-05-01T12:00:01Z\r\n\n3,1714564802.5\n",
+            None,
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].value, "1,5");
+        assert_eq!(
+            rows[0].time,
+            DataTime::WallClock(humantime::parse_rfc3339_weak("2024-05-01T12:00:01Z").unwrap())
+        );
+        assert_eq!(rows[1].time, DataTime::Number(1_714_564_802.5));
+
+        let rows = parse_data("time,end,phase\n1,2,warmup\n3,,run\n", None).unwrap();
+        assert_eq!(rows[0].end, Some(DataTime::Number(2.0)));
+        assert_eq!(rows[1].end, None);
+        assert_eq!(rows[1].value, "run");
+        assert!(matches!(
+            parse_data("time,value\n1,2\n", Some("rate")),
+            Err(AttachDataError::NoColumn(_))
+        ));
+        assert!(matches!(
+            parse_data("time,value\nsoon,2\n", None),
+            Err(AttachDataError::Parse(2, _))
+        ));
+
+        let rows = parse_data(
+            r#"[{"ts": 5, "rate": 12.5}, {"ts": "6", "rate": 13}]"#,
+            Some("rate"),
+        )
+        .unwrap();
+        assert_eq!(rows[1].time, DataTime::Number(6.0));
+        assert_eq!(rows[1].value, "13");
+        let rows = parse_data("[[1, \"a\"], [2, \"b\"]]", None).unwrap();
+        assert_eq!(rows[1].value, "b");
+    }
+
+    #[test]
+    fn aligns_times() {
+        let start_time_ms = 1_714_564_800_000.0;
+        let unix = TimeAlignment {
+            unit_ms: 1000.0,
+            anchor: None,
+            offset_ms: -5.0,
+        };
+        let time = unix.resolve(&DataTime::Number(1_714_564_801.0), start_time_ms);
+        assert_eq!(time.unwrap(), 995.0);
+
+        let anchored = TimeAlignment {
+            unit_ms: 1.0,
+            anchor: Some(parse_anchor("1500=12:00:02Z").unwrap()),
+            offset_ms: 0.0,
+        };
+        let time = anchored.resolve(&DataTime::Number(1600.0), start_time_ms);
+        assert_eq!(time.unwrap(), 2100.0);
+        let relative = TimeAlignment {
+            anchor: Some(TimeAnchor {
+                data_time: 0.0,
+                time: AnnotationTime::Relative(Duration::from_secs(1)),
+            }),
+            ..anchored
+        };
+        let time = relative.resolve(&DataTime::Number(20.0), start_time_ms);
+        assert_eq!(time.unwrap(), 1020.0);
+        assert!(parse_anchor("12:00:02Z").is_err());
+    }
+
+    #[test]
+    fn attaches_counters_and_markers() {
+        let alignment = TimeAlignment {
+            unit_ms: 1.0,
+            anchor: Some(parse_anchor("0=0").unwrap()),
+            offset_ms: 0.0,
+        };
+        let mut profile = test_profile();
+        let rows = parse_data("time,rate\n20,7\n10,5\n", None).unwrap();
+        let name = "Requests/s";
+        let added = attach_data(
+            &mut profile,
+            &rows,
+            DataKind::Counter,
+            name,
+            &alignment,
+            None,
+        );
+        assert_eq!(added.unwrap(), 2);
+        let counter = &profile["counters"][0];
+        assert_eq!(counter["mainThreadIndex"], json!(0));
+        assert_eq!(counter["pid"], json!("10"));
+        assert_eq!(counter["samples"]["time"], json!([10.0, 20.0]));
+        assert_eq!(counter["samples"]["count"], json!([5.0, 2.0]));
+
+        let rows = parse_data("time,end,phase\n1,3,warmup\n", None).unwrap();
+        let kind = DataKind::Marker;
+        attach_data(&mut profile, &rows, kind, "Phase", &alignment, Some("20")).unwrap();
+        let threads = profile["threads"].as_array().unwrap();
+        let thread = threads
+            .iter()
+            .find(|thread| thread["pid"] == json!("20") && thread["isMainThread"] == json!(true))
+            .unwrap();
+        let markers = &thread["markers"];
+        assert_eq!(markers["length"], json!(1));
+        assert_eq!(markers["phase"], json!([1]));
+        assert_eq!(markers["endTime"], json!([3.0]));
+        let value_index = markers["data"][0]["value"].as_u64().unwrap() as usize;
+        assert_eq!(thread["stringArray"][value_index], json!("warmup"));
+
+        let rows = parse_data("time,rate\n1,fast\n", None).unwrap();
+        assert!(matches!(
+            attach_data(
+                &mut profile,
+                &rows,
+                DataKind::Counter,
+                name,
+                &alignment,
+                None
+            ),
+            Err(AttachDataError::NotANumber { row: 1, .. })
+        ));
+        assert!(matches!(
+            attach_data(&mut profile, &rows, kind, name, &alignment, Some("30")),
+            Err(AttachDataError::NoProcess(_))
+        ));
+    }
+}
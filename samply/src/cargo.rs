@@ -0,0 +1,287 @@
+//! Support for `samply cargo test` and `samply cargo bench`: Build the test
+//! binaries with debug info, find the one which contains the requested tests,
+//! and return the command line for running it under the profiler.
+
+use std::ffi::{OsStr, OsString};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use serde_derive::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CargoCommand {
+    Test,
+    Bench,
+}
+
+impl CargoCommand {
+    pub fn name(self) -> &'static str {
+        match self {
+            CargoCommand::Test => "test",
+            CargoCommand::Bench => "bench",
+        }
+    }
+}
+
+/// A test or benchmark executable which was built by cargo.
+#[derive(Debug, Clone)]
+pub struct TestBinary {
+    pub target_name: String,
+    pub target_kind: String,
+    pub executable: PathBuf,
+    pub manifest_dir: PathBuf,
+}
+
+/// The arguments of `samply cargo test` / `samply cargo bench`, split up
+/// into the parts that go to cargo and the parts that go to the test binary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SplitCargoArgs {
+    /// Arguments for `cargo test --no-run`.
+    pub cargo_args: Vec<OsString>,
+    /// The test name filter, if any.
+    pub filter: Option<OsString>,
+    /// Arguments after `--`, for the test binary.
+    pub binary_args: Vec<OsString>,
+}
+
+/// Cargo options which take a value as the next argument.
+const CARGO_OPTIONS_WITH_VALUE: &[&str] = &[
+    "-p",
+    "--package",
+    "--exclude",
+    "--bin",
+    "--example",
+    "--test",
+    "--bench",
+    "-F",
+    "--features",
+    "-j",
+    "--jobs",
+    "--profile",
+    "--target",
+    "--target-dir",
+    "--manifest-path",
+    "--color",
+    "--config",
+    "-Z",
+];
+
+/// Splits `cargo test [OPTIONS] [TESTNAME] [-- <args>...]` into its parts.
+pub fn split_cargo_args(args: &[OsString]) -> SplitCargoArgs {
+    let mut split = SplitCargoArgs::default();
+    let mut args = args.iter();
+    let mut next_is_option_value = false;
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            split.binary_args.extend(args.cloned());
+            break;
+        }
+        let is_option = arg.as_encoded_bytes().starts_with(b"-");
+        if !next_is_option_value && !is_option && split.filter.is_none() {
+            split.filter = Some(arg.clone());
+        } else {
+            split.cargo_args.push(arg.clone());
+        }
+        next_is_option_value = is_option && CARGO_OPTIONS_WITH_VALUE.iter().any(|o| arg == *o);
+    }
+    split
+}
+
+impl SplitCargoArgs {
+    /// The arguments for running the test binary. `cargo bench` passes
+    /// `--bench` to benchmark binaries, so we do the same.
+    pub fn test_binary_args(&self, command: CargoCommand) -> Vec<OsString> {
+        let mut args: Vec<OsString> = self.filter.iter().cloned().collect();
+        if command == CargoCommand::Bench {
+            args.push("--bench".into());
+        }
+        args.extend(self.binary_args.iter().cloned());
+        args
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoMessage {
+    reason: String,
+    #[serde(default)]
+    executable: Option<PathBuf>,
+    #[serde(default)]
+    manifest_path: Option<PathBuf>,
+    #[serde(default)]
+    profile: Option<ArtifactProfile>,
+    #[serde(default)]
+    target: Option<ArtifactTarget>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtifactProfile {
+    test: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtifactTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+/// Runs `cargo test --no-run` (or `cargo bench --no-run`) with debug info
+/// enabled, and returns the test executables that were built.
+pub fn build_test_binaries(
+    command: CargoCommand,
+    cargo_args: &[OsString],
+) -> Result<Vec<TestBinary>, String> {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut child = Command::new(cargo)
+        .arg(command.name())
+        .arg("--no-run")
+        .arg("--message-format=json-render-diagnostics")
+        .args(cargo_args)
+        .env("CARGO_PROFILE_TEST_DEBUG", "true")
+        .env("CARGO_PROFILE_BENCH_DEBUG", "true")
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Could not run cargo: {err}"))?;
+
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut binaries = Vec::new();
+    for line in stdout.lines() {
+        let line = line.map_err(|err| format!("Could not read cargo output: {err}"))?;
+        let Ok(message) = serde_json::from_str::<CargoMessage>(&line) else {
+            continue;
+        };
+        if message.reason != "compiler-artifact" {
+            continue;
+        }
+        let (Some(executable), Some(profile), Some(target), Some(manifest_path)) = (
+            message.executable,
+            message.profile,
+            message.target,
+            message.manifest_path,
+        ) else {
+            continue;
+        };
+        if !profile.test {
+            continue;
+        }
+        binaries.push(TestBinary {
+            target_name: target.name,
+            target_kind: target.kind.join(", "),
+            executable,
+            manifest_dir: manifest_path
+                .parent()
+                .map(ToOwned::to_owned)
+                .unwrap_or_default(),
+        });
+    }
+
+    let status = child
+        .wait()
+        .map_err(|err| format!("Could not wait for cargo: {err}"))?;
+    if !status.success() {
+        return Err(format!("cargo {} failed with {status}", command.name()));
+    }
+    Ok(binaries)
+}
+
+/// Returns the number of tests and benchmarks in the binary which match the
+/// filter, using the `--list` option of the test harness.
+fn matching_test_count(binary: &TestBinary, filter: Option<&OsStr>) -> usize {
+    let output = Command::new(&binary.executable)
+        .args(filter)
+        .arg("--list")
+        .current_dir(&binary.manifest_dir)
+        .stderr(Stdio::null())
+        .output();
+    let Ok(output) = output else {
+        return 0;
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| {
+            line.ends_with(": test") || line.ends_with(": bench") || line.ends_with(": benchmark")
+        })
+        .count()
+}
+
+/// Picks the binary to profile. If cargo built more than one test binary, we
+/// pick the one that has tests matching the filter, if there's exactly one.
+pub fn select_test_binary(
+    binaries: Vec<TestBinary>,
+    filter: Option<&OsStr>,
+) -> Result<TestBinary, String> {
+    if binaries.len() <= 1 {
+        return binaries
+            .into_iter()
+            .next()
+            .ok_or_else(|| "cargo didn't build any test binaries.".to_string());
+    }
+
+    let (matching, non_matching): (Vec<_>, Vec<_>) = binaries
+        .into_iter()
+        .partition(|binary| matching_test_count(binary, filter) > 0);
+    if matching.len() == 1 {
+        return Ok(matching.into_iter().next().unwrap());
+    }
+
+    let (mut message, candidates) = if matching.is_empty() {
+        (
+            "None of the test binaries contain matching tests.".to_string(),
+            non_matching,
+        )
+    } else {
+        (
+            "More than one test binary contains matching tests, but samply can only profile one."
+                .to_string(),
+            matching,
+        )
+    };
+    message.push_str(" Pick a binary with --lib, --test <NAME> or --bench <NAME>:");
+    for binary in candidates {
+        message.push_str(&format!(
+            "\n  {} ({}): {}",
+            binary.target_name,
+            binary.target_kind,
+            binary.executable.display()
+        ));
+    }
+    Err(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn os_strings(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn split_args() {
+        let split = split_cargo_args(&os_strings(&[
+            "-p",
+            "mycrate",
+            "--release",
+            "parse",
+            "--",
+            "--nocapture",
+        ]));
+        assert_eq!(
+            split.cargo_args,
+            os_strings(&["-p", "mycrate", "--release"])
+        );
+        assert_eq!(split.filter, Some("parse".into()));
+        assert_eq!(split.binary_args, os_strings(&["--nocapture"]));
+        assert_eq!(
+            split.test_binary_args(CargoCommand::Bench),
+            os_strings(&["parse", "--bench", "--nocapture"])
+        );
+
+        let split = split_cargo_args(&os_strings(&["--test=integration", "--lib"]));
+        assert_eq!(
+            split.cargo_args,
+            os_strings(&["--test=integration", "--lib"])
+        );
+        assert_eq!(split.filter, None);
+    }
+}
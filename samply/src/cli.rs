@@ -0,0 +1,3435 @@
+//! The `samply` command line tool, which [`cli_main`] runs.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "macos",
+    target_os = "linux",
+    target_os = "windows"
+))]
+use crate::cargo;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use crate::linux::profiler;
+#[cfg(target_os = "macos")]
+use crate::mac;
+#[cfg(target_os = "macos")]
+use crate::mac::profiler;
+use crate::profile_json_preparse::parse_libinfo_map_from_profile_file;
+use crate::profile_query::ProfileQuery;
+use crate::server::{start_server_main, PortSelection, ServerProps};
+use crate::shared::included_processes::IncludedProcesses;
+use crate::shared::namespace_categories::NamespaceCategoryRule;
+#[cfg(any(target_os = "android", target_os = "macos", target_os = "linux"))]
+use crate::shared::record_error::RecordError;
+use crate::shared::recording_props::{
+    CoreClrProfileProps, ProcessLaunchProps, ProfileCreationProps, RecordingMode, RecordingProps,
+    TimestampClock,
+};
+#[cfg(target_os = "windows")]
+use crate::shared::recording_props::{EtwProviderAlias, EtwProviderProps, StackWalkEvent};
+use crate::shared::save_profile::save_profile_to_file;
+use crate::shared::symbol_props::SymbolProps;
+#[cfg(target_os = "windows")]
+use crate::windows;
+#[cfg(target_os = "windows")]
+use crate::windows::profiler;
+use crate::{
+    annotate, attach_data, export_symbols, flamegraph, follow, import, linux_shared, merge, pprof,
+    profile_json_preparse, report, search_index, server, session_dir, split, stats, symbolicate,
+    symbolication_sandbox, syscall_log, validate, wakegraph,
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use fxprof_processed_profile::{Profile, ThreadOrder};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "samply",
+    version,
+    about = r#"
+samply is a sampling CPU profiler.
+Run a command, record a CPU profile of its execution, and open the profiler UI.
+Recording is currently supported on Linux and macOS.
+On other platforms, samply can only load existing profiles.
+
+EXAMPLES:
+    # Default usage:
+    samply record ./yourcommand yourargs
+
+    # On Linux, you can also profile existing processes by pid:
+    samply record -p 12345 # Linux only
+
+    # Alternative usage: Save profile to file for later viewing, and then load it.
+    samply record --save-only -o prof.json -- ./yourcommand yourargs
+    samply load prof.json # Opens in the browser and supplies symbols
+
+    # Build the tests of a Rust crate and profile the ones matching a filter:
+    samply cargo test my_test_name
+
+    # Import perf.data files from Linux perf or Android simpleperf:
+    samply import perf.data
+
+    # Import Trace Event Format JSON files, e.g. from chrome://tracing:
+    samply import trace.json
+
+    # Import V8 CPU profiles from the Chrome DevTools or from node --cpu-prof:
+    samply import CPU.20240101.120000.4321.0.001.cpuprofile
+
+    # Import .NET traces from dotnet-trace collect:
+    samply import myapp_20240101_120000.nettrace
+
+    # Recover the profile of a recording which crashed, from its checkpoint file:
+    samply record --checkpoint-interval 5 -o prof.json.gz ./yourcommand yourargs
+    samply recover prof.json.gz.checkpoint
+
+    # Restart a Windows service and profile it, or launch a packaged app (Windows only):
+    samply record --service Spooler
+    samply record --appid Microsoft.WindowsCalculator_8wekyb3d8bbwe!App
+
+    # Skip the first 5 seconds, then record for 30 seconds:
+    samply record --delay 5s --duration 30s ./yourcommand yourargs
+
+    # Color the timeline by crate / namespace, with tokio and hyper grouped as "Async":
+    samply record --categorize-by-namespace --namespace-category tokio=Async \
+        --namespace-category hyper=Async ./yourcommand yourargs
+
+    # Print the top functions and categories of a saved profile as Markdown or CSV:
+    samply report prof.json --format csv --top 20
+
+    # Write a search index with the profile, and find the functions matching "parse":
+    samply record --search-index -o prof.json.gz ./yourcommand yourargs
+    samply grep -i parse prof.json.gz
+
+    # Save the symbols needed by a profile as Breakpad .sym files:
+    samply export symbols prof.json -o symbols/
+
+    # Symbolicate the addresses (relative to the image base) in addresses.txt:
+    samply symbolicate addresses.txt --lib target/release/myapp
+
+    # Print the stack depths and the samples per thread and per library of a profile:
+    samply stats prof.json
+
+    # Check a profile from another tool for broken references, and repair them:
+    samply validate exported.json --repair repaired.json
+
+    # Render a saved profile as a flame graph:
+    samply export flamegraph prof.json -o flamegraph.svg
+
+    # Record a client here and a server on another machine, and merge the profiles:
+    samply record --follow ssh://host2 --follow-args="-p 4321" ./client
+
+    # Merge profiles which were recorded at the same time on different machines:
+    samply merge client.json.gz server.json.gz -o merged.json.gz --clock-offset server.json.gz=12.5
+
+    # Line up a Chrome trace (Trace Event Format) with a samply recording of the same time:
+    samply merge prof.json.gz trace.json -o merged.json.gz
+
+    # Split a system-wide profile into one profile per process, e.g. split/prof.1234-firefox.json.gz:
+    samply split prof.json.gz --by-process -o split
+
+    # Mark a deploy at 12:03:05 UTC, and the first 30 seconds as the warmup phase:
+    samply annotate-profile prof.json.gz --marker "Deploy v1.2@12:03:05Z" --marker "Warmup@0s..30s"
+
+    # Show the syscalls from an strace log, made with strace -f -ttt -T, as markers:
+    samply annotate-profile prof.json.gz --syscall-log strace.log
+
+    # Add a request rate, with Unix times in its "time" column, as a counter:
+    samply attach-data prof.json.gz requests.csv --type counter --name "Requests/s"
+"#
+)]
+struct Opt {
+    #[command(subcommand)]
+    action: Action,
+}
+
+#[derive(Debug, Subcommand)]
+enum Action {
+    #[cfg(any(
+        target_os = "android",
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "windows"
+    ))]
+    /// Record a profile and display it.
+    Record(RecordArgs),
+
+    #[cfg(any(
+        target_os = "android",
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "windows"
+    ))]
+    /// Build the tests or benchmarks with cargo, and record a profile of the test binary.
+    Cargo(CargoArgs),
+
+    /// Load a profile from a file and display it.
+    #[command(alias = "serve")]
+    Load(LoadArgs),
+
+    /// Import a perf.data file (from Linux perf or Android simpleperf), an ETW trace, a
+    /// Trace Event Format JSON file (e.g. from chrome://tracing), a .cpuprofile file (from
+    /// the Chrome DevTools or Node's --cpu-prof), a .nettrace file (from dotnet-trace), a
+    /// heaptrack or massif memory profile, or the text output of macOS's `sample` or
+    /// `spindump`, and display the profile.
+    Import(ImportArgs),
+
+    /// Print a summary of a profile's call tree, with the self and total weight per
+    /// function and per category, as a Markdown or CSV table.
+    Report(ReportArgs),
+
+    /// Find the functions and markers whose names contain a pattern, with their sample
+    /// weights and marker counts. Uses the profile's search index (written by
+    /// `--search-index`) if there is one.
+    Grep(GrepArgs),
+
+    /// Print statistics of a profile, to characterize it without loading it in the
+    /// profiler: the distribution of stack depths, the samples per thread, the markers
+    /// per name and the samples per library.
+    Stats(StatsArgs),
+
+    /// Check that a profile in the processed profile format is consistent, e.g. one
+    /// written by a third-party exporter: that the columns of each table have the same
+    /// length, that all string, stack, frame and thread indexes are valid, and that the
+    /// samples are in time order. Exits with an error if there are problems which
+    /// weren't repaired.
+    Validate(ValidateArgs),
+
+    /// Export data from a profile.
+    Export(ExportArgs),
+
+    /// Resolve a list of addresses in a library to functions, files, line numbers and
+    /// inlined frames, e.g. to symbolicate crash or sanitizer output. Reads one hex
+    /// address per line.
+    Symbolicate(SymbolicateArgs),
+
+    /// Convert the checkpoint file of a recording which didn't finish, e.g. because samply
+    /// or the machine crashed, into a profile, and display it. The checkpoint file is
+    /// written by `samply record --checkpoint-interval`, and has to be recovered on the
+    /// machine which recorded it.
+    Recover(ImportArgs),
+
+    /// Combine profiles which were recorded at the same time on different machines, e.g.
+    /// on a client and on a server, into one profile, aligned by their start times.
+    Merge(MergeArgs),
+
+    /// Split a profile into one profile per process, e.g. a system-wide profile which is
+    /// too big to load. Each profile gets the libraries which its process uses.
+    Split(SplitArgs),
+
+    /// Add markers for external events, e.g. deploys or the phases of a test, to a saved
+    /// profile, at wall-clock times or at times relative to the start of the profile.
+    AnnotateProfile(AnnotateProfileArgs),
+
+    /// Add externally collected time series, e.g. the request rate of a server, from a
+    /// CSV or JSON file with timestamps to a saved profile, as a counter or as markers.
+    AttachData(AttachDataArgs),
+
+    #[cfg(target_os = "windows")]
+    #[clap(hide = true)]
+    /// Used in the elevated helper process.
+    RunElevatedHelper(RunElevatedHelperArgs),
+
+    #[clap(hide = true)]
+    /// Used in the sandboxed symbolication helper process.
+    RunSymbolicationHelper(RunSymbolicationHelperArgs),
+
+    #[clap(hide = true)]
+    /// Used in the sandboxed helper process of `samply import --sandbox-symbolication`.
+    RunImportHelper(RunImportHelperArgs),
+
+    #[clap(hide = true)]
+    /// Used on the other machines of `samply record --follow`, to measure their clock offset.
+    ClockSync,
+
+    /// Codesign the samply binary on macOS to allow attaching to processes.
+    #[cfg(target_os = "macos")]
+    Setup,
+}
+
+#[derive(Debug, Args)]
+struct LoadArgs {
+    /// Path to the file that should be loaded.
+    file: PathBuf,
+
+    /// Watch the file for changes, and reload the profile in the browser when it's
+    /// rewritten, e.g. by a conversion script that's being worked on. The browser
+    /// opens a small page which keeps the profiler tab up to date. The library
+    /// paths are only read once, so libraries which are new in the rewritten
+    /// profile may be missing symbols.
+    #[arg(long)]
+    watch: bool,
+
+    #[command(flatten)]
+    server_args: ServerArgs,
+
+    #[command(flatten)]
+    symbol_args: SymbolArgs,
+}
+
+#[derive(Debug, Args)]
+struct ReportArgs {
+    /// Path to the profile file, as saved by `samply record` or `samply import`.
+    file: PathBuf,
+
+    /// Output format.
+    #[arg(long, default_value_t = ReportFormatArg::Md)]
+    format: ReportFormatArg,
+
+    /// Number of functions to list, by descending self weight.
+    #[arg(long, default_value = "50")]
+    top: usize,
+
+    /// Only summarize the thread with this index. By default, all threads are combined.
+    #[arg(long)]
+    thread: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+struct GrepArgs {
+    /// The text to search for in function and marker names.
+    pattern: String,
+
+    /// Path to the profile file, as saved by `samply record` or `samply import`.
+    file: PathBuf,
+
+    /// Match the pattern regardless of case.
+    #[arg(short, long)]
+    ignore_case: bool,
+}
+
+#[derive(Debug, Args)]
+struct StatsArgs {
+    /// Path to the profile file, as saved by `samply record` or `samply import`.
+    file: PathBuf,
+
+    /// Number of threads, markers and libraries to list.
+    #[arg(long, default_value = "20")]
+    top: usize,
+}
+
+#[derive(Debug, Args)]
+struct ValidateArgs {
+    /// Path to the profile file, in the processed profile format.
+    file: PathBuf,
+
+    /// Repair the problems which can be repaired, e.g. by sorting the samples by time or
+    /// by dropping invalid stack references, and write the repaired profile to this file.
+    #[arg(long, value_name = "OUTPUT")]
+    repair: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ExportArgs {
+    #[command(subcommand)]
+    what: ExportAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum ExportAction {
+    /// Write a Breakpad .sym file for every library referenced by the profile, so
+    /// that the profile can be symbolicated later with `--breakpad-symbol-dir`.
+    Symbols(ExportSymbolsArgs),
+
+    /// Render the samples as a self-contained, interactive flame graph, as SVG or
+    /// as an HTML page, depending on the extension of the output file.
+    Flamegraph(ExportFlamegraphArgs),
+
+    /// Write the samples as a gzipped pprof profile, for `go tool pprof` and other
+    /// pprof tools. Samples are labeled with their CPU and its core type, if known.
+    Pprof(ExportPprofArgs),
+
+    /// Write a graph of which threads woke which, from the "Wakeup" markers of a profile
+    /// recorded with `--wakeups`, to trace latency chains across threads and processes.
+    Wakegraph(ExportWakegraphArgs),
+}
+
+#[derive(Debug, Args)]
+struct ExportSymbolsArgs {
+    /// Path to the profile file, as saved by `samply record` or `samply import`.
+    file: PathBuf,
+
+    /// The directory to write the .sym files to.
+    #[arg(short, long)]
+    output: PathBuf,
+
+    #[command(flatten)]
+    symbol_args: SymbolArgs,
+}
+
+#[derive(Debug, Args)]
+struct ExportWakegraphArgs {
+    /// Path to the profile file, as saved by `samply record` or `samply import`.
+    file: PathBuf,
+
+    /// The file to write the graph to. By default, it's written to stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Output format: a Graphviz DOT graph, or JSON with the nodes and edges.
+    #[arg(long, default_value_t = WakeGraphFormatArg::Dot)]
+    format: WakeGraphFormatArg,
+}
+
+#[derive(Debug, Args)]
+struct ExportFlamegraphArgs {
+    /// Path to the profile file, as saved by `samply record` or `samply import`.
+    file: PathBuf,
+
+    /// The file to write the flame graph to, ending in .svg or .html.
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Only include the thread with this index. By default, all threads are merged.
+    #[arg(long, conflicts_with = "pid")]
+    thread: Option<usize>,
+
+    /// Only include the threads of the process with this pid.
+    #[arg(long)]
+    pid: Option<String>,
+
+    /// The title at the top of the flame graph. Defaults to the profile's file name.
+    #[arg(long)]
+    title: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct ExportPprofArgs {
+    /// Path to the profile file, as saved by `samply record` or `samply import`.
+    file: PathBuf,
+
+    /// The file to write the pprof profile to, e.g. profile.pb.gz.
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Only include the thread with this index. By default, all threads are merged.
+    #[arg(long, conflicts_with = "pid")]
+    thread: Option<usize>,
+
+    /// Only include the threads of the process with this pid.
+    #[arg(long)]
+    pid: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct MergeArgs {
+    /// The profiles to merge. The first one is the time reference of the merged profile.
+    /// Trace Event Format files, e.g. from chrome://tracing, are imported first.
+    #[arg(required = true, num_args = 2..)]
+    files: Vec<PathBuf>,
+
+    /// Output filename.
+    #[arg(short, long, default_value = "merged.json.gz")]
+    output: PathBuf,
+
+    /// How far the clock of the machine which recorded a profile was ahead of the clock of
+    /// the machine which recorded the first profile, in milliseconds. Needed if the clocks
+    /// weren't synchronized, e.g. with NTP. Can be specified multiple times.
+    #[arg(long, value_name = "FILE=MS", value_parser = parse_clock_offset)]
+    clock_offset: Vec<(PathBuf, f64)>,
+}
+
+#[derive(Debug, Args)]
+struct SplitArgs {
+    /// The profile to split.
+    file: PathBuf,
+
+    /// Write one profile per process. This is the only way of splitting a profile at the
+    /// moment.
+    #[arg(long, required = true)]
+    by_process: bool,
+
+    /// Only write the profiles of the processes with these pids. Can be specified
+    /// multiple times.
+    #[arg(long)]
+    pid: Vec<String>,
+
+    /// The directory for the profiles, which are named like the split profile, with the
+    /// pid and the name of the process, e.g. "profile.1234-firefox.json.gz". Defaults to
+    /// the directory of the split profile.
+    #[arg(short, long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct AnnotateProfileArgs {
+    /// The profile to add the markers to.
+    file: PathBuf,
+
+    /// A marker to add, as NAME@TIME for an instant marker or NAME@START..END for an
+    /// interval marker. Times are either wall-clock times in UTC, e.g. "12:03:05Z" or
+    /// "2024-05-01T12:03:05Z", or durations since the start of the profile, e.g. "+90s"
+    /// or "1m30s". A plain number is in seconds. Can be specified multiple times.
+    #[arg(
+        long,
+        required_unless_present = "syscall_log",
+        value_name = "NAME@TIME",
+        value_parser = annotate::parse_annotation
+    )]
+    marker: Vec<annotate::Annotation>,
+
+    /// A log from "strace -ttt" or "dtruss", whose syscalls are added as interval markers
+    /// on the threads which made them. Use "strace -f -ttt -T" to get the thread ids and
+    /// the durations, and "dtruss -a" for the same with dtruss.
+    #[arg(long, value_name = "FILE")]
+    syscall_log: Option<PathBuf>,
+
+    /// When the dtruss log started, in the same format as the times of --marker. strace
+    /// logs have wall-clock times, so this is only used for dtruss logs. Defaults to the
+    /// start of the profile.
+    #[arg(long, value_name = "TIME", value_parser = annotate::parse_time)]
+    syscall_log_start: Option<annotate::AnnotationTime>,
+
+    /// Output filename. Defaults to overwriting the input file.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct AttachDataArgs {
+    /// The profile to add the data to.
+    file: PathBuf,
+
+    /// The data: a CSV file with a header row, or a JSON array of objects or of
+    /// [time, value] pairs. The times are in the "time", "timestamp" or "ts" column, or
+    /// in the first column, either as RFC 3339 timestamps or as numbers. Markers can have
+    /// an "end" column.
+    data: PathBuf,
+
+    /// Whether each row becomes a sample of a counter or a marker.
+    #[arg(long = "type", default_value_t = AttachDataTypeArg::Counter)]
+    data_type: AttachDataTypeArg,
+
+    /// The name of the counter or of the markers.
+    #[arg(long)]
+    name: String,
+
+    /// The column with the values. Defaults to the first column which isn't the time or
+    /// end column, or to "value" for JSON objects.
+    #[arg(long)]
+    column: Option<String>,
+
+    /// The unit of numeric timestamps.
+    #[arg(long, default_value_t = TimeUnitArg::S)]
+    time_unit: TimeUnitArg,
+
+    /// Align numeric timestamps by saying when one of them was, as VALUE=TIME, e.g.
+    /// "0=12:03:05Z" or "1500=+2s". TIME is in the same format as the times of
+    /// `annotate-profile --marker`. Without an anchor, numeric timestamps are Unix times.
+    #[arg(long, value_name = "VALUE=TIME", value_parser = attach_data::parse_anchor)]
+    anchor: Option<attach_data::TimeAnchor>,
+
+    /// How far the clock of the data was behind the clock of the profile, in
+    /// milliseconds. Added to all times.
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 0.0,
+        allow_hyphen_values = true
+    )]
+    clock_offset: f64,
+
+    /// Add the data to the process with this pid. Defaults to the first process.
+    #[arg(long)]
+    pid: Option<String>,
+
+    /// Output filename. Defaults to overwriting the input file.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+fn parse_clock_offset(s: &str) -> Result<(PathBuf, f64), String> {
+    let (file, offset) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("Expected FILE=MS, got {s:?}"))?;
+    let offset = offset
+        .parse()
+        .map_err(|_| format!("Invalid clock offset {offset:?}"))?;
+    Ok((PathBuf::from(file), offset))
+}
+
+#[derive(Debug, Args)]
+struct SymbolicateArgs {
+    /// The file with the addresses, one per line. Reads from stdin if omitted or "-".
+    addresses: Option<PathBuf>,
+
+    /// The binary or debug file to look up the addresses in.
+    #[arg(
+        long,
+        conflicts_with = "debug_name",
+        required_unless_present = "debug_name"
+    )]
+    lib: Option<PathBuf>,
+
+    /// The debug name of the library, e.g. "xul.pdb" or "libxul.so", to look up its
+    /// symbols in the symbol directories and on the symbol servers.
+    #[arg(long, requires = "debug_id")]
+    debug_name: Option<String>,
+
+    /// The debug ID of the library, in Breakpad form or as a UUID.
+    #[arg(long, value_parser = parse_debug_id)]
+    debug_id: Option<debugid::DebugId>,
+
+    /// How to interpret the addresses: relative to the image base (e.g. RVAs on Windows),
+    /// as addresses in the binary's address space (like addr2line), or as file offsets.
+    #[arg(long, default_value_t = AddressKindArg::Relative)]
+    address_kind: AddressKindArg,
+
+    /// Output format.
+    #[arg(long, default_value_t = SymbolicateFormatArg::Text)]
+    format: SymbolicateFormatArg,
+
+    #[command(flatten)]
+    symbol_args: SymbolArgs,
+}
+
+fn parse_debug_id(s: &str) -> Result<debugid::DebugId, String> {
+    debugid::DebugId::from_breakpad(s)
+        .or_else(|_| debugid::DebugId::from_str(s))
+        .map_err(|_| format!("Invalid debug ID {s:?}"))
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum AddressKindArg {
+    Relative,
+    Svma,
+    FileOffset,
+}
+
+impl std::fmt::Display for AddressKindArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum SymbolicateFormatArg {
+    Text,
+    Json,
+}
+
+impl std::fmt::Display for SymbolicateFormatArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum ReportFormatArg {
+    Csv,
+    Md,
+}
+
+impl std::fmt::Display for ReportFormatArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum WakeGraphFormatArg {
+    Dot,
+    Json,
+}
+
+impl std::fmt::Display for WakeGraphFormatArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum AttachDataTypeArg {
+    Counter,
+    Marker,
+}
+
+impl std::fmt::Display for AttachDataTypeArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum TimeUnitArg {
+    S,
+    Ms,
+    Us,
+    Ns,
+}
+
+impl TimeUnitArg {
+    fn as_ms(self) -> f64 {
+        match self {
+            TimeUnitArg::S => 1000.0,
+            TimeUnitArg::Ms => 1.0,
+            TimeUnitArg::Us => 0.001,
+            TimeUnitArg::Ns => 0.000_001,
+        }
+    }
+}
+
+impl std::fmt::Display for TimeUnitArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum ThreadOrderArg {
+    Default,
+    Cpu,
+    Name,
+    FirstSample,
+}
+
+impl std::fmt::Display for ThreadOrderArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+impl From<ThreadOrderArg> for ThreadOrder {
+    fn from(arg: ThreadOrderArg) -> Self {
+        match arg {
+            ThreadOrderArg::Default => ThreadOrder::Default,
+            ThreadOrderArg::Cpu => ThreadOrder::CpuUsage,
+            ThreadOrderArg::Name => ThreadOrder::Name,
+            ThreadOrderArg::FirstSample => ThreadOrder::FirstSampleTime,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct ImportArgs {
+    /// Path to the profile file that should be imported.
+    file: PathBuf,
+
+    /// Optional extra paths to ETL files for user sessions.
+    user_etl: Vec<PathBuf>,
+
+    #[command(flatten)]
+    profile_creation_args: ProfileCreationArgs,
+
+    /// Do not run a local server after recording.
+    #[arg(short, long)]
+    save_only: bool,
+
+    /// Output filename.
+    #[arg(short, long, default_value = "profile.json.gz")]
+    output: PathBuf,
+
+    #[command(flatten)]
+    server_args: ServerArgs,
+
+    #[command(flatten)]
+    symbol_args: SymbolArgs,
+
+    /// Additional directories to use for looking up jitdump and marker files.
+    #[arg(long)]
+    aux_file_dir: Vec<PathBuf>,
+
+    /// Only include processes with this name substring (can be specified multiple times).
+    #[arg(long)]
+    name: Option<Vec<String>>,
+
+    /// Only include process with this PID (can be specified multiple times).
+    #[arg(long)]
+    pid: Option<Vec<u32>>,
+
+    /// Explicitly specify architecture of profile to import, e.g. "aarch64" or "x86_64".
+    #[arg(long)]
+    override_arch: Option<String>,
+
+    /// Enable CoreCLR event conversion.
+    #[clap(long, require_equals = true, value_name = "FLAG", value_enum, value_delimiter = ',', num_args = 0.., default_values_t = vec![CoreClrArgs::Enabled])]
+    coreclr: Vec<CoreClrArgs>,
+
+    /// Time range of recording to include in profile. Format is "start-stop" or "start+duration" with each part optional, e.g. "5s", "5s-", "-10s", "1s-10s" or "1s+9s".
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_parser=parse_time_range)]
+    time_range: Option<(std::time::Duration, std::time::Duration)>,
+
+    /// Read ETL files twice: first for processes, images and JIT symbols, then for samples,
+    /// which are added to the profile as they are read. This bounds memory use for very
+    /// large traces, at the cost of reading the file twice.
+    #[cfg(target_os = "windows")]
+    #[arg(long)]
+    two_pass: bool,
+
+    /// Decode the events of the provider with the first GUID as the events of the
+    /// provider with the second GUID, for ETL files from collectors which log the
+    /// kernel's events under their own provider GUIDs. The second GUID can also be
+    /// one of the kernel providers PerfInfo, StackWalk, Thread, Process or Image.
+    /// Can be specified multiple times.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "GUID=GUID", value_parser = EtwProviderAlias::parse)]
+    etw_provider_alias: Vec<EtwProviderAlias>,
+
+    /// Read region definitions from a WPA regions of interest XML file, and add an
+    /// interval marker for each region in the ETL file, from its start event to its
+    /// stop event. The marker goes on the thread of the start event.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "FILE")]
+    regions_of_interest: Option<PathBuf>,
+}
+
+#[allow(unused)]
+fn parse_time_range(
+    arg: &str,
+) -> Result<(std::time::Duration, std::time::Duration), humantime::DurationError> {
+    let (is_duration, splitchar) = if arg.contains('+') {
+        (true, '+')
+    } else {
+        (false, '-')
+    };
+
+    let parts: Vec<&str> = arg.splitn(2, splitchar).collect();
+
+    let start = if parts[0].is_empty() {
+        std::time::Duration::ZERO
+    } else {
+        humantime::parse_duration(parts[0])?
+    };
+
+    let end = if parts.len() == 1 || parts[1].is_empty() {
+        std::time::Duration::MAX
+    } else {
+        humantime::parse_duration(parts[1])?
+    };
+
+    Ok((start, if is_duration { start + end } else { end }))
+}
+
+#[allow(unused)]
+#[derive(Debug, Args)]
+struct RecordArgs {
+    /// Sampling rate, in Hz
+    #[arg(short, long, default_value = "1000")]
+    rate: f64,
+
+    /// Stop recording after this much time, e.g. "30s" or "2m". A plain number is in seconds.
+    /// With --delay, the time counts from the end of the delay.
+    #[arg(short, long, value_parser = parse_duration_arg)]
+    duration: Option<Duration>,
+
+    /// Only start recording samples after this much time, e.g. "5s", counted from when the
+    /// command is launched or when samply attaches. A plain number is in seconds.
+    #[arg(long, value_parser = parse_duration_arg)]
+    delay: Option<Duration>,
+
+    /// Stop recording once the process with this pid has exited, e.g. a test driver which
+    /// runs the profiled processes.
+    #[arg(long, value_name = "PID")]
+    until_exit_of: Option<u32>,
+
+    /// Stop recording after this much time at the latest, including the --delay, e.g. "1h".
+    /// This is a safety limit for recordings which were left running by accident; the
+    /// profile is still saved.
+    #[arg(long, value_parser = parse_duration_arg)]
+    max_duration: Option<Duration>,
+
+    /// Stop recording once this many gigabytes of raw data have been recorded: the ETL files
+    /// on Windows, or the perf event data on Linux (Linux and Windows only). This keeps a
+    /// forgotten recording from filling the disk; the profile is still saved.
+    #[cfg(any(target_os = "android", target_os = "linux", target_os = "windows"))]
+    #[arg(long, value_name = "GB")]
+    max_recording_size: Option<f64>,
+
+    /// How many times to run the profiled command.
+    #[arg(long, default_value = "1")]
+    iteration_count: u32,
+
+    #[command(flatten)]
+    profile_creation_args: ProfileCreationArgs,
+
+    /// Do not run a local server after recording.
+    #[arg(short, long)]
+    save_only: bool,
+
+    /// Output filename.
+    #[arg(short, long, default_value = "profile.json.gz")]
+    output: PathBuf,
+
+    /// Save the recording as a session directory instead of a single file: the profile,
+    /// a manifest of how it was recorded, the log of the recording, and a list of the
+    /// libraries which it references, with their identifiers. See also --copy-binaries.
+    #[arg(long, value_name = "DIR", conflicts_with = "output")]
+    output_dir: Option<PathBuf>,
+
+    /// With --output-dir, also copy the referenced libraries and their debug files into the
+    /// session directory, so that the profile can still be symbolicated once they're gone.
+    #[arg(long, requires = "output_dir")]
+    copy_binaries: bool,
+
+    #[command(flatten)]
+    server_args: ServerArgs,
+
+    #[command(flatten)]
+    symbol_args: SymbolArgs,
+
+    /// Profile the execution of this command. With --appid, these are the
+    /// arguments for the app.
+    #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+    #[cfg_attr(
+        not(target_os = "windows"),
+        arg(
+            required_unless_present_any = ["pid", "all"],
+            conflicts_with_all = ["pid", "all"]
+        )
+    )]
+    #[cfg_attr(
+        target_os = "windows",
+        arg(
+            required_unless_present_any = ["pid", "all", "service", "appid"],
+            conflicts_with_all = ["pid", "all", "service"]
+        )
+    )]
+    command: Vec<std::ffi::OsString>,
+
+    /// Process ID of existing process to attach to.
+    #[arg(short, long, conflicts_with = "all")]
+    pid: Option<u32>,
+
+    /// Restart this Windows service and profile it, including its startup (Windows
+    /// only). Requires administrator privileges. The service keeps running after
+    /// the recording is stopped with Ctrl+C.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["pid", "all", "appid"])]
+    service: Option<String>,
+
+    /// Launch the packaged (UWP / MSIX) app with this Application User Model ID and
+    /// profile it, e.g. `Microsoft.WindowsCalculator_8wekyb3d8bbwe!App` (Windows
+    /// only). The recording stops when the app exits.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "APPID", conflicts_with_all = ["pid", "all"])]
+    appid: Option<String>,
+
+    /// Also record on another machine, given as `ssh://[user@]host[:port]`, and merge its
+    /// profile into this one, with the clocks of both machines synchronized. samply needs
+    /// to be installed there; add its path to the URL if it isn't in the PATH of ssh
+    /// sessions, e.g. `ssh://host2/home/me/.cargo/bin/samply`. Can be specified multiple
+    /// times.
+    #[arg(long, value_name = "URL", value_parser = follow::FollowTarget::parse)]
+    follow: Vec<follow::FollowTarget>,
+
+    /// The `samply record` arguments which select what to record on the --follow machines,
+    /// e.g. `--follow-args="-p 4321"`. The recording there is stopped when the recording
+    /// here ends.
+    #[arg(
+        long,
+        value_name = "ARGS",
+        default_value = "--all",
+        allow_hyphen_values = true
+    )]
+    follow_args: String,
+
+    /// Profile entire system (all processes). On macOS, this only includes the
+    /// processes which samply is allowed to attach to; see `samply setup`.
+    #[arg(short, long, visible_alias = "all-processes", conflicts_with = "pid")]
+    all: bool,
+
+    /// Enable CoreCLR event capture.
+    #[clap(long, require_equals = true, value_name = "FLAG", value_enum, value_delimiter = ',', num_args = 0.., default_missing_value = "enabled")]
+    coreclr: Vec<CoreClrArgs>,
+
+    /// VM hack for arm64 Windows VMs to not try to record PROFILE events (Windows only).
+    #[cfg(target_os = "windows")]
+    #[arg(long)]
+    vm_hack: bool,
+
+    /// Enable Graphics-related event capture.
+    #[arg(long)]
+    gfx: bool,
+
+    /// Enable browser-related event capture (JavaScript stacks and trace events)
+    #[arg(long)]
+    browsers: bool,
+
+    /// Keep the ETL file after recording (Windows only).
+    #[cfg(target_os = "windows")]
+    #[arg(long)]
+    keep_etl: bool,
+
+    /// Read these CPU performance counters (PMCs) with every sample, and show them
+    /// as per-process counter tracks (Windows only). Use the names listed by
+    /// `xperf -pmcsources`, e.g. `--pmc BranchMispredictions,CacheMisses`.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "COUNTERS", value_delimiter = ',')]
+    pmc: Vec<String>,
+
+    /// Take a sample every --pmc-sampling-interval occurrences of this CPU
+    /// performance counter instead of on a timer (Windows only), e.g.
+    /// `--pmc-sampling BranchMispredictions` or `--pmc-sampling LLCMisses` to see
+    /// where branches are mispredicted or where the last-level cache misses. Use the
+    /// names listed by `xperf -pmcsources`.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "SOURCE", conflicts_with_all = ["vm_hack", "pmc"])]
+    pmc_sampling: Option<String>,
+
+    /// The number of counter events between two samples, with --pmc-sampling.
+    #[cfg(target_os = "windows")]
+    #[arg(
+        long,
+        value_name = "EVENTS",
+        default_value_t = 10_000,
+        requires = "pmc_sampling"
+    )]
+    pmc_sampling_interval: u32,
+
+    /// Enable antivirus event capture (Windows only): Windows Defender events, and
+    /// minifilter callbacks which delay file I/O by at least a millisecond.
+    #[cfg(target_os = "windows")]
+    #[arg(long)]
+    antivirus: bool,
+
+    /// Record without administrator rights, using only a user-mode ETW session (Windows
+    /// only). This captures the events of the --coreclr, --browsers, --gfx,
+    /// --antivirus and --provider providers, with their stacks, but no CPU samples
+    /// or minifilter events. Membership in the "Performance Log Users" group is
+    /// still required.
+    #[cfg(target_os = "windows")]
+    #[arg(long, conflicts_with_all = ["vm_hack", "pmc", "pmc_sampling"])]
+    user_mode_only: bool,
+
+    /// Enable a user-mode ETW provider, given by name or GUID, with optional keywords
+    /// and level, e.g. `--provider My-Product-Provider:0x10:5` (Windows only). Its
+    /// events are shown as markers. Can be specified multiple times.
+    #[cfg(target_os = "windows")]
+    #[arg(long = "provider", value_name = "PROVIDER[:KEYWORDS[:LEVEL]]", value_parser = EtwProviderProps::parse)]
+    providers: Vec<EtwProviderProps>,
+
+    /// The clock to take sample timestamps from (Linux only). Use "boottime" to
+    /// correlate with logs from a system which gets suspended during the recording.
+    /// Timestamps in jitdump and marker files are always expected to be "monotonic".
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, value_enum, default_value_t = ClockArg::Monotonic)]
+    clock: ClockArg,
+
+    /// Capture the call stack from the Last Branch Record (LBR) with each sample,
+    /// and use it to fix up the innermost frames of the unwound stack (Linux only).
+    /// This helps with leaf functions which don't set up a frame pointer.
+    /// Requires a CPU with LBR call stack support, such as Intel Haswell or newer.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long)]
+    lbr: bool,
+
+    /// Sample with a BPF program which counts the stacks in the kernel, and collect
+    /// the counts every 100ms, instead of copying the stack memory of every sample
+    /// (Linux only). This has much less overhead at high sampling rates and when
+    /// profiling all processes, but user stacks are walked with frame pointers, and
+    /// samples only have the time of the collection they were counted in. Requires
+    /// root, or CAP_BPF and CAP_PERFMON.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "lbr",
+            "checkpoint_interval",
+            "tracepoint",
+            "contention",
+            "thread_affinity",
+        ]
+    )]
+    bpf: bool,
+
+    /// Enable the tracepoint SUBSYSTEM:EVENT, e.g. `syscalls:sys_enter_openat` or
+    /// `block:block_rq_issue`, and add a marker with the decoded arguments for each
+    /// hit, with the stack at that point (Linux only). Can be specified multiple
+    /// times. The tracepoint formats are read from tracefs, which usually requires
+    /// root.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, value_name = "SUBSYSTEM:EVENT")]
+    tracepoint: Vec<String>,
+
+    /// Record futex waits and wakes, and add a "Lock contention" marker for each
+    /// wait with the stack of the waiting thread, and a "Lock release" marker with
+    /// the stack of the thread which woke it, usually the lock holder (Linux only).
+    /// The markers have the futex address, so searching for it shows all waits on
+    /// the same lock. Uses the futex syscall tracepoints, which usually requires
+    /// root.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long)]
+    contention: bool,
+
+    /// Record sched_setaffinity calls, and add a "Thread affinity" marker for each
+    /// one with the stack of the call and the CPUs which the thread may run on
+    /// afterwards, e.g. to find threads which were pinned to a few cores (Linux
+    /// only). Uses the syscall tracepoints, which usually requires root.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long)]
+    thread_affinity: bool,
+
+    /// Write the raw events to <OUTPUT>.checkpoint while recording, and make sure that
+    /// everything up to the last checkpoint is on disk, every SECONDS seconds (Linux only).
+    /// If samply or the machine crashes during the recording, `samply recover` converts the
+    /// checkpoint file into a profile. The file is deleted once the profile has been saved.
+    /// It contains the raw stack memory of every sample, so it grows quickly.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, value_name = "SECONDS")]
+    checkpoint_interval: Option<f64>,
+
+    /// Sample processes whose name contains PROCESS at a lower rate, in Hz. For
+    /// example, `--interval-for mds=10` samples a noisy background process less
+    /// often. RATE can't be higher than --rate. Only every n-th sample of such
+    /// processes is kept, with a weight of n, so that their sample counts stay
+    /// comparable. Can be specified multiple times. Not supported with --bpf.
+    #[arg(long, value_name = "PROCESS=RATE", value_parser = parse_process_rate)]
+    interval_for: Vec<(String, f64)>,
+
+    /// Show the power usage as counter tracks (Windows and macOS only). On Windows,
+    /// this reads the Energy Meter Interface (EMI) channels of the system, e.g.
+    /// "CPU Cores" or "GPU". On Apple Silicon Macs, this reads the energy use of
+    /// each profiled process.
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    #[arg(long)]
+    power_counters: bool,
+}
+
+/// Parses a duration like "30s", "1m30s" or "1.5", which is in seconds.
+fn parse_duration_arg(s: &str) -> Result<Duration, String> {
+    if let Ok(seconds) = s.parse::<f64>() {
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(format!("invalid duration {s:?}"));
+        }
+        return Ok(Duration::from_secs_f64(seconds));
+    }
+    humantime::parse_duration(s).map_err(|err| format!("invalid duration {s:?}: {err}"))
+}
+
+fn parse_process_rate(s: &str) -> Result<(String, f64), String> {
+    let (process, rate) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected PROCESS=RATE, got {s:?}"))?;
+    let rate: f64 = rate
+        .parse()
+        .map_err(|_| format!("invalid sampling rate {rate:?}"))?;
+    if rate <= 0.0 {
+        return Err(format!(
+            "sampling rate must be greater than zero, got {rate}"
+        ));
+    }
+    Ok((process.to_string(), rate))
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum ClockArg {
+    Monotonic,
+    Boottime,
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl std::fmt::Display for ClockArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+#[derive(Debug, Args)]
+struct CargoArgs {
+    /// Sampling rate, in Hz
+    #[arg(short, long, default_value = "1000")]
+    rate: f64,
+
+    /// Do not run a local server after recording.
+    #[arg(short, long)]
+    save_only: bool,
+
+    /// Output filename.
+    #[arg(short, long, default_value = "profile.json.gz")]
+    output: PathBuf,
+
+    #[command(flatten)]
+    server_args: ServerArgs,
+
+    #[command(flatten)]
+    symbol_args: SymbolArgs,
+
+    /// The cargo command to run.
+    #[arg(value_enum)]
+    cargo_command: CargoCommandArg,
+
+    /// Arguments for cargo, such as a test name filter or `--test <NAME>`.
+    /// Arguments after `--` are passed to the test binary.
+    #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+    cargo_args: Vec<std::ffi::OsString>,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum CargoCommandArg {
+    /// Profile `cargo test`.
+    Test,
+    /// Profile `cargo bench`.
+    Bench,
+}
+
+#[cfg(target_os = "windows")]
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum StackWalkArg {
+    Profile,
+    Cswitch,
+    ReadyThread,
+    VirtualAlloc,
+    VirtualFree,
+}
+
+#[cfg(target_os = "windows")]
+impl std::fmt::Display for StackWalkArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl ProfileCreationArgs {
+    fn stack_walk_events(&self) -> Vec<StackWalkEvent> {
+        // The CPU samples are useless without their stacks.
+        let mut events = vec![StackWalkEvent::Profile];
+        for arg in &self.stack_walk {
+            let event = match arg {
+                StackWalkArg::Profile => StackWalkEvent::Profile,
+                StackWalkArg::Cswitch => StackWalkEvent::CSwitch,
+                StackWalkArg::ReadyThread => StackWalkEvent::ReadyThread,
+                StackWalkArg::VirtualAlloc => StackWalkEvent::VirtualAlloc,
+                StackWalkArg::VirtualFree => StackWalkEvent::VirtualFree,
+            };
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn scheduler_latency_threshold(&self) -> Option<Duration> {
+        self.scheduler_latency
+            .map(|ms| Duration::try_from_secs_f64(ms / 1000.0).unwrap_or_default())
+    }
+
+    fn hang_threshold(&self) -> Option<Duration> {
+        self.hangs
+            .map(|ms| Duration::try_from_secs_f64(ms / 1000.0).unwrap_or_default())
+    }
+
+    fn max_marker_value_size(&self) -> Option<usize> {
+        Some(self.max_marker_value_size).filter(|&size| size != 0)
+    }
+
+    fn max_marker_payload_size(&self) -> Option<usize> {
+        Some(self.max_marker_payload_size).filter(|&size| size != 0)
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum CoreClrArgs {
+    Enabled,
+    #[cfg(target_os = "windows")]
+    GcMarkers,
+    #[cfg(target_os = "windows")]
+    GcSuspendedThreads,
+    #[cfg(target_os = "windows")]
+    GcDetailedAllocs,
+    #[cfg(target_os = "windows")]
+    EventStacks,
+}
+
+impl std::fmt::Display for CoreClrArgs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+#[derive(Debug, Args)]
+struct ServerArgs {
+    /// Do not open the profiler UI.
+    #[arg(short, long)]
+    no_open: bool,
+
+    /// The address to use for the local web server
+    #[arg(long, default_value = "127.0.0.1")]
+    address: String,
+
+    /// The port to use for the local web server
+    #[arg(short = 'P', long, default_value = "3000+")]
+    port: String,
+
+    /// Print debugging output.
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+/// Arguments describing where to obtain symbol files.
+#[derive(Debug, Args)]
+struct SymbolArgs {
+    /// Extra directories containing symbol files
+    #[arg(long)]
+    symbol_dir: Vec<PathBuf>,
+
+    /// Additional URLs of symbol servers serving PDB / DLL / EXE files
+    #[arg(long)]
+    windows_symbol_server: Vec<String>,
+
+    /// Overrides the default cache directory for Windows symbol files which were downloaded from a symbol server
+    #[arg(long)]
+    windows_symbol_cache: Option<PathBuf>,
+
+    /// Additional URLs of symbol servers serving Breakpad .sym files
+    #[arg(long)]
+    breakpad_symbol_server: Vec<String>,
+
+    /// Additional local directories containing Breakpad .sym files
+    #[arg(long)]
+    breakpad_symbol_dir: Vec<String>,
+
+    /// Overrides the default cache directory for Breakpad symbol files
+    #[arg(long)]
+    breakpad_symbol_cache: Option<PathBuf>,
+
+    /// Extra directory containing symbol files, with the directory structure used by simpleperf's scripts
+    #[arg(long)]
+    simpleperf_binary_cache: Option<PathBuf>,
+
+    /// Parse symbol files in a separate, sandboxed process. Use this when loading
+    /// profiles from untrusted sources, whose referenced binaries could be malicious.
+    /// With `samply import`, the imported file is also converted in such a process.
+    /// On Linux, a seccomp filter limits the process to the syscalls it needs, and
+    /// Landlock limits writes to the symbol cache. On macOS, a sandbox profile does
+    /// the same. On Windows, the process runs at low integrity, so it can only write
+    /// to the symbol cache, in a job object which doesn't allow child processes; the
+    /// network isn't blocked there. Symbol files are only downloaded if the process
+    /// can write them to the symbol cache.
+    #[arg(long)]
+    sandbox_symbolication: bool,
+
+    /// Replace a path prefix in source file paths, for binaries which were built
+    /// on a different machine, so that the source view can find local files.
+    /// Takes FROM=TO, e.g. /builds/worker/checkouts/gecko=~/src/gecko. Can be
+    /// specified multiple times; the first matching rule is used.
+    #[arg(long, value_name = "FROM=TO", value_parser = SymbolProps::parse_source_path_map)]
+    source_path_map: Vec<(String, String)>,
+
+    /// Send an HTTP header when downloading source files whose URL starts with
+    /// URL_PREFIX. Source files of PDBs with srcsrv or SourceLink information are
+    /// downloaded when they're not found locally; this authenticates to private
+    /// repositories. Takes URL_PREFIX=NAME: VALUE, e.g.
+    /// "https://raw.githubusercontent.com/myorg/=Authorization: Bearer $GITHUB_TOKEN".
+    /// Words of the form $VAR in VALUE are read from the environment variable VAR.
+    #[arg(long, value_name = "URL_PREFIX=NAME: VALUE", value_parser = SymbolProps::parse_source_url_header)]
+    source_url_header: Vec<(String, String, String)>,
+
+    /// Add the parameter types from DWARF debug info to function names which
+    /// don't have them, e.g. `add(int, long)` instead of `add` for a C function.
+    /// Names from mangled C++ symbols and from PDBs always include them.
+    #[arg(long)]
+    full_signatures: bool,
+
+    /// Don't load the debug files of libraries whose file name contains PATTERN,
+    /// ignoring case, e.g. `--skip-symbols-for xul` for a multi-gigabyte xul.pdb.
+    /// These libraries are symbolicated with the symbols in the binary itself,
+    /// e.g. the exported functions of a DLL, which is much faster but less
+    /// detailed. Can be specified multiple times.
+    #[arg(long, value_name = "PATTERN")]
+    skip_symbols_for: Vec<String>,
+
+    /// Don't make any network requests for symbols or source files: symbol
+    /// servers, debuginfod and source file URLs are skipped, and only local
+    /// files and the files in the symbol caches are used.
+    #[arg(long)]
+    offline_symbols: bool,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct ProfileCreationArgs {
+    /// Set a custom name for the recorded profile.
+    /// By default it is either the command that was run or the process pid.
+    #[arg(long)]
+    profile_name: Option<String>,
+
+    /// Only include the main thread of each process in order to reduce profile size,
+    /// only respected on Windows and macOS
+    #[arg(long)]
+    main_thread_only: bool,
+
+    /// Merge non-overlapping threads of the same name.
+    #[arg(long)]
+    reuse_threads: bool,
+
+    /// Merge non-overlapping processes of the same name into one process
+    /// track, without merging their threads. Useful for services that keep
+    /// crashing and restarting. Implied by --reuse-threads.
+    #[arg(long)]
+    reuse_processes_by_name: bool,
+
+    /// Fold repeated frames at the base of the stack.
+    #[arg(long)]
+    fold_recursive_prefix: bool,
+
+    /// If a process produces jitdump or marker files, unlink them after
+    /// opening. This ensures that the files will not be left in /tmp,
+    /// but it will also be impossible to look at JIT disassembly, and line
+    /// numbers will be missing for JIT frames.
+    #[arg(long)]
+    unlink_aux_files: bool,
+
+    /// Create a separate thread for each CPU. Not supported on macOS
+    #[arg(long)]
+    per_cpu_threads: bool,
+
+    /// Give every thread a sample in each sampling interval, also while it's blocked, with
+    /// the stack at which it blocked. Without this, the blocked time between two samples
+    /// becomes a single weighted sample. Only needed on Linux and Windows, where it uses
+    /// the context switch events; macOS samples every thread in each interval anyway.
+    #[arg(long)]
+    wall_clock: bool,
+
+    /// Include up to <INCLUDE_ARGS> command line arguments in the process name.
+    /// This can help differentiate processes if the same executable is used
+    /// for different types of programs. And in --reuse-threads mode it
+    /// allows more control over which processes are matched up.
+    #[arg(long, default_value = "0", num_args=0..=1, require_equals = true, default_missing_value = "100")]
+    include_args: usize,
+
+    /// Emit .syms.json sidecar file containing gathered symbol info for all frames referenced by
+    /// this profile. With this file along with the profile, samply can load the profile
+    /// and provide symbols to the front end without needing debug files to be
+    /// available. (Unstable: will probably change to include the full information
+    /// in the profile.json, instead of a sidecar file.)
+    #[arg(long)]
+    unstable_presymbolicate: bool,
+
+    /// Don't embed the symbols which are found while creating the profile (e.g. from
+    /// /proc/kallsyms or from simpleperf's symbol records) in the profile. Symbolication
+    /// is left to samply's symbol server when the profile is viewed, which makes
+    /// creating profiles for very large traces faster. Symbols will only be available
+    /// if the binaries can be found when the profile is loaded.
+    #[arg(long, conflicts_with = "unstable_presymbolicate")]
+    no_presymbolicate: bool,
+
+    /// Write a search index next to the profile, e.g. profile.json.gz.idx for
+    /// profile.json.gz, with the sample weights of each function and the number of
+    /// markers of each name. `samply grep` and the server's /api/search endpoint use
+    /// it to find functions without parsing the whole profile.
+    #[arg(long)]
+    search_index: bool,
+
+    /// Don't print the summary of the profile (CPU usage per process, hottest functions
+    /// and profile size) after recording.
+    #[arg(long)]
+    no_summary: bool,
+
+    /// Emit markers for any unknown ETW events that are encountered.
+    #[cfg(target_os = "windows")]
+    #[arg(long)]
+    unknown_event_markers: bool,
+
+    /// Cut off property values in the text of markers from ETW events after this
+    /// many bytes. 0 disables the limit.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "BYTES", default_value_t = 1024)]
+    max_marker_value_size: usize,
+
+    /// Leave out the remaining properties of an ETW event once the text of its
+    /// marker is this many bytes long. 0 disables the limit.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "BYTES", default_value_t = 4096)]
+    max_marker_payload_size: usize,
+
+    /// Include the System process (pid 4) with a track for each kernel thread,
+    /// even when only profiling specific processes.
+    #[cfg(target_os = "windows")]
+    #[arg(long)]
+    include_system_process: bool,
+
+    /// Add markers which show when each thread was running, ready to run but
+    /// waiting for a CPU, or blocked, based on context switch events.
+    #[cfg(target_os = "windows")]
+    #[arg(long)]
+    thread_states: bool,
+
+    /// Add a "Scheduler latency" counter with the time which threads waited for a
+    /// CPU after they became ready to run, and markers for the waits which took
+    /// longer than <MS> milliseconds (10 by default). The latency distribution of
+    /// the worst threads is added to the profile's metadata.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "MS", num_args = 0..=1, require_equals = true, default_missing_value = "10")]
+    scheduler_latency: Option<f64>,
+
+    /// Add a "Wakeup" marker each time a thread is made ready to run, with the thread
+    /// which woke it, e.g. by releasing a lock. `samply export wakegraph` turns these
+    /// markers into a graph of which threads wake which.
+    #[cfg(target_os = "windows")]
+    #[arg(long)]
+    wakeups: bool,
+
+    /// Add "Hang" markers for the times in which a thread with a message loop, e.g. a
+    /// UI thread, didn't check for window messages for longer than <MS> milliseconds
+    /// (200 by default). Each marker has the most common stack of its hang. The hangs
+    /// are found from Win32k's MessageCheckDelay events and from the gaps between the
+    /// thread's waits for messages.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "MS", num_args = 0..=1, require_equals = true, default_missing_value = "200")]
+    hangs: Option<f64>,
+
+    /// The kernel events which get stack walks. The CPU samples always get them;
+    /// `cswitch` stacks give the samples for the time which threads spend blocked.
+    /// Stacks for fewer events make long recordings smaller and faster to convert.
+    /// When importing, this tells samply which events the trace has stack walks for.
+    #[cfg(target_os = "windows")]
+    #[arg(
+        long,
+        value_name = "EVENTS",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "profile,cswitch"
+    )]
+    stack_walk: Vec<StackWalkArg>,
+
+    /// Limit the size of the profile JSON (before compression) to this many megabytes.
+    /// If the profile is larger, marker stacks are dropped and samples are downsampled
+    /// (keeping every Nth sample, with adjusted weights) until it fits.
+    #[arg(long, value_name = "MB")]
+    max_profile_size: Option<u64>,
+
+    /// Truncate stacks which are deeper than this many frames. Only the N outermost
+    /// frames are kept, and the remaining frames are replaced with a "(truncated)"
+    /// frame. This limits the memory used for runaway recursion stacks.
+    #[arg(long, value_name = "N")]
+    max_stack_depth: Option<usize>,
+
+    /// Give frames a category for the Rust crate, C++ namespace or Java package of
+    /// their function (e.g. `tokio` or `com.example`), so that the category graph
+    /// shows which parts of the code the time is spent in. Native code is
+    /// symbolicated with local symbol files to find its function names.
+    #[arg(long)]
+    categorize_by_namespace: bool,
+
+    /// Put functions whose names start with PREFIX into the category CATEGORY, e.g.
+    /// `--namespace-category tokio=Async`. Can be specified multiple times; the
+    /// first matching rule wins. Implies --categorize-by-namespace.
+    #[arg(long, value_name = "PREFIX=CATEGORY", value_parser = NamespaceCategoryRule::parse)]
+    namespace_category: Vec<NamespaceCategoryRule>,
+
+    /// Don't give the frames of common runtimes (libc, the C++ standard library,
+    /// tokio, rayon, the CLR, the JVM and the Python interpreter) their own
+    /// categories.
+    #[arg(long)]
+    no_runtime_categories: bool,
+
+    /// Don't give the kernel code under syscall entry points a "Syscall" category with
+    /// a subcategory for each syscall, e.g. "read" or "futex" (Linux only). This needs
+    /// kernel symbols.
+    #[arg(long)]
+    no_syscall_categories: bool,
+
+    /// Keep the profiler's own frames at the leaf end of stacks, e.g. signal
+    /// trampolines or the kernel's stack walking code for ETW stacks.
+    #[arg(long)]
+    keep_collector_frames: bool,
+
+    /// Add a "CPU (process group)" track to each process which has child
+    /// processes in the profile, with the combined CPU usage of the process and
+    /// all its descendants, e.g. of a whole build under `make`. Not supported
+    /// on macOS, where samply doesn't know the parent of each process.
+    #[arg(long)]
+    process_group_cpu: bool,
+
+    /// The order of the processes and threads when the profile is opened:
+    /// `default` lets the profiler decide, `cpu` lists the busiest ones first,
+    /// `name` sorts them by name, and `first-sample` by when they were first
+    /// sampled. Within each process, the main thread always comes first.
+    #[arg(long, value_name = "ORDER", value_enum, default_value_t = ThreadOrderArg::Default)]
+    thread_order: ThreadOrderArg,
+
+    /// Initially hide the threads which used less than 1% of the CPU time of the
+    /// busiest thread. Hidden threads can be shown again in the profiler.
+    #[arg(long)]
+    hide_idle_threads: bool,
+}
+
+#[derive(Debug, Args)]
+struct RunElevatedHelperArgs {
+    #[arg(long)]
+    ipc_directory: PathBuf,
+
+    #[arg(long)]
+    output_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct RunSymbolicationHelperArgs {
+    /// The profile whose libraries should be symbolicated.
+    #[arg(long)]
+    profile: Option<PathBuf>,
+
+    /// The SymbolProps, serialized as JSON.
+    #[arg(long)]
+    symbol_props: String,
+
+    #[arg(long)]
+    verbose: bool,
+}
+
+#[derive(Debug, Args)]
+struct RunImportHelperArgs {
+    /// Import the file as a perf.data file, whatever its name, like `samply recover`.
+    #[arg(long)]
+    perf_data: bool,
+
+    #[command(flatten)]
+    import_args: ImportArgs,
+}
+
+/// The entry point of the `samply` command line tool.
+#[doc(hidden)]
+pub fn cli_main() {
+    let opt = Opt::parse();
+
+    #[cfg(any(
+        target_os = "android",
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "windows"
+    ))]
+    let session_log_file = match &opt.action {
+        Action::Record(RecordArgs {
+            output_dir: Some(output_dir),
+            ..
+        }) => match session_dir::create_session_dir(output_dir) {
+            Ok(file) => Some(file),
+            Err(err) => {
+                eprintln!("Could not create the directory {output_dir:?}: {err}");
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    #[cfg(not(any(
+        target_os = "android",
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "windows"
+    )))]
+    let session_log_file = None;
+    session_dir::init_logger(session_log_file);
+
+    match opt.action {
+        Action::Load(load_args) => {
+            let profile_filename = &load_args.file;
+            let input_file = match File::open(profile_filename) {
+                Ok(file) => file,
+                Err(err) => {
+                    eprintln!("Could not open file {:?}: {}", load_args.file, err);
+                    std::process::exit(1)
+                }
+            };
+
+            let libinfo_map =
+                match parse_libinfo_map_from_profile_file(input_file, profile_filename) {
+                    Ok(libinfo_map) => libinfo_map,
+                    Err(err) => {
+                        eprintln!("Could not parse the input file as JSON: {}", err);
+                        eprintln!(
+                            "If this is a perf.data file, please use `samply import` instead."
+                        );
+                        std::process::exit(1)
+                    }
+                };
+            start_server_main(
+                profile_filename,
+                load_args.server_props(),
+                load_args.symbol_props(),
+                libinfo_map,
+            );
+        }
+
+        Action::Import(import_args) => {
+            if import_args.symbol_args.sandbox_symbolication {
+                // The helper gets the same arguments, which follow "import".
+                import_args.convert_in_helper(std::env::args_os().skip(2));
+            } else {
+                let profile = convert_file_to_profile(&import_args.open_file(), &import_args);
+                import_args.save_profile(&profile);
+            }
+            import_args.write_search_index_if_requested();
+            import_args.start_server_for_output();
+        }
+
+        Action::Recover(mut import_args) => {
+            let repaired = match linux_shared::checkpoint::repair_checkpoint(&import_args.file) {
+                Ok(repaired) => repaired,
+                Err(err) => {
+                    eprintln!("Could not recover {:?}: {}", import_args.file, err);
+                    std::process::exit(1)
+                }
+            };
+            eprintln!(
+                "Recovering {} bytes of events, {} of them written after the last checkpoint.",
+                repaired.data_size,
+                repaired.data_size - repaired.checkpointed_size
+            );
+            if import_args.symbol_args.sandbox_symbolication {
+                let mut helper_args = vec![OsString::from("--perf-data")];
+                if import_args.override_arch.is_none() {
+                    helper_args.push("--override-arch".into());
+                    helper_args.push(std::env::consts::ARCH.into());
+                }
+                helper_args.extend(std::env::args_os().skip(2));
+                import_args.convert_in_helper(helper_args);
+            } else {
+                // The checkpoint file doesn't say which architecture it was recorded on.
+                if import_args.override_arch.is_none() {
+                    import_args.override_arch = Some(std::env::consts::ARCH.to_string());
+                }
+                let profile =
+                    convert_perf_data_file_to_profile(&import_args.open_file(), &import_args);
+                import_args.save_profile(&profile);
+            }
+            import_args.write_search_index_if_requested();
+            import_args.start_server_for_output();
+        }
+
+        Action::Report(report_args) => {
+            let query = match ProfileQuery::load_from_file(&report_args.file) {
+                Ok(query) => query,
+                Err(err) => {
+                    eprintln!("Could not load {:?}: {}", report_args.file, err);
+                    std::process::exit(1)
+                }
+            };
+            if let Some(thread) = report_args.thread {
+                if thread >= query.thread_count() {
+                    eprintln!(
+                        "Invalid thread index {thread}, the profile has {} threads.",
+                        query.thread_count()
+                    );
+                    std::process::exit(1)
+                }
+            }
+            let format = match report_args.format {
+                ReportFormatArg::Csv => report::ReportFormat::Csv,
+                ReportFormatArg::Md => report::ReportFormat::Markdown,
+            };
+            if let Err(err) = report::write_report(
+                &mut std::io::stdout().lock(),
+                &query,
+                report_args.thread,
+                format,
+                report_args.top,
+            ) {
+                eprintln!("Could not write the report: {err}");
+                std::process::exit(1)
+            }
+        }
+
+        Action::Grep(grep_args) => {
+            let index = match search_index::SearchIndex::load_for_profile(&grep_args.file) {
+                Ok(index) => index,
+                Err(err) => {
+                    eprintln!("Could not load {:?}: {}", grep_args.file, err);
+                    std::process::exit(1)
+                }
+            };
+            let results = index.search(&grep_args.pattern, grep_args.ignore_case);
+            if let Err(err) =
+                search_index::write_search_results(&mut std::io::stdout().lock(), &results)
+            {
+                eprintln!("Could not write the results: {err}");
+                std::process::exit(1)
+            }
+            if results.functions.is_empty() && results.markers.is_empty() {
+                std::process::exit(1)
+            }
+        }
+
+        Action::Stats(stats_args) => {
+            let query = match ProfileQuery::load_from_file(&stats_args.file) {
+                Ok(query) => query,
+                Err(err) => {
+                    eprintln!("Could not load {:?}: {}", stats_args.file, err);
+                    std::process::exit(1)
+                }
+            };
+            if let Err(err) =
+                stats::write_stats(&mut std::io::stdout().lock(), &query, stats_args.top)
+            {
+                eprintln!("Could not write the statistics: {err}");
+                std::process::exit(1)
+            }
+        }
+
+        Action::Validate(validate_args) => {
+            let mut profile = match merge::load_profile_json(&validate_args.file) {
+                Ok(profile) => profile,
+                Err(err) => {
+                    eprintln!("Could not load {:?}: {err}", validate_args.file);
+                    std::process::exit(1)
+                }
+            };
+            let repair = validate_args.repair.is_some();
+            let problems = match validate::validate_profile(&mut profile, repair) {
+                Ok(problems) => problems,
+                Err(err) => {
+                    eprintln!("Could not validate {:?}: {err}", validate_args.file);
+                    std::process::exit(1)
+                }
+            };
+            for problem in &problems {
+                println!("{problem}");
+            }
+            let unrepaired = problems.iter().filter(|problem| !problem.repaired).count();
+            if problems.is_empty() {
+                println!("No problems found.");
+            } else if !repair {
+                println!("Found {} problems.", problems.len());
+            } else {
+                println!(
+                    "Found {} problems, {unrepaired} of which weren't repaired.",
+                    problems.len()
+                );
+            }
+            if let Some(output) = &validate_args.repair {
+                if let Err(err) = save_profile_to_file(&profile, output) {
+                    eprintln!("Could not write {output:?}: {err}");
+                    std::process::exit(1)
+                }
+                eprintln!("Wrote the repaired profile to {output:?}.");
+            }
+            if unrepaired != 0 {
+                std::process::exit(1)
+            }
+        }
+
+        Action::Export(ExportArgs {
+            what: ExportAction::Flamegraph(export_args),
+        }) => {
+            let query = match ProfileQuery::load_from_file(&export_args.file) {
+                Ok(query) => query,
+                Err(err) => {
+                    eprintln!("Could not load {:?}: {}", export_args.file, err);
+                    std::process::exit(1)
+                }
+            };
+            let threads = exported_threads(&query, export_args.thread, export_args.pid.as_deref());
+            let format = match export_args.output.extension() {
+                Some(ext) if ext == "html" || ext == "htm" => flamegraph::FlamegraphFormat::Html,
+                _ => flamegraph::FlamegraphFormat::Svg,
+            };
+            let title = export_args.title.unwrap_or_else(|| {
+                export_args
+                    .file
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            let result = File::create(&export_args.output).and_then(|file| {
+                let mut writer = std::io::BufWriter::new(file);
+                flamegraph::write_flamegraph(&mut writer, &query, &threads, &title, format)?;
+                std::io::Write::flush(&mut writer)
+            });
+            if let Err(err) = result {
+                eprintln!("Could not write {:?}: {err}", export_args.output);
+                std::process::exit(1)
+            }
+        }
+
+        Action::Export(ExportArgs {
+            what: ExportAction::Pprof(export_args),
+        }) => {
+            let query = match ProfileQuery::load_from_file(&export_args.file) {
+                Ok(query) => query,
+                Err(err) => {
+                    eprintln!("Could not load {:?}: {}", export_args.file, err);
+                    std::process::exit(1)
+                }
+            };
+            let threads = exported_threads(&query, export_args.thread, export_args.pid.as_deref());
+            let result = File::create(&export_args.output).and_then(|file| {
+                pprof::write_pprof(std::io::BufWriter::new(file), &query, &threads)
+            });
+            if let Err(err) = result {
+                eprintln!("Could not write {:?}: {err}", export_args.output);
+                std::process::exit(1)
+            }
+        }
+
+        Action::Export(ExportArgs {
+            what: ExportAction::Wakegraph(export_args),
+        }) => {
+            let query = match ProfileQuery::load_from_file(&export_args.file) {
+                Ok(query) => query,
+                Err(err) => {
+                    eprintln!("Could not load {:?}: {}", export_args.file, err);
+                    std::process::exit(1)
+                }
+            };
+            let graph = wakegraph::WakeGraph::from_profile(&query);
+            if graph.is_empty() {
+                eprintln!(
+                    "The profile has no {:?} markers. Record it with `samply record --wakeups` (Windows only).",
+                    wakegraph::WAKEUP_MARKER_NAME
+                );
+                std::process::exit(1)
+            }
+            let format = match export_args.format {
+                WakeGraphFormatArg::Dot => wakegraph::WakeGraphFormat::Dot,
+                WakeGraphFormatArg::Json => wakegraph::WakeGraphFormat::Json,
+            };
+            let result = match &export_args.output {
+                Some(output) => File::create(output).and_then(|file| {
+                    let mut writer = std::io::BufWriter::new(file);
+                    graph.write(&mut writer, format)?;
+                    std::io::Write::flush(&mut writer)
+                }),
+                None => graph.write(&mut std::io::stdout().lock(), format),
+            };
+            if let Err(err) = result {
+                eprintln!("Could not write the wake graph: {err}");
+                std::process::exit(1)
+            }
+        }
+
+        Action::Export(ExportArgs {
+            what: ExportAction::Symbols(export_args),
+        }) => {
+            let profile_filename = &export_args.file;
+            let input_file = match File::open(profile_filename) {
+                Ok(file) => file,
+                Err(err) => {
+                    eprintln!("Could not open file {:?}: {}", profile_filename, err);
+                    std::process::exit(1)
+                }
+            };
+            let libinfo_map =
+                match parse_libinfo_map_from_profile_file(input_file, profile_filename) {
+                    Ok(libinfo_map) => libinfo_map,
+                    Err(err) => {
+                        eprintln!("Could not parse the input file as JSON: {}", err);
+                        std::process::exit(1)
+                    }
+                };
+            let symbol_manager = server::create_symbol_manager(
+                export_args.symbol_args.symbol_props(),
+                false,
+                libinfo_map.clone(),
+                Some(profile_filename),
+            );
+            let exported = match export_symbols::export_symbols(
+                &symbol_manager,
+                &libinfo_map,
+                &export_args.output,
+            ) {
+                Ok(exported) => exported,
+                Err(err) => {
+                    eprintln!("Could not write the symbol files: {err}");
+                    std::process::exit(1)
+                }
+            };
+            for (debug_name, reason) in &exported.skipped {
+                eprintln!("Skipped {debug_name}: {reason}");
+            }
+            eprintln!(
+                "Wrote {} symbol files to {:?}.",
+                exported.written.len(),
+                export_args.output
+            );
+        }
+
+        Action::Symbolicate(symbolicate_args) => {
+            let input = match &symbolicate_args.addresses {
+                Some(path) if path != Path::new("-") => std::fs::read_to_string(path),
+                _ => std::io::read_to_string(std::io::stdin()),
+            };
+            let addresses = match input.map_err(|err| err.to_string()) {
+                Ok(input) => symbolicate::parse_addresses(&input),
+                Err(err) => Err(format!("Could not read the addresses: {err}")),
+            };
+            let addresses = match addresses {
+                Ok(addresses) => addresses,
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(1)
+                }
+            };
+            let symbol_manager = server::create_symbol_manager(
+                symbolicate_args.symbol_args.symbol_props(),
+                false,
+                HashMap::new(),
+                None,
+            );
+            let kind = match symbolicate_args.address_kind {
+                AddressKindArg::Relative => symbolicate::AddressKind::Relative,
+                AddressKindArg::Svma => symbolicate::AddressKind::Svma,
+                AddressKindArg::FileOffset => symbolicate::AddressKind::FileOffset,
+            };
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            let results = rt.block_on(async {
+                let symbol_map = match (&symbolicate_args.lib, &symbolicate_args.debug_name) {
+                    (Some(lib), _) => {
+                        symbol_manager
+                            .load_symbol_map_for_binary_at_path(lib, None)
+                            .await
+                    }
+                    (None, Some(debug_name)) => {
+                        let debug_id = symbolicate_args.debug_id.unwrap();
+                        symbol_manager.load_symbol_map(debug_name, debug_id).await
+                    }
+                    (None, None) => unreachable!("clap requires --lib or --debug-name"),
+                };
+                match symbol_map {
+                    Ok(symbol_map) => {
+                        Ok(symbolicate::symbolicate_addresses(&symbol_map, kind, &addresses).await)
+                    }
+                    Err(err) => Err(err),
+                }
+            });
+            let results = match results {
+                Ok(results) => results,
+                Err(err) => {
+                    eprintln!("Could not load the symbols: {err}");
+                    std::process::exit(1)
+                }
+            };
+            let mut stdout = std::io::stdout().lock();
+            let result = match symbolicate_args.format {
+                SymbolicateFormatArg::Text => symbolicate::write_text(&mut stdout, &results),
+                SymbolicateFormatArg::Json => symbolicate::write_json(&mut stdout, &results),
+            };
+            if let Err(err) = result {
+                eprintln!("Could not write the results: {err}");
+                std::process::exit(1)
+            }
+        }
+
+        #[cfg(any(
+            target_os = "android",
+            target_os = "macos",
+            target_os = "linux",
+            target_os = "windows"
+        ))]
+        Action::Record(record_args) => {
+            let recording_props = record_args.recording_props();
+            let recording_mode = record_args.recording_mode();
+            let profile_creation_props = record_args.profile_creation_props();
+            let symbol_props = record_args.symbol_props();
+            let server_props = record_args.server_props();
+
+            let remote_recordings = match follow::start_remote_recordings(
+                &record_args.follow,
+                &record_args.follow_args,
+            ) {
+                Ok(remote_recordings) => remote_recordings,
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                }
+            };
+            // With --follow, the server is only started once the remote profiles
+            // have been merged into the local one, and with --output-dir, once the
+            // session directory is complete.
+            let finish_later = !remote_recordings.is_empty() || record_args.output_dir.is_some();
+            let (server_props, server_props_after_merge) = if !finish_later {
+                (server_props, None)
+            } else {
+                (None, server_props)
+            };
+
+            let exit_status = match profiler::start_recording(
+                recording_mode,
+                recording_props,
+                profile_creation_props,
+                symbol_props,
+                server_props,
+            ) {
+                Ok((exit_status, _profile)) => exit_status,
+                Err(err) => {
+                    eprintln!("Error: {err}");
+                    std::process::exit(1);
+                }
+            };
+            if !finish_later {
+                std::process::exit(exit_status.code().unwrap_or(0));
+            }
+
+            let profile_filename = &record_args.output_file();
+            if !remote_recordings.is_empty() {
+                if let Err(err) =
+                    follow::merge_remote_recordings(remote_recordings, profile_filename)
+                {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                }
+                if record_args.profile_creation_args.search_index {
+                    // The index of the local profile is out of date now.
+                    search_index::write_search_index_for_profile(profile_filename);
+                }
+            }
+            if let Some(output_dir) = &record_args.output_dir {
+                if let Err(err) = session_dir::finish_session_dir(
+                    output_dir,
+                    exit_status.code(),
+                    record_args.copy_binaries,
+                ) {
+                    eprintln!("Could not write the session directory {output_dir:?}: {err}");
+                    std::process::exit(1);
+                }
+                eprintln!("Saved the session to {output_dir:?}.");
+            }
+            if let Some(server_props) = server_props_after_merge {
+                let libinfo_map = parse_libinfo_map_from_profile_file(
+                    File::open(profile_filename).expect("Couldn't open file we just wrote"),
+                    profile_filename,
+                )
+                .expect("Couldn't parse libinfo map from profile file");
+                start_server_main(
+                    profile_filename,
+                    server_props,
+                    record_args.symbol_props(),
+                    libinfo_map,
+                );
+            }
+            std::process::exit(exit_status.code().unwrap_or(0));
+        }
+
+        #[cfg(any(
+            target_os = "android",
+            target_os = "macos",
+            target_os = "linux",
+            target_os = "windows"
+        ))]
+        Action::Cargo(cargo_args) => {
+            let record_args = cargo_args.record_args();
+            let exit_status = match profiler::start_recording(
+                record_args.recording_mode(),
+                record_args.recording_props(),
+                record_args.profile_creation_props(),
+                record_args.symbol_props(),
+                record_args.server_props(),
+            ) {
+                Ok((exit_status, _profile)) => exit_status,
+                Err(err) => {
+                    eprintln!("Error: {err}");
+                    std::process::exit(1);
+                }
+            };
+            std::process::exit(exit_status.code().unwrap_or(0));
+        }
+
+        #[cfg(target_os = "windows")]
+        Action::RunElevatedHelper(RunElevatedHelperArgs {
+            ipc_directory,
+            output_path,
+        }) => {
+            windows::run_elevated_helper(&ipc_directory, output_path);
+        }
+
+        Action::ClockSync => {
+            follow::run_clock_sync_responder();
+        }
+
+        Action::Merge(merge_args) => {
+            let mut inputs = Vec::with_capacity(merge_args.files.len());
+            for file in &merge_args.files {
+                let profile = match load_merge_input(file) {
+                    Ok(profile) => profile,
+                    Err(err) => {
+                        eprintln!("Could not load {file:?}: {err}");
+                        std::process::exit(1)
+                    }
+                };
+                let clock_offset_ms = merge_args
+                    .clock_offset
+                    .iter()
+                    .rev()
+                    .find(|(offset_file, _)| offset_file == file)
+                    .map_or(0.0, |(_, offset)| *offset);
+                inputs.push(merge::MergeInput {
+                    profile,
+                    label: Some(merge::label_for_profile_path(file)),
+                    clock_offset_ms,
+                });
+            }
+            let merged = match merge::merge_profiles(inputs) {
+                Ok(merged) => merged,
+                Err(err) => {
+                    eprintln!("Could not merge the profiles: {err}");
+                    std::process::exit(1)
+                }
+            };
+            if let Err(err) = save_profile_to_file(&merged, &merge_args.output) {
+                eprintln!("Could not write {:?}: {err}", merge_args.output);
+                std::process::exit(1)
+            }
+            eprintln!("Wrote the merged profile to {:?}.", merge_args.output);
+        }
+
+        Action::Split(split_args) => {
+            let profile = match merge::load_profile_json(&split_args.file) {
+                Ok(profile) => profile,
+                Err(err) => {
+                    eprintln!("Could not load {:?}: {err}", split_args.file);
+                    std::process::exit(1)
+                }
+            };
+            let processes = match split::split_by_process(profile) {
+                Ok(processes) => processes,
+                Err(err) => {
+                    eprintln!("Could not split the profile: {err}");
+                    std::process::exit(1)
+                }
+            };
+            if let Some(output_dir) = &split_args.output_dir {
+                if let Err(err) = std::fs::create_dir_all(output_dir) {
+                    eprintln!("Could not create {output_dir:?}: {err}");
+                    std::process::exit(1)
+                }
+            }
+            let mut written_count = 0;
+            for process in processes {
+                if !split_args.pid.is_empty() && !split_args.pid.contains(&process.pid) {
+                    continue;
+                }
+                let output = split::output_path(
+                    &split_args.file,
+                    split_args.output_dir.as_deref(),
+                    &process,
+                );
+                if let Err(err) = save_profile_to_file(&process.profile, &output) {
+                    eprintln!("Could not write {output:?}: {err}");
+                    std::process::exit(1)
+                }
+                eprintln!(
+                    "Wrote the profile of {} to {output:?}.",
+                    process.process_name
+                );
+                written_count += 1;
+            }
+            if written_count == 0 {
+                eprintln!("There are no processes to write.");
+                std::process::exit(1)
+            }
+        }
+
+        Action::AnnotateProfile(annotate_args) => {
+            let mut profile = match merge::load_profile_json(&annotate_args.file) {
+                Ok(profile) => profile,
+                Err(err) => {
+                    eprintln!("Could not load {:?}: {err}", annotate_args.file);
+                    std::process::exit(1)
+                }
+            };
+            if !annotate_args.marker.is_empty() {
+                if let Err(err) = annotate::annotate_profile(&mut profile, &annotate_args.marker) {
+                    eprintln!("Could not annotate the profile: {err}");
+                    std::process::exit(1)
+                }
+            }
+            let mut marker_count = annotate_args.marker.len();
+            if let Some(syscall_log_path) = &annotate_args.syscall_log {
+                let log = match std::fs::read_to_string(syscall_log_path) {
+                    Ok(text) => syscall_log::parse_syscall_log(&text),
+                    Err(err) => {
+                        eprintln!("Could not read {syscall_log_path:?}: {err}");
+                        std::process::exit(1)
+                    }
+                };
+                let log_start = annotate_args
+                    .syscall_log_start
+                    .clone()
+                    .unwrap_or(annotate::AnnotationTime::Relative(Duration::ZERO));
+                let result = log.and_then(|log| {
+                    syscall_log::add_syscall_markers(&mut profile, &log, &log_start)
+                });
+                match result {
+                    Ok((added, unmatched)) => {
+                        if unmatched != 0 {
+                            eprintln!(
+                                "Skipped {unmatched} syscalls of threads not in the profile."
+                            );
+                        }
+                        marker_count += added;
+                    }
+                    Err(err) => {
+                        eprintln!("Could not add the syscalls from {syscall_log_path:?}: {err}");
+                        std::process::exit(1)
+                    }
+                }
+            }
+            let output = annotate_args.output.as_ref().unwrap_or(&annotate_args.file);
+            if let Err(err) = save_profile_to_file(&profile, output) {
+                eprintln!("Could not write {output:?}: {err}");
+                std::process::exit(1)
+            }
+            eprintln!("Added {marker_count} markers to the profile in {output:?}.");
+        }
+
+        Action::AttachData(attach_args) => {
+            let mut profile = match merge::load_profile_json(&attach_args.file) {
+                Ok(profile) => profile,
+                Err(err) => {
+                    eprintln!("Could not load {:?}: {err}", attach_args.file);
+                    std::process::exit(1)
+                }
+            };
+            let text = match std::fs::read_to_string(&attach_args.data) {
+                Ok(text) => text,
+                Err(err) => {
+                    eprintln!("Could not read {:?}: {err}", attach_args.data);
+                    std::process::exit(1)
+                }
+            };
+            let kind = match attach_args.data_type {
+                AttachDataTypeArg::Counter => attach_data::DataKind::Counter,
+                AttachDataTypeArg::Marker => attach_data::DataKind::Marker,
+            };
+            let alignment = attach_data::TimeAlignment {
+                unit_ms: attach_args.time_unit.as_ms(),
+                anchor: attach_args.anchor.clone(),
+                offset_ms: attach_args.clock_offset,
+            };
+            let result =
+                attach_data::parse_data(&text, attach_args.column.as_deref()).and_then(|rows| {
+                    attach_data::attach_data(
+                        &mut profile,
+                        &rows,
+                        kind,
+                        &attach_args.name,
+                        &alignment,
+                        attach_args.pid.as_deref(),
+                    )
+                });
+            let row_count = match result {
+                Ok(row_count) => row_count,
+                Err(err) => {
+                    eprintln!("Could not add the data from {:?}: {err}", attach_args.data);
+                    std::process::exit(1)
+                }
+            };
+            let output = attach_args.output.as_ref().unwrap_or(&attach_args.file);
+            if let Err(err) = save_profile_to_file(&profile, output) {
+                eprintln!("Could not write {output:?}: {err}");
+                std::process::exit(1)
+            }
+            let name = &attach_args.name;
+            match kind {
+                attach_data::DataKind::Counter => eprintln!(
+                    "Added the counter {name:?} with {row_count} samples to the profile in {output:?}."
+                ),
+                attach_data::DataKind::Marker => eprintln!(
+                    "Added {row_count} {name:?} markers to the profile in {output:?}."
+                ),
+            }
+        }
+
+        Action::RunSymbolicationHelper(RunSymbolicationHelperArgs {
+            profile,
+            symbol_props,
+            verbose,
+        }) => {
+            symbolication_sandbox::run_symbolication_helper(
+                profile.as_deref(),
+                &symbol_props,
+                verbose,
+            );
+        }
+
+        Action::RunImportHelper(RunImportHelperArgs {
+            perf_data,
+            import_args,
+        }) => {
+            symbolication_sandbox::restrict_import_helper();
+            let input_file = import_args.open_file();
+            let profile = if perf_data {
+                convert_perf_data_file_to_profile(&input_file, &import_args)
+            } else {
+                convert_file_to_profile(&input_file, &import_args)
+            };
+            // The parent process saves the profile.
+            let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+            let result = serde_json::to_writer(&mut stdout, &profile)
+                .map_err(std::io::Error::from)
+                .and_then(|()| stdout.flush());
+            if let Err(err) = result {
+                eprintln!("Could not send the profile to the samply process: {err}");
+                std::process::exit(1)
+            }
+        }
+
+        #[cfg(target_os = "macos")]
+        Action::Setup => {
+            mac::codesign_setup::codesign_setup();
+        }
+    }
+}
+
+impl LoadArgs {
+    fn server_props(&self) -> ServerProps {
+        ServerProps {
+            watch: self.watch,
+            ..self.server_args.server_props()
+        }
+    }
+
+    fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+}
+
+impl ImportArgs {
+    fn server_props(&self) -> Option<ServerProps> {
+        if self.save_only {
+            None
+        } else {
+            Some(self.server_args.server_props())
+        }
+    }
+
+    fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+
+    fn open_file(&self) -> File {
+        match File::open(&self.file) {
+            Ok(file) => file,
+            Err(err) => {
+                eprintln!("Could not open file {:?}: {}", self.file, err);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    fn save_profile(&self, profile: &Profile) {
+        if let Err(err) = save_profile_to_file(profile, &self.output) {
+            eprintln!("Could not write {:?}: {err}", self.output);
+            std::process::exit(1)
+        }
+    }
+
+    /// Converts the file in a sandboxed helper process, which is launched with
+    /// `helper_args`, and saves the profile.
+    fn convert_in_helper(&self, helper_args: impl IntoIterator<Item = OsString>) {
+        if let Err(err) = symbolication_sandbox::run_import_helper(helper_args, &self.output) {
+            eprintln!("Could not import {:?}: {err}", self.file);
+            std::process::exit(1)
+        }
+    }
+
+    /// Serves the converted profile, unless --save-only was given.
+    fn write_search_index_if_requested(&self) {
+        if self.profile_creation_args.search_index {
+            search_index::write_search_index_for_profile(&self.output);
+        }
+    }
+
+    fn start_server_for_output(&self) {
+        if let Some(server_props) = self.server_props() {
+            let profile_filename = &self.output;
+            let libinfo_map = profile_json_preparse::parse_libinfo_map_from_profile_file(
+                File::open(profile_filename).expect("Couldn't open file we just wrote"),
+                profile_filename,
+            )
+            .expect("Couldn't parse libinfo map from profile file");
+            start_server_main(
+                profile_filename,
+                server_props,
+                self.symbol_props(),
+                libinfo_map,
+            );
+        }
+    }
+
+    fn profile_creation_props(&self) -> ProfileCreationProps {
+        let filename = self.file.file_name().unwrap_or(self.file.as_os_str());
+        let fallback_profile_name = filename.to_string_lossy().into();
+        ProfileCreationProps {
+            profile_name: self.profile_creation_args.profile_name.clone(),
+            fallback_profile_name,
+            main_thread_only: self.profile_creation_args.main_thread_only,
+            reuse_threads: self.profile_creation_args.reuse_threads,
+            reuse_processes_by_name: self.profile_creation_args.reuse_processes_by_name,
+            fold_recursive_prefix: self.profile_creation_args.fold_recursive_prefix,
+            unlink_aux_files: self.profile_creation_args.unlink_aux_files,
+            create_per_cpu_threads: self.profile_creation_args.per_cpu_threads,
+            wall_clock_samples: self.profile_creation_args.wall_clock,
+            arg_count_to_include_in_process_name: self.profile_creation_args.include_args,
+            override_arch: self.override_arch.clone(),
+            unstable_presymbolicate: self.profile_creation_args.unstable_presymbolicate,
+            no_presymbolicate: self.profile_creation_args.no_presymbolicate,
+            search_index: self.profile_creation_args.search_index,
+            recording_summary: !self.profile_creation_args.no_summary,
+            coreclr: to_coreclr_profile_props(&self.coreclr),
+            etw_providers: Vec::new(),
+            #[cfg(target_os = "windows")]
+            etw_provider_aliases: self.etw_provider_alias.clone(),
+            #[cfg(not(target_os = "windows"))]
+            etw_provider_aliases: Vec::new(),
+            #[cfg(target_os = "windows")]
+            unknown_event_markers: self.profile_creation_args.unknown_event_markers,
+            #[cfg(not(target_os = "windows"))]
+            unknown_event_markers: false,
+            #[cfg(target_os = "windows")]
+            max_marker_value_size: self.profile_creation_args.max_marker_value_size(),
+            #[cfg(not(target_os = "windows"))]
+            max_marker_value_size: None,
+            #[cfg(target_os = "windows")]
+            max_marker_payload_size: self.profile_creation_args.max_marker_payload_size(),
+            #[cfg(not(target_os = "windows"))]
+            max_marker_payload_size: None,
+            #[cfg(target_os = "windows")]
+            include_system_process: self.profile_creation_args.include_system_process,
+            #[cfg(not(target_os = "windows"))]
+            include_system_process: false,
+            #[cfg(target_os = "windows")]
+            thread_states: self.profile_creation_args.thread_states,
+            #[cfg(not(target_os = "windows"))]
+            thread_states: false,
+            #[cfg(target_os = "windows")]
+            scheduler_latency_threshold: self.profile_creation_args.scheduler_latency_threshold(),
+            #[cfg(not(target_os = "windows"))]
+            scheduler_latency_threshold: None,
+            #[cfg(target_os = "windows")]
+            wakeups: self.profile_creation_args.wakeups,
+            #[cfg(not(target_os = "windows"))]
+            wakeups: false,
+            #[cfg(target_os = "windows")]
+            hang_threshold: self.profile_creation_args.hang_threshold(),
+            #[cfg(not(target_os = "windows"))]
+            hang_threshold: None,
+            #[cfg(target_os = "windows")]
+            stack_walk_events: self.profile_creation_args.stack_walk_events(),
+            #[cfg(not(target_os = "windows"))]
+            stack_walk_events: Vec::new(),
+            #[cfg(target_os = "windows")]
+            time_range: self.time_range,
+            #[cfg(not(target_os = "windows"))]
+            time_range: None,
+            max_profile_size: self.profile_creation_args.max_profile_size(),
+            max_stack_depth: self.profile_creation_args.max_stack_depth,
+            namespace_category_rules: self.profile_creation_args.namespace_category_rules(),
+            runtime_categories: !self.profile_creation_args.no_runtime_categories,
+            syscall_categories: !self.profile_creation_args.no_syscall_categories,
+            remove_collector_frames: !self.profile_creation_args.keep_collector_frames,
+            process_group_cpu: self.profile_creation_args.process_group_cpu,
+            thread_order: self.profile_creation_args.thread_order.into(),
+            hide_idle_threads: self.profile_creation_args.hide_idle_threads,
+        }
+    }
+
+    // TODO: Use for perf.data import
+    #[allow(unused)]
+    fn included_processes(&self) -> Option<IncludedProcesses> {
+        match (&self.name, &self.pid) {
+            (None, None) => None, // No filtering, include all processes
+            (names, pids) => Some(IncludedProcesses {
+                name_substrings: names.clone().unwrap_or_default(),
+                pids: pids.clone().unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "macos",
+    target_os = "linux",
+    target_os = "windows"
+))]
+impl CargoArgs {
+    /// Builds the test binary, and returns the arguments for recording it.
+    fn record_args(self) -> RecordArgs {
+        let command = match self.cargo_command {
+            CargoCommandArg::Test => cargo::CargoCommand::Test,
+            CargoCommandArg::Bench => cargo::CargoCommand::Bench,
+        };
+        let split_args = cargo::split_cargo_args(&self.cargo_args);
+        let binary = cargo::build_test_binaries(command, &split_args.cargo_args)
+            .and_then(|binaries| cargo::select_test_binary(binaries, split_args.filter.as_deref()))
+            .unwrap_or_else(|err| {
+                eprintln!("Error: {err}");
+                std::process::exit(1)
+            });
+
+        // Cargo runs test binaries with CARGO_MANIFEST_DIR set, and some tests rely on it.
+        let mut manifest_dir_var = std::ffi::OsString::from("CARGO_MANIFEST_DIR=");
+        manifest_dir_var.push(&binary.manifest_dir);
+
+        // Parse a `samply record` command line for the test binary, so that
+        // all other recording options get their default values.
+        let samply_args = ["samply", "record", "--"].map(std::ffi::OsString::from);
+        let opt = Opt::parse_from(
+            samply_args
+                .into_iter()
+                .chain([manifest_dir_var, binary.executable.into_os_string()])
+                .chain(split_args.test_binary_args(command)),
+        );
+        let Action::Record(mut record_args) = opt.action else {
+            unreachable!("We passed the record subcommand");
+        };
+        record_args.rate = self.rate;
+        record_args.save_only = self.save_only;
+        record_args.output = self.output;
+        record_args.server_args = self.server_args;
+        record_args.symbol_args = self.symbol_args;
+        record_args.profile_creation_args.profile_name = Some(match &split_args.filter {
+            Some(filter) => format!("cargo {} {}", command.name(), filter.to_string_lossy()),
+            None => format!("cargo {} {}", command.name(), binary.target_name),
+        });
+        record_args
+    }
+}
+
+/// Parses a `samply record` command line for [`crate::Recorder`], and returns
+/// the props which the platform recorders take.
+#[cfg(any(target_os = "android", target_os = "macos", target_os = "linux"))]
+pub(crate) fn recorder_props(
+    samply_args: Vec<std::ffi::OsString>,
+) -> Result<
+    (
+        RecordingMode,
+        RecordingProps,
+        ProfileCreationProps,
+        SymbolProps,
+    ),
+    RecordError,
+> {
+    let opt = Opt::try_parse_from(samply_args)
+        .map_err(|err| RecordError::InvalidOptions(err.to_string()))?;
+    let Action::Record(record_args) = opt.action else {
+        unreachable!("The recorder passes the record subcommand");
+    };
+    if !record_args.follow.is_empty() {
+        return Err(RecordError::InvalidOptions(
+            "--follow is only supported by the samply command line tool".to_string(),
+        ));
+    }
+    let recording_mode = record_args
+        .try_recording_mode()
+        .map_err(RecordError::InvalidOptions)?;
+    let recording_props = record_args
+        .try_recording_props()
+        .map_err(RecordError::InvalidOptions)?;
+    Ok((
+        recording_mode,
+        recording_props,
+        record_args.profile_creation_props(),
+        record_args.symbol_props(),
+    ))
+}
+
+impl RecordArgs {
+    #[allow(unused)]
+    fn server_props(&self) -> Option<ServerProps> {
+        if self.save_only {
+            None
+        } else {
+            Some(self.server_args.server_props())
+        }
+    }
+
+    fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+
+    /// The file to save the profile to, which is in the session directory with
+    /// --output-dir.
+    fn output_file(&self) -> PathBuf {
+        match &self.output_dir {
+            Some(output_dir) => output_dir.join(session_dir::PROFILE_FILE_NAME),
+            None => self.output.clone(),
+        }
+    }
+
+    #[allow(unused)]
+    pub fn recording_props(&self) -> RecordingProps {
+        self.try_recording_props().unwrap_or_else(|err| {
+            eprintln!("Error: {err}");
+            std::process::exit(1)
+        })
+    }
+
+    /// Like [`Self::recording_props`], but returns invalid options as an error
+    /// instead of exiting.
+    fn try_recording_props(&self) -> Result<RecordingProps, String> {
+        let time_limit = self.duration;
+        if self.rate <= 0.0 {
+            return Err(format!(
+                "sampling rate must be greater than zero, got {}",
+                self.rate
+            ));
+        }
+        let interval = Duration::from_secs_f64(1.0 / self.rate);
+        for (process, rate) in &self.interval_for {
+            if *rate > self.rate {
+                return Err(format!(
+                    "the sampling rate for {process:?} ({rate} Hz) can't be higher than --rate ({} Hz)",
+                    self.rate
+                ));
+            }
+        }
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        if let Some(seconds) = self.checkpoint_interval {
+            if !seconds.is_finite() || seconds <= 0.0 {
+                return Err(format!(
+                    "the checkpoint interval must be greater than zero, got {seconds}"
+                ));
+            }
+        }
+        #[cfg(any(target_os = "android", target_os = "linux", target_os = "windows"))]
+        let max_recording_size = match self.max_recording_size {
+            Some(gigabytes) if !gigabytes.is_finite() || gigabytes <= 0.0 => {
+                return Err(format!(
+                    "the maximum recording size must be greater than zero, got {gigabytes}"
+                ));
+            }
+            Some(gigabytes) => Some((gigabytes * 1_000_000_000.0) as u64),
+            None => None,
+        };
+        #[cfg(not(any(target_os = "android", target_os = "linux", target_os = "windows")))]
+        let max_recording_size = None;
+        Ok(RecordingProps {
+            output_file: self.output_file(),
+            time_limit,
+            delay: self.delay.unwrap_or_default(),
+            until_exit_of: self.until_exit_of,
+            max_duration: self.max_duration,
+            max_recording_size,
+            interval,
+            gfx: self.gfx,
+            browsers: self.browsers,
+            #[cfg(target_os = "windows")]
+            vm_hack: self.vm_hack,
+            #[cfg(not(target_os = "windows"))]
+            vm_hack: false,
+            #[cfg(target_os = "windows")]
+            keep_etl: self.keep_etl,
+            #[cfg(not(target_os = "windows"))]
+            keep_etl: false,
+            #[cfg(target_os = "windows")]
+            pmc_counters: self.pmc.clone(),
+            #[cfg(not(target_os = "windows"))]
+            pmc_counters: Vec::new(),
+            #[cfg(target_os = "windows")]
+            pmc_sampling_source: self.pmc_sampling.clone(),
+            #[cfg(not(target_os = "windows"))]
+            pmc_sampling_source: None,
+            #[cfg(target_os = "windows")]
+            pmc_sampling_interval: self.pmc_sampling_interval,
+            #[cfg(not(target_os = "windows"))]
+            pmc_sampling_interval: 0,
+            #[cfg(target_os = "windows")]
+            antivirus: self.antivirus,
+            #[cfg(not(target_os = "windows"))]
+            antivirus: false,
+            #[cfg(target_os = "windows")]
+            user_mode_only: self.user_mode_only,
+            #[cfg(not(target_os = "windows"))]
+            user_mode_only: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            clock: match self.clock {
+                ClockArg::Monotonic => TimestampClock::Monotonic,
+                ClockArg::Boottime => TimestampClock::Boottime,
+            },
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            clock: TimestampClock::Monotonic,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            lbr_call_stacks: self.lbr,
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            lbr_call_stacks: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            bpf: self.bpf,
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            bpf: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            tracepoints: self.tracepoint.clone(),
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            tracepoints: Vec::new(),
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            contention: self.contention,
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            contention: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            thread_affinity: self.thread_affinity,
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            thread_affinity: false,
+            process_intervals: self
+                .interval_for
+                .iter()
+                .map(|(process, rate)| (process.clone(), Duration::from_secs_f64(1.0 / rate)))
+                .collect(),
+            #[cfg(any(target_os = "macos", target_os = "windows"))]
+            power_counters: self.power_counters,
+            #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+            power_counters: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            checkpoint_interval: self.checkpoint_interval.map(Duration::from_secs_f64),
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            checkpoint_interval: None,
+        })
+    }
+
+    pub fn recording_mode(&self) -> RecordingMode {
+        self.try_recording_mode().unwrap_or_else(|err| {
+            eprintln!("Error: {err}");
+            std::process::exit(1)
+        })
+    }
+
+    /// Like [`Self::recording_mode`], but returns an invalid command as an
+    /// error instead of exiting.
+    fn try_recording_mode(&self) -> Result<RecordingMode, String> {
+        #[cfg(target_os = "windows")]
+        if let Some(service) = &self.service {
+            return Ok(RecordingMode::Service(service.clone()));
+        }
+        #[cfg(target_os = "windows")]
+        if let Some(app_id) = &self.appid {
+            return Ok(RecordingMode::PackagedApp {
+                app_id: app_id.clone(),
+                args: self.command.clone(),
+            });
+        }
+
+        let (command, iteration_count) = match (self.all, &self.pid) {
+            (true, _) => return Ok(RecordingMode::All),
+            (false, Some(pid)) => return Ok(RecordingMode::Pid(*pid)),
+            (false, None) => (&self.command, self.iteration_count),
+        };
+
+        assert!(
+            !command.is_empty(),
+            "CLI parsing should have ensured that we have at least one command name"
+        );
+        let mut env_vars = Vec::new();
+        let mut i = 0;
+        while let Some((var_name, var_val)) = command.get(i).and_then(|s| split_at_first_equals(s))
+        {
+            env_vars.push((var_name.to_owned(), var_val.to_owned()));
+            i += 1;
+        }
+        if i == command.len() {
+            return Err(format!("No command name found. Every item looks like an environment variable (contains '='): {command:?}"));
+        }
+        let command_name = command[i].clone();
+        let args = command[(i + 1)..].to_owned();
+        let launch_props = ProcessLaunchProps {
+            env_vars,
+            command_name,
+            args,
+            iteration_count,
+        };
+
+        Ok(RecordingMode::Launch(launch_props))
+    }
+
+    pub fn profile_creation_props(&self) -> ProfileCreationProps {
+        let fallback_profile_name = match self.recording_mode() {
+            RecordingMode::All => "All processes".to_string(),
+            RecordingMode::Pid(pid) => format!("PID {pid}"),
+            #[cfg(target_os = "windows")]
+            RecordingMode::Service(service) => service,
+            #[cfg(target_os = "windows")]
+            RecordingMode::PackagedApp { app_id, .. } => app_id,
+            RecordingMode::Launch(launch_props) => {
+                let filename = Path::new(&launch_props.command_name)
+                    .file_name()
+                    .unwrap_or(launch_props.command_name.as_os_str());
+                filename.to_string_lossy().into()
+            }
+        };
+        ProfileCreationProps {
+            profile_name: self.profile_creation_args.profile_name.clone(),
+            fallback_profile_name,
+            main_thread_only: self.profile_creation_args.main_thread_only,
+            reuse_threads: self.profile_creation_args.reuse_threads,
+            reuse_processes_by_name: self.profile_creation_args.reuse_processes_by_name,
+            fold_recursive_prefix: self.profile_creation_args.fold_recursive_prefix,
+            unlink_aux_files: self.profile_creation_args.unlink_aux_files,
+            create_per_cpu_threads: self.profile_creation_args.per_cpu_threads,
+            wall_clock_samples: self.profile_creation_args.wall_clock,
+            arg_count_to_include_in_process_name: self.profile_creation_args.include_args,
+            override_arch: None,
+            unstable_presymbolicate: self.profile_creation_args.unstable_presymbolicate,
+            no_presymbolicate: self.profile_creation_args.no_presymbolicate,
+            search_index: self.profile_creation_args.search_index,
+            recording_summary: !self.profile_creation_args.no_summary,
+            coreclr: to_coreclr_profile_props(&self.coreclr),
+            #[cfg(target_os = "windows")]
+            etw_providers: self.providers.clone(),
+            #[cfg(not(target_os = "windows"))]
+            etw_providers: Vec::new(),
+            etw_provider_aliases: Vec::new(),
+            #[cfg(target_os = "windows")]
+            unknown_event_markers: self.profile_creation_args.unknown_event_markers,
+            #[cfg(not(target_os = "windows"))]
+            unknown_event_markers: false,
+            #[cfg(target_os = "windows")]
+            max_marker_value_size: self.profile_creation_args.max_marker_value_size(),
+            #[cfg(not(target_os = "windows"))]
+            max_marker_value_size: None,
+            #[cfg(target_os = "windows")]
+            max_marker_payload_size: self.profile_creation_args.max_marker_payload_size(),
+            #[cfg(not(target_os = "windows"))]
+            max_marker_payload_size: None,
+            #[cfg(target_os = "windows")]
+            include_system_process: self.profile_creation_args.include_system_process,
+            #[cfg(not(target_os = "windows"))]
+            include_system_process: false,
+            #[cfg(target_os = "windows")]
+            thread_states: self.profile_creation_args.thread_states,
+            #[cfg(not(target_os = "windows"))]
+            thread_states: false,
+            #[cfg(target_os = "windows")]
+            scheduler_latency_threshold: self.profile_creation_args.scheduler_latency_threshold(),
+            #[cfg(not(target_os = "windows"))]
+            scheduler_latency_threshold: None,
+            #[cfg(target_os = "windows")]
+            wakeups: self.profile_creation_args.wakeups,
+            #[cfg(not(target_os = "windows"))]
+            wakeups: false,
+            #[cfg(target_os = "windows")]
+            hang_threshold: self.profile_creation_args.hang_threshold(),
+            #[cfg(not(target_os = "windows"))]
+            hang_threshold: None,
+            #[cfg(target_os = "windows")]
+            stack_walk_events: self.profile_creation_args.stack_walk_events(),
+            #[cfg(not(target_os = "windows"))]
+            stack_walk_events: Vec::new(),
+            time_range: None,
+            max_profile_size: self.profile_creation_args.max_profile_size(),
+            max_stack_depth: self.profile_creation_args.max_stack_depth,
+            namespace_category_rules: self.profile_creation_args.namespace_category_rules(),
+            runtime_categories: !self.profile_creation_args.no_runtime_categories,
+            syscall_categories: !self.profile_creation_args.no_syscall_categories,
+            remove_collector_frames: !self.profile_creation_args.keep_collector_frames,
+            process_group_cpu: self.profile_creation_args.process_group_cpu,
+            thread_order: self.profile_creation_args.thread_order.into(),
+            hide_idle_threads: self.profile_creation_args.hide_idle_threads,
+        }
+    }
+}
+
+impl ProfileCreationArgs {
+    fn max_profile_size(&self) -> Option<u64> {
+        self.max_profile_size.map(|mb| mb * 1024 * 1024)
+    }
+
+    fn namespace_category_rules(&self) -> Option<Vec<NamespaceCategoryRule>> {
+        if self.categorize_by_namespace || !self.namespace_category.is_empty() {
+            Some(self.namespace_category.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl ServerArgs {
+    pub fn server_props(&self) -> ServerProps {
+        let open_in_browser = !self.no_open;
+        let port_selection = match PortSelection::try_from_str(&self.port) {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!(
+                    "Could not parse port as <u16> or <u16>+, got port {}, error: {}",
+                    self.port, e
+                );
+                std::process::exit(1)
+            }
+        };
+
+        // parse address from string
+        let address = match IpAddr::from_str(&self.address) {
+            Ok(addr) => addr,
+            Err(e) => {
+                eprintln!(
+                    "Could not parse address as IpAddr, got address {:?}, error: {}",
+                    self.address, e
+                );
+                std::process::exit(1)
+            }
+        };
+
+        ServerProps {
+            address,
+            port_selection,
+            verbose: self.verbose,
+            open_in_browser,
+            watch: false,
+        }
+    }
+}
+
+impl SymbolArgs {
+    pub fn symbol_props(&self) -> SymbolProps {
+        SymbolProps {
+            symbol_dir: self.symbol_dir.clone(),
+            windows_symbol_server: self.windows_symbol_server.clone(),
+            windows_symbol_cache: self.windows_symbol_cache.clone(),
+            breakpad_symbol_server: self.breakpad_symbol_server.clone(),
+            breakpad_symbol_dir: self.breakpad_symbol_dir.clone(),
+            breakpad_symbol_cache: self.breakpad_symbol_cache.clone(),
+            simpleperf_binary_cache: self.simpleperf_binary_cache.clone(),
+            sandbox: self.sandbox_symbolication,
+            source_path_map: self.source_path_map.clone(),
+            source_url_headers: self.source_url_header.clone(),
+            full_signatures: self.full_signatures,
+            skip_symbols_for: self.skip_symbols_for.clone(),
+            offline: self.offline_symbols,
+        }
+    }
+}
+
+fn to_coreclr_profile_props(coreclr_args: &[CoreClrArgs]) -> CoreClrProfileProps {
+    // on Windows, the ..Default::default() has no effect, and clippy doesn't like it
+    #[allow(clippy::needless_update)]
+    CoreClrProfileProps {
+        enabled: coreclr_args.contains(&CoreClrArgs::Enabled),
+        #[cfg(target_os = "windows")]
+        gc_markers: coreclr_args.contains(&CoreClrArgs::GcMarkers),
+        #[cfg(target_os = "windows")]
+        gc_suspensions: coreclr_args.contains(&CoreClrArgs::GcSuspendedThreads),
+        #[cfg(target_os = "windows")]
+        gc_detailed_allocs: coreclr_args.contains(&CoreClrArgs::GcDetailedAllocs),
+        #[cfg(target_os = "windows")]
+        event_stacks: coreclr_args.contains(&CoreClrArgs::EventStacks),
+        ..Default::default()
+    }
+}
+
+/// The threads for `--thread` or `--pid`, or all threads. Exits with an error
+/// if there's no such thread or process.
+fn exported_threads(query: &ProfileQuery, thread: Option<usize>, pid: Option<&str>) -> Vec<usize> {
+    match (thread, pid) {
+        (Some(thread), _) if thread >= query.thread_count() => {
+            eprintln!(
+                "Invalid thread index {thread}, the profile has {} threads.",
+                query.thread_count()
+            );
+            std::process::exit(1)
+        }
+        (Some(thread), _) => vec![thread],
+        (None, Some(pid)) => {
+            let threads = query.process_thread_indexes(pid);
+            if threads.is_empty() {
+                eprintln!("The profile has no process with pid {pid}.");
+                std::process::exit(1)
+            }
+            threads
+        }
+        (None, None) => (0..query.thread_count()).collect(),
+    }
+}
+
+fn split_at_first_equals(s: &OsStr) -> Option<(&OsStr, &OsStr)> {
+    let bytes = s.as_encoded_bytes();
+    let pos = bytes.iter().position(|b| *b == b'=')?;
+    let name = &bytes[..pos];
+    let val = &bytes[(pos + 1)..];
+    // SAFETY:
+    // - `name` and `val` only contain content that originated from `OsStr::as_encoded_bytes`
+    // - Only split with ASCII '=' which is a non-empty UTF-8 substring
+    let (name, val) = unsafe {
+        (
+            OsStr::from_encoded_bytes_unchecked(name),
+            OsStr::from_encoded_bytes_unchecked(val),
+        )
+    };
+    Some((name, val))
+}
+
+fn convert_file_to_profile(input_file: &File, import_args: &ImportArgs) -> Profile {
+    if import_args.file.extension() == Some(OsStr::new("etl")) {
+        return convert_etl_file_to_profile(input_file, import_args);
+    }
+
+    if import_args.file.extension() == Some(OsStr::new("json")) {
+        return convert_chrome_trace_file_to_profile(input_file, import_args);
+    }
+
+    if import_args.file.extension() == Some(OsStr::new("cpuprofile")) {
+        return convert_cpuprofile_file_to_profile(input_file, import_args);
+    }
+
+    if import_args.file.extension() == Some(OsStr::new("nettrace")) {
+        return convert_nettrace_file_to_profile(input_file, import_args);
+    }
+
+    let file_name = import_args
+        .file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if file_name.starts_with("massif.out") {
+        return convert_massif_file_to_profile(input_file, &file_name, import_args);
+    }
+    if file_name.starts_with("heaptrack.") {
+        return convert_heaptrack_file_to_profile(input_file, &file_name, import_args);
+    }
+    if is_mac_sample_text_file(input_file) {
+        return convert_mac_sample_file_to_profile(input_file, import_args);
+    }
+
+    convert_perf_data_file_to_profile(input_file, import_args)
+}
+
+#[cfg(target_os = "windows")]
+fn convert_etl_file_to_profile(_input_file: &File, import_args: &ImportArgs) -> Profile {
+    let profile_creation_props = import_args.profile_creation_props();
+    let included_processes = import_args.included_processes();
+    windows::import::convert_etl_file_to_profile(
+        &import_args.file,
+        &import_args.user_etl,
+        profile_creation_props,
+        included_processes,
+        import_args.two_pass,
+        import_args.regions_of_interest.as_deref(),
+    )
+}
+
+#[cfg(not(target_os = "windows"))]
+fn convert_etl_file_to_profile(_input_file: &File, import_args: &ImportArgs) -> Profile {
+    eprintln!(
+        "Error: Could not import ETW trace from file {}",
+        import_args.file.to_string_lossy()
+    );
+    eprintln!("Importing ETW traces is only supported on Windows.");
+    std::process::exit(1);
+}
+
+fn convert_chrome_trace_file_to_profile(input_file: &File, import_args: &ImportArgs) -> Profile {
+    let file_meta = input_file.metadata().ok();
+    let file_mod_time = file_meta.and_then(|metadata| metadata.modified().ok());
+    let profile_creation_props = import_args.profile_creation_props();
+    let reader = BufReader::new(input_file);
+    match import::chrome_trace::convert(reader, file_mod_time, profile_creation_props) {
+        Ok(profile) => profile,
+        Err(error) => {
+            eprintln!("Error importing Chrome trace file: {:?}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Loads one of the inputs of `samply merge`. Files which aren't processed
+/// profiles are imported as Trace Event Format files.
+fn load_merge_input(file: &Path) -> Result<serde_json::Value, String> {
+    match merge::load_profile_json(file) {
+        Ok(profile) if profile.get("meta").is_some() => return Ok(profile),
+        Err(merge::MergeError::Io(err)) => return Err(err.to_string()),
+        _ => {}
+    }
+    let input_file = File::open(file).map_err(|err| err.to_string())?;
+    let file_mod_time = input_file.metadata().and_then(|m| m.modified()).ok();
+    let profile_name = merge::label_for_profile_path(file);
+    let profile = import::chrome_trace::convert_with_profile_name(
+        BufReader::new(input_file),
+        file_mod_time,
+        &profile_name,
+    )
+    .map_err(|err| format!("Neither a profile nor a Trace Event Format file: {err}"))?;
+    serde_json::to_value(&profile).map_err(|err| err.to_string())
+}
+
+fn convert_cpuprofile_file_to_profile(input_file: &File, import_args: &ImportArgs) -> Profile {
+    let file_meta = input_file.metadata().ok();
+    let file_mod_time = file_meta.and_then(|metadata| metadata.modified().ok());
+    let file_name = import_args
+        .file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let profile_creation_props = import_args.profile_creation_props();
+    let reader = BufReader::new(input_file);
+    match import::cpuprofile::convert(reader, &file_name, file_mod_time, profile_creation_props) {
+        Ok(profile) => profile,
+        Err(error) => {
+            eprintln!("Error importing .cpuprofile file: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn convert_nettrace_file_to_profile(input_file: &File, import_args: &ImportArgs) -> Profile {
+    let file_name = import_args
+        .file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let profile_creation_props = import_args.profile_creation_props();
+    let reader = BufReader::new(input_file);
+    match import::nettrace::convert(reader, &file_name, profile_creation_props) {
+        Ok(profile) => profile,
+        Err(error) => {
+            eprintln!("Error importing .nettrace file: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn convert_massif_file_to_profile(
+    input_file: &File,
+    file_name: &str,
+    import_args: &ImportArgs,
+) -> Profile {
+    let file_meta = input_file.metadata().ok();
+    let file_mod_time = file_meta.and_then(|metadata| metadata.modified().ok());
+    let profile_creation_props = import_args.profile_creation_props();
+    let reader = BufReader::new(input_file);
+    match import::massif::convert(reader, file_name, file_mod_time, profile_creation_props) {
+        Ok(profile) => profile,
+        Err(error) => {
+            eprintln!("Error importing massif file: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn convert_heaptrack_file_to_profile(
+    input_file: &File,
+    file_name: &str,
+    import_args: &ImportArgs,
+) -> Profile {
+    let file_meta = input_file.metadata().ok();
+    let file_mod_time = file_meta.and_then(|metadata| metadata.modified().ok());
+    let profile_creation_props = import_args.profile_creation_props();
+    let reader = BufReader::new(input_file);
+    let result = if file_name.ends_with(".gz") {
+        let decoder = flate2::bufread::GzDecoder::new(reader);
+        import::heaptrack::convert(decoder, file_name, file_mod_time, profile_creation_props)
+    } else if file_name.ends_with(".zst") {
+        eprintln!("Error importing heaptrack file: zstd-compressed files are not supported.");
+        eprintln!("Please decompress the file first, for example with `zstd -d {file_name}`.");
+        std::process::exit(1);
+    } else {
+        import::heaptrack::convert(reader, file_name, file_mod_time, profile_creation_props)
+    };
+    match result {
+        Ok(profile) => profile,
+        Err(error) => {
+            eprintln!("Error importing heaptrack file: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Whether the file is the text output of macOS's `sample` or `spindump`, which
+/// doesn't have a fixed file name or extension.
+fn is_mac_sample_text_file(mut input_file: &File) -> bool {
+    let mut prefix = Vec::new();
+    let read_result = (&mut input_file).take(4096).read_to_end(&mut prefix);
+    input_file.rewind().is_ok()
+        && read_result.is_ok()
+        && import::mac_sample::is_sample_text(&prefix)
+}
+
+fn convert_mac_sample_file_to_profile(input_file: &File, import_args: &ImportArgs) -> Profile {
+    let file_meta = input_file.metadata().ok();
+    let file_mod_time = file_meta.and_then(|metadata| metadata.modified().ok());
+    let profile_creation_props = import_args.profile_creation_props();
+    let reader = BufReader::new(input_file);
+    match import::mac_sample::convert(reader, file_mod_time, profile_creation_props) {
+        Ok(profile) => profile,
+        Err(error) => {
+            eprintln!("Error importing sample or spindump file: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn convert_perf_data_file_to_profile(input_file: &File, import_args: &ImportArgs) -> Profile {
+    let path = import_args
+        .file
+        .canonicalize()
+        .expect("Couldn't form absolute path");
+    let file_meta = input_file.metadata().ok();
+    let file_mod_time = file_meta.and_then(|metadata| metadata.modified().ok());
+    let profile_creation_props = import_args.profile_creation_props();
+    let mut binary_lookup_dirs = import_args.symbol_props().symbol_dir.clone();
+    let mut aux_file_lookup_dirs = import_args.aux_file_dir.clone();
+    if let Some(parent_dir) = path.parent() {
+        binary_lookup_dirs.push(parent_dir.into());
+        aux_file_lookup_dirs.push(parent_dir.into());
+    }
+    let reader = BufReader::new(input_file);
+    match import::perf::convert(
+        reader,
+        file_mod_time,
+        binary_lookup_dirs,
+        aux_file_lookup_dirs,
+        profile_creation_props,
+    ) {
+        Ok(profile) => profile,
+        Err(error) => {
+            eprintln!("Error importing perf.data file: {:?}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verify_cli() {
+        use clap::CommandFactory;
+        Opt::command().debug_assert();
+    }
+
+    #[cfg(any(target_os = "android", target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn verify_cli_record() {
+        let opt = Opt::parse_from(["samply", "record", "rustup", "show"]);
+        assert!(
+            matches!(opt.action, Action::Record(record_args) if record_args.command == ["rustup", "show"])
+        );
+
+        let opt = Opt::parse_from(["samply", "record", "rustup", "--no-open"]);
+        assert!(
+        matches!(opt.action, Action::Record(record_args) if record_args.command == ["rustup", "--no-open"]),
+        "Arguments of the form --arg should be considered part of the command even if they match samply options."
+    );
+
+        let opt = Opt::parse_from(["samply", "record", "--no-open", "rustup"]);
+        assert!(
+            matches!(opt.action, Action::Record(record_args) if record_args.command == ["rustup"] && record_args.server_args.no_open),
+            "Arguments which come before the command name should be treated as samply arguments."
+        );
+
+        // Make sure you can't pass both a pid and a command name at the same time.
+        let opt_res = Opt::try_parse_from(["samply", "record", "-p", "1234", "rustup"]);
+        assert!(opt_res.is_err());
+
+        let opt = Opt::parse_from([
+            "samply",
+            "record",
+            "--output-dir",
+            "session",
+            "--copy-binaries",
+            "rustup",
+        ]);
+        assert!(
+            matches!(opt.action, Action::Record(record_args) if record_args.output_file() == PathBuf::from("session/profile.json.gz"))
+        );
+        let opt_res = Opt::try_parse_from(["samply", "record", "--copy-binaries", "rustup"]);
+        assert!(opt_res.is_err(), "--copy-binaries requires --output-dir.");
+        let opt_res = Opt::try_parse_from([
+            "samply",
+            "record",
+            "-o",
+            "a.json",
+            "--output-dir",
+            "b",
+            "rustup",
+        ]);
+        assert!(
+            opt_res.is_err(),
+            "--output and --output-dir can't be combined."
+        );
+    }
+
+    #[test]
+    fn import_simpleperf_jit_fixture() {
+        use fxprof_processed_profile::HotFrameLocation;
+
+        let perf_data = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../fixtures/other/simpleperf-jit/perf.data");
+        let opt = Opt::parse_from([
+            OsStr::new("samply"),
+            OsStr::new("import"),
+            perf_data.as_os_str(),
+        ]);
+        let Action::Import(import_args) = opt.action else {
+            panic!("Expected an import action");
+        };
+        let profile = import::perf::convert(
+            BufReader::new(File::open(&perf_data).unwrap()),
+            None,
+            Vec::new(),
+            Vec::new(),
+            import_args.profile_creation_props(),
+        )
+        .unwrap();
+
+        // The JIT functions get their names from the symbols in the FILE2
+        // section, including both methods of the app cache symfile.
+        let summary = profile.summary(10, 1);
+        let hottest_frames: Vec<_> = summary
+            .hottest_frames
+            .iter()
+            .map(|frame| match &frame.location {
+                HotFrameLocation::AddressInLib {
+                    lib, symbol_name, ..
+                } => (lib.name.as_str(), symbol_name.as_deref(), frame.self_weight),
+                location => panic!("Unexpected frame location {location:?}"),
+            })
+            .collect();
+        assert_eq!(
+            hottest_frames,
+            [
+                ("JIT app cache", Some("int com.example.jit.Fib.fib(int)"), 3),
+                ("JIT app cache", Some("void com.example.jit.Fib.run()"), 2),
+                ("JIT zygote cache", Some("void android.os.Looper.loop()"), 1),
+            ]
+        );
+
+        // The META_INFO section names the profile and fills in the metadata.
+        let meta = serde_json::to_value(&profile).unwrap()["meta"].take();
+        assert_eq!(meta["product"], "com.example.jit on Google Pixel 6");
+        assert_eq!(meta["oscpu"], "Android 14");
+        let simpleperf_section = meta["extra"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|section| section["label"] == "Simpleperf")
+            .unwrap();
+        let simpleperf_entry = |label: &str| {
+            simpleperf_section["entries"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|entry| entry["label"] == label)
+                .map(|entry| entry["value"].clone())
+        };
+        assert_eq!(
+            simpleperf_entry("Simpleperf version").unwrap(),
+            "1.build.11240384"
+        );
+        assert_eq!(simpleperf_entry("App type").unwrap(), "debuggable");
+    }
+}
@@ -0,0 +1,147 @@
+//! `samply export symbols`: Writes a Breakpad .sym file for every library
+//! referenced by a profile, so that the profile can be symbolicated again
+//! later without access to the original binaries or symbol servers.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use debugid::DebugId;
+use wholesym::{CodeId, LibraryInfo, SymbolManager};
+
+/// The outcome of [`export_symbols`].
+#[derive(Debug, Default)]
+pub struct ExportedSymbols {
+    /// The .sym files that were written.
+    pub written: Vec<PathBuf>,
+    /// The libraries for which no symbols could be found, with the reason.
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Loads the symbols of each library in `libinfo_map` and writes them to
+/// `output_dir`, using the `<debug_name>/<BREAKPADID>/<name>.sym` layout that
+/// `--breakpad-symbol-dir` expects.
+pub fn export_symbols(
+    symbol_manager: &SymbolManager,
+    libinfo_map: &HashMap<(String, DebugId), LibraryInfo>,
+    output_dir: &Path,
+) -> io::Result<ExportedSymbols> {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    let mut libs: Vec<_> = libinfo_map.iter().collect();
+    libs.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut result = ExportedSymbols::default();
+    for ((debug_name, debug_id), lib_info) in libs {
+        let symbol_map = match rt.block_on(symbol_manager.load_symbol_map(debug_name, *debug_id)) {
+            Ok(symbol_map) => symbol_map,
+            Err(err) => {
+                result.skipped.push((debug_name.clone(), err.to_string()));
+                continue;
+            }
+        };
+
+        let path = output_dir.join(sym_file_rel_path(debug_name, *debug_id));
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let mut w = BufWriter::new(File::create(&path)?);
+        write_sym_file(
+            &mut w,
+            debug_name,
+            *debug_id,
+            lib_info,
+            symbol_map.iter_symbols(),
+        )?;
+        w.flush()?;
+        result.written.push(path);
+    }
+    Ok(result)
+}
+
+/// The same relative path that wholesym uses when it looks for a .sym file in
+/// a Breakpad symbol directory.
+fn sym_file_rel_path(debug_name: &str, debug_id: DebugId) -> PathBuf {
+    let sym_name = format!("{}.sym", debug_name.trim_end_matches(".pdb"));
+    [debug_name, &debug_id.breakpad().to_string(), &sym_name]
+        .iter()
+        .collect()
+}
+
+/// Writes a Breakpad symbol file with a MODULE record, an INFO CODE_ID record
+/// if the code ID is known, and one PUBLIC record per symbol.
+fn write_sym_file<'a>(
+    w: &mut impl Write,
+    debug_name: &str,
+    debug_id: DebugId,
+    lib_info: &LibraryInfo,
+    symbols: impl Iterator<Item = (u32, Cow<'a, str>)>,
+) -> io::Result<()> {
+    let os = match (&lib_info.code_id, debug_name.ends_with(".pdb")) {
+        (Some(CodeId::PeCodeId(_)), _) | (None, true) => "windows",
+        (Some(CodeId::MachoUuid(_)), _) => "mac",
+        (Some(CodeId::ElfBuildId(_)), _) => "Linux",
+        (None, false) => "unknown",
+    };
+    let arch = lib_info.arch.as_deref().unwrap_or("unknown");
+    writeln!(w, "MODULE {os} {arch} {} {debug_name}", debug_id.breakpad())?;
+    if let Some(code_id) = &lib_info.code_id {
+        match &lib_info.name {
+            Some(name) => writeln!(w, "INFO CODE_ID {code_id} {name}")?,
+            None => writeln!(w, "INFO CODE_ID {code_id}")?,
+        }
+    }
+
+    let mut symbols: Vec<_> = symbols.collect();
+    symbols.sort_by_key(|(address, _)| *address);
+    symbols.dedup_by_key(|(address, _)| *address);
+    for (address, name) in symbols {
+        // Names can't span lines in the .sym format.
+        let name = name.replace(['\r', '\n'], " ");
+        writeln!(w, "PUBLIC {address:x} 0 {name}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn sym_file_contents() {
+        let debug_id = DebugId::from_breakpad("3AC1B3B1C1F1B5A0F3B84D4CE8C7C2F21").unwrap();
+        let lib_info = LibraryInfo {
+            name: Some("mylib.dll".into()),
+            arch: Some("x86_64".into()),
+            code_id: Some(CodeId::from_str("5AB380779000").unwrap()),
+            ..Default::default()
+        };
+        let symbols = vec![
+            (0x2000, Cow::Borrowed("second")),
+            (0x1000, Cow::Borrowed("first")),
+            (0x2000, Cow::Borrowed("second_alias")),
+        ];
+        let mut out = Vec::new();
+        write_sym_file(
+            &mut out,
+            "mylib.pdb",
+            debug_id,
+            &lib_info,
+            symbols.into_iter(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "MODULE windows x86_64 3AC1B3B1C1F1B5A0F3B84D4CE8C7C2F21 mylib.pdb\n\
+             INFO CODE_ID 5AB380779000 mylib.dll\n\
+             PUBLIC 1000 0 first\n\
+             PUBLIC 2000 0 second\n"
+        );
+        assert_eq!(
+            sym_file_rel_path("mylib.pdb", debug_id),
+            Path::new("mylib.pdb/3AC1B3B1C1F1B5A0F3B84D4CE8C7C2F21/mylib.sym")
+        );
+    }
+}
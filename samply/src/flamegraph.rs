@@ -0,0 +1,307 @@
+//! `samply export flamegraph`: Renders the samples of a saved profile as a
+//! self-contained flame graph, either as an SVG file or as an HTML page with
+//! the SVG inline. Clicking a frame zooms in on it, using a small script which
+//! is embedded in the SVG.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::profile_query::ProfileQuery;
+use crate::report::format_weight;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlamegraphFormat {
+    Svg,
+    Html,
+}
+
+const IMAGE_WIDTH: f64 = 1200.0;
+const PADDING: f64 = 10.0;
+const CONTENT_WIDTH: f64 = IMAGE_WIDTH - 2.0 * PADDING;
+const TITLE_HEIGHT: f64 = 40.0;
+const FRAME_HEIGHT: f64 = 16.0;
+const FONT_SIZE: f64 = 12.0;
+/// The average width of a monospace character, relative to the font size.
+const CHAR_WIDTH: f64 = 0.59;
+/// Frames narrower than this many pixels are left out, like in flamegraph.pl.
+/// Otherwise the output gets huge for profiles with many rare stacks.
+const MIN_FRAME_WIDTH: f64 = 0.1;
+
+/// A node of the merged call tree. Children are sorted by name, which is the
+/// usual order in flame graphs.
+#[derive(Debug, Default)]
+struct Node<'a> {
+    weight: f64,
+    children: BTreeMap<&'a str, Node<'a>>,
+}
+
+impl<'a> Node<'a> {
+    fn add_stack(&mut self, stack: &[&'a str], weight: f64) {
+        self.weight += weight;
+        let mut node = self;
+        for name in stack {
+            node = node.children.entry(name).or_default();
+            node.weight += weight;
+        }
+    }
+
+    /// The depth of the deepest descendant that is wide enough to be drawn.
+    fn visible_depth(&self, total_weight: f64) -> usize {
+        self.children
+            .values()
+            .filter(|child| is_visible(child, total_weight))
+            .map(|child| child.visible_depth(total_weight) + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+fn is_visible(node: &Node, total_weight: f64) -> bool {
+    node.weight / total_weight * CONTENT_WIDTH >= MIN_FRAME_WIDTH
+}
+
+/// Writes a flame graph of the samples of the given threads, merged into a
+/// single call tree.
+pub fn write_flamegraph(
+    w: &mut impl Write,
+    query: &ProfileQuery,
+    threads: &[usize],
+    title: &str,
+    format: FlamegraphFormat,
+) -> io::Result<()> {
+    let mut root = Node::default();
+    query.for_each_sample_stack(threads, |stack, weight| root.add_stack(stack, weight));
+    write_tree(w, &root, title, format)
+}
+
+fn write_tree(
+    w: &mut impl Write,
+    root: &Node,
+    title: &str,
+    format: FlamegraphFormat,
+) -> io::Result<()> {
+    let total_weight = root.weight;
+    let max_depth = if total_weight > 0.0 {
+        root.visible_depth(total_weight)
+    } else {
+        0
+    };
+    let height = TITLE_HEIGHT + (max_depth + 1) as f64 * FRAME_HEIGHT + 2.0 * PADDING + FONT_SIZE;
+    let title = xml_escape(title);
+
+    match format {
+        FlamegraphFormat::Svg => writeln!(w, r#"<?xml version="1.0" standalone="no"?>"#)?,
+        FlamegraphFormat::Html => {
+            writeln!(w, "<!DOCTYPE html>")?;
+            writeln!(
+                w,
+                r#"<html><head><meta charset="utf-8"><title>{title}</title></head>"#
+            )?;
+            writeln!(w, r#"<body style="margin: 0">"#)?;
+        }
+    }
+    writeln!(
+        w,
+        r#"<svg version="1.1" width="{IMAGE_WIDTH}" height="{height}" viewBox="0 0 {IMAGE_WIDTH} {height}" xmlns="http://www.w3.org/2000/svg">"#
+    )?;
+    writeln!(
+        w,
+        "<style>text {{ font-family: monospace; font-size: {FONT_SIZE}px; }} g.f {{ cursor: pointer; }} g.f text {{ pointer-events: none; }} #title {{ font-size: 17px; }} #reset {{ cursor: pointer; }}</style>"
+    )?;
+    writeln!(w, r##"<rect width="100%" height="100%" fill="#f8f8f8"/>"##)?;
+    writeln!(
+        w,
+        r#"<text id="title" x="{}" y="24" text-anchor="middle">{title}</text>"#,
+        IMAGE_WIDTH / 2.0
+    )?;
+    writeln!(
+        w,
+        r#"<text id="reset" x="{PADDING}" y="24">Reset zoom</text>"#
+    )?;
+    let details = if total_weight > 0.0 {
+        " "
+    } else {
+        "No samples"
+    };
+    writeln!(
+        w,
+        r#"<text id="details" x="{PADDING}" y="{}">{details}</text>"#,
+        height - PADDING
+    )?;
+    if total_weight > 0.0 {
+        let mut frame_writer = FrameWriter {
+            w,
+            total_weight,
+            max_depth,
+        };
+        frame_writer.write_frame("all", root, 0.0, 0)?;
+    }
+    writeln!(w, "<script><![CDATA[{}]]></script>", zoom_script())?;
+    writeln!(w, "</svg>")?;
+    if format == FlamegraphFormat::Html {
+        writeln!(w, "</body></html>")?;
+    }
+    Ok(())
+}
+
+struct FrameWriter<'w, W: Write> {
+    w: &'w mut W,
+    total_weight: f64,
+    max_depth: usize,
+}
+
+impl<W: Write> FrameWriter<'_, W> {
+    /// Writes the frame for `node`, which starts at `x0` (as a fraction of
+    /// the total width), followed by the frames of its descendants.
+    fn write_frame(&mut self, name: &str, node: &Node, x0: f64, depth: usize) -> io::Result<()> {
+        let fraction = node.weight / self.total_weight;
+        let x1 = x0 + fraction;
+        let left = PADDING + x0 * CONTENT_WIDTH;
+        let width = fraction * CONTENT_WIDTH;
+        let y = TITLE_HEIGHT + (self.max_depth - depth) as f64 * FRAME_HEIGHT;
+        let (r, g, b) = frame_color(name);
+        let escaped_name = xml_escape(name);
+        writeln!(
+            self.w,
+            r#"<g class="f" data-n="{escaped_name}" data-x0="{x0:.6}" data-x1="{x1:.6}" data-d="{depth}"><title>{escaped_name} ({}, {:.2}%)</title><rect x="{left:.2}" y="{y}" width="{width:.2}" height="{}" rx="2" fill="rgb({r},{g},{b})"/><text x="{:.2}" y="{}">{}</text></g>"#,
+            format_weight(node.weight),
+            fraction * 100.0,
+            FRAME_HEIGHT - 1.0,
+            left + 3.0,
+            y + FRAME_HEIGHT - 4.5,
+            xml_escape(&fit_text(name, width)),
+        )?;
+
+        let mut child_x0 = x0;
+        for (child_name, child) in &node.children {
+            if is_visible(child, self.total_weight) {
+                self.write_frame(child_name, child, child_x0, depth + 1)?;
+            }
+            child_x0 += child.weight / self.total_weight;
+        }
+        Ok(())
+    }
+}
+
+/// Truncates the name to what fits into a frame of the given width. Keep in
+/// sync with `fit` in the zoom script.
+fn fit_text(name: &str, width: f64) -> String {
+    let max_chars = ((width - 6.0) / (FONT_SIZE * CHAR_WIDTH)).floor();
+    if max_chars < 3.0 {
+        return String::new();
+    }
+    let max_chars = max_chars as usize;
+    if name.chars().count() <= max_chars {
+        name.to_string()
+    } else {
+        let mut truncated: String = name.chars().take(max_chars - 2).collect();
+        truncated.push_str("..");
+        truncated
+    }
+}
+
+/// A warm color which only depends on the name, so that the same function
+/// has the same color everywhere in the graph.
+fn frame_color(name: &str) -> (u8, u8, u8) {
+    // FNV-1a
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    let r = 205 + (hash % 50) as u8;
+    let g = ((hash >> 8) % 230) as u8;
+    let b = ((hash >> 16) % 55) as u8;
+    (r, g, b)
+}
+
+fn zoom_script() -> String {
+    format!(
+        r#"
+const frames = Array.from(document.querySelectorAll("g.f"));
+const details = document.getElementById("details");
+function fit(name, width) {{
+  const maxChars = Math.floor((width - 6) / {char_width});
+  if (maxChars < 3) return "";
+  return name.length <= maxChars ? name : name.slice(0, maxChars - 2) + "..";
+}}
+function zoom(start, end, depth) {{
+  for (const f of frames) {{
+    const x0 = +f.dataset.x0, x1 = +f.dataset.x1;
+    if (x1 <= start || x0 >= end) {{
+      f.style.display = "none";
+      continue;
+    }}
+    f.style.display = "";
+    const left = (Math.max(x0, start) - start) / (end - start) * {CONTENT_WIDTH};
+    const right = (Math.min(x1, end) - start) / (end - start) * {CONTENT_WIDTH};
+    const rect = f.querySelector("rect"), text = f.querySelector("text");
+    rect.setAttribute("x", {PADDING} + left);
+    rect.setAttribute("width", right - left);
+    text.setAttribute("x", {PADDING} + left + 3);
+    text.textContent = fit(f.dataset.n, right - left);
+    f.style.opacity = +f.dataset.d < depth ? 0.5 : 1;
+  }}
+}}
+for (const f of frames) {{
+  f.addEventListener("click", () => zoom(+f.dataset.x0, +f.dataset.x1, +f.dataset.d));
+  f.addEventListener("mouseover", () => details.textContent = f.querySelector("title").textContent);
+  f.addEventListener("mouseout", () => details.textContent = " ");
+}}
+document.getElementById("reset").addEventListener("click", () => zoom(0, 1, 0));
+"#,
+        char_width = FONT_SIZE * CHAR_WIDTH,
+    )
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flamegraph_svg() {
+        let mut root = Node::default();
+        root.add_stack(&["main", "work"], 1.0);
+        root.add_stack(&["main", "work", "Vec<T>::push"], 2.0);
+        root.add_stack(&["main"], 1.0);
+        assert_eq!(root.weight, 4.0);
+        assert_eq!(root.children["main"].children["work"].weight, 3.0);
+        assert_eq!(root.visible_depth(root.weight), 3);
+
+        let mut out = Vec::new();
+        write_tree(&mut out, &root, "Test & more", FlamegraphFormat::Svg).unwrap();
+        let svg = String::from_utf8(out).unwrap();
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.contains(">Test &amp; more</text>"));
+        assert!(svg.contains(r#"data-n="main" data-x0="0.000000" data-x1="1.000000" data-d="1""#));
+        assert!(svg.contains("<title>Vec&lt;T&gt;::push (2, 50.00%)</title>"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+
+        let mut out = Vec::new();
+        write_tree(&mut out, &Node::default(), "Empty", FlamegraphFormat::Html).unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("No samples"));
+        assert!(!html.contains(r#"class="f""#));
+    }
+
+    #[test]
+    fn text_fitting() {
+        assert_eq!(fit_text("main", 100.0), "main");
+        assert_eq!(fit_text("a_long_function_name", 60.0), "a_lon..");
+        assert_eq!(fit_text("main", 10.0), "");
+    }
+}
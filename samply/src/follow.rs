@@ -0,0 +1,391 @@
+//! Recording on other machines at the same time as on this one, for
+//! `samply record --follow ssh://host`.
+//!
+//! samply is started on each other machine over ssh, with `samply record
+//! --save-only`. Before that, the offset between the clocks of the two
+//! machines is measured with a few round trips to `samply clock-sync` on the
+//! other machine, the same way NTP does it. Once the local recording has
+//! finished, the remote recordings are stopped with a Ctrl+C through the ssh
+//! session's terminal, and their profiles are copied back with scp and merged
+//! into the local profile.
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::merge::{self, MergeError, MergeInput};
+
+/// How many round trips are used to measure the clock offset. The one with
+/// the shortest round trip time gives the most accurate offset.
+const CLOCK_SYNC_ROUND_TRIPS: usize = 10;
+
+/// A machine to record on, from a `ssh://[user@]host[:port][/path/to/samply]` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FollowTarget {
+    /// The ssh destination, `host` or `user@host`.
+    destination: String,
+    port: Option<u16>,
+    /// The samply executable on the other machine.
+    samply_path: String,
+}
+
+impl FollowTarget {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("ssh://")
+            .ok_or_else(|| format!("Expected an ssh:// URL, got {url:?}"))?;
+        let (authority, samply_path) = match rest.find('/') {
+            Some(slash) if slash + 1 < rest.len() => (&rest[..slash], &rest[slash..]),
+            Some(slash) => (&rest[..slash], "samply"),
+            None => (rest, "samply"),
+        };
+        let (host_part, user) = match authority.rsplit_once('@') {
+            Some((user, host)) => (host, Some(user)),
+            None => (authority, None),
+        };
+        let (host, port) = match host_part.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("Invalid port {port:?} in {url:?}"))?;
+                (host, Some(port))
+            }
+            None => (host_part, None),
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host name in {url:?}"));
+        }
+        let destination = match user {
+            Some(user) => format!("{user}@{host}"),
+            None => host.to_string(),
+        };
+        Ok(Self {
+            destination,
+            port,
+            samply_path: samply_path.to_string(),
+        })
+    }
+
+    /// The host name, for labeling the machine's processes in the merged profile.
+    pub fn host(&self) -> &str {
+        match self.destination.rsplit_once('@') {
+            Some((_user, host)) => host,
+            None => &self.destination,
+        }
+    }
+
+    fn ssh_command(&self, options: &[&str]) -> Command {
+        let mut command = Command::new("ssh");
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        command.args(options).arg(&self.destination);
+        command
+    }
+
+    fn scp_command(&self, remote_path: &str, local_path: &Path) -> Command {
+        let mut command = Command::new("scp");
+        command.arg("-q");
+        if let Some(port) = self.port {
+            command.arg("-P").arg(port.to_string());
+        }
+        command.arg(format!("{}:{remote_path}", self.destination));
+        command.arg(local_path);
+        command
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FollowError {
+    #[error("Could not run ssh for {0}: {1}")]
+    Ssh(String, std::io::Error),
+
+    #[error("Could not measure the clock offset of {0}: {1}")]
+    ClockSync(String, String),
+
+    #[error("Could not copy the profile from {0}")]
+    Copy(String),
+
+    #[error("Could not merge the profiles: {0}")]
+    Merge(#[from] MergeError),
+
+    #[error("Could not save the merged profile: {0}")]
+    Save(std::io::Error),
+}
+
+/// One round trip of the clock synchronization, in nanoseconds since the
+/// Unix epoch on the respective machine's clock.
+#[derive(Debug, Clone, Copy)]
+pub struct ClockSample {
+    pub local_send_ns: u128,
+    pub remote_ns: u128,
+    pub local_receive_ns: u128,
+}
+
+/// Estimates how far the remote clock is ahead of the local clock, in
+/// milliseconds, assuming that the remote clock was read halfway through
+/// the round trip.
+pub fn estimate_clock_offset(samples: &[ClockSample]) -> Option<f64> {
+    let best = samples
+        .iter()
+        .min_by_key(|sample| sample.local_receive_ns.saturating_sub(sample.local_send_ns))?;
+    let local_midpoint_ns = (best.local_send_ns + best.local_receive_ns) / 2;
+    let offset_ns = best.remote_ns as i128 - local_midpoint_ns as i128;
+    Some(offset_ns as f64 / 1_000_000.0)
+}
+
+fn now_ns() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// The other end of the clock synchronization: Prints the current time for
+/// every line that is read from stdin, until stdin is closed.
+pub fn run_clock_sync_responder() {
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout().lock();
+    for line in stdin.lock().lines() {
+        if line.is_err() {
+            break;
+        }
+        if writeln!(stdout, "{}", now_ns())
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+fn measure_clock_offset(target: &FollowTarget) -> Result<f64, FollowError> {
+    let host = || target.host().to_string();
+    let mut child = target
+        .ssh_command(&[])
+        .arg(&target.samply_path)
+        .arg("clock-sync")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| FollowError::Ssh(host(), err))?;
+    let mut stdin = child.stdin.take().expect("stdin was piped");
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout was piped"));
+
+    let mut samples = Vec::with_capacity(CLOCK_SYNC_ROUND_TRIPS);
+    let mut line = String::new();
+    for _ in 0..CLOCK_SYNC_ROUND_TRIPS {
+        let local_send_ns = now_ns();
+        if writeln!(stdin).and_then(|_| stdin.flush()).is_err() {
+            break;
+        }
+        line.clear();
+        match stdout.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let local_receive_ns = now_ns();
+        let remote_ns = line.trim().parse().map_err(|_| {
+            FollowError::ClockSync(host(), format!("unexpected response {:?}", line.trim()))
+        })?;
+        samples.push(ClockSample {
+            local_send_ns,
+            remote_ns,
+            local_receive_ns,
+        });
+    }
+    drop(stdin);
+    let _ = child.wait();
+
+    estimate_clock_offset(&samples).ok_or_else(|| {
+        FollowError::ClockSync(
+            host(),
+            format!("no response from `{} clock-sync`", target.samply_path),
+        )
+    })
+}
+
+/// A `samply record` which is running on another machine.
+pub struct RemoteRecording {
+    target: FollowTarget,
+    ssh: Child,
+    ssh_stdin: ChildStdin,
+    remote_path: String,
+    clock_offset_ms: f64,
+}
+
+impl RemoteRecording {
+    /// Measures the clock offset of the target machine and starts recording
+    /// there, with the given `samply record` arguments.
+    pub fn start(target: &FollowTarget, record_args: &str) -> Result<Self, FollowError> {
+        let clock_offset_ms = measure_clock_offset(target)?;
+        eprintln!(
+            "The clock of {} is {clock_offset_ms:.3}ms ahead of the local clock.",
+            target.host()
+        );
+
+        let remote_path = format!("samply-follow-{}.json.gz", std::process::id());
+        // Allocate a terminal on the other machine, so that writing a Ctrl+C
+        // to the session interrupts the recording there.
+        let mut command = target.ssh_command(&["-tt"]);
+        command
+            .arg(format!(
+                "{} record --save-only -o {remote_path} {record_args}",
+                target.samply_path
+            ))
+            .stdin(Stdio::piped());
+        // Keep a Ctrl+C in this terminal from killing the ssh sessions, so that
+        // the remote recordings can be stopped and saved properly.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        #[cfg(windows)]
+        {
+            const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+            std::os::windows::process::CommandExt::creation_flags(
+                &mut command,
+                CREATE_NEW_PROCESS_GROUP,
+            );
+        }
+        let mut ssh = command
+            .spawn()
+            .map_err(|err| FollowError::Ssh(target.host().to_string(), err))?;
+        let ssh_stdin = ssh.stdin.take().expect("stdin was piped");
+        Ok(Self {
+            target: target.clone(),
+            ssh,
+            ssh_stdin,
+            remote_path,
+            clock_offset_ms,
+        })
+    }
+
+    /// Stops the remote recording, waits for it to be saved, and copies the
+    /// profile to `local_path`.
+    pub fn finish(mut self, local_path: &Path) -> Result<MergeInput, FollowError> {
+        let host = self.target.host().to_string();
+        // Ctrl+C. If the recording has already ended on its own, the session
+        // is gone and this fails, which is fine.
+        let _ = self
+            .ssh_stdin
+            .write_all(b"\x03")
+            .and_then(|_| self.ssh_stdin.flush());
+        drop(self.ssh_stdin);
+        let _ = self.ssh.wait();
+
+        let copied = self
+            .target
+            .scp_command(&self.remote_path, local_path)
+            .status()
+            .map_err(|err| FollowError::Ssh(host.clone(), err))?;
+        if !copied.success() {
+            return Err(FollowError::Copy(host));
+        }
+        let _ = self
+            .target
+            .ssh_command(&[])
+            .arg("rm")
+            .arg("-f")
+            .arg(&self.remote_path)
+            .status();
+
+        let profile = merge::load_profile_json(local_path)?;
+        let _ = std::fs::remove_file(local_path);
+        Ok(MergeInput {
+            profile,
+            label: Some(host),
+            clock_offset_ms: self.clock_offset_ms,
+        })
+    }
+}
+
+/// Starts recording on all targets. If one of them fails, the recordings
+/// which were already started are stopped again.
+pub fn start_remote_recordings(
+    targets: &[FollowTarget],
+    record_args: &str,
+) -> Result<Vec<RemoteRecording>, FollowError> {
+    let mut recordings = Vec::with_capacity(targets.len());
+    for target in targets {
+        match RemoteRecording::start(target, record_args) {
+            Ok(recording) => recordings.push(recording),
+            Err(err) => {
+                for mut recording in recordings {
+                    let _ = recording.ssh.kill();
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(recordings)
+}
+
+/// Stops the remote recordings and merges their profiles into the local
+/// profile at `profile_path`.
+pub fn merge_remote_recordings(
+    recordings: Vec<RemoteRecording>,
+    profile_path: &Path,
+) -> Result<(), FollowError> {
+    let mut inputs = vec![MergeInput {
+        profile: merge::load_profile_json(profile_path)?,
+        label: None,
+        clock_offset_ms: 0.0,
+    }];
+    for recording in recordings {
+        let host = recording.target.host().to_string();
+        let mut local_path = PathBuf::from(profile_path);
+        local_path.set_file_name(format!("samply-follow-{host}.json.gz"));
+        match recording.finish(&local_path) {
+            Ok(input) => inputs.push(input),
+            Err(err) => eprintln!("Skipping the profile from {host}: {err}"),
+        }
+    }
+    let merged = merge::merge_profiles(inputs)?;
+    crate::shared::save_profile::save_profile_to_file(&merged, profile_path)
+        .map_err(FollowError::Save)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_follow_targets() {
+        assert_eq!(
+            FollowTarget::parse("ssh://host2"),
+            Ok(FollowTarget {
+                destination: "host2".to_string(),
+                port: None,
+                samply_path: "samply".to_string(),
+            })
+        );
+        let target = FollowTarget::parse("ssh://me@host2:2222/home/me/.cargo/bin/samply").unwrap();
+        assert_eq!(target.destination, "me@host2");
+        assert_eq!(target.port, Some(2222));
+        assert_eq!(target.samply_path, "/home/me/.cargo/bin/samply");
+        assert_eq!(target.host(), "host2");
+        assert!(FollowTarget::parse("host2").is_err());
+        assert!(FollowTarget::parse("ssh://host2:port").is_err());
+        assert!(FollowTarget::parse("ssh://me@").is_err());
+    }
+
+    #[test]
+    fn clock_offset_uses_shortest_round_trip() {
+        let samples = [
+            // Slow round trip, e.g. while the connection was being set up.
+            ClockSample {
+                local_send_ns: 1_000_000_000,
+                remote_ns: 1_550_000_000,
+                local_receive_ns: 1_300_000_000,
+            },
+            // The remote clock is 500ms ahead.
+            ClockSample {
+                local_send_ns: 2_000_000_000,
+                remote_ns: 2_501_000_000,
+                local_receive_ns: 2_002_000_000,
+            },
+        ];
+        assert_eq!(estimate_clock_offset(&samples), Some(500.0));
+        assert_eq!(estimate_clock_offset(&[]), None);
+    }
+}
@@ -0,0 +1,549 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fxprof_processed_profile::{
+    CategoryHandle, CounterHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation,
+    MarkerSchema, MarkerStaticField, MarkerTiming, ProcessHandle, Profile, ReferenceTimestamp,
+    SamplingInterval, StaticSchemaMarker, StringHandle, ThreadHandle, Timestamp,
+};
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::shared::recording_props::ProfileCreationProps;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O Error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A trace in the Trace Event Format, as documented at
+/// <https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU>.
+///
+/// The file can either be a bare array of events, or an object with a
+/// `traceEvents` property.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TraceFile {
+    Array(Vec<TraceEvent>),
+    Object {
+        #[serde(rename = "traceEvents")]
+        trace_events: Vec<TraceEvent>,
+        #[serde(default)]
+        metadata: Map<String, Value>,
+    },
+}
+
+#[derive(Deserialize)]
+struct TraceEvent {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    cat: String,
+    ph: String,
+    ts: Option<f64>,
+    dur: Option<f64>,
+    #[serde(default)]
+    pid: Value,
+    #[serde(default)]
+    tid: Value,
+    id: Option<Value>,
+    #[serde(default)]
+    args: Map<String, Value>,
+}
+
+pub fn convert<R: Read>(
+    reader: R,
+    file_mod_time: Option<SystemTime>,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, Error> {
+    convert_with_profile_name(reader, file_mod_time, profile_creation_props.profile_name())
+}
+
+/// Like [`convert`], for callers which don't have [`ProfileCreationProps`],
+/// e.g. `samply merge`.
+pub fn convert_with_profile_name<R: Read>(
+    mut reader: R,
+    file_mod_time: Option<SystemTime>,
+    profile_name: &str,
+) -> Result<Profile, Error> {
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents)?;
+    let (mut events, metadata) = match parse_trace_events(&contents)? {
+        TraceFile::Array(events) => (events, Map::new()),
+        TraceFile::Object {
+            trace_events,
+            metadata,
+        } => (trace_events, metadata),
+    };
+
+    // Only metadata events may leave out the timestamp.
+    events.retain(|e| e.ts.is_some() || e.ph == "M");
+
+    // Events are not required to be sorted. A stable sort keeps the order of
+    // B / E pairs which have the same timestamp.
+    events.sort_by(|a, b| {
+        let a = a.ts.unwrap_or(0.0);
+        let b = b.ts.unwrap_or(0.0);
+        a.total_cmp(&b)
+    });
+
+    let timed_events = events.iter().filter(|e| e.ph != "M");
+    let reference_ts_us = timed_events.clone().find_map(|e| e.ts).unwrap_or(0.0);
+    let last_ts_us = timed_events
+        .filter_map(|e| Some(e.ts? + e.dur.unwrap_or(0.0)))
+        .fold(reference_ts_us, f64::max);
+
+    let reference_timestamp =
+        reference_timestamp(reference_ts_us, last_ts_us, &metadata, file_mod_time);
+    let profile = Profile::new(
+        profile_name,
+        reference_timestamp,
+        SamplingInterval::from_millis(1),
+    );
+    let mut converter = Converter::new(profile, reference_ts_us);
+    for event in &events {
+        converter.handle_event(event);
+    }
+    Ok(converter.finish())
+}
+
+/// The first timestamped event is time zero of the profile, so that's what the
+/// reference timestamp needs to describe. Traces don't have a standard way of
+/// saying when they were recorded, so we go by the first of these which works:
+///
+///  - Some producers use microseconds since the Unix epoch as their clock.
+///  - DevTools traces store when the recording started in `metadata.startTime`.
+///  - Otherwise, we assume that the file was written right after the last
+///    event, and go back from its modification time by the trace duration.
+///
+/// This wall-clock time is what `samply merge` uses to line up the trace with
+/// other profiles, so the estimates are only as good as the clocks involved.
+fn reference_timestamp(
+    first_ts_us: f64,
+    last_ts_us: f64,
+    metadata: &Map<String, Value>,
+    file_mod_time: Option<SystemTime>,
+) -> ReferenceTimestamp {
+    // 2000-01-01 to 2100-01-01. A monotonic clock would need decades of uptime
+    // to get there.
+    const PLAUSIBLE_EPOCH_US: std::ops::Range<f64> = 946_684_800e6..4_102_444_800e6;
+    if PLAUSIBLE_EPOCH_US.contains(&first_ts_us) {
+        return ReferenceTimestamp::from_millis_since_unix_epoch(first_ts_us / 1000.0);
+    }
+    if let Some(start_time) = metadata
+        .get("startTime")
+        .and_then(Value::as_str)
+        .and_then(|s| humantime::parse_rfc3339_weak(s).ok())
+    {
+        return ReferenceTimestamp::from_system_time(start_time);
+    }
+    match file_mod_time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        Some(mod_time) => {
+            let duration_ms = (last_ts_us - first_ts_us) / 1000.0;
+            ReferenceTimestamp::from_millis_since_unix_epoch(
+                mod_time.as_secs_f64() * 1000.0 - duration_ms,
+            )
+        }
+        None => ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+    }
+}
+
+/// Chrome doesn't always write the closing bracket of the event array, and the
+/// format explicitly allows for that, so that traces can be streamed.
+fn parse_trace_events(contents: &[u8]) -> Result<TraceFile, serde_json::Error> {
+    match serde_json::from_slice(contents) {
+        Ok(trace_file) => Ok(trace_file),
+        Err(err) => {
+            let trimmed = trim_ascii_whitespace(contents);
+            if !trimmed.starts_with(b"[") || trimmed.ends_with(b"]") {
+                return Err(err);
+            }
+            let mut fixed = trimmed.strip_suffix(b",").unwrap_or(trimmed).to_vec();
+            fixed.push(b']');
+            serde_json::from_slice(&fixed).map_err(|_| err)
+        }
+    }
+}
+
+fn trim_ascii_whitespace(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |pos| pos + 1);
+    &bytes[start..end]
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OpenDuration {
+    name: StringHandle,
+    category: StringHandle,
+    args: StringHandle,
+    start: Timestamp,
+}
+
+struct Converter {
+    profile: Profile,
+    reference_ts_us: f64,
+    processes: HashMap<u32, ProcessHandle>,
+    threads: HashMap<(u32, u32), ThreadHandle>,
+    /// Stacks of B events which haven't seen their E event yet, per thread.
+    open_durations: HashMap<ThreadHandle, Vec<OpenDuration>>,
+    /// Async b events which haven't seen their e event yet, keyed by
+    /// (pid, cat, id, name).
+    open_async: HashMap<(u32, String, String, String), (ThreadHandle, OpenDuration)>,
+    /// Counters and their most recent value, keyed by (pid, counter name).
+    counters: HashMap<(u32, String), (CounterHandle, f64)>,
+}
+
+impl Converter {
+    fn new(profile: Profile, reference_ts_us: f64) -> Self {
+        Self {
+            profile,
+            reference_ts_us,
+            processes: HashMap::new(),
+            threads: HashMap::new(),
+            open_durations: HashMap::new(),
+            open_async: HashMap::new(),
+            counters: HashMap::new(),
+        }
+    }
+
+    fn convert_time(&self, ts_us: f64) -> Timestamp {
+        let nanos = (ts_us - self.reference_ts_us) * 1000.0;
+        Timestamp::from_nanos_since_reference(nanos.max(0.0) as u64)
+    }
+
+    fn process(&mut self, pid: u32, start_time: Timestamp) -> ProcessHandle {
+        *self.processes.entry(pid).or_insert_with(|| {
+            self.profile
+                .add_process(&format!("Process {pid}"), pid, start_time)
+        })
+    }
+
+    fn thread(&mut self, pid: u32, tid: u32, start_time: Timestamp) -> ThreadHandle {
+        if let Some(thread) = self.threads.get(&(pid, tid)) {
+            return *thread;
+        }
+        let process = self.process(pid, start_time);
+        let thread = self
+            .profile
+            .add_thread(process, tid, start_time, tid == pid);
+        self.threads.insert((pid, tid), thread);
+        thread
+    }
+
+    fn handle_event(&mut self, event: &TraceEvent) {
+        let pid = id_to_u32(&event.pid);
+        let tid = id_to_u32(&event.tid);
+        // Metadata events are the only ones without a timestamp.
+        let ts_us = event.ts.unwrap_or(self.reference_ts_us);
+        let timestamp = self.convert_time(ts_us);
+
+        match event.ph.as_str() {
+            "M" => self.handle_metadata(event, pid, tid, timestamp),
+            "X" => {
+                let thread = self.thread(pid, tid, timestamp);
+                let end = self.convert_time(ts_us + event.dur.unwrap_or(0.0));
+                let marker = self.make_marker(event);
+                self.profile
+                    .add_marker(thread, MarkerTiming::Interval(timestamp, end), marker);
+            }
+            "B" => {
+                let thread = self.thread(pid, tid, timestamp);
+                let open = self.open_duration(event, timestamp);
+                self.open_durations.entry(thread).or_default().push(open);
+            }
+            "E" => {
+                let thread = self.thread(pid, tid, timestamp);
+                let Some(open) = self
+                    .open_durations
+                    .get_mut(&thread)
+                    .and_then(|stack| stack.pop())
+                else {
+                    return;
+                };
+                self.add_duration_marker(
+                    thread,
+                    open,
+                    MarkerTiming::Interval(open.start, timestamp),
+                );
+            }
+            "b" => {
+                let thread = self.thread(pid, tid, timestamp);
+                let open = self.open_duration(event, timestamp);
+                let key = self.async_key(event, pid);
+                self.open_async.insert(key, (thread, open));
+            }
+            "e" => {
+                let key = self.async_key(event, pid);
+                if let Some((thread, open)) = self.open_async.remove(&key) {
+                    self.add_duration_marker(
+                        thread,
+                        open,
+                        MarkerTiming::Interval(open.start, timestamp),
+                    );
+                }
+            }
+            "i" | "I" | "n" | "R" => {
+                let thread = self.thread(pid, tid, timestamp);
+                let marker = self.make_marker(event);
+                self.profile
+                    .add_marker(thread, MarkerTiming::Instant(timestamp), marker);
+            }
+            "C" => self.handle_counter(event, pid, timestamp),
+            _ => {
+                // Flow events, object snapshots, samples etc. are not supported.
+            }
+        }
+    }
+
+    fn handle_metadata(&mut self, event: &TraceEvent, pid: u32, tid: u32, timestamp: Timestamp) {
+        let Some(name) = event.args.get("name").and_then(Value::as_str) else {
+            return;
+        };
+        match event.name.as_str() {
+            "process_name" => {
+                let process = self.process(pid, timestamp);
+                self.profile.set_process_name(process, name);
+            }
+            "thread_name" => {
+                let thread = self.thread(pid, tid, timestamp);
+                self.profile.set_thread_name(thread, name);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_counter(&mut self, event: &TraceEvent, pid: u32, timestamp: Timestamp) {
+        let process = self.process(pid, timestamp);
+        for (series, value) in &event.args {
+            let Some(value) = value.as_f64() else {
+                continue;
+            };
+            let counter_name = if event.args.len() == 1 {
+                event.name.clone()
+            } else {
+                format!("{} {series}", event.name)
+            };
+            let (counter, last_value) = self
+                .counters
+                .entry((pid, counter_name))
+                .or_insert_with_key(|(_, counter_name)| {
+                    let counter = self.profile.add_counter(
+                        process,
+                        counter_name,
+                        if event.cat.is_empty() {
+                            "Trace"
+                        } else {
+                            &event.cat
+                        },
+                        "Imported from a trace event counter",
+                    );
+                    (counter, 0.0)
+                });
+            self.profile
+                .add_counter_sample(*counter, timestamp, value - *last_value, 1);
+            *last_value = value;
+        }
+    }
+
+    fn async_key(&self, event: &TraceEvent, pid: u32) -> (u32, String, String, String) {
+        let id = match &event.id {
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+            None => String::new(),
+        };
+        (pid, event.cat.clone(), id, event.name.clone())
+    }
+
+    fn open_duration(&mut self, event: &TraceEvent, start: Timestamp) -> OpenDuration {
+        let marker = self.make_marker(event);
+        OpenDuration {
+            name: marker.name,
+            category: marker.category,
+            args: marker.args,
+            start,
+        }
+    }
+
+    fn add_duration_marker(
+        &mut self,
+        thread: ThreadHandle,
+        open: OpenDuration,
+        timing: MarkerTiming,
+    ) {
+        let marker = TraceEventMarker {
+            name: open.name,
+            category: open.category,
+            args: open.args,
+        };
+        self.profile.add_marker(thread, timing, marker);
+    }
+
+    fn make_marker(&mut self, event: &TraceEvent) -> TraceEventMarker {
+        let args = if event.args.is_empty() {
+            String::new()
+        } else {
+            Value::Object(event.args.clone()).to_string()
+        };
+        TraceEventMarker {
+            name: self.profile.intern_string(&event.name),
+            category: self.profile.intern_string(&event.cat),
+            args: self.profile.intern_string(&args),
+        }
+    }
+
+    fn finish(mut self) -> Profile {
+        // Durations which never saw their end event extend to the end of the profile.
+        for (thread, stack) in std::mem::take(&mut self.open_durations) {
+            for open in stack {
+                self.add_duration_marker(thread, open, MarkerTiming::IntervalStart(open.start));
+            }
+        }
+        for (_, (thread, open)) in std::mem::take(&mut self.open_async) {
+            self.add_duration_marker(thread, open, MarkerTiming::IntervalStart(open.start));
+        }
+        self.profile
+    }
+}
+
+/// pids and tids are usually numbers, but some producers use strings.
+fn id_to_u32(id: &Value) -> u32 {
+    match id {
+        Value::Number(n) => n.as_u64().unwrap_or(0) as u32,
+        Value::String(s) => s.parse().unwrap_or_else(|_| {
+            // Derive a stable number from the string.
+            s.bytes()
+                .fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32))
+        }),
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceEventMarker {
+    name: StringHandle,
+    category: StringHandle,
+    args: StringHandle,
+}
+
+impl StaticSchemaMarker for TraceEventMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "TraceEvent";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.name}".into()),
+            tooltip_label: Some("{marker.name}".into()),
+            table_label: Some("{marker.name} ({marker.data.cat}) {marker.data.args}".into()),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "cat".into(),
+                    label: "Category".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "args".into(),
+                    label: "Arguments".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "Imported from a Trace Event Format file.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        CategoryHandle::OTHER
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.category,
+            1 => self.args,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_unterminated_array() {
+        let json = br#"[{"name":"a","ph":"B","ts":1,"pid":1,"tid":1},
+            {"name":"a","ph":"E","ts":2,"pid":1,"tid":1},
+        "#;
+        let TraceFile::Array(events) = parse_trace_events(json).unwrap() else {
+            panic!("expected an array");
+        };
+        assert_eq!(events.len(), 2);
+
+        let json = br#"{"traceEvents":[{"name":"c","ph":"C","ts":1,"pid":"1","args":{"v":3}}]}"#;
+        let TraceFile::Object { trace_events, .. } = parse_trace_events(json).unwrap() else {
+            panic!("expected an object");
+        };
+        assert_eq!(id_to_u32(&trace_events[0].pid), 1);
+    }
+
+    #[test]
+    fn test_events_without_ts_are_skipped() {
+        let json = br#"[{"name":"thread_name","ph":"M","pid":1,"tid":2,"args":{"name":"Main"}},
+            {"name":"a","ph":"X","ts":1000,"dur":500,"pid":1,"tid":2},
+            {"name":"b","ph":"X","dur":500,"pid":1,"tid":2}]"#;
+        let profile = convert_with_profile_name(&json[..], None, "test").unwrap();
+        let json = serde_json::to_value(&profile).unwrap();
+        let thread = &json["threads"][0];
+        assert_eq!(thread["name"], "Main");
+        assert_eq!(thread["markers"]["length"], 1);
+        assert_eq!(thread["markers"]["startTime"][0], 0.0);
+        assert_eq!(thread["markers"]["endTime"][0], 0.5);
+    }
+
+    #[test]
+    fn test_reference_timestamp() {
+        let no_metadata = Map::new();
+        let epoch_us = 1_700_000_000_000_000.0;
+        assert_eq!(
+            reference_timestamp(epoch_us, epoch_us + 5e6, &no_metadata, None),
+            ReferenceTimestamp::from_millis_since_unix_epoch(1_700_000_000_000.0)
+        );
+
+        let mut devtools_metadata = Map::new();
+        devtools_metadata.insert("startTime".into(), "2023-11-14T22:13:20Z".into());
+        assert_eq!(
+            reference_timestamp(5e6, 10e6, &devtools_metadata, None),
+            ReferenceTimestamp::from_millis_since_unix_epoch(1_700_000_000_000.0)
+        );
+
+        // The file was written 5 seconds after the first event.
+        let mod_time = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_005);
+        assert_eq!(
+            reference_timestamp(5e6, 10e6, &no_metadata, Some(mod_time)),
+            ReferenceTimestamp::from_millis_since_unix_epoch(1_700_000_000_000.0)
+        );
+    }
+}
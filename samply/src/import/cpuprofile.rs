@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::time::SystemTime;
+
+use fxprof_processed_profile::{
+    CategoryColor, CategoryPairHandle, CpuDelta, Frame, FrameFlags, FrameInfo, Profile,
+    ReferenceTimestamp, SamplingInterval, Timestamp,
+};
+use serde_derive::Deserialize;
+
+use crate::shared::recording_props::ProfileCreationProps;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O Error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Sample refers to unknown node {0}")]
+    UnknownNode(u64),
+}
+
+/// A CPU profile in the format of the V8 inspector protocol's `Profiler.Profile`,
+/// as saved by the Chrome DevTools and by Node's `--cpu-prof`.
+///
+/// All times are in microseconds. `timeDeltas[i]` is the time between sample
+/// `i - 1` (or `startTime`) and sample `i`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CpuProfile {
+    nodes: Vec<Node>,
+    #[serde(default)]
+    samples: Vec<u64>,
+    #[serde(default)]
+    time_deltas: Vec<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Node {
+    id: u64,
+    call_frame: CallFrame,
+    /// Older profiles list the children of each node, newer ones the parent.
+    #[serde(default)]
+    children: Vec<u64>,
+    parent: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallFrame {
+    #[serde(default)]
+    function_name: String,
+    #[serde(default)]
+    url: String,
+    /// Zero-based, -1 if unknown.
+    #[serde(default = "unknown_position")]
+    line_number: i64,
+    /// Zero-based, -1 if unknown.
+    #[serde(default = "unknown_position")]
+    column_number: i64,
+}
+
+fn unknown_position() -> i64 {
+    -1
+}
+
+pub fn convert<R: Read>(
+    reader: R,
+    file_name: &str,
+    file_mod_time: Option<SystemTime>,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, Error> {
+    let cpu_profile: CpuProfile = serde_json::from_reader(reader)?;
+
+    // The profile's startTime is in V8's monotonic clock, which is unrelated
+    // to wall-clock time, so the samples are placed relative to the file's
+    // modification time instead, and start at zero.
+    let reference_timestamp = match file_mod_time {
+        Some(mod_time) => ReferenceTimestamp::from_system_time(mod_time),
+        None => ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+    };
+    build_profile(
+        &cpu_profile,
+        profile_creation_props.profile_name(),
+        file_name,
+        reference_timestamp,
+    )
+}
+
+fn build_profile(
+    cpu_profile: &CpuProfile,
+    profile_name: &str,
+    file_name: &str,
+    reference_timestamp: ReferenceTimestamp,
+) -> Result<Profile, Error> {
+    let mut profile = Profile::new(
+        profile_name,
+        reference_timestamp,
+        sampling_interval(&cpu_profile.time_deltas),
+    );
+    let pid = pid_from_file_name(file_name);
+    let process_name = if file_name.starts_with("CPU.") {
+        "node"
+    } else {
+        file_name.strip_suffix(".cpuprofile").unwrap_or(file_name)
+    };
+    let start_time = Timestamp::from_nanos_since_reference(0);
+    let process = profile.add_process(process_name, pid, start_time);
+    let thread = profile.add_thread(process, pid, start_time, true);
+    profile.set_thread_name(thread, "JavaScript");
+
+    let categories = Categories {
+        javascript: profile
+            .add_category("JavaScript", CategoryColor::Yellow)
+            .into(),
+        gc: profile
+            .add_category("GC / CC", CategoryColor::Orange)
+            .into(),
+        idle: profile
+            .add_category("Idle", CategoryColor::Transparent)
+            .into(),
+        other: profile.add_category("Other", CategoryColor::Gray).into(),
+    };
+
+    let mut parents: HashMap<u64, u64> = HashMap::new();
+    for node in &cpu_profile.nodes {
+        if let Some(parent) = node.parent {
+            parents.insert(node.id, parent);
+        }
+        for child in &node.children {
+            parents.insert(*child, node.id);
+        }
+    }
+    let nodes: HashMap<u64, &Node> = cpu_profile.nodes.iter().map(|n| (n.id, n)).collect();
+
+    let mut frame_infos: HashMap<u64, Option<FrameInfo>> = HashMap::new();
+    let mut time_us = 0.0;
+    let mut stack = Vec::new();
+    for (sample_index, node_id) in cpu_profile.samples.iter().enumerate() {
+        let delta_us = cpu_profile
+            .time_deltas
+            .get(sample_index)
+            .copied()
+            .unwrap_or(0.0);
+        time_us += delta_us;
+
+        // Walk up to the root, then reverse, to get the stack from the root.
+        stack.clear();
+        let mut current = Some(*node_id);
+        let mut is_idle = false;
+        while let Some(id) = current {
+            let node = nodes.get(&id).ok_or(Error::UnknownNode(id))?;
+            if node.call_frame.function_name == "(idle)" {
+                is_idle = true;
+            }
+            let frame_info = frame_infos.entry(id).or_insert_with(|| {
+                frame_info_for_call_frame(&node.call_frame, &categories, &mut profile)
+            });
+            if let Some(frame_info) = frame_info {
+                stack.push(frame_info.clone());
+            }
+            current = parents.get(&id).copied();
+            if stack.len() > nodes.len() {
+                // The parent links have a cycle.
+                break;
+            }
+        }
+        stack.reverse();
+
+        let timestamp = Timestamp::from_nanos_since_reference((time_us.max(0.0) * 1000.0) as u64);
+        let cpu_delta = if is_idle {
+            CpuDelta::ZERO
+        } else {
+            CpuDelta::from_micros(delta_us.max(0.0) as u64)
+        };
+        profile.add_sample(thread, timestamp, stack.iter().cloned(), cpu_delta, 1);
+    }
+
+    Ok(profile)
+}
+
+struct Categories {
+    javascript: CategoryPairHandle,
+    gc: CategoryPairHandle,
+    idle: CategoryPairHandle,
+    other: CategoryPairHandle,
+}
+
+/// Returns the frame for a call frame, or None for the `(root)` node, which
+/// isn't a real frame.
+fn frame_info_for_call_frame(
+    call_frame: &CallFrame,
+    categories: &Categories,
+    profile: &mut Profile,
+) -> Option<FrameInfo> {
+    let (name, category_pair, flags) = match call_frame.function_name.as_str() {
+        "(root)" => return None,
+        "(idle)" => ("(idle)".to_string(), categories.idle, FrameFlags::empty()),
+        "(garbage collector)" => (
+            "(garbage collector)".to_string(),
+            categories.gc,
+            FrameFlags::empty(),
+        ),
+        // "(program)" is native code in the engine or the embedder.
+        name if name.starts_with('(') && call_frame.url.is_empty() => {
+            (name.to_string(), categories.other, FrameFlags::empty())
+        }
+        name => (
+            js_frame_name(name, call_frame),
+            categories.javascript,
+            FrameFlags::IS_JS,
+        ),
+    };
+    Some(FrameInfo {
+        frame: Frame::Label(profile.intern_string(&name)),
+        category_pair,
+        flags,
+    })
+}
+
+/// E.g. `render app.js:12:5`, with one-based line and column numbers.
+fn js_frame_name(function_name: &str, call_frame: &CallFrame) -> String {
+    let function_name = if function_name.is_empty() {
+        "(anonymous)"
+    } else {
+        function_name
+    };
+    if call_frame.url.is_empty() {
+        return function_name.to_string();
+    }
+    match (call_frame.line_number, call_frame.column_number) {
+        (line, column) if line >= 0 && column >= 0 => {
+            format!(
+                "{function_name} {}:{}:{}",
+                call_frame.url,
+                line + 1,
+                column + 1
+            )
+        }
+        (line, _) if line >= 0 => format!("{function_name} {}:{}", call_frame.url, line + 1),
+        _ => format!("{function_name} {}", call_frame.url),
+    }
+}
+
+/// The median of the time deltas, which is close to the configured interval
+/// even if some samples were delayed.
+fn sampling_interval(time_deltas: &[f64]) -> SamplingInterval {
+    let mut deltas: Vec<f64> = time_deltas.iter().copied().filter(|d| *d > 0.0).collect();
+    if deltas.is_empty() {
+        return SamplingInterval::from_millis(1);
+    }
+    deltas.sort_by(f64::total_cmp);
+    let median_us = deltas[deltas.len() / 2];
+    SamplingInterval::from_nanos((median_us * 1000.0) as u64)
+}
+
+/// Node names its profiles `CPU.<date>.<time>.<pid>.<thread id>.<sequence>.cpuprofile`.
+fn pid_from_file_name(file_name: &str) -> u32 {
+    if !file_name.starts_with("CPU.") {
+        return 0;
+    }
+    file_name
+        .split('.')
+        .nth(3)
+        .and_then(|pid| pid.parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn test_convert_cpuprofile() {
+        let json = br#"{
+            "nodes": [
+                {"id": 1, "callFrame": {"functionName": "(root)", "url": "", "lineNumber": -1, "columnNumber": -1}, "children": [2, 4]},
+                {"id": 2, "callFrame": {"functionName": "main", "url": "file:///app.js", "lineNumber": 0, "columnNumber": 0}, "children": [3]},
+                {"id": 3, "callFrame": {"functionName": "", "url": "file:///app.js", "lineNumber": 9, "columnNumber": 4}},
+                {"id": 4, "callFrame": {"functionName": "(garbage collector)", "url": "", "lineNumber": -1, "columnNumber": -1}, "parent": 1}
+            ],
+            "startTime": 1000,
+            "endTime": 1400,
+            "samples": [2, 3, 3, 4],
+            "timeDeltas": [100, 100, 100, 100]
+        }"#;
+        let cpu_profile: CpuProfile = serde_json::from_slice(json).unwrap();
+        let profile = build_profile(
+            &cpu_profile,
+            "test",
+            "CPU.20240101.120000.4321.0.001.cpuprofile",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+        )
+        .unwrap();
+        let profile: Value = serde_json::to_value(&profile).unwrap();
+
+        let thread = &profile["threads"][0];
+        assert_eq!(thread["pid"], "4321");
+        assert_eq!(thread["processName"], "node");
+        assert_eq!(thread["samples"]["length"], 4);
+        assert_eq!(thread["samples"]["time"][3], 0.4);
+        let strings = thread["stringArray"].as_array().unwrap();
+        assert!(strings.contains(&Value::from("main file:///app.js:1:1")));
+        assert!(strings.contains(&Value::from("(anonymous) file:///app.js:10:5")));
+        assert!(!strings.contains(&Value::from("(root)")));
+        assert_eq!(profile["meta"]["interval"], 0.1);
+    }
+}
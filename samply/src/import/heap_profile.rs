@@ -0,0 +1,102 @@
+use fxprof_processed_profile::{
+    CategoryColor, CategoryPairHandle, CounterHandle, Frame, FrameFlags, FrameInfo, Profile,
+    ReferenceTimestamp, SamplingInterval, ThreadHandle, Timestamp,
+};
+
+/// Builds a profile from the allocations in a memory profile, for the
+/// heaptrack and massif importers.
+///
+/// Each allocation becomes an allocation sample whose stack consists of label
+/// frames with the function names, so that the call tree can be weighted by
+/// bytes. The total heap size is shown in a memory counter.
+pub struct HeapProfileBuilder {
+    profile: Profile,
+    thread: ThreadHandle,
+    category: CategoryPairHandle,
+    heap_size_counter: CounterHandle,
+    heap_size: i64,
+}
+
+impl HeapProfileBuilder {
+    pub fn new(
+        profile_name: &str,
+        reference_timestamp: ReferenceTimestamp,
+        process_name: &str,
+        pid: u32,
+    ) -> Self {
+        let mut profile = Profile::new(
+            profile_name,
+            reference_timestamp,
+            SamplingInterval::from_millis(1),
+        );
+        let start_time = Timestamp::from_nanos_since_reference(0);
+        let process = profile.add_process(process_name, pid, start_time);
+        let thread = profile.add_thread(process, pid, start_time, true);
+        profile.set_thread_name(thread, process_name);
+        let category = profile.add_category("Heap", CategoryColor::Orange).into();
+        let heap_size_counter =
+            profile.add_counter(process, "malloc", "Memory", "Amount of allocated memory");
+        Self {
+            profile,
+            thread,
+            category,
+            heap_size_counter,
+            heap_size: 0,
+        }
+    }
+
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        &mut self.profile
+    }
+
+    /// Adds an allocation (positive size) or deallocation (negative size) with
+    /// the given stack, ordered from the root to the allocating function.
+    ///
+    /// The address is only used to match deallocations with allocations.
+    pub fn add_allocation<'a>(
+        &mut self,
+        timestamp: Timestamp,
+        stack: impl Iterator<Item = &'a str>,
+        address: u64,
+        size: i64,
+    ) {
+        let frames: Vec<FrameInfo> = stack
+            .map(|function_name| FrameInfo {
+                frame: Frame::Label(self.profile.intern_string(function_name)),
+                category_pair: self.category,
+                flags: FrameFlags::empty(),
+            })
+            .collect();
+        self.profile.add_allocation_sample(
+            self.thread,
+            timestamp,
+            frames.into_iter(),
+            address,
+            size,
+        );
+    }
+
+    /// Updates the heap size counter.
+    pub fn set_heap_size(&mut self, timestamp: Timestamp, heap_size: i64) {
+        self.profile.add_counter_sample(
+            self.heap_size_counter,
+            timestamp,
+            (heap_size - self.heap_size) as f64,
+            1,
+        );
+        self.heap_size = heap_size;
+    }
+
+    pub fn finish(self) -> Profile {
+        self.profile
+    }
+}
+
+/// Extracts the pid from file names like "massif.out.1234" or
+/// "heaptrack.myprogram.1234.gz".
+pub fn pid_from_file_name(file_name: &str) -> u32 {
+    file_name
+        .rsplit('.')
+        .find_map(|part| part.parse().ok())
+        .unwrap_or(0)
+}
@@ -0,0 +1,198 @@
+use std::io::{BufRead, BufReader, Read};
+use std::time::SystemTime;
+
+use fxprof_processed_profile::{Profile, ReferenceTimestamp, Timestamp};
+use wholesym::samply_symbols::demangle_any;
+
+use super::heap_profile::{pid_from_file_name, HeapProfileBuilder};
+use crate::shared::recording_props::ProfileCreationProps;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O Error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid heaptrack file, line {0}: {1}")]
+    Format(usize, String),
+}
+
+/// An instruction pointer with its symbolicated function names: the function
+/// itself, followed by the functions which were inlined into it at this
+/// address.
+#[derive(Debug, Default)]
+struct InstructionPointer {
+    function_names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TraceNode {
+    ip_index: usize,
+    parent_index: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AllocationInfo {
+    size: u64,
+    trace_index: usize,
+}
+
+/// Converts a heaptrack data file (heaptrack.<program>.<pid>), after
+/// decompression.
+///
+/// Every allocation and deallocation becomes an allocation sample. Heaptrack
+/// doesn't record the addresses of allocations, so we use the index of the
+/// allocation's (size, stack) pair instead; this is enough to match each
+/// deallocation with an allocation of the same size and stack.
+pub fn convert<R: Read>(
+    reader: R,
+    file_name: &str,
+    file_mod_time: Option<SystemTime>,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, Error> {
+    let reference_timestamp = match file_mod_time {
+        Some(mod_time) => ReferenceTimestamp::from_system_time(mod_time),
+        None => ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+    };
+    let mut builder: Option<HeapProfileBuilder> = None;
+    let new_builder = |command: Option<&str>| {
+        let process_name = command
+            .and_then(|command| command.split_whitespace().next())
+            .map(|program| program.rsplit('/').next().unwrap_or(program))
+            .unwrap_or("heaptrack");
+        HeapProfileBuilder::new(
+            profile_creation_props.profile_name(),
+            reference_timestamp,
+            process_name,
+            pid_from_file_name(file_name),
+        )
+    };
+
+    // All indexes in the file are 1-based, and 0 means "none".
+    let mut strings: Vec<String> = vec![String::new()];
+    let mut ips: Vec<InstructionPointer> = vec![InstructionPointer::default()];
+    let mut traces: Vec<TraceNode> = vec![TraceNode {
+        ip_index: 0,
+        parent_index: 0,
+    }];
+    let mut allocation_infos: Vec<AllocationInfo> = vec![AllocationInfo {
+        size: 0,
+        trace_index: 0,
+    }];
+    let mut file_format_version = 0;
+    let mut timestamp = Timestamp::from_millis_since_reference(0.0);
+    let mut heap_size: i64 = 0;
+
+    for (line_index, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let format_error = |message: &str| Error::Format(line_index + 1, message.to_string());
+        let Some(kind) = line.get(..1) else {
+            continue;
+        };
+        let rest = line[1..].trim_start();
+        let mut numbers = rest.split(' ').map(|n| usize::from_str_radix(n, 16));
+        let mut next_number = || {
+            numbers
+                .next()
+                .and_then(Result::ok)
+                .ok_or_else(|| format_error("expected a hex number"))
+        };
+        match kind {
+            "v" => {
+                next_number()?; // heaptrack version
+                file_format_version = next_number().unwrap_or(0);
+            }
+            "X" => {
+                builder.get_or_insert_with(|| new_builder(Some(rest)));
+            }
+            "s" => {
+                // Since file format version 3, strings are prefixed with their
+                // length, so that they can contain newlines.
+                let string = if file_format_version >= 3 {
+                    rest.split_once(' ').map_or("", |(_, string)| string)
+                } else {
+                    rest
+                };
+                strings.push(string.to_string());
+            }
+            "t" => {
+                let ip_index = next_number()?;
+                let parent_index = next_number()?;
+                traces.push(TraceNode {
+                    ip_index,
+                    parent_index,
+                });
+            }
+            "i" => {
+                let ip = next_number()?;
+                let module_index = next_number()?;
+                let mut function_names = Vec::new();
+                // Then (function, file, line) for the function and for each
+                // inlined function.
+                while let Ok(function_index) = next_number() {
+                    let _file_index = next_number();
+                    let _line = next_number();
+                    let name = strings.get(function_index).map_or("", String::as_str);
+                    if !name.is_empty() {
+                        function_names.push(demangle_any(name));
+                    }
+                }
+                if function_names.is_empty() {
+                    let module = strings.get(module_index).map_or("", String::as_str);
+                    let module = module.rsplit('/').next().unwrap_or(module);
+                    function_names.push(format!("0x{ip:x} ({module})"));
+                }
+                ips.push(InstructionPointer { function_names });
+            }
+            "a" => {
+                let size = next_number()? as u64;
+                let trace_index = next_number()?;
+                allocation_infos.push(AllocationInfo { size, trace_index });
+            }
+            "+" | "-" => {
+                let info_index = next_number()?;
+                let info = *allocation_infos
+                    .get(info_index)
+                    .ok_or_else(|| format_error("unknown allocation index"))?;
+                let builder = builder.get_or_insert_with(|| new_builder(None));
+
+                let mut stack: Vec<&str> = Vec::new();
+                let mut trace_index = info.trace_index;
+                while trace_index != 0 {
+                    let Some(node) = traces.get(trace_index) else {
+                        return Err(format_error("unknown trace index"));
+                    };
+                    if let Some(ip) = ips.get(node.ip_index) {
+                        stack.extend(ip.function_names.iter().rev().map(String::as_str));
+                    }
+                    if node.parent_index >= trace_index {
+                        return Err(format_error("trace parent doesn't come first"));
+                    }
+                    trace_index = node.parent_index;
+                }
+
+                let size = if kind == "+" {
+                    info.size as i64
+                } else {
+                    -(info.size as i64)
+                };
+                heap_size += size;
+                builder.add_allocation(
+                    timestamp,
+                    stack.iter().rev().copied(),
+                    info_index as u64,
+                    size,
+                );
+            }
+            "c" => {
+                let builder = builder.get_or_insert_with(|| new_builder(None));
+                builder.set_heap_size(timestamp, heap_size);
+                timestamp = Timestamp::from_millis_since_reference(next_number()? as f64);
+            }
+            _ => {}
+        }
+    }
+
+    let mut builder = builder.unwrap_or_else(|| new_builder(None));
+    builder.set_heap_size(timestamp, heap_size);
+    Ok(builder.finish())
+}
@@ -0,0 +1,503 @@
+use std::io::Read;
+use std::time::SystemTime;
+
+use fxprof_processed_profile::{
+    CategoryColor, CategoryHandle, CategoryPairHandle, CpuDelta, Frame, FrameFlags, FrameInfo,
+    Profile, ReferenceTimestamp, SamplingInterval, Timestamp,
+};
+
+use crate::shared::recording_props::ProfileCreationProps;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O Error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("The file has no call graph with any threads")]
+    NoThreads,
+}
+
+#[derive(Debug, Default)]
+struct ParsedProcess {
+    name: String,
+    pid: u32,
+    threads: Vec<ParsedThread>,
+}
+
+#[derive(Debug)]
+struct ParsedThread {
+    name: String,
+    tid: u32,
+    nodes: Vec<CallTreeNode>,
+}
+
+/// A node of a thread's call tree. `count` is the number of samples whose
+/// stack went through this node, including the samples of its children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CallTreeNode {
+    parent: Option<usize>,
+    count: u64,
+    function: String,
+    is_kernel: bool,
+}
+
+/// The processes and threads of a `sample` or `spindump` report.
+#[derive(Debug, Default)]
+struct Report {
+    processes: Vec<ParsedProcess>,
+    interval_nanos: Option<u64>,
+    is_spindump: bool,
+}
+
+/// Whether `prefix`, the start of a file, looks like the text output of macOS's
+/// `sample` or `spindump` tools.
+pub fn is_sample_text(prefix: &[u8]) -> bool {
+    let prefix = String::from_utf8_lossy(prefix);
+    prefix.contains("Analysis of sampling ")
+        || (prefix.lines().any(|line| line.starts_with("Data Source:"))
+            && prefix.lines().any(|line| line.starts_with("Steps:")))
+}
+
+/// Converts the text output of macOS's `sample` and `spindump` tools, which is
+/// often the only thing users can get from a customer's machine.
+///
+/// Both tools only print an aggregated call tree per thread, with the number
+/// of samples in each node. Each node with samples of its own becomes one
+/// sample, weighted by that number, and the samples are laid out one after the
+/// other in call tree order. The call tree is accurate, but the timeline
+/// isn't.
+pub fn convert<R: Read>(
+    mut reader: R,
+    file_mod_time: Option<SystemTime>,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, Error> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+    let report = parse_report(&contents);
+    if report.processes.iter().all(|p| p.threads.is_empty()) {
+        return Err(Error::NoThreads);
+    }
+
+    let reference_timestamp = match file_mod_time {
+        Some(mod_time) => ReferenceTimestamp::from_system_time(mod_time),
+        None => ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+    };
+    Ok(build_profile(
+        &report,
+        profile_creation_props.profile_name(),
+        reference_timestamp,
+    ))
+}
+
+fn parse_report(text: &str) -> Report {
+    let mut report = Report::default();
+    // The call tree nodes of the current thread which can still get children,
+    // with the column of their sample count.
+    let mut open_nodes: Vec<(usize, usize)> = Vec::new();
+    let mut in_thread = false;
+    for line in text.lines() {
+        if let Some(process) = line.strip_prefix("Process:") {
+            report.processes.push(parse_process(process));
+            in_thread = false;
+            continue;
+        }
+        if let Some(analysis) = line.strip_prefix("Analysis of sampling ") {
+            // "Analysis of sampling Foo (pid 1234) every 1 millisecond"
+            report.interval_nanos = analysis
+                .rsplit_once(" every ")
+                .and_then(|(_, interval)| parse_interval(interval));
+            continue;
+        }
+        if let Some(steps) = line.strip_prefix("Steps:") {
+            // "Steps:            1000 (10ms sampling interval)"
+            report.interval_nanos = steps
+                .split_once('(')
+                .and_then(|(_, interval)| interval.strip_suffix(" sampling interval)"))
+                .and_then(parse_interval);
+            continue;
+        }
+        if line.starts_with("Data Source:") {
+            report.is_spindump = true;
+            continue;
+        }
+        if [
+            "Total number in stack",
+            "Sort by top of stack",
+            "Binary Images:",
+            "Heaviest stack",
+        ]
+        .iter()
+        .any(|prefix| line.trim_start().starts_with(prefix))
+        {
+            in_thread = false;
+            continue;
+        }
+        if let Some(thread) = line.trim_start().strip_prefix("Thread 0x") {
+            // spindump: `Thread 0x1a2b    DispatchQueue "com.apple.main-thread"(1)    1000 samples`
+            let tid_end = thread.find(char::is_whitespace).unwrap_or(thread.len());
+            let tid = u32::from_str_radix(&thread[..tid_end], 16).unwrap_or(0);
+            let name = quoted_after(thread, "Thread name \"")
+                .or_else(|| quoted_after(thread, "DispatchQueue \""))
+                .map(ToString::to_string)
+                .unwrap_or_else(|| format!("Thread 0x{}", &thread[..tid_end]));
+            start_thread(&mut report, name, tid);
+            open_nodes.clear();
+            in_thread = true;
+            continue;
+        }
+        let Some((column, count, is_kernel, frame)) = parse_stack_line(line) else {
+            continue;
+        };
+        if let Some(thread) = frame.strip_prefix("Thread_") {
+            // sample: `2503 Thread_4051907   DispatchQueue_1: com.apple.main-thread  (serial)`
+            let tid_end = thread
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(thread.len());
+            let tid = thread[..tid_end].parse().unwrap_or(0);
+            let description = thread[tid_end..].trim_start_matches(':').trim();
+            let description = match description.split_once(": ") {
+                Some((queue, name)) if queue.starts_with("DispatchQueue") => name,
+                _ => description,
+            };
+            let name = match description.split_once("  (") {
+                Some((name, _)) => name.trim(),
+                None => description,
+            };
+            let name = match name {
+                "" => format!("Thread_{tid}"),
+                name => name.to_string(),
+            };
+            start_thread(&mut report, name, tid);
+            open_nodes.clear();
+            in_thread = true;
+            continue;
+        }
+        if !in_thread {
+            continue;
+        }
+        let thread = report
+            .processes
+            .last_mut()
+            .and_then(|process| process.threads.last_mut())
+            .expect("in_thread is only set after a thread was added");
+        while open_nodes.last().is_some_and(|&(c, _)| c >= column) {
+            open_nodes.pop();
+        }
+        let node = CallTreeNode {
+            parent: open_nodes.last().map(|&(_, node)| node),
+            count,
+            function: function_name(frame),
+            is_kernel,
+        };
+        open_nodes.push((column, thread.nodes.len()));
+        thread.nodes.push(node);
+    }
+    report
+}
+
+/// Parses "Foo [1234]" from a "Process:" line.
+fn parse_process(text: &str) -> ParsedProcess {
+    let text = text.trim();
+    let (name, pid) = match text.rsplit_once('[') {
+        Some((name, pid)) => (name.trim(), pid.split(']').next().unwrap_or("")),
+        None => (text, ""),
+    };
+    ParsedProcess {
+        name: name.to_string(),
+        pid: pid.parse().unwrap_or(0),
+        threads: Vec::new(),
+    }
+}
+
+fn start_thread(report: &mut Report, name: String, tid: u32) {
+    if report.processes.is_empty() {
+        report.processes.push(ParsedProcess {
+            name: "Unknown".to_string(),
+            ..Default::default()
+        });
+    }
+    let process = report.processes.last_mut().unwrap();
+    process.threads.push(ParsedThread {
+        name,
+        tid,
+        nodes: Vec::new(),
+    });
+}
+
+/// Parses "1 millisecond", "10ms" or "100 microseconds" into nanoseconds.
+fn parse_interval(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let unit_start = text.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let value: f64 = text[..unit_start].parse().ok()?;
+    let nanos_per_unit = match text[unit_start..].trim() {
+        "ms" | "millisecond" | "milliseconds" => 1_000_000.0,
+        "us" | "µs" | "microsecond" | "microseconds" => 1_000.0,
+        _ => return None,
+    };
+    Some((value * nanos_per_unit) as u64).filter(|&nanos| nanos != 0)
+}
+
+fn quoted_after<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let (_, rest) = text.split_once(prefix)?;
+    rest.split('"').next()
+}
+
+/// Parses a call tree line into the column of the sample count, the count,
+/// whether it's a kernel frame, and the frame description.
+///
+/// ```text
+///     +   2503 main  (in Foo) + 123  [0x100003f00]
+///       *10  ??? (kernel.release.t6000 + 123) [0xfffffe0008a1b2c4]
+/// ```
+///
+/// `sample` draws the tree with `+`, `!`, `:` and `|`, and `spindump` marks
+/// kernel frames with a `*`.
+fn parse_stack_line(line: &str) -> Option<(usize, u64, bool, &str)> {
+    let column = line.find(|c| !matches!(c, ' ' | '+' | '!' | ':' | '|'))?;
+    let rest = &line[column..];
+    let (is_kernel, rest) = match rest.strip_prefix('*') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let count_end = rest.find(|c: char| !c.is_ascii_digit())?;
+    let count = rest[..count_end].parse().ok()?;
+    let frame = &rest[count_end..];
+    if !frame.starts_with(char::is_whitespace) || frame.trim().is_empty() {
+        return None;
+    }
+    Some((column, count, is_kernel, frame.trim()))
+}
+
+/// Returns the function name of a frame, or "??? (in library)" for frames
+/// without a symbol.
+///
+/// ```text
+/// sample:   main  (in Foo) + 123  [0x100003f00]  main.c:12
+/// spindump: main + 123 (Foo + 16128) [0x100003f00]
+/// ```
+fn function_name(frame: &str) -> String {
+    let frame = match frame.split_once(" [0x") {
+        Some((frame, _)) => frame,
+        None => frame,
+    };
+    let (function, library) = if let Some((function, rest)) = frame.split_once("  (in ") {
+        (function, rest.split(')').next())
+    } else if let Some((function, rest)) = frame.rsplit_once(" (") {
+        let library = rest.split([')', '+']).next().map(str::trim);
+        let function = match function.rsplit_once(" + ") {
+            Some((name, offset)) if offset.bytes().all(|b| b.is_ascii_digit()) => name,
+            _ => function,
+        };
+        (function, library)
+    } else {
+        (frame, None)
+    };
+    match (function.trim(), library) {
+        ("???", Some(library)) => format!("??? (in {library})"),
+        (function, _) => function.to_string(),
+    }
+}
+
+fn build_profile(
+    report: &Report,
+    profile_name: &str,
+    reference_timestamp: ReferenceTimestamp,
+) -> Profile {
+    let default_interval_nanos = if report.is_spindump {
+        10_000_000
+    } else {
+        1_000_000
+    };
+    let interval_nanos = report.interval_nanos.unwrap_or(default_interval_nanos);
+    let mut profile = Profile::new(
+        profile_name,
+        reference_timestamp,
+        SamplingInterval::from_nanos(interval_nanos),
+    );
+    let tool = if report.is_spindump {
+        "spindump"
+    } else {
+        "sample"
+    };
+    profile.add_extra_meta_info(
+        "Import",
+        "Call tree",
+        &format!("Aggregated by {tool}. The order of the samples in the timeline is made up."),
+    );
+    let user_category: CategoryPairHandle = CategoryHandle::OTHER.into();
+    let kernel_category: CategoryPairHandle =
+        profile.add_category("Kernel", CategoryColor::Orange).into();
+
+    let start_time = Timestamp::from_nanos_since_reference(0);
+    for parsed_process in &report.processes {
+        if parsed_process.threads.is_empty() {
+            continue;
+        }
+        let process = profile.add_process(&parsed_process.name, parsed_process.pid, start_time);
+        for (thread_index, parsed_thread) in parsed_process.threads.iter().enumerate() {
+            let thread =
+                profile.add_thread(process, parsed_thread.tid, start_time, thread_index == 0);
+            profile.set_thread_name(thread, &parsed_thread.name);
+
+            let nodes = &parsed_thread.nodes;
+            let frames: Vec<FrameInfo> = nodes
+                .iter()
+                .map(|node| FrameInfo {
+                    frame: Frame::Label(profile.intern_string(&node.function)),
+                    category_pair: if node.is_kernel {
+                        kernel_category
+                    } else {
+                        user_category
+                    },
+                    flags: FrameFlags::empty(),
+                })
+                .collect();
+            let mut self_counts: Vec<u64> = nodes.iter().map(|node| node.count).collect();
+            for node in nodes {
+                if let Some(parent) = node.parent {
+                    self_counts[parent] = self_counts[parent].saturating_sub(node.count);
+                }
+            }
+
+            let mut time_nanos = 0;
+            let mut stack = Vec::new();
+            for (node_index, &self_count) in self_counts.iter().enumerate() {
+                if self_count == 0 {
+                    continue;
+                }
+                stack.clear();
+                let mut current = Some(node_index);
+                while let Some(index) = current {
+                    stack.push(frames[index].clone());
+                    current = nodes[index].parent;
+                }
+                stack.reverse();
+                let duration_nanos = self_count * interval_nanos;
+                profile.add_sample(
+                    thread,
+                    Timestamp::from_nanos_since_reference(time_nanos),
+                    stack.iter().cloned(),
+                    CpuDelta::from_nanos(duration_nanos),
+                    self_count.min(i32::MAX as u64) as i32,
+                );
+                time_nanos += duration_nanos;
+            }
+        }
+    }
+    profile
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE_OUTPUT: &str = "Analysis of sampling Foo (pid 1234) every 1 millisecond
+Process:         Foo [1234]
+Path:            /Applications/Foo.app/Contents/MacOS/Foo
+
+Call graph:
+    10 Thread_4051907   DispatchQueue_1: com.apple.main-thread  (serial)
+    + 10 start  (in dyld) + 1903  [0x1a4e0e0e0]
+    +   10 main  (in Foo) + 123  [0x100003f00]  main.c:12
+    +     7 foo  (in Foo) + 12  [0x100004000]
+    +     ! 7 ???  (in libbar.dylib)  load address 0x104000000 + 0x1234  [0x104001234]
+    +     2 bar  (in Foo) + 8  [0x100004100]
+    3 Thread_4051908
+    + 3 thread_start  (in libsystem_pthread.dylib) + 8  [0x1a5000000]
+
+Total number in stack (recursive counted multiple times):
+        10       main  (in Foo) + 123  [0x100003f00]
+";
+
+    const SPINDUMP_OUTPUT: &str = "Date/Time:        2024-01-01 12:00:00.000 +0100
+Data Source:      Stackshots
+Steps:            100 (10ms sampling interval)
+
+Process:          Foo [1234]
+Heaviest stack for the main thread of the target process:
+  100  start + 1903 (dyld + 24800) [0x1a4e0e0e0]
+
+  Thread 0x3de0a5    DispatchQueue \"com.apple.main-thread\"(1)    100 samples (1-100)
+  100  start + 1903 (dyld + 24800) [0x1a4e0e0e0]
+    100  main + 123 (Foo + 16128) [0x100003f00]
+      60  mach_msg_trap + 8 (libsystem_kernel.dylib + 3000) [0x1a5000bb8]
+       *60  ??? (kernel.release.t6000 + 123) [0xfffffe0008a1b2c4]
+
+Binary Images:
+       0x100000000 -        0x100007fff  Foo <UUID> /Applications/Foo.app/Contents/MacOS/Foo
+";
+
+    #[test]
+    fn parse_sample() {
+        assert!(is_sample_text(SAMPLE_OUTPUT.as_bytes()));
+        let report = parse_report(SAMPLE_OUTPUT);
+        assert_eq!(report.interval_nanos, Some(1_000_000));
+        assert!(!report.is_spindump);
+        let process = &report.processes[0];
+        assert_eq!((process.name.as_str(), process.pid), ("Foo", 1234));
+        let threads: Vec<_> = process
+            .threads
+            .iter()
+            .map(|t| (t.name.as_str(), t.tid, t.nodes.len()))
+            .collect();
+        assert_eq!(
+            threads,
+            [
+                ("com.apple.main-thread", 4051907, 5),
+                ("Thread_4051908", 4051908, 1)
+            ]
+        );
+        let nodes = &process.threads[0].nodes;
+        let functions: Vec<_> = nodes.iter().map(|n| n.function.as_str()).collect();
+        assert_eq!(
+            functions,
+            ["start", "main", "foo", "??? (in libbar.dylib)", "bar"]
+        );
+        let parents: Vec<_> = nodes.iter().map(|n| n.parent).collect();
+        assert_eq!(parents, [None, Some(0), Some(1), Some(2), Some(1)]);
+
+        let profile = build_profile(
+            &report,
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+        );
+        let json = serde_json::to_value(&profile).unwrap();
+        let samples = &json["threads"][0]["samples"];
+        // main has one sample of its own.
+        assert_eq!(samples["weight"], serde_json::json!([1, 7, 2]));
+        assert_eq!(samples["time"], serde_json::json!([0.0, 1.0, 8.0]));
+    }
+
+    #[test]
+    fn parse_spindump() {
+        assert!(is_sample_text(SPINDUMP_OUTPUT.as_bytes()));
+        let report = parse_report(SPINDUMP_OUTPUT);
+        assert_eq!(report.interval_nanos, Some(10_000_000));
+        assert!(report.is_spindump);
+        let thread = &report.processes[0].threads[0];
+        assert_eq!(
+            (thread.name.as_str(), thread.tid),
+            ("com.apple.main-thread", 0x3de0a5)
+        );
+        assert_eq!(
+            thread.nodes[3],
+            CallTreeNode {
+                parent: Some(2),
+                count: 60,
+                function: "??? (in kernel.release.t6000)".to_string(),
+                is_kernel: true,
+            }
+        );
+        let functions: Vec<_> = thread.nodes.iter().map(|n| n.function.as_str()).collect();
+        assert_eq!(
+            functions,
+            [
+                "start",
+                "main",
+                "mach_msg_trap",
+                "??? (in kernel.release.t6000)"
+            ]
+        );
+        assert!(!is_sample_text(b"name: sys_enter_openat\nID: 614\n"));
+    }
+}
@@ -0,0 +1,230 @@
+use std::io::Read;
+use std::time::SystemTime;
+
+use fxprof_processed_profile::{Profile, ReferenceTimestamp, Timestamp};
+
+use super::heap_profile::{pid_from_file_name, HeapProfileBuilder};
+use crate::shared::recording_props::ProfileCreationProps;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O Error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid massif file: {0}")]
+    Format(String),
+}
+
+#[derive(Debug, Default)]
+struct Snapshot {
+    time: u64,
+    mem_heap: u64,
+    heap_tree: Option<HeapTreeNode>,
+    is_peak: bool,
+}
+
+/// A node of a detailed snapshot's heap tree. The children of a node are the
+/// callers of the node's function, and `bytes` includes the children's bytes.
+#[derive(Debug)]
+struct HeapTreeNode {
+    bytes: u64,
+    label: String,
+    children: Vec<HeapTreeNode>,
+}
+
+/// Converts the output of valgrind's massif tool (massif.out.<pid>).
+///
+/// The heap size of every snapshot goes into a memory counter. The heap tree
+/// of the peak snapshot (or the last detailed snapshot, if there is no peak)
+/// becomes a set of allocation samples, so that the call tree shows which
+/// functions the memory at the peak was allocated from.
+pub fn convert<R: Read>(
+    mut reader: R,
+    file_name: &str,
+    file_mod_time: Option<SystemTime>,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, Error> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+
+    let mut command = None;
+    let mut time_unit = "i".to_string();
+    let mut snapshots: Vec<Snapshot> = Vec::new();
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        let Some((key, value)) = line.split_once([':', '=']) else {
+            continue;
+        };
+        let value = value.trim();
+        match key {
+            "cmd" => command = Some(value.to_string()),
+            "time_unit" => time_unit = value.to_string(),
+            "snapshot" => snapshots.push(Snapshot::default()),
+            "time" | "mem_heap_B" | "heap_tree" => {
+                let Some(snapshot) = snapshots.last_mut() else {
+                    return Err(Error::Format(format!("{key} outside of a snapshot")));
+                };
+                match key {
+                    "time" => snapshot.time = parse_number(value)?,
+                    "mem_heap_B" => snapshot.mem_heap = parse_number(value)?,
+                    _ => {
+                        snapshot.is_peak = value == "peak";
+                        if value == "detailed" || value == "peak" {
+                            snapshot.heap_tree = Some(parse_heap_tree(&mut lines)?);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let reference_timestamp = match file_mod_time {
+        Some(mod_time) => ReferenceTimestamp::from_system_time(mod_time),
+        None => ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+    };
+    let process_name = command
+        .as_deref()
+        .and_then(|command| command.split_whitespace().next())
+        .map(|program| program.rsplit('/').next().unwrap_or(program))
+        .unwrap_or("massif");
+    let mut builder = HeapProfileBuilder::new(
+        profile_creation_props.profile_name(),
+        reference_timestamp,
+        process_name,
+        pid_from_file_name(file_name),
+    );
+
+    // Massif's time unit can also be instructions or bytes allocated. We show
+    // those as nanoseconds, so that the timeline is still ordered correctly.
+    let time_unit_description = match time_unit.as_str() {
+        "ms" => "milliseconds",
+        "B" => "bytes allocated and deallocated, shown as nanoseconds",
+        _ => "instructions executed, shown as nanoseconds",
+    };
+    builder
+        .profile_mut()
+        .add_extra_meta_info("Massif", "Time unit", time_unit_description);
+    let to_timestamp = |time: u64| match time_unit.as_str() {
+        "ms" => Timestamp::from_millis_since_reference(time as f64),
+        _ => Timestamp::from_nanos_since_reference(time),
+    };
+
+    for snapshot in &snapshots {
+        builder.set_heap_size(to_timestamp(snapshot.time), snapshot.mem_heap as i64);
+    }
+
+    let snapshot_with_tree = snapshots
+        .iter()
+        .find(|snapshot| snapshot.is_peak)
+        .or_else(|| snapshots.iter().rfind(|s| s.heap_tree.is_some()));
+    if let Some(snapshot) = snapshot_with_tree {
+        let timestamp = to_timestamp(snapshot.time);
+        let root = snapshot.heap_tree.as_ref().unwrap();
+        // The root node is the pseudo-function "(heap allocation functions)".
+        let mut address = 0;
+        for child in &root.children {
+            add_allocations_for_node(
+                &mut builder,
+                timestamp,
+                child,
+                &mut Vec::new(),
+                &mut address,
+            );
+        }
+    }
+
+    Ok(builder.finish())
+}
+
+fn parse_number(value: &str) -> Result<u64, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::Format(format!("invalid number {value}")))
+}
+
+/// Parses a node line like "n2: 1000 0x4005F4: main (prog.c:5)" and the
+/// lines of its children.
+fn parse_heap_tree<'a>(lines: &mut impl Iterator<Item = &'a str>) -> Result<HeapTreeNode, Error> {
+    let line = lines
+        .next()
+        .ok_or_else(|| Error::Format("unexpected end of heap tree".to_string()))?
+        .trim_start();
+    let parse_error = || Error::Format(format!("invalid heap tree line {line}"));
+    let (child_count, rest) = line
+        .strip_prefix('n')
+        .and_then(|line| line.split_once(": "))
+        .ok_or_else(parse_error)?;
+    let child_count: usize = child_count.parse().map_err(|_| parse_error())?;
+    let (bytes, label) = rest.split_once(' ').unwrap_or((rest, ""));
+    let bytes = bytes.parse().map_err(|_| parse_error())?;
+    let children = (0..child_count)
+        .map(|_| parse_heap_tree(lines))
+        .collect::<Result<_, _>>()?;
+    Ok(HeapTreeNode {
+        bytes,
+        label: function_name_from_label(label).to_string(),
+        children,
+    })
+}
+
+/// Strips the code address from labels like "0x4005F4: main (prog.c:5)".
+fn function_name_from_label(label: &str) -> &str {
+    match label.split_once(": ") {
+        Some((address, function_name)) if address.starts_with("0x") => function_name,
+        _ => label,
+    }
+}
+
+/// Adds an allocation sample for the bytes which were allocated directly by
+/// this node's function, and recurses into the callers.
+///
+/// `stack` has the functions from the allocating function up to this node.
+fn add_allocations_for_node<'a>(
+    builder: &mut HeapProfileBuilder,
+    timestamp: Timestamp,
+    node: &'a HeapTreeNode,
+    stack: &mut Vec<&'a str>,
+    address: &mut u64,
+) {
+    stack.push(&node.label);
+    let children_bytes: u64 = node.children.iter().map(|child| child.bytes).sum();
+    let self_bytes = node.bytes.saturating_sub(children_bytes);
+    if self_bytes != 0 {
+        // Each sample gets its own address, because we don't know the real ones.
+        *address += 1;
+        builder.add_allocation(
+            timestamp,
+            stack.iter().rev().copied(),
+            *address,
+            self_bytes as i64,
+        );
+    }
+    for child in &node.children {
+        add_allocations_for_node(builder, timestamp, child, stack, address);
+    }
+    stack.pop();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_heap_tree() {
+        let tree = "n2: 1000 (heap allocation functions) malloc/new/new[], --alloc-fns, etc.
+ n1: 600 0x4005F4: g (prog.c:5)
+  n0: 600 0x400600: main (prog.c:10)
+ n0: 400 in 1 place, below massif's threshold (1.00%)
+";
+        let root = parse_heap_tree(&mut tree.lines()).unwrap();
+        assert_eq!(root.bytes, 1000);
+        assert_eq!(root.children.len(), 2);
+        assert_eq!(root.children[0].label, "g (prog.c:5)");
+        assert_eq!(root.children[0].children[0].label, "main (prog.c:10)");
+        assert_eq!(
+            root.children[1].label,
+            "in 1 place, below massif's threshold (1.00%)"
+        );
+    }
+}
@@ -1 +1,8 @@
+pub mod chrome_trace;
+pub mod cpuprofile;
+pub mod heap_profile;
+pub mod heaptrack;
+pub mod mac_sample;
+pub mod massif;
+pub mod nettrace;
 pub mod perf;
@@ -0,0 +1,1076 @@
+//! Imports `.nettrace` files, which `dotnet-trace collect` writes: the events
+//! of one .NET process, recorded with EventPipe.
+//!
+//! The samples come from the `Microsoft-DotNETCore-SampleProfiler` provider.
+//! Their stacks only have managed frames, which are named from the method load
+//! and rundown events. GC, exception and assembly load events become the same
+//! markers as in ETW traces on Windows.
+//!
+//! The format is described in
+//! <https://github.com/microsoft/perfview/blob/main/src/TraceEvent/EventPipe/EventPipeFormat.md>.
+//! We support the "V4" and "V5" versions, which all current runtimes write.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use fxprof_processed_profile::{
+    CategoryColor, CategoryHandle, CategoryPairHandle, CpuDelta, Frame, FrameFlags, FrameInfo,
+    MarkerTiming, ProcessHandle, Profile, ReferenceTimestamp, SamplingInterval, StaticSchemaMarker,
+    StringHandle, ThreadHandle, Timestamp,
+};
+use num_traits::FromPrimitive;
+
+use crate::shared::coreclr::{
+    method_name, CoreClrAssemblyLoadMarker, CoreClrExceptionCaughtMarker,
+    CoreClrExceptionThrownMarker, CoreClrGcAllocMarker, CoreClrGcEventMarker, DisplayUnknownIfNone,
+    GcReason, GcSuspendEeReason, GcType,
+};
+use crate::shared::recording_props::ProfileCreationProps;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O Error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Not a .nettrace file, or a version of the format which isn't supported")]
+    NotNettrace,
+
+    #[error("Unsupported .nettrace version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Unexpected end of the file at offset {0}")]
+    UnexpectedEof(usize),
+
+    #[error("Unexpected serialization tag {tag} at offset {offset}")]
+    UnexpectedTag { tag: u8, offset: usize },
+
+    #[error("Invalid variable-length integer at offset {0}")]
+    InvalidVarInt(usize),
+
+    #[error("Unknown object type {0:?}")]
+    UnknownObject(String),
+}
+
+const MAGIC: &[u8] = b"Nettrace";
+const SERIALIZATION_SIGNATURE: &[u8] = b"!FastSerialization.1";
+
+/// The tags of the "FastSerialization" object stream.
+mod tag {
+    pub const NULL_REFERENCE: u8 = 1;
+    pub const BEGIN_PRIVATE_OBJECT: u8 = 5;
+    pub const END_OBJECT: u8 = 6;
+}
+
+const SAMPLE_PROFILER_PROVIDER: &str = "Microsoft-DotNETCore-SampleProfiler";
+const RUNTIME_PROVIDER: &str = "Microsoft-Windows-DotNETRuntime";
+const RUNDOWN_PROVIDER: &str = "Microsoft-Windows-DotNETRuntimeRundown";
+
+/// The IDs of the events of the runtime provider which we convert.
+mod event_id {
+    pub const GC_START: u32 = 1;
+    pub const GC_END: u32 = 2;
+    pub const GC_RESTART_EE_END: u32 = 3;
+    pub const GC_SUSPEND_EE_BEGIN: u32 = 9;
+    pub const GC_SAMPLED_OBJECT_ALLOCATION_HIGH: u32 = 20;
+    pub const GC_SAMPLED_OBJECT_ALLOCATION_LOW: u32 = 32;
+    pub const GC_TRIGGERED: u32 = 35;
+    pub const EXCEPTION_THROWN: u32 = 80;
+    pub const METHOD_LOAD_VERBOSE: u32 = 143;
+    pub const ASSEMBLY_LOAD: u32 = 154;
+    pub const EXCEPTION_CATCH_START: u32 = 250;
+
+    /// MethodDCStartVerbose and MethodDCEndVerbose, in the rundown provider.
+    pub const METHOD_DC_START_VERBOSE: u32 = 143;
+    pub const METHOD_DC_END_VERBOSE: u32 = 144;
+}
+
+/// The `Type` field of the SampleProfiler's ThreadSample events, for threads
+/// which were running managed code. The other threads were in native code,
+/// usually blocked.
+const THREAD_SAMPLE_MANAGED: u32 = 2;
+
+pub fn convert<R: Read>(
+    mut reader: R,
+    file_name: &str,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, Error> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let trace = parse_nettrace(&data)?;
+    Ok(build_profile(
+        &trace,
+        profile_creation_props.profile_name(),
+        file_name,
+    ))
+}
+
+/// Little-endian reads from the file, with file offsets for the errors.
+#[derive(Clone)]
+struct Reader<'a> {
+    /// The file, up to the end of the current block.
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(Error::UnexpectedEof(self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// A pointer-sized value, in the pointer size of the traced process.
+    fn pointer(&mut self, pointer_size: usize) -> Result<u64, Error> {
+        Ok(address_from_bytes(self.bytes(pointer_size)?))
+    }
+
+    /// An unsigned LEB128 integer.
+    fn var_u64(&mut self) -> Result<u64, Error> {
+        let start = self.pos;
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+            if shift >= 64 {
+                return Err(Error::InvalidVarInt(start));
+            }
+        }
+    }
+
+    fn var_u32(&mut self) -> Result<u32, Error> {
+        Ok(self.var_u64()? as u32)
+    }
+
+    /// A nul-terminated UTF-16 string.
+    fn utf16_string(&mut self) -> Result<String, Error> {
+        let mut units = Vec::new();
+        loop {
+            match self.u16()? {
+                0 => return Ok(String::from_utf16_lossy(&units)),
+                unit => units.push(unit),
+            }
+        }
+    }
+
+    fn expect_tag(&mut self, expected: u8) -> Result<(), Error> {
+        let offset = self.pos;
+        match self.u8()? {
+            tag if tag == expected => Ok(()),
+            tag => Err(Error::UnexpectedTag { tag, offset }),
+        }
+    }
+
+    fn align_to_4(&mut self) {
+        self.pos = self.pos.next_multiple_of(4);
+    }
+
+    /// Reads the size of a block and returns a reader for its contents, which
+    /// start at the next 4-byte aligned offset in the file.
+    fn block(&mut self) -> Result<Reader<'a>, Error> {
+        let size = self.u32()? as usize;
+        self.align_to_4();
+        let start = self.pos;
+        self.bytes(size)?;
+        Ok(Reader {
+            data: &self.data[..self.pos],
+            pos: start,
+        })
+    }
+}
+
+fn address_from_bytes(bytes: &[u8]) -> u64 {
+    match bytes.len() {
+        4 => u32::from_le_bytes(bytes.try_into().unwrap()).into(),
+        _ => u64::from_le_bytes(bytes.try_into().unwrap()),
+    }
+}
+
+#[derive(Debug)]
+struct Trace<'a> {
+    /// The wall-clock time of `sync_time_qpc`, in milliseconds since the Unix
+    /// epoch.
+    sync_time_ms: f64,
+    sync_time_qpc: u64,
+    qpc_frequency: u64,
+    pointer_size: usize,
+    pid: u32,
+    sampling_interval_ns: u32,
+    metadata: HashMap<u32, EventMetadata>,
+    events: Vec<Event<'a>>,
+    /// The stacks of all events, leaf first. The events refer to them by index.
+    stacks: Vec<Vec<u64>>,
+}
+
+impl Trace<'_> {
+    fn timestamp(&self, timestamp_qpc: u64) -> Timestamp {
+        let ticks = u128::from(timestamp_qpc.saturating_sub(self.sync_time_qpc));
+        let nanos = ticks * 1_000_000_000 / u128::from(self.qpc_frequency.max(1));
+        Timestamp::from_nanos_since_reference(nanos as u64)
+    }
+}
+
+#[derive(Debug)]
+struct EventMetadata {
+    provider: String,
+    event_id: u32,
+}
+
+#[derive(Debug)]
+struct Event<'a> {
+    metadata_id: u32,
+    tid: u64,
+    timestamp_qpc: u64,
+    stack: Option<usize>,
+    payload: &'a [u8],
+}
+
+/// The header fields of an event. With compressed headers, each field is
+/// only stored if it differs from the previous event in the block.
+#[derive(Debug, Default)]
+struct EventHeader {
+    metadata_id: u32,
+    tid: u64,
+    stack_id: u32,
+    timestamp_qpc: u64,
+    payload_size: u32,
+}
+
+fn parse_nettrace(data: &[u8]) -> Result<Trace<'_>, Error> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(MAGIC.len()).ok() != Some(MAGIC) {
+        return Err(Error::NotNettrace);
+    }
+    let signature_len = reader.u32()? as usize;
+    if reader.bytes(signature_len).ok() != Some(SERIALIZATION_SIGNATURE) {
+        return Err(Error::NotNettrace);
+    }
+
+    let mut trace = Trace {
+        sync_time_ms: 0.0,
+        sync_time_qpc: 0,
+        qpc_frequency: 1_000_000_000,
+        pointer_size: 8,
+        pid: 0,
+        sampling_interval_ns: 0,
+        metadata: HashMap::new(),
+        events: Vec::new(),
+        stacks: Vec::new(),
+    };
+    // Stack IDs are only valid until the next sequence point.
+    let mut stack_ids: HashMap<u32, usize> = HashMap::new();
+    loop {
+        let offset = reader.pos;
+        match reader.u8()? {
+            tag::NULL_REFERENCE => break,
+            tag::BEGIN_PRIVATE_OBJECT => {}
+            tag => return Err(Error::UnexpectedTag { tag, offset }),
+        }
+        let object_type = read_object_type(&mut reader)?;
+        match object_type.as_str() {
+            "Trace" => read_trace_object(&mut reader, &mut trace)?,
+            "MetadataBlock" => {
+                let mut block = reader.block()?;
+                read_events(&mut block, |_header, payload| {
+                    read_metadata(payload, &mut trace.metadata)
+                })?;
+            }
+            "EventBlock" => {
+                let mut block = reader.block()?;
+                read_events(&mut block, |header, payload| {
+                    trace.events.push(Event {
+                        metadata_id: header.metadata_id,
+                        tid: header.tid,
+                        timestamp_qpc: header.timestamp_qpc,
+                        stack: stack_ids.get(&header.stack_id).copied(),
+                        payload,
+                    });
+                    Ok(())
+                })?;
+            }
+            "StackBlock" => {
+                let mut block = reader.block()?;
+                let first_id = block.u32()?;
+                let count = block.u32()?;
+                for stack_id in first_id..first_id.saturating_add(count) {
+                    let size = block.u32()? as usize;
+                    let stack = block
+                        .bytes(size)?
+                        .chunks_exact(trace.pointer_size)
+                        .map(address_from_bytes)
+                        .collect();
+                    stack_ids.insert(stack_id, trace.stacks.len());
+                    trace.stacks.push(stack);
+                }
+            }
+            "SPBlock" => {
+                reader.block()?;
+                stack_ids.clear();
+            }
+            _ => return Err(Error::UnknownObject(object_type)),
+        }
+        reader.expect_tag(tag::END_OBJECT)?;
+    }
+    Ok(trace)
+}
+
+/// Reads the type of an object, which is serialized like an object itself,
+/// and returns the type name.
+fn read_object_type(reader: &mut Reader) -> Result<String, Error> {
+    reader.expect_tag(tag::BEGIN_PRIVATE_OBJECT)?;
+    reader.expect_tag(tag::NULL_REFERENCE)?;
+    let version = reader.u32()?;
+    let min_reader_version = reader.u32()?;
+    let name_len = reader.u32()? as usize;
+    let name = String::from_utf8_lossy(reader.bytes(name_len)?).into_owned();
+    reader.expect_tag(tag::END_OBJECT)?;
+    if name == "Trace" && (version < 4 || min_reader_version > 4) {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    Ok(name)
+}
+
+fn read_trace_object(reader: &mut Reader, trace: &mut Trace) -> Result<(), Error> {
+    // A SYSTEMTIME, in UTC.
+    let year = reader.u16()?;
+    let month = reader.u16()?;
+    let _day_of_week = reader.u16()?;
+    let day = reader.u16()?;
+    let hour = reader.u16()?;
+    let minute = reader.u16()?;
+    let second = reader.u16()?;
+    let millisecond = reader.u16()?;
+    let days = days_since_unix_epoch(year.into(), month.into(), day.into());
+    let seconds =
+        days * 86400 + i64::from(hour) * 3600 + i64::from(minute) * 60 + i64::from(second);
+    trace.sync_time_ms = seconds as f64 * 1000.0 + f64::from(millisecond);
+
+    trace.sync_time_qpc = reader.u64()?;
+    trace.qpc_frequency = reader.u64()?;
+    trace.pointer_size = match reader.u32()? {
+        4 => 4,
+        _ => 8,
+    };
+    trace.pid = reader.u32()?;
+    let _processor_count = reader.u32()?;
+    trace.sampling_interval_ns = reader.u32()?;
+    Ok(())
+}
+
+/// The number of days between 1970-01-01 and the given date in the proleptic
+/// Gregorian calendar.
+fn days_since_unix_epoch(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Calls `f` for each event in an EventBlock or a MetadataBlock.
+fn read_events<'a>(
+    block: &mut Reader<'a>,
+    mut f: impl FnMut(&EventHeader, &'a [u8]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let block_start = block.pos;
+    let header_size = block.u16()?;
+    let flags = block.u16()?;
+    let is_compressed = flags & 1 != 0;
+    block.pos = block_start + usize::from(header_size);
+
+    let mut header = EventHeader::default();
+    while !block.is_at_end() {
+        if is_compressed {
+            read_compressed_event_header(block, &mut header)?;
+        } else {
+            read_event_header(block, &mut header)?;
+        }
+        let payload = block.bytes(header.payload_size as usize)?;
+        if !is_compressed {
+            block.align_to_4();
+        }
+        f(&header, payload)?;
+    }
+    Ok(())
+}
+
+fn read_event_header(reader: &mut Reader, header: &mut EventHeader) -> Result<(), Error> {
+    let _event_size = reader.u32()?;
+    // The high bit is the "sorted" flag.
+    header.metadata_id = reader.u32()? & 0x7fff_ffff;
+    let _sequence_number = reader.u32()?;
+    header.tid = reader.u64()?;
+    let _capture_tid = reader.u64()?;
+    let _processor_number = reader.u32()?;
+    header.stack_id = reader.u32()?;
+    header.timestamp_qpc = reader.u64()?;
+    // The activity ID and the related activity ID.
+    reader.bytes(32)?;
+    header.payload_size = reader.u32()?;
+    Ok(())
+}
+
+fn read_compressed_event_header(
+    reader: &mut Reader,
+    header: &mut EventHeader,
+) -> Result<(), Error> {
+    let flags = reader.u8()?;
+    if flags & 0x1 != 0 {
+        header.metadata_id = reader.var_u32()?;
+    }
+    if flags & 0x2 != 0 {
+        let _sequence_number_delta = reader.var_u32()?;
+        let _capture_tid = reader.var_u64()?;
+        let _processor_number = reader.var_u32()?;
+    }
+    if flags & 0x4 != 0 {
+        header.tid = reader.var_u64()?;
+    }
+    if flags & 0x8 != 0 {
+        header.stack_id = reader.var_u32()?;
+    }
+    header.timestamp_qpc = header.timestamp_qpc.wrapping_add(reader.var_u64()?);
+    if flags & 0x10 != 0 {
+        // The activity ID.
+        reader.bytes(16)?;
+    }
+    if flags & 0x20 != 0 {
+        // The related activity ID.
+        reader.bytes(16)?;
+    }
+    // 0x40 is the "sorted" flag, which has no data.
+    if flags & 0x80 != 0 {
+        header.payload_size = reader.var_u32()?;
+    }
+    Ok(())
+}
+
+/// Reads the payload of an event in a MetadataBlock, which describes the
+/// events with its metadata ID.
+fn read_metadata(payload: &[u8], metadata: &mut HashMap<u32, EventMetadata>) -> Result<(), Error> {
+    let mut reader = Reader::new(payload);
+    let metadata_id = reader.u32()?;
+    let provider = reader.utf16_string()?;
+    let event_id = reader.u32()?;
+    metadata.insert(metadata_id, EventMetadata { provider, event_id });
+    Ok(())
+}
+
+struct Method {
+    start: u64,
+    end: u64,
+    name: StringHandle,
+}
+
+/// The managed methods with native code, from the MethodLoadVerbose events and
+/// from the rundown at the end of the trace.
+struct Methods(Vec<Method>);
+
+impl Methods {
+    fn new(trace: &Trace, profile: &mut Profile) -> Self {
+        let mut methods = Vec::new();
+        for event in &trace.events {
+            let Some(metadata) = trace.metadata.get(&event.metadata_id) else {
+                continue;
+            };
+            let is_method_event = match metadata.provider.as_str() {
+                RUNTIME_PROVIDER => metadata.event_id == event_id::METHOD_LOAD_VERBOSE,
+                RUNDOWN_PROVIDER => matches!(
+                    metadata.event_id,
+                    event_id::METHOD_DC_START_VERBOSE | event_id::METHOD_DC_END_VERBOSE
+                ),
+                _ => false,
+            };
+            if !is_method_event {
+                continue;
+            }
+            if let Ok(method) = read_method_event(event.payload, profile) {
+                methods.push(method);
+            }
+        }
+        methods.sort_by_key(|method| method.start);
+        methods.dedup_by_key(|method| method.start);
+        Self(methods)
+    }
+
+    fn lookup(&self, address: u64) -> Option<(usize, &Method)> {
+        let index = self.0.partition_point(|method| method.start <= address);
+        let index = index.checked_sub(1)?;
+        let method = &self.0[index];
+        (address < method.end).then_some((index, method))
+    }
+}
+
+/// Reads MethodLoadVerbose, MethodDCStartVerbose and MethodDCEndVerbose
+/// events, which all start with the same fields.
+fn read_method_event(payload: &[u8], profile: &mut Profile) -> Result<Method, Error> {
+    let mut reader = Reader::new(payload);
+    let _method_id = reader.u64()?;
+    let _module_id = reader.u64()?;
+    let start = reader.u64()?;
+    let size = reader.u32()?;
+    let _method_token = reader.u32()?;
+    let _method_flags = reader.u32()?;
+    let namespace = reader.utf16_string()?;
+    let basename = reader.utf16_string()?;
+    let signature = reader.utf16_string()?;
+    Ok(Method {
+        start,
+        end: start + u64::from(size),
+        name: profile.intern_string(&method_name(&basename, &namespace, &signature)),
+    })
+}
+
+struct Categories {
+    jit: CategoryPairHandle,
+    gc: CategoryHandle,
+    exception: CategoryHandle,
+    loader: CategoryHandle,
+    other: CategoryPairHandle,
+}
+
+/// A GC or a suspension of the runtime which has started on a thread.
+struct OpenGcMarker {
+    start: Timestamp,
+    name: &'static str,
+    description: String,
+    stack: Option<usize>,
+}
+
+struct Converter<'a> {
+    trace: &'a Trace<'a>,
+    profile: Profile,
+    process: ProcessHandle,
+    categories: Categories,
+    methods: Methods,
+    threads: HashMap<u64, ThreadHandle>,
+    method_frames: HashMap<usize, FrameInfo>,
+    unknown_frames: HashMap<u64, FrameInfo>,
+    open_gc_markers: HashMap<(u64, &'static str), OpenGcMarker>,
+    last_exception_type_on_thread: HashMap<u64, StringHandle>,
+}
+
+fn build_profile(trace: &Trace, profile_name: &str, file_name: &str) -> Profile {
+    let sampling_interval = match trace.sampling_interval_ns {
+        0 => SamplingInterval::from_millis(1),
+        interval_ns => SamplingInterval::from_nanos(interval_ns.into()),
+    };
+    let mut profile = Profile::new(
+        profile_name,
+        ReferenceTimestamp::from_millis_since_unix_epoch(trace.sync_time_ms),
+        sampling_interval,
+    );
+    let process = profile.add_process(
+        process_name_from_file_name(file_name),
+        trace.pid,
+        Timestamp::from_nanos_since_reference(0),
+    );
+    let categories = Categories {
+        jit: profile
+            .add_category("CoreCLR JIT", CategoryColor::Purple)
+            .into(),
+        gc: profile.add_category("CoreCLR GC", CategoryColor::Red),
+        exception: profile.add_category("CoreCLR Exception", CategoryColor::Orange),
+        loader: profile.add_category("CoreCLR Loader", CategoryColor::Green),
+        other: CategoryHandle::OTHER.into(),
+    };
+    let methods = Methods::new(trace, &mut profile);
+    let mut converter = Converter {
+        trace,
+        profile,
+        process,
+        categories,
+        methods,
+        threads: HashMap::new(),
+        method_frames: HashMap::new(),
+        unknown_frames: HashMap::new(),
+        open_gc_markers: HashMap::new(),
+        last_exception_type_on_thread: HashMap::new(),
+    };
+
+    // The events are only sorted within each block and thread.
+    let mut events: Vec<&Event> = trace.events.iter().collect();
+    events.sort_by_key(|event| event.timestamp_qpc);
+    for event in events {
+        let Some(metadata) = trace.metadata.get(&event.metadata_id) else {
+            continue;
+        };
+        match metadata.provider.as_str() {
+            SAMPLE_PROFILER_PROVIDER => converter.handle_sample(event),
+            // Malformed payloads are skipped.
+            RUNTIME_PROVIDER => {
+                let _ = converter.handle_runtime_event(metadata.event_id, event);
+            }
+            _ => {}
+        }
+    }
+    converter.profile
+}
+
+impl Converter<'_> {
+    fn thread(&mut self, tid: u64, timestamp: Timestamp) -> ThreadHandle {
+        *self.threads.entry(tid).or_insert_with(|| {
+            let tid = tid as u32;
+            let thread =
+                self.profile
+                    .add_thread(self.process, tid, timestamp, tid == self.trace.pid);
+            self.profile
+                .set_thread_name(thread, &format!("Thread {tid}"));
+            thread
+        })
+    }
+
+    /// The frames of a stack, from the root to the leaf.
+    fn frames(&mut self, stack: usize) -> Vec<FrameInfo> {
+        let addresses = &self.trace.stacks[stack];
+        let mut frames = Vec::with_capacity(addresses.len());
+        for (depth, &address) in addresses.iter().enumerate().rev() {
+            // All frames but the leaf have return addresses, which can be just
+            // past the end of the calling method.
+            let lookup_address = if depth == 0 {
+                address
+            } else {
+                address.saturating_sub(1)
+            };
+            let frame = match self.methods.lookup(lookup_address) {
+                Some((index, method)) => {
+                    let name = method.name;
+                    let category_pair = self.categories.jit;
+                    self.method_frames
+                        .entry(index)
+                        .or_insert_with(|| FrameInfo {
+                            frame: Frame::Label(name),
+                            category_pair,
+                            flags: FrameFlags::empty(),
+                        })
+                        .clone()
+                }
+                None => {
+                    let category_pair = self.categories.other;
+                    let profile = &mut self.profile;
+                    self.unknown_frames
+                        .entry(address)
+                        .or_insert_with(|| FrameInfo {
+                            frame: Frame::Label(profile.intern_string(&format!("0x{address:x}"))),
+                            category_pair,
+                            flags: FrameFlags::empty(),
+                        })
+                        .clone()
+                }
+            };
+            frames.push(frame);
+        }
+        frames
+    }
+
+    fn handle_sample(&mut self, event: &Event) {
+        let Some(stack) = event.stack else {
+            return;
+        };
+        let timestamp = self.trace.timestamp(event.timestamp_qpc);
+        let thread = self.thread(event.tid, timestamp);
+        let sample_type = Reader::new(event.payload).u32().unwrap_or(0);
+        let cpu_delta = if sample_type == THREAD_SAMPLE_MANAGED {
+            CpuDelta::from_nanos(self.profile_interval_ns())
+        } else {
+            CpuDelta::ZERO
+        };
+        let frames = self.frames(stack);
+        self.profile
+            .add_sample(thread, timestamp, frames.into_iter(), cpu_delta, 1);
+    }
+
+    fn profile_interval_ns(&self) -> u64 {
+        match self.trace.sampling_interval_ns {
+            0 => 1_000_000,
+            interval_ns => interval_ns.into(),
+        }
+    }
+
+    fn add_marker<T: StaticSchemaMarker>(
+        &mut self,
+        tid: u64,
+        timing: MarkerTiming,
+        stack: Option<usize>,
+        marker: T,
+    ) {
+        let start = match timing {
+            MarkerTiming::Instant(start)
+            | MarkerTiming::Interval(start, _)
+            | MarkerTiming::IntervalStart(start) => start,
+            MarkerTiming::IntervalEnd(end) => end,
+        };
+        let thread = self.thread(tid, start);
+        let marker = self.profile.add_marker(thread, timing, marker);
+        if let Some(stack) = stack {
+            let frames = self.frames(stack);
+            self.profile
+                .set_marker_stack(thread, marker, frames.into_iter());
+        }
+    }
+
+    fn handle_runtime_event(&mut self, event_id: u32, event: &Event) -> Result<(), Error> {
+        let timestamp = self.trace.timestamp(event.timestamp_qpc);
+        let tid = event.tid;
+        let mut reader = Reader::new(event.payload);
+        match event_id {
+            event_id::GC_START => {
+                let count = reader.u32()?;
+                let depth = reader.u32()?;
+                let reason = GcReason::from_u32(reader.u32()?);
+                let gc_type = GcType::from_u32(reader.u32()?);
+                self.open_gc_markers.insert(
+                    (tid, "GC"),
+                    OpenGcMarker {
+                        start: timestamp,
+                        name: "GC",
+                        description: format!(
+                            "{}: {} (GC #{}, gen{})",
+                            DisplayUnknownIfNone(&gc_type),
+                            DisplayUnknownIfNone(&reason),
+                            count,
+                            depth
+                        ),
+                        stack: event.stack,
+                    },
+                );
+            }
+            event_id::GC_SUSPEND_EE_BEGIN => {
+                let reason = GcSuspendEeReason::from_u32(reader.u32()?);
+                self.open_gc_markers.insert(
+                    (tid, "GCSuspendEE"),
+                    OpenGcMarker {
+                        start: timestamp,
+                        name: "GC Suspended Thread",
+                        description: format!("Suspended: {}", DisplayUnknownIfNone(&reason)),
+                        stack: event.stack,
+                    },
+                );
+            }
+            event_id::GC_END | event_id::GC_RESTART_EE_END => {
+                let key = if event_id == event_id::GC_END {
+                    "GC"
+                } else {
+                    "GCSuspendEE"
+                };
+                if let Some(open) = self.open_gc_markers.remove(&(tid, key)) {
+                    let name = self.profile.intern_string(open.name);
+                    let description = self.profile.intern_string(&open.description);
+                    let category = self.categories.gc;
+                    self.add_marker(
+                        tid,
+                        MarkerTiming::Interval(open.start, timestamp),
+                        open.stack,
+                        CoreClrGcEventMarker(name, description, category),
+                    );
+                }
+            }
+            event_id::GC_TRIGGERED => {
+                let reason = GcReason::from_u32(reader.u32()?);
+                let name = self.profile.intern_string("GC Trigger");
+                let description = self
+                    .profile
+                    .intern_string(&format!("GC Trigger: {}", DisplayUnknownIfNone(&reason)));
+                let category = self.categories.gc;
+                self.add_marker(
+                    tid,
+                    MarkerTiming::Instant(timestamp),
+                    event.stack,
+                    CoreClrGcEventMarker(name, description, category),
+                );
+            }
+            event_id::GC_SAMPLED_OBJECT_ALLOCATION_HIGH
+            | event_id::GC_SAMPLED_OBJECT_ALLOCATION_LOW => {
+                let _address = reader.pointer(self.trace.pointer_size)?;
+                let type_id = reader.pointer(self.trace.pointer_size)?;
+                let _object_count = reader.u32()?;
+                let total_size = reader.u64()?;
+                let clr_type = self.profile.intern_string(&format!("0x{type_id:x}"));
+                let category = self.categories.gc;
+                self.add_marker(
+                    tid,
+                    MarkerTiming::Instant(timestamp),
+                    event.stack,
+                    CoreClrGcAllocMarker(clr_type, total_size as f64, category),
+                );
+            }
+            event_id::EXCEPTION_THROWN => {
+                let exception_type = reader.utf16_string()?;
+                let exception_message = reader.utf16_string()?;
+                let exception_type = self.profile.intern_string(&exception_type);
+                let exception_message = self.profile.intern_string(&exception_message);
+                self.last_exception_type_on_thread
+                    .insert(tid, exception_type);
+                let category = self.categories.exception;
+                self.add_marker(
+                    tid,
+                    MarkerTiming::Instant(timestamp),
+                    event.stack,
+                    CoreClrExceptionThrownMarker(exception_type, exception_message, category),
+                );
+            }
+            event_id::EXCEPTION_CATCH_START => {
+                // This event doesn't carry the exception type, so we use the
+                // type of the most recent exception thrown on this thread.
+                let _entry_address = reader.u64()?;
+                let _method_id = reader.u64()?;
+                let method_name = reader.utf16_string()?;
+                let exception_type = match self.last_exception_type_on_thread.remove(&tid) {
+                    Some(exception_type) => exception_type,
+                    None => self.profile.intern_string("Unknown"),
+                };
+                let method_name = self.profile.intern_string(&method_name);
+                let category = self.categories.exception;
+                self.add_marker(
+                    tid,
+                    MarkerTiming::Instant(timestamp),
+                    event.stack,
+                    CoreClrExceptionCaughtMarker(exception_type, method_name, category),
+                );
+            }
+            event_id::ASSEMBLY_LOAD => {
+                let _assembly_id = reader.u64()?;
+                let _app_domain_id = reader.u64()?;
+                let _binding_id = reader.u64()?;
+                let _assembly_flags = reader.u32()?;
+                let assembly_name = reader.utf16_string()?;
+                let assembly_name = self.profile.intern_string(&assembly_name);
+                let category = self.categories.loader;
+                self.add_marker(
+                    tid,
+                    MarkerTiming::Instant(timestamp),
+                    event.stack,
+                    CoreClrAssemblyLoadMarker(assembly_name, category),
+                );
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// `dotnet-trace` names its files `<process name>_<yyyyMMdd>_<HHmmss>.nettrace`.
+fn process_name_from_file_name(file_name: &str) -> &str {
+    let name = file_name.strip_suffix(".nettrace").unwrap_or(file_name);
+    let is_number = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit());
+    let mut parts = name.rsplitn(3, '_');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(time), Some(date), Some(process_name))
+            if is_number(time, 6) && is_number(date, 8) && !process_name.is_empty() =>
+        {
+            process_name
+        }
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn utf16(out: &mut Vec<u8>, s: &str) {
+        for unit in s.encode_utf16().chain([0]) {
+            out.extend(unit.to_le_bytes());
+        }
+    }
+
+    fn begin_object(out: &mut Vec<u8>, type_name: &str) {
+        out.extend([tag::BEGIN_PRIVATE_OBJECT, tag::BEGIN_PRIVATE_OBJECT]);
+        out.push(tag::NULL_REFERENCE);
+        out.extend(4u32.to_le_bytes());
+        out.extend(4u32.to_le_bytes());
+        out.extend((type_name.len() as u32).to_le_bytes());
+        out.extend(type_name.as_bytes());
+        out.push(tag::END_OBJECT);
+    }
+
+    fn block(out: &mut Vec<u8>, type_name: &str, content: &[u8]) {
+        begin_object(out, type_name);
+        out.extend((content.len() as u32).to_le_bytes());
+        out.resize(out.len().next_multiple_of(4), 0);
+        out.extend(content);
+        out.push(tag::END_OBJECT);
+    }
+
+    /// An EventBlock or MetadataBlock with uncompressed headers.
+    fn event_block(events: &[(u32, u64, u32, u64, Vec<u8>)]) -> Vec<u8> {
+        let mut content = Vec::new();
+        content.extend(20u16.to_le_bytes());
+        content.extend(0u16.to_le_bytes());
+        content.extend([0; 16]);
+        for (metadata_id, tid, stack_id, timestamp, payload) in events {
+            content.extend(0u32.to_le_bytes());
+            content.extend(metadata_id.to_le_bytes());
+            content.extend(0u32.to_le_bytes());
+            content.extend(tid.to_le_bytes());
+            content.extend(tid.to_le_bytes());
+            content.extend(0u32.to_le_bytes());
+            content.extend(stack_id.to_le_bytes());
+            content.extend(timestamp.to_le_bytes());
+            content.extend([0; 32]);
+            content.extend((payload.len() as u32).to_le_bytes());
+            content.extend(payload);
+            content.resize(content.len().next_multiple_of(4), 0);
+        }
+        content
+    }
+
+    fn metadata(metadata_id: u32, provider: &str, event_id: u32) -> (u32, u64, u32, u64, Vec<u8>) {
+        let mut payload = Vec::new();
+        payload.extend(metadata_id.to_le_bytes());
+        utf16(&mut payload, provider);
+        payload.extend(event_id.to_le_bytes());
+        utf16(&mut payload, "");
+        (0, 0, 0, 0, payload)
+    }
+
+    fn nettrace_file() -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend((SERIALIZATION_SIGNATURE.len() as u32).to_le_bytes());
+        out.extend(SERIALIZATION_SIGNATURE);
+
+        begin_object(&mut out, "Trace");
+        // 2024-01-02 03:04:05.678
+        for field in [2024u16, 1, 2, 2, 3, 4, 5, 678] {
+            out.extend(field.to_le_bytes());
+        }
+        out.extend(1_000_000u64.to_le_bytes());
+        out.extend(1_000_000_000u64.to_le_bytes());
+        out.extend(8u32.to_le_bytes());
+        out.extend(4321u32.to_le_bytes());
+        out.extend(8u32.to_le_bytes());
+        out.extend(1_000_000u32.to_le_bytes());
+        out.push(tag::END_OBJECT);
+
+        block(
+            &mut out,
+            "MetadataBlock",
+            &event_block(&[
+                metadata(1, SAMPLE_PROFILER_PROVIDER, 0),
+                metadata(2, RUNTIME_PROVIDER, event_id::EXCEPTION_THROWN),
+                metadata(3, RUNDOWN_PROVIDER, event_id::METHOD_DC_END_VERBOSE),
+            ]),
+        );
+
+        let mut stacks = Vec::new();
+        stacks.extend(1u32.to_le_bytes());
+        stacks.extend(1u32.to_le_bytes());
+        stacks.extend(16u32.to_le_bytes());
+        stacks.extend(0x1010u64.to_le_bytes());
+        stacks.extend(0x2000u64.to_le_bytes());
+        block(&mut out, "StackBlock", &stacks);
+
+        let mut exception = Vec::new();
+        utf16(&mut exception, "System.InvalidOperationException");
+        utf16(&mut exception, "Oops");
+        let mut method = Vec::new();
+        method.extend(1u64.to_le_bytes());
+        method.extend(2u64.to_le_bytes());
+        method.extend(0x1000u64.to_le_bytes());
+        method.extend(0x100u32.to_le_bytes());
+        method.extend([0; 8]);
+        utf16(&mut method, "App");
+        utf16(&mut method, "Work");
+        utf16(&mut method, "void  ()");
+        block(
+            &mut out,
+            "EventBlock",
+            &event_block(&[
+                (
+                    1,
+                    4321,
+                    1,
+                    3_000_000,
+                    THREAD_SAMPLE_MANAGED.to_le_bytes().to_vec(),
+                ),
+                (2, 4321, 1, 2_000_000, exception),
+                (3, 4321, 0, 9_000_000, method),
+            ]),
+        );
+        block(&mut out, "SPBlock", &[0; 16]);
+        out.push(tag::NULL_REFERENCE);
+        out
+    }
+
+    #[test]
+    fn test_convert_nettrace() {
+        let data = nettrace_file();
+        let trace = parse_nettrace(&data).unwrap();
+        assert_eq!(trace.pid, 4321);
+        assert_eq!(trace.events.len(), 3);
+        assert_eq!(trace.stacks, vec![vec![0x1010, 0x2000]]);
+        assert_eq!(
+            trace.sync_time_ms, 1_704_164_645_678.0,
+            "2024-01-02T03:04:05.678Z"
+        );
+
+        let profile = build_profile(&trace, "test", "App_20240102_030405.nettrace");
+        let profile: Value = serde_json::to_value(&profile).unwrap();
+        let thread = &profile["threads"][0];
+        assert_eq!(thread["processName"], "App");
+        assert_eq!(thread["samples"]["length"], 1);
+        assert_eq!(thread["samples"]["time"][0], 2.0);
+        let strings = thread["stringArray"].as_array().unwrap();
+        assert!(strings.contains(&Value::from("Work [App] \u{2329}void  ()\u{232a}")));
+        assert!(strings.contains(&Value::from("0x2000")));
+        assert_eq!(thread["markers"]["length"], 1);
+        let clrtype = thread["markers"]["data"][0]["clrtype"].as_u64().unwrap();
+        assert_eq!(
+            strings[clrtype as usize],
+            "System.InvalidOperationException"
+        );
+    }
+
+    #[test]
+    fn test_compressed_event_header() {
+        let data = [0x8f, 7, 42, 0x85, 0x01, 3, 0x85, 0x01, 3, 0xe8, 0x07, 5];
+        let mut reader = Reader::new(&data);
+        let mut header = EventHeader {
+            timestamp_qpc: 10,
+            ..Default::default()
+        };
+        read_compressed_event_header(&mut reader, &mut header).unwrap();
+        assert_eq!(header.metadata_id, 7);
+        assert_eq!(header.tid, 133);
+        assert_eq!(header.stack_id, 3);
+        assert_eq!(header.timestamp_qpc, 1010);
+        assert_eq!(header.payload_size, 5);
+        assert!(reader.is_at_end());
+    }
+}
@@ -33,10 +33,20 @@ pub fn convert<C: Read + Seek>(
 ) -> Result<Profile, Error> {
     let perf_file = PerfFileReader::parse_file(cursor)?;
 
-    let arch = perf_file.perf_file.arch().ok().flatten();
+    // Linux perf stores the `uname -m` value ("aarch64"), whereas simpleperf
+    // stores its own architecture name ("arm64").
+    let arch = match &profile_creation_props.override_arch {
+        Some(arch) => Some(arch.clone()),
+        None => perf_file
+            .perf_file
+            .arch()
+            .ok()
+            .flatten()
+            .map(ToOwned::to_owned),
+    };
 
-    let profile = match arch {
-        Some("aarch64") => {
+    let profile = match arch.as_deref() {
+        Some("aarch64" | "arm64") => {
             let cache = framehop::aarch64::CacheAarch64::new();
             convert_impl::<framehop::aarch64::UnwinderAarch64<MmapRangeOrVec>, ConvertRegsAarch64, _>(
                 perf_file,
@@ -48,7 +58,7 @@ pub fn convert<C: Read + Seek>(
             )
         }
         _ => {
-            if arch != Some("x86_64") {
+            if arch.as_deref() != Some("x86_64") {
                 eprintln!(
                     "Unknown arch {}, dwarf-based unwinding may be incorrect.",
                     arch.unwrap_or_default()
@@ -92,7 +102,13 @@ where
         .unwrap()
         .map_or(0, |r| r.first_sample_time);
     let endian = perf_file.endian();
-    let simpleperf_meta_info = perf_file.simpleperf_meta_info().ok().flatten();
+    let simpleperf_meta_info = match perf_file.simpleperf_meta_info() {
+        Ok(meta_info) => meta_info,
+        Err(err) => {
+            eprintln!("Could not read simpleperf's META_INFO section: {err}");
+            None
+        }
+    };
     let is_simpleperf = simpleperf_meta_info.is_some();
     let call_chain_return_addresses_are_preadjusted = is_simpleperf;
 
@@ -105,7 +121,15 @@ where
         eprintln!("event {event_name}");
     }
     let interpretation = EventInterpretation::divine_from_attrs(attributes);
-    let simpleperf_symbol_tables = perf_file.simpleperf_symbol_tables().ok().flatten();
+    // The symbols from simpleperf's FILE or FILE2 section, including the ones
+    // of the JIT symfiles.
+    let simpleperf_symbol_tables = match perf_file.simpleperf_symbol_tables() {
+        Ok(symbol_tables) => symbol_tables,
+        Err(err) => {
+            eprintln!("Could not read the symbol tables from simpleperf's FILE section: {err}");
+            None
+        }
+    };
     let reference_timestamp = if let Some(seconds_since_unix_epoch) =
         get_simpleperf_timestamp(simpleperf_meta_info.as_ref())
     {
@@ -178,13 +202,18 @@ where
         interpretation.clone(),
         simpleperf_symbol_tables,
         call_chain_return_addresses_are_preadjusted,
+        C::PTR_AUTH_STRIPPER,
     );
 
-    if let Some(android_version) = simpleperf_meta_info
-        .as_ref()
-        .and_then(|mi| mi.get("android_version"))
-    {
-        converter.set_os_name(&format!("Android {android_version}"));
+    if let Some(simpleperf_meta_info) = simpleperf_meta_info.as_ref() {
+        if let Some(android_version) = simpleperf_meta_info.get("android_version") {
+            converter.set_os_name(&format!("Android {android_version}"));
+        }
+        for (key, label) in SIMPLEPERF_META_INFO_LABELS {
+            if let Some(value) = simpleperf_meta_info.get(key) {
+                converter.add_extra_meta_info("Simpleperf", label, value);
+            }
+        }
     }
 
     let mut last_timestamp = 0;
@@ -209,7 +238,7 @@ where
         match parsed_record {
             EventRecord::Sample(e) => {
                 if attr_index == interpretation.main_event_attr_index {
-                    converter.handle_main_event_sample::<C>(&e);
+                    converter.handle_main_event_sample::<C>(&e, &[]);
                 } else if Some(attr_index) == interpretation.sched_switch_attr_index {
                     converter.handle_sched_switch_sample::<C>(&e);
                 }
@@ -266,6 +295,21 @@ where
     converter.finish()
 }
 
+/// The entries of simpleperf's META_INFO section which are shown in the
+/// profile's metadata, with their labels.
+const SIMPLEPERF_META_INFO_LABELS: &[(&str, &str)] = &[
+    ("simpleperf_version", "Simpleperf version"),
+    ("product_props", "Device"),
+    ("android_build_fingerprint", "Android build"),
+    ("kernel_version", "Kernel version"),
+    ("app_package_name", "App"),
+    ("app_type", "App type"),
+    ("event_type_info", "Events"),
+    ("clockid", "Clock"),
+    ("system_wide_collection", "System-wide"),
+    ("trace_offcpu", "Off-CPU samples"),
+];
+
 fn get_simpleperf_timestamp(meta_info: Option<&HashMap<&str, &str>>) -> Option<f64> {
     let meta_info = meta_info?;
     let timestamp_str = meta_info.get("timestamp")?;
@@ -0,0 +1,81 @@
+//! samply is a command line CPU profiler. This crate contains the `samply`
+//! binary, and also lets other programs record profiles with [`Recorder`],
+//! for example test harnesses and benchmark runners.
+//!
+//! ```no_run
+//! # #[cfg(not(target_os = "windows"))]
+//! # fn main() -> Result<(), samply::RecordError> {
+//! let recording = samply::Recorder::launch("./my-benchmark")
+//!     .arg("--quick")
+//!     .output("benchmark-profile.json.gz")
+//!     .record()?;
+//! let summary = recording.profile.summary(10, 1);
+//! println!(
+//!     "Exit status {}, {} samples, saved to {:?}",
+//!     recording.exit_status, summary.sample_count, recording.profile_path
+//! );
+//! # Ok(())
+//! # }
+//! # #[cfg(target_os = "windows")]
+//! # fn main() {}
+//! ```
+//!
+//! The recorded [`Profile`] is the one from the `fxprof-processed-profile`
+//! crate, which is re-exported here together with the types of its summary.
+//!
+//! Recording through the library is supported on Linux and macOS.
+
+#[cfg(target_os = "macos")]
+mod mac;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod linux;
+
+#[cfg(target_os = "windows")]
+mod windows;
+
+mod annotate;
+mod attach_data;
+#[cfg(any(
+    target_os = "android",
+    target_os = "macos",
+    target_os = "linux",
+    target_os = "windows"
+))]
+mod cargo;
+mod cli;
+mod export_symbols;
+mod flamegraph;
+mod follow;
+mod import;
+mod linux_shared;
+mod merge;
+mod name;
+mod pprof;
+mod profile_chunks;
+mod profile_json_preparse;
+mod profile_query;
+#[cfg(any(target_os = "android", target_os = "macos", target_os = "linux"))]
+mod recorder;
+mod report;
+mod search_index;
+mod server;
+mod session_dir;
+mod shared;
+mod split;
+mod stats;
+mod symbolicate;
+mod symbolication_sandbox;
+mod syscall_log;
+mod validate;
+mod wakegraph;
+
+#[doc(hidden)]
+pub use cli::cli_main;
+pub use fxprof_processed_profile;
+pub use fxprof_processed_profile::{
+    HotFrame, HotFrameLocation, ProcessSummary, Profile, ProfileSummary,
+};
+#[cfg(any(target_os = "android", target_os = "macos", target_os = "linux"))]
+pub use recorder::{Recorder, Recording};
+pub use shared::record_error::RecordError;
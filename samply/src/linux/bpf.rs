@@ -0,0 +1,628 @@
+//! A sampling backend which counts stacks in the kernel. A BPF program is
+//! attached to a cpu-clock perf event on every CPU. On each sample, it stores
+//! the kernel and user stacks in a stack trace map and increments the count of
+//! the (thread, user stack, kernel stack) key in a hash map. We drain both maps
+//! periodically, so only the distinct stacks are copied to user space, instead
+//! of the stack memory of every sample.
+//!
+//! There are two pairs of these maps, and a third map says which pair the
+//! program writes to. Each drain switches the program over to the other pair
+//! and then empties the previous one, so that we never delete a stack which a
+//! new sample still refers to, and never miss an increment of a count.
+//!
+//! The kernel walks user stacks with frame pointers, so code without frame
+//! pointers gets truncated stacks. We don't need any BPF tooling for this: the
+//! program is small enough to be written out as raw instructions.
+
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+use std::{io, mem, thread};
+
+use libc::{c_int, c_long, c_void, syscall, SYS_bpf};
+
+use super::sys::*;
+
+const BPF_MAP_CREATE: c_int = 0;
+const BPF_MAP_LOOKUP_ELEM: c_int = 1;
+const BPF_MAP_UPDATE_ELEM: c_int = 2;
+const BPF_MAP_DELETE_ELEM: c_int = 3;
+const BPF_MAP_GET_NEXT_KEY: c_int = 4;
+const BPF_PROG_LOAD: c_int = 5;
+
+const BPF_MAP_TYPE_HASH: u32 = 1;
+const BPF_MAP_TYPE_ARRAY: u32 = 2;
+const BPF_MAP_TYPE_STACK_TRACE: u32 = 7;
+const BPF_PROG_TYPE_PERF_EVENT: u32 = 7;
+
+const BPF_FUNC_MAP_LOOKUP_ELEM: i32 = 1;
+const BPF_FUNC_MAP_UPDATE_ELEM: i32 = 2;
+const BPF_FUNC_GET_CURRENT_PID_TGID: i32 = 14;
+const BPF_FUNC_GET_STACKID: i32 = 27;
+
+const BPF_F_USER_STACK: i32 = 1 << 8;
+const BPF_ANY: u64 = 0;
+const BPF_NOEXIST: i32 = 1;
+const BPF_PSEUDO_MAP_FD: u8 = 1;
+
+/// The maximum depth of the stacks in the stack trace map. This is the
+/// kernel's default for `kernel.perf_event_max_stack`.
+const MAX_STACK_DEPTH: usize = 127;
+
+/// The maximum number of distinct stacks, and of distinct counted keys,
+/// between two drains. Samples beyond that are dropped.
+const MAX_ENTRIES: u32 = 16384;
+
+/// How long we wait after switching the program to the other pair of maps,
+/// before we drain the previous pair. The program runs for a few microseconds
+/// in the interrupt of a sample, so all runs which started before the switch
+/// are done by then.
+const BUFFER_SWITCH_GRACE_PERIOD: Duration = Duration::from_millis(1);
+
+/// The key of the counts map, as written by the BPF program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(C)]
+struct CountKey {
+    tid: u32,
+    pid: u32,
+    user_stack_id: i32,
+    kernel_stack_id: i32,
+}
+
+/// The stacks of a thread which were sampled `count` times since the
+/// previous drain. Both stacks are leaf-first, and either can be empty.
+#[derive(Debug, Clone)]
+pub struct AggregatedSample {
+    pub pid: u32,
+    pub tid: u32,
+    pub kernel_stack: Vec<u64>,
+    pub user_stack: Vec<u64>,
+    pub count: u64,
+}
+
+#[repr(C)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+struct MapElemAttr {
+    map_fd: u32,
+    _padding: u32,
+    key: u64,
+    value_or_next_key: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_count: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct BpfInsn {
+    code: u8,
+    regs: u8,
+    offset: i16,
+    imm: i32,
+}
+
+/// The frame pointer register, which points to the end of the program's stack.
+const FP: u8 = 10;
+
+const fn insn(code: u8, dst: u8, src: u8, offset: i16, imm: i32) -> BpfInsn {
+    #[cfg(target_endian = "little")]
+    let regs = (src << 4) | dst;
+    #[cfg(target_endian = "big")]
+    let regs = (dst << 4) | src;
+    BpfInsn {
+        code,
+        regs,
+        offset,
+        imm,
+    }
+}
+
+/// `dst = src`
+const fn mov64_reg(dst: u8, src: u8) -> BpfInsn {
+    insn(0xbf, dst, src, 0, 0)
+}
+
+/// `dst = imm`
+const fn mov64_imm(dst: u8, imm: i32) -> BpfInsn {
+    insn(0xb7, dst, 0, 0, imm)
+}
+
+/// `dst += imm`
+const fn add64_imm(dst: u8, imm: i32) -> BpfInsn {
+    insn(0x07, dst, 0, 0, imm)
+}
+
+/// `dst <<= imm`
+const fn lsh64_imm(dst: u8, imm: i32) -> BpfInsn {
+    insn(0x67, dst, 0, 0, imm)
+}
+
+/// `dst >>= imm`
+const fn rsh64_imm(dst: u8, imm: i32) -> BpfInsn {
+    insn(0x77, dst, 0, 0, imm)
+}
+
+/// `dst = *(u32 *)(src + offset)`
+const fn load32(dst: u8, src: u8, offset: i16) -> BpfInsn {
+    insn(0x61, dst, src, offset, 0)
+}
+
+/// `*(u32 *)(dst + offset) = src`
+const fn store32(dst: u8, offset: i16, src: u8) -> BpfInsn {
+    insn(0x63, dst, src, offset, 0)
+}
+
+/// `*(u64 *)(dst + offset) = src`
+const fn store64(dst: u8, offset: i16, src: u8) -> BpfInsn {
+    insn(0x7b, dst, src, offset, 0)
+}
+
+/// `lock *(u64 *)(dst + offset) += src`
+const fn atomic_add64(dst: u8, offset: i16, src: u8) -> BpfInsn {
+    insn(0xdb, dst, src, offset, 0)
+}
+
+/// `if dst == imm goto +offset`
+const fn jump_if_eq_imm(dst: u8, imm: i32, offset: i16) -> BpfInsn {
+    insn(0x15, dst, 0, offset, imm)
+}
+
+/// `goto +offset`
+const fn jump(offset: i16) -> BpfInsn {
+    insn(0x05, 0, 0, offset, 0)
+}
+
+const fn call(helper: i32) -> BpfInsn {
+    insn(0x85, 0, 0, 0, helper)
+}
+
+const fn exit() -> BpfInsn {
+    insn(0x95, 0, 0, 0, 0)
+}
+
+/// `dst = map`, as two instructions. The kernel replaces the map fd with the
+/// address of the map when the program is loaded.
+const fn load_map_fd(dst: u8, map_fd: RawFd) -> [BpfInsn; 2] {
+    [
+        insn(0x18, dst, BPF_PSEUDO_MAP_FD, 0, map_fd),
+        insn(0, 0, 0, 0, 0),
+    ]
+}
+
+/// Assembles the program which runs on each sample. In C, it would be:
+///
+/// ```c
+/// u64 pid_tgid = bpf_get_current_pid_tgid();
+/// if ((u32)pid_tgid == 0) return 0; // The idle task.
+/// struct count_key key = { .tid = pid_tgid, .pid = pid_tgid >> 32 };
+/// u32 zero = 0;
+/// u32 *buffer = bpf_map_lookup_elem(&buffer_index, &zero);
+/// if (!buffer) return 0;
+/// if (*buffer == 1) {
+///     count_sample(ctx, &key, &stacks_1, &counts_1);
+/// } else {
+///     count_sample(ctx, &key, &stacks_0, &counts_0);
+/// }
+/// return 0;
+/// ```
+///
+/// The key lives at fp-16, the initial count at fp-24 and `zero` at fp-28.
+fn sampling_program(buffer_index_fd: RawFd, buffers: [(RawFd, RawFd); 2]) -> Vec<BpfInsn> {
+    let [first, second] =
+        buffers.map(|(stacks_fd, counts_fd)| count_sample_insns(stacks_fd, counts_fd));
+    // The prologue has 18 instructions, and the first body is followed by a
+    // jump over the second one.
+    let end = 18 + first.len() + 1 + second.len();
+    let jump_to_end_from = |index: usize| (end - index - 1) as i16;
+
+    let mut insns = vec![
+        mov64_reg(6, 1),
+        call(BPF_FUNC_GET_CURRENT_PID_TGID),
+        mov64_reg(1, 0),
+        lsh64_imm(1, 32),
+        jump_if_eq_imm(1, 0, jump_to_end_from(4)),
+        store32(FP, -16, 0),
+        rsh64_imm(0, 32),
+        store32(FP, -12, 0),
+        mov64_imm(1, 0),
+        store32(FP, -28, 1),
+    ];
+    insns.extend(load_map_fd(1, buffer_index_fd));
+    insns.extend([
+        mov64_reg(2, FP),
+        add64_imm(2, -28),
+        call(BPF_FUNC_MAP_LOOKUP_ELEM),
+        jump_if_eq_imm(0, 0, jump_to_end_from(15)),
+        load32(0, 0, 0),
+        jump_if_eq_imm(0, 1, first.len() as i16 + 1), // to the second body
+    ]);
+    insns.extend(first);
+    insns.push(jump(second.len() as i16));
+    insns.extend(second);
+    insns.extend([mov64_imm(0, 0), exit()]);
+    insns
+}
+
+/// Assembles the body of [`sampling_program`] for one pair of maps, which
+/// continues after its last instruction in all cases. In C, it would be:
+///
+/// ```c
+/// key.user_stack_id = bpf_get_stackid(ctx, stacks, BPF_F_USER_STACK);
+/// key.kernel_stack_id = bpf_get_stackid(ctx, stacks, 0);
+/// u64 *count = bpf_map_lookup_elem(counts, &key);
+/// if (count) {
+///     __sync_fetch_and_add(count, 1);
+/// } else {
+///     u64 one = 1;
+///     bpf_map_update_elem(counts, &key, &one, BPF_NOEXIST);
+/// }
+/// ```
+fn count_sample_insns(stacks_fd: RawFd, counts_fd: RawFd) -> Vec<BpfInsn> {
+    let mut insns = vec![mov64_reg(1, 6)];
+    insns.extend(load_map_fd(2, stacks_fd));
+    insns.extend([
+        mov64_imm(3, BPF_F_USER_STACK),
+        call(BPF_FUNC_GET_STACKID),
+        store32(FP, -8, 0),
+        mov64_reg(1, 6),
+    ]);
+    insns.extend(load_map_fd(2, stacks_fd));
+    insns.extend([
+        mov64_imm(3, 0),
+        call(BPF_FUNC_GET_STACKID),
+        store32(FP, -4, 0),
+    ]);
+    insns.extend(load_map_fd(1, counts_fd));
+    insns.extend([
+        mov64_reg(2, FP),
+        add64_imm(2, -16),
+        call(BPF_FUNC_MAP_LOOKUP_ELEM),
+        jump_if_eq_imm(0, 0, 3), // to the insert
+        mov64_imm(1, 1),
+        atomic_add64(0, 0, 1),
+        jump(10), // past the insert
+        mov64_imm(1, 1),
+        store64(FP, -24, 1),
+    ]);
+    insns.extend(load_map_fd(1, counts_fd));
+    insns.extend([
+        mov64_reg(2, FP),
+        add64_imm(2, -16),
+        mov64_reg(3, FP),
+        add64_imm(3, -24),
+        mov64_imm(4, BPF_NOEXIST),
+        call(BPF_FUNC_MAP_UPDATE_ELEM),
+    ]);
+    insns
+}
+
+fn sys_bpf<T>(cmd: c_int, attr: &mut T) -> io::Result<c_long> {
+    let result = unsafe {
+        syscall(
+            SYS_bpf,
+            cmd,
+            attr as *mut T as *mut c_void,
+            mem::size_of::<T>() as u32,
+        )
+    };
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        result => Ok(result),
+    }
+}
+
+fn create_map(
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+) -> io::Result<OwnedFd> {
+    let mut attr = MapCreateAttr {
+        map_type,
+        key_size,
+        value_size,
+        max_entries,
+        map_flags: 0,
+    };
+    let fd = sys_bpf(BPF_MAP_CREATE, &mut attr)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+fn map_elem_attr<K, V>(map: &OwnedFd, key: *const K, value_or_next_key: *mut V) -> MapElemAttr {
+    MapElemAttr {
+        map_fd: map.as_raw_fd() as u32,
+        _padding: 0,
+        key: key as u64,
+        value_or_next_key: value_or_next_key as u64,
+        flags: 0,
+    }
+}
+
+fn lookup_elem<K, V>(map: &OwnedFd, key: &K, value: &mut V) -> bool {
+    let mut attr = map_elem_attr(map, key, value);
+    sys_bpf(BPF_MAP_LOOKUP_ELEM, &mut attr).is_ok()
+}
+
+fn update_elem<K, V>(map: &OwnedFd, key: &K, value: &V) -> io::Result<()> {
+    let mut attr = map_elem_attr(map, key, value as *const V as *mut V);
+    attr.flags = BPF_ANY;
+    sys_bpf(BPF_MAP_UPDATE_ELEM, &mut attr).map(|_| ())
+}
+
+fn delete_elem<K>(map: &OwnedFd, key: &K) {
+    let mut attr = map_elem_attr::<K, u8>(map, key, std::ptr::null_mut());
+    let _ = sys_bpf(BPF_MAP_DELETE_ELEM, &mut attr);
+}
+
+/// Returns all keys of the map. If `key` is null, GET_NEXT_KEY returns the first key.
+fn map_keys<K: Copy + Default>(map: &OwnedFd) -> Vec<K> {
+    let mut keys = Vec::new();
+    let mut next_key = K::default();
+    loop {
+        let key = keys.last().map_or(std::ptr::null(), |key| key as *const K);
+        let mut attr = map_elem_attr(map, key, &mut next_key);
+        if sys_bpf(BPF_MAP_GET_NEXT_KEY, &mut attr).is_err() {
+            return keys;
+        }
+        keys.push(next_key);
+    }
+}
+
+fn load_program(insns: &[BpfInsn]) -> io::Result<OwnedFd> {
+    let license = b"GPL\0";
+    let mut log = vec![0u8; 65536];
+    let mut attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_PERF_EVENT,
+        insn_count: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 1,
+        log_size: log.len() as u32,
+        log_buf: log.as_mut_ptr() as u64,
+        kern_version: 0,
+    };
+    match sys_bpf(BPF_PROG_LOAD, &mut attr) {
+        Ok(fd) => Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) }),
+        Err(err) => {
+            let length = memchr::memchr(b'\0', &log).unwrap_or(log.len());
+            let log = String::from_utf8_lossy(&log[..length]);
+            Err(io::Error::new(
+                err.kind(),
+                format!("{err}, verifier log:\n{}", log.trim_end()),
+            ))
+        }
+    }
+}
+
+/// Opens a cpu-clock perf event for all threads on the CPU, which samples at
+/// `frequency` Hz and runs `program` on each sample.
+fn open_cpu_clock_event(cpu: u32, frequency: u32, program: &OwnedFd) -> io::Result<OwnedFd> {
+    let mut attr: PerfEventAttr = unsafe { mem::zeroed() };
+    attr.size = mem::size_of::<PerfEventAttr>() as u32;
+    attr.kind = PERF_TYPE_SOFTWARE;
+    attr.config = PERF_COUNT_SW_CPU_CLOCK;
+    attr.sample_period_or_freq = frequency as u64;
+    attr.flags = PERF_ATTR_FLAG_DISABLED | PERF_ATTR_FLAG_FREQ;
+
+    let fd = sys_perf_event_open(&attr, -1, cpu as c_int, -1, PERF_FLAG_FD_CLOEXEC);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let raw_fd = fd.as_raw_fd();
+    unsafe {
+        if libc::ioctl(raw_fd, PERF_EVENT_IOC_SET_BPF as _, program.as_raw_fd()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::ioctl(raw_fd, PERF_EVENT_IOC_ENABLE as _) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(fd)
+}
+
+/// Parses a CPU list like `0-3,5` from `/sys/devices/system/cpu/online`.
+pub(super) fn parse_cpu_list(list: &str) -> Option<Vec<u32>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',') {
+        match range.split_once('-') {
+            Some((start, end)) => cpus.extend(start.parse::<u32>().ok()?..=end.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+fn online_cpus() -> Vec<u32> {
+    std::fs::read_to_string("/sys/devices/system/cpu/online")
+        .ok()
+        .and_then(|list| parse_cpu_list(&list))
+        .unwrap_or_else(|| (0..num_cpus::get() as u32).collect())
+}
+
+/// One of the two pairs of maps which the program writes to.
+struct MapPair {
+    stacks: OwnedFd,
+    counts: OwnedFd,
+}
+
+impl MapPair {
+    fn create() -> io::Result<Self> {
+        let stacks = create_map(
+            BPF_MAP_TYPE_STACK_TRACE,
+            mem::size_of::<u32>() as u32,
+            (MAX_STACK_DEPTH * mem::size_of::<u64>()) as u32,
+            MAX_ENTRIES,
+        )?;
+        let counts = create_map(
+            BPF_MAP_TYPE_HASH,
+            mem::size_of::<CountKey>() as u32,
+            mem::size_of::<u64>() as u32,
+            MAX_ENTRIES,
+        )?;
+        Ok(Self { stacks, counts })
+    }
+
+    fn read_stack(&self, stack_id: i32) -> Vec<u64> {
+        let mut addresses = [0u64; MAX_STACK_DEPTH];
+        if !lookup_elem(&self.stacks, &(stack_id as u32), &mut addresses) {
+            return Vec::new();
+        }
+        let length = addresses
+            .iter()
+            .position(|address| *address == 0)
+            .unwrap_or(MAX_STACK_DEPTH);
+        addresses[..length].to_vec()
+    }
+}
+
+/// The BPF program, its maps, and the perf events it's attached to. Sampling
+/// stops when this is dropped.
+pub struct BpfSampler {
+    /// A single-element array with the index of the pair in `buffers` which
+    /// the program currently writes to.
+    buffer_index: OwnedFd,
+    buffers: [MapPair; 2],
+    active_buffer: usize,
+    _program: OwnedFd,
+    _events: Vec<OwnedFd>,
+}
+
+impl BpfSampler {
+    /// Loads the program and starts sampling all CPUs at `frequency` Hz.
+    pub fn open(frequency: u32) -> io::Result<Self> {
+        let buffer_index = create_map(
+            BPF_MAP_TYPE_ARRAY,
+            mem::size_of::<u32>() as u32,
+            mem::size_of::<u32>() as u32,
+            1,
+        )?;
+        let buffers = [MapPair::create()?, MapPair::create()?];
+        let program = load_program(&sampling_program(
+            buffer_index.as_raw_fd(),
+            buffers
+                .each_ref()
+                .map(|pair| (pair.stacks.as_raw_fd(), pair.counts.as_raw_fd())),
+        ))?;
+        let events = online_cpus()
+            .into_iter()
+            .map(|cpu| open_cpu_clock_event(cpu, frequency, &program))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self {
+            buffer_index,
+            buffers,
+            active_buffer: 0,
+            _program: program,
+            _events: events,
+        })
+    }
+
+    /// Calls `f` for each counted key since the previous drain, and resets the
+    /// counts. The samples whose stack didn't fit into the stack map are lost.
+    pub fn drain(&mut self, mut f: impl FnMut(AggregatedSample)) {
+        let drained = self.active_buffer;
+        let next = 1 - drained;
+        if update_elem(&self.buffer_index, &0u32, &(next as u32)).is_err() {
+            return;
+        }
+        self.active_buffer = next;
+        thread::sleep(BUFFER_SWITCH_GRACE_PERIOD);
+
+        // Nothing writes to this pair anymore until the next drain, so we can
+        // empty it completely.
+        let pair = &self.buffers[drained];
+        let keys: Vec<CountKey> = map_keys(&pair.counts);
+        let mut stack_cache: HashMap<i32, Vec<u64>> = HashMap::new();
+        for key in keys {
+            let mut count = 0u64;
+            let found = lookup_elem(&pair.counts, &key, &mut count);
+            delete_elem(&pair.counts, &key);
+            if !found || count == 0 {
+                continue;
+            }
+            let mut get_stack = |stack_id: i32| {
+                if stack_id < 0 {
+                    // The stack was empty, e.g. the kernel stack of a sample in
+                    // user code, or it couldn't be stored.
+                    return Vec::new();
+                }
+                stack_cache
+                    .entry(stack_id)
+                    .or_insert_with(|| pair.read_stack(stack_id))
+                    .clone()
+            };
+            let kernel_stack = get_stack(key.kernel_stack_id);
+            let user_stack = get_stack(key.user_stack_id);
+            f(AggregatedSample {
+                pid: key.pid,
+                tid: key.tid,
+                kernel_stack,
+                user_stack,
+                count,
+            });
+        }
+        // This includes the stacks of samples which didn't fit into the counts map.
+        for stack_id in map_keys::<u32>(&pair.stacks) {
+            delete_elem(&pair.stacks, &stack_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,5\n"), Some(vec![0, 1, 2, 3, 5]));
+        assert_eq!(parse_cpu_list("0"), Some(vec![0]));
+        assert_eq!(parse_cpu_list(""), None);
+    }
+
+    #[test]
+    fn test_sampling_program_jumps() {
+        let insns = sampling_program(3, [(4, 5), (6, 7)]);
+        assert_eq!(insns.len(), 83);
+        // The first body is at 18..49 and the second one at 50..81.
+        let jumps = [
+            // The jumps to the end must land on `r0 = 0`, the second-to-last instruction.
+            (4, 81),
+            (15, 81),
+            (49, 81),
+            // To the second body.
+            (17, 50),
+            // To the insert, and past it to the end of the body.
+            (35, 39),
+            (38, 49),
+            (67, 71),
+            (70, 81),
+        ];
+        for (index, target) in jumps {
+            let offset = insns[index].offset as usize;
+            assert_eq!(index + 1 + offset, target);
+        }
+        assert_eq!(insns[81].code, 0xb7);
+        assert_eq!(insns[82].code, 0x95);
+        // Each body uses its own pair of maps.
+        assert_eq!(insns[19].imm, 4);
+        assert_eq!(insns[51].imm, 6);
+    }
+}
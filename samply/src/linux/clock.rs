@@ -0,0 +1,43 @@
+use std::time::{Duration, SystemTime};
+
+use crate::shared::recording_props::TimestampClock;
+
+/// The clock id which perf uses for the event timestamps.
+pub fn clock_id(clock: TimestampClock) -> libc::clockid_t {
+    match clock {
+        TimestampClock::Monotonic => libc::CLOCK_MONOTONIC,
+        TimestampClock::Boottime => libc::CLOCK_BOOTTIME,
+    }
+}
+
+pub fn clock_name(clock: TimestampClock) -> &'static str {
+    match clock {
+        TimestampClock::Monotonic => "CLOCK_MONOTONIC",
+        TimestampClock::Boottime => "CLOCK_BOOTTIME",
+    }
+}
+
+/// Returns the current time of the given clock, in nanoseconds.
+pub fn now_nanos(clock_id: libc::clockid_t) -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(clock_id, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Returns the wall-clock time at which the given clock was zero.
+///
+/// Raw perf timestamps are nanoseconds on the perf clock, so using this as the
+/// profile's reference timestamp makes every timestamp in the profile map to
+/// the correct wall-clock time.
+pub fn wall_clock_time_at_clock_zero(clock_id: libc::clockid_t) -> SystemTime {
+    // Read the clock between two reads of the wall clock, and use the midpoint,
+    // to reduce the error from being preempted between the reads.
+    let wall_before = SystemTime::now();
+    let clock_nanos = now_nanos(clock_id);
+    let wall_after = SystemTime::now();
+    let wall_now = wall_before + wall_after.duration_since(wall_before).unwrap_or_default() / 2;
+    wall_now - Duration::from_nanos(clock_nanos)
+}
@@ -0,0 +1,34 @@
+//! The kinds of cores of the CPUs, read from sysfs.
+
+use super::bpf::parse_cpu_list;
+
+/// Returns a `(cpu, class)` pair for each CPU, where a higher class means a
+/// faster core. On Intel CPUs with hybrid cores, the performance cores are
+/// listed in `/sys/devices/cpu_core/cpus` and the efficiency cores in
+/// `/sys/devices/cpu_atom/cpus`. On arm64, each CPU has a `cpu_capacity`.
+/// Returns nothing if neither is available.
+pub fn cpu_core_classes() -> Vec<(u32, u32)> {
+    let read_cpu_list = |path: &str| {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|list| parse_cpu_list(&list))
+    };
+    if let (Some(core_cpus), Some(atom_cpus)) = (
+        read_cpu_list("/sys/devices/cpu_core/cpus"),
+        read_cpu_list("/sys/devices/cpu_atom/cpus"),
+    ) {
+        let core_cpus = core_cpus.into_iter().map(|cpu| (cpu, 1));
+        let atom_cpus = atom_cpus.into_iter().map(|cpu| (cpu, 0));
+        return core_cpus.chain(atom_cpus).collect();
+    }
+    let Some(cpus) = read_cpu_list("/sys/devices/system/cpu/possible") else {
+        return Vec::new();
+    };
+    cpus.into_iter()
+        .filter_map(|cpu| {
+            let path = format!("/sys/devices/system/cpu/cpu{cpu}/cpu_capacity");
+            let capacity = std::fs::read_to_string(path).ok()?;
+            Some((cpu, capacity.trim().parse().ok()?))
+        })
+        .collect()
+}
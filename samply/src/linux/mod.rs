@@ -1,7 +1,11 @@
+mod bpf;
+mod clock;
+mod core_types;
 mod perf_event;
 mod perf_group;
 mod proc_maps;
 mod process;
 pub mod profiler;
 mod sorter;
+mod steal_time;
 mod sys;
@@ -9,7 +9,9 @@ use std::{cmp, fmt, io, mem, ptr, slice};
 
 use libc::{self, c_void, pid_t};
 use linux_perf_data::linux_perf_event_reader;
-use linux_perf_event_reader::{Endianness, RawData, RawEventRecord, RecordParseInfo, RecordType};
+use linux_perf_event_reader::{
+    Endianness, RawData, RawDataU64, RawEventRecord, RecordParseInfo, RecordType,
+};
 
 use super::sys::*;
 
@@ -86,6 +88,10 @@ pub struct Perf {
     fd: RawFd,
     position: u64,
     parse_info: RecordParseInfo,
+    /// The raw `perf_event_attr`, for writing checkpoint files.
+    attr_bytes: Vec<u8>,
+    /// Set for tracepoint events, see [`PerfBuilder::tracepoint`].
+    tracepoint_index: Option<usize>,
 }
 
 impl Drop for Perf {
@@ -163,6 +169,9 @@ pub struct PerfBuilder {
     enable_on_exec: bool,
     exclude_kernel: bool,
     gather_context_switches: bool,
+    branch_call_stack: bool,
+    tracepoint: Option<(usize, u64)>,
+    clock_id: libc::clockid_t,
 }
 
 impl PerfBuilder {
@@ -227,6 +236,29 @@ impl PerfBuilder {
         self
     }
 
+    /// Captures the user-space call stack from the Last Branch Record with each
+    /// sample. This is only supported for hardware events, on CPUs with LBR
+    /// call stack support.
+    pub fn sample_branch_call_stack(mut self) -> Self {
+        self.branch_call_stack = true;
+        self
+    }
+
+    /// Opens the tracepoint with the given id instead of the sampling event.
+    /// Every hit of the tracepoint becomes a sample with the tracepoint's raw
+    /// data. `index` is returned by [`EventRef::tracepoint_index`] for the
+    /// events of this tracepoint. No mmap / comm / task / context switch
+    /// records are generated, because the sampling event already has them.
+    pub fn tracepoint(mut self, index: usize, id: u64) -> Self {
+        self.tracepoint = Some((index, id));
+        self
+    }
+
+    pub fn clock_id(mut self, clock_id: libc::clockid_t) -> Self {
+        self.clock_id = clock_id;
+        self
+    }
+
     pub fn open(self) -> io::Result<Perf> {
         let pid = self.pid;
         let cpu = self.cpu.map(|cpu| cpu as i32).unwrap_or(-1);
@@ -238,6 +270,9 @@ impl PerfBuilder {
         let start_disabled = self.start_disabled;
         let exclude_kernel = self.exclude_kernel;
         let gather_context_switches = self.gather_context_switches;
+        let branch_call_stack = self.branch_call_stack;
+        let tracepoint = self.tracepoint;
+        let clock_id = self.clock_id;
 
         // debug!(
         //     "Opening perf events; pid={}, cpu={}, frequency={}, stack_size={}, reg_mask=0x{:016X}, event_source={:?}, inherit={}, start_disabled={}...",
@@ -252,7 +287,7 @@ impl PerfBuilder {
         // );
 
         let max_sample_rate = Perf::max_sample_rate();
-        if let Some(max_sample_rate) = max_sample_rate {
+        if let (Some(max_sample_rate), None) = (max_sample_rate, tracepoint) {
             // debug!("Maximum sample rate: {}", max_sample_rate);
             if frequency > max_sample_rate {
                 let message = format!( "frequency can be at most {max_sample_rate} as configured in /proc/sys/kernel/perf_event_max_sample_rate" );
@@ -286,12 +321,16 @@ impl PerfBuilder {
         let mut attr: PerfEventAttr = unsafe { mem::zeroed() };
         attr.size = mem::size_of::<PerfEventAttr>() as u32;
 
-        match event_source {
-            EventSource::HwCpuCycles => {
+        match (tracepoint, event_source) {
+            (Some((_index, id)), _) => {
+                attr.kind = PERF_TYPE_TRACEPOINT;
+                attr.config = id;
+            }
+            (None, EventSource::HwCpuCycles) => {
                 attr.kind = PERF_TYPE_HARDWARE;
                 attr.config = PERF_COUNT_HW_CPU_CYCLES;
             }
-            EventSource::SwCpuClock => {
+            (None, EventSource::SwCpuClock) => {
                 attr.kind = PERF_TYPE_SOFTWARE;
                 attr.config = PERF_COUNT_SW_CPU_CLOCK;
             }
@@ -311,20 +350,36 @@ impl PerfBuilder {
             attr.sample_type |= PERF_SAMPLE_STACK_USER;
         }
 
+        if tracepoint.is_some() {
+            attr.sample_type |= PERF_SAMPLE_RAW;
+        }
+
+        if branch_call_stack && tracepoint.is_none() {
+            attr.sample_type |= PERF_SAMPLE_BRANCH_STACK;
+            attr.branch_sample_type = PERF_SAMPLE_BRANCH_USER | PERF_SAMPLE_BRANCH_CALL_STACK;
+        }
+
         attr.sample_regs_user = reg_mask;
         attr.sample_stack_user = stack_size;
-        attr.sample_period_or_freq = frequency;
-        attr.clock_id = libc::CLOCK_MONOTONIC;
-
-        attr.flags = PERF_ATTR_FLAG_DISABLED
-            | PERF_ATTR_FLAG_MMAP
-            | PERF_ATTR_FLAG_MMAP2
-            | PERF_ATTR_FLAG_MMAP_DATA
-            | PERF_ATTR_FLAG_COMM
-            | PERF_ATTR_FLAG_FREQ
-            | PERF_ATTR_FLAG_TASK
-            | PERF_ATTR_FLAG_SAMPLE_ID_ALL
-            | PERF_ATTR_FLAG_USE_CLOCKID;
+        attr.clock_id = clock_id;
+
+        if tracepoint.is_some() {
+            // Every hit of the tracepoint is a sample.
+            attr.sample_period_or_freq = 1;
+            attr.flags =
+                PERF_ATTR_FLAG_DISABLED | PERF_ATTR_FLAG_SAMPLE_ID_ALL | PERF_ATTR_FLAG_USE_CLOCKID;
+        } else {
+            attr.sample_period_or_freq = frequency;
+            attr.flags = PERF_ATTR_FLAG_DISABLED
+                | PERF_ATTR_FLAG_MMAP
+                | PERF_ATTR_FLAG_MMAP2
+                | PERF_ATTR_FLAG_MMAP_DATA
+                | PERF_ATTR_FLAG_COMM
+                | PERF_ATTR_FLAG_FREQ
+                | PERF_ATTR_FLAG_TASK
+                | PERF_ATTR_FLAG_SAMPLE_ID_ALL
+                | PERF_ATTR_FLAG_USE_CLOCKID;
+        }
 
         if self.enable_on_exec {
             attr.flags |= PERF_ATTR_FLAG_ENABLE_ON_EXEC;
@@ -338,7 +393,7 @@ impl PerfBuilder {
             attr.flags |= PERF_ATTR_FLAG_INHERIT;
         }
 
-        if gather_context_switches {
+        if gather_context_switches && tracepoint.is_none() {
             attr.flags |= PERF_ATTR_FLAG_CONTEX_SWITCH;
         }
 
@@ -407,6 +462,8 @@ impl PerfBuilder {
             fd,
             position: 0,
             parse_info,
+            attr_bytes: attr_bytes.to_vec(),
+            tracepoint_index: tracepoint.map(|(index, _id)| index),
         };
 
         if !start_disabled {
@@ -436,6 +493,9 @@ impl Perf {
             enable_on_exec: false,
             exclude_kernel: true,
             gather_context_switches: false,
+            branch_call_stack: false,
+            tracepoint: None,
+            clock_id: libc::CLOCK_MONOTONIC,
         }
     }
 
@@ -451,6 +511,14 @@ impl Perf {
         head != self.position
     }
 
+    pub fn attr_bytes(&self) -> &[u8] {
+        &self.attr_bytes
+    }
+
+    pub fn is_tracepoint(&self) -> bool {
+        self.tracepoint_index.is_some()
+    }
+
     #[inline]
     pub fn fd(&self) -> RawFd {
         self.fd
@@ -524,6 +592,7 @@ pub struct EventRef {
     prev_position: u64,
     position: u64,
     parse_info: RecordParseInfo,
+    tracepoint_index: Option<usize>,
 }
 
 impl fmt::Debug for EventRef {
@@ -551,6 +620,34 @@ impl EventRef {
 
         self.event_location.get(buffer, self.parse_info)
     }
+
+    /// The index which was passed to [`PerfBuilder::tracepoint`], if this
+    /// event comes from a tracepoint event.
+    pub fn tracepoint_index(&self) -> Option<usize> {
+        self.tracepoint_index
+    }
+}
+
+/// Reads the call sites from the LBR call stack of a sample record which was
+/// recorded with [`PerfBuilder::sample_branch_call_stack`], innermost call first.
+pub fn read_lbr_call_stack(record: &RawEventRecord, call_sites: &mut Vec<u64>) {
+    // The branch stack comes after the ip, pid/tid, time, cpu and period
+    // fields, which are always requested by `PerfBuilder::open`.
+    const BRANCH_STACK_INDEX: usize = 5;
+    // Each entry is a perf_branch_entry: from, to, flags.
+    const BRANCH_ENTRY_LEN: usize = 3;
+
+    call_sites.clear();
+    let data = RawDataU64::from_raw_data::<byteorder::NativeEndian>(record.data);
+    let Some(entry_count) = data.get(BRANCH_STACK_INDEX) else {
+        return;
+    };
+    for entry_index in 0..entry_count as usize {
+        match data.get(BRANCH_STACK_INDEX + 1 + entry_index * BRANCH_ENTRY_LEN) {
+            Some(from) => call_sites.push(from),
+            None => break,
+        }
+    }
 }
 
 pub struct EventIter<'a> {
@@ -580,6 +677,7 @@ impl<'a> Iterator for EventIter<'a> {
             prev_position,
             position: perf.position,
             parse_info: self.perf.parse_info,
+            tracepoint_index: self.perf.tracepoint_index,
         })
     }
 }
@@ -10,7 +10,7 @@ use linux_perf_data::linux_perf_event_reader::get_record_timestamp;
 use mio::unix::SourceFd;
 use mio::{Events, Interest, Poll, Token};
 
-use super::perf_event::{EventRef, EventSource, Perf};
+use super::perf_event::{EventRef, EventSource, Perf, PerfBuilder};
 use super::sorter::EventSorter;
 
 struct StoppedProcess(u32);
@@ -72,6 +72,10 @@ pub struct PerfGroup {
     stack_size: u32,
     regs_mask: u64,
     event_source: EventSource,
+    clock_id: libc::clockid_t,
+    lbr_call_stacks: bool,
+    /// The ids of the tracepoints which are opened for each sampling event.
+    tracepoint_ids: Vec<u64>,
     stopped_processes: Vec<StoppedProcess>,
 }
 
@@ -98,7 +102,15 @@ pub enum AttachMode {
 }
 
 impl PerfGroup {
-    pub fn new(frequency: u32, stack_size: u32, regs_mask: u64, event_source: EventSource) -> Self {
+    pub fn new(
+        frequency: u32,
+        stack_size: u32,
+        regs_mask: u64,
+        event_source: EventSource,
+        clock_id: libc::clockid_t,
+        lbr_call_stacks: bool,
+        tracepoint_ids: Vec<u64>,
+    ) -> Self {
         PerfGroup {
             event_sorter: EventSorter::new(),
             members: Default::default(),
@@ -108,19 +120,34 @@ impl PerfGroup {
             stack_size,
             event_source,
             regs_mask,
+            clock_id,
+            lbr_call_stacks,
+            tracepoint_ids,
             stopped_processes: Vec::new(),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn open(
         pid: u32,
         frequency: u32,
         stack_size: u32,
         event_source: EventSource,
         regs_mask: u64,
+        clock_id: libc::clockid_t,
+        lbr_call_stacks: bool,
+        tracepoint_ids: Vec<u64>,
         attach_mode: AttachMode,
     ) -> Result<Self, io::Error> {
-        let mut group = PerfGroup::new(frequency, stack_size, regs_mask, event_source);
+        let mut group = PerfGroup::new(
+            frequency,
+            stack_size,
+            regs_mask,
+            event_source,
+            clock_id,
+            lbr_call_stacks,
+            tracepoint_ids,
+        );
         group.open_process(pid, attach_mode)?;
         Ok(group)
     }
@@ -143,16 +170,21 @@ impl PerfGroup {
                 .sample_kernel()
                 .gather_context_switches()
                 .event_source(self.event_source)
+                .clock_id(self.clock_id)
                 .inherit_to_children()
                 .start_disabled();
 
             if attach_mode == AttachMode::AttachWithEnableOnExec {
                 builder = builder.enable_on_exec();
             }
+            if self.lbr_call_stacks {
+                builder = builder.sample_branch_call_stack();
+            }
 
-            let perf = builder.open()?;
+            let perf = builder.clone().open()?;
 
             perf_events.push((Some(cpu), perf));
+            self.open_tracepoints(&builder, Some(cpu), &mut perf_events)?;
         }
 
         if cpu_count * (threads.len() + 1) >= 1000 {
@@ -165,13 +197,18 @@ impl PerfGroup {
                     .sample_user_regs(self.regs_mask)
                     .sample_kernel()
                     .event_source(self.event_source)
+                    .clock_id(self.clock_id)
                     .start_disabled();
                 if attach_mode == AttachMode::AttachWithEnableOnExec {
                     builder = builder.enable_on_exec();
                 }
-                let perf = builder.open()?;
+                if self.lbr_call_stacks {
+                    builder = builder.sample_branch_call_stack();
+                }
+                let perf = builder.clone().open()?;
 
                 perf_events.push((None, perf));
+                self.open_tracepoints(&builder, None, &mut perf_events)?;
             }
         } else {
             for cpu in 0..cpu_count as u32 {
@@ -185,14 +222,19 @@ impl PerfGroup {
                         .sample_kernel()
                         .gather_context_switches()
                         .event_source(self.event_source)
+                        .clock_id(self.clock_id)
                         .inherit_to_children()
                         .start_disabled();
                     if attach_mode == AttachMode::AttachWithEnableOnExec {
                         builder = builder.enable_on_exec();
                     }
-                    let perf = builder.open()?;
+                    if self.lbr_call_stacks {
+                        builder = builder.sample_branch_call_stack();
+                    }
+                    let perf = builder.clone().open()?;
 
                     perf_events.push((Some(cpu), perf));
+                    self.open_tracepoints(&builder, Some(cpu), &mut perf_events)?;
                 }
             }
         }
@@ -210,6 +252,34 @@ impl PerfGroup {
         Ok(())
     }
 
+    /// Opens the tracepoint events for the same thread and CPU as `builder`.
+    fn open_tracepoints(
+        &self,
+        builder: &PerfBuilder,
+        cpu: Option<u32>,
+        perf_events: &mut Vec<(Option<u32>, Perf)>,
+    ) -> Result<(), io::Error> {
+        for (index, &id) in self.tracepoint_ids.iter().enumerate() {
+            let perf = builder.clone().tracepoint(index, id).open()?;
+            perf_events.push((cpu, perf));
+        }
+        Ok(())
+    }
+
+    /// Whether the samples of this group have an LBR call stack.
+    pub fn has_lbr_call_stacks(&self) -> bool {
+        self.lbr_call_stacks
+    }
+
+    /// The raw `perf_event_attr` of the group's sampling events. They only
+    /// differ in flags which don't affect how the records are parsed.
+    pub fn attr_bytes(&self) -> Option<&[u8]> {
+        self.members
+            .values()
+            .find(|member| !member.is_tracepoint())
+            .map(|member| member.attr_bytes())
+    }
+
     pub fn is_empty(&self) -> bool {
         self.members.is_empty()
     }
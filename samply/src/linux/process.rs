@@ -122,13 +122,22 @@ impl SuspendedLaunchedProcess {
                     panic!("short read on the execerr pipe")
                 }
                 Err(nix::errno::Errno::EINTR) => {}
-                Err(_) => std::process::exit(1),
+                Err(err) => return Err(err.into()),
             }
         }
 
         Ok(RunningProcess { pid: self.pid })
     }
 
+    /// Ends the child process without executing the command, e.g. because
+    /// profiling couldn't be started.
+    pub fn cancel(self) {
+        let pid = self.pid;
+        // Closing the resume pipe makes the child exit.
+        drop(self);
+        let _ = nix::sys::wait::waitpid(pid, None);
+    }
+
     /// Executed in the forked child process. This function never returns.
     fn run_child(
         recv_end_of_resume_pipe: OwnedFd,
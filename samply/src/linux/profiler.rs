@@ -1,35 +1,47 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::ops::Deref;
 use std::os::unix::process::ExitStatusExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::ExitStatus;
 use std::thread;
 use std::time::{Duration, SystemTime};
 
 use crossbeam_channel::{Receiver, Sender};
-use fxprof_processed_profile::ReferenceTimestamp;
+use fxprof_processed_profile::{Profile, ReferenceTimestamp, Timestamp};
 use linux_perf_data::linux_perf_event_reader::{
     CpuMode, Endianness, EventRecord, Mmap2FileId, Mmap2InodeAndVersion, Mmap2Record, RawData,
+    RecordType,
 };
 use nix::sys::wait::WaitStatus;
 use tokio::sync::oneshot;
 
-use super::perf_event::EventSource;
+use super::bpf::BpfSampler;
+use super::clock;
+use super::core_types;
+use super::perf_event::{read_lbr_call_stack, EventSource};
 use super::perf_group::{AttachMode, PerfGroup};
 use super::proc_maps;
 use super::process::SuspendedLaunchedProcess;
+use super::steal_time::StealTimeReader;
+use crate::linux_shared::checkpoint::CheckpointWriter;
 use crate::linux_shared::vdso::VdsoObject;
 use crate::linux_shared::{
-    ConvertRegs, Converter, EventInterpretation, MmapRangeOrVec, OffCpuIndicator,
+    ConvertRegs, Converter, EventInterpretation, MmapRangeOrVec, OffCpuIndicator, TracepointFormat,
+    DELETED_MAPPING_SUFFIX, FUTEX_ENTER_TRACEPOINT, FUTEX_EXIT_TRACEPOINT,
+    SETAFFINITY_ENTER_TRACEPOINT, SETAFFINITY_EXIT_TRACEPOINT,
 };
 use crate::server::{start_server_main, ServerProps};
 use crate::shared::ctrl_c::CtrlC;
+use crate::shared::record_error::RecordError;
 use crate::shared::recording_props::{
-    ProcessLaunchProps, ProfileCreationProps, RecordingMode, RecordingProps,
+    ProcessLaunchProps, ProfileCreationProps, RecordingMode, RecordingProps, TimestampClock,
 };
+use crate::shared::recording_schedule::{process_is_alive, RecordingSchedule};
+use crate::shared::recording_summary::print_recording_summary;
 use crate::shared::save_profile::save_profile_to_file;
 use crate::shared::symbol_props::SymbolProps;
+use crate::shared::time_zone::recording_start_meta_info;
 
 #[cfg(target_arch = "x86_64")]
 pub type ConvertRegsNative = crate::linux_shared::ConvertRegsX86_64;
@@ -43,23 +55,35 @@ pub fn start_recording(
     profile_creation_props: ProfileCreationProps,
     symbol_props: SymbolProps,
     server_props: Option<ServerProps>,
-) -> Result<ExitStatus, ()> {
+) -> Result<(ExitStatus, Profile), RecordError> {
+    if recording_props.bpf {
+        return start_bpf_recording(
+            recording_mode,
+            recording_props,
+            profile_creation_props,
+            symbol_props,
+            server_props,
+        );
+    }
+
     let process_launch_props = match recording_mode {
         RecordingMode::All => {
             // TODO: Implement, by sudo launching a helper process which opens cpu-wide perf events
-            eprintln!("Error: Profiling all processes is currently not supported on Linux.");
             eprintln!("You can profile processes which you launch via samply, or attach to a single process.");
-            std::process::exit(1)
+            eprintln!("With --bpf, samply can record all processes.");
+            return Err(RecordError::InvalidOptions(
+                "Profiling all processes is currently not supported on Linux.".to_string(),
+            ));
         }
         RecordingMode::Pid(pid) => {
-            start_profiling_pid(
+            let profile = start_profiling_pid(
                 pid,
                 recording_props,
                 profile_creation_props,
                 symbol_props,
                 server_props,
-            );
-            return Ok(ExitStatus::from_raw(0));
+            )?;
+            return Ok((ExitStatus::from_raw(0), profile));
         }
         RecordingMode::Launch(process_launch_props) => process_launch_props,
     };
@@ -73,6 +97,8 @@ pub fn start_recording(
         iteration_count,
     } = process_launch_props;
 
+    let tracepoint_formats = load_tracepoint_formats(&recording_props)?;
+
     if profile_creation_props.coreclr.any_enabled() {
         // We need to set DOTNET_PerfMapEnabled=2 in the environment if it's not already set.
         // TODO: implement unlink_aux_files for linux
@@ -90,7 +116,7 @@ pub fn start_recording(
     // The command will not start running until we tell it to.
     let process =
         SuspendedLaunchedProcess::launch_in_suspended_state(&command_name, &args, &env_vars)
-            .unwrap();
+            .map_err(RecordError::Launch)?;
     let pid = process.pid();
 
     // Create a channel for the observer thread to notify the main thread once
@@ -103,7 +129,13 @@ pub fn start_recording(
     // Launch the observer thread. This thread will manage the perf events.
     let output_file_copy = recording_props.output_file.clone();
     let interval = recording_props.interval;
-    let time_limit = recording_props.time_limit;
+    let schedule = RecordingSchedule::new(&recording_props);
+    let clock = recording_props.clock;
+    let lbr_call_stacks = recording_props.lbr_call_stacks;
+    let checkpoint_interval = recording_props.checkpoint_interval;
+    let contention = recording_props.contention;
+    let thread_affinity = recording_props.thread_affinity;
+    let process_sample_strides = recording_props.process_sample_strides();
     let initial_exec_name = command_name.to_string_lossy().to_string();
     let initial_cmdline: Vec<String> = std::iter::once(initial_exec_name.clone())
         .chain(args.iter().map(|arg| arg.to_string_lossy().to_string()))
@@ -115,7 +147,18 @@ pub fn start_recording(
     let initial_exec_name_and_cmdline = (initial_exec_name, initial_cmdline);
     let observer_thread = thread::spawn(move || {
         let unstable_presymbolicate = profile_creation_props.unstable_presymbolicate;
-        let mut converter = make_converter(interval, profile_creation_props);
+        let search_index = profile_creation_props.search_index;
+        let recording_summary = profile_creation_props.recording_summary;
+        let mut converter = make_converter(interval, clock, profile_creation_props);
+        let tracepoint_ids: Vec<u64> = tracepoint_formats.iter().map(|f| f.id).collect();
+        converter.set_tracepoint_formats(tracepoint_formats);
+        if contention {
+            converter.enable_contention_tracking();
+        }
+        if thread_affinity {
+            converter.enable_thread_affinity_tracking();
+        }
+        converter.set_process_sample_strides(process_sample_strides);
 
         // Wait for the initial pid to profile.
         let SamplerRequest::StartProfilingAnotherProcess(pid, attach_mode) =
@@ -124,8 +167,19 @@ pub fn start_recording(
             panic!("The first message should be a StartProfilingAnotherProcess")
         };
 
-        // Create the perf events, setting ENABLE_ON_EXEC.
-        let perf_group = init_profiler(interval, pid, attach_mode, &mut converter);
+        // Create the perf events, setting ENABLE_ON_EXEC. If this fails, we
+        // return without replying, and the main thread gets the error from us.
+        let (perf_group, checkpoint) = init_profiler(
+            interval,
+            clock,
+            lbr_call_stacks,
+            &tracepoint_ids,
+            pid,
+            attach_mode,
+            checkpoint_interval,
+            &output_file_copy,
+            &mut converter,
+        )?;
 
         // Tell the main thread to tell the child process to begin executing.
         profile_another_pid_reply_sender.send(true).unwrap();
@@ -141,14 +195,18 @@ pub fn start_recording(
         run_profiler(
             perf_group,
             converter,
+            checkpoint,
             &output_file_copy,
-            time_limit,
+            schedule,
             profile_another_pid_request_receiver,
             profile_another_pid_reply_sender,
             stop_receiver,
             unstable_presymbolicate,
+            search_index,
+            recording_summary,
+            clock,
             Some(initial_exec_name_and_cmdline),
-        );
+        )
     });
 
     // We're on the main thread here and the observer thread has just been launched.
@@ -160,23 +218,26 @@ pub fn start_recording(
             AttachMode::AttachWithEnableOnExec,
         ))
         .unwrap();
-    let _ = profile_another_pid_reply_receiver.recv().unwrap();
+    if profile_another_pid_reply_receiver.recv().is_err() {
+        // The observer thread couldn't start profiling, and returns the error.
+        process.cancel();
+        return observer_thread
+            .join()
+            .expect("couldn't join observer thread")
+            .map(|_profile| unreachable!("The observer thread only hangs up on errors"));
+    }
 
     // Now tell the child process to start executing.
     let process = match process.unsuspend_and_run() {
         Ok(process) => process,
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-            let command_name = command_name.to_string_lossy();
-            if command_name.starts_with('-') {
-                eprintln!("error: unexpected argument '{command_name}' found");
-            } else {
-                eprintln!("Error: Could not find an executable with the name {command_name}.");
-            }
-            std::process::exit(1)
-        }
-        Err(run_err) => {
-            eprintln!("Could not launch child process: {run_err}");
-            std::process::exit(1)
+        Err(err) => {
+            // Nothing will run in the perf events, so the observer thread
+            // stops once we tell it to.
+            let _ = profile_another_pid_request_sender
+                .send(SamplerRequest::StopProfilingOncePerfEventsExhausted);
+            ctrl_c_receiver.close();
+            let _ = observer_thread.join();
+            return Err(RecordError::launch(&command_name, err));
         }
     };
 
@@ -198,33 +259,37 @@ pub fn start_recording(
             break;
         }
         eprintln!("Running iteration {i} of {iteration_count}...");
-        let process =
-            SuspendedLaunchedProcess::launch_in_suspended_state(&command_name, &args, &env_vars)
-                .unwrap();
+        let process = match SuspendedLaunchedProcess::launch_in_suspended_state(
+            &command_name,
+            &args,
+            &env_vars,
+        ) {
+            Ok(process) => process,
+            Err(err) => {
+                eprintln!("Could not launch child process: {err}");
+                break;
+            }
+        };
         let pid = process.pid();
 
         // Tell the sampler to start profiling another pid, and wait for it to signal us to go ahead.
-        profile_another_pid_request_sender
-            .send(SamplerRequest::StartProfilingAnotherProcess(
-                pid,
-                AttachMode::AttachWithEnableOnExec,
-            ))
-            .unwrap();
-        let succeeded = profile_another_pid_reply_receiver.recv().unwrap();
+        // The sampler is gone if the recording was stopped by --duration or --until-exit-of.
+        let request =
+            SamplerRequest::StartProfilingAnotherProcess(pid, AttachMode::AttachWithEnableOnExec);
+        if profile_another_pid_request_sender.send(request).is_err() {
+            break;
+        }
+        let succeeded = profile_another_pid_reply_receiver.recv().unwrap_or(false);
         if !succeeded {
             break;
         }
 
-        // Now tell the child process to start executing.
+        // Now tell the child process to start executing. The profile of the
+        // previous iterations is kept if this fails.
         let process = match process.unsuspend_and_run() {
             Ok(process) => process,
-            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-                let command_name = command_name.to_string_lossy();
-                eprintln!("Error: Could not find an executable with the name {command_name}.");
-                std::process::exit(1)
-            }
-            Err(run_err) => {
-                eprintln!("Could not launch child process: {run_err}");
+            Err(err) => {
+                eprintln!("{}", RecordError::launch(&command_name, err));
                 break;
             }
         };
@@ -232,9 +297,9 @@ pub fn start_recording(
         wait_status = process.wait().expect("couldn't wait for child");
     }
 
-    profile_another_pid_request_sender
-        .send(SamplerRequest::StopProfilingOncePerfEventsExhausted)
-        .unwrap();
+    // This fails if the sampler has already stopped, e.g. because of --duration.
+    let _ = profile_another_pid_request_sender
+        .send(SamplerRequest::StopProfilingOncePerfEventsExhausted);
 
     // The launched subprocess is done. From now on, we want to terminate if the user presses Ctrl+C.
     ctrl_c_receiver.close();
@@ -242,9 +307,9 @@ pub fn start_recording(
     // Now wait for the observer thread to quit. It will keep running until all
     // perf events are closed, which happens if all processes which the events
     // are attached to have quit.
-    observer_thread
+    let profile = observer_thread
         .join()
-        .expect("couldn't join observer thread");
+        .expect("couldn't join observer thread")?;
 
     if let Some(server_props) = server_props {
         let profile_filename = &recording_props.output_file;
@@ -261,7 +326,7 @@ pub fn start_recording(
         WaitStatus::Exited(_pid, exit_code) => ExitStatus::from_raw(exit_code),
         _ => ExitStatus::default(),
     };
-    Ok(exit_status)
+    Ok((exit_status, profile))
 }
 
 fn start_profiling_pid(
@@ -270,7 +335,9 @@ fn start_profiling_pid(
     profile_creation_props: ProfileCreationProps,
     symbol_props: SymbolProps,
     server_props: Option<ServerProps>,
-) {
+) -> Result<Profile, RecordError> {
+    let tracepoint_formats = load_tracepoint_formats(&recording_props)?;
+
     // When the first Ctrl+C is received, stop recording.
     let ctrl_c_receiver = CtrlC::observe_oneshot();
 
@@ -285,15 +352,38 @@ fn start_profiling_pid(
     let observer_thread = thread::spawn({
         move || {
             let interval = recording_props.interval;
-            let time_limit = recording_props.time_limit;
+            let schedule = RecordingSchedule::new(&recording_props);
+            let clock = recording_props.clock;
+            let lbr_call_stacks = recording_props.lbr_call_stacks;
             let unstable_presymbolicate = profile_creation_props.unstable_presymbolicate;
-            let mut converter = make_converter(interval, profile_creation_props);
+            let search_index = profile_creation_props.search_index;
+            let recording_summary = profile_creation_props.recording_summary;
+            let mut converter = make_converter(interval, clock, profile_creation_props);
+            let tracepoint_ids: Vec<u64> = tracepoint_formats.iter().map(|f| f.id).collect();
+            converter.set_tracepoint_formats(tracepoint_formats);
+            if recording_props.contention {
+                converter.enable_contention_tracking();
+            }
+            if recording_props.thread_affinity {
+                converter.enable_thread_affinity_tracking();
+            }
+            converter.set_process_sample_strides(recording_props.process_sample_strides());
             let SamplerRequest::StartProfilingAnotherProcess(pid, attach_mode) =
                 profile_another_pid_request_receiver.recv().unwrap()
             else {
                 panic!("The first message should be a StartProfilingAnotherProcess")
             };
-            let perf_group = init_profiler(interval, pid, attach_mode, &mut converter);
+            let (perf_group, checkpoint) = init_profiler(
+                interval,
+                clock,
+                lbr_call_stacks,
+                &tracepoint_ids,
+                pid,
+                attach_mode,
+                recording_props.checkpoint_interval,
+                &recording_props.output_file,
+                &mut converter,
+            )?;
 
             // Tell the main thread that we are now executing.
             profile_another_pid_reply_sender.send(true).unwrap();
@@ -302,12 +392,16 @@ fn start_profiling_pid(
             run_profiler(
                 perf_group,
                 converter,
+                checkpoint,
                 &output_file,
-                time_limit,
+                schedule,
                 profile_another_pid_request_receiver,
                 profile_another_pid_reply_sender,
                 ctrl_c_receiver,
                 unstable_presymbolicate,
+                search_index,
+                recording_summary,
+                clock,
                 None,
             )
         }
@@ -322,21 +416,26 @@ fn start_profiling_pid(
             AttachMode::StopAttachEnableResume,
         ))
         .unwrap();
-    let _ = profile_another_pid_reply_receiver.recv().unwrap();
+    if profile_another_pid_reply_receiver.recv().is_err() {
+        // The observer thread couldn't start profiling, and returns the error.
+        return observer_thread
+            .join()
+            .expect("couldn't join observer thread");
+    }
 
     // Now that we know that profiler initialization has succeeded, tell the user about it.
     eprintln!("Recording process with PID {pid} until Ctrl+C...");
 
-    profile_another_pid_request_sender
-        .send(SamplerRequest::StopProfilingOncePerfEventsExhausted)
-        .unwrap();
+    // This fails if the sampler has already stopped, e.g. because of --duration.
+    let _ = profile_another_pid_request_sender
+        .send(SamplerRequest::StopProfilingOncePerfEventsExhausted);
 
     // Now wait for the observer thread to quit. It will keep running until the
     // CtrlC receiver has been notified, or until all perf events are closed,
     // which happens if all processes which the events are attached to have quit.
-    observer_thread
+    let profile = observer_thread
         .join()
-        .expect("couldn't join observer thread");
+        .expect("couldn't join observer thread")?;
 
     // From now on, pressing Ctrl+C will kill our process, because the observer will have
     // dropped its CtrlC receiver by now.
@@ -350,6 +449,8 @@ fn start_profiling_pid(
 
         start_server_main(&output_file, server_props, symbol_props, libinfo_map);
     }
+
+    Ok(profile)
 }
 
 fn paranoia_level() -> Option<u32> {
@@ -360,6 +461,7 @@ fn paranoia_level() -> Option<u32> {
 
 fn make_converter(
     interval: Duration,
+    clock: TimestampClock,
     profile_creation_props: ProfileCreationProps,
 ) -> Converter<framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>> {
     let interval_nanos = if interval.as_nanos() > 0 {
@@ -368,7 +470,10 @@ fn make_converter(
         1_000_000 // 1 million nano seconds = 1 milli second
     };
 
+    // Raw timestamps are nanoseconds since the perf clock's zero, so we use the
+    // wall-clock time at that point as the reference timestamp.
     let first_sample_time = 0;
+    let clock_zero = clock::wall_clock_time_at_clock_zero(clock::clock_id(clock));
 
     let endian = if cfg!(target_endian = "little") {
         Endianness::LittleEndian
@@ -390,7 +495,7 @@ fn make_converter(
         framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>,
     >::new(
         &profile_creation_props,
-        ReferenceTimestamp::from_system_time(SystemTime::now()),
+        ReferenceTimestamp::from_system_time(clock_zero),
         profile_creation_props.profile_name(),
         HashMap::new(),
         machine_info.as_ref().map(|info| info.release.as_str()),
@@ -402,21 +507,69 @@ fn make_converter(
         interpretation,
         None,
         false,
+        ConvertRegsNative::PTR_AUTH_STRIPPER,
     );
     if let Ok(os_release) = os_release::OsRelease::new() {
         converter.set_os_name(&os_release.pretty_name);
     }
+    converter.set_cpu_core_types(&core_types::cpu_core_classes());
+    converter.add_extra_meta_info("Timestamps", "Clock", clock::clock_name(clock));
+    converter.add_extra_meta_info(
+        "Timestamps",
+        "Wall-clock time at clock zero (UTC)",
+        &humantime::format_rfc3339_nanos(clock_zero).to_string(),
+    );
+    converter.set_profiling_start_time(Timestamp::from_nanos_since_reference(clock::now_nanos(
+        clock::clock_id(clock),
+    )));
+    for (label, value) in recording_start_meta_info(SystemTime::now()) {
+        converter.add_extra_meta_info("Recording", label, &value);
+    }
     converter
 }
 
+/// Reads the formats of the `--tracepoint` arguments, of the futex tracepoints
+/// for `--contention` and of the `sched_setaffinity` tracepoints for
+/// `--thread-affinity`, and fails if one of them can't be read.
+/// This happens before any process is launched or attached to.
+fn load_tracepoint_formats(
+    recording_props: &RecordingProps,
+) -> Result<Vec<TracepointFormat>, RecordError> {
+    let mut tracepoints = recording_props.tracepoints.clone();
+    let mut implied_tracepoints = Vec::new();
+    if recording_props.contention {
+        implied_tracepoints.extend([FUTEX_ENTER_TRACEPOINT, FUTEX_EXIT_TRACEPOINT]);
+    }
+    if recording_props.thread_affinity {
+        implied_tracepoints.extend([SETAFFINITY_ENTER_TRACEPOINT, SETAFFINITY_EXIT_TRACEPOINT]);
+    }
+    for name in implied_tracepoints {
+        if !tracepoints.iter().any(|tracepoint| tracepoint == name) {
+            tracepoints.push(name.to_string());
+        }
+    }
+    tracepoints
+        .iter()
+        .map(|name| {
+            TracepointFormat::load(name).map_err(|err| RecordError::StartProfiling(err.to_string()))
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
 fn init_profiler(
     interval: Duration,
+    clock: TimestampClock,
+    lbr_call_stacks: bool,
+    tracepoint_ids: &[u64],
     pid: u32,
     attach_mode: AttachMode,
+    checkpoint_interval: Option<Duration>,
+    output_file: &Path,
     converter: &mut Converter<
         framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>,
     >,
-) -> PerfGroup {
+) -> Result<(PerfGroup, Option<CheckpointWriter>), RecordError> {
     let interval_nanos = if interval.as_nanos() > 0 {
         interval.as_nanos() as u64
     } else {
@@ -427,15 +580,36 @@ fn init_profiler(
     let stack_size = 32000;
     let regs_mask = ConvertRegsNative::regs_mask();
 
-    let perf = PerfGroup::open(
+    let mut perf = PerfGroup::open(
         pid,
         frequency,
         stack_size,
         EventSource::HwCpuCycles,
         regs_mask,
+        clock::clock_id(clock),
+        lbr_call_stacks,
+        tracepoint_ids.to_vec(),
         attach_mode,
     );
 
+    if lbr_call_stacks {
+        if let Err(error) = &perf {
+            eprintln!("Could not capture LBR call stacks: {error}");
+            eprintln!("This needs a CPU with LBR call stack support. Recording without them.");
+            perf = PerfGroup::open(
+                pid,
+                frequency,
+                stack_size,
+                EventSource::HwCpuCycles,
+                regs_mask,
+                clock::clock_id(clock),
+                false,
+                tracepoint_ids.to_vec(),
+                attach_mode,
+            );
+        }
+    }
+
     if let Err(error) = &perf {
         if error.kind() == std::io::ErrorKind::PermissionDenied {
             if let Some(level) = paranoia_level() {
@@ -449,7 +623,7 @@ fn init_profiler(
                     eprintln!("You can execute the following command and then try again:");
                     eprintln!("    echo '1' | sudo tee /proc/sys/kernel/perf_event_paranoid");
                     eprintln!();
-                    std::process::exit(1);
+                    return Err(RecordError::StartProfiling(error.to_string()));
                 }
             }
         }
@@ -459,7 +633,7 @@ fn init_profiler(
         Ok(perf) => perf,
         Err(_) => {
             // We've already checked for permission denied due to paranoia
-            // level, and exited with a warning in that case.
+            // level, and returned with a warning in that case.
 
             // Another reason for the error could be the type of perf event:
             // The "Hardware CPU cycles" event is not supported in some contexts, for example in VMs.
@@ -470,41 +644,76 @@ fn init_profiler(
                 stack_size,
                 EventSource::SwCpuClock,
                 regs_mask,
+                clock::clock_id(clock),
+                false,
+                tracepoint_ids.to_vec(),
                 attach_mode,
             );
-            match perf {
-                Ok(perf) => perf, // Success!
-                Err(error) => {
-                    eprintln!("Failed to start profiling: {error}");
-                    std::process::exit(1);
-                }
-            }
+            perf.map_err(|error| RecordError::StartProfiling(error.to_string()))?
         }
     };
 
-    let (exe_name, cmdline) = get_process_cmdline(pid).expect("Couldn't read process cmdline");
-    let comm_data = std::fs::read(format!("/proc/{pid}/comm")).expect("Couldn't read process comm");
-    let length = memchr::memchr(b'\0', &comm_data).unwrap_or(comm_data.len());
-    let comm_name = std::str::from_utf8(&comm_data[..length])
-        .unwrap()
-        .trim_end();
-    converter.register_existing_process(pid as i32, comm_name, &exe_name, cmdline);
+    let mut checkpoint = checkpoint_interval.and_then(|interval| {
+        let path = checkpoint_path(output_file);
+        let result = CheckpointWriter::create(&path, perf.attr_bytes()?, interval);
+        match result {
+            Ok(checkpoint) => {
+                eprintln!(
+                    "Writing checkpoints to {path:?}. If the recording doesn't finish, run `samply recover {}`.",
+                    path.display()
+                );
+                Some(checkpoint)
+            }
+            Err(err) => {
+                eprintln!("Could not create the checkpoint file {path:?}: {err}");
+                None
+            }
+        }
+    });
+
+    register_process_from_proc(pid, converter, &mut checkpoint).map_err(|err| {
+        RecordError::StartProfiling(format!("Couldn't read the process from /proc: {err}"))
+    })?;
+
+    // eprintln!("Enabling perf events...");
+    match attach_mode {
+        AttachMode::StopAttachEnableResume => perf.enable(),
+        AttachMode::AttachWithEnableOnExec => {
+            // The perf event will get enabled automatically once the forked child process execs.
+        }
+    }
+
+    Ok((perf, checkpoint))
+}
+
+/// Registers a process which is already running with the converter: its name,
+/// its threads and its current mappings, as read from `/proc/<pid>/`.
+fn register_process_from_proc(
+    pid: u32,
+    converter: &mut Converter<
+        framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>,
+    >,
+    checkpoint: &mut Option<CheckpointWriter>,
+) -> std::io::Result<()> {
+    let (exe_name, cmdline) = get_process_cmdline(pid)?;
+    let comm_name = read_comm(format!("/proc/{pid}/comm"))?;
+    let parent_pid = parent_pid(pid).map(|parent_pid| parent_pid as i32);
+    converter.register_existing_process(pid as i32, parent_pid, &comm_name, &exe_name, cmdline);
 
     // TODO: Gather threads / processes recursively, here and in PerfGroup setup.
-    for thread_entry in std::fs::read_dir(format!("/proc/{pid}/task"))
-        .unwrap()
-        .flatten()
-    {
-        let tid: u32 = thread_entry.file_name().to_string_lossy().parse().unwrap();
-        let comm_path = format!("/proc/{pid}/task/{tid}/comm");
-        if let Ok(buffer) = std::fs::read(comm_path) {
-            let length = memchr::memchr(b'\0', &buffer).unwrap_or(buffer.len());
-            let name = std::str::from_utf8(&buffer[..length]).unwrap().trim_end();
-            converter.register_existing_thread(pid as i32, tid as i32, name);
+    for thread_entry in std::fs::read_dir(format!("/proc/{pid}/task"))?.flatten() {
+        let Ok(tid) = thread_entry.file_name().to_string_lossy().parse::<u32>() else {
+            continue;
+        };
+        if let Ok(name) = read_comm(format!("/proc/{pid}/task/{tid}/comm")) {
+            converter.register_existing_thread(pid as i32, tid as i32, &name);
+            write_checkpoint(checkpoint, |writer| {
+                writer.write_comm(pid as i32, tid as i32, &name)
+            });
         }
     }
 
-    let maps = read_string_lossy(format!("/proc/{pid}/maps")).expect("couldn't read proc maps");
+    let maps = read_string_lossy(format!("/proc/{pid}/maps"))?;
     let maps = proc_maps::parse(&maps);
 
     let vdso_file_id = VdsoObject::shared_instance_for_this_process()
@@ -529,42 +738,91 @@ fn init_profiler(
             flags |= libc::MAP_PRIVATE;
         }
 
+        let deleted_file_build_id = region
+            .name
+            .ends_with(DELETED_MAPPING_SUFFIX)
+            .then(|| deleted_mapping_build_id(pid, &region))
+            .flatten();
         let file_id = match (region.name.deref(), vdso_file_id.as_ref()) {
             ("[vdso]", Some(vdso_file_id)) => vdso_file_id.clone(),
-            _ => Mmap2FileId::InodeAndVersion(Mmap2InodeAndVersion {
-                major: region.major,
-                minor: region.minor,
-                inode: region.inode,
-                inode_generation: 0,
-            }),
+            _ => match deleted_file_build_id {
+                Some(build_id) => Mmap2FileId::BuildId(build_id),
+                None => Mmap2FileId::InodeAndVersion(Mmap2InodeAndVersion {
+                    major: region.major,
+                    minor: region.minor,
+                    inode: region.inode,
+                    inode_generation: 0,
+                }),
+            },
         };
 
-        converter.handle_mmap2(
-            Mmap2Record {
-                pid: pid as i32,
-                tid: pid as i32,
-                address: region.start,
-                length: region.end - region.start,
-                page_offset: region.file_offset,
-                file_id,
-                protection: protection as _,
-                flags: flags as _,
-                path: RawData::Single(&region.name.into_bytes()),
-                cpu_mode: CpuMode::User,
-            },
-            0,
-        );
+        let path = region.name.into_bytes();
+        let record = Mmap2Record {
+            pid: pid as i32,
+            tid: pid as i32,
+            address: region.start,
+            length: region.end - region.start,
+            page_offset: region.file_offset,
+            file_id,
+            protection: protection as _,
+            flags: flags as _,
+            path: RawData::Single(&path),
+            cpu_mode: CpuMode::User,
+        };
+        write_checkpoint(checkpoint, |writer| writer.write_mmap2(&record));
+        converter.handle_mmap2(record, 0);
     }
 
-    // eprintln!("Enabling perf events...");
-    match attach_mode {
-        AttachMode::StopAttachEnableResume => perf.enable(),
-        AttachMode::AttachWithEnableOnExec => {
-            // The perf event will get enabled automatically once the forked child process execs.
+    Ok(())
+}
+
+/// Reads a process or thread name from a `comm` file.
+fn read_comm<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    let buffer = std::fs::read(path)?;
+    let length = memchr::memchr(b'\0', &buffer).unwrap_or(buffer.len());
+    Ok(String::from_utf8_lossy(&buffer[..length])
+        .trim_end()
+        .to_string())
+}
+
+/// Reads the build ID of a mapped file which has been deleted, e.g. by a redeploy
+/// of a long-running service. The process keeps the file alive, and we can still
+/// read it through `/proc/<pid>/map_files/`. With the build ID, the file's symbols
+/// can be found in `/usr/lib/debug/.build-id/` or on a debuginfod server.
+fn deleted_mapping_build_id(pid: u32, region: &proc_maps::Region) -> Option<Vec<u8>> {
+    use object::Object;
+
+    let path = format!("/proc/{pid}/map_files/{:x}-{:x}", region.start, region.end);
+    let file = File::open(path).ok()?;
+    let mmap = unsafe { memmap2::MmapOptions::new().map(&file) }.ok()?;
+    let object = object::File::parse(&mmap[..]).ok()?;
+    Some(object.build_id().ok()??.to_owned())
+}
+
+/// The checkpoint file is written next to the output file, e.g. to
+/// `profile.json.gz.checkpoint`.
+fn checkpoint_path(output_file: &Path) -> PathBuf {
+    let mut path = output_file.as_os_str().to_owned();
+    path.push(".checkpoint");
+    PathBuf::from(path)
+}
+
+/// Calls `f` with the checkpoint writer, if there is one. Stops checkpointing
+/// if writing fails, e.g. because the disk is full, without stopping the
+/// recording.
+fn write_checkpoint(
+    checkpoint: &mut Option<CheckpointWriter>,
+    f: impl FnOnce(&mut CheckpointWriter) -> std::io::Result<()>,
+) {
+    if let Some(writer) = checkpoint {
+        if let Err(err) = f(writer) {
+            eprintln!(
+                "Could not write to the checkpoint file {:?}, no longer checkpointing: {err}",
+                writer.path()
+            );
+            *checkpoint = None;
         }
     }
-
-    perf
 }
 
 enum SamplerRequest {
@@ -578,25 +836,62 @@ fn run_profiler(
     mut converter: Converter<
         framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>,
     >,
+    mut checkpoint: Option<CheckpointWriter>,
     output_filename: &Path,
-    _time_limit: Option<Duration>,
+    schedule: RecordingSchedule,
     more_processes_request_receiver: Receiver<SamplerRequest>,
     more_processes_reply_sender: Sender<bool>,
     mut stop_receiver: oneshot::Receiver<()>,
     unstable_presymbolicate: bool,
+    search_index: bool,
+    recording_summary: bool,
+    clock: TimestampClock,
     mut initial_exec_name_and_cmdline: Option<(String, Vec<String>)>,
-) {
+) -> Result<Profile, RecordError> {
     // eprintln!("Running...");
 
     let mut should_stop_profiling_once_perf_events_exhausted = false;
     let mut pending_lost_events = 0;
     let mut total_lost_events = 0;
     let mut last_timestamp = 0;
+    let mut steal_time_reader = StealTimeReader::new(clock::clock_id(clock));
+    let has_lbr_call_stacks = perf.has_lbr_call_stacks();
+    let mut lbr_call_stack = Vec::new();
+    let cpu_count = num_cpus::get();
+    let mut is_sampling = false;
+    let mut stop_reason = None;
+    // The size of the perf event records we've read, for --max-recording-size.
+    let mut recorded_bytes = 0;
     loop {
         if stop_receiver.try_recv().is_ok() {
             break;
         }
 
+        stop_reason = schedule
+            .check_stop()
+            .or_else(|| schedule.check_recording_size(|| recorded_bytes));
+        if stop_reason.is_some() {
+            break;
+        }
+
+        if !is_sampling && schedule.is_sampling() {
+            is_sampling = true;
+            if !schedule.delay().is_zero() {
+                // The profile starts when the delay ends.
+                let now = clock::now_nanos(clock::clock_id(clock));
+                converter.set_profiling_start_time(Timestamp::from_nanos_since_reference(now));
+            }
+        }
+
+        if let Some(span) = steal_time_reader.poll() {
+            converter.handle_vm_steal_time(
+                span.start_timestamp_raw,
+                span.end_timestamp_raw,
+                span.steal_nanos,
+                cpu_count,
+            );
+        }
+
         match more_processes_request_receiver.try_recv() {
             Ok(SamplerRequest::StartProfilingAnotherProcess(another_pid, attach_mode)) => {
                 match perf.open_process(another_pid, attach_mode) {
@@ -645,6 +940,20 @@ fn run_profiler(
 
         perf.consume_events(&mut |event_ref| {
             let record = event_ref.get();
+            // The record header is 8 bytes.
+            recorded_bytes += record.data.len() as u64 + 8;
+            if !is_sampling && record.record_type == RecordType::SAMPLE {
+                // We're still in the --delay.
+                return;
+            }
+            let tracepoint_index = event_ref.tracepoint_index();
+            if tracepoint_index.is_none() {
+                // The checkpoint only describes the main sampling event, so
+                // tracepoint samples would be replayed as CPU samples.
+                write_checkpoint(&mut checkpoint, |writer| {
+                    writer.write_record(record.record_type.0, record.misc, &record.data.as_slice())
+                });
+            }
             let parsed_record = record.parse().unwrap();
             // debug!("Recording parsed_record: {:#?}", parsed_record);
 
@@ -659,7 +968,15 @@ fn run_profiler(
 
             match parsed_record {
                 EventRecord::Sample(e) => {
-                    converter.handle_main_event_sample::<ConvertRegsNative>(&e);
+                    if let Some(index) = tracepoint_index {
+                        converter.handle_tracepoint_sample::<ConvertRegsNative>(&e, index);
+                    } else {
+                        if has_lbr_call_stacks {
+                            read_lbr_call_stack(&record, &mut lbr_call_stack);
+                        }
+                        converter
+                            .handle_main_event_sample::<ConvertRegsNative>(&e, &lbr_call_stack);
+                    }
                     /*
                     } else if interpretation.sched_switch_attr_index == Some(attr_index) {
                         converter.handle_sched_switch_sample::<C>(e);
@@ -684,7 +1001,7 @@ fn run_profiler(
                                 // pid no longer exists, or the pid may even refer to a different process now.
                                 // Unfortunately there are no perf event records that give us the process
                                 // command line.
-                                get_process_cmdline(e.pid as u32).ok()
+                                get_exec_cmdline(e.pid as u32, &e.name.as_slice())
                             };
                         converter.handle_exec(e, record.timestamp(), exec_name_and_cmdline);
                     } else {
@@ -720,6 +1037,7 @@ fn run_profiler(
                 pending_lost_events = 0;
             }
         });
+        write_checkpoint(&mut checkpoint, CheckpointWriter::end_round);
 
         perf.wait();
     }
@@ -728,16 +1046,390 @@ fn run_profiler(
         eprintln!("Lost {total_lost_events} events.");
     }
 
+    for (label, value) in schedule.meta_info(stop_reason) {
+        converter.add_extra_meta_info("Recording", label, &value);
+    }
+
+    // Converting and saving the profile can take a while, so make sure that
+    // everything can be recovered until it's done.
+    write_checkpoint(&mut checkpoint, CheckpointWriter::checkpoint);
+
     let profile = converter.finish();
 
-    save_profile_to_file(&profile, output_filename).expect("Couldn't write JSON");
+    save_profile_to_file(&profile, output_filename).map_err(RecordError::Save)?;
+    if let Some(checkpoint) = checkpoint {
+        let path = checkpoint.path().to_owned();
+        if let Err(err) = checkpoint.remove() {
+            eprintln!("Could not delete the checkpoint file {path:?}: {err}");
+        }
+    }
+
+    finish_saved_profile(
+        &profile,
+        output_filename,
+        unstable_presymbolicate,
+        search_index,
+        recording_summary,
+    );
+    Ok(profile)
+}
 
+/// Writes the files which go next to the saved profile, and prints the summary
+/// if requested.
+fn finish_saved_profile(
+    profile: &Profile,
+    output_filename: &Path,
+    unstable_presymbolicate: bool,
+    search_index: bool,
+    recording_summary: bool,
+) {
     if unstable_presymbolicate {
         crate::shared::symbol_precog::presymbolicate(
-            &profile,
+            profile,
             &output_filename.with_extension("syms.json"),
         );
     }
+
+    if search_index {
+        crate::search_index::write_search_index_for_profile(output_filename);
+    }
+
+    if recording_summary {
+        print_recording_summary(profile, output_filename);
+    }
+}
+
+/// How often the BPF backend collects the stack counts from the kernel. This is
+/// the time resolution of its samples.
+const BPF_DRAIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Which processes' samples the BPF backend keeps. It always samples all CPUs.
+enum BpfProcessFilter {
+    All,
+    /// The process and its descendants, for `samply record --pid`.
+    ProcessTree(u32),
+    /// The descendants of the process, for the commands which samply launches.
+    DescendantsOf(u32),
+}
+
+impl BpfProcessFilter {
+    fn includes(&self, pid: u32) -> bool {
+        match *self {
+            BpfProcessFilter::All => true,
+            BpfProcessFilter::ProcessTree(root) => pid == root || is_descendant_of(pid, root),
+            BpfProcessFilter::DescendantsOf(ancestor) => is_descendant_of(pid, ancestor),
+        }
+    }
+}
+
+/// Walks up the parent chain of `pid` in /proc. Processes which have already
+/// exited can't be checked, so they don't count as descendants.
+fn is_descendant_of(mut pid: u32, ancestor: u32) -> bool {
+    while let Some(parent_pid) = parent_pid(pid) {
+        if parent_pid == ancestor {
+            return true;
+        }
+        if parent_pid == 0 {
+            return false;
+        }
+        pid = parent_pid;
+    }
+    false
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = read_string_lossy(format!("/proc/{pid}/stat")).ok()?;
+    // The process name is in parentheses and can contain spaces, so we
+    // start after the last closing parenthesis: "<pid> (<comm>) <state> <ppid> ..."
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Records with the BPF backend, which counts stacks in the kernel, see
+/// [`BpfSampler`]. Unlike the perf event backend, this can record all processes.
+fn start_bpf_recording(
+    recording_mode: RecordingMode,
+    recording_props: RecordingProps,
+    profile_creation_props: ProfileCreationProps,
+    symbol_props: SymbolProps,
+    server_props: Option<ServerProps>,
+) -> Result<(ExitStatus, Profile), RecordError> {
+    if !recording_props.process_intervals.is_empty() {
+        // The BPF sampler counts the stacks in the kernel, so there are no
+        // individual samples to drop.
+        eprintln!("Warning: --interval-for is not supported with --bpf, and is ignored.");
+    }
+    let interval_nanos = if recording_props.interval.as_nanos() > 0 {
+        recording_props.interval.as_nanos() as u64
+    } else {
+        1_000_000 // 1 million nano seconds = 1 milli second
+    };
+    let frequency = (1_000_000_000 / interval_nanos) as u32;
+    let sampler = match BpfSampler::open(frequency) {
+        Ok(sampler) => sampler,
+        Err(error) => {
+            if error.kind() == std::io::ErrorKind::PermissionDenied {
+                eprintln!("BPF sampling needs root, or the CAP_BPF and CAP_PERFMON capabilities.");
+            }
+            return Err(RecordError::StartProfiling(format!(
+                "BPF sampling: {error}"
+            )));
+        }
+    };
+
+    let (filter, process_launch_props) = match recording_mode {
+        RecordingMode::All => {
+            eprintln!("Recording all processes until Ctrl+C...");
+            (BpfProcessFilter::All, None)
+        }
+        RecordingMode::Pid(pid) => {
+            eprintln!("Recording process with PID {pid} until Ctrl+C...");
+            (BpfProcessFilter::ProcessTree(pid), None)
+        }
+        RecordingMode::Launch(process_launch_props) => (
+            BpfProcessFilter::DescendantsOf(std::process::id()),
+            Some(process_launch_props),
+        ),
+    };
+
+    // Without a launched command, we record until Ctrl+C. With one, Ctrl+C
+    // reaches the command, and we stop once it's done.
+    let (stop_sender, stop_receiver, ctrl_c_receiver) = match process_launch_props {
+        Some(_) => {
+            let (stop_sender, stop_receiver) = oneshot::channel();
+            (
+                Some(stop_sender),
+                stop_receiver,
+                Some(CtrlC::observe_oneshot()),
+            )
+        }
+        None => (None, CtrlC::observe_oneshot(), None),
+    };
+
+    let output_file = recording_props.output_file.clone();
+    let coreclr_enabled = profile_creation_props.coreclr.any_enabled();
+    let observer_thread = thread::spawn(move || {
+        let schedule = RecordingSchedule::new(&recording_props);
+        let clock = recording_props.clock;
+        let unstable_presymbolicate = profile_creation_props.unstable_presymbolicate;
+        let search_index = profile_creation_props.search_index;
+        let recording_summary = profile_creation_props.recording_summary;
+        let converter = make_converter(recording_props.interval, clock, profile_creation_props);
+        run_bpf_profiler(
+            sampler,
+            converter,
+            filter,
+            interval_nanos,
+            &recording_props.output_file,
+            schedule,
+            stop_receiver,
+            unstable_presymbolicate,
+            search_index,
+            recording_summary,
+            clock,
+        )
+    });
+
+    let exit_status = match process_launch_props {
+        Some(process_launch_props) => {
+            let exit_status = run_launched_commands(process_launch_props, coreclr_enabled);
+            // This fails if the sampler has already stopped, e.g. because of --duration.
+            if let Some(stop_sender) = stop_sender {
+                let _ = stop_sender.send(());
+            }
+            exit_status
+        }
+        None => Ok(ExitStatus::from_raw(0)),
+    };
+
+    // The launched commands are done. From now on, we want to terminate if the
+    // user presses Ctrl+C.
+    if let Some(mut ctrl_c_receiver) = ctrl_c_receiver {
+        ctrl_c_receiver.close();
+    }
+
+    let profile = observer_thread
+        .join()
+        .expect("couldn't join observer thread");
+    let exit_status = exit_status?;
+    let profile = profile?;
+
+    if let Some(server_props) = server_props {
+        let libinfo_map = crate::profile_json_preparse::parse_libinfo_map_from_profile_file(
+            File::open(&output_file).expect("Couldn't open file we just wrote"),
+            &output_file,
+        )
+        .expect("Couldn't parse libinfo map from profile file");
+
+        start_server_main(&output_file, server_props, symbol_props, libinfo_map);
+    }
+
+    Ok((exit_status, profile))
+}
+
+/// Runs the command, as many times as requested, and returns the exit status
+/// of the last run. Fails if the command can't be launched the first time. The BPF sampler is already running, so the commands don't
+/// need to wait for the profiler to attach.
+fn run_launched_commands(
+    process_launch_props: ProcessLaunchProps,
+    coreclr_enabled: bool,
+) -> Result<ExitStatus, RecordError> {
+    let ProcessLaunchProps {
+        mut env_vars,
+        command_name,
+        args,
+        iteration_count,
+    } = process_launch_props;
+
+    if coreclr_enabled && !env_vars.iter().any(|p| p.0 == "DOTNET_PerfMapEnabled") {
+        env_vars.push(("DOTNET_PerfMapEnabled".into(), "2".into()));
+    }
+
+    let mut wait_status = WaitStatus::StillAlive;
+    for i in 1..=iteration_count {
+        if i > 1 {
+            if !matches!(wait_status, WaitStatus::Exited(_pid, 0)) {
+                eprintln!(
+                    "Skipping remaining iterations due to non-success exit status: {wait_status:?}"
+                );
+                break;
+            }
+            eprintln!("Running iteration {i} of {iteration_count}...");
+        }
+        let process =
+            SuspendedLaunchedProcess::launch_in_suspended_state(&command_name, &args, &env_vars)
+                .and_then(|process| process.unsuspend_and_run());
+        let process = match process {
+            Ok(process) => process,
+            Err(err) if i == 1 => return Err(RecordError::launch(&command_name, err)),
+            Err(err) => {
+                // Keep the samples of the previous iterations.
+                eprintln!("{}", RecordError::launch(&command_name, err));
+                break;
+            }
+        };
+        wait_status = process.wait().expect("couldn't wait for child");
+    }
+
+    Ok(match wait_status {
+        WaitStatus::Exited(_pid, exit_code) => ExitStatus::from_raw(exit_code),
+        _ => ExitStatus::default(),
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_bpf_profiler(
+    mut sampler: BpfSampler,
+    mut converter: Converter<
+        framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>,
+    >,
+    filter: BpfProcessFilter,
+    interval_nanos: u64,
+    output_filename: &Path,
+    schedule: RecordingSchedule,
+    mut stop_receiver: oneshot::Receiver<()>,
+    unstable_presymbolicate: bool,
+    search_index: bool,
+    recording_summary: bool,
+    clock: TimestampClock,
+) -> Result<Profile, RecordError> {
+    // Whether we keep the samples of each pid we've seen.
+    let mut process_is_included: HashMap<u32, bool> = HashMap::new();
+    let mut seen_threads: HashSet<u32> = HashSet::new();
+    let mut is_sampling = false;
+    // The size of the stacks we've read from the BPF maps, for --max-recording-size.
+    let mut recorded_bytes = 0;
+    let mut steal_time_reader = StealTimeReader::new(clock::clock_id(clock));
+    let cpu_count = num_cpus::get();
+    let stop_reason = loop {
+        thread::sleep(BPF_DRAIN_INTERVAL);
+
+        // We drain once more after deciding to stop, to get the last samples.
+        let stop_reason = schedule
+            .check_stop()
+            .or_else(|| schedule.check_recording_size(|| recorded_bytes));
+        let mut should_stop = stop_reason.is_some() || stop_receiver.try_recv().is_ok();
+        if let BpfProcessFilter::ProcessTree(pid) = filter {
+            should_stop |= !process_is_alive(pid);
+        }
+
+        let was_sampling = is_sampling;
+        if !is_sampling && schedule.is_sampling() {
+            is_sampling = true;
+            if !schedule.delay().is_zero() {
+                // The profile starts when the delay ends.
+                let now = clock::now_nanos(clock::clock_id(clock));
+                converter.set_profiling_start_time(Timestamp::from_nanos_since_reference(now));
+            }
+        }
+
+        if let Some(span) = steal_time_reader.poll() {
+            if was_sampling {
+                converter.handle_vm_steal_time(
+                    span.start_timestamp_raw,
+                    span.end_timestamp_raw,
+                    span.steal_nanos,
+                    cpu_count,
+                );
+            }
+        }
+
+        let timestamp = clock::now_nanos(clock::clock_id(clock));
+        sampler.drain(|sample| {
+            recorded_bytes += ((sample.kernel_stack.len() + sample.user_stack.len()) * 8) as u64;
+            if !was_sampling {
+                // These samples were counted during the --delay.
+                return;
+            }
+            let is_included = *process_is_included.entry(sample.pid).or_insert_with(|| {
+                let is_included = filter.includes(sample.pid);
+                if is_included {
+                    // The process may have exited already. Its samples
+                    // are still added, but without its libraries.
+                    let _ = register_process_from_proc(sample.pid, &mut converter, &mut None);
+                }
+                is_included
+            });
+            if !is_included {
+                return;
+            }
+            if seen_threads.insert(sample.tid) {
+                let comm_path = format!("/proc/{}/task/{}/comm", sample.pid, sample.tid);
+                if let Ok(name) = read_comm(comm_path) {
+                    converter.register_existing_thread(sample.pid as i32, sample.tid as i32, &name);
+                }
+            }
+            converter.handle_aggregated_stack_sample(
+                sample.pid as i32,
+                sample.tid as i32,
+                timestamp,
+                &sample.kernel_stack,
+                &sample.user_stack,
+                sample.count,
+                interval_nanos,
+            );
+        });
+
+        if should_stop {
+            break stop_reason;
+        }
+    };
+
+    for (label, value) in schedule.meta_info(stop_reason) {
+        converter.add_extra_meta_info("Recording", label, &value);
+    }
+    converter.add_extra_meta_info("Recording", "Sampling backend", "BPF");
+
+    let profile = converter.finish();
+    save_profile_to_file(&profile, output_filename).map_err(RecordError::Save)?;
+    finish_saved_profile(
+        &profile,
+        output_filename,
+        unstable_presymbolicate,
+        search_index,
+        recording_summary,
+    );
+    Ok(profile)
 }
 
 pub fn read_string_lossy<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
@@ -745,6 +1437,16 @@ pub fn read_string_lossy<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
     Ok(String::from_utf8_lossy(&data).into_owned())
 }
 
+/// Reads the command line of a process which just exec'd, unless the process
+/// has exec'd again since then, which would give it a different comm name.
+fn get_exec_cmdline(pid: u32, comm_name: &[u8]) -> Option<(String, Vec<String>)> {
+    let current_comm_name = read_comm(format!("/proc/{pid}/comm")).ok()?;
+    if current_comm_name.as_bytes() != comm_name {
+        return None;
+    }
+    get_process_cmdline(pid).ok()
+}
+
 fn get_process_cmdline(pid: u32) -> std::io::Result<(String, Vec<String>)> {
     let path = format!("/proc/{pid}/cmdline");
     let cmdline_bytes = std::fs::read(&path)?;
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use super::clock;
+
+/// Polls the system-wide "steal" time from /proc/stat.
+///
+/// Steal time is the time during which the hypervisor ran something else
+/// (another guest, or the host) while this guest wanted to run. It's always
+/// zero on bare metal, but inside VMs it can explain stalls that have nothing
+/// to do with the profiled code.
+pub struct StealTimeReader {
+    clock_id: libc::clockid_t,
+    nanos_per_tick: u64,
+    last_poll: Option<(u64, u64)>, // (timestamp_raw, steal_ticks)
+}
+
+/// Steal time that was accumulated between two polls.
+pub struct StealTimeSpan {
+    pub start_timestamp_raw: u64,
+    pub end_timestamp_raw: u64,
+    pub steal_nanos: u64,
+}
+
+/// We don't want to read /proc/stat more often than this.
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl StealTimeReader {
+    /// `clock_id` must be the clock that's used for the perf event timestamps.
+    pub fn new(clock_id: libc::clockid_t) -> Self {
+        let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        let ticks_per_second = if ticks_per_second > 0 {
+            ticks_per_second as u64
+        } else {
+            100
+        };
+        Self {
+            clock_id,
+            nanos_per_tick: 1_000_000_000 / ticks_per_second,
+            last_poll: None,
+        }
+    }
+
+    /// Reads the current steal time, and returns the steal time since the last
+    /// poll, if there was any. Does nothing if the last poll was very recent.
+    pub fn poll(&mut self) -> Option<StealTimeSpan> {
+        let now = clock::now_nanos(self.clock_id);
+        if let Some((last_timestamp, _)) = self.last_poll {
+            if now.saturating_sub(last_timestamp) < MIN_POLL_INTERVAL.as_nanos() as u64 {
+                return None;
+            }
+        }
+
+        let steal_ticks = read_total_steal_ticks()?;
+        let (start_timestamp_raw, prev_steal_ticks) = self.last_poll.replace((now, steal_ticks))?;
+        let delta_ticks = steal_ticks.checked_sub(prev_steal_ticks)?;
+        if delta_ticks == 0 {
+            return None;
+        }
+        Some(StealTimeSpan {
+            start_timestamp_raw,
+            end_timestamp_raw: now,
+            steal_nanos: delta_ticks * self.nanos_per_tick,
+        })
+    }
+}
+
+/// Parses the "steal" column from the aggregate "cpu" line in /proc/stat.
+///
+/// ```plain
+/// cpu  user nice system idle iowait irq softirq steal guest guest_nice
+/// ```
+fn read_total_steal_ticks() -> Option<u64> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    let cpu_line = stat.lines().find(|line| line.starts_with("cpu "))?;
+    cpu_line.split_whitespace().nth(8)?.parse().ok()
+}
@@ -91,6 +91,9 @@ pub const PERF_SAMPLE_IDENTIFIER: u64 = 1 << 16;
 pub const PERF_SAMPLE_TRANSACTION: u64 = 1 << 17;
 pub const PERF_SAMPLE_REGS_INTR: u64 = 1 << 18;
 
+pub const PERF_SAMPLE_BRANCH_USER: u64 = 1 << 0;
+pub const PERF_SAMPLE_BRANCH_CALL_STACK: u64 = 1 << 11;
+
 pub const PERF_REG_X86_AX: u64 = 0;
 pub const PERF_REG_X86_BX: u64 = 1;
 pub const PERF_REG_X86_CX: u64 = 2;
@@ -222,6 +225,7 @@ mod ioctl {
         pub const IOC_SIZEBITS: c_ulong = 14;
         pub const IOC_DIRBITS: c_ulong = 2;
         pub const IOC_NONE: c_ulong = 0;
+        pub const IOC_WRITE: c_ulong = 1;
     }
 
     #[cfg(any(
@@ -236,6 +240,7 @@ mod ioctl {
         pub const IOC_SIZEBITS: c_ulong = 13;
         pub const IOC_DIRBITS: c_ulong = 3;
         pub const IOC_NONE: c_ulong = 1;
+        pub const IOC_WRITE: c_ulong = 4;
     }
 
     pub use self::arch::*;
@@ -265,6 +270,7 @@ macro_rules! io {
 
 pub const PERF_EVENT_IOC_ENABLE: c_ulong = io!(b'$', 0);
 pub const PERF_EVENT_IOC_DISABLE: c_ulong = io!(b'$', 1);
+pub const PERF_EVENT_IOC_SET_BPF: c_ulong = ioc!(ioctl::IOC_WRITE, b'$', 8, 4);
 
 #[repr(C)]
 pub struct PerfEventAttr {
@@ -0,0 +1,330 @@
+//! Checkpoint files for `samply record --checkpoint-interval`: While recording,
+//! the raw perf event records are appended to a perf.data file, so that a
+//! recording which didn't finish, e.g. because samply or the machine crashed,
+//! can still be converted into a profile with `samply recover`.
+//!
+//! The data section size in the file header is only updated at a checkpoint,
+//! once everything before it has been synced to disk. The header of a file
+//! from an interrupted recording therefore describes the data up to the last
+//! checkpoint; [`repair_checkpoint`] extends it to all complete records which
+//! made it to disk after that.
+//!
+//! The file has no feature sections, so the importer doesn't know the
+//! architecture or the build IDs. `samply recover` assumes that the file is
+//! recovered on the machine which recorded it.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use linux_perf_data::linux_perf_event_reader::{Mmap2FileId, Mmap2Record};
+
+/// The size of `perf_file_header`, with the features bitmap at the end.
+const HEADER_SIZE: u64 = 104;
+/// The offset of the data section's size in the header.
+const DATA_SIZE_OFFSET: u64 = 48;
+
+const PERF_RECORD_COMM: u32 = 3;
+const PERF_RECORD_MMAP2: u32 = 10;
+const PERF_RECORD_FINISHED_ROUND: u32 = 68;
+const PERF_RECORD_MISC_USER: u16 = 2;
+const PERF_RECORD_MISC_MMAP_BUILD_ID: u16 = 1 << 14;
+
+const PERF_SAMPLE_TID: u64 = 1 << 1;
+const PERF_SAMPLE_TIME: u64 = 1 << 2;
+const PERF_SAMPLE_ID: u64 = 1 << 6;
+const PERF_SAMPLE_CPU: u64 = 1 << 7;
+const PERF_SAMPLE_STREAM_ID: u64 = 1 << 9;
+const PERF_SAMPLE_IDENTIFIER: u64 = 1 << 16;
+const PERF_ATTR_FLAG_SAMPLE_ID_ALL: u64 = 1 << 18;
+
+fn perf_magic() -> [u8; 8] {
+    u64::from_le_bytes(*b"PERFILE2").to_ne_bytes()
+}
+
+/// Appends perf event records to a checkpoint file. All records belong to a
+/// single perf event attribute, whose raw `perf_event_attr` bytes are given
+/// when the file is created.
+pub struct CheckpointWriter {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    data_size: u64,
+    /// The `sample_type` of the attribute if non-sample records carry a
+    /// sample ID trailer (`sample_id_all`), which synthesized records need too.
+    sample_id_type: Option<u64>,
+    interval: Duration,
+    last_checkpoint: Instant,
+}
+
+impl CheckpointWriter {
+    pub fn create(path: &Path, attr_bytes: &[u8], interval: Duration) -> io::Result<Self> {
+        if attr_bytes.len() < 48 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unexpected perf_event_attr size",
+            ));
+        }
+        let attr_size = attr_bytes.len() as u64;
+        let sample_type = u64::from_ne_bytes(attr_bytes[24..32].try_into().unwrap());
+        let attr_flags = u64::from_ne_bytes(attr_bytes[40..48].try_into().unwrap());
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        // One perf_file_attr: the attr, followed by an empty section of IDs.
+        let attrs_size = attr_size + 16;
+        let data_offset = HEADER_SIZE + attrs_size;
+        writer.write_all(&perf_magic())?;
+        for value in [
+            HEADER_SIZE,
+            attr_size,
+            HEADER_SIZE, // attrs offset
+            attrs_size,
+            data_offset,
+            0, // data size
+            0, // event_types offset
+            0, // event_types size
+        ] {
+            writer.write_all(&value.to_ne_bytes())?;
+        }
+        // No features.
+        writer.write_all(&[0; 32])?;
+        writer.write_all(attr_bytes)?;
+        writer.write_all(&[0; 16])?;
+
+        let has_sample_id_all = attr_flags & PERF_ATTR_FLAG_SAMPLE_ID_ALL != 0;
+        let mut checkpoint_writer = Self {
+            writer,
+            path: path.to_owned(),
+            data_size: 0,
+            sample_id_type: has_sample_id_all.then_some(sample_type),
+            interval,
+            last_checkpoint: Instant::now(),
+        };
+        checkpoint_writer.checkpoint()?;
+        Ok(checkpoint_writer)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends a record. `data` is the record without its `perf_event_header`.
+    pub fn write_record(&mut self, record_type: u32, misc: u16, data: &[u8]) -> io::Result<()> {
+        let size = data.len() + 8;
+        let size = u16::try_from(size)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record is too large"))?;
+        self.writer.write_all(&record_type.to_ne_bytes())?;
+        self.writer.write_all(&misc.to_ne_bytes())?;
+        self.writer.write_all(&size.to_ne_bytes())?;
+        self.writer.write_all(data)?;
+        self.data_size += u64::from(size);
+        Ok(())
+    }
+
+    /// Appends a COMM record for a process or thread which already existed
+    /// when the recording started.
+    pub fn write_comm(&mut self, pid: i32, tid: i32, name: &str) -> io::Result<()> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&pid.to_ne_bytes());
+        data.extend_from_slice(&tid.to_ne_bytes());
+        push_padded_string(&mut data, name.as_bytes());
+        self.push_sample_id(&mut data, pid, tid);
+        self.write_record(PERF_RECORD_COMM, PERF_RECORD_MISC_USER, &data)
+    }
+
+    /// Appends an MMAP2 record for a user space mapping which already existed
+    /// when the recording started.
+    pub fn write_mmap2(&mut self, record: &Mmap2Record) -> io::Result<()> {
+        let mut misc = PERF_RECORD_MISC_USER;
+        let mut data = Vec::new();
+        data.extend_from_slice(&record.pid.to_ne_bytes());
+        data.extend_from_slice(&record.tid.to_ne_bytes());
+        data.extend_from_slice(&record.address.to_ne_bytes());
+        data.extend_from_slice(&record.length.to_ne_bytes());
+        data.extend_from_slice(&record.page_offset.to_ne_bytes());
+        match &record.file_id {
+            Mmap2FileId::InodeAndVersion(inode) => {
+                data.extend_from_slice(&inode.major.to_ne_bytes());
+                data.extend_from_slice(&inode.minor.to_ne_bytes());
+                data.extend_from_slice(&inode.inode.to_ne_bytes());
+                data.extend_from_slice(&inode.inode_generation.to_ne_bytes());
+            }
+            Mmap2FileId::BuildId(build_id) => {
+                misc |= PERF_RECORD_MISC_MMAP_BUILD_ID;
+                let mut padded_build_id = [0; 20];
+                let len = build_id.len().min(20);
+                padded_build_id[..len].copy_from_slice(&build_id[..len]);
+                data.extend_from_slice(&[len as u8, 0, 0, 0]);
+                data.extend_from_slice(&padded_build_id);
+            }
+        }
+        data.extend_from_slice(&record.protection.to_ne_bytes());
+        data.extend_from_slice(&record.flags.to_ne_bytes());
+        push_padded_string(&mut data, &record.path.as_slice());
+        self.push_sample_id(&mut data, record.pid, record.tid);
+        self.write_record(PERF_RECORD_MMAP2, misc, &data)
+    }
+
+    /// Marks the end of a batch of records, and checkpoints the file if the
+    /// checkpoint interval has passed.
+    pub fn end_round(&mut self) -> io::Result<()> {
+        self.write_record(PERF_RECORD_FINISHED_ROUND, 0, &[])?;
+        if self.last_checkpoint.elapsed() >= self.interval {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the records to disk and then updates the header to include them.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.sync_data()?;
+        file.seek(SeekFrom::Start(DATA_SIZE_OFFSET))?;
+        file.write_all(&self.data_size.to_ne_bytes())?;
+        file.sync_data()?;
+        file.seek(SeekFrom::End(0))?;
+        self.last_checkpoint = Instant::now();
+        Ok(())
+    }
+
+    /// Deletes the checkpoint file, once the profile has been saved.
+    pub fn remove(self) -> io::Result<()> {
+        let Self { writer, path, .. } = self;
+        drop(writer);
+        std::fs::remove_file(path)
+    }
+
+    /// Fills in the sample ID trailer of a synthesized non-sample record. Only
+    /// the pid and tid are known; the timestamp is zero, which sorts these
+    /// records before all recorded ones.
+    fn push_sample_id(&self, data: &mut Vec<u8>, pid: i32, tid: i32) {
+        let Some(sample_type) = self.sample_id_type else {
+            return;
+        };
+        if sample_type & PERF_SAMPLE_TID != 0 {
+            data.extend_from_slice(&pid.to_ne_bytes());
+            data.extend_from_slice(&tid.to_ne_bytes());
+        }
+        let zeroed_u64_count = [
+            PERF_SAMPLE_TIME,
+            PERF_SAMPLE_ID,
+            PERF_SAMPLE_STREAM_ID,
+            PERF_SAMPLE_CPU,
+            PERF_SAMPLE_IDENTIFIER,
+        ]
+        .into_iter()
+        .filter(|flag| sample_type & flag != 0)
+        .count();
+        data.resize(data.len() + zeroed_u64_count * 8, 0);
+    }
+}
+
+/// Appends a NUL-terminated string, padded to a multiple of eight bytes.
+fn push_padded_string(data: &mut Vec<u8>, s: &[u8]) {
+    data.extend_from_slice(s);
+    let padded_len = (s.len() + 1).next_multiple_of(8);
+    data.resize(data.len() + padded_len - s.len(), 0);
+}
+
+/// The data section of a repaired checkpoint file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepairedCheckpoint {
+    /// The size of the data up to the last checkpoint.
+    pub checkpointed_size: u64,
+    /// The size of all complete records.
+    pub data_size: u64,
+}
+
+/// Updates the header of a checkpoint file from an interrupted recording, so
+/// that its data section includes the records which were written after the
+/// last checkpoint. The records are kept up to the first one which is
+/// incomplete or which doesn't look like a record, e.g. because it was only
+/// partly written when the machine crashed.
+pub fn repair_checkpoint(path: &Path) -> io::Result<RepairedCheckpoint> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut header = [0; HEADER_SIZE as usize];
+    file.read_exact(&mut header)?;
+    if header[..8] != perf_magic() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a checkpoint file from samply record",
+        ));
+    }
+    let read_u64 =
+        |offset: usize| u64::from_ne_bytes(header[offset..offset + 8].try_into().unwrap());
+    let data_offset = read_u64(40);
+    let checkpointed_size = read_u64(DATA_SIZE_OFFSET as usize);
+
+    let mut data_size = checkpointed_size;
+    let mut reader = BufReader::new(&mut file);
+    reader.seek(SeekFrom::Start(data_offset + data_size))?;
+    let mut record_header = [0; 8];
+    while data_offset + data_size + 8 <= file_len {
+        reader.read_exact(&mut record_header)?;
+        let record_type = u32::from_ne_bytes(record_header[..4].try_into().unwrap());
+        let size = u64::from(u16::from_ne_bytes(record_header[6..].try_into().unwrap()));
+        let is_known_type = (1..=127).contains(&record_type);
+        if !is_known_type || size < 8 || data_offset + data_size + size > file_len {
+            break;
+        }
+        reader.seek_relative(size as i64 - 8)?;
+        data_size += size;
+    }
+    drop(reader);
+
+    if data_size != checkpointed_size {
+        file.seek(SeekFrom::Start(DATA_SIZE_OFFSET))?;
+        file.write_all(&data_size.to_ne_bytes())?;
+    }
+    Ok(RepairedCheckpoint {
+        checkpointed_size,
+        data_size,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_repair_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile.json.gz.checkpoint");
+
+        // A perf_event_attr with sample_type TID | TIME and sample_id_all.
+        let mut attr = vec![0; 128];
+        attr[4..8].copy_from_slice(&128u32.to_ne_bytes());
+        attr[24..32].copy_from_slice(&(PERF_SAMPLE_TID | PERF_SAMPLE_TIME).to_ne_bytes());
+        attr[40..48].copy_from_slice(&PERF_ATTR_FLAG_SAMPLE_ID_ALL.to_ne_bytes());
+
+        let mut writer = CheckpointWriter::create(&path, &attr, Duration::from_secs(60)).unwrap();
+        // 8 + 8 (pid, tid) + 8 ("main\0" padded) + 16 (sample id)
+        writer.write_comm(12, 12, "main").unwrap();
+        writer.checkpoint().unwrap();
+        writer.write_comm(12, 13, "worker").unwrap();
+        writer.end_round().unwrap();
+        // Leave the records after the checkpoint in the file, without
+        // updating the header, like a recording which crashed.
+        writer.writer.flush().unwrap();
+        drop(writer);
+        // And a record which was only partly written.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[3, 0, 0, 0, 2, 0, 40, 0, 12, 0]).unwrap();
+        drop(file);
+
+        let repaired = repair_checkpoint(&path).unwrap();
+        assert_eq!(
+            repaired,
+            RepairedCheckpoint {
+                checkpointed_size: 40,
+                data_size: 88,
+            }
+        );
+        // Repairing again doesn't change anything.
+        let repaired = repair_checkpoint(&path).unwrap();
+        assert_eq!(repaired.checkpointed_size, 88);
+        assert_eq!(repaired.data_size, 88);
+    }
+}
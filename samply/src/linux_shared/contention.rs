@@ -0,0 +1,282 @@
+//! Lock contention markers for `samply record --contention`: futex waits and
+//! wakes are recorded with the `sys_enter_futex` and `sys_exit_futex`
+//! tracepoints. Each wait becomes a "Lock contention" marker on the waiting
+//! thread, with the stack at which it blocked, and each wake becomes a "Lock
+//! release" marker on the waking thread, usually the one which held the lock,
+//! with its stack. Both markers have the address of the futex, so all waits on
+//! one lock can be found with the marker search.
+
+use std::collections::HashMap;
+
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+
+use crate::shared::unresolved_samples::UnresolvedStackHandle;
+
+pub const FUTEX_ENTER_TRACEPOINT: &str = "syscalls:sys_enter_futex";
+pub const FUTEX_EXIT_TRACEPOINT: &str = "syscalls:sys_exit_futex";
+
+/// What a futex operation does, from the `op` argument of the syscall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexOpKind {
+    /// The thread blocks until the futex is woken.
+    Wait,
+    /// The thread wakes the threads which wait on the futex.
+    Wake,
+    Other,
+}
+
+impl FutexOpKind {
+    pub fn from_op(op: u64) -> Self {
+        // Without FUTEX_PRIVATE_FLAG (128) and FUTEX_CLOCK_REALTIME (256).
+        match op & 0x7f {
+            // FUTEX_WAIT | FUTEX_LOCK_PI | FUTEX_WAIT_BITSET | FUTEX_WAIT_REQUEUE_PI | FUTEX_LOCK_PI2
+            0 | 6 | 9 | 11 | 13 => FutexOpKind::Wait,
+            // FUTEX_WAKE | FUTEX_REQUEUE | FUTEX_CMP_REQUEUE | FUTEX_WAKE_OP | FUTEX_UNLOCK_PI
+            // | FUTEX_WAKE_BITSET | FUTEX_CMP_REQUEUE_PI
+            1 | 3 | 4 | 5 | 7 | 10 | 12 => FutexOpKind::Wake,
+            _ => FutexOpKind::Other,
+        }
+    }
+}
+
+/// A futex wait which hasn't returned yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PendingWait {
+    pid: i32,
+    address: u64,
+    start_timestamp_mono: u64,
+    stack: UnresolvedStackHandle,
+}
+
+/// A futex wait which has returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinishedWait {
+    pub address: u64,
+    pub start_timestamp_mono: u64,
+    /// The stack at which the thread blocked.
+    pub stack: UnresolvedStackHandle,
+    /// The thread which woke the futex during the wait, if any. Waits can also
+    /// end with a timeout or a signal.
+    pub waker_tid: Option<i32>,
+}
+
+/// Pairs the futex syscall entries and exits of all threads.
+#[derive(Debug, Default)]
+pub struct ContentionTracker {
+    /// The wait in progress on each thread, by tid.
+    waits: HashMap<i32, PendingWait>,
+    /// The most recent wake of each futex, by pid and address, with the tid
+    /// of the waking thread. Private futexes are only unique per process.
+    last_wakes: HashMap<(i32, u64), (i32, u64)>,
+}
+
+impl ContentionTracker {
+    /// Handles a `sys_enter_futex` sample. Returns the kind of the operation,
+    /// so that the caller can add a "Lock release" marker for wakes.
+    pub fn handle_enter(
+        &mut self,
+        pid: i32,
+        tid: i32,
+        timestamp_mono: u64,
+        address: u64,
+        op: u64,
+        stack: UnresolvedStackHandle,
+    ) -> FutexOpKind {
+        let kind = FutexOpKind::from_op(op);
+        match kind {
+            FutexOpKind::Wait => {
+                let wait = PendingWait {
+                    pid,
+                    address,
+                    start_timestamp_mono: timestamp_mono,
+                    stack,
+                };
+                self.waits.insert(tid, wait);
+            }
+            FutexOpKind::Wake => {
+                self.last_wakes
+                    .insert((pid, address), (tid, timestamp_mono));
+            }
+            FutexOpKind::Other => {}
+        }
+        kind
+    }
+
+    /// Handles a `sys_exit_futex` sample, and returns the wait which ended, if
+    /// this thread was waiting.
+    pub fn handle_exit(&mut self, tid: i32, timestamp_mono: u64) -> Option<FinishedWait> {
+        let wait = self.waits.remove(&tid)?;
+        let waker_tid = self
+            .last_wakes
+            .get(&(wait.pid, wait.address))
+            .filter(|(_, wake_timestamp)| {
+                (wait.start_timestamp_mono..=timestamp_mono).contains(wake_timestamp)
+            })
+            .map(|(waker_tid, _)| *waker_tid);
+        Some(FinishedWait {
+            address: wait.address,
+            start_timestamp_mono: wait.start_timestamp_mono,
+            stack: wait.stack,
+            waker_tid,
+        })
+    }
+}
+
+/// An interval marker for the time a thread was blocked in a futex wait.
+#[derive(Debug, Clone)]
+pub struct LockContentionMarker {
+    /// The futex address, formatted as hex.
+    pub address: StringHandle,
+    /// The tid of the thread which woke the futex, or "unknown".
+    pub holder: StringHandle,
+}
+
+impl StaticSchemaMarker for LockContentionMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "LockContention";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.address}".into()),
+            tooltip_label: Some(
+                "Waited for {marker.data.address}, released by {marker.data.holder}".into(),
+            ),
+            table_label: Some(
+                "Waited for {marker.data.address}, released by {marker.data.holder}".into(),
+            ),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "address".into(),
+                    label: "Lock address".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "holder".into(),
+                    label: "Released by".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "The thread was blocked in a futex wait, e.g. for a mutex or a condition variable. The stack is where it blocked.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Lock contention")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        CategoryHandle::OTHER
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.address,
+            1 => self.holder,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+/// An instant marker for a futex wake, on the thread which released the lock.
+#[derive(Debug, Clone)]
+pub struct LockReleaseMarker {
+    /// The futex address, formatted as hex.
+    pub address: StringHandle,
+}
+
+impl StaticSchemaMarker for LockReleaseMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "LockRelease";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.address}".into()),
+            tooltip_label: Some("Released {marker.data.address}".into()),
+            table_label: Some("Released {marker.data.address}".into()),
+            fields: vec![MarkerFieldSchema {
+                key: "address".into(),
+                label: "Lock address".into(),
+                format: MarkerFieldFormat::String,
+                searchable: true,
+            }],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "The thread woke the waiters of a futex, e.g. when unlocking a contended mutex. The stack is where it released the lock.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Lock release")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        CategoryHandle::OTHER
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.address
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pairs_waits_with_wakes() {
+        let stack = UnresolvedStackHandle::EMPTY;
+        let mut tracker = ContentionTracker::default();
+        // FUTEX_WAIT_PRIVATE
+        assert_eq!(
+            tracker.handle_enter(1, 2, 100, 0x1000, 128, stack),
+            FutexOpKind::Wait
+        );
+        assert_eq!(
+            tracker.handle_enter(1, 3, 100, 0x2000, 128, stack),
+            FutexOpKind::Wait
+        );
+        // FUTEX_WAKE_PRIVATE, in another process with the same address.
+        assert_eq!(
+            tracker.handle_enter(5, 6, 120, 0x1000, 129, stack),
+            FutexOpKind::Wake
+        );
+        assert_eq!(
+            tracker.handle_enter(1, 4, 150, 0x1000, 129, stack),
+            FutexOpKind::Wake
+        );
+        assert_eq!(
+            tracker.handle_exit(2, 160),
+            Some(FinishedWait {
+                address: 0x1000,
+                start_timestamp_mono: 100,
+                stack,
+                waker_tid: Some(4),
+            })
+        );
+        // A timeout.
+        assert_eq!(tracker.handle_exit(3, 200).unwrap().waker_tid, None);
+        // The exit of the waking thread's syscall.
+        assert_eq!(tracker.handle_exit(4, 151), None);
+        // FUTEX_FD is neither.
+        assert_eq!(FutexOpKind::from_op(2), FutexOpKind::Other);
+    }
+}
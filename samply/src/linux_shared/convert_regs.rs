@@ -1,4 +1,4 @@
-use framehop::aarch64::UnwindRegsAarch64;
+use framehop::aarch64::{PtrAuthMask, UnwindRegsAarch64};
 use framehop::x86_64::UnwindRegsX86_64;
 use linux_perf_data::linux_perf_event_reader;
 use linux_perf_event_reader::constants::{
@@ -7,8 +7,14 @@ use linux_perf_event_reader::constants::{
 };
 use linux_perf_event_reader::Regs;
 
+use crate::shared::ptr_auth::PtrAuthStripper;
+
 pub trait ConvertRegs {
     type UnwindRegs;
+
+    /// Removes pointer authentication codes from the addresses in stacks.
+    const PTR_AUTH_STRIPPER: PtrAuthStripper;
+
     fn convert_regs(regs: &Regs) -> (u64, u64, Self::UnwindRegs);
 
     #[allow(unused)]
@@ -18,6 +24,8 @@ pub trait ConvertRegs {
 pub struct ConvertRegsX86_64;
 impl ConvertRegs for ConvertRegsX86_64 {
     type UnwindRegs = UnwindRegsX86_64;
+    const PTR_AUTH_STRIPPER: PtrAuthStripper = PtrAuthStripper::NONE;
+
     fn convert_regs(regs: &Regs) -> (u64, u64, UnwindRegsX86_64) {
         let ip = regs.get(PERF_REG_X86_IP).unwrap();
         let sp = regs.get(PERF_REG_X86_SP).unwrap();
@@ -34,12 +42,17 @@ impl ConvertRegs for ConvertRegsX86_64 {
 pub struct ConvertRegsAarch64;
 impl ConvertRegs for ConvertRegsAarch64 {
     type UnwindRegs = UnwindRegsAarch64;
+    const PTR_AUTH_STRIPPER: PtrAuthStripper = PtrAuthStripper::ARM64;
+
     fn convert_regs(regs: &Regs) -> (u64, u64, UnwindRegsAarch64) {
         let ip = regs.get(PERF_REG_ARM64_PC).unwrap();
         let lr = regs.get(PERF_REG_ARM64_LR).unwrap();
         let sp = regs.get(PERF_REG_ARM64_SP).unwrap();
         let fp = regs.get(PERF_REG_ARM64_X29).unwrap();
-        let regs = UnwindRegsAarch64::new(lr, sp, fp);
+        // The link register and the return addresses which the unwinder reads
+        // from the stack may be signed.
+        let mask = PtrAuthMask(Self::PTR_AUTH_STRIPPER.address_mask());
+        let regs = UnwindRegsAarch64::new_with_ptr_auth_mask(mask, lr, sp, fp);
         (ip, sp, regs)
     }
 
@@ -10,7 +10,7 @@ use fxprof_processed_profile::{
     CategoryColor, CategoryHandle, CategoryPairHandle, CpuDelta, LibraryHandle, LibraryInfo,
     MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema, MarkerTiming, Profile,
     ReferenceTimestamp, SamplingInterval, StaticSchemaMarker, StringHandle, SymbolTable,
-    ThreadHandle,
+    ThreadHandle, Timestamp,
 };
 use linux_perf_data::linux_perf_event_reader::TaskWasPreempted;
 use linux_perf_data::simpleperf_dso_type::{DSO_DEX_FILE, DSO_KERNEL, DSO_KERNEL_MODULE};
@@ -24,32 +24,55 @@ use linux_perf_event_reader::{
     MmapRecord, RawDataU64, SampleRecord,
 };
 use memmap2::Mmap;
-use object::{CompressedFileRange, CompressionFormat, Object, ObjectSection};
+use object::{BinaryFormat, CompressedFileRange, CompressionFormat, Object, ObjectSection};
 use samply_symbols::{debug_id_for_object, DebugIdExt};
 use wholesym::samply_symbols::demangle_any;
 use wholesym::{samply_symbols, CodeId, ElfBuildId};
 
 use super::avma_range::AvmaRange;
+use super::contention::{
+    ContentionTracker, FutexOpKind, LockContentionMarker, LockReleaseMarker,
+    FUTEX_ENTER_TRACEPOINT, FUTEX_EXIT_TRACEPOINT,
+};
 use super::convert_regs::ConvertRegs;
 use super::event_interpretation::{EventInterpretation, OffCpuIndicator};
 use super::injected_jit_object::{correct_bad_perf_jit_so_file, jit_function_name};
 use super::kernel_symbols::{kernel_module_build_id, KernelSymbols};
+use super::lbr::fix_up_stack_with_lbr_call_stack;
 use super::mmap_range_or_vec::MmapRangeOrVec;
 use super::pe_mappings::{PeMappings, SuspectedPeMapping};
+use super::process::{full_name_for_truncated_comm, MAX_COMM_NAME_LEN};
 use super::processes::Processes;
 use super::rss_stat::{RssStat, MM_ANONPAGES, MM_FILEPAGES, MM_SHMEMPAGES, MM_SWAPENTS};
 use super::svma_file_range::compute_vma_bias;
-use super::vdso::VdsoObject;
+use super::syscall_categories::categorize_profile_by_syscall;
+use super::thread_affinity::{
+    cpu_count, read_cpus_allowed_list, AffinityTracker, ThreadAffinityMarker,
+    SETAFFINITY_ENTER_TRACEPOINT, SETAFFINITY_EXIT_TRACEPOINT,
+};
+use super::tracepoint::{TracepointFormat, TracepointMarker};
+use super::vdso::{vsyscall_symbol_table, VdsoObject, VSYSCALL_PAGE_START};
+use super::vm_steal::VmStealTrack;
+use crate::shared::anonymous_code::is_anonymous_mapping_path;
+use crate::shared::collector_frames::remove_collector_frames;
 use crate::shared::context_switch::{ContextSwitchHandler, OffCpuSampleGroup};
+use crate::shared::cpu_core_types::core_type_names;
 use crate::shared::jit_category_manager::JitCategoryManager;
 use crate::shared::lib_mappings::{AndroidArtInfo, LibMappingInfo};
+use crate::shared::namespace_categories::{categorize_profile_by_namespace, NamespaceCategoryRule};
 use crate::shared::per_cpu::Cpus;
+use crate::shared::process_intervals::ProcessSampleStrides;
 use crate::shared::process_name::make_process_name;
 use crate::shared::process_sample_data::{
     OtherEventMarker, RssStatMarker, RssStatMember, SchedSwitchMarkerOnCpuTrack,
     SchedSwitchMarkerOnThreadTrack,
 };
+use crate::shared::profile_size_budget::shrink_profile_to_size_budget;
+use crate::shared::ptr_auth::PtrAuthStripper;
+use crate::shared::r2r_map::{perfinfo_image_signature, signatures_match, try_load_r2r_map};
 use crate::shared::recording_props::ProfileCreationProps;
+use crate::shared::recycling::ProcessRecycler;
+use crate::shared::runtime_categories::categorize_profile_by_runtime;
 use crate::shared::synthetic_jit_library::SyntheticJitLibrary;
 use crate::shared::timestamp_converter::TimestampConverter;
 use crate::shared::types::{StackFrame, StackMode};
@@ -76,7 +99,20 @@ where
     unresolved_stacks: UnresolvedStacks,
     off_cpu_weight_per_sample: i32,
     off_cpu_indicator: Option<OffCpuIndicator>,
+    /// Whether off-cpu time gets a sample per interval, see
+    /// [`OffCpuSampleGroup::sample_timestamps`].
+    wall_clock_samples: bool,
     event_names: Vec<String>,
+    /// The formats of the tracepoints enabled with `samply record --tracepoint`,
+    /// in the order of the `--tracepoint` arguments.
+    tracepoint_formats: Vec<TracepointFormat>,
+    /// Pairs the futex tracepoints for `samply record --contention`.
+    contention: Option<ContentionTracker>,
+    /// Pairs the `sched_setaffinity` tracepoints for
+    /// `samply record --thread-affinity`.
+    thread_affinity: Option<AffinityTracker>,
+    /// Which samples to keep of the processes from `samply record --interval-for`.
+    process_sample_strides: ProcessSampleStrides,
     kernel_symbols: Option<KernelSymbols>,
     kernel_image_mapping: Option<KernelImageMapping>,
     simpleperf_symbol_tables_user: HashMap<Vec<u8>, SymbolTableFromSimpleperf>,
@@ -84,15 +120,29 @@ where
     simpleperf_symbol_tables_kernel_image: Option<Vec<SimpleperfSymbol>>,
     simpleperf_symbol_tables_kernel_modules: HashMap<Vec<u8>, SymbolTableFromSimpleperf>,
     simpleperf_jit_app_cache_library: SyntheticJitLibrary,
+    simpleperf_jit_zygote_cache_library: SyntheticJitLibrary,
     pe_mappings: PeMappings,
     jit_category_manager: JitCategoryManager,
     arg_count_to_include_in_process_name: usize,
     cpus: Option<Cpus>,
+    vm_steal_track: Option<VmStealTrack>,
+    max_profile_size: Option<u64>,
+    namespace_category_rules: Option<Vec<NamespaceCategoryRule>>,
+    runtime_categories: bool,
+    syscall_categories: bool,
+    remove_collector_frames: bool,
+    process_group_cpu: bool,
 
     /// Whether repeated frames at the base of the stack should be folded
     /// into one frame.
     fold_recursive_prefix: bool,
 
+    /// Whether symbol tables which were found during conversion (from kallsyms
+    /// or from simpleperf's symbol records) should be embedded in the profile.
+    /// If false, symbolication is left to the symbol server when the profile
+    /// is viewed.
+    embed_symbol_tables: bool,
+
     /// Determines how the addresses in sample call chains should be interpreted.
     /// Any addresses after the first frame address are either "return addresses"
     /// (i.e. they are the address of the instruction *after* the call instruction),
@@ -104,10 +154,18 @@ where
     /// already done the adjusting, either by adjusting the call chains coming from
     /// the kernel or by doing its own unwinding with an adjusting unwinder,
     call_chain_return_addresses_are_preadjusted: bool,
+
+    /// Removes pointer authentication codes from the return addresses of arm64
+    /// stacks.
+    ptr_auth_stripper: PtrAuthStripper,
 }
 
 const DEFAULT_OFF_CPU_SAMPLING_INTERVAL_NS: u64 = 1_000_000; // 1ms
 
+/// The kernel appends this to the path of a mapped file which has been deleted,
+/// e.g. because it was replaced by a newer version during a deploy.
+pub const DELETED_MAPPING_SUFFIX: &str = " (deleted)";
+
 impl<U> Converter<U>
 where
     U: Unwinder<Module = Module<MmapRangeOrVec>> + Default,
@@ -127,12 +185,15 @@ where
         interpretation: EventInterpretation,
         simpleperf_symbol_tables: Option<Vec<SimpleperfFileRecord>>,
         call_chain_return_addresses_are_preadjusted: bool,
+        ptr_auth_stripper: PtrAuthStripper,
     ) -> Self {
         let interval = match interpretation.sampling_is_time_based {
             Some(nanos) => SamplingInterval::from_nanos(nanos),
             None => SamplingInterval::from_millis(1),
         };
         let mut profile = Profile::new(profile_name, reference_timestamp, interval);
+        profile.set_thread_order(profile_creation_props.thread_order);
+        profile.set_hide_idle_threads(profile_creation_props.hide_idle_threads);
         if let Some(linux_version) = linux_version {
             profile.set_os_name(&format!("Linux {linux_version}"));
         }
@@ -156,13 +217,22 @@ where
         let simpleperf_jit_category: CategoryPairHandle = profile
             .add_category("JIT app cache", CategoryColor::Green)
             .into();
-        let allow_jit_function_recycling = profile_creation_props.reuse_threads;
+        let allow_jit_function_recycling = profile_creation_props.reuse_processes();
         let simpleperf_jit_app_cache_library = SyntheticJitLibrary::new(
             "JIT app cache".to_string(),
             simpleperf_jit_category,
             &mut profile,
             allow_jit_function_recycling,
         );
+        let simpleperf_jit_zygote_category: CategoryPairHandle = profile
+            .add_category("JIT zygote cache", CategoryColor::Green)
+            .into();
+        let simpleperf_jit_zygote_cache_library = SyntheticJitLibrary::new(
+            "JIT zygote cache".to_string(),
+            simpleperf_jit_zygote_category,
+            &mut profile,
+            allow_jit_function_recycling,
+        );
         if let Some(simpleperf_symbol_tables) = simpleperf_symbol_tables {
             let dex_category: CategoryPairHandle =
                 profile.add_category("DEX", CategoryColor::Green).into();
@@ -175,15 +245,16 @@ where
                 }
 
                 let path = f.path.clone().into_bytes();
-                if is_simpleperf_jit_path(&f.path) {
-                    simpleperf_symbol_tables_jit.insert(path, f.symbol);
+                if simpleperf_jit_cache_kind(&f.path).is_some() {
+                    let mut symbols = f.symbol;
+                    symbols.sort_by_key(|sym| sym.vaddr);
+                    simpleperf_symbol_tables_jit.insert(path, symbols);
                     continue;
                 }
 
-                let is_jit = false;
                 let (category, art_info) = if f.path.ends_with(".oat") {
                     (Some(oat_category), Some(AndroidArtInfo::JavaFrame))
-                } else if f.r#type == DSO_DEX_FILE || f.path.ends_with(".odex") || is_jit {
+                } else if f.r#type == DSO_DEX_FILE || f.path.ends_with(".odex") {
                     (Some(dex_category), Some(AndroidArtInfo::JavaFrame))
                 } else if f.path.ends_with("libart.so") {
                     (None, Some(AndroidArtInfo::LibArt))
@@ -234,13 +305,16 @@ where
             None
         };
 
+        let process_recycler = if profile_creation_props.reuse_processes() {
+            Some(ProcessRecycler::new(profile_creation_props.reuse_threads))
+        } else {
+            None
+        };
+
         Self {
             profile,
             cache,
-            processes: Processes::new(
-                profile_creation_props.reuse_threads,
-                profile_creation_props.unlink_aux_files,
-            ),
+            processes: Processes::new(process_recycler, profile_creation_props.unlink_aux_files),
             timestamp_converter,
             current_sample_time: first_sample_time,
             build_ids,
@@ -250,9 +324,16 @@ where
             aux_file_lookup_dirs,
             off_cpu_weight_per_sample,
             context_switch_handler: ContextSwitchHandler::new(off_cpu_sampling_interval_ns),
-            unresolved_stacks: UnresolvedStacks::default(),
+            unresolved_stacks: UnresolvedStacks::with_max_depth(
+                profile_creation_props.max_stack_depth,
+            ),
             off_cpu_indicator: interpretation.off_cpu_indicator,
+            wall_clock_samples: profile_creation_props.wall_clock_samples,
             event_names: interpretation.event_names,
+            tracepoint_formats: Vec::new(),
+            contention: None,
+            thread_affinity: None,
+            process_sample_strides: ProcessSampleStrides::default(),
             kernel_symbols,
             kernel_image_mapping: None,
             simpleperf_symbol_tables_user,
@@ -260,26 +341,84 @@ where
             simpleperf_symbol_tables_kernel_image,
             simpleperf_symbol_tables_kernel_modules,
             simpleperf_jit_app_cache_library,
+            simpleperf_jit_zygote_cache_library,
             pe_mappings: PeMappings::new(),
             jit_category_manager: JitCategoryManager::new(),
             fold_recursive_prefix: profile_creation_props.fold_recursive_prefix,
+            embed_symbol_tables: !profile_creation_props.no_presymbolicate,
             arg_count_to_include_in_process_name: profile_creation_props
                 .arg_count_to_include_in_process_name,
             cpus,
+            vm_steal_track: None,
+            max_profile_size: profile_creation_props.max_profile_size,
+            namespace_category_rules: profile_creation_props.namespace_category_rules.clone(),
+            runtime_categories: profile_creation_props.runtime_categories,
+            syscall_categories: profile_creation_props.syscall_categories,
+            remove_collector_frames: profile_creation_props.remove_collector_frames,
+            process_group_cpu: profile_creation_props.process_group_cpu,
             call_chain_return_addresses_are_preadjusted,
+            ptr_auth_stripper,
         }
     }
 
+    /// Records steal time that was observed between two raw perf clock
+    /// timestamps, summed up across all CPUs. The "Hypervisor" track is only
+    /// created once we've seen some steal time, so that profiles from bare metal
+    /// machines don't get an empty track. Only the Linux recorder reads steal
+    /// time; perf.data files don't contain it.
+    #[cfg_attr(not(any(target_os = "android", target_os = "linux")), allow(unused))]
+    pub fn handle_vm_steal_time(
+        &mut self,
+        start_timestamp_raw: u64,
+        end_timestamp_raw: u64,
+        steal_nanos: u64,
+        cpu_count: usize,
+    ) {
+        let start_time = self.timestamp_converter.convert_time(start_timestamp_raw);
+        let end_time = self.timestamp_converter.convert_time(end_timestamp_raw);
+        let span_nanos = end_timestamp_raw.saturating_sub(start_timestamp_raw);
+        let track = self
+            .vm_steal_track
+            .get_or_insert_with(|| VmStealTrack::new(start_time, &mut self.profile));
+        track.add_steal_time(
+            start_time,
+            end_time,
+            span_nanos,
+            steal_nanos,
+            cpu_count,
+            &mut self.profile,
+        );
+    }
+
     pub fn finish(mut self) -> Profile {
         let mut profile = self.profile;
         self.simpleperf_jit_app_cache_library
             .finish_and_set_symbol_table(&mut profile);
+        self.simpleperf_jit_zygote_cache_library
+            .finish_and_set_symbol_table(&mut profile);
         self.processes.finish(
             &mut profile,
             &self.unresolved_stacks,
             &mut self.jit_category_manager,
             &self.timestamp_converter,
+            self.process_group_cpu,
+            self.ptr_auth_stripper,
         );
+        if self.remove_collector_frames {
+            remove_collector_frames(&mut profile);
+        }
+        if self.runtime_categories {
+            categorize_profile_by_runtime(&mut profile);
+        }
+        if self.syscall_categories {
+            categorize_profile_by_syscall(&mut profile);
+        }
+        if let Some(rules) = &self.namespace_category_rules {
+            categorize_profile_by_namespace(&mut profile, rules);
+        }
+        if let Some(max_profile_size) = self.max_profile_size {
+            shrink_profile_to_size_budget(&mut profile, max_profile_size);
+        }
         profile
     }
 
@@ -291,9 +430,31 @@ where
         self.profile.set_os_name(os_name);
     }
 
+    /// Names the kind of core of each CPU in the profile metadata, see
+    /// [`core_type_names`].
+    #[allow(unused)]
+    pub fn set_cpu_core_types(&mut self, cpu_classes: &[(u32, u32)]) {
+        for (cpu, core_type) in core_type_names(cpu_classes) {
+            self.profile.set_cpu_core_type(cpu, core_type);
+        }
+    }
+
+    #[allow(unused)]
+    pub fn set_profiling_start_time(&mut self, start_time: Timestamp) {
+        self.profile.set_profiling_start_time(start_time);
+    }
+
+    #[allow(unused)]
+    pub fn add_extra_meta_info(&mut self, section: &str, label: &str, value: &str) {
+        self.profile.add_extra_meta_info(section, label, value);
+    }
+
+    /// `lbr_call_stack` has the call sites from the sample's LBR call stack,
+    /// innermost call first, or is empty if no LBR call stack was captured.
     pub fn handle_main_event_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
         &mut self,
         e: &SampleRecord,
+        lbr_call_stack: &[u64],
     ) {
         let pid = e.pid.expect("Can't handle samples without pids");
         let tid = e.tid.expect("Can't handle samples without tids");
@@ -314,6 +475,21 @@ where
             &mut self.profile,
             &self.timestamp_converter,
         );
+        let stride = match &process.name {
+            Some(name) if !self.process_sample_strides.is_empty() => {
+                let stride = self.process_sample_strides.stride_for_process(name);
+                if stride > 1 && process.sample_thinner.is_first_sample() {
+                    self.process_sample_strides.add_meta_info(
+                        &mut self.profile,
+                        name,
+                        pid as u32,
+                        stride,
+                    );
+                }
+                stride
+            }
+            _ => 1,
+        };
 
         let mut stack = Vec::new();
         Self::get_sample_stack::<C>(
@@ -324,6 +500,7 @@ where
             self.fold_recursive_prefix,
             self.call_chain_return_addresses_are_preadjusted,
         );
+        fix_up_stack_with_lbr_call_stack(&mut stack, lbr_call_stack);
 
         let thread = process.threads.get_thread_by_tid(tid, &mut self.profile);
 
@@ -351,11 +528,17 @@ where
                 cpu_delta_ns,
                 &self.timestamp_converter,
                 self.off_cpu_weight_per_sample,
+                self.wall_clock_samples,
                 off_cpu_stack,
                 &mut process.unresolved_samples,
             );
         }
 
+        // The running time of dropped samples goes to the next kept sample.
+        let Some(weight) = process.sample_thinner.next_sample(stride) else {
+            return;
+        };
+
         let cpu_delta = if self.off_cpu_indicator.is_some() {
             CpuDelta::from_nanos(
                 self.context_switch_handler
@@ -370,15 +553,26 @@ where
         };
 
         let stack_index = self.unresolved_stacks.convert(stack.iter().rev().cloned());
-        process.unresolved_samples.add_sample(
-            thread_handle,
-            profile_timestamp,
-            timestamp,
-            stack_index,
-            cpu_delta,
-            1,
-            None,
-        );
+        match e.cpu {
+            Some(cpu) => process.unresolved_samples.add_sample_on_cpu(
+                thread_handle,
+                profile_timestamp,
+                timestamp,
+                stack_index,
+                cpu_delta,
+                weight,
+                cpu,
+            ),
+            None => process.unresolved_samples.add_sample(
+                thread_handle,
+                profile_timestamp,
+                timestamp,
+                stack_index,
+                cpu_delta,
+                weight,
+                None,
+            ),
+        }
 
         if let (Some(cpu_index), Some(cpus)) = (e.cpu, &mut self.cpus) {
             let cpu = cpus.get_mut(cpu_index as usize, &mut self.profile);
@@ -405,7 +599,7 @@ where
                 timestamp,
                 stack_index,
                 cpu_delta,
-                1,
+                weight,
                 Some(thread.thread_label_frame.clone()),
             );
 
@@ -415,12 +609,71 @@ where
                 timestamp,
                 stack_index,
                 CpuDelta::ZERO,
-                1,
+                weight,
                 Some(thread.thread_label_frame.clone()),
             );
         }
     }
 
+    /// Adds a sample whose stack was collected and counted in the kernel, by the
+    /// BPF sampling backend. The stack was hit `count` times since the previous
+    /// drain, so the sample gets `count` as its weight, at the time of the drain.
+    /// Both stacks are leaf-first, and the user stack only has the frames which
+    /// the kernel could find by walking frame pointers.
+    #[allow(clippy::too_many_arguments)]
+    pub fn handle_aggregated_stack_sample(
+        &mut self,
+        pid: i32,
+        tid: i32,
+        timestamp: u64,
+        kernel_stack: &[u64],
+        user_stack: &[u64],
+        count: u64,
+        interval_nanos: u64,
+    ) {
+        if tid == 0 || count == 0 || (kernel_stack.is_empty() && user_stack.is_empty()) {
+            return;
+        }
+        self.current_sample_time = timestamp;
+        let profile_timestamp = self.timestamp_converter.convert_time(timestamp);
+
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        process.check_jitdump(
+            &mut self.jit_category_manager,
+            &mut self.profile,
+            &self.timestamp_converter,
+        );
+
+        let mut is_first_frame = true;
+        let mut stack = Vec::with_capacity(kernel_stack.len() + user_stack.len());
+        let frames = kernel_stack
+            .iter()
+            .map(|address| (*address, StackMode::Kernel))
+            .chain(user_stack.iter().map(|address| (*address, StackMode::User)));
+        for (address, mode) in frames {
+            stack.push(match is_first_frame {
+                true => StackFrame::InstructionPointer(address, mode),
+                false => StackFrame::ReturnAddress(address, mode),
+            });
+            is_first_frame = false;
+        }
+
+        let thread = process.threads.get_thread_by_tid(tid, &mut self.profile);
+        thread.last_sample_timestamp = Some(timestamp);
+        let thread_handle = thread.profile_thread;
+
+        let stack_index = self.unresolved_stacks.convert(stack.iter().rev().cloned());
+        process.unresolved_samples.add_sample(
+            thread_handle,
+            profile_timestamp,
+            timestamp,
+            stack_index,
+            CpuDelta::from_nanos(count.saturating_mul(interval_nanos)),
+            i32::try_from(count).unwrap_or(i32::MAX),
+            None,
+        );
+    }
+
     pub fn handle_sched_switch_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
         &mut self,
         e: &SampleRecord,
@@ -634,6 +887,238 @@ where
         }
     }
 
+    #[allow(unused)]
+    pub fn set_tracepoint_formats(&mut self, tracepoint_formats: Vec<TracepointFormat>) {
+        self.tracepoint_formats = tracepoint_formats;
+    }
+
+    /// Turns the futex tracepoints into lock contention markers instead of
+    /// tracepoint markers. The tracepoints need to be among the formats passed
+    /// to [`Self::set_tracepoint_formats`].
+    #[allow(unused)]
+    pub fn enable_contention_tracking(&mut self) {
+        self.contention = Some(ContentionTracker::default());
+    }
+
+    /// Only keeps every n-th sample of the processes which should be sampled
+    /// at a lower rate, with a weight of n.
+    #[allow(unused)]
+    pub fn set_process_sample_strides(&mut self, process_sample_strides: ProcessSampleStrides) {
+        self.process_sample_strides = process_sample_strides;
+    }
+
+    /// Turns the `sched_setaffinity` tracepoints into thread affinity markers
+    /// instead of tracepoint markers. The tracepoints need to be among the
+    /// formats passed to [`Self::set_tracepoint_formats`].
+    #[allow(unused)]
+    pub fn enable_thread_affinity_tracking(&mut self) {
+        self.thread_affinity = Some(AffinityTracker::default());
+    }
+
+    /// Adds a marker with the decoded arguments of a tracepoint sample, and
+    /// with the sample's stack.
+    #[allow(unused)]
+    pub fn handle_tracepoint_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
+        &mut self,
+        e: &SampleRecord,
+        tracepoint_index: usize,
+    ) {
+        let Some(format) = self.tracepoint_formats.get(tracepoint_index) else {
+            return;
+        };
+        let pid = e.pid.expect("Can't handle samples without pids");
+        let timestamp_mono = e
+            .timestamp
+            .expect("Can't handle samples without timestamps");
+        let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        let raw = e.raw.map(|raw| raw.as_slice()).unwrap_or_default();
+
+        if self.contention.is_some() && format.name == FUTEX_EXIT_TRACEPOINT {
+            self.handle_futex_exit(pid, e.tid, timestamp_mono);
+            return;
+        }
+        if self.thread_affinity.is_some() && format.name == SETAFFINITY_EXIT_TRACEPOINT {
+            if let Some(ret) = format.field_value("ret", &raw, self.endian) {
+                self.handle_setaffinity_exit(pid, e.tid, ret as i64);
+            }
+            return;
+        }
+        let setaffinity_pid_arg =
+            if self.thread_affinity.is_some() && format.name == SETAFFINITY_ENTER_TRACEPOINT {
+                match format.field_value("pid", &raw, self.endian) {
+                    Some(pid_arg) => Some(pid_arg as i32),
+                    None => return,
+                }
+            } else {
+                None
+            };
+        let futex_op = if self.contention.is_some() && format.name == FUTEX_ENTER_TRACEPOINT {
+            let address = format.field_value("uaddr", &raw, self.endian);
+            let op = format.field_value("op", &raw, self.endian);
+            match (address, op) {
+                (Some(address), Some(op)) => Some((address, op)),
+                _ => return,
+            }
+        } else {
+            None
+        };
+
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        process.check_jitdump(
+            &mut self.jit_category_manager,
+            &mut self.profile,
+            &self.timestamp_converter,
+        );
+        let mut stack = Vec::new();
+        Self::get_sample_stack::<C>(
+            e,
+            &process.unwinder,
+            &mut self.cache,
+            &mut stack,
+            self.fold_recursive_prefix,
+            self.call_chain_return_addresses_are_preadjusted,
+        );
+        let unresolved_stack = self.unresolved_stacks.convert(stack.into_iter().rev());
+        let thread_handle = match e.tid {
+            Some(tid) => {
+                process
+                    .threads
+                    .get_thread_by_tid(tid, &mut self.profile)
+                    .profile_thread
+            }
+            None => process.threads.main_thread.profile_thread,
+        };
+        if let (Some(pid_arg), Some(thread_affinity)) =
+            (setaffinity_pid_arg, &mut self.thread_affinity)
+        {
+            // The marker is added once the call has returned.
+            let tid = e.tid.unwrap_or(pid);
+            thread_affinity.handle_enter(tid, pid_arg, timestamp_mono, unresolved_stack);
+            return;
+        }
+        let marker_handle = match (futex_op, &mut self.contention) {
+            (Some((address, op)), Some(contention)) => {
+                let tid = e.tid.unwrap_or(pid);
+                let kind = contention.handle_enter(
+                    pid,
+                    tid,
+                    timestamp_mono,
+                    address,
+                    op,
+                    unresolved_stack,
+                );
+                if kind != FutexOpKind::Wake {
+                    // Waits get their marker once they return.
+                    return;
+                }
+                let address = self.profile.intern_string(&format!("{address:#x}"));
+                self.profile.add_marker(
+                    thread_handle,
+                    MarkerTiming::Instant(timestamp),
+                    LockReleaseMarker { address },
+                )
+            }
+            _ => {
+                let args = format.decode(&raw, self.endian);
+                let name = self.profile.intern_string(&format.name);
+                let args = self.profile.intern_string(&args);
+                self.profile.add_marker(
+                    thread_handle,
+                    MarkerTiming::Instant(timestamp),
+                    TracepointMarker { name, args },
+                )
+            }
+        };
+        process.unresolved_samples.attach_stack_to_marker(
+            thread_handle,
+            timestamp,
+            timestamp_mono,
+            unresolved_stack,
+            marker_handle,
+        );
+    }
+
+    /// Adds a "Lock contention" marker for the futex wait which ended on this
+    /// thread, if it was waiting, with the stack at which it blocked.
+    fn handle_futex_exit(&mut self, pid: i32, tid: Option<i32>, timestamp_mono: u64) {
+        let Some(contention) = &mut self.contention else {
+            return;
+        };
+        let tid = tid.unwrap_or(pid);
+        let Some(wait) = contention.handle_exit(tid, timestamp_mono) else {
+            return;
+        };
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let thread_handle = process
+            .threads
+            .get_thread_by_tid(tid, &mut self.profile)
+            .profile_thread;
+        let start_timestamp = self
+            .timestamp_converter
+            .convert_time(wait.start_timestamp_mono);
+        let end_timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        let address = self.profile.intern_string(&format!("{:#x}", wait.address));
+        let holder = match wait.waker_tid {
+            Some(waker_tid) => format!("tid {waker_tid}"),
+            None => "unknown".to_string(),
+        };
+        let holder = self.profile.intern_string(&holder);
+        let marker_handle = self.profile.add_marker(
+            thread_handle,
+            MarkerTiming::Interval(start_timestamp, end_timestamp),
+            LockContentionMarker { address, holder },
+        );
+        process.unresolved_samples.attach_stack_to_marker(
+            thread_handle,
+            start_timestamp,
+            wait.start_timestamp_mono,
+            wait.stack,
+            marker_handle,
+        );
+    }
+
+    /// Adds a "Thread affinity" marker for the `sched_setaffinity` call which
+    /// returned on this thread, if it succeeded, with the stack of the call.
+    fn handle_setaffinity_exit(&mut self, pid: i32, tid: Option<i32>, ret: i64) {
+        let Some(thread_affinity) = &mut self.thread_affinity else {
+            return;
+        };
+        let tid = tid.unwrap_or(pid);
+        let Some(change) = thread_affinity.handle_exit(tid, ret) else {
+            return;
+        };
+        let cpus = read_cpus_allowed_list(change.target_tid);
+        let count = cpus.as_deref().and_then(cpu_count).unwrap_or(0);
+        let cpus = self
+            .profile
+            .intern_string(cpus.as_deref().unwrap_or("unknown"));
+        let target = self
+            .profile
+            .intern_string(&format!("tid {}", change.target_tid));
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let thread_handle = process
+            .threads
+            .get_thread_by_tid(tid, &mut self.profile)
+            .profile_thread;
+        let timestamp = self.timestamp_converter.convert_time(change.timestamp_mono);
+        let marker_handle = self.profile.add_marker(
+            thread_handle,
+            MarkerTiming::Instant(timestamp),
+            ThreadAffinityMarker {
+                thread: target,
+                cpus,
+                cpu_count: count,
+            },
+        );
+        process.unresolved_samples.attach_stack_to_marker(
+            thread_handle,
+            timestamp,
+            change.timestamp_mono,
+            change.stack,
+            marker_handle,
+        );
+    }
+
     /// Get the stack contained in this sample, and put it into `stack`.
     ///
     /// We can have both the kernel stack and the user stack, or just one of
@@ -753,6 +1238,10 @@ where
             return;
         }
 
+        if e.pid != -1 && self.check_anonymous_code_region(e.pid, &path, e.address, e.length) {
+            return;
+        }
+
         let dso_key = match DsoKey::detect(&path, e.cpu_mode) {
             Some(dso_key) => dso_key,
             None => return,
@@ -816,6 +1305,10 @@ where
             return;
         }
 
+        if self.check_anonymous_code_region(e.pid, &path, e.address, e.length) {
+            return;
+        }
+
         let build_id = match &e.file_id {
             Mmap2FileId::BuildId(build_id) => Some(build_id.to_owned()),
             Mmap2FileId::InodeAndVersion(_) => {
@@ -840,6 +1333,31 @@ where
         );
     }
 
+    /// Records executable mappings of anonymous memory, so that samples in them
+    /// can be grouped by region if no jitdump or perf map file describes them.
+    /// Returns true if the mapping was anonymous.
+    fn check_anonymous_code_region(
+        &mut self,
+        pid: i32,
+        path: &[u8],
+        start_avma: u64,
+        size: u64,
+    ) -> bool {
+        if !is_anonymous_mapping_path(path) {
+            return false;
+        }
+        let avma_range = AvmaRange::with_start_size(start_avma, size);
+        if path.is_empty() && self.pe_mappings.find_mapping(&avma_range).is_some() {
+            // Wine maps PE images as anonymous memory; these are handled as modules.
+            return false;
+        }
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        process
+            .anonymous_code_regions
+            .add_region(start_avma, start_avma + size);
+        true
+    }
+
     fn check_jitdump_or_marker_file(&mut self, path: &[u8], pid: i32, tid: i32) -> bool {
         let Ok(path) = std::str::from_utf8(path) else {
             return false;
@@ -910,6 +1428,7 @@ where
                         cpu_delta_ns,
                         &self.timestamp_converter,
                         self.off_cpu_weight_per_sample,
+                        self.wall_clock_samples,
                         off_cpu_stack,
                         &mut process.unresolved_samples,
                     );
@@ -1013,6 +1532,7 @@ where
                 &mut self.profile,
             );
             child_process.adopt_fork_data_from_parent(fork_data);
+            child_process.parent_pid = Some(e.ppid);
         } else {
             // New thread within the same process.
             // eprintln!("New thread: pid={}, old_tid={}, new_tid={}", e.pid, e.ptid, e.tid);
@@ -1074,16 +1594,23 @@ where
         };
         let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
 
-        let name = if let Some((exec_name, args)) = exec_name_and_cmdline {
-            make_process_name(&exec_name, args, self.arg_count_to_include_in_process_name)
-        } else {
-            comm_name.clone()
-        };
+        let (name, command_line, truncated_exec_name) =
+            if let Some((exec_name, args)) = exec_name_and_cmdline {
+                let command_line = shlex::try_join(args.iter().map(String::as_str)).ok();
+                let name =
+                    make_process_name(&exec_name, args, self.arg_count_to_include_in_process_name);
+                (name, command_line, None)
+            } else {
+                let truncated_exec_name =
+                    (comm_name.len() == MAX_COMM_NAME_LEN).then(|| comm_name.clone());
+                (comm_name, None, truncated_exec_name)
+            };
 
         // eprintln!("Process execve: pid={}, tid={}, new name: {}", e.pid, e.tid, name);
 
         // Mark the old thread / process as ended.
         if is_main {
+            let parent_pid = self.processes.parent_pid(e.pid);
             self.processes.remove(
                 e.pid,
                 timestamp,
@@ -1091,12 +1618,15 @@ where
                 &mut self.jit_category_manager,
                 &self.timestamp_converter,
             );
-            self.processes.recycle_or_get_new(
+            let process = self.processes.recycle_or_get_new(
                 e.pid,
                 Some(name.to_string()),
                 timestamp,
                 &mut self.profile,
             );
+            process.parent_pid = parent_pid;
+            process.command_line = command_line;
+            process.truncated_exec_name = truncated_exec_name;
         } else {
             eprintln!(
                 "Unexpected is_execve on non-main thread! pid: {}, tid: {}",
@@ -1148,12 +1678,15 @@ where
     pub fn register_existing_process(
         &mut self,
         pid: i32,
+        parent_pid: Option<i32>,
         comm_name: &str,
         exe_name: &str,
         args: Vec<String>,
     ) {
         let process = self.processes.get_by_pid(pid, &mut self.profile);
         let process_handle = process.profile_process;
+        process.parent_pid = parent_pid;
+        process.command_line = shlex::try_join(args.iter().map(String::as_str)).ok();
 
         let name = make_process_name(exe_name, args, self.arg_count_to_include_in_process_name);
         self.profile.set_process_name(process_handle, &name);
@@ -1248,6 +1781,7 @@ where
                 .get(path_slice)
                 .map(|s| s.symbol_table.clone())
         };
+        let symbol_table = symbol_table.filter(|_| self.embed_symbol_tables);
 
         let lib_handle = self.profile.add_lib(LibraryInfo {
             debug_id: debug_id.unwrap_or_default(),
@@ -1307,30 +1841,72 @@ where
         let path = e.path.as_slice();
         let address = e.address;
         let mapping_size = e.length;
-        let (name, len) = self
-            .get_simpleperf_jit_function_name(&path, address)
-            .unwrap_or_else(|| (format!("jit_fun_{address:x}"), mapping_size as u32));
+        let mut functions =
+            self.get_simpleperf_jit_functions(&path, address, address + mapping_size);
+        if functions.is_empty() {
+            functions.push((format!("jit_fun_{address:x}"), address, mapping_size as u32));
+        }
 
         let process = self.processes.get_by_pid(e.pid, &mut self.profile);
-        let synthetic_lib = &mut self.simpleperf_jit_app_cache_library;
+        let synthetic_lib = match simpleperf_jit_cache_kind(&String::from_utf8_lossy(&path)) {
+            Some(SimpleperfJitCache::Zygote) => &mut self.simpleperf_jit_zygote_cache_library,
+            _ => &mut self.simpleperf_jit_app_cache_library,
+        };
         let info = LibMappingInfo::new_java_mapping(
             synthetic_lib.lib_handle(),
             Some(synthetic_lib.default_category()),
         );
-        process.add_jit_function(timestamp_raw, synthetic_lib, name, address, len, info);
+        for (name, start_avma, len) in functions {
+            process.add_jit_function(
+                timestamp_raw,
+                synthetic_lib,
+                name,
+                start_avma,
+                len,
+                info.clone(),
+            );
+        }
     }
 
-    fn get_simpleperf_jit_function_name(
+    /// Returns the (name, start address, size) of the JIT-compiled methods in
+    /// the given address range of a simpleperf JIT symfile. A symfile can
+    /// contain several methods, for example when ART packs the debug info of
+    /// the zygote's JIT code.
+    fn get_simpleperf_jit_functions(
         &self,
         path_slice: &[u8],
         start_avma: u64,
-    ) -> Option<(String, u32)> {
-        let symbols = self.simpleperf_symbol_tables_jit.get(path_slice)?;
-        let index = symbols
-            .binary_search_by_key(&start_avma, |sym| sym.vaddr)
-            .ok()?;
-        let sym = &symbols[index];
-        Some((sym.name.clone(), sym.len))
+        end_avma: u64,
+    ) -> Vec<(String, u64, u32)> {
+        let Some(symbols) = self.simpleperf_symbol_tables_jit.get(path_slice) else {
+            return Vec::new();
+        };
+        let first_index = symbols.partition_point(|sym| sym.vaddr < start_avma);
+        symbols[first_index..]
+            .iter()
+            .take_while(|sym| sym.vaddr < end_avma)
+            .map(|sym| {
+                let len = u64::from(sym.len).min(end_avma - sym.vaddr) as u32;
+                (sym.name.clone(), sym.vaddr, len)
+            })
+            .collect()
+    }
+
+    /// Gives a process whose exec name was truncated its full name, from the
+    /// first executable mapping after the exec, which is the executable itself.
+    fn check_exec_file_name(&mut self, pid: i32, path: &Path, timestamp: u64) {
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let Some(comm_name) = process.truncated_exec_name.take() else {
+            return;
+        };
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            return;
+        };
+        if let Some(name) = full_name_for_truncated_comm(&comm_name, file_name) {
+            let timestamp = self.timestamp_converter.convert_time(timestamp);
+            self.processes
+                .rename_process(pid, timestamp, name.to_string(), &mut self.profile);
+        }
     }
 
     /// Tell the unwinder and the profile about this module.
@@ -1356,9 +1932,17 @@ where
             build_id.map(|build_id| CodeId::ElfBuildId(ElfBuildId::from_bytes(build_id)));
 
         let original_path = path_slice;
+        // If the file has been deleted, a different file may exist at its path now,
+        // so we only use the build ID to find it.
+        let (path_slice, is_deleted) =
+            match path_slice.strip_suffix(DELETED_MAPPING_SUFFIX.as_bytes()) {
+                Some(path_slice) => (path_slice, true),
+                None => (path_slice, false),
+            };
         let Some(path) = path_from_unix_bytes(path_slice) else {
             return;
         };
+        self.check_exec_file_name(process_pid, path, timestamp);
 
         let mut mapping_info = MappingInfo::new_elf(path, avma_range);
         if path_slice.is_empty() {
@@ -1370,7 +1954,11 @@ where
         let mut file = None;
         let mut path = mapping_info.path.to_string_lossy().to_string();
 
-        if let Ok((f, p)) = open_file_with_fallback(&mapping_info.path, &self.binary_lookup_dirs) {
+        if is_deleted {
+            // Leave the file to the build ID lookup in case 5.
+        } else if let Ok((f, p)) =
+            open_file_with_fallback(&mapping_info.path, &self.binary_lookup_dirs)
+        {
             // Fix up bad files from `perf inject --jit`.
             if let Some((fixed_file, fixed_path)) = correct_bad_perf_jit_so_file(&f, &path) {
                 file = Some(fixed_file);
@@ -1428,7 +2016,11 @@ where
                 debug_name: name.clone(),
                 name,
                 arch: None,
-                symbol_table: Some(symbol_table.symbol_table.clone()),
+                // DEX files can't be symbolicated by the symbol server, so we
+                // always keep their symbols.
+                symbol_table: (self.embed_symbol_tables
+                    || symbol_table.file_offset_of_min_vaddr_in_elf_file.is_none())
+                .then(|| symbol_table.symbol_table.clone()),
             });
             let info = match symbol_table.art_info {
                 Some(AndroidArtInfo::LibArt) => LibMappingInfo::new_libart_mapping(lib_handle),
@@ -1448,41 +2040,48 @@ where
         }
 
         // Case 2: We have access to the file that was loaded into the process.
-        if let Some(file) = file {
-            let mmap = match unsafe { memmap2::MmapOptions::new().map(&file) } {
-                Ok(mmap) => Arc::new(mmap),
-                Err(err) => {
-                    eprintln!("Could not mmap file {path}: {err:?}");
-                    return;
-                }
-            };
-
-            let file = match object::File::parse(&mmap[..]) {
-                Ok(file) => file,
-                Err(_) => {
-                    eprintln!("File {path} has unrecognized format");
-                    return;
-                }
-            };
-
-            let file_code_id = mapping_info.code_id.clone().or_else(|| {
+        let mmap = match file.map(|file| unsafe { memmap2::MmapOptions::new().map(&file) }) {
+            Some(Ok(mmap)) => Some(Arc::new(mmap)),
+            Some(Err(err)) => {
+                eprintln!("Could not mmap file {path}: {err:?}");
+                return;
+            }
+            None => None,
+        };
+        let file = match mmap.as_deref().map(|mmap| object::File::parse(&mmap[..])) {
+            Some(Ok(file)) => Some(file),
+            Some(Err(_)) => {
+                eprintln!("File {path} has unrecognized format");
+                return;
+            }
+            None => None,
+        };
+        let file_code_id = file.as_ref().and_then(|file| {
+            mapping_info.code_id.clone().or_else(|| {
                 Some(CodeId::ElfBuildId(ElfBuildId::from_bytes(
                     file.build_id().ok()??,
                 )))
-            });
-            if expected_code_id.as_ref().is_some_and(|expected_code_id| {
+            })
+        });
+        // If the file at the path has a different build ID, it was replaced after it
+        // was mapped, e.g. by a redeploy. Then we fall back to the build ID lookup
+        // in case 5.
+        let is_replaced = file.is_some()
+            && expected_code_id.as_ref().is_some_and(|expected_code_id| {
                 !Self::code_id_matches(file_code_id.as_ref(), expected_code_id, &path)
-            }) {
-                return;
-            }
-
-            let module_section_info =
-                Self::module_section_info_with_object(Some(mmap.clone()), &file);
-            let Some(library_info) =
+            });
+        if let Some(file) = file.filter(|_| !is_replaced) {
+            let module_section_info = Self::module_section_info_with_object(mmap.clone(), &file);
+            let Some(mut library_info) =
                 Self::library_info_with_object(&name, &path, &file, file_code_id)
             else {
                 return;
             };
+            if file.format() == BinaryFormat::Pe {
+                // Symbols for .NET ReadyToRun code can't be found by the symbol
+                // server, so we always keep them.
+                library_info.symbol_table = Self::r2r_symbol_table(process_pid, &path);
+            }
 
             let Some(base_avma) = mapping_info.compute_base_avma(&file, mapping_start_file_offset)
             else {
@@ -1509,6 +2108,7 @@ where
                     avma_range.end(),
                     relative_address_at_start,
                     lib_handle,
+                    &name,
                     &mut self.jit_category_manager,
                     &mut self.profile,
                 );
@@ -1536,11 +2136,13 @@ where
                 let module_section_info =
                     Self::module_section_info_with_object(None, vdso.object());
                 let code_id = vdso.code_id().clone();
-                let Some(library_info) =
+                let Some(mut library_info) =
                     Self::library_info_with_object(&name, &path, vdso.object(), Some(code_id))
                 else {
                     return;
                 };
+                // The symbol server can't find the VDSO, so always keep its symbols.
+                library_info.symbol_table = Some(vdso.symbol_table());
 
                 let Some(base_avma) =
                     mapping_info.compute_base_avma(vdso.object(), mapping_start_file_offset)
@@ -1569,7 +2171,33 @@ where
             }
         }
 
-        // Case 4: We don't have access to the file.
+        // Case 4: This is the x86_64 vsyscall page, which has a fixed address
+        // and a fixed layout.
+        if name == "[vsyscall]" && mapping_start_avma == VSYSCALL_PAGE_START {
+            let lib_handle = self.profile.add_lib(LibraryInfo {
+                debug_id: DebugId::nil(),
+                code_id: None,
+                path: path.clone(),
+                debug_path: path,
+                debug_name: name.clone(),
+                name,
+                arch: None,
+                symbol_table: Some(Arc::new(vsyscall_symbol_table())),
+            });
+            process.add_regular_lib_mapping(
+                timestamp,
+                avma_range.start(),
+                avma_range.end(),
+                0,
+                LibMappingInfo::new_lib(lib_handle),
+            );
+            return;
+        }
+
+        // Case 5: We don't have access to the file, or the file has been deleted or
+        // replaced since it was mapped. The build ID, if we have one, lets the
+        // symbolication find the right file, e.g. in /usr/lib/debug/.build-id/ or
+        // on a debuginfod server.
 
         // Without access to the binary file, make some guesses. We can't really
         // know what the right base address is because we don't have the section
@@ -1648,6 +2276,24 @@ where
         })
     }
 
+    /// The symbols from the perf map of a .NET ReadyToRun image, unless the
+    /// runtime logged a different signature for the image than the map has.
+    fn r2r_symbol_table(process_pid: i32, path: &str) -> Option<Arc<SymbolTable>> {
+        let map = try_load_r2r_map(Path::new(path))?;
+        if let (Some(signature), Some(expected)) = (
+            &map.signature,
+            perfinfo_image_signature(process_pid as u32, path),
+        ) {
+            if !signatures_match(signature, &expected) {
+                eprintln!(
+                    "Ignoring the perf map of {path}, it was generated for a different build."
+                );
+                return None;
+            }
+        }
+        Some(Arc::new(map.into_symbol_table()))
+    }
+
     fn module_section_info_with_object<'data, R: object::ReadRef<'data>>(
         mmap_arc: Option<Arc<Mmap>>,
         file: &object::File<'data, R>,
@@ -1737,15 +2383,35 @@ where
 //     dbg!(jit_function_name(&file));
 // }
 
+#[allow(clippy::too_many_arguments)]
 fn process_off_cpu_sample_group(
     off_cpu_sample: OffCpuSampleGroup,
     thread_handle: ThreadHandle,
     cpu_delta_ns: u64,
     timestamp_converter: &TimestampConverter,
     off_cpu_weight_per_sample: i32,
+    wall_clock_samples: bool,
     off_cpu_stack: UnresolvedStackHandle,
     samples: &mut UnresolvedSamples,
 ) {
+    if wall_clock_samples {
+        // One sample per interval. The first one carries any leftover accumulated
+        // running time ("cpu delta").
+        let mut cpu_delta = CpuDelta::from_nanos(cpu_delta_ns);
+        for timestamp in off_cpu_sample.sample_timestamps() {
+            samples.add_sample(
+                thread_handle,
+                timestamp_converter.convert_time(timestamp),
+                timestamp,
+                off_cpu_stack,
+                std::mem::replace(&mut cpu_delta, CpuDelta::ZERO),
+                off_cpu_weight_per_sample,
+                None,
+            );
+        }
+        return;
+    }
+
     let OffCpuSampleGroup {
         begin_timestamp,
         end_timestamp,
@@ -1785,10 +2451,18 @@ fn process_off_cpu_sample_group(
     }
 }
 
-/// Returns true for paths such as the following:
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SimpleperfJitCache {
+    App,
+    Zygote,
+}
+
+/// Returns which JIT cache a simpleperf JIT symfile is for, for paths such as
+/// the following:
 ///  - "/data/local/tmp/perf.data_jit_app_cache:1039560-1040440"
+///  - "/data/local/tmp/perf.data_jit_zygote_cache:3072-8192"
 ///  - "./TemporaryFile-osHvVs" (used by older versions of simpleperf, e.g. on Android 11)
-fn is_simpleperf_jit_path(path: &str) -> bool {
+fn simpleperf_jit_cache_kind(path: &str) -> Option<SimpleperfJitCache> {
     let path = match path.rsplit_once(':') {
         Some((base_path, _range)) => base_path,
         None => path,
@@ -1797,7 +2471,13 @@ fn is_simpleperf_jit_path(path: &str) -> bool {
         Some(pos) => &path[pos + 1..],
         None => path,
     };
-    name.ends_with("_jit_app_cache") || name.starts_with("TemporaryFile-")
+    if name.ends_with("_jit_zygote_cache") {
+        Some(SimpleperfJitCache::Zygote)
+    } else if name.ends_with("_jit_app_cache") || name.starts_with("TemporaryFile-") {
+        Some(SimpleperfJitCache::App)
+    } else {
+        None
+    }
 }
 
 struct MappingInfo {
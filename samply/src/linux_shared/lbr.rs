@@ -0,0 +1,134 @@
+//! Corrects the innermost frames of unwound stacks with the help of the
+//! call stack from the Last Branch Record (LBR).
+//!
+//! Frame pointer unwinding skips the caller of a leaf function which doesn't
+//! set up a frame pointer, and DWARF unwinding gives up when the leaf has no
+//! unwind info. The LBR call stack records the call instructions of the
+//! innermost calls in hardware, so it doesn't have these problems. It only
+//! holds a limited number of entries though (16 or 32), so we use it for the
+//! innermost frames and keep the unwound frames beyond the point where both
+//! stacks agree.
+
+use crate::shared::types::{StackFrame, StackMode};
+
+/// The maximum length of a call instruction on x86.
+const MAX_CALL_INSTRUCTION_LEN: u64 = 15;
+
+/// Replaces the innermost caller frames in `stack` with the call sites from
+/// the LBR call stack, up to the first caller frame on which both agree.
+///
+/// `stack` is ordered from the leaf to the root, as produced by
+/// `Converter::get_sample_stack`. `call_sites` are the addresses of the call
+/// instructions from the LBR call stack, innermost call first.
+pub fn fix_up_stack_with_lbr_call_stack(stack: &mut Vec<StackFrame>, call_sites: &[u64]) {
+    if call_sites.is_empty() {
+        return;
+    }
+
+    // The LBR call stack only contains user-space calls.
+    let Some(leaf_index) = stack
+        .iter()
+        .position(|frame| matches!(frame, StackFrame::InstructionPointer(_, StackMode::User)))
+    else {
+        return;
+    };
+    let callers_start = leaf_index + 1;
+
+    // Find the innermost call site which is also present in the unwound stack.
+    let sync_point = call_sites
+        .iter()
+        .enumerate()
+        .find_map(|(call_index, &call_site)| {
+            let frame_offset = stack[callers_start..]
+                .iter()
+                .position(|frame| is_return_address_for_call_site(frame, call_site))?;
+            Some((call_index, callers_start + frame_offset))
+        });
+
+    let (lbr_frame_count, replaced_end) = match sync_point {
+        Some(sync_point) => sync_point,
+        None => {
+            // The stacks don't agree anywhere. Trust the LBR stack only if
+            // unwinding didn't get any further than the LBR stack goes.
+            let unwinding_failed = stack.last() == Some(&StackFrame::TruncatedStackMarker);
+            if !unwinding_failed && stack.len() - callers_start > call_sites.len() {
+                return;
+            }
+            (call_sites.len(), stack.len())
+        }
+    };
+
+    stack.splice(
+        callers_start..replaced_end,
+        call_sites[..lbr_frame_count]
+            .iter()
+            .map(|&call_site| StackFrame::AdjustedReturnAddress(call_site, StackMode::User)),
+    );
+}
+
+fn is_return_address_for_call_site(frame: &StackFrame, call_site: u64) -> bool {
+    match *frame {
+        StackFrame::ReturnAddress(address, StackMode::User) => {
+            address > call_site && address - call_site <= MAX_CALL_INSTRUCTION_LEN
+        }
+        StackFrame::AdjustedReturnAddress(address, StackMode::User) => {
+            address >= call_site && address - call_site < MAX_CALL_INSTRUCTION_LEN
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(address: u64) -> StackFrame {
+        StackFrame::InstructionPointer(address, StackMode::User)
+    }
+
+    fn ret(address: u64) -> StackFrame {
+        StackFrame::ReturnAddress(address, StackMode::User)
+    }
+
+    fn lbr(address: u64) -> StackFrame {
+        StackFrame::AdjustedReturnAddress(address, StackMode::User)
+    }
+
+    #[test]
+    fn inserts_skipped_caller() {
+        // The frame pointer unwinder skipped the caller of the leaf function,
+        // whose call site is at 0x2000.
+        let mut stack = vec![ip(0x1010), ret(0x3005), ret(0x4005)];
+        fix_up_stack_with_lbr_call_stack(&mut stack, &[0x2000, 0x3000, 0x4000]);
+        assert_eq!(
+            stack,
+            vec![ip(0x1010), lbr(0x2000), ret(0x3005), ret(0x4005)]
+        );
+    }
+
+    #[test]
+    fn keeps_matching_stack() {
+        let mut stack = vec![ip(0x1010), ret(0x2005), ret(0x3005)];
+        fix_up_stack_with_lbr_call_stack(&mut stack, &[0x2000, 0x3000]);
+        assert_eq!(stack, vec![ip(0x1010), ret(0x2005), ret(0x3005)]);
+    }
+
+    #[test]
+    fn replaces_failed_unwind() {
+        let mut stack = vec![ip(0x1010), StackFrame::TruncatedStackMarker];
+        fix_up_stack_with_lbr_call_stack(&mut stack, &[0x2000, 0x3000]);
+        assert_eq!(stack, vec![ip(0x1010), lbr(0x2000), lbr(0x3000)]);
+    }
+
+    #[test]
+    fn ignores_unrelated_lbr_stack() {
+        // The unwound stack is deeper than the LBR stack and doesn't match it,
+        // for example because the LBR stack was corrupted by a longjmp.
+        let mut stack = vec![ip(0x1010), ret(0x2005), ret(0x3005), ret(0x4005)];
+        fix_up_stack_with_lbr_call_stack(&mut stack, &[0x5000, 0x6000]);
+        assert_eq!(
+            stack,
+            vec![ip(0x1010), ret(0x2005), ret(0x3005), ret(0x4005)]
+        );
+    }
+}
@@ -1,9 +1,13 @@
 mod avma_range;
+#[allow(unused)]
+pub mod checkpoint;
+mod contention;
 mod convert_regs;
 mod converter;
 mod event_interpretation;
 mod injected_jit_object;
 mod kernel_symbols;
+mod lbr;
 mod mmap_range_or_vec;
 mod object_rewriter;
 mod pe_mappings;
@@ -12,12 +16,22 @@ mod process_threads;
 mod processes;
 mod rss_stat;
 mod svma_file_range;
+mod syscall_categories;
 mod thread;
+mod thread_affinity;
+mod tracepoint;
 #[allow(unused)]
 pub mod vdso;
+mod vm_steal;
 
+#[allow(unused)]
+pub use contention::{FUTEX_ENTER_TRACEPOINT, FUTEX_EXIT_TRACEPOINT};
 pub use convert_regs::{ConvertRegs, ConvertRegsAarch64, ConvertRegsX86_64};
-pub use converter::Converter;
+pub use converter::{Converter, DELETED_MAPPING_SUFFIX};
 #[allow(unused)]
 pub use event_interpretation::{EventInterpretation, KnownEvent, OffCpuIndicator};
 pub use mmap_range_or_vec::MmapRangeOrVec;
+#[allow(unused)]
+pub use thread_affinity::{SETAFFINITY_ENTER_TRACEPOINT, SETAFFINITY_EXIT_TRACEPOINT};
+#[allow(unused)]
+pub use tracepoint::TracepointFormat;
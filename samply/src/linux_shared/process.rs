@@ -8,19 +8,25 @@ use fxprof_processed_profile::{
 
 use super::process_threads::ProcessThreads;
 use super::thread::Thread;
+use crate::shared::anonymous_code::AnonymousCodeRegions;
 use crate::shared::jit_category_manager::JitCategoryManager;
 use crate::shared::jit_function_add_marker::JitFunctionAddMarker;
 use crate::shared::jit_function_recycler::JitFunctionRecycler;
 use crate::shared::jitdump_manager::JitDumpManager;
 use crate::shared::lib_mappings::{LibMappingAdd, LibMappingInfo, LibMappingOp, LibMappingOpQueue};
-use crate::shared::marker_file::get_markers;
+use crate::shared::lifetime_markers::{Lifetime, ProcessLifetimeMarker};
+use crate::shared::marker_file::{add_user_counters, get_markers};
 use crate::shared::perf_map::try_load_perf_map;
+use crate::shared::process_intervals::SampleThinner;
 use crate::shared::process_sample_data::{MarkerSpanOnThread, ProcessSampleData};
 use crate::shared::recycling::{ProcessRecyclingData, ThreadRecycler};
 use crate::shared::synthetic_jit_library::SyntheticJitLibrary;
 use crate::shared::timestamp_converter::TimestampConverter;
 use crate::shared::unresolved_samples::UnresolvedSamples;
 
+/// The kernel truncates process names in COMM records to this many bytes.
+pub const MAX_COMM_NAME_LEN: usize = 15;
+
 pub struct Process<U> {
     pub profile_process: ProcessHandle,
     pub unwinder: U,
@@ -32,17 +38,30 @@ pub struct Process<U> {
     pub unresolved_samples: UnresolvedSamples,
     pub jit_app_cache_mapping_ops: LibMappingOpQueue,
     pub jit_function_recycler: Option<JitFunctionRecycler>,
+    /// Executable anonymous memory, e.g. from JITs which don't write jitdump files.
+    pub anonymous_code_regions: AnonymousCodeRegions,
     marker_file_paths: Vec<(ThreadHandle, PathBuf, Vec<PathBuf>)>,
     pub prev_mm_filepages_size: i64,
     pub prev_mm_anonpages_size: i64,
     pub prev_mm_swapents_size: i64,
     pub prev_mm_shmempages_size: i64,
     pub mem_counter: Option<CounterHandle>,
+    pub lifetime: Lifetime,
+    pub parent_pid: Option<i32>,
+    /// Only known for processes whose exec we observed with its arguments.
+    pub command_line: Option<String>,
+    /// The name from the COMM record of the exec, if it's as long as the
+    /// kernel allows, i.e. probably truncated, and we couldn't read the
+    /// command line. The first mapping of the executable gives the full name.
+    pub truncated_exec_name: Option<String>,
+    /// Drops samples if the process has a lower rate from `--interval-for`.
+    pub sample_thinner: SampleThinner,
 }
 
 pub struct ProcessForkData<U> {
     unwinder: U,
     lib_mapping_ops: LibMappingOpQueue,
+    anonymous_code_regions: AnonymousCodeRegions,
 }
 
 impl<U> Process<U>
@@ -59,6 +78,7 @@ where
         thread_recycler: Option<ThreadRecycler>,
         jit_function_recycler: Option<JitFunctionRecycler>,
         unlink_aux_files: bool,
+        lifetime: Lifetime,
     ) -> Self {
         Self {
             profile_process: process_handle,
@@ -74,16 +94,23 @@ where
                 main_thread_label_frame,
                 name,
                 thread_recycler,
+                lifetime,
             ),
             unresolved_samples: Default::default(),
             jit_app_cache_mapping_ops: LibMappingOpQueue::default(),
             jit_function_recycler,
+            anonymous_code_regions: AnonymousCodeRegions::default(),
             marker_file_paths: Vec::new(),
             prev_mm_filepages_size: 0,
             prev_mm_anonpages_size: 0,
             prev_mm_swapents_size: 0,
             prev_mm_shmempages_size: 0,
             mem_counter: None,
+            lifetime,
+            parent_pid: None,
+            command_line: None,
+            truncated_exec_name: None,
+            sample_thinner: SampleThinner::default(),
         }
     }
 
@@ -92,6 +119,7 @@ where
         ProcessForkData {
             unwinder: self.unwinder.clone(),
             lib_mapping_ops: self.lib_mapping_ops.clone(),
+            anonymous_code_regions: self.anonymous_code_regions.clone(),
         }
     }
 
@@ -99,6 +127,7 @@ where
     pub fn adopt_fork_data_from_parent(&mut self, fork_data: ProcessForkData<U>) {
         self.unwinder = fork_data.unwinder;
         self.lib_mapping_ops = fork_data.lib_mapping_ops;
+        self.anonymous_code_regions = fork_data.anonymous_code_regions;
     }
 
     pub fn rename_with_recycling(
@@ -186,6 +215,27 @@ where
     pub fn notify_dead(&mut self, end_time: Timestamp, profile: &mut Profile) {
         self.threads.notify_process_dead(end_time, profile);
         profile.set_process_end_time(self.profile_process, end_time);
+        self.lifetime.set_end(end_time);
+    }
+
+    fn add_lifetime_marker(&self, profile: &mut Profile) {
+        let name = match &self.name {
+            Some(name) => profile.intern_string(name),
+            None => profile.intern_string(&format!("<{}>", self.pid)),
+        };
+        let marker = ProcessLifetimeMarker {
+            name,
+            pid: self.pid as u32,
+            parent_pid: self.parent_pid.unwrap_or(0) as u32,
+            command_line: profile.intern_string(self.command_line.as_deref().unwrap_or("")),
+            // perf doesn't record exit codes.
+            exit_code: profile.intern_string(""),
+        };
+        profile.add_marker(
+            self.threads.main_thread.profile_thread,
+            self.lifetime.marker_timing(),
+            marker,
+        );
     }
 
     pub fn finish(
@@ -195,6 +245,7 @@ where
         timestamp_converter: &TimestampConverter,
     ) -> (ProcessSampleData, Option<(String, ProcessRecyclingData)>) {
         self.unwinder = U::default();
+        self.add_lifetime_marker(profile);
 
         let perf_map_mappings = if !self.unresolved_samples.is_empty() {
             try_load_perf_map(
@@ -207,6 +258,13 @@ where
             None
         };
 
+        let anonymous_code_regions = std::mem::take(&mut self.anonymous_code_regions);
+        let anonymous_code_mappings = if !self.unresolved_samples.is_empty() {
+            anonymous_code_regions.into_lib_mappings(self.pid as u32, profile, jit_category_manager)
+        } else {
+            None
+        };
+
         let jitdump_manager =
             std::mem::replace(&mut self.jitdump_manager, JitDumpManager::new(false));
         let mut jitdump_ops = jitdump_manager.finish(
@@ -221,11 +279,12 @@ where
         }
 
         let mut marker_spans = Vec::new();
+        let mut counter_samples = Vec::new();
         for (thread_handle, marker_file_path, lookup_dirs) in self.marker_file_paths {
-            if let Ok(marker_spans_from_this_file) =
+            if let Ok(marker_file_contents) =
                 get_markers(&marker_file_path, &lookup_dirs, *timestamp_converter)
             {
-                marker_spans.extend(marker_spans_from_this_file.into_iter().map(|span| {
+                marker_spans.extend(marker_file_contents.spans.into_iter().map(|span| {
                     MarkerSpanOnThread {
                         thread_handle,
                         start_time: span.start_time,
@@ -233,23 +292,26 @@ where
                         name: span.name,
                     }
                 }));
+                counter_samples.extend(marker_file_contents.counter_samples);
             }
         }
+        add_user_counters(profile, self.profile_process, counter_samples);
 
         let process_sample_data = ProcessSampleData::new(
             std::mem::take(&mut self.unresolved_samples),
             std::mem::take(&mut self.lib_mapping_ops),
             jitdump_ops,
             perf_map_mappings,
+            anonymous_code_mappings,
             marker_spans,
         );
 
-        let thread_recycler = self.threads.finish();
+        let thread_recycler = self.threads.finish(profile);
 
         let process_recycling_data = if let (
             Some(name),
             Some(jit_function_recycler),
-            (Some(thread_recycler), main_thread_recycling_data),
+            (thread_recycler, main_thread_recycling_data),
         ) = (self.name, self.jit_function_recycler, thread_recycler)
         {
             let recycling_data = ProcessRecyclingData {
@@ -296,19 +358,22 @@ where
         end_address: u64,
         mut relative_address_at_start: u32,
         mut lib_handle: LibraryHandle,
+        lib_name: &str,
         jit_category_manager: &mut JitCategoryManager,
         profile: &mut Profile,
     ) {
         let main_thread = self.threads.main_thread.profile_thread;
         let timing = MarkerTiming::Instant(profile_timestamp);
-        let name = match symbol_name {
-            Some(name) => profile.intern_string(name),
-            None => profile.intern_string("<unknown>"),
+        let code_size = (end_address - start_address) as u32;
+        let marker = JitFunctionAddMarker {
+            name: profile.intern_string(symbol_name.unwrap_or("<unknown>")),
+            tier: profile.intern_string(JitCategoryManager::jit_tier(symbol_name.unwrap_or(""))),
+            module: profile.intern_string(lib_name),
+            code_size,
         };
-        profile.add_marker(main_thread, timing, JitFunctionAddMarker(name));
+        profile.add_marker(main_thread, timing, marker);
 
         if let (Some(name), Some(recycler)) = (symbol_name, self.jit_function_recycler.as_mut()) {
-            let code_size = (end_address - start_address) as u32;
             (lib_handle, relative_address_at_start) =
                 recycler.recycle(name, code_size, lib_handle, relative_address_at_start);
         }
@@ -359,3 +424,30 @@ where
         })
     }
 }
+
+/// Returns the file name of the executable if `comm_name` is a truncated
+/// version of it.
+pub fn full_name_for_truncated_comm<'a>(comm_name: &str, file_name: &'a str) -> Option<&'a str> {
+    let is_truncated = comm_name.len() == MAX_COMM_NAME_LEN
+        && file_name.len() > comm_name.len()
+        && file_name.starts_with(comm_name);
+    is_truncated.then_some(file_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_full_name_for_truncated_comm() {
+        assert_eq!(
+            full_name_for_truncated_comm("x86_64-linux-gn", "x86_64-linux-gnu-gcc-12"),
+            Some("x86_64-linux-gnu-gcc-12")
+        );
+        assert_eq!(full_name_for_truncated_comm("cc1plus", "cc1plus"), None);
+        assert_eq!(
+            full_name_for_truncated_comm("build-script-bu", "ld-linux-x86-64.so.2"),
+            None
+        );
+    }
+}
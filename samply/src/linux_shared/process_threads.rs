@@ -5,7 +5,9 @@ use fxprof_processed_profile::{
 };
 
 use super::thread::Thread;
+use crate::shared::lifetime_markers::Lifetime;
 use crate::shared::recycling::ThreadRecycler;
+use crate::shared::thread_counts::ThreadCounts;
 use crate::shared::types::FastHashMap;
 
 pub struct ProcessThreads {
@@ -14,6 +16,7 @@ pub struct ProcessThreads {
     pub main_thread: Thread,
     pub threads_by_tid: FastHashMap<i32, Thread>,
     pub thread_recycler: Option<ThreadRecycler>,
+    thread_counts: ThreadCounts,
 }
 
 impl ProcessThreads {
@@ -24,13 +27,17 @@ impl ProcessThreads {
         main_thread_label_frame: FrameInfo,
         name: Option<String>,
         thread_recycler: Option<ThreadRecycler>,
+        lifetime: Lifetime,
     ) -> Self {
+        let mut thread_counts = ThreadCounts::default();
+        thread_counts.thread_started(lifetime.start());
         Self {
             pid,
             profile_process: process_handle,
-            main_thread: Thread::new(main_thread_handle, main_thread_label_frame, name),
+            main_thread: Thread::new(main_thread_handle, main_thread_label_frame, name, lifetime),
             threads_by_tid: Default::default(),
             thread_recycler,
+            thread_counts,
         }
     }
 
@@ -39,18 +46,14 @@ impl ProcessThreads {
         name: String,
         process_handle: ProcessHandle,
         main_thread_recycling_data: (ThreadHandle, FrameInfo),
-        thread_recycler: ThreadRecycler,
-    ) -> (ThreadRecycler, (ThreadHandle, FrameInfo)) {
+        thread_recycler: Option<ThreadRecycler>,
+    ) -> (Option<ThreadRecycler>, (ThreadHandle, FrameInfo)) {
         let _old_process_handle = std::mem::replace(&mut self.profile_process, process_handle);
         let (_old_name, old_main_thread_recycling_data) = self
             .main_thread
             .rename_with_recycling(name, main_thread_recycling_data);
-        let old_thread_recycler =
-            std::mem::replace(&mut self.thread_recycler, Some(thread_recycler));
-        (
-            old_thread_recycler.expect("thread_recycler should be Some"),
-            old_main_thread_recycling_data,
-        )
+        let old_thread_recycler = std::mem::replace(&mut self.thread_recycler, thread_recycler);
+        (old_thread_recycler, old_main_thread_recycling_data)
     }
 
     pub fn recycle_or_get_new_thread(
@@ -65,13 +68,18 @@ impl ProcessThreads {
         }
         match self.threads_by_tid.entry(tid) {
             Entry::Vacant(entry) => {
+                self.thread_counts.thread_started(start_time);
                 if let (Some(name), Some(thread_recycler)) = (&name, self.thread_recycler.as_mut())
                 {
                     if let Some((thread_handle, thread_label_frame)) =
                         thread_recycler.recycle_by_name(name)
                     {
-                        let thread =
-                            Thread::new(thread_handle, thread_label_frame, Some(name.clone()));
+                        let thread = Thread::new(
+                            thread_handle,
+                            thread_label_frame,
+                            Some(name.clone()),
+                            Lifetime::new(start_time, false),
+                        );
                         return entry.insert(thread);
                     }
                 }
@@ -83,7 +91,12 @@ impl ProcessThreads {
                 }
                 let thread_label_frame =
                     make_thread_label_frame(profile, name.as_deref(), self.pid, tid);
-                let thread = Thread::new(thread_handle, thread_label_frame, name);
+                let thread = Thread::new(
+                    thread_handle,
+                    thread_label_frame,
+                    name,
+                    Lifetime::new(start_time, false),
+                );
                 entry.insert(thread)
             }
             Entry::Occupied(entry) => {
@@ -101,6 +114,7 @@ impl ProcessThreads {
                 let thread = entry.into_mut();
                 if thread.last_sample_timestamp.is_none() {
                     profile.set_thread_start_time(thread.profile_thread, start_time);
+                    thread.lifetime = Lifetime::new(start_time, false);
                 }
                 thread
             }
@@ -147,8 +161,10 @@ impl ProcessThreads {
     /// Called when a process has exited, before finish(). Not called if the process
     /// is still alive at the end of the profiling run.
     pub fn notify_process_dead(&mut self, end_time: Timestamp, profile: &mut Profile) {
-        for (_tid, mut thread) in self.threads_by_tid.drain() {
+        for (tid, mut thread) in self.threads_by_tid.drain() {
+            self.thread_counts.thread_ended(end_time);
             thread.notify_dead(end_time, profile);
+            thread.add_lifetime_marker(self.pid, tid, profile);
 
             let (name, thread_recycling_data) = thread.finish();
 
@@ -157,11 +173,21 @@ impl ProcessThreads {
             }
         }
 
+        self.thread_counts.thread_ended(end_time);
         self.main_thread.notify_dead(end_time, profile);
     }
 
     /// Called when the process has exited, or at the end of profiling. Called after notify_process_dead.
-    pub fn finish(self) -> (Option<ThreadRecycler>, (ThreadHandle, FrameInfo)) {
+    pub fn finish(
+        self,
+        profile: &mut Profile,
+    ) -> (Option<ThreadRecycler>, (ThreadHandle, FrameInfo)) {
+        // Threads which were still alive at the end of profiling.
+        for (tid, thread) in &self.threads_by_tid {
+            thread.add_lifetime_marker(self.pid, *tid, profile);
+        }
+        self.thread_counts
+            .add_counter(profile, self.profile_process);
         let (_main_thread_name, main_thread_recycling_data) = self.main_thread.finish();
         (self.thread_recycler, main_thread_recycling_data)
     }
@@ -171,12 +197,10 @@ impl ProcessThreads {
             return &mut self.main_thread;
         }
         self.threads_by_tid.entry(tid).or_insert_with(|| {
-            let profile_thread = profile.add_thread(
-                self.profile_process,
-                tid as u32,
-                Timestamp::from_millis_since_reference(0.0),
-                false,
-            );
+            let fake_start_time = Timestamp::from_millis_since_reference(0.0);
+            self.thread_counts.thread_started(fake_start_time);
+            let profile_thread =
+                profile.add_thread(self.profile_process, tid as u32, fake_start_time, false);
             let thread_label_frame = make_thread_label_frame(profile, None, self.pid, tid);
             Thread {
                 profile_thread,
@@ -185,6 +209,7 @@ impl ProcessThreads {
                 off_cpu_stack: None,
                 name: None,
                 thread_label_frame,
+                lifetime: Lifetime::new(fake_start_time, true),
             }
         })
     }
@@ -194,7 +219,9 @@ impl ProcessThreads {
             return;
         };
 
+        self.thread_counts.thread_ended(time);
         thread.notify_dead(time, profile);
+        thread.add_lifetime_marker(self.pid, tid, profile);
 
         let (name, thread_recylcing_data) = thread.finish();
 
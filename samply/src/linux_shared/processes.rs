@@ -8,8 +8,11 @@ use super::process::Process;
 use super::process_threads::make_thread_label_frame;
 use crate::shared::jit_category_manager::JitCategoryManager;
 use crate::shared::jit_function_recycler::JitFunctionRecycler;
+use crate::shared::lifetime_markers::Lifetime;
+use crate::shared::process_groups::ProcessTree;
 use crate::shared::process_sample_data::ProcessSampleData;
-use crate::shared::recycling::{ProcessRecycler, ProcessRecyclingData, ThreadRecycler};
+use crate::shared::ptr_auth::PtrAuthStripper;
+use crate::shared::recycling::{ProcessRecycler, ProcessRecyclingData};
 use crate::shared::timestamp_converter::TimestampConverter;
 use crate::shared::unresolved_samples::UnresolvedStacks;
 
@@ -26,6 +29,9 @@ where
     /// The sample data for all removed processes.
     process_sample_datas: Vec<ProcessSampleData>,
 
+    /// The parent of each process, for the process group CPU counters.
+    process_tree: ProcessTree,
+
     /// Whether aux files (like jitdump) should be unlinked on open
     unlink_aux_data: bool,
 }
@@ -34,16 +40,12 @@ impl<U> Processes<U>
 where
     U: Unwinder + Default,
 {
-    pub fn new(allow_reuse: bool, unlink_aux_data: bool) -> Self {
-        let process_recycler = if allow_reuse {
-            Some(ProcessRecycler::new())
-        } else {
-            None
-        };
+    pub fn new(process_recycler: Option<ProcessRecycler>, unlink_aux_data: bool) -> Self {
         Self {
             processes_by_pid: HashMap::new(),
             process_recycler,
             process_sample_datas: Vec::new(),
+            process_tree: ProcessTree::default(),
             unlink_aux_data,
         }
     }
@@ -65,7 +67,7 @@ where
                         main_thread_recycling_data,
                         thread_recycler,
                         jit_function_recycler,
-                    }) = process_recycler.recycle_by_name(name_ref)
+                    }) = process_recycler.recycle_by_name(name_ref, profile)
                     {
                         let (main_thread_handle, main_thread_label_frame) =
                             main_thread_recycling_data;
@@ -75,9 +77,10 @@ where
                             main_thread_handle,
                             main_thread_label_frame,
                             name,
-                            Some(thread_recycler),
+                            thread_recycler,
                             Some(jit_function_recycler),
                             self.unlink_aux_data,
+                            Lifetime::new(start_time, false),
                         );
                         return entry.insert(process);
                     }
@@ -96,14 +99,15 @@ where
                 }
                 let main_thread_label_frame =
                     make_thread_label_frame(profile, name.as_deref(), pid, pid);
-                let (thread_recycler, jit_function_recycler) = if self.process_recycler.is_some() {
-                    (
-                        Some(ThreadRecycler::new()),
-                        Some(JitFunctionRecycler::default()),
-                    )
-                } else {
-                    (None, None)
-                };
+                let (thread_recycler, jit_function_recycler) =
+                    if let Some(process_recycler) = &self.process_recycler {
+                        (
+                            process_recycler.new_thread_recycler(),
+                            Some(JitFunctionRecycler::default()),
+                        )
+                    } else {
+                        (None, None)
+                    };
                 let process = Process::new(
                     pid,
                     process_handle,
@@ -113,6 +117,7 @@ where
                     thread_recycler,
                     jit_function_recycler,
                     self.unlink_aux_data,
+                    Lifetime::new(start_time, false),
                 );
                 entry.insert(process)
             }
@@ -133,6 +138,7 @@ where
                         process.threads.main_thread.profile_thread,
                         start_time,
                     );
+                    process.lifetime = Lifetime::new(start_time, false);
                 }
                 process
             }
@@ -147,14 +153,15 @@ where
             let main_thread_handle =
                 profile.add_thread(process_handle, pid as u32, fake_start_time, true);
             let main_thread_label_frame = make_thread_label_frame(profile, None, pid, pid);
-            let (thread_recycler, jit_function_recycler) = if self.process_recycler.is_some() {
-                (
-                    Some(ThreadRecycler::new()),
-                    Some(JitFunctionRecycler::default()),
-                )
-            } else {
-                (None, None)
-            };
+            let (thread_recycler, jit_function_recycler) =
+                if let Some(process_recycler) = &self.process_recycler {
+                    (
+                        process_recycler.new_thread_recycler(),
+                        Some(JitFunctionRecycler::default()),
+                    )
+                } else {
+                    (None, None)
+                };
             Process::new(
                 pid,
                 process_handle,
@@ -164,10 +171,15 @@ where
                 thread_recycler,
                 jit_function_recycler,
                 self.unlink_aux_data,
+                Lifetime::new(fake_start_time, true),
             )
         })
     }
 
+    pub fn parent_pid(&self, pid: i32) -> Option<i32> {
+        self.processes_by_pid.get(&pid)?.parent_pid
+    }
+
     pub fn remove(
         &mut self,
         pid: i32,
@@ -181,6 +193,7 @@ where
         };
 
         process.notify_dead(time, profile);
+        add_to_process_tree(&mut self.process_tree, &process);
 
         let (process_sample_data, process_recycling_data) =
             process.finish(profile, jit_category_manager, timestamp_converter);
@@ -213,7 +226,8 @@ where
                 }
 
                 if let Some(process_recycler) = self.process_recycler.as_mut() {
-                    let Some(process_recycling_data) = process_recycler.recycle_by_name(&name)
+                    let Some(process_recycling_data) =
+                        process_recycler.recycle_by_name(&name, profile)
                     else {
                         return;
                     };
@@ -237,9 +251,12 @@ where
         unresolved_stacks: &UnresolvedStacks,
         jit_category_manager: &mut JitCategoryManager,
         timestamp_converter: &TimestampConverter,
+        process_group_cpu: bool,
+        ptr_auth_stripper: PtrAuthStripper,
     ) {
         // Gather the ProcessSampleData from any processes which are still alive at the end of profiling.
         for process in self.processes_by_pid.into_values() {
+            add_to_process_tree(&mut self.process_tree, &process);
             let (process_sample_data, _process_recycling_data) =
                 process.finish(profile, jit_category_manager, timestamp_converter);
             if !process_sample_data.is_empty() {
@@ -255,9 +272,23 @@ where
                 profile,
                 user_category,
                 kernel_category,
+                ptr_auth_stripper,
                 &mut stack_frame_scratch_buf,
                 unresolved_stacks,
             );
         }
+
+        if process_group_cpu {
+            self.process_tree.add_process_group_counters(profile);
+        }
     }
 }
+
+fn add_to_process_tree<U>(process_tree: &mut ProcessTree, process: &Process<U>) {
+    process_tree.add_process(
+        process.pid as u32,
+        process.parent_pid.map(|parent_pid| parent_pid as u32),
+        process.profile_process,
+        process.name.as_deref().unwrap_or("<unknown>"),
+    );
+}
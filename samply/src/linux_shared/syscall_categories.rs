@@ -0,0 +1,57 @@
+//! Attributes the kernel time under syscall entry points to the syscall, with a
+//! "Syscall" category which has a subcategory for each syscall, e.g. "read" or
+//! "futex". The category breakdown then shows the time in syscalls by type.
+//!
+//! The syscall is found from the name of the kernel's per-syscall entry
+//! function, e.g. `__x64_sys_read` or `__arm64_sys_futex`, so this needs kernel
+//! symbols, i.e. a readable /proc/kallsyms while recording, or symbols from
+//! simpleperf.
+
+use fxprof_processed_profile::{CategoryColor, Profile};
+use regex::Regex;
+
+const SYSCALL_ENTRY_REGEX: &str = concat!(
+    r"^(?:__(?:x64|ia32|x32|arm64|riscv|s390x|powerpc)_(?:compat_)?sys_",
+    r"|__se_(?:compat_)?sys_|__do_(?:compat_)?sys_|SyS_)(\w+)$",
+);
+
+/// Returns the name of the syscall whose entry function this is.
+fn syscall_for_function<'a>(entry_regex: &Regex, function_name: &'a str) -> Option<&'a str> {
+    Some(entry_regex.captures(function_name)?.get(1)?.as_str())
+}
+
+/// Gives the stacks under syscall entry points the "Syscall" category, with a
+/// subcategory for each syscall.
+pub fn categorize_profile_by_syscall(profile: &mut Profile) {
+    let entry_regex = Regex::new(SYSCALL_ENTRY_REGEX).unwrap();
+    profile.categorize_stacks_below_frames(|_lib_name, function_name| {
+        let syscall = syscall_for_function(&entry_regex, function_name?)?;
+        Some((
+            "Syscall".to_string(),
+            CategoryColor::Red,
+            Some(syscall.to_string()),
+        ))
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_syscall_for_function() {
+        let entry_regex = Regex::new(SYSCALL_ENTRY_REGEX).unwrap();
+        let syscall = |function_name| syscall_for_function(&entry_regex, function_name);
+        assert_eq!(syscall("__x64_sys_read"), Some("read"));
+        assert_eq!(syscall("__arm64_sys_futex"), Some("futex"));
+        assert_eq!(
+            syscall("__ia32_compat_sys_epoll_pwait"),
+            Some("epoll_pwait")
+        );
+        assert_eq!(syscall("__se_sys_write"), Some("write"));
+        assert_eq!(syscall("SyS_openat"), Some("openat"));
+        assert_eq!(syscall("do_syscall_64"), None);
+        assert_eq!(syscall("ksys_read"), None);
+        assert_eq!(syscall("sys_imageblit"), None);
+    }
+}
@@ -3,6 +3,7 @@ use std::fmt::Debug;
 use fxprof_processed_profile::{Frame, FrameInfo, Profile, StringHandle, ThreadHandle, Timestamp};
 
 use crate::shared::context_switch::ThreadContextSwitchData;
+use crate::shared::lifetime_markers::{Lifetime, ThreadLifetimeMarker};
 use crate::shared::unresolved_samples::UnresolvedStackHandle;
 
 #[derive(Debug)]
@@ -17,6 +18,7 @@ pub struct Thread {
     pub off_cpu_stack: Option<UnresolvedStackHandle>,
     pub name: Option<String>,
     pub thread_label_frame: FrameInfo,
+    pub lifetime: Lifetime,
 }
 
 impl Thread {
@@ -24,6 +26,7 @@ impl Thread {
         thread_handle: ThreadHandle,
         thread_label_frame: FrameInfo,
         name: Option<String>,
+        lifetime: Lifetime,
     ) -> Self {
         Self {
             profile_thread: thread_handle,
@@ -32,6 +35,7 @@ impl Thread {
             off_cpu_stack: None,
             name,
             thread_label_frame,
+            lifetime,
         }
     }
 
@@ -67,6 +71,20 @@ impl Thread {
 
     pub fn notify_dead(&mut self, end_time: Timestamp, profile: &mut Profile) {
         profile.set_thread_end_time(self.profile_thread, end_time);
+        self.lifetime.set_end(end_time);
+    }
+
+    pub fn add_lifetime_marker(&self, pid: i32, tid: i32, profile: &mut Profile) {
+        let name = match &self.name {
+            Some(name) => profile.intern_string(name),
+            None => profile.intern_string(&format!("Thread {tid}")),
+        };
+        let marker = ThreadLifetimeMarker {
+            name,
+            pid: pid as u32,
+            tid: tid as u32,
+        };
+        profile.add_marker(self.profile_thread, self.lifetime.marker_timing(), marker);
     }
 
     pub fn finish(self) -> (Option<String>, (ThreadHandle, FrameInfo)) {
@@ -0,0 +1,200 @@
+//! Thread affinity markers for `samply record --thread-affinity`: each
+//! successful `sched_setaffinity` call becomes a "Thread affinity" marker on
+//! the calling thread, with the stack of the call and the CPUs which the
+//! target thread may now run on.
+//!
+//! The syscall tracepoints only have a pointer to the new CPU mask, so the
+//! mask is read from `/proc/<tid>/status` once the call has returned. This
+//! works while recording, as long as the target thread still exists when the
+//! event is processed.
+
+use std::collections::HashMap;
+
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+
+use crate::shared::unresolved_samples::UnresolvedStackHandle;
+
+pub const SETAFFINITY_ENTER_TRACEPOINT: &str = "syscalls:sys_enter_sched_setaffinity";
+pub const SETAFFINITY_EXIT_TRACEPOINT: &str = "syscalls:sys_exit_sched_setaffinity";
+
+/// A `sched_setaffinity` call which hasn't returned yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AffinityChange {
+    /// The tid of the thread whose affinity is set.
+    pub target_tid: i32,
+    pub timestamp_mono: u64,
+    pub stack: UnresolvedStackHandle,
+}
+
+/// Pairs the `sched_setaffinity` syscall entries and exits of all threads.
+#[derive(Debug, Default)]
+pub struct AffinityTracker {
+    /// The call in progress on each thread, by tid.
+    calls: HashMap<i32, AffinityChange>,
+}
+
+impl AffinityTracker {
+    /// Handles a `sys_enter_sched_setaffinity` sample. `pid_arg` is the `pid`
+    /// argument of the syscall, which is a tid, or 0 for the calling thread.
+    pub fn handle_enter(
+        &mut self,
+        tid: i32,
+        pid_arg: i32,
+        timestamp_mono: u64,
+        stack: UnresolvedStackHandle,
+    ) {
+        let target_tid = if pid_arg == 0 { tid } else { pid_arg };
+        let change = AffinityChange {
+            target_tid,
+            timestamp_mono,
+            stack,
+        };
+        self.calls.insert(tid, change);
+    }
+
+    /// Handles a `sys_exit_sched_setaffinity` sample, and returns the change
+    /// if the call on this thread succeeded.
+    pub fn handle_exit(&mut self, tid: i32, ret: i64) -> Option<AffinityChange> {
+        let change = self.calls.remove(&tid)?;
+        (ret == 0).then_some(change)
+    }
+}
+
+/// Reads the CPUs which the thread may run on, e.g. "0-3,8", from procfs.
+pub fn read_cpus_allowed_list(tid: i32) -> Option<String> {
+    let status = std::fs::read_to_string(format!("/proc/{tid}/status")).ok()?;
+    parse_cpus_allowed_list(&status)
+}
+
+fn parse_cpus_allowed_list(status: &str) -> Option<String> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Cpus_allowed_list:"))
+        .map(|list| list.trim().to_string())
+}
+
+/// The number of CPUs in a CPU list like "0-3,8".
+pub fn cpu_count(cpu_list: &str) -> Option<u32> {
+    let mut count = 0;
+    for range in cpu_list.split(',').filter(|range| !range.is_empty()) {
+        count += match range.split_once('-') {
+            Some((first, last)) => {
+                let first = first.parse::<u32>().ok()?;
+                last.parse::<u32>().ok()?.checked_sub(first)? + 1
+            }
+            None => {
+                range.parse::<u32>().ok()?;
+                1
+            }
+        };
+    }
+    Some(count)
+}
+
+/// An instant marker for a change of a thread's CPU affinity.
+#[derive(Debug, Clone)]
+pub struct ThreadAffinityMarker {
+    /// "tid <tid>" of the thread whose affinity was set.
+    pub thread: StringHandle,
+    /// The CPUs which the thread may run on, e.g. "0-3,8", or "unknown".
+    pub cpus: StringHandle,
+    pub cpu_count: u32,
+}
+
+impl StaticSchemaMarker for ThreadAffinityMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "ThreadAffinity";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.cpus}".into()),
+            tooltip_label: Some(
+                "Set affinity of {marker.data.thread} to CPUs {marker.data.cpus} ({marker.data.cpuCount} CPUs)"
+                    .into(),
+            ),
+            table_label: Some(
+                "Set affinity of {marker.data.thread} to CPUs {marker.data.cpus} ({marker.data.cpuCount} CPUs)"
+                    .into(),
+            ),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "thread".into(),
+                    label: "Thread".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "cpus".into(),
+                    label: "CPUs".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "cpuCount".into(),
+                    label: "CPU count".into(),
+                    format: MarkerFieldFormat::Integer,
+                    searchable: false,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "The thread called sched_setaffinity, which restricts the CPUs a thread may run on. The stack is where it was called.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Thread affinity")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        CategoryHandle::OTHER
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.thread,
+            1 => self.cpus,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.cpu_count.into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pairs_calls_and_parses_cpu_lists() {
+        let stack = UnresolvedStackHandle::EMPTY;
+        let mut tracker = AffinityTracker::default();
+        tracker.handle_enter(10, 0, 100, stack);
+        tracker.handle_enter(11, 12, 100, stack);
+        assert_eq!(
+            tracker.handle_exit(10, 0),
+            Some(AffinityChange {
+                target_tid: 10,
+                timestamp_mono: 100,
+                stack,
+            })
+        );
+        // EINVAL
+        assert_eq!(tracker.handle_exit(11, -22), None);
+        assert_eq!(tracker.handle_exit(10, 0), None);
+
+        let status = "Name:\tworker\nCpus_allowed:\tff\nCpus_allowed_list:\t0-3,8\n";
+        assert_eq!(parse_cpus_allowed_list(status).as_deref(), Some("0-3,8"));
+        assert_eq!(cpu_count("0-3,8"), Some(5));
+        assert_eq!(cpu_count("2"), Some(1));
+        assert_eq!(cpu_count("x"), None);
+        assert_eq!(cpu_count("3-1"), None);
+    }
+}
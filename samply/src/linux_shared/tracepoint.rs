@@ -0,0 +1,357 @@
+//! Tracepoint markers for `samply record --tracepoint`: the raw data of each
+//! tracepoint sample is decoded with the field layout from the tracepoint's
+//! format file in tracefs, and shown as a marker with the decoded arguments.
+
+use std::fmt::Write;
+
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+use linux_perf_data::Endianness;
+
+/// The directories where tracefs is usually mounted.
+const TRACEFS_DIRS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// The layout of a tracepoint's raw data, from
+/// `/sys/kernel/tracing/events/<subsystem>/<event>/format`.
+///
+/// ```text
+/// name: sys_enter_openat
+/// ID: 614
+/// format:
+///         field:unsigned short common_type;       offset:0;       size:2; signed:0;
+///         ...
+///         field:int dfd;  offset:16;      size:8; signed:0;
+///         field:const char * filename;    offset:24;      size:8; signed:0;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracepointFormat {
+    /// The full name, e.g. `syscalls:sys_enter_openat`.
+    pub name: String,
+    /// The tracepoint id, which is the `config` of the perf event.
+    pub id: u64,
+    pub fields: Vec<TracepointField>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracepointField {
+    pub name: String,
+    /// The C type, e.g. `const char *` or `char[16]`.
+    pub type_name: String,
+    pub offset: usize,
+    pub size: usize,
+    pub signed: bool,
+    pub kind: TracepointFieldKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TracepointFieldKind {
+    Integer,
+    /// Shown in hex.
+    Pointer,
+    /// A fixed-size char array, e.g. `char comm[16]`.
+    CharArray,
+    /// A `__data_loc char[]`: a u32 with the offset of the string in the low
+    /// 16 bits and its length in the high 16 bits.
+    DynamicString,
+    /// Anything else is shown as its size.
+    Other,
+}
+
+impl TracepointFormat {
+    /// Reads the format of the tracepoint `subsystem:event` from tracefs.
+    #[allow(unused)]
+    pub fn load(name: &str) -> Result<Self, String> {
+        let Some((subsystem, event)) = name.split_once(':') else {
+            return Err(format!(
+                "Invalid tracepoint {name:?}, expected SUBSYSTEM:EVENT, e.g. syscalls:sys_enter_openat"
+            ));
+        };
+        let mut last_error = None;
+        for tracefs_dir in TRACEFS_DIRS {
+            let path = format!("{tracefs_dir}/events/{subsystem}/{event}/format");
+            match std::fs::read_to_string(&path) {
+                Ok(text) => return Self::parse(name, &text),
+                Err(err) => last_error = Some(format!("Could not read {path}: {err}")),
+            }
+        }
+        Err(format!(
+            "Unknown tracepoint {name:?}. {}. Reading tracefs usually requires root.",
+            last_error.unwrap_or_default()
+        ))
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let mut id = None;
+        let mut fields = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(id_str) = line.strip_prefix("ID:") {
+                id = id_str.trim().parse().ok();
+            } else if let Some(field) = line.strip_prefix("field:") {
+                fields.push(TracepointField::parse(field).ok_or_else(|| {
+                    format!("Could not parse field {line:?} of tracepoint {name}")
+                })?);
+            }
+        }
+        let id = id.ok_or_else(|| format!("The format of tracepoint {name} has no ID"))?;
+        Ok(Self {
+            name: name.to_owned(),
+            id,
+            fields,
+        })
+    }
+
+    /// Formats the fields of the raw sample data as `name=value` pairs. The
+    /// `common_*` fields, which every tracepoint has, are left out.
+    pub fn decode(&self, raw: &[u8], endian: Endianness) -> String {
+        let mut text = String::new();
+        for field in &self.fields {
+            if field.name.starts_with("common_") {
+                continue;
+            }
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            write!(text, "{}=", field.name).unwrap();
+            field.write_value(&mut text, raw, endian);
+        }
+        text
+    }
+
+    /// Reads the integer or pointer field `name` from the raw sample data.
+    pub fn field_value(&self, name: &str, raw: &[u8], endian: Endianness) -> Option<u64> {
+        let field = self.fields.iter().find(|field| field.name == name)?;
+        if !matches!(
+            field.kind,
+            TracepointFieldKind::Integer | TracepointFieldKind::Pointer
+        ) {
+            return None;
+        }
+        let bytes = raw.get(field.offset..field.offset + field.size)?;
+        Some(read_int(bytes, endian, field.signed))
+    }
+}
+
+impl TracepointField {
+    /// Parses e.g. `const char * filename;\toffset:24;\tsize:8;\tsigned:0;`.
+    fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.split(';').map(str::trim);
+        let declaration = parts.next()?;
+        let mut offset = None;
+        let mut size = None;
+        let mut signed = false;
+        for part in parts {
+            match part.split_once(':') {
+                Some(("offset", value)) => offset = value.parse().ok(),
+                Some(("size", value)) => size = value.parse().ok(),
+                Some(("signed", value)) => signed = value == "1",
+                _ => {}
+            }
+        }
+
+        let (declaration, is_dynamic) = match declaration.strip_prefix("__data_loc ") {
+            Some(declaration) => (declaration, true),
+            None => (declaration, false),
+        };
+        // The name is the last identifier, with an array suffix in either the
+        // name (`char comm[16]`) or the type (`__data_loc char[] name`).
+        let name_start = declaration.rfind([' ', '*']).map_or(0, |pos| pos + 1);
+        let (type_name, name) = declaration.split_at(name_start);
+        let (name, array_suffix) = match name.find('[') {
+            Some(pos) => name.split_at(pos),
+            None => (name, ""),
+        };
+        let type_name = format!("{}{array_suffix}", type_name.trim_end());
+        let is_char = type_name.starts_with("char") || type_name.starts_with("const char");
+        let kind = if is_dynamic && is_char {
+            TracepointFieldKind::DynamicString
+        } else if is_char && type_name.ends_with(']') {
+            TracepointFieldKind::CharArray
+        } else if type_name.contains('*') {
+            TracepointFieldKind::Pointer
+        } else if !type_name.ends_with(']') && matches!(size, Some(1 | 2 | 4 | 8)) {
+            TracepointFieldKind::Integer
+        } else {
+            TracepointFieldKind::Other
+        };
+
+        Some(Self {
+            name: name.to_owned(),
+            type_name,
+            offset: offset?,
+            size: size?,
+            signed,
+            kind,
+        })
+    }
+
+    fn write_value(&self, text: &mut String, raw: &[u8], endian: Endianness) {
+        let Some(bytes) = raw.get(self.offset..self.offset + self.size) else {
+            text.push('?');
+            return;
+        };
+        match self.kind {
+            TracepointFieldKind::Integer if self.signed => {
+                write!(text, "{}", read_int(bytes, endian, true) as i64).unwrap()
+            }
+            TracepointFieldKind::Integer => {
+                write!(text, "{}", read_int(bytes, endian, false)).unwrap()
+            }
+            TracepointFieldKind::Pointer => {
+                write!(text, "{:#x}", read_int(bytes, endian, false)).unwrap()
+            }
+            TracepointFieldKind::CharArray => write_c_string(text, bytes),
+            TracepointFieldKind::DynamicString => {
+                let data_loc = read_int(bytes, endian, false);
+                let start = (data_loc & 0xffff) as usize;
+                let len = (data_loc >> 16 & 0xffff) as usize;
+                match raw.get(start..start + len) {
+                    Some(bytes) => write_c_string(text, bytes),
+                    None => text.push('?'),
+                }
+            }
+            TracepointFieldKind::Other => write!(text, "<{} bytes>", self.size).unwrap(),
+        }
+    }
+}
+
+/// Reads an integer of 1, 2, 4 or 8 bytes, sign-extended to 64 bits if `signed`.
+fn read_int(bytes: &[u8], endian: Endianness, signed: bool) -> u64 {
+    let mut buf = [0; 8];
+    let len = bytes.len().min(8);
+    let value = match endian {
+        Endianness::LittleEndian => {
+            buf[..len].copy_from_slice(&bytes[..len]);
+            u64::from_le_bytes(buf)
+        }
+        Endianness::BigEndian => {
+            buf[8 - len..].copy_from_slice(&bytes[..len]);
+            u64::from_be_bytes(buf)
+        }
+    };
+    let unused_bits = 64 - 8 * len as u32;
+    if signed && unused_bits != 0 && unused_bits != 64 {
+        ((value << unused_bits) as i64 >> unused_bits) as u64
+    } else {
+        value
+    }
+}
+
+fn write_c_string(text: &mut String, bytes: &[u8]) {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    write!(text, "{:?}", String::from_utf8_lossy(&bytes[..len])).unwrap();
+}
+
+/// A marker for a tracepoint sample, with the decoded arguments.
+#[derive(Debug, Clone)]
+pub struct TracepointMarker {
+    pub name: StringHandle,
+    pub args: StringHandle,
+}
+
+impl StaticSchemaMarker for TracepointMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Tracepoint";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.args}".into()),
+            tooltip_label: Some("{marker.name} {marker.data.args}".into()),
+            table_label: Some("{marker.name} {marker.data.args}".into()),
+            fields: vec![MarkerFieldSchema {
+                key: "args".into(),
+                label: "Arguments".into(),
+                format: MarkerFieldFormat::String,
+                searchable: true,
+            }],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "Emitted for each hit of a tracepoint which was enabled with --tracepoint."
+                    .into(),
+            }],
+        }
+    }
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        CategoryHandle::OTHER
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.args
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SYS_ENTER_OPENAT: &str = "name: sys_enter_openat
+ID: 614
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:int __syscall_nr;\toffset:8;\tsize:4;\tsigned:1;
+\tfield:int dfd;\toffset:16;\tsize:8;\tsigned:0;
+\tfield:const char * filename;\toffset:24;\tsize:8;\tsigned:0;
+\tfield:char comm[8];\toffset:32;\tsize:8;\tsigned:0;
+\tfield:__data_loc char[] path;\toffset:40;\tsize:4;\tsigned:0;
+\tfield:s16 delta;\toffset:44;\tsize:2;\tsigned:1;
+
+print fmt: \"dfd: 0x%08lx\", ((unsigned long)(REC->dfd))
+";
+
+    #[test]
+    fn parse_and_decode() {
+        let format =
+            TracepointFormat::parse("syscalls:sys_enter_openat", SYS_ENTER_OPENAT).unwrap();
+        assert_eq!(format.id, 614);
+        assert_eq!(format.fields.len(), 10);
+        let filename = &format.fields[6];
+        assert_eq!(filename.name, "filename");
+        assert_eq!(filename.type_name, "const char *");
+        assert_eq!(filename.kind, TracepointFieldKind::Pointer);
+        let comm = &format.fields[7];
+        assert_eq!(
+            (comm.name.as_str(), comm.type_name.as_str()),
+            ("comm", "char[8]")
+        );
+        assert_eq!(comm.kind, TracepointFieldKind::CharArray);
+        assert_eq!(format.fields[8].name, "path");
+        assert_eq!(format.fields[8].kind, TracepointFieldKind::DynamicString);
+
+        let mut raw = vec![0u8; 52];
+        raw[8..12].copy_from_slice(&257i32.to_le_bytes());
+        raw[16..24].copy_from_slice(&0xffff_ff9cu64.to_le_bytes());
+        raw[24..32].copy_from_slice(&0x7ffd_1234u64.to_le_bytes());
+        raw[32..36].copy_from_slice(b"bash");
+        let data_loc = 48u32 | (4 << 16);
+        raw[40..44].copy_from_slice(&data_loc.to_le_bytes());
+        raw[44..46].copy_from_slice(&(-3i16).to_le_bytes());
+        raw[48..52].copy_from_slice(b"/tmp");
+        assert_eq!(
+            format.decode(&raw, Endianness::LittleEndian),
+            "__syscall_nr=257 dfd=4294967196 filename=0x7ffd1234 comm=\"bash\" path=\"/tmp\" delta=-3"
+        );
+        let field = |name| format.field_value(name, &raw, Endianness::LittleEndian);
+        assert_eq!(field("filename"), Some(0x7ffd_1234));
+        assert_eq!(field("delta"), Some(-3i64 as u64));
+        assert_eq!(field("comm"), None);
+        assert_eq!(field("nonexistent"), None);
+
+        assert!(TracepointFormat::parse("a:b", "format:\n").is_err());
+    }
+}
@@ -1,6 +1,9 @@
-use object::Object;
+use std::sync::Arc;
+
+use fxprof_processed_profile::{Symbol, SymbolTable};
+use object::{Object, ObjectSymbol, SymbolKind};
 use once_cell::sync::OnceCell;
-use wholesym::{CodeId, ElfBuildId};
+use wholesym::{samply_symbols, CodeId, ElfBuildId};
 
 /// Returns the memory address range in this process where the VDSO is mapped.
 pub fn get_vdso_range() -> Option<(usize, usize)> {
@@ -32,6 +35,7 @@ pub struct VdsoObject {
     object: object::File<'static, &'static [u8]>,
     build_id: &'static [u8],
     code_id: CodeId,
+    symbol_table: Arc<SymbolTable>,
 }
 
 impl VdsoObject {
@@ -49,10 +53,12 @@ impl VdsoObject {
                 let object = object::File::parse(data).ok()?;
                 let build_id = object.build_id().ok()??;
                 let code_id = CodeId::ElfBuildId(ElfBuildId::from_bytes(build_id));
+                let symbol_table = Arc::new(symbol_table_for_object(&object));
                 Some(VdsoObject {
                     object,
                     build_id,
                     code_id,
+                    symbol_table,
                 })
             })
             .as_ref()
@@ -69,4 +75,54 @@ impl VdsoObject {
     pub fn build_id(&self) -> &[u8] {
         self.build_id
     }
+
+    /// The VDSO's exported functions. The VDSO isn't a file on disk, so the
+    /// symbol server can't find it; these symbols need to go into the profile.
+    pub fn symbol_table(&self) -> Arc<SymbolTable> {
+        self.symbol_table.clone()
+    }
+}
+
+/// Builds a symbol table from the dynamic symbols of the VDSO, which is all
+/// the VDSO has. The addresses are relative to the image base.
+fn symbol_table_for_object<'data>(object: &impl Object<'data>) -> SymbolTable {
+    let base_svma = samply_symbols::relative_address_base(object);
+    let symbols = object
+        .dynamic_symbols()
+        .chain(object.symbols())
+        .filter(|sym| sym.kind() == SymbolKind::Text && sym.is_definition())
+        .filter_map(|sym| {
+            let name = sym.name().ok().filter(|name| !name.is_empty())?;
+            let address = u32::try_from(sym.address().checked_sub(base_svma)?).ok()?;
+            let size = u32::try_from(sym.size()).ok().filter(|size| *size != 0);
+            Some(Symbol {
+                address,
+                size,
+                name: name.to_owned(),
+            })
+        })
+        .collect();
+    // If a function has several names, e.g. "clock_gettime" and
+    // "__vdso_clock_gettime", SymbolTable keeps the one which sorts first,
+    // which is the "__vdso_" one.
+    SymbolTable::new(symbols)
+}
+
+/// The address of the legacy vsyscall page on x86_64. Unlike the VDSO, it's at
+/// the same address in every process.
+pub const VSYSCALL_PAGE_START: u64 = 0xffff_ffff_ff60_0000;
+
+/// The vsyscall page has a fixed layout: one entry point every 0x400 bytes.
+/// It isn't an ELF image, so there's nothing to read symbols from.
+pub fn vsyscall_symbol_table() -> SymbolTable {
+    let entry = |address: u32, name: &str| Symbol {
+        address,
+        size: Some(0x400),
+        name: name.to_owned(),
+    };
+    SymbolTable::new(vec![
+        entry(0x0, "vsyscall_gettimeofday"),
+        entry(0x400, "vsyscall_time"),
+        entry(0x800, "vsyscall_getcpu"),
+    ])
 }
@@ -0,0 +1,211 @@
+//! The "Hypervisor" track, which shows VM steal time. Only the Linux recorder
+//! fills it: the guest kernel gets steal time from the hypervisor and reports
+//! it in /proc/stat. Windows guests have no equivalent that user mode can
+//! read, and the Hyper-V ETW providers only log on the host, so Windows
+//! recordings don't get this track.
+
+use fxprof_processed_profile::{
+    CategoryHandle, CounterHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation,
+    MarkerSchema, MarkerStaticField, MarkerTiming, Profile, StaticSchemaMarker, StringHandle,
+    ThreadHandle, Timestamp,
+};
+
+/// The pid and tid of the "Hypervisor" track. Real pids on Linux are at most
+/// 2^22, so this can't collide with a profiled process or with the idle task
+/// (pid 0). `u32::MAX` is left alone because perf uses -1 for unknown pids.
+const HYPERVISOR_PSEUDO_PID: u32 = u32::MAX - 1;
+
+/// A synthetic "Hypervisor" track which shows how much CPU time the hypervisor
+/// took away from this VM ("steal time"), both as markers for each polling
+/// interval and as a "Steal time" counter.
+pub struct VmStealTrack {
+    thread_handle: ThreadHandle,
+    counter: CounterHandle,
+    last_counter_sample_time: Option<Timestamp>,
+}
+
+impl VmStealTrack {
+    pub fn new(start_time: Timestamp, profile: &mut Profile) -> Self {
+        let process_handle = profile.add_process("Hypervisor", HYPERVISOR_PSEUDO_PID, start_time);
+        let thread_handle =
+            profile.add_thread(process_handle, HYPERVISOR_PSEUDO_PID, start_time, true);
+        profile.set_thread_name(thread_handle, "Steal time");
+        let counter = profile.add_counter(
+            process_handle,
+            "Steal time",
+            "CPU",
+            "CPU time that the hypervisor took away from this VM, summed up across all CPUs",
+        );
+        Self {
+            thread_handle,
+            counter,
+            last_counter_sample_time: None,
+        }
+    }
+
+    pub fn add_steal_time(
+        &mut self,
+        start_time: Timestamp,
+        end_time: Timestamp,
+        span_nanos: u64,
+        steal_nanos: u64,
+        cpu_count: usize,
+        profile: &mut Profile,
+    ) {
+        if span_nanos == 0 {
+            return;
+        }
+        // Steal time is summed up across all CPUs.
+        let total_cpu_nanos = span_nanos * cpu_count.max(1) as u64;
+        let fraction = steal_nanos as f64 / total_cpu_nanos as f64;
+        let steal_millis = steal_nanos as f64 / 1_000_000.0;
+        profile.add_marker(
+            self.thread_handle,
+            MarkerTiming::Interval(start_time, end_time),
+            VmStealTimeMarker {
+                steal_millis,
+                fraction: fraction.min(1.0),
+            },
+        );
+
+        // Polls without any steal time aren't reported, so the counter needs an
+        // empty sample at the start of this span. Otherwise the steal time would
+        // be spread out over the whole time since the previous span.
+        if self.last_counter_sample_time != Some(start_time) {
+            profile.add_counter_sample(self.counter, start_time, 0.0, 0);
+        }
+        profile.add_counter_sample(self.counter, end_time, steal_millis, 1);
+        self.last_counter_sample_time = Some(end_time);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VmStealTimeMarker {
+    steal_millis: f64,
+    fraction: f64,
+}
+
+impl StaticSchemaMarker for VmStealTimeMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "VmStealTime";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![
+                MarkerLocation::MarkerChart,
+                MarkerLocation::MarkerTable,
+                MarkerLocation::TimelineOverview,
+            ],
+            chart_label: Some("Steal time: {marker.data.fraction}".into()),
+            tooltip_label: Some(
+                "Steal time: {marker.data.steal} ({marker.data.fraction} of CPU time)".into(),
+            ),
+            table_label: Some("Steal time: {marker.data.steal}".into()),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "steal".into(),
+                    label: "Steal time".into(),
+                    format: MarkerFieldFormat::Milliseconds,
+                    searchable: false,
+                },
+                MarkerFieldSchema {
+                    key: "fraction".into(),
+                    label: "Fraction of CPU time".into(),
+                    format: MarkerFieldFormat::Percentage,
+                    searchable: false,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "Time during which the hypervisor ran something else while this VM's CPUs wanted to run. High steal time means the host is oversubscribed.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Steal time")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        CategoryHandle::OTHER
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        unreachable!()
+    }
+
+    fn number_field_value(&self, field_index: u32) -> f64 {
+        match field_index {
+            0 => self.steal_millis,
+            1 => self.fraction,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{ReferenceTimestamp, SamplingInterval};
+
+    use super::*;
+
+    #[test]
+    fn track_does_not_collide_with_idle_task() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let at = Timestamp::from_millis_since_reference;
+        let idle = profile.add_process("swapper", 0, at(0.0));
+        profile.add_thread(idle, 0, at(0.0), true);
+        let mut track = VmStealTrack::new(at(0.0), &mut profile);
+        track.add_steal_time(at(0.0), at(100.0), 100_000_000, 50_000_000, 2, &mut profile);
+
+        let json = serde_json::to_value(&profile).unwrap();
+        let threads = json["threads"].as_array().unwrap();
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[1]["name"], "Hypervisor");
+        assert_ne!(threads[1]["pid"], threads[0]["pid"]);
+        assert_ne!(threads[1]["tid"], threads[0]["tid"]);
+        assert_eq!(threads[1]["markers"]["length"], 1);
+        assert_eq!(threads[1]["markers"]["data"][0]["fraction"], 0.25);
+    }
+
+    #[test]
+    fn counter_has_no_steal_time_between_spans() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let at = Timestamp::from_millis_since_reference;
+        let mut track = VmStealTrack::new(at(0.0), &mut profile);
+        track.add_steal_time(at(0.0), at(100.0), 100_000_000, 50_000_000, 2, &mut profile);
+        track.add_steal_time(
+            at(100.0),
+            at(200.0),
+            100_000_000,
+            20_000_000,
+            2,
+            &mut profile,
+        );
+        // Nothing was stolen between 200ms and 500ms.
+        track.add_steal_time(
+            at(500.0),
+            at(600.0),
+            100_000_000,
+            10_000_000,
+            2,
+            &mut profile,
+        );
+
+        let json = serde_json::to_value(&profile).unwrap();
+        let counter = &json["counters"][0];
+        assert_eq!(counter["name"], "Steal time");
+        assert_eq!(
+            counter["samples"]["count"],
+            serde_json::json!([0.0, 50.0, 20.0, 0.0, 10.0])
+        );
+    }
+}
@@ -44,6 +44,7 @@ use super::dyld_bindings::{self};
 use super::error::SamplingError;
 use super::kernel_error::{self, IntoResult, KernelError};
 use super::task_profiler::UnwindSectionBytes;
+use crate::shared::ptr_auth::PtrAuthStripper;
 
 pub const TASK_DYLD_INFO_COUNT: mach_msg_type_number_t = 5;
 
@@ -459,11 +460,20 @@ fn get_virtual_address_size() -> Option<u32> {
 }
 
 #[cfg(target_arch = "aarch64")]
-static PTR_AUTH_MASK: Lazy<PtrAuthMask> = Lazy::new(|| {
-    let addr_bits = get_virtual_address_size().unwrap_or(47);
-    let mask_bits = 64 - addr_bits;
-    PtrAuthMask(u64::MAX >> mask_bits)
-});
+static PTR_AUTH_STRIPPER: Lazy<PtrAuthStripper> =
+    Lazy::new(|| PtrAuthStripper::arm64(get_virtual_address_size().unwrap_or(47)));
+
+/// Removes pointer authentication codes from the addresses in this machine's stacks.
+#[cfg(target_arch = "aarch64")]
+pub fn ptr_auth_stripper() -> PtrAuthStripper {
+    *PTR_AUTH_STRIPPER
+}
+
+/// Removes pointer authentication codes from the addresses in this machine's stacks.
+#[cfg(not(target_arch = "aarch64"))]
+pub fn ptr_auth_stripper() -> PtrAuthStripper {
+    PtrAuthStripper::NONE
+}
 
 #[cfg(target_arch = "aarch64")]
 fn get_unwinding_registers(
@@ -480,7 +490,7 @@ fn get_unwinding_registers(
         )
     }
     .into_result()?;
-    let mask = *PTR_AUTH_MASK;
+    let mask = PtrAuthMask(PTR_AUTH_STRIPPER.address_mask());
     Ok((
         mask.strip_ptr_auth(state.__pc),
         UnwindRegsAarch64::new_with_ptr_auth_mask(mask, state.__lr, state.__sp, state.__fp),
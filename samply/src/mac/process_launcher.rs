@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::Write;
@@ -9,17 +9,19 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use flate2::write::GzDecoder;
+use mach::kern_return::{kern_return_t, KERN_SUCCESS};
 use mach::task::{task_resume, task_suspend};
 use mach::traps::task_for_pid;
 use tempfile::tempdir;
 
 use crate::shared::ctrl_c::CtrlC;
+use crate::shared::record_error::RecordError;
 
 pub use super::mach_ipc::{mach_port_t, MachError, OsIpcSender};
 use super::mach_ipc::{mach_task_self, BlockingMode, OsIpcMultiShotServer, MACH_PORT_NULL};
 
 pub trait RootTaskRunner {
-    fn run_root_task(&self) -> Result<ExitStatus, MachError>;
+    fn run_root_task(&self) -> Result<ExitStatus, RecordError>;
 }
 
 pub struct TaskLauncher {
@@ -30,13 +32,13 @@ pub struct TaskLauncher {
 }
 
 impl RootTaskRunner for TaskLauncher {
-    fn run_root_task(&self) -> Result<ExitStatus, MachError> {
+    fn run_root_task(&self) -> Result<ExitStatus, RecordError> {
         // Ignore Ctrl+C while the subcommand is running. The signal still reaches the process
         // under observation while we continue to record it. (ctrl+c will send the SIGINT signal
         // to all processes in the foreground process group).
         let mut ctrl_c_receiver = CtrlC::observe_oneshot();
 
-        let mut root_child = self.launch_child();
+        let mut root_child = self.launch_child()?;
         let mut exit_status = root_child.wait().expect("couldn't wait for child");
 
         for i in 2..=self.iteration_count {
@@ -48,7 +50,7 @@ impl RootTaskRunner for TaskLauncher {
                 break;
             }
             eprintln!("Running iteration {i} of {}...", self.iteration_count);
-            let mut root_child = self.launch_child();
+            let mut root_child = self.launch_child()?;
             exit_status = root_child.wait().expect("couldn't wait for child");
         }
 
@@ -93,27 +95,12 @@ impl TaskLauncher {
         })
     }
 
-    pub fn launch_child(&self) -> Child {
-        match Command::new(&self.program)
+    pub fn launch_child(&self) -> Result<Child, RecordError> {
+        Command::new(&self.program)
             .args(&self.args)
             .envs(self.child_env.clone())
             .spawn()
-        {
-            Ok(child) => child,
-            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-                let command_name = self.program.to_string_lossy();
-                if command_name.starts_with('-') {
-                    eprintln!("error: unexpected argument '{command_name}' found");
-                } else {
-                    eprintln!("Error: Could not find an executable with the name {command_name}.");
-                }
-                std::process::exit(1)
-            }
-            Err(err) => {
-                eprintln!("Error: Could not launch child process: {err}");
-                std::process::exit(1)
-            }
-        }
+            .map_err(|err| RecordError::launch(&self.program, err))
     }
 }
 
@@ -260,7 +247,7 @@ pub struct ExistingProcessRunner {
 }
 
 impl RootTaskRunner for ExistingProcessRunner {
-    fn run_root_task(&self) -> Result<ExitStatus, MachError> {
+    fn run_root_task(&self) -> Result<ExitStatus, RecordError> {
         let ctrl_c_receiver = CtrlC::observe_oneshot();
 
         eprintln!("Profiling {}, press Ctrl-C to stop...", self.pid);
@@ -276,39 +263,159 @@ impl RootTaskRunner for ExistingProcessRunner {
 }
 
 impl ExistingProcessRunner {
-    pub fn new(pid: u32, task_accepter: &mut TaskAccepter) -> ExistingProcessRunner {
+    pub fn new(pid: u32, task_accepter: &mut TaskAccepter) -> Result<Self, RecordError> {
         let mut queue_pid = |pid, failure_is_ok| {
-            let task = unsafe {
-                let mut task = MACH_PORT_NULL;
-                let kr = task_for_pid(mach_task_self(), pid as i32, &mut task);
-                if kr != 0 {
-                    if failure_is_ok {
-                        eprintln!("Warning: task_for_pid for child task failed with error code {kr}. Ignoring child, it may have already exited.");
-                        return;
-                    }
-
-                    eprintln!("Error: task_for_pid for target task failed with error code {kr}.");
+            let task = match suspended_task_for_pid(pid) {
+                Ok(task) => task,
+                Err(kr) if failure_is_ok => {
+                    eprintln!("Warning: task_for_pid for child task failed with error code {kr}. Ignoring child, it may have already exited.");
+                    return Ok(());
+                }
+                Err(kr) => {
                     eprintln!(
                         "Please run 'samply setup' in order to grant appropriate entitlements"
                     );
                     eprintln!("to the binary.");
-                    std::process::exit(1);
+                    return Err(RecordError::StartProfiling(format!(
+                        "task_for_pid for target task failed with error code {kr}."
+                    )));
                 }
-                task_suspend(task);
-                task
             };
             task_accepter.queue_received_stuff(ReceivedStuff::AcceptedTask(AcceptedTask {
                 task,
                 pid,
                 sender_channel: None,
             }));
+            Ok(())
         };
 
         // always root pid first
-        queue_pid(pid, false);
+        queue_pid(pid, false)?;
 
         // TODO: find all its children
 
-        ExistingProcessRunner { pid }
+        Ok(ExistingProcessRunner { pid })
+    }
+}
+
+/// Gets the task port for the process and suspends the task. The task is
+/// resumed by [`AcceptedTask::start_execution`].
+fn suspended_task_for_pid(pid: u32) -> Result<mach_port_t, kern_return_t> {
+    unsafe {
+        let mut task = MACH_PORT_NULL;
+        let kr = task_for_pid(mach_task_self(), pid as i32, &mut task);
+        if kr != KERN_SUCCESS {
+            return Err(kr);
+        }
+        task_suspend(task);
+        Ok(task)
+    }
+}
+
+/// The root task runner for `samply record --all`. The processes are found
+/// by a [`ProcessScanner`]; this just waits for Ctrl+C.
+pub struct AllProcessesRunner;
+
+impl RootTaskRunner for AllProcessesRunner {
+    fn run_root_task(&self) -> Result<ExitStatus, RecordError> {
+        let ctrl_c_receiver = CtrlC::observe_oneshot();
+
+        eprintln!("Profiling all processes, press Ctrl-C to stop...");
+
+        ctrl_c_receiver
+            .blocking_recv()
+            .expect("Ctrl+C receiver failed");
+
+        eprintln!("Done.");
+
+        Ok(ExitStatus::default())
+    }
+}
+
+/// Finds the processes on the system, for profiling all processes. There's
+/// no notification for new processes which we could use, so the process list
+/// needs to be polled.
+///
+/// Getting the task port of a process only works for processes which samply
+/// is allowed to debug. With System Integrity Protection enabled, this
+/// excludes Apple's own processes and any process with the hardened runtime,
+/// so those are skipped.
+pub struct ProcessScanner {
+    /// The pids from the previous scan, including the ones we couldn't attach
+    /// to, so that we don't retry them on every scan.
+    known_pids: HashSet<u32>,
+    is_first_scan: bool,
+}
+
+impl ProcessScanner {
+    pub fn new() -> Self {
+        let mut known_pids = HashSet::new();
+        known_pids.insert(std::process::id());
+        ProcessScanner {
+            known_pids,
+            is_first_scan: true,
+        }
+    }
+
+    /// Returns the suspended tasks of the processes which have appeared since
+    /// the last call.
+    pub fn scan_for_new_tasks(&mut self) -> Vec<AcceptedTask> {
+        let mut pids: HashSet<u32> = list_all_pids().into_iter().collect();
+        pids.insert(std::process::id());
+
+        let mut tasks = Vec::new();
+        let mut failed_count = 0;
+        for &pid in pids.difference(&self.known_pids) {
+            match suspended_task_for_pid(pid) {
+                Ok(task) => tasks.push(AcceptedTask {
+                    task,
+                    pid,
+                    sender_channel: None,
+                }),
+                Err(_) => failed_count += 1,
+            }
+        }
+
+        if self.is_first_scan {
+            self.is_first_scan = false;
+            if tasks.is_empty() {
+                eprintln!("Error: Could not attach to any process.");
+                eprintln!("Please run 'samply setup' in order to grant appropriate entitlements");
+                eprintln!("to the binary.");
+                std::process::exit(1);
+            }
+            if failed_count != 0 {
+                eprintln!(
+                    "Attached to {} processes. Skipping {failed_count} processes which samply isn't allowed to profile, such as system processes.",
+                    tasks.len()
+                );
+            }
+        }
+
+        // Forget the pids of processes which have exited, in case the pid
+        // gets reused.
+        self.known_pids = pids;
+        tasks
+    }
+}
+
+fn list_all_pids() -> Vec<u32> {
+    // With a null buffer, proc_listallpids returns the number of processes.
+    let count = unsafe { libc::proc_listallpids(std::ptr::null_mut(), 0) };
+    if count <= 0 {
+        return Vec::new();
     }
+    // Leave some room for processes which are launched in the meantime.
+    let mut pids: Vec<libc::pid_t> = vec![0; count as usize + 64];
+    let count = unsafe {
+        libc::proc_listallpids(
+            pids.as_mut_ptr() as *mut libc::c_void,
+            (pids.len() * std::mem::size_of::<libc::pid_t>()) as libc::c_int,
+        )
+    };
+    pids.truncate(count.max(0) as usize);
+    pids.into_iter()
+        .filter(|&pid| pid > 0)
+        .map(|pid| pid as u32)
+        .collect()
 }
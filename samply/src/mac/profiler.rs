@@ -6,17 +6,21 @@ use std::thread;
 use std::time::Duration;
 
 use crossbeam_channel::unbounded;
+use fxprof_processed_profile::Profile;
 
 use super::error::SamplingError;
 use super::process_launcher::{
-    ExistingProcessRunner, MachError, ReceivedStuff, RootTaskRunner, TaskAccepter, TaskLauncher,
+    AllProcessesRunner, ExistingProcessRunner, MachError, ProcessScanner, ReceivedStuff,
+    RootTaskRunner, TaskAccepter, TaskLauncher,
 };
 use super::sampler::{JitdumpOrMarkerPath, Sampler, TaskInit, TaskInitOrShutdown};
 use super::time::get_monotonic_timestamp;
 use crate::server::{start_server_main, ServerProps};
+use crate::shared::record_error::RecordError;
 use crate::shared::recording_props::{
     ProcessLaunchProps, ProfileCreationProps, RecordingMode, RecordingProps,
 };
+use crate::shared::recording_summary::print_recording_summary;
 use crate::shared::save_profile::save_profile_to_file;
 use crate::shared::symbol_props::SymbolProps;
 
@@ -26,18 +30,18 @@ pub fn start_recording(
     mut profile_creation_props: ProfileCreationProps,
     symbol_props: SymbolProps,
     server_props: Option<ServerProps>,
-) -> Result<ExitStatus, MachError> {
+) -> Result<(ExitStatus, Profile), RecordError> {
     let output_file = recording_props.output_file.clone();
 
     let mut task_accepter = TaskAccepter::new()?;
+    let mut process_scanner = None;
 
     let root_task_runner: Box<dyn RootTaskRunner> = match recording_mode {
         RecordingMode::All => {
-            eprintln!("Error: Profiling all processes is not supported on macOS.");
-            eprintln!("You can only profile processes which you launch via samply, or attach to via --pid.");
-            std::process::exit(1)
+            process_scanner = Some(ProcessScanner::new());
+            Box::new(AllProcessesRunner)
         }
-        RecordingMode::Pid(pid) => Box::new(ExistingProcessRunner::new(pid, &mut task_accepter)),
+        RecordingMode::Pid(pid) => Box::new(ExistingProcessRunner::new(pid, &mut task_accepter)?),
         RecordingMode::Launch(process_launch_props) => {
             let ProcessLaunchProps {
                 mut env_vars,
@@ -70,6 +74,8 @@ pub fn start_recording(
     };
 
     let unstable_presymbolicate = profile_creation_props.unstable_presymbolicate;
+    let search_index = profile_creation_props.search_index;
+    let recording_summary = profile_creation_props.recording_summary;
 
     let (task_sender, task_receiver) = unbounded();
 
@@ -92,7 +98,18 @@ pub fn start_recording(
                 task_sender.send(TaskInitOrShutdown::Shutdown).ok();
                 break;
             }
-            let timeout = Duration::from_secs_f64(1.0);
+            let timeout = match &mut process_scanner {
+                Some(process_scanner) => {
+                    for accepted_task in process_scanner.scan_for_new_tasks() {
+                        task_accepter
+                            .queue_received_stuff(ReceivedStuff::AcceptedTask(accepted_task));
+                    }
+                    // Check for new processes frequently, so that we don't
+                    // miss much of their startup.
+                    Duration::from_millis(50)
+                }
+                None => Duration::from_secs_f64(1.0),
+            };
             match task_accepter.next_message(timeout) {
                 Ok(ReceivedStuff::AcceptedTask(accepted_task)) => {
                     let pid = accepted_task.get_id();
@@ -170,21 +187,17 @@ pub fn start_recording(
 
     let profile = match profile_result {
         Ok(profile) => profile,
-        Err(SamplingError::CouldNotObtainRootTask) => {
-            eprintln!("Profiling failed: Could not obtain the root task.");
-            eprintln!();
+        Err(err @ SamplingError::CouldNotObtainRootTask) => {
             eprintln!("On macOS, samply cannot profile system commands, such as the sleep command or system python. This is because system executables are signed in such a way that they block the DYLD_INSERT_LIBRARIES environment variable, which subverts samply's attempt to siphon out the mach task port of the process.");
             eprintln!();
             eprintln!("Suggested remedy: You can profile any binaries that you've compiled yourself, or which are unsigned or locally-signed, such as anything installed by cargo install or by Homebrew.");
-            std::process::exit(1)
-        }
-        Err(e) => {
-            eprintln!("An error occurred during profiling: {e}");
-            std::process::exit(1)
+            eprintln!();
+            return Err(RecordError::Profiling(err.to_string()));
         }
+        Err(err) => return Err(RecordError::Profiling(err.to_string())),
     };
 
-    save_profile_to_file(&profile, &output_file).expect("Couldn't write JSON");
+    save_profile_to_file(&profile, &output_file).map_err(RecordError::Save)?;
 
     if unstable_presymbolicate {
         crate::shared::symbol_precog::presymbolicate(
@@ -193,6 +206,14 @@ pub fn start_recording(
         );
     }
 
+    if search_index {
+        crate::search_index::write_search_index_for_profile(&output_file);
+    }
+
+    if recording_summary {
+        print_recording_summary(&profile, &output_file);
+    }
+
     if let Some(server_props) = server_props {
         let libinfo_map = crate::profile_json_preparse::parse_libinfo_map_from_profile_file(
             File::open(&output_file).expect("Couldn't open file we just wrote"),
@@ -203,5 +224,11 @@ pub fn start_recording(
         start_server_main(&output_file, server_props, symbol_props, libinfo_map);
     }
 
-    Ok(exit_status)
+    Ok((exit_status, profile))
+}
+
+impl From<MachError> for RecordError {
+    fn from(err: MachError) -> Self {
+        RecordError::Profiling(format!("{err:?}"))
+    }
 }
@@ -4,14 +4,24 @@ use std::time::{Duration, SystemTime};
 use std::{mem, thread};
 
 use crossbeam_channel::Receiver;
-use fxprof_processed_profile::{CategoryColor, CategoryPairHandle, Profile, ReferenceTimestamp};
+use fxprof_processed_profile::{
+    CategoryColor, CategoryPairHandle, Profile, ReferenceTimestamp, Timestamp,
+};
 use mach::port::mach_port_t;
 
 use super::error::SamplingError;
+use super::proc_maps::ptr_auth_stripper;
+use super::task_energy;
 use super::task_profiler::TaskProfiler;
 use super::time::get_monotonic_timestamp;
+use crate::shared::collector_frames::remove_collector_frames;
+use crate::shared::namespace_categories::categorize_profile_by_namespace;
+use crate::shared::profile_size_budget::shrink_profile_to_size_budget;
 use crate::shared::recording_props::{ProfileCreationProps, RecordingProps};
+use crate::shared::recording_schedule::RecordingSchedule;
 use crate::shared::recycling::ProcessRecycler;
+use crate::shared::runtime_categories::categorize_profile_by_runtime;
+use crate::shared::time_zone::recording_start_meta_info;
 use crate::shared::timestamp_converter::TimestampConverter;
 use crate::shared::unresolved_samples::UnresolvedStacks;
 
@@ -54,6 +64,7 @@ impl Sampler {
     }
 
     pub fn run(self) -> Result<Profile, SamplingError> {
+        let schedule = RecordingSchedule::new(&self.recording_props);
         let reference_mono = get_monotonic_timestamp();
         let reference_system_time = SystemTime::now();
 
@@ -67,13 +78,24 @@ impl Sampler {
             ReferenceTimestamp::from_system_time(reference_system_time),
             self.recording_props.interval.into(),
         );
+        profile.set_thread_order(self.profile_creation_props.thread_order);
+        profile.set_hide_idle_threads(self.profile_creation_props.hide_idle_threads);
         if let Some(macos_name_and_version) = get_macos_name_and_version() {
             profile.set_os_name(&macos_name_and_version);
         }
+        profile.set_profiling_start_time(Timestamp::from_nanos_since_reference(0));
+        for (label, value) in recording_start_meta_info(reference_system_time) {
+            profile.add_extra_meta_info("Recording", label, &value);
+        }
 
         let mut jit_category_manager =
             crate::shared::jit_category_manager::JitCategoryManager::new();
 
+        let sample_power = self.recording_props.power_counters && task_energy::IS_SUPPORTED;
+        if self.recording_props.power_counters && !task_energy::IS_SUPPORTED {
+            eprintln!("Warning: --power-counters is only supported on Apple Silicon Macs.");
+        }
+
         let default_category =
             CategoryPairHandle::from(profile.add_category("User", CategoryColor::Yellow));
 
@@ -88,13 +110,15 @@ impl Sampler {
                 return Err(SamplingError::CouldNotObtainRootTask);
             }
         };
-        let mut process_recycler = if self.profile_creation_props.reuse_threads {
-            Some(ProcessRecycler::new())
+        let mut process_recycler = if self.profile_creation_props.reuse_processes() {
+            Some(ProcessRecycler::new(
+                self.profile_creation_props.reuse_threads,
+            ))
         } else {
             None
         };
 
-        let root_task = TaskProfiler::new(
+        let mut root_task = TaskProfiler::new(
             root_task_init,
             timestamp_converter,
             &self.profile_creation_props.fallback_profile_name,
@@ -103,14 +127,18 @@ impl Sampler {
             self.profile_creation_props.clone(),
         )
         .expect("couldn't create root TaskProfiler");
+        self.apply_process_interval(&mut root_task, &mut profile);
 
         let mut process_sample_datas = Vec::new();
         let mut stack_scratch_buffer = Vec::new();
         let mut live_tasks = vec![root_task];
         let mut unwinder_cache = Default::default();
-        let mut unresolved_stacks = UnresolvedStacks::default();
+        let mut unresolved_stacks =
+            UnresolvedStacks::with_max_depth(self.profile_creation_props.max_stack_depth);
         let mut last_sleep_overshoot = 0;
         let mut stop_profiling = false;
+        let mut is_sampling = false;
+        let mut stop_reason = None;
 
         loop {
             loop {
@@ -135,7 +163,7 @@ impl Sampler {
                         break;
                     }
                 };
-                if let Ok(new_task) = TaskProfiler::new(
+                if let Ok(mut new_task) = TaskProfiler::new(
                     task_init,
                     timestamp_converter,
                     &self.profile_creation_props.fallback_profile_name,
@@ -143,6 +171,7 @@ impl Sampler {
                     process_recycler.as_mut(),
                     self.profile_creation_props.clone(),
                 ) {
+                    self.apply_process_interval(&mut new_task, &mut profile);
                     live_tasks.push(new_task);
                 } else {
                     // The task is probably already dead again. We get here for tasks which are
@@ -160,15 +189,26 @@ impl Sampler {
                 break;
             }
 
-            let sample_mono = get_monotonic_timestamp();
-            if let Some(time_limit) = self.recording_props.time_limit {
-                if sample_mono - reference_mono >= time_limit.as_nanos() as u64 {
-                    // Time limit reached.
-                    break;
-                }
+            stop_reason = schedule.check_stop();
+            if stop_reason.is_some() {
+                break;
+            }
+
+            if !schedule.is_sampling() {
+                // We're still in the --delay.
+                thread::sleep(self.recording_props.interval);
+                continue;
             }
 
+            let sample_mono = get_monotonic_timestamp();
             let sample_timestamp = timestamp_converter.convert_time(sample_mono);
+            if !is_sampling {
+                is_sampling = true;
+                if !schedule.delay().is_zero() {
+                    // The profile starts when the delay ends.
+                    profile.set_profiling_start_time(sample_timestamp);
+                }
+            }
 
             let mut tasks = Vec::with_capacity(live_tasks.capacity());
             mem::swap(&mut live_tasks, &mut tasks);
@@ -184,6 +224,9 @@ impl Sampler {
                     &mut unresolved_stacks,
                 )?;
                 if still_alive {
+                    if sample_power {
+                        task.sample_power(sample_timestamp, &mut profile);
+                    }
                     live_tasks.push(task);
                 } else {
                     task.notify_dead(sample_timestamp, &mut profile);
@@ -212,7 +255,7 @@ impl Sampler {
 
         // Gather the sample data from the remaining live tasks.
         // `live_tasks` can be non-empty if we stopped profiling before all tasks ended,
-        // for example because the time limit was reached,
+        // for example because the --duration was reached,
         for task in live_tasks.into_iter() {
             let (process_sample_data, _process_recycling_data) =
                 task.finish(&mut jit_category_manager, &mut profile);
@@ -225,13 +268,48 @@ impl Sampler {
                 &mut profile,
                 default_category,
                 default_category,
+                ptr_auth_stripper(),
                 &mut stack_frame_scratch_buf,
                 &unresolved_stacks,
             );
         }
 
+        for (label, value) in schedule.meta_info(stop_reason) {
+            profile.add_extra_meta_info("Recording", label, &value);
+        }
+
+        if self.profile_creation_props.remove_collector_frames {
+            remove_collector_frames(&mut profile);
+        }
+        if self.profile_creation_props.runtime_categories {
+            categorize_profile_by_runtime(&mut profile);
+        }
+        if let Some(rules) = &self.profile_creation_props.namespace_category_rules {
+            categorize_profile_by_namespace(&mut profile, rules);
+        }
+        if let Some(max_profile_size) = self.profile_creation_props.max_profile_size {
+            shrink_profile_to_size_budget(&mut profile, max_profile_size);
+        }
+
         Ok(profile)
     }
+
+    /// Applies the sampling interval from `--interval-for`, if one matches the
+    /// task's process, and records it in the profile metadata.
+    fn apply_process_interval(&self, task: &mut TaskProfiler, profile: &mut Profile) {
+        let strides = self.recording_props.process_sample_strides();
+        let ticks_per_sample = strides.stride_for_process(task.executable_name());
+        if ticks_per_sample == 1 {
+            return;
+        }
+        task.set_ticks_per_sample(ticks_per_sample);
+        strides.add_meta_info(
+            profile,
+            task.executable_name(),
+            task.pid(),
+            ticks_per_sample,
+        );
+    }
 }
 
 fn get_macos_name_and_version() -> Option<String> {
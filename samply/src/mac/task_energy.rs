@@ -0,0 +1,86 @@
+//! The energy use of a task, for `--power-counters`. The kernel only keeps
+//! track of it on Apple Silicon, in the `task_energy` field of
+//! `TASK_POWER_INFO_V2`.
+
+use mach::port::mach_port_t;
+
+#[cfg(target_arch = "aarch64")]
+mod power_info {
+    #![allow(non_camel_case_types, dead_code)]
+
+    use mach::message::mach_msg_type_number_t;
+    use mach::task::task_info;
+    use mach::task_info::task_info_t;
+
+    use super::mach_port_t;
+    use crate::mac::kernel_error::{self, IntoResult};
+
+    const TASK_POWER_INFO_V2: u32 = 26;
+
+    // Defined manually, from /usr/include/mach/task_info.h.
+    #[repr(C)]
+    #[derive(Default, Debug)]
+    struct task_power_info {
+        total_user: u64,
+        total_system: u64,
+        task_interrupt_wakeups: u64,
+        task_platform_idle_wakeups: u64,
+        task_timer_wakeups_bin_1: u64,
+        task_timer_wakeups_bin_2: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default, Debug)]
+    struct gpu_energy_data {
+        task_gpu_utilisation: u64,
+        task_gpu_stat_reserved0: u64,
+        task_gpu_stat_reserved1: u64,
+        task_gpu_stat_reserved2: u64,
+    }
+
+    /// The arm64 layout, which has the `task_energy` field.
+    #[repr(C)]
+    #[derive(Default, Debug)]
+    struct task_power_info_v2 {
+        cpu_energy: task_power_info,
+        gpu_energy: gpu_energy_data,
+        task_energy: u64,
+        task_ptime: u64,
+        task_pset_switches: u64,
+    }
+
+    const TASK_POWER_INFO_V2_COUNT: mach_msg_type_number_t =
+        (std::mem::size_of::<task_power_info_v2>() / std::mem::size_of::<u32>()) as _;
+
+    pub fn get_task_energy_nanojoules(task: mach_port_t) -> kernel_error::Result<u64> {
+        let mut info = task_power_info_v2::default();
+        let mut count = TASK_POWER_INFO_V2_COUNT;
+        unsafe {
+            task_info(
+                task,
+                TASK_POWER_INFO_V2,
+                &mut info as *mut task_power_info_v2 as task_info_t,
+                &mut count,
+            )
+        }
+        .into_result()?;
+        Ok(info.task_energy)
+    }
+}
+
+/// Whether this machine keeps track of the energy use of tasks.
+pub const IS_SUPPORTED: bool = cfg!(target_arch = "aarch64");
+
+/// Returns the energy which the task has used since it started, in nanojoules,
+/// or `None` if it's not available.
+pub fn get_task_energy_nanojoules(task: mach_port_t) -> Option<u64> {
+    #[cfg(target_arch = "aarch64")]
+    {
+        power_info::get_task_energy_nanojoules(task).ok()
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        let _ = task;
+        None
+    }
+}
@@ -33,6 +33,7 @@ use super::proc_maps::{
     VmSubData,
 };
 use super::sampler::{JitdumpOrMarkerPath, TaskInit};
+use super::task_energy::get_task_energy_nanojoules;
 use super::thread_profiler::{get_thread_id, get_thread_name, ThreadProfiler};
 use crate::shared::jit_category_manager::JitCategoryManager;
 use crate::shared::jit_function_recycler::JitFunctionRecycler;
@@ -41,8 +42,9 @@ use crate::shared::lib_mappings::{
     LibMappingAdd, LibMappingInfo, LibMappingOp, LibMappingOpQueue, LibMappingRemove,
 };
 use crate::shared::marker_file;
-use crate::shared::marker_file::get_markers;
+use crate::shared::marker_file::{add_user_counters, get_markers};
 use crate::shared::perf_map::try_load_perf_map;
+use crate::shared::power_counters::{nanojoules_to_picowatt_hours, PowerCounter};
 use crate::shared::process_name::make_process_name;
 use crate::shared::process_sample_data::{MarkerSpanOnThread, ProcessSampleData};
 use crate::shared::recording_props::ProfileCreationProps;
@@ -117,6 +119,12 @@ pub struct TaskProfiler {
     jit_function_recycler: Option<JitFunctionRecycler>,
     timestamp_converter: TimestampConverter,
     profile_creation_props: Arc<ProfileCreationProps>,
+    /// The task is only sampled on every n-th tick of the sampler, and each
+    /// sample gets a weight of n.
+    ticks_per_sample: u32,
+    tick_count: u32,
+    /// Created on the first call to `sample_power`.
+    power_counter: Option<PowerCounter>,
 }
 
 impl TaskProfiler {
@@ -173,7 +181,7 @@ impl TaskProfiler {
 
         let recycling_data = process_recycler
             .as_mut()
-            .and_then(|r| r.recycle_by_name(&name));
+            .and_then(|r| r.recycle_by_name(&name, profile));
 
         let mut live_threads = HashMap::new();
         let mut thread_act_iter = thread_acts.into_iter();
@@ -203,7 +211,7 @@ impl TaskProfiler {
                     process_handle,
                     main_thread_handle,
                     main_thread_label_frame,
-                    Some(thread_recycler),
+                    thread_recycler,
                     Some(jit_function_recycler),
                 )
             }
@@ -220,9 +228,9 @@ impl TaskProfiler {
                     pid,
                     main_thread_tid,
                 );
-                let (thread_recycler, jit_function_recycler) = match process_recycler {
-                    Some(_) => (
-                        Some(ThreadRecycler::new()),
+                let (thread_recycler, jit_function_recycler) = match &process_recycler {
+                    Some(process_recycler) => (
+                        process_recycler.new_thread_recycler(),
                         Some(JitFunctionRecycler::default()),
                     ),
                     None => (None, None),
@@ -311,6 +319,9 @@ impl TaskProfiler {
             jit_function_recycler,
             timestamp_converter,
             profile_creation_props,
+            ticks_per_sample: 1,
+            tick_count: 0,
+            power_counter: None,
         };
 
         task_profiler.process_lib_modifications(start_time_mono, initial_lib_mods, profile);
@@ -318,6 +329,19 @@ impl TaskProfiler {
         Ok(task_profiler)
     }
 
+    pub fn executable_name(&self) -> &str {
+        &self.executable_name
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Makes this task only get sampled on every n-th call to `sample`.
+    pub fn set_ticks_per_sample(&mut self, ticks_per_sample: u32) {
+        self.ticks_per_sample = ticks_per_sample.max(1);
+    }
+
     pub fn sample(
         &mut self,
         now: Timestamp,
@@ -365,6 +389,12 @@ impl TaskProfiler {
         stack_scratch_buffer: &mut Vec<FrameAddress>,
         unresolved_stacks: &mut UnresolvedStacks,
     ) -> Result<(), SamplingError> {
+        let tick = self.tick_count;
+        self.tick_count = self.tick_count.wrapping_add(1);
+        if tick % self.ticks_per_sample != 0 {
+            return Ok(());
+        }
+
         // First, check for any newly-loaded libraries.
         if let Ok(changes) = self.lib_info_manager.check_for_changes() {
             self.process_lib_modifications(now_mono, changes, profile);
@@ -436,6 +466,7 @@ impl TaskProfiler {
                 unresolved_stacks,
                 &mut self.unresolved_samples,
                 self.profile_creation_props.fold_recursive_prefix,
+                self.ticks_per_sample as i32,
             )?;
             if still_alive {
                 now_live_threads.insert(thread_act);
@@ -628,6 +659,20 @@ impl TaskProfiler {
         );
     }
 
+    /// Adds a sample with the energy the task used since the previous call to
+    /// its power counter, for `--power-counters`.
+    pub fn sample_power(&mut self, now: Timestamp, profile: &mut Profile) {
+        let Some(energy_nj) = get_task_energy_nanojoules(self.task) else {
+            return;
+        };
+        let profile_process = self.profile_process;
+        let power_counter = self.power_counter.get_or_insert_with(|| {
+            let description = format!("Energy use of {} (pid {})", self.executable_name, self.pid);
+            PowerCounter::new(profile, profile_process, "Process Power", &description)
+        });
+        power_counter.add_reading(profile, now, nanojoules_to_picowatt_hours(energy_nj));
+    }
+
     /// Called when a process has exited, before finish(). Not called if the process
     /// is still alive at the end of the profiling run.
     pub fn notify_dead(&mut self, end_time: Timestamp, profile: &mut Profile) {
@@ -663,11 +708,12 @@ impl TaskProfiler {
             &self.timestamp_converter,
         );
         let mut marker_spans = Vec::new();
+        let mut counter_samples = Vec::new();
         for (thread_handle, marker_file_path) in self.marker_file_paths {
-            if let Ok(marker_spans_from_this_file) =
+            if let Ok(marker_file_contents) =
                 get_markers(&marker_file_path, &[], self.timestamp_converter)
             {
-                marker_spans.extend(marker_spans_from_this_file.into_iter().map(|span| {
+                marker_spans.extend(marker_file_contents.spans.into_iter().map(|span| {
                     MarkerSpanOnThread {
                         thread_handle,
                         start_time: span.start_time,
@@ -675,22 +721,23 @@ impl TaskProfiler {
                         name: span.name,
                     }
                 }));
+                counter_samples.extend(marker_file_contents.counter_samples);
                 if self.profile_creation_props.unlink_aux_files {
                     std::fs::remove_file(marker_file_path).ok();
                 }
             }
         }
+        add_user_counters(profile, self.profile_process, counter_samples);
         let process_sample_data = ProcessSampleData::new(
             self.unresolved_samples,
             self.lib_mapping_ops,
             jitdump_lib_ops,
             perf_map_mappings,
+            None,
             marker_spans,
         );
 
-        let recycling_data = if let (Some(jit_function_recycler), Some(thread_recycler)) =
-            (self.jit_function_recycler, self.thread_recycler)
-        {
+        let recycling_data = if let Some(jit_function_recycler) = self.jit_function_recycler {
             Some((
                 self.executable_name,
                 ProcessRecyclingData {
@@ -699,7 +746,7 @@ impl TaskProfiler {
                         self.main_thread_handle,
                         self.main_thread_label_frame,
                     ),
-                    thread_recycler,
+                    thread_recycler: self.thread_recycler,
                     jit_function_recycler,
                 },
             ))
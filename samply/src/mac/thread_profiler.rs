@@ -85,6 +85,7 @@ impl ThreadProfiler {
         unresolved_stacks: &mut UnresolvedStacks,
         unresolved_samples: &mut UnresolvedSamples,
         fold_recursive_prefix: bool,
+        weight: i32,
     ) -> Result<bool, SamplingError> {
         let result = self.sample_impl(
             stackwalker,
@@ -94,6 +95,7 @@ impl ThreadProfiler {
             unresolved_stacks,
             unresolved_samples,
             fold_recursive_prefix,
+            weight,
         );
         match result {
             Ok(()) => Ok(true),
@@ -127,6 +129,7 @@ impl ThreadProfiler {
         unresolved_stacks: &mut UnresolvedStacks,
         unresolved_samples: &mut UnresolvedSamples,
         fold_recursive_prefix: bool,
+        weight: i32,
     ) -> Result<(), SamplingError> {
         self.tick_count += 1;
 
@@ -164,7 +167,7 @@ impl ThreadProfiler {
                 sample_time_mono,
                 stack,
                 cpu_delta,
-                1,
+                weight,
                 None,
             );
         } else {
@@ -187,7 +190,7 @@ impl ThreadProfiler {
                 self.profile_thread,
                 now,
                 now_mono,
-                1,
+                weight,
                 None,
             );
         }
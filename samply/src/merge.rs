@@ -0,0 +1,441 @@
+//! Combines profiles which were recorded at the same time, e.g. on a client
+//! and on a server machine, into one profile, for `samply merge` and
+//! `samply record --follow`.
+//!
+//! The profiles are aligned by their wall-clock start times (`meta.startTime`),
+//! corrected by the clock offset of the machine they were recorded on. The
+//! threads, libraries, categories and counters of all profiles are appended
+//! to those of the first one, which is the time reference of the result.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use flate2::bufread::GzDecoder;
+use serde_json::{json, Map, Value};
+
+/// One of the profiles to merge.
+pub struct MergeInput {
+    /// The profile, in the processed profile format.
+    pub profile: Value,
+    /// Appended to the process names, and prepended to the pids, of this
+    /// profile, to tell apart the machines in the merged profile.
+    pub label: Option<String>,
+    /// How far the clock of the machine which recorded this profile was ahead
+    /// of the clock of the machine which recorded the first profile.
+    pub clock_offset_ms: f64,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MergeError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("No profiles to merge")]
+    NoInputs,
+
+    #[error("Unexpected profile format: {0}")]
+    UnexpectedFormat(&'static str),
+}
+
+pub fn load_profile_json(path: &Path) -> Result<Value, MergeError> {
+    let reader = BufReader::new(File::open(path)?);
+    let profile = if path.extension().is_some_and(|ext| ext == "gz") {
+        read_json(GzDecoder::new(reader))?
+    } else {
+        read_json(reader)?
+    };
+    Ok(profile)
+}
+
+fn read_json(reader: impl Read) -> Result<Value, serde_json::Error> {
+    serde_json::from_reader(reader)
+}
+
+/// A label for the processes of a profile file, e.g. `server` for `server.json.gz`.
+pub fn label_for_profile_path(path: &Path) -> String {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let label = file_name.strip_suffix(".gz").unwrap_or(&file_name);
+    let label = label.strip_suffix(".json").unwrap_or(label);
+    label.to_string()
+}
+
+/// Merges the profiles into the first one.
+pub fn merge_profiles(inputs: Vec<MergeInput>) -> Result<Value, MergeError> {
+    let mut inputs = inputs.into_iter();
+    let first = inputs.next().ok_or(MergeError::NoInputs)?;
+    let mut merged = first.profile;
+    let reference_start_time = start_time(&merged)? - first.clock_offset_ms;
+    let mut merged_info = Vec::new();
+    if let Some(label) = first.label {
+        tag_and_shift(&mut merged, Some(&label), 0.0, None, 0, 0)?;
+        merged_info.push((label, describe_offset(first.clock_offset_ms)));
+    }
+
+    for input in inputs {
+        let mut profile = input.profile;
+        let delta_ms = start_time(&profile)? - input.clock_offset_ms - reference_start_time;
+
+        let category_map = merge_categories(&mut merged, &profile)?;
+        let lib_offset = append_array(&mut merged, &mut profile, "libs")?;
+        let thread_offset = array_len(&merged, "threads")?;
+        tag_and_shift(
+            &mut profile,
+            input.label.as_deref(),
+            delta_ms,
+            Some(&category_map),
+            lib_offset,
+            thread_offset,
+        )?;
+        append_array(&mut merged, &mut profile, "threads")?;
+        append_array(&mut merged, &mut profile, "counters")?;
+        merge_meta(&mut merged, &profile, delta_ms)?;
+
+        let label = input.label.unwrap_or_else(|| "(unnamed)".to_string());
+        merged_info.push((label, describe_offset(input.clock_offset_ms)));
+    }
+
+    if !merged_info.is_empty() {
+        let meta = object_mut(&mut merged, "meta")?;
+        let extra = meta
+            .entry("extra")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        let entries: Vec<Value> = merged_info
+            .into_iter()
+            .map(|(label, value)| json!({ "label": label, "format": "string", "value": value }))
+            .collect();
+        if let Value::Array(extra) = extra {
+            extra.push(json!({ "label": "Merged profiles", "entries": entries }));
+        }
+    }
+
+    Ok(merged)
+}
+
+fn describe_offset(clock_offset_ms: f64) -> String {
+    format!("clock offset {clock_offset_ms:.3}ms")
+}
+
+fn start_time(profile: &Value) -> Result<f64, MergeError> {
+    profile["meta"]["startTime"]
+        .as_f64()
+        .ok_or(MergeError::UnexpectedFormat("missing meta.startTime"))
+}
+
+fn object_mut<'a>(
+    value: &'a mut Value,
+    key: &str,
+) -> Result<&'a mut Map<String, Value>, MergeError> {
+    value
+        .get_mut(key)
+        .and_then(Value::as_object_mut)
+        .ok_or(MergeError::UnexpectedFormat("expected an object"))
+}
+
+fn array_len(profile: &Value, key: &str) -> Result<usize, MergeError> {
+    match profile.get(key) {
+        Some(Value::Array(array)) => Ok(array.len()),
+        None => Ok(0),
+        Some(_) => Err(MergeError::UnexpectedFormat("expected an array")),
+    }
+}
+
+/// Moves the elements of `from[key]` to the end of `to[key]`, and returns the
+/// index of the first moved element.
+fn append_array(to: &mut Value, from: &mut Value, key: &str) -> Result<usize, MergeError> {
+    let mut elements = match from.get_mut(key).map(Value::take) {
+        Some(Value::Array(elements)) => elements,
+        None | Some(Value::Null) => Vec::new(),
+        Some(_) => return Err(MergeError::UnexpectedFormat("expected an array")),
+    };
+    let to = to
+        .as_object_mut()
+        .ok_or(MergeError::UnexpectedFormat("expected an object"))?
+        .entry(key)
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or(MergeError::UnexpectedFormat("expected an array"))?;
+    let offset = to.len();
+    to.append(&mut elements);
+    Ok(offset)
+}
+
+/// Adds the categories of `profile` which `merged` doesn't have yet, and
+/// returns the index in `merged` for each category index of `profile`.
+/// Categories are shared if they have the same name and subcategories.
+fn merge_categories(merged: &mut Value, profile: &Value) -> Result<Vec<u64>, MergeError> {
+    let categories = match &profile["meta"]["categories"] {
+        Value::Array(categories) => categories.clone(),
+        _ => Vec::new(),
+    };
+    let merged_categories = object_mut(merged, "meta")?
+        .entry("categories")
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or(MergeError::UnexpectedFormat(
+            "expected meta.categories to be an array",
+        ))?;
+    let mut category_map = Vec::with_capacity(categories.len());
+    for category in categories {
+        let existing = merged_categories.iter().position(|c| {
+            c["name"] == category["name"] && c["subcategories"] == category["subcategories"]
+        });
+        let index = existing.unwrap_or_else(|| {
+            merged_categories.push(category);
+            merged_categories.len() - 1
+        });
+        category_map.push(index as u64);
+    }
+    Ok(category_map)
+}
+
+/// Merges the marker schemas, the interval and the profiling range.
+fn merge_meta(merged: &mut Value, profile: &Value, delta_ms: f64) -> Result<(), MergeError> {
+    let meta = object_mut(merged, "meta")?;
+
+    if let Value::Array(schemas) = &profile["meta"]["markerSchema"] {
+        let merged_schemas = meta
+            .entry("markerSchema")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(merged_schemas) = merged_schemas {
+            for schema in schemas {
+                if !merged_schemas.iter().any(|s| s["name"] == schema["name"]) {
+                    merged_schemas.push(schema.clone());
+                }
+            }
+        }
+    }
+
+    if let Some(interval) = profile["meta"]["interval"].as_f64() {
+        match meta.get("interval").and_then(Value::as_f64) {
+            Some(merged_interval) if merged_interval <= interval => {}
+            _ => {
+                meta.insert("interval".to_string(), json!(interval));
+            }
+        }
+    }
+
+    let ranges = [
+        ("profilingStartTime", f64::min as fn(f64, f64) -> f64),
+        ("profilingEndTime", f64::max),
+    ];
+    for (key, combine) in ranges {
+        if let Some(time) = profile["meta"][key].as_f64() {
+            let time = time + delta_ms;
+            let time = match meta.get(key).and_then(Value::as_f64) {
+                Some(merged_time) => combine(merged_time, time),
+                None => time,
+            };
+            meta.insert(key.to_string(), json!(time));
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites a profile for inclusion in the merged profile: Labels its
+/// processes, shifts its timestamps by `delta_ms`, and adjusts its indexes
+/// into the profile-global tables.
+fn tag_and_shift(
+    profile: &mut Value,
+    label: Option<&str>,
+    delta_ms: f64,
+    category_map: Option<&[u64]>,
+    lib_offset: usize,
+    thread_offset: usize,
+) -> Result<(), MergeError> {
+    let map_category = |index: u64| match category_map {
+        Some(category_map) => category_map.get(index as usize).copied().unwrap_or(index),
+        None => index,
+    };
+
+    if let Some(Value::Array(threads)) = profile.get_mut("threads") {
+        for thread in threads {
+            if let Some(label) = label {
+                tag_pid(&mut thread["pid"], label);
+                if let Value::String(process_name) = &mut thread["processName"] {
+                    *process_name = format!("{process_name} ({label})");
+                }
+            }
+            for key in [
+                "registerTime",
+                "unregisterTime",
+                "processStartupTime",
+                "processShutdownTime",
+            ] {
+                shift_times(&mut thread[key], delta_ms);
+            }
+            shift_times(&mut thread["samples"]["time"], delta_ms);
+            shift_times(&mut thread["markers"]["startTime"], delta_ms);
+            shift_times(&mut thread["markers"]["endTime"], delta_ms);
+            if thread.get("nativeAllocations").is_some() {
+                shift_times(&mut thread["nativeAllocations"]["time"], delta_ms);
+            }
+
+            map_indexes(&mut thread["stackTable"]["category"], map_category);
+            map_indexes(&mut thread["frameTable"]["category"], map_category);
+            map_indexes(&mut thread["markers"]["category"], map_category);
+            let map_lib = |index: u64| index + lib_offset as u64;
+            map_indexes(&mut thread["resourceTable"]["lib"], map_lib);
+            map_indexes(&mut thread["nativeSymbols"]["libIndex"], map_lib);
+        }
+    }
+
+    if let Some(Value::Array(counters)) = profile.get_mut("counters") {
+        for counter in counters {
+            if let Some(label) = label {
+                tag_pid(&mut counter["pid"], label);
+            }
+            map_indexes(&mut counter["mainThreadIndex"], |index| {
+                index + thread_offset as u64
+            });
+            shift_times(&mut counter["samples"]["time"], delta_ms);
+        }
+    }
+    Ok(())
+}
+
+fn tag_pid(pid: &mut Value, label: &str) {
+    let tagged = match pid {
+        Value::String(pid) => format!("{label}:{pid}"),
+        Value::Number(pid) => format!("{label}:{pid}"),
+        _ => return,
+    };
+    *pid = Value::String(tagged);
+}
+
+/// Adds `delta_ms` to a timestamp, or to each timestamp in an array. Nulls
+/// and missing values are left alone.
+fn shift_times(value: &mut Value, delta_ms: f64) {
+    match value {
+        Value::Number(time) => {
+            if let Some(time) = time.as_f64() {
+                *value = json!(time + delta_ms);
+            }
+        }
+        Value::Array(times) => {
+            for time in times {
+                shift_times(time, delta_ms);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Applies `f` to an index, or to each index in an array. Nulls and missing
+/// values are left alone.
+fn map_indexes(value: &mut Value, f: impl Fn(u64) -> u64 + Copy) {
+    match value {
+        Value::Number(index) => {
+            if let Some(index) = index.as_u64() {
+                *value = json!(f(index));
+            }
+        }
+        Value::Array(indexes) => {
+            for index in indexes {
+                map_indexes(index, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{
+        CategoryColor, CpuDelta, Frame, FrameFlags, FrameInfo, Profile, ReferenceTimestamp,
+        SamplingInterval, Timestamp,
+    };
+
+    use super::*;
+
+    fn make_profile(start_time_ms: f64, process_name: &str, category_name: &str) -> Value {
+        let mut profile = Profile::new(
+            process_name,
+            ReferenceTimestamp::from_millis_since_unix_epoch(start_time_ms),
+            SamplingInterval::from_millis(1),
+        );
+        let category = profile.add_category(category_name, CategoryColor::Blue);
+        let process = profile.add_process(
+            process_name,
+            42,
+            Timestamp::from_millis_since_reference(0.0),
+        );
+        let thread = profile.add_thread(
+            process,
+            42,
+            Timestamp::from_millis_since_reference(0.0),
+            true,
+        );
+        let frame = FrameInfo {
+            frame: Frame::Label(profile.intern_string("main")),
+            category_pair: category.into(),
+            flags: FrameFlags::empty(),
+        };
+        profile.add_sample(
+            thread,
+            Timestamp::from_millis_since_reference(10.0),
+            vec![frame].into_iter(),
+            CpuDelta::ZERO,
+            1,
+        );
+        serde_json::to_value(&profile).unwrap()
+    }
+
+    #[test]
+    fn merged_profiles_are_aligned_by_start_time() {
+        let client = make_profile(1_000_000.0, "client", "Network");
+        // Recorded on a machine whose clock is 500ms ahead, and started 100ms later.
+        let server = make_profile(1_000_600.0, "server", "Database");
+
+        let merged = merge_profiles(vec![
+            MergeInput {
+                profile: client,
+                label: None,
+                clock_offset_ms: 0.0,
+            },
+            MergeInput {
+                profile: server,
+                label: Some("host2".to_string()),
+                clock_offset_ms: 500.0,
+            },
+        ])
+        .unwrap();
+
+        let threads = merged["threads"].as_array().unwrap();
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0]["samples"]["time"][0], json!(10.0));
+        assert_eq!(threads[1]["samples"]["time"][0], json!(110.0));
+        assert_eq!(threads[1]["processName"], json!("server (host2)"));
+        assert_eq!(threads[1]["pid"], json!("host2:42"));
+
+        let categories = merged["meta"]["categories"].as_array().unwrap();
+        let server_category = threads[1]["stackTable"]["category"][0].as_u64().unwrap();
+        assert_eq!(
+            categories[server_category as usize]["name"],
+            json!("Database")
+        );
+        let client_category = threads[0]["stackTable"]["category"][0].as_u64().unwrap();
+        assert_eq!(
+            categories[client_category as usize]["name"],
+            json!("Network")
+        );
+    }
+
+    #[test]
+    fn labels_from_file_names() {
+        assert_eq!(
+            label_for_profile_path(Path::new("out/server.json.gz")),
+            "server"
+        );
+        assert_eq!(label_for_profile_path(Path::new("client.json")), "client");
+        assert_eq!(label_for_profile_path(Path::new("trace")), "trace");
+    }
+
+    #[test]
+    fn merging_nothing_is_an_error() {
+        assert!(matches!(merge_profiles(vec![]), Err(MergeError::NoInputs)));
+    }
+}
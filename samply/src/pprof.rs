@@ -0,0 +1,331 @@
+//! `samply export pprof`: Writes the samples of a saved profile in pprof's
+//! gzip-compressed protobuf format (`profile.proto`), which `go tool pprof`
+//! and many other tools read.
+//!
+//! Each sample has the CPU it was taken on as a numeric `cpu` label, and the
+//! kind of that CPU's core as a `core_type` label if the profile knows it,
+//! so that the samples can be grouped by core, e.g. with
+//! `go tool pprof -tagroot=cpu`. The protobuf encoding is written by hand,
+//! since only a handful of message types are needed.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::profile_query::ProfileQuery;
+
+// Field numbers from profile.proto.
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION: u32 = 4;
+const PROFILE_FUNCTION: u32 = 5;
+const PROFILE_STRING_TABLE: u32 = 6;
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+const SAMPLE_LOCATION_ID: u32 = 1;
+const SAMPLE_VALUE: u32 = 2;
+const SAMPLE_LABEL: u32 = 3;
+const LABEL_KEY: u32 = 1;
+const LABEL_STR: u32 = 2;
+const LABEL_NUM: u32 = 3;
+const LOCATION_ID: u32 = 1;
+const LOCATION_LINE: u32 = 4;
+const LINE_FUNCTION_ID: u32 = 1;
+const FUNCTION_ID: u32 = 1;
+const FUNCTION_NAME: u32 = 2;
+const FUNCTION_SYSTEM_NAME: u32 = 3;
+
+/// Writes the samples of the given threads as a gzipped pprof profile.
+pub fn write_pprof(w: impl Write, query: &ProfileQuery, threads: &[usize]) -> io::Result<()> {
+    let mut builder = PprofBuilder::new();
+    query.for_each_sample(threads, |stack, weight, cpu| {
+        let cpu = cpu.map(|cpu| (cpu, query.cpu_core_type(cpu)));
+        builder.add_sample(stack, weight, cpu);
+    });
+    let mut encoder = GzEncoder::new(w, Compression::default());
+    encoder.write_all(&builder.finish())?;
+    encoder.finish()?;
+    Ok(())
+}
+
+/// Builds the encoded `Profile` message. Each function name gets one
+/// `Function` and one `Location` with the same id.
+struct PprofBuilder {
+    /// The encoded fields of the `Profile` message, except for the string
+    /// table. Protobuf fields can come in any order.
+    message: Vec<u8>,
+    strings: Vec<String>,
+    string_indexes: HashMap<String, u64>,
+    location_ids: HashMap<String, u64>,
+}
+
+impl PprofBuilder {
+    fn new() -> Self {
+        let mut builder = Self {
+            message: Vec::new(),
+            strings: Vec::new(),
+            string_indexes: HashMap::new(),
+            location_ids: HashMap::new(),
+        };
+        // The first string must be the empty string.
+        builder.string("");
+        let mut sample_type = Vec::new();
+        write_varint_field(&mut sample_type, VALUE_TYPE_TYPE, builder.string("samples"));
+        write_varint_field(&mut sample_type, VALUE_TYPE_UNIT, builder.string("count"));
+        write_bytes_field(&mut builder.message, PROFILE_SAMPLE_TYPE, &sample_type);
+        builder
+    }
+
+    /// Adds a sample. `stack` goes from the root to the leaf, and `cpu` has
+    /// the CPU number and the kind of its core.
+    fn add_sample(&mut self, stack: &[&str], weight: f64, cpu: Option<(u32, Option<&str>)>) {
+        // pprof lists the locations from the leaf to the root.
+        let mut location_ids = Vec::new();
+        for name in stack.iter().rev() {
+            let location_id = self.location_id(name);
+            write_varint(&mut location_ids, location_id);
+        }
+        let mut sample = Vec::new();
+        write_bytes_field(&mut sample, SAMPLE_LOCATION_ID, &location_ids);
+        // Weights are negative in diff profiles, which int64 values allow.
+        write_varint_field(&mut sample, SAMPLE_VALUE, weight.round() as i64 as u64);
+        if let Some((cpu, core_type)) = cpu {
+            let mut label = Vec::new();
+            write_varint_field(&mut label, LABEL_KEY, self.string("cpu"));
+            write_varint_field(&mut label, LABEL_NUM, u64::from(cpu));
+            write_bytes_field(&mut sample, SAMPLE_LABEL, &label);
+            if let Some(core_type) = core_type {
+                let mut label = Vec::new();
+                write_varint_field(&mut label, LABEL_KEY, self.string("core_type"));
+                write_varint_field(&mut label, LABEL_STR, self.string(core_type));
+                write_bytes_field(&mut sample, SAMPLE_LABEL, &label);
+            }
+        }
+        write_bytes_field(&mut self.message, PROFILE_SAMPLE, &sample);
+    }
+
+    fn location_id(&mut self, name: &str) -> u64 {
+        if let Some(&id) = self.location_ids.get(name) {
+            return id;
+        }
+        // Ids start at 1, because 0 means "no id".
+        let id = self.location_ids.len() as u64 + 1;
+        let name_index = self.string(name);
+        let mut function = Vec::new();
+        write_varint_field(&mut function, FUNCTION_ID, id);
+        write_varint_field(&mut function, FUNCTION_NAME, name_index);
+        write_varint_field(&mut function, FUNCTION_SYSTEM_NAME, name_index);
+        write_bytes_field(&mut self.message, PROFILE_FUNCTION, &function);
+
+        let mut line = Vec::new();
+        write_varint_field(&mut line, LINE_FUNCTION_ID, id);
+        let mut location = Vec::new();
+        write_varint_field(&mut location, LOCATION_ID, id);
+        write_bytes_field(&mut location, LOCATION_LINE, &line);
+        write_bytes_field(&mut self.message, PROFILE_LOCATION, &location);
+
+        self.location_ids.insert(name.to_string(), id);
+        id
+    }
+
+    fn string(&mut self, s: &str) -> u64 {
+        if let Some(&index) = self.string_indexes.get(s) {
+            return index;
+        }
+        let index = self.strings.len() as u64;
+        self.strings.push(s.to_string());
+        self.string_indexes.insert(s.to_string(), index);
+        index
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for s in &self.strings {
+            write_bytes_field(&mut self.message, PROFILE_STRING_TABLE, s.as_bytes());
+        }
+        self.message
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    write_varint(buf, u64::from(field) << 3);
+    write_varint(buf, value);
+}
+
+fn write_bytes_field(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_varint(buf, u64::from(field) << 3 | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use fxprof_processed_profile::{
+        CategoryHandle, CpuDelta, Frame, FrameFlags, FrameInfo, Profile, ReferenceTimestamp,
+        SamplingInterval, Timestamp,
+    };
+
+    use super::*;
+
+    #[derive(Debug)]
+    enum FieldValue<'a> {
+        Varint(u64),
+        Bytes(&'a [u8]),
+    }
+
+    fn read_varint(buf: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = buf[0];
+            *buf = &buf[1..];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    /// Splits an encoded message into its fields.
+    fn decode(mut buf: &[u8]) -> Vec<(u32, FieldValue<'_>)> {
+        let mut fields = Vec::new();
+        while !buf.is_empty() {
+            let key = read_varint(&mut buf);
+            let value = match key & 7 {
+                0 => FieldValue::Varint(read_varint(&mut buf)),
+                2 => {
+                    let len = read_varint(&mut buf) as usize;
+                    let (bytes, rest) = buf.split_at(len);
+                    buf = rest;
+                    FieldValue::Bytes(bytes)
+                }
+                wire_type => panic!("unexpected wire type {wire_type}"),
+            };
+            fields.push(((key >> 3) as u32, value));
+        }
+        fields
+    }
+
+    fn varints(buf: &[u8], field: u32) -> Vec<u64> {
+        decode(buf)
+            .into_iter()
+            .filter_map(|(f, value)| match value {
+                FieldValue::Varint(value) if f == field => Some(value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn messages(buf: &[u8], field: u32) -> Vec<&[u8]> {
+        decode(buf)
+            .into_iter()
+            .filter_map(|(f, value)| match value {
+                FieldValue::Bytes(bytes) if f == field => Some(bytes),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn samples_have_cpu_labels() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+        let thread = profile.add_thread(
+            process,
+            123,
+            Timestamp::from_millis_since_reference(0.0),
+            true,
+        );
+        let mut frames = |names: &[&str]| -> Vec<FrameInfo> {
+            names
+                .iter()
+                .map(|name| FrameInfo {
+                    frame: Frame::Label(profile.intern_string(name)),
+                    category_pair: CategoryHandle::OTHER.into(),
+                    flags: FrameFlags::empty(),
+                })
+                .collect()
+        };
+        let (main_work, main) = (frames(&["main", "work"]), frames(&["main"]));
+        let ts = Timestamp::from_millis_since_reference;
+        profile.add_sample_on_cpu(thread, ts(1.0), main_work.into_iter(), CpuDelta::ZERO, 2, 3);
+        profile.add_sample(thread, ts(2.0), main.into_iter(), CpuDelta::ZERO, 1);
+        profile.set_cpu_core_type(3, "Efficiency");
+        let json = serde_json::to_vec(&profile).unwrap();
+        let query = ProfileQuery::load(&json[..]).unwrap();
+
+        let mut gzipped = Vec::new();
+        write_pprof(&mut gzipped, &query, &[0]).unwrap();
+        let mut message = Vec::new();
+        GzDecoder::new(&gzipped[..])
+            .read_to_end(&mut message)
+            .unwrap();
+
+        let strings: Vec<&str> = messages(&message, PROFILE_STRING_TABLE)
+            .into_iter()
+            .map(|s| std::str::from_utf8(s).unwrap())
+            .collect();
+        assert_eq!(strings[0], "");
+        let string = |index: u64| strings[index as usize];
+        let sample_type = messages(&message, PROFILE_SAMPLE_TYPE)[0];
+        assert_eq!(string(varints(sample_type, VALUE_TYPE_TYPE)[0]), "samples");
+
+        let function_names: HashMap<u64, &str> = messages(&message, PROFILE_FUNCTION)
+            .into_iter()
+            .map(|f| {
+                let name = string(varints(f, FUNCTION_NAME)[0]);
+                (varints(f, FUNCTION_ID)[0], name)
+            })
+            .collect();
+        let location_functions: HashMap<u64, u64> = messages(&message, PROFILE_LOCATION)
+            .into_iter()
+            .map(|l| {
+                let line = messages(l, LOCATION_LINE)[0];
+                (
+                    varints(l, LOCATION_ID)[0],
+                    varints(line, LINE_FUNCTION_ID)[0],
+                )
+            })
+            .collect();
+
+        let samples = messages(&message, PROFILE_SAMPLE);
+        assert_eq!(samples.len(), 2);
+        let stack = |sample: &[u8]| -> Vec<&str> {
+            let mut location_ids = messages(sample, SAMPLE_LOCATION_ID)[0];
+            let mut names = Vec::new();
+            while !location_ids.is_empty() {
+                let id = read_varint(&mut location_ids);
+                names.push(function_names[&location_functions[&id]]);
+            }
+            names
+        };
+        assert_eq!(stack(samples[0]), ["work", "main"]);
+        assert_eq!(varints(samples[0], SAMPLE_VALUE), [2]);
+        let labels = messages(samples[0], SAMPLE_LABEL);
+        assert_eq!(labels.len(), 2);
+        assert_eq!(string(varints(labels[0], LABEL_KEY)[0]), "cpu");
+        assert_eq!(varints(labels[0], LABEL_NUM), [3]);
+        assert_eq!(string(varints(labels[1], LABEL_KEY)[0]), "core_type");
+        assert_eq!(string(varints(labels[1], LABEL_STR)[0]), "Efficiency");
+
+        assert_eq!(stack(samples[1]), ["main"]);
+        assert!(messages(samples[1], SAMPLE_LABEL).is_empty());
+    }
+}
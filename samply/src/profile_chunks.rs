@@ -0,0 +1,150 @@
+//! Splits a profile into chunks which the server can deliver one at a time, so
+//! that a front end can show the processes and threads of a huge profile
+//! before all of its samples have arrived. The chunks always come in the same
+//! order: the profile without its libraries and samples, then the libraries,
+//! then the samples of each thread. Putting the libraries and the samples back
+//! into the first chunk gives the original profile.
+
+use serde_json::{json, Value};
+
+/// The name of the chunk with everything but the libraries and the samples.
+pub const PROFILE_CHUNK_NAME: &str = "profile";
+pub const LIBS_CHUNK_NAME: &str = "libs";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProfileChunksError {
+    #[error("Unexpected profile format: {0}")]
+    UnexpectedFormat(&'static str),
+}
+
+/// The serialized chunks of one profile, by name.
+#[derive(Debug)]
+pub struct ProfileChunks {
+    chunks: Vec<(String, String)>,
+}
+
+/// The name of the chunk with the samples of the thread at `thread_index`.
+pub fn samples_chunk_name(thread_index: usize) -> String {
+    format!("samples-{thread_index}")
+}
+
+impl ProfileChunks {
+    pub fn from_profile(mut profile: Value) -> Result<Self, ProfileChunksError> {
+        let Some(profile_object) = profile.as_object_mut() else {
+            return Err(ProfileChunksError::UnexpectedFormat(
+                "the profile is not an object",
+            ));
+        };
+        let libs = profile_object
+            .get_mut("libs")
+            .map_or(json!([]), |libs| std::mem::replace(libs, json!([])));
+        let Some(threads) = profile_object
+            .get_mut("threads")
+            .and_then(Value::as_array_mut)
+        else {
+            return Err(ProfileChunksError::UnexpectedFormat(
+                "the profile has no threads array",
+            ));
+        };
+        let mut samples_chunks = Vec::new();
+        for (thread_index, thread) in threads.iter_mut().enumerate() {
+            let Some(samples) = thread.get_mut("samples") else {
+                continue;
+            };
+            // Leave an empty table behind, so that the first chunk is a valid
+            // profile on its own.
+            let empty_samples = empty_table(samples);
+            let samples = std::mem::replace(samples, empty_samples);
+            samples_chunks.push((samples_chunk_name(thread_index), samples.to_string()));
+        }
+
+        let mut chunks = vec![
+            (PROFILE_CHUNK_NAME.to_string(), profile.to_string()),
+            (LIBS_CHUNK_NAME.to_string(), libs.to_string()),
+        ];
+        chunks.extend(samples_chunks);
+        Ok(Self { chunks })
+    }
+
+    /// Lists the chunks in the order in which they should be fetched, with
+    /// their sizes, so that the front end can show the loading progress.
+    pub fn manifest_json(&self) -> String {
+        let chunks: Vec<Value> = self
+            .chunks
+            .iter()
+            .map(|(name, json)| json!({ "name": name, "bytes": json.len() }))
+            .collect();
+        let total_bytes: usize = self.chunks.iter().map(|(_, json)| json.len()).sum();
+        json!({ "chunks": chunks, "totalBytes": total_bytes }).to_string()
+    }
+
+    pub fn chunk(&self, name: &str) -> Option<&str> {
+        self.chunks
+            .iter()
+            .find(|(chunk_name, _)| chunk_name == name)
+            .map(|(_, json)| json.as_str())
+    }
+}
+
+/// Returns a table with the same columns as `table` and no rows.
+fn empty_table(table: &Value) -> Value {
+    let Some(columns) = table.as_object() else {
+        return Value::Null;
+    };
+    let columns = columns
+        .iter()
+        .map(|(key, column)| {
+            let empty_column = match column {
+                Value::Array(_) => json!([]),
+                Value::Number(_) if key == "length" => json!(0),
+                other => other.clone(),
+            };
+            (key.clone(), empty_column)
+        })
+        .collect();
+    Value::Object(columns)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_and_reassemble() {
+        let profile = json!({
+            "meta": { "version": 24 },
+            "libs": [{ "name": "libc.so.6" }],
+            "threads": [
+                {
+                    "name": "main",
+                    "samples": { "length": 2, "stack": [0, 1], "time": [0.0, 1.0], "weightType": "samples" },
+                },
+                { "name": "worker", "samples": { "length": 1, "stack": [0], "time": [2.0] } },
+            ],
+        });
+        let chunks = ProfileChunks::from_profile(profile.clone()).unwrap();
+        let manifest: Value = serde_json::from_str(&chunks.manifest_json()).unwrap();
+        let names: Vec<&str> = manifest["chunks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|chunk| chunk["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["profile", "libs", "samples-0", "samples-1"]);
+
+        let mut first: Value = serde_json::from_str(chunks.chunk("profile").unwrap()).unwrap();
+        assert_eq!(
+            first["threads"][0]["samples"],
+            json!({ "length": 0, "stack": [], "time": [], "weightType": "samples" })
+        );
+        assert_eq!(first["libs"], json!([]));
+        first["libs"] = serde_json::from_str(chunks.chunk("libs").unwrap()).unwrap();
+        for thread_index in 0..2 {
+            let samples = chunks.chunk(&samples_chunk_name(thread_index)).unwrap();
+            first["threads"][thread_index]["samples"] = serde_json::from_str(samples).unwrap();
+        }
+        assert_eq!(first, profile);
+        assert_eq!(chunks.chunk("samples-2"), None);
+        assert!(ProfileChunks::from_profile(json!([])).is_err());
+    }
+}
@@ -0,0 +1,683 @@
+//! Answers the `/api/...` queries of the local server about the loaded
+//! profile, so that scripts and editor extensions can get at the profile
+//! data without understanding the processed profile format.
+//!
+//! Function names are returned as they are stored in the profile file. For
+//! native code which hasn't been symbolicated yet, these are addresses.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use flate2::bufread::GzDecoder;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProfileJson {
+    #[serde(default)]
+    meta: MetaJson,
+    #[serde(default)]
+    threads: Vec<ThreadJson>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct MetaJson {
+    #[serde(default)]
+    categories: Vec<CategoryJson>,
+    /// The kind of core of each CPU, by CPU number, on machines with
+    /// performance and efficiency cores.
+    #[serde(default)]
+    cpu_core_types: Vec<Option<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CategoryJson {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ThreadJson {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    process_name: Option<String>,
+    #[serde(default)]
+    pid: Value,
+    #[serde(default)]
+    tid: Value,
+    samples: SamplesJson,
+    stack_table: StackTableJson,
+    frame_table: FrameTableJson,
+    func_table: FuncTableJson,
+    #[serde(default)]
+    resource_table: Option<ResourceTableJson>,
+    #[serde(default)]
+    markers: Option<MarkersJson>,
+    string_array: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SamplesJson {
+    stack: Vec<Option<usize>>,
+    #[serde(default)]
+    weight: Option<Vec<f64>>,
+    /// The CPU which each sample was taken on, if the recorder knew it.
+    #[serde(default)]
+    cpu: Option<Vec<Option<u32>>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StackTableJson {
+    prefix: Vec<Option<usize>>,
+    frame: Vec<usize>,
+    #[serde(default)]
+    category: Vec<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FrameTableJson {
+    func: Vec<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FuncTableJson {
+    name: Vec<usize>,
+    /// -1 for functions without a resource, e.g. labels.
+    #[serde(default)]
+    resource: Vec<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResourceTableJson {
+    name: Vec<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MarkersJson {
+    name: Vec<usize>,
+    start_time: Vec<Option<f64>>,
+    end_time: Vec<Option<f64>>,
+    data: Vec<Value>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ThreadSummary<'a> {
+    index: usize,
+    name: &'a str,
+    process_name: Option<&'a str>,
+    pid: &'a Value,
+    tid: &'a Value,
+    sample_count: usize,
+    marker_count: usize,
+}
+
+/// Identifies a thread of the profile. The pid and tid are strings or numbers,
+/// depending on the profile's importer.
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadIdentity<'a> {
+    pub name: &'a str,
+    pub process_name: Option<&'a str>,
+    pub pid: &'a Value,
+    pub tid: &'a Value,
+}
+
+/// The sample weight in which a function or category is at the top of the
+/// stack (self) or anywhere in the stack (total).
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WeightSummary {
+    pub name: String,
+    pub self_weight: f64,
+    pub total_weight: f64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MarkerEntry<'a> {
+    thread: usize,
+    name: &'a str,
+    start_time: Option<f64>,
+    end_time: Option<f64>,
+    data: &'a Value,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProfileQueryError {
+    #[error("Could not read the profile: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Could not parse the profile: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Missing query parameter {0}")]
+    MissingParameter(&'static str),
+
+    #[error("Invalid thread index {0}")]
+    InvalidThread(String),
+}
+
+pub struct ProfileQuery {
+    profile: ProfileJson,
+}
+
+impl ProfileQuery {
+    pub fn load_from_file(path: &Path) -> Result<Self, ProfileQueryError> {
+        let reader = BufReader::new(File::open(path)?);
+        if path.extension().is_some_and(|ext| ext == "gz") {
+            Self::load(GzDecoder::new(reader))
+        } else {
+            Self::load(reader)
+        }
+    }
+
+    pub fn load(reader: impl Read) -> Result<Self, ProfileQueryError> {
+        let profile = serde_json::from_reader(reader)?;
+        Ok(Self { profile })
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.profile.threads.len()
+    }
+
+    /// The sum of the sample weights of the given thread, or of all threads.
+    pub fn total_sample_weight(&self, thread: Option<usize>) -> f64 {
+        self.threads(thread)
+            .flat_map(|thread| (0..thread.samples.stack.len()).map(|i| sample_weight(thread, i)))
+            .sum()
+    }
+
+    /// Returns the self and total weight of each function in the given
+    /// thread, or in all threads, sorted by descending self weight.
+    pub fn function_summary(&self, thread: Option<usize>) -> Vec<WeightSummary> {
+        let mut totals = HashMap::new();
+        for thread in self.threads(thread) {
+            add_stack_totals(
+                thread,
+                |stack_index| func_name(thread, stack_index),
+                &mut totals,
+            );
+        }
+        sorted_summary(totals)
+    }
+
+    /// Returns the self and total weight of each category in the given
+    /// thread, or in all threads, sorted by descending self weight.
+    pub fn category_summary(&self, thread: Option<usize>) -> Vec<WeightSummary> {
+        let categories = &self.profile.meta.categories;
+        let mut totals = HashMap::new();
+        for thread in self.threads(thread) {
+            let category_name = |stack_index: usize| {
+                let category = *thread.stack_table.category.get(stack_index)?;
+                categories
+                    .get(category)
+                    .map(|category| category.name.as_str())
+            };
+            add_stack_totals(thread, category_name, &mut totals);
+        }
+        sorted_summary(totals)
+    }
+
+    /// Returns the self and total weight of each library in the given thread,
+    /// or in all threads, sorted by descending self weight. Frames without a
+    /// library, e.g. labels and JIT frames, aren't counted.
+    pub fn library_summary(&self, thread: Option<usize>) -> Vec<WeightSummary> {
+        let mut totals = HashMap::new();
+        for thread in self.threads(thread) {
+            add_stack_totals(
+                thread,
+                |stack_index| library_name(thread, stack_index),
+                &mut totals,
+            );
+        }
+        sorted_summary(totals)
+    }
+
+    /// The number of samples in the thread with the given index.
+    pub fn sample_count(&self, thread: usize) -> usize {
+        self.profile
+            .threads
+            .get(thread)
+            .map_or(0, |thread| thread.samples.stack.len())
+    }
+
+    /// Returns the number of frames in the stack of each sample of the given
+    /// thread, or of all threads. Samples without a stack are left out.
+    pub fn sample_stack_depths(&self, thread: Option<usize>) -> Vec<usize> {
+        let mut depths = Vec::new();
+        for thread in self.threads(thread) {
+            // Prefixes come before the stacks which use them, so the depth of
+            // every stack is known once its prefix's depth is.
+            let mut stack_depths: Vec<usize> = Vec::with_capacity(thread.stack_table.prefix.len());
+            for (stack_index, prefix) in thread.stack_table.prefix.iter().enumerate() {
+                let depth = match *prefix {
+                    Some(prefix) if prefix < stack_index => stack_depths[prefix] + 1,
+                    _ => 1,
+                };
+                stack_depths.push(depth);
+            }
+            depths.extend(
+                thread
+                    .samples
+                    .stack
+                    .iter()
+                    .filter_map(|stack| stack_depths.get((*stack)?).copied()),
+            );
+        }
+        depths
+    }
+
+    /// Returns the number of markers with each name in the given thread, or in
+    /// all threads, sorted by descending count.
+    pub fn marker_counts(&self, thread: Option<usize>) -> Vec<(String, usize)> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for thread in self.threads(thread) {
+            let Some(markers) = &thread.markers else {
+                continue;
+            };
+            for &name_index in &markers.name {
+                let name = thread
+                    .string_array
+                    .get(name_index)
+                    .map_or("", String::as_str);
+                *counts.entry(name).or_default() += 1;
+            }
+        }
+        let mut counts: Vec<(String, usize)> = counts
+            .into_iter()
+            .map(|(name, count)| (name.to_string(), count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
+    }
+
+    /// The name, process name, pid and tid of the thread with the given index.
+    pub fn thread_identity(&self, thread: usize) -> Option<ThreadIdentity<'_>> {
+        let thread = self.profile.threads.get(thread)?;
+        Some(ThreadIdentity {
+            name: &thread.name,
+            process_name: thread.process_name.as_deref(),
+            pid: &thread.pid,
+            tid: &thread.tid,
+        })
+    }
+
+    /// Calls `f` for each marker with the given name, with the index of its
+    /// thread and its data. String fields of the data are indexes into the
+    /// thread's string table, see [`ProfileQuery::thread_string`].
+    pub fn for_each_marker<'a>(&'a self, name: &str, mut f: impl FnMut(usize, &'a Value)) {
+        for (thread_index, thread) in self.profile.threads.iter().enumerate() {
+            let Some(markers) = &thread.markers else {
+                continue;
+            };
+            for (i, &name_index) in markers.name.iter().enumerate() {
+                if thread.string_array.get(name_index).map(String::as_str) != Some(name) {
+                    continue;
+                }
+                if let Some(data) = markers.data.get(i) {
+                    f(thread_index, data);
+                }
+            }
+        }
+    }
+
+    /// Looks up a string in the string table of the given thread.
+    pub fn thread_string(&self, thread: usize, index: usize) -> Option<&str> {
+        let thread = self.profile.threads.get(thread)?;
+        thread.string_array.get(index).map(String::as_str)
+    }
+
+    /// The indexes of the threads of the process with the given pid.
+    pub fn process_thread_indexes(&self, pid: &str) -> Vec<usize> {
+        self.profile
+            .threads
+            .iter()
+            .enumerate()
+            .filter(|(_, thread)| match &thread.pid {
+                Value::String(thread_pid) => thread_pid == pid,
+                Value::Number(thread_pid) => thread_pid.to_string() == pid,
+                _ => false,
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Calls `f` for each sample of the given threads, with the function
+    /// names of the sample's stack from the root to the leaf, and with the
+    /// sample's weight.
+    pub fn for_each_sample_stack<'a>(
+        &'a self,
+        threads: &[usize],
+        mut f: impl FnMut(&[&'a str], f64),
+    ) {
+        self.for_each_sample(threads, |stack, weight, _cpu| f(stack, weight));
+    }
+
+    /// Like [`ProfileQuery::for_each_sample_stack`], but also passes the CPU
+    /// which the sample was taken on, if the profile has it.
+    pub fn for_each_sample<'a>(
+        &'a self,
+        threads: &[usize],
+        mut f: impl FnMut(&[&'a str], f64, Option<u32>),
+    ) {
+        let mut stack = Vec::new();
+        for thread in threads.iter().filter_map(|i| self.profile.threads.get(*i)) {
+            for (i, sample_stack) in thread.samples.stack.iter().enumerate() {
+                let Some(mut stack_index) = *sample_stack else {
+                    continue;
+                };
+                stack.clear();
+                loop {
+                    stack.push(func_name(thread, stack_index).unwrap_or("???"));
+                    match thread.stack_table.prefix.get(stack_index) {
+                        Some(&Some(prefix)) if prefix < stack_index => stack_index = prefix,
+                        _ => break,
+                    }
+                }
+                stack.reverse();
+                let cpu = thread.samples.cpu.as_ref().and_then(|cpus| *cpus.get(i)?);
+                f(&stack, sample_weight(thread, i), cpu);
+            }
+        }
+    }
+
+    /// The kind of core of the CPU with the given number, e.g. "Performance"
+    /// or "Efficiency", if the profile has it.
+    pub fn cpu_core_type(&self, cpu: u32) -> Option<&str> {
+        self.profile
+            .meta
+            .cpu_core_types
+            .get(cpu as usize)?
+            .as_deref()
+    }
+
+    fn threads(&self, thread: Option<usize>) -> impl Iterator<Item = &ThreadJson> {
+        self.profile
+            .threads
+            .iter()
+            .enumerate()
+            .filter(move |(index, _)| thread.is_none() || thread == Some(*index))
+            .map(|(_, thread)| thread)
+    }
+
+    /// Answers a GET request for `/api/<endpoint>?<query>`.
+    pub fn query_json_api(&self, endpoint: &str, query: &str) -> Option<String> {
+        let params = parse_query_string(query);
+        let result = match endpoint {
+            "threads" => Ok(self.threads_json()),
+            "top-functions" => self.top_functions_json(&params),
+            "markers" => self.markers_json(&params),
+            _ => return None,
+        };
+        Some(
+            result
+                .unwrap_or_else(|err| serde_json::json!({ "error": err.to_string() }).to_string()),
+        )
+    }
+
+    /// Returns all threads with their index, which the other endpoints take
+    /// as the `thread` parameter.
+    fn threads_json(&self) -> String {
+        let threads: Vec<ThreadSummary> = self
+            .profile
+            .threads
+            .iter()
+            .enumerate()
+            .map(|(index, thread)| ThreadSummary {
+                index,
+                name: &thread.name,
+                process_name: thread.process_name.as_deref(),
+                pid: &thread.pid,
+                tid: &thread.tid,
+                sample_count: thread.samples.stack.len(),
+                marker_count: thread.markers.as_ref().map_or(0, |m| m.name.len()),
+            })
+            .collect();
+        serde_json::to_string(&threads).unwrap()
+    }
+
+    /// Returns the functions of a thread with the highest self weight, with
+    /// their self and total (running) weight. Takes the `thread` index and an
+    /// optional `limit` (default 50).
+    fn top_functions_json(
+        &self,
+        params: &HashMap<String, String>,
+    ) -> Result<String, ProfileQueryError> {
+        let thread = self
+            .thread_param(params)?
+            .ok_or(ProfileQueryError::MissingParameter("thread"))?;
+        let limit = params
+            .get("limit")
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(50);
+        let mut functions = self.function_summary(Some(thread));
+        functions.truncate(limit);
+        Ok(serde_json::to_string(&functions).unwrap())
+    }
+
+    /// Returns the markers whose name contains the `name` parameter, from the
+    /// thread given by the `thread` parameter or from all threads.
+    fn markers_json(&self, params: &HashMap<String, String>) -> Result<String, ProfileQueryError> {
+        let thread_filter = self.thread_param(params)?;
+        let name_filter = params.get("name").map(String::as_str).unwrap_or("");
+        let mut markers = Vec::new();
+        for (thread_index, thread) in self.profile.threads.iter().enumerate() {
+            if thread_filter.is_some_and(|filter| filter != thread_index) {
+                continue;
+            }
+            let Some(thread_markers) = &thread.markers else {
+                continue;
+            };
+            for (i, &name_index) in thread_markers.name.iter().enumerate() {
+                let name = thread
+                    .string_array
+                    .get(name_index)
+                    .map_or("", String::as_str);
+                if !name.contains(name_filter) {
+                    continue;
+                }
+                markers.push(MarkerEntry {
+                    thread: thread_index,
+                    name,
+                    start_time: thread_markers.start_time.get(i).copied().flatten(),
+                    end_time: thread_markers.end_time.get(i).copied().flatten(),
+                    data: thread_markers.data.get(i).unwrap_or(&Value::Null),
+                });
+            }
+        }
+        Ok(serde_json::to_string(&markers).unwrap())
+    }
+
+    fn thread_param(
+        &self,
+        params: &HashMap<String, String>,
+    ) -> Result<Option<usize>, ProfileQueryError> {
+        let Some(thread) = params.get("thread") else {
+            return Ok(None);
+        };
+        match thread.parse() {
+            Ok(index) if index < self.profile.threads.len() => Ok(Some(index)),
+            _ => Err(ProfileQueryError::InvalidThread(thread.clone())),
+        }
+    }
+}
+
+fn sample_weight(thread: &ThreadJson, sample_index: usize) -> f64 {
+    match &thread.samples.weight {
+        Some(weights) => weights.get(sample_index).copied().unwrap_or(1.0),
+        None => 1.0,
+    }
+}
+
+fn func_name(thread: &ThreadJson, stack_index: usize) -> Option<&str> {
+    let frame = *thread.stack_table.frame.get(stack_index)?;
+    let func = *thread.frame_table.func.get(frame)?;
+    let name_index = *thread.func_table.name.get(func)?;
+    thread.string_array.get(name_index).map(String::as_str)
+}
+
+fn library_name(thread: &ThreadJson, stack_index: usize) -> Option<&str> {
+    let frame = *thread.stack_table.frame.get(stack_index)?;
+    let func = *thread.frame_table.func.get(frame)?;
+    let resource = usize::try_from(*thread.func_table.resource.get(func)?).ok()?;
+    let name_index = *thread.resource_table.as_ref()?.name.get(resource)?;
+    thread.string_array.get(name_index).map(String::as_str)
+}
+
+/// Adds the self and total weight of each sample in the thread to the
+/// entries for the keys of the sample's stack. A key which appears more than
+/// once in a stack, e.g. a recursive function, only counts once towards its
+/// total weight.
+fn add_stack_totals<'a>(
+    thread: &ThreadJson,
+    key_for_stack: impl Fn(usize) -> Option<&'a str>,
+    totals: &mut HashMap<&'a str, (f64, f64)>,
+) {
+    let mut seen_in_sample = HashSet::new();
+    for (i, stack) in thread.samples.stack.iter().enumerate() {
+        let Some(mut stack_index) = *stack else {
+            continue;
+        };
+        let weight = sample_weight(thread, i);
+        if let Some(key) = key_for_stack(stack_index) {
+            totals.entry(key).or_default().0 += weight;
+        }
+        seen_in_sample.clear();
+        loop {
+            if let Some(key) = key_for_stack(stack_index) {
+                if seen_in_sample.insert(key) {
+                    totals.entry(key).or_default().1 += weight;
+                }
+            }
+            match thread.stack_table.prefix.get(stack_index) {
+                Some(&Some(prefix)) if prefix < stack_index => stack_index = prefix,
+                _ => break,
+            }
+        }
+    }
+}
+
+fn sorted_summary(totals: HashMap<&str, (f64, f64)>) -> Vec<WeightSummary> {
+    let mut summary: Vec<WeightSummary> = totals
+        .into_iter()
+        .map(|(name, (self_weight, total_weight))| WeightSummary {
+            name: name.to_string(),
+            self_weight,
+            total_weight,
+        })
+        .collect();
+    summary.sort_by(|a, b| {
+        b.self_weight
+            .total_cmp(&a.self_weight)
+            .then_with(|| b.total_weight.total_cmp(&a.total_weight))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    summary
+}
+
+pub fn parse_query_string(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |s: &str| {
+                percent_encoding::percent_decode_str(&s.replace('+', " "))
+                    .decode_utf8_lossy()
+                    .into_owned()
+            };
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{
+        CategoryHandle, CpuDelta, Frame, FrameFlags, FrameInfo, Profile, ReferenceTimestamp,
+        SamplingInterval, Timestamp,
+    };
+
+    use super::*;
+
+    #[test]
+    fn top_functions_and_threads() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+        let thread = profile.add_thread(
+            process,
+            123,
+            Timestamp::from_millis_since_reference(0.0),
+            true,
+        );
+        let stacks: [&[&str]; 3] = [&["main", "work"], &["main", "work", "inner"], &["main"]];
+        for (i, stack) in stacks.iter().enumerate() {
+            let frames: Vec<FrameInfo> = stack
+                .iter()
+                .map(|name| FrameInfo {
+                    frame: Frame::Label(profile.intern_string(name)),
+                    category_pair: CategoryHandle::OTHER.into(),
+                    flags: FrameFlags::empty(),
+                })
+                .collect();
+            let timestamp = Timestamp::from_millis_since_reference(i as f64);
+            profile.add_sample(thread, timestamp, frames.into_iter(), CpuDelta::ZERO, 1);
+        }
+        let json = serde_json::to_vec(&profile).unwrap();
+        let query = ProfileQuery::load(&json[..]).unwrap();
+
+        let threads: Value =
+            serde_json::from_str(&query.query_json_api("threads", "").unwrap()).unwrap();
+        assert_eq!(threads[0]["name"], "app");
+        assert_eq!(threads[0]["sampleCount"], 3);
+
+        let functions = query.function_summary(Some(0));
+        let timing = |name: &str, self_weight, total_weight| WeightSummary {
+            name: name.to_string(),
+            self_weight,
+            total_weight,
+        };
+        assert_eq!(
+            functions,
+            vec![
+                timing("main", 1.0, 3.0),
+                timing("work", 1.0, 2.0),
+                timing("inner", 1.0, 1.0),
+            ]
+        );
+
+        assert_eq!(query.total_sample_weight(None), 3.0);
+        assert_eq!(
+            query.category_summary(None),
+            vec![timing("Other", 3.0, 3.0)]
+        );
+
+        let mut sample_stacks = Vec::new();
+        query.for_each_sample_stack(&query.process_thread_indexes("123"), |stack, weight| {
+            sample_stacks.push((stack.join(";"), weight))
+        });
+        assert_eq!(
+            sample_stacks,
+            vec![
+                ("main;work".to_string(), 1.0),
+                ("main;work;inner".to_string(), 1.0),
+                ("main".to_string(), 1.0),
+            ]
+        );
+        assert!(query.process_thread_indexes("456").is_empty());
+
+        let error = query.query_json_api("top-functions", "thread=5").unwrap();
+        assert!(error.contains("Invalid thread index 5"));
+        assert_eq!(query.query_json_api("unknown", ""), None);
+    }
+}
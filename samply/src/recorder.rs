@@ -0,0 +1,220 @@
+//! Recording from other programs, without going through the command line.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use fxprof_processed_profile::Profile;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use crate::linux::profiler;
+#[cfg(target_os = "macos")]
+use crate::mac::profiler;
+use crate::shared::record_error::RecordError;
+
+/// Records a profile of a command which it launches, or of a running process.
+///
+/// A `Recorder` takes the same options as `samply record`. The profile is
+/// returned, and also saved to a file in the same way as `samply record
+/// --save-only` saves it, so it can be opened later with `samply load`.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    target: Target,
+    env_vars: Vec<(OsString, OsString)>,
+    options: Vec<OsString>,
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Launch {
+        command: OsString,
+        args: Vec<OsString>,
+    },
+    Pid(u32),
+}
+
+/// The result of [`Recorder::record`].
+#[derive(Debug)]
+pub struct Recording {
+    /// The exit status of the launched command, or success when attaching to a process.
+    pub exit_status: ExitStatus,
+    /// The recorded profile. Its addresses aren't symbolicated yet.
+    pub profile: Profile,
+    /// Where the profile was saved, in the Firefox Profiler's format.
+    pub profile_path: PathBuf,
+}
+
+impl Recorder {
+    /// Records the given command, which is looked up in `PATH` like a shell would.
+    pub fn launch(command: impl Into<OsString>) -> Self {
+        Self::new(Target::Launch {
+            command: command.into(),
+            args: Vec::new(),
+        })
+    }
+
+    /// Records the already running process with the given pid, until it exits.
+    pub fn attach(pid: u32) -> Self {
+        Self::new(Target::Pid(pid))
+    }
+
+    fn new(target: Target) -> Self {
+        Self {
+            target,
+            env_vars: Vec::new(),
+            options: Vec::new(),
+        }
+    }
+
+    /// Adds an argument for the launched command.
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        if let Target::Launch { args, .. } = &mut self.target {
+            args.push(arg.into());
+        }
+        self
+    }
+
+    /// Adds arguments for the launched command.
+    pub fn args<I: IntoIterator<Item = impl Into<OsString>>>(mut self, new_args: I) -> Self {
+        if let Target::Launch { args, .. } = &mut self.target {
+            args.extend(new_args.into_iter().map(Into::into));
+        }
+        self
+    }
+
+    /// Sets an environment variable for the launched command.
+    pub fn env(mut self, name: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.env_vars.push((name.into(), value.into()));
+        self
+    }
+
+    /// The file to save the profile to. Defaults to `profile.json.gz`.
+    pub fn output(self, path: impl Into<PathBuf>) -> Self {
+        let path: PathBuf = path.into();
+        self.option("--output").option(path)
+    }
+
+    /// The sampling rate, in Hz. Defaults to 1000.
+    pub fn rate(self, rate: f64) -> Self {
+        self.option("--rate").option(rate.to_string())
+    }
+
+    /// Stops recording after this much time.
+    pub fn duration(self, duration: Duration) -> Self {
+        self.option("--duration")
+            .option(duration.as_secs_f64().to_string())
+    }
+
+    /// Only records samples after this much time has passed since the command
+    /// was launched, or since attaching to the process.
+    pub fn delay(self, delay: Duration) -> Self {
+        self.option("--delay")
+            .option(delay.as_secs_f64().to_string())
+    }
+
+    /// Stops recording once the process with the given pid has exited.
+    pub fn until_exit_of(self, pid: u32) -> Self {
+        self.option("--until-exit-of").option(pid.to_string())
+    }
+
+    /// Runs the launched command this many times.
+    pub fn iteration_count(self, count: u32) -> Self {
+        self.option("--iteration-count").option(count.to_string())
+    }
+
+    /// The name shown for the profile in the profiler UI.
+    pub fn profile_name(self, name: &str) -> Self {
+        self.option("--profile-name").option(name)
+    }
+
+    /// Passes any other `samply record` option, e.g. `"--reuse-threads"`.
+    /// Options which take a value need one call for the option and one for
+    /// the value, or a single `"--option=value"` argument.
+    pub fn option(mut self, option: impl Into<OsString>) -> Self {
+        self.options.push(option.into());
+        self
+    }
+
+    /// Records the profile and saves it. Returns once the launched command
+    /// has exited, or once the process which was attached to has exited.
+    /// Invalid options and commands which can't be launched are returned as
+    /// errors.
+    pub fn record(self) -> Result<Recording, RecordError> {
+        let mut samply_args: Vec<OsString> = ["samply", "record", "--save-only"]
+            .map(OsString::from)
+            .into();
+        samply_args.extend(self.options);
+        match self.target {
+            Target::Launch { command, args } => {
+                samply_args.push("--".into());
+                // `samply record` takes environment variables as NAME=VALUE
+                // arguments in front of the command.
+                samply_args.extend(self.env_vars.into_iter().map(|(name, value)| {
+                    let mut var = name;
+                    var.push("=");
+                    var.push(value);
+                    var
+                }));
+                samply_args.push(command);
+                samply_args.extend(args);
+            }
+            Target::Pid(pid) => {
+                samply_args.push("--pid".into());
+                samply_args.push(pid.to_string().into());
+            }
+        }
+
+        let (recording_mode, recording_props, profile_creation_props, symbol_props) =
+            crate::cli::recorder_props(samply_args)?;
+        let profile_path = recording_props.output_file.clone();
+        let (exit_status, profile) = profiler::start_recording(
+            recording_mode,
+            recording_props,
+            profile_creation_props,
+            symbol_props,
+            None,
+        )?;
+
+        Ok(Recording {
+            exit_status,
+            profile,
+            profile_path,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recorder_options_are_parsed_like_the_command_line() {
+        let recorder = Recorder::launch("true").rate(0.0).output("out.json.gz");
+        assert!(matches!(
+            recorder.record(),
+            Err(RecordError::InvalidOptions(_))
+        ));
+
+        let recorder = Recorder::launch("true").option("--no-such-option");
+        assert!(matches!(
+            recorder.record(),
+            Err(RecordError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn missing_command_is_an_error() {
+        let output = std::env::temp_dir().join("samply-recorder-test.json.gz");
+        let result = Recorder::launch("samply-test-no-such-command")
+            .output(&output)
+            .record();
+        // Without the permission to open perf events, this fails before the
+        // command is launched.
+        assert!(matches!(
+            result,
+            Err(RecordError::CommandNotFound(_) | RecordError::StartProfiling(_))
+        ));
+        let _ = std::fs::remove_file(output);
+    }
+}
@@ -0,0 +1,126 @@
+//! `samply report`: Writes self / total tables per function and per category
+//! of a saved profile, as CSV or Markdown.
+
+use std::io::{self, Write};
+
+use crate::profile_query::{ProfileQuery, WeightSummary};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+    Markdown,
+}
+
+/// Writes the `top` functions with the highest self weight and all
+/// categories of the given thread (or of all threads) to `w`.
+pub fn write_report(
+    w: &mut impl Write,
+    query: &ProfileQuery,
+    thread: Option<usize>,
+    format: ReportFormat,
+    top: usize,
+) -> io::Result<()> {
+    let total_weight = query.total_sample_weight(thread);
+    let mut functions = query.function_summary(thread);
+    functions.truncate(top);
+    let categories = query.category_summary(thread);
+
+    match format {
+        ReportFormat::Csv => {
+            writeln!(w, "kind,name,self,self_percent,total,total_percent")?;
+            for (kind, rows) in [("function", &functions), ("category", &categories)] {
+                for row in rows {
+                    writeln!(
+                        w,
+                        "{kind},{},{},{:.1},{},{:.1}",
+                        csv_escape(&row.name),
+                        format_weight(row.self_weight),
+                        percentage(row.self_weight, total_weight),
+                        format_weight(row.total_weight),
+                        percentage(row.total_weight, total_weight),
+                    )?;
+                }
+            }
+        }
+        ReportFormat::Markdown => {
+            writeln!(w, "## Functions")?;
+            writeln!(w)?;
+            write_markdown_table(w, "Function", &functions, total_weight)?;
+            writeln!(w)?;
+            writeln!(w, "## Categories")?;
+            writeln!(w)?;
+            write_markdown_table(w, "Category", &categories, total_weight)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_markdown_table(
+    w: &mut impl Write,
+    name_header: &str,
+    rows: &[WeightSummary],
+    total_weight: f64,
+) -> io::Result<()> {
+    writeln!(w, "| {name_header} | Self | Self % | Total | Total % |")?;
+    writeln!(w, "| --- | ---: | ---: | ---: | ---: |")?;
+    for row in rows {
+        writeln!(
+            w,
+            "| {} | {} | {:.1}% | {} | {:.1}% |",
+            markdown_escape(&row.name),
+            format_weight(row.self_weight),
+            percentage(row.self_weight, total_weight),
+            format_weight(row.total_weight),
+            percentage(row.total_weight, total_weight),
+        )?;
+    }
+    Ok(())
+}
+
+fn percentage(weight: f64, total_weight: f64) -> f64 {
+    if total_weight == 0.0 {
+        0.0
+    } else {
+        weight / total_weight * 100.0
+    }
+}
+
+/// Sample counts are whole numbers, but weights from imported profiles don't
+/// have to be.
+pub fn format_weight(weight: f64) -> String {
+    if weight.fract() == 0.0 {
+        format!("{weight:.0}")
+    } else {
+        format!("{weight:.2}")
+    }
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub fn markdown_escape(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(csv_escape("main"), "main");
+        assert_eq!(
+            csv_escape("std::vec::Vec<T, A>::push"),
+            "\"std::vec::Vec<T, A>::push\""
+        );
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(markdown_escape("a | b"), "a \\| b");
+        assert_eq!(format_weight(3.0), "3");
+        assert_eq!(format_weight(2.5), "2.50");
+    }
+}
@@ -0,0 +1,313 @@
+//! The search index sidecar (`profile.json.idx`) for `--search-index`: an
+//! inverted index from function names to their sample weights and from marker
+//! names to their counts, with the threads they appear in. It is small compared
+//! to the profile, so `samply grep` and the server's `/api/search` endpoint can
+//! find a function in a huge profile without parsing the whole profile.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::profile_query::{ProfileQuery, ProfileQueryError};
+
+/// Incremented when the format changes in an incompatible way. Sidecar files
+/// with a different version are ignored and the index is rebuilt.
+const SEARCH_INDEX_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchIndex {
+    version: u32,
+    total_sample_weight: f64,
+    /// Sorted by descending total weight.
+    functions: Vec<FunctionEntry>,
+    /// Sorted by descending count.
+    markers: Vec<MarkerEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionEntry {
+    pub name: String,
+    pub self_weight: f64,
+    pub total_weight: f64,
+    /// The indexes of the threads with samples in this function.
+    pub threads: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkerEntry {
+    pub name: String,
+    pub count: usize,
+    /// The indexes of the threads with markers of this name.
+    pub threads: Vec<usize>,
+}
+
+/// The entries of a [`SearchIndex`] which match a search.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResults<'a> {
+    pub total_sample_weight: f64,
+    pub functions: Vec<&'a FunctionEntry>,
+    pub markers: Vec<&'a MarkerEntry>,
+}
+
+impl SearchIndex {
+    pub fn from_profile_query(query: &ProfileQuery) -> Self {
+        let mut functions: HashMap<String, (FunctionEntry, BTreeSet<usize>)> = HashMap::new();
+        let mut markers: HashMap<String, (MarkerEntry, BTreeSet<usize>)> = HashMap::new();
+        for thread in 0..query.thread_count() {
+            for summary in query.function_summary(Some(thread)) {
+                let (entry, threads) = functions.entry(summary.name).or_insert_with_key(|name| {
+                    let entry = FunctionEntry {
+                        name: name.clone(),
+                        self_weight: 0.0,
+                        total_weight: 0.0,
+                        threads: Vec::new(),
+                    };
+                    (entry, BTreeSet::new())
+                });
+                entry.self_weight += summary.self_weight;
+                entry.total_weight += summary.total_weight;
+                threads.insert(thread);
+            }
+            for (name, count) in query.marker_counts(Some(thread)) {
+                let (entry, threads) = markers.entry(name).or_insert_with_key(|name| {
+                    let entry = MarkerEntry {
+                        name: name.clone(),
+                        count: 0,
+                        threads: Vec::new(),
+                    };
+                    (entry, BTreeSet::new())
+                });
+                entry.count += count;
+                threads.insert(thread);
+            }
+        }
+
+        let mut functions: Vec<FunctionEntry> = functions
+            .into_values()
+            .map(|(entry, threads)| FunctionEntry {
+                threads: threads.into_iter().collect(),
+                ..entry
+            })
+            .collect();
+        functions.sort_by(|a, b| {
+            b.total_weight
+                .total_cmp(&a.total_weight)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        let mut markers: Vec<MarkerEntry> = markers
+            .into_values()
+            .map(|(entry, threads)| MarkerEntry {
+                threads: threads.into_iter().collect(),
+                ..entry
+            })
+            .collect();
+        markers.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+        Self {
+            version: SEARCH_INDEX_VERSION,
+            total_sample_weight: query.total_sample_weight(None),
+            functions,
+            markers,
+        }
+    }
+
+    /// Returns the index for the profile at `profile_path`: the sidecar file if
+    /// it's present and up to date, otherwise an index built from the profile.
+    pub fn load_for_profile(profile_path: &Path) -> Result<Self, ProfileQueryError> {
+        if let Some(index) = Self::read_sidecar(profile_path) {
+            return Ok(index);
+        }
+        let query = ProfileQuery::load_from_file(profile_path)?;
+        Ok(Self::from_profile_query(&query))
+    }
+
+    /// Reads the sidecar file of the profile, unless it's missing, older than
+    /// the profile or from a different version of samply.
+    fn read_sidecar(profile_path: &Path) -> Option<Self> {
+        let sidecar_path = sidecar_path(profile_path);
+        let profile_modified = profile_path.metadata().ok()?.modified().ok()?;
+        let sidecar_modified = sidecar_path.metadata().ok()?.modified().ok()?;
+        if sidecar_modified < profile_modified {
+            return None;
+        }
+        let reader = BufReader::new(File::open(&sidecar_path).ok()?);
+        let index: Self = serde_json::from_reader(reader).ok()?;
+        (index.version == SEARCH_INDEX_VERSION).then_some(index)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Returns the functions and markers whose names contain `pattern`.
+    pub fn search(&self, pattern: &str, ignore_case: bool) -> SearchResults<'_> {
+        let pattern_lowercase = pattern.to_lowercase();
+        let matches = |name: &str| match ignore_case {
+            true => name.to_lowercase().contains(&pattern_lowercase),
+            false => name.contains(pattern),
+        };
+        SearchResults {
+            total_sample_weight: self.total_sample_weight,
+            functions: self.functions.iter().filter(|f| matches(&f.name)).collect(),
+            markers: self.markers.iter().filter(|m| matches(&m.name)).collect(),
+        }
+    }
+}
+
+/// The path of the sidecar file, e.g. `profile.json.gz.idx` for `profile.json.gz`.
+pub fn sidecar_path(profile_path: &Path) -> PathBuf {
+    let mut path = profile_path.as_os_str().to_owned();
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+/// Builds the search index of the profile which was just saved to
+/// `profile_path`, and writes it next to the profile.
+pub fn write_search_index_for_profile(profile_path: &Path) {
+    let index = match ProfileQuery::load_from_file(profile_path) {
+        Ok(query) => SearchIndex::from_profile_query(&query),
+        Err(err) => {
+            eprintln!("Could not build the search index for {profile_path:?}: {err}");
+            return;
+        }
+    };
+    let sidecar_path = sidecar_path(profile_path);
+    if let Err(err) = index.write(&sidecar_path) {
+        eprintln!("Could not write the search index {sidecar_path:?}: {err}");
+    }
+}
+
+/// `samply grep`: Writes the functions and markers which match `pattern` to
+/// `w`, one per line, with their weights or counts.
+pub fn write_search_results(w: &mut impl Write, results: &SearchResults) -> io::Result<()> {
+    let threads = |threads: &[usize]| {
+        let threads: Vec<String> = threads.iter().map(usize::to_string).collect();
+        threads.join(",")
+    };
+    let percentage = |weight: f64| match results.total_sample_weight {
+        total if total > 0.0 => weight / total * 100.0,
+        _ => 0.0,
+    };
+    for function in &results.functions {
+        writeln!(
+            w,
+            "function\t{}\tself {} ({:.1}%)\ttotal {} ({:.1}%)\tthreads {}",
+            function.name,
+            function.self_weight,
+            percentage(function.self_weight),
+            function.total_weight,
+            percentage(function.total_weight),
+            threads(&function.threads),
+        )?;
+    }
+    for marker in &results.markers {
+        writeln!(
+            w,
+            "marker\t{}\tcount {}\tthreads {}",
+            marker.name,
+            marker.count,
+            threads(&marker.threads),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{
+        CategoryHandle, CpuDelta, Frame, FrameFlags, FrameInfo, Profile, ReferenceTimestamp,
+        SamplingInterval, Timestamp,
+    };
+
+    use super::*;
+
+    #[test]
+    fn index_and_search() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+        let threads = [
+            profile.add_thread(
+                process,
+                123,
+                Timestamp::from_millis_since_reference(0.0),
+                true,
+            ),
+            profile.add_thread(
+                process,
+                124,
+                Timestamp::from_millis_since_reference(0.0),
+                false,
+            ),
+        ];
+        let stacks: [(usize, &[&str]); 3] = [
+            (0, &["main", "parse_args"]),
+            (0, &["main", "Parser::parse"]),
+            (1, &["thread_start", "Parser::parse"]),
+        ];
+        for (i, (thread, stack)) in stacks.iter().enumerate() {
+            let frames: Vec<FrameInfo> = stack
+                .iter()
+                .map(|name| FrameInfo {
+                    frame: Frame::Label(profile.intern_string(name)),
+                    category_pair: CategoryHandle::OTHER.into(),
+                    flags: FrameFlags::empty(),
+                })
+                .collect();
+            let timestamp = Timestamp::from_millis_since_reference(i as f64);
+            profile.add_sample(
+                threads[*thread],
+                timestamp,
+                frames.into_iter(),
+                CpuDelta::ZERO,
+                1,
+            );
+        }
+        let json = serde_json::to_vec(&profile).unwrap();
+        let query = ProfileQuery::load(&json[..]).unwrap();
+        let index = SearchIndex::from_profile_query(&query);
+
+        let parser_parse = FunctionEntry {
+            name: "Parser::parse".to_string(),
+            self_weight: 2.0,
+            total_weight: 2.0,
+            threads: vec![0, 1],
+        };
+        let parse_args = FunctionEntry {
+            name: "parse_args".to_string(),
+            self_weight: 1.0,
+            total_weight: 1.0,
+            threads: vec![0],
+        };
+        assert_eq!(index.search("Parse", false).functions, vec![&parser_parse]);
+        assert_eq!(
+            index.search("PARSE", true).functions,
+            vec![&parser_parse, &parse_args]
+        );
+        assert!(index.search("nonexistent", true).functions.is_empty());
+
+        let roundtripped: SearchIndex = serde_json::from_str(&index.to_json()).unwrap();
+        assert_eq!(roundtripped, index);
+
+        assert_eq!(
+            sidecar_path(Path::new("dir/profile.json.gz")),
+            Path::new("dir/profile.json.gz.idx")
+        );
+    }
+}
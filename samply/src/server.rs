@@ -4,7 +4,8 @@ use std::net::{IpAddr, SocketAddr};
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
 
 use futures_util::TryStreamExt;
 use http_body_util::combinators::BoxBody;
@@ -14,6 +15,7 @@ use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{header, Method, Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
+use once_cell::sync::OnceCell;
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use platform_dirs::AppDirs;
 use rand::RngCore;
@@ -22,10 +24,15 @@ use tokio_util::io::ReaderStream;
 use wholesym::debugid::DebugId;
 use wholesym::{LibraryInfo, SymbolManager, SymbolManagerConfig};
 
+use crate::merge::load_profile_json;
 use crate::name::SAMPLY_NAME;
+use crate::profile_chunks::ProfileChunks;
+use crate::profile_query::{parse_query_string, ProfileQuery};
+use crate::search_index::SearchIndex;
 use crate::shared;
 use crate::shared::ctrl_c::CtrlC;
 use crate::shared::symbol_props::SymbolProps;
+use crate::symbolication_sandbox::SandboxedSymbolicator;
 
 #[derive(Clone, Debug)]
 pub struct ServerProps {
@@ -33,6 +40,8 @@ pub struct ServerProps {
     pub port_selection: PortSelection,
     pub verbose: bool,
     pub open_in_browser: bool,
+    /// Reload the profile in the browser when the profile file changes.
+    pub watch: bool,
 }
 
 #[tokio::main]
@@ -121,7 +130,80 @@ fn create_symbol_manager_config(symbol_props: SymbolProps, verbose: bool) -> Sym
         config = config.extra_symbols_directory(dir);
     }
 
+    for (from, to) in symbol_props.source_path_map {
+        config = config.source_path_substitution(from, to);
+    }
+
+    for (url_prefix, name, value) in symbol_props.source_url_headers {
+        config = config.source_url_header(url_prefix, name, value);
+    }
+
+    for pattern in symbol_props.skip_symbols_for {
+        config = config.skip_debug_files_for(pattern);
+    }
+
     config
+        .full_function_signatures(symbol_props.full_signatures)
+        .offline(symbol_props.offline)
+}
+
+/// The directories which the symbol manager downloads and caches symbol files
+/// into. The sandboxed symbolication helper can only write beneath these.
+pub fn symbol_cache_dirs(symbol_props: &SymbolProps) -> Vec<PathBuf> {
+    create_symbol_manager_config(symbol_props.clone(), false).cache_dirs()
+}
+
+/// Creates the symbol manager which answers the symbolication API requests.
+/// This parses symbol files, so it runs in the symbolication helper process
+/// if symbolication is sandboxed.
+pub fn create_symbol_manager(
+    symbol_props: SymbolProps,
+    verbose: bool,
+    libinfo_map: HashMap<(String, DebugId), LibraryInfo>,
+    profile_filename: Option<&Path>,
+) -> SymbolManager {
+    let config = create_symbol_manager_config(symbol_props, verbose);
+    let mut symbol_manager = SymbolManager::with_config(config);
+    for lib_info in libinfo_map.into_values() {
+        symbol_manager.add_known_library(lib_info);
+    }
+
+    if let Some(profile_filename) = profile_filename {
+        let precog_filename = profile_filename.with_extension("syms.json");
+        if let Some(precog_info) =
+            shared::symbol_precog::PrecogSymbolInfo::try_load(&precog_filename)
+        {
+            for (debug_id, syms) in precog_info.into_hash_map().into_iter() {
+                let lib_info = LibraryInfo {
+                    debug_id: Some(debug_id),
+                    ..LibraryInfo::default()
+                };
+                symbol_manager.add_known_library_symbols(lib_info, syms);
+            }
+        }
+    }
+
+    symbol_manager
+}
+
+/// Answers symbolication API requests, either directly or by forwarding them
+/// to the sandboxed symbolication helper process.
+enum Symbolicator {
+    InProcess(SymbolManager),
+    Sandboxed(SandboxedSymbolicator),
+}
+
+impl Symbolicator {
+    async fn query_json_api(&self, path: &str, request_json: &str) -> String {
+        match self {
+            Symbolicator::InProcess(symbol_manager) => {
+                symbol_manager.query_json_api(path, request_json).await
+            }
+            Symbolicator::Sandboxed(sandboxed) => {
+                tokio::task::block_in_place(|| sandboxed.query_json_api(path, request_json))
+            }
+        }
+    }
 }
 
 async fn start_server(
@@ -157,45 +239,50 @@ async fn start_server(
         );
         template_values.insert("PROFILER_URL", profiler_url.clone());
         template_values.insert("PROFILE_URL", profile_url);
-        Some(profiler_url)
+        if server_props.watch {
+            // The watch page opens the profiler, and opens it again when the
+            // profile changes.
+            Some(format!("{symbol_server_url}/watch"))
+        } else {
+            Some(profiler_url)
+        }
     } else {
         None
     };
 
     let template_values = Arc::new(template_values);
 
-    let config = create_symbol_manager_config(symbol_props, server_props.verbose);
-    let mut symbol_manager = SymbolManager::with_config(config);
-    for lib_info in libinfo_map.into_values() {
-        symbol_manager.add_known_library(lib_info);
-    }
-
-    if let Some(profile_filename) = profile_filename {
-        let precog_filename = profile_filename.with_extension("syms.json");
-        if let Some(precog_info) =
-            shared::symbol_precog::PrecogSymbolInfo::try_load(&precog_filename)
-        {
-            for (debug_id, syms) in precog_info.into_hash_map().into_iter() {
-                let lib_info = LibraryInfo {
-                    debug_id: Some(debug_id),
-                    ..LibraryInfo::default()
-                };
-                symbol_manager.add_known_library_symbols(lib_info, syms);
+    let symbolicator = if symbol_props.sandbox {
+        match SandboxedSymbolicator::spawn(profile_filename, &symbol_props, server_props.verbose) {
+            Ok(sandboxed) => Symbolicator::Sandboxed(sandboxed),
+            Err(err) => {
+                eprintln!("Could not launch the sandboxed symbolication helper: {err}");
+                std::process::exit(1);
             }
         }
-    }
-
-    let symbol_manager = Arc::new(symbol_manager);
+    } else {
+        Symbolicator::InProcess(create_symbol_manager(
+            symbol_props,
+            server_props.verbose,
+            libinfo_map,
+            profile_filename,
+        ))
+    };
+    let symbolicator = Arc::new(symbolicator);
 
     let server = tokio::task::spawn(run_server(
         listener,
-        symbol_manager,
+        symbolicator,
         profile_filename.map(PathBuf::from),
         template_values,
         path_prefix,
+        server_props.watch,
     ));
 
     eprintln!("Local server listening at {server_origin}");
+    if let (true, Some(profile_filename)) = (server_props.watch, profile_filename) {
+        eprintln!("Watching {profile_filename:?} for changes.");
+    }
     if !server_props.open_in_browser {
         if let Some(profiler_url) = &profiler_url {
             println!("{profiler_url}");
@@ -205,6 +292,7 @@ async fn start_server(
 
     if server_props.open_in_browser {
         if let Some(profiler_url) = &profiler_url {
+            eprintln!("Opening {profiler_url}");
             let _ = opener::open_browser(profiler_url);
         }
     }
@@ -269,6 +357,8 @@ const TEMPLATE_WITH_PROFILE: &str = r#"
 <ul>
     <li><a href="PROFILER_URL">Open the profile in the profiler UI</a></li>
     <li><a download href="PROFILE_URL">Download the raw profile JSON</a></li>
+    <li>Query the profile with GET requests to <code>PATH_PREFIX/api/threads</code>, <code>PATH_PREFIX/api/top-functions?thread=INDEX</code>, <code>PATH_PREFIX/api/markers?name=NAME</code> and <code>PATH_PREFIX/api/search?q=TEXT</code>.</li>
+    <li><a download href="PATH_PREFIX/profile.json.idx">Download the search index</a> of function and marker names</li>
     <li>Obtain symbols by POSTing to <code>PATH_PREFIX/symbolicate/v5</code>, with the format specified by the <a href="https://tecken.readthedocs.io/en/latest/symbolication.html">Mozilla symbolication API documentation</a>.</li>
     <li>Obtain source code by POSTing to <code>PATH_PREFIX/source/v1</code>, with the format specified in this <a href="https://github.com/mstange/profiler-get-symbols/issues/24#issuecomment-989985588">github comment</a>.</li>
 </ul>
@@ -288,15 +378,101 @@ const TEMPLATE_WITHOUT_PROFILE: &str = r#"
 </ul>
 "#;
 
+/// A page which opens the profiler in another tab, and opens the profile again in
+/// that tab when the profile file changes. The profiler can't be told to reload
+/// its profile, but navigating the tab which this page opened works across origins.
+const TEMPLATE_WATCH: &str = r#"
+<!DOCTYPE html>
+<html lang="en">
+<meta charset="utf-8">
+<title>Watching the profile</title>
+<body>
+
+<p>samply is watching the profile file for changes. When it changes, the profile is opened again in the profiler tab.</p>
+<p><button id="open">Open the profile</button> <span id="status"></span></p>
+<script>
+const profilerUrl = "PROFILER_URL";
+let profilerWindow = null;
+let version = null;
+
+function openProfiler() {
+    profilerWindow = window.open(profilerUrl, "samply-profiler");
+}
+
+function setStatus(text) {
+    document.getElementById("status").textContent = text;
+}
+
+document.getElementById("open").onclick = openProfiler;
+// This is blocked if the browser doesn't allow this page to open tabs; the
+// button works in that case.
+openProfiler();
+
+setInterval(async () => {
+    let newVersion;
+    try {
+        newVersion = await (await fetch("PATH_PREFIX/profile-version")).text();
+    } catch (e) {
+        setStatus("The samply server has stopped.");
+        return;
+    }
+    if (version !== null && newVersion !== version) {
+        if (profilerWindow && !profilerWindow.closed) {
+            profilerWindow.location.href = profilerUrl;
+        } else {
+            openProfiler();
+        }
+        setStatus(`Reloaded at ${new Date().toLocaleTimeString()}`);
+    }
+    version = newVersion;
+}, 1000);
+</script>
+"#;
+
+/// Identifies the contents of the profile file, by its modification time and size.
+fn profile_version(profile_filename: &Path) -> Option<String> {
+    let metadata = std::fs::metadata(profile_filename).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{}-{}", modified.as_nanos(), metadata.len()))
+}
+
+/// The profile is only parsed once the first /api/ request comes in. The search
+/// index is read from the profile's sidecar file, or built from the profile if
+/// there is none, once it's first needed. The same goes for the chunks of
+/// /profile/chunks. With `--watch`, all of them are dropped when the profile
+/// file changes.
+#[derive(Default)]
+struct ProfileDerivedData {
+    version: Option<String>,
+    profile_query: Arc<OnceCell<Result<ProfileQuery, String>>>,
+    search_index: Arc<OnceCell<Result<SearchIndex, String>>>,
+    profile_chunks: Arc<OnceCell<Result<ProfileChunks, String>>>,
+}
+
+impl ProfileDerivedData {
+    fn for_version(&mut self, version: Option<String>) -> &Self {
+        if version != self.version {
+            *self = ProfileDerivedData {
+                version,
+                ..Default::default()
+            };
+        }
+        self
+    }
+}
+
 async fn run_server(
     listener: TcpListener,
-    symbol_manager: Arc<SymbolManager>,
+    symbolicator: Arc<Symbolicator>,
     profile_filename: Option<PathBuf>,
     template_values: Arc<HashMap<&'static str, String>>,
     path_prefix: String,
+    watch: bool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let mut ctrl_c_receiver = CtrlC::observe_oneshot();
 
+    let derived_data = Arc::new(Mutex::new(ProfileDerivedData::default()));
+
     // We start a loop to continuously accept incoming connections
     loop {
         let (stream, _) = tokio::select! {
@@ -310,10 +486,11 @@ async fn run_server(
         // `hyper::rt` IO traits.
         let io = TokioIo::new(stream);
 
-        let symbol_manager = symbol_manager.clone();
+        let symbolicator = symbolicator.clone();
         let profile_filename = profile_filename.clone();
         let template_values = template_values.clone();
         let path_prefix = path_prefix.clone();
+        let derived_data = derived_data.clone();
 
         // Spawn a tokio task to serve multiple connections concurrently
         tokio::task::spawn(async move {
@@ -326,9 +503,11 @@ async fn run_server(
                         symbolication_service(
                             req,
                             template_values.clone(),
-                            symbol_manager.clone(),
+                            symbolicator.clone(),
                             profile_filename.clone(),
                             path_prefix.clone(),
+                            derived_data.clone(),
+                            watch,
                         )
                     }),
                 )
@@ -343,9 +522,11 @@ async fn run_server(
 async fn symbolication_service(
     req: Request<hyper::body::Incoming>,
     template_values: Arc<HashMap<&'static str, String>>,
-    symbol_manager: Arc<SymbolManager>,
+    symbolicator: Arc<Symbolicator>,
     profile_filename: Option<PathBuf>,
     path_prefix: String,
+    derived_data: Arc<Mutex<ProfileDerivedData>>,
+    watch: bool,
 ) -> Result<Response<Either<String, BoxBody<Bytes, std::io::Error>>>, hyper::Error> {
     let has_profile = profile_filename.is_some();
     let method = req.method();
@@ -440,6 +621,123 @@ async fn symbolication_service(
             let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
             *response.body_mut() = Either::Right(stream_body.boxed());
         }
+        (&Method::GET, path, Some(profile_filename))
+            if path == "/profile/chunks" || path.starts_with("/profile/chunks/") =>
+        {
+            // The profile in chunks, for front ends which want to show the
+            // threads of a huge profile before all of its samples are loaded.
+            // /profile/chunks lists the chunks in the order in which they
+            // should be requested.
+            let chunk_name = path.strip_prefix("/profile/chunks/");
+            let profile_chunks = derived_data
+                .lock()
+                .unwrap()
+                .for_version(watch.then(|| profile_version(&profile_filename)).flatten())
+                .profile_chunks
+                .clone();
+            let response_json = tokio::task::block_in_place(|| {
+                let profile_chunks = profile_chunks.get_or_init(|| {
+                    let profile =
+                        load_profile_json(&profile_filename).map_err(|err| err.to_string())?;
+                    ProfileChunks::from_profile(profile).map_err(|err| err.to_string())
+                });
+                match (profile_chunks, chunk_name) {
+                    (Ok(profile_chunks), None) => Some(profile_chunks.manifest_json()),
+                    (Ok(profile_chunks), Some(name)) => {
+                        profile_chunks.chunk(name).map(ToString::to_string)
+                    }
+                    (Err(err), _) => Some(serde_json::json!({ "error": err }).to_string()),
+                }
+            });
+            match response_json {
+                Some(response_json) => {
+                    response.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        header::HeaderValue::from_static("application/json; charset=UTF-8"),
+                    );
+                    *response.body_mut() = Either::Left(response_json);
+                }
+                None => {
+                    *response.status_mut() = StatusCode::NOT_FOUND;
+                }
+            }
+        }
+        (&Method::GET, "/watch", Some(_)) if watch => {
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("text/html"),
+            );
+            *response.body_mut() =
+                Either::Left(substitute_template(TEMPLATE_WATCH, &template_values));
+        }
+        (&Method::GET, "/profile-version", Some(profile_filename)) if watch => {
+            response.headers_mut().insert(
+                header::CACHE_CONTROL,
+                header::HeaderValue::from_static("no-store"),
+            );
+            *response.body_mut() =
+                Either::Left(profile_version(&profile_filename).unwrap_or_default());
+        }
+        (&Method::GET, "/profile.json.idx" | "/api/search", Some(profile_filename)) => {
+            let query = parse_query_string(req.uri().query().unwrap_or(""));
+            let search_index = derived_data
+                .lock()
+                .unwrap()
+                .for_version(watch.then(|| profile_version(&profile_filename)).flatten())
+                .search_index
+                .clone();
+            let response_json = tokio::task::block_in_place(|| {
+                let search_index = search_index.get_or_init(|| {
+                    SearchIndex::load_for_profile(&profile_filename).map_err(|err| err.to_string())
+                });
+                match (search_index, path_without_prefix) {
+                    (Ok(search_index), "/profile.json.idx") => search_index.to_json(),
+                    (Ok(search_index), _) => {
+                        let pattern = query.get("q").map(String::as_str).unwrap_or("");
+                        let ignore_case = query.get("ignore_case").is_some_and(|v| v != "0");
+                        let results = search_index.search(pattern, ignore_case);
+                        serde_json::to_string(&results).unwrap()
+                    }
+                    (Err(err), _) => serde_json::json!({ "error": err }).to_string(),
+                }
+            });
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/json"),
+            );
+            *response.body_mut() = Either::Left(response_json);
+        }
+        (&Method::GET, path, Some(profile_filename)) if path.starts_with("/api/") => {
+            let endpoint = &path["/api/".len()..];
+            let query = req.uri().query().unwrap_or("");
+            let profile_query = derived_data
+                .lock()
+                .unwrap()
+                .for_version(watch.then(|| profile_version(&profile_filename)).flatten())
+                .profile_query
+                .clone();
+            let response_json = tokio::task::block_in_place(|| {
+                let profile_query = profile_query.get_or_init(|| {
+                    ProfileQuery::load_from_file(&profile_filename).map_err(|err| err.to_string())
+                });
+                match profile_query {
+                    Ok(profile_query) => profile_query.query_json_api(endpoint, query),
+                    Err(err) => Some(serde_json::json!({ "error": err }).to_string()),
+                }
+            });
+            match response_json {
+                Some(response_json) => {
+                    response.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        header::HeaderValue::from_static("application/json"),
+                    );
+                    *response.body_mut() = Either::Left(response_json);
+                }
+                None => {
+                    *response.status_mut() = StatusCode::NOT_FOUND;
+                }
+            }
+        }
         (&Method::POST, path, _) => {
             response.headers_mut().insert(
                 header::CONTENT_TYPE,
@@ -451,7 +749,7 @@ async fn symbolication_service(
             // Convert the `Collected<Bytes>` into a `String`.
             let full_body =
                 String::from_utf8(full_body.to_bytes().to_vec()).expect("invalid utf-8");
-            let response_json = symbol_manager.query_json_api(&path, &full_body).await;
+            let response_json = symbolicator.query_json_api(&path, &full_body).await;
 
             *response.body_mut() = Either::Left(response_json);
         }
@@ -0,0 +1,255 @@
+//! `samply record --output-dir`: Writes a recording as a session directory,
+//! which has everything that's needed to look at the profile again much later,
+//! on another machine:
+//!
+//! - `profile.json.gz`: the profile.
+//! - `session.json`: when and how the profile was recorded.
+//! - `samply.log`: the log messages from the recording, at the info level and
+//!   above, regardless of `RUST_LOG`.
+//! - `libraries.json`: the libraries which the profile references, with the
+//!   identifiers which are needed to find their symbols.
+//! - `binaries/`: with `--copy-binaries`, copies of these libraries and of
+//!   their debug files, if they still exist. They're named like the library
+//!   and its debug file, so that `samply load profile.json.gz --symbol-dir
+//!   binaries` finds them.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use debugid::DebugId;
+use serde_json::{json, Value};
+use wholesym::LibraryInfo;
+
+use crate::profile_json_preparse::parse_libinfo_map_from_profile_file;
+
+pub const PROFILE_FILE_NAME: &str = "profile.json.gz";
+const MANIFEST_FILE_NAME: &str = "session.json";
+const LOG_FILE_NAME: &str = "samply.log";
+const LIBRARIES_FILE_NAME: &str = "libraries.json";
+const BINARIES_DIR_NAME: &str = "binaries";
+
+/// Creates the session directory and opens its log file. This happens before
+/// the recording starts, so that the log has all messages.
+pub fn create_session_dir(dir: &Path) -> io::Result<File> {
+    fs::create_dir_all(dir)?;
+    File::create(dir.join(LOG_FILE_NAME))
+}
+
+/// Logs like `env_logger`, and also writes the messages at the info level
+/// and above to the log file of a session directory.
+struct SessionLogger {
+    stderr_logger: env_logger::Logger,
+    file: Mutex<File>,
+}
+
+impl log::Log for SessionLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info || self.stderr_logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.stderr_logger.matches(record) {
+            self.stderr_logger.log(record);
+        }
+        if record.level() <= log::Level::Info {
+            let mut file = self.file.lock().unwrap();
+            let _ = writeln!(
+                file,
+                "[{} {} {}] {}",
+                humantime::format_rfc3339_millis(SystemTime::now()),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr_logger.flush();
+        let _ = self.file.lock().unwrap().flush();
+    }
+}
+
+/// Sets up logging, as configured by `RUST_LOG`, and if there's a session log
+/// file, to that file too.
+pub fn init_logger(session_log_file: Option<File>) {
+    let Some(file) = session_log_file else {
+        env_logger::init();
+        return;
+    };
+    let stderr_logger = env_logger::Builder::from_default_env().build();
+    let max_level = stderr_logger.filter().max(log::LevelFilter::Info);
+    let logger = SessionLogger {
+        stderr_logger,
+        file: Mutex::new(file),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Writes the rest of the session directory, once the profile has been saved
+/// to [`PROFILE_FILE_NAME`] in it.
+pub fn finish_session_dir(
+    dir: &Path,
+    exit_code: Option<i32>,
+    copy_binaries: bool,
+) -> io::Result<()> {
+    let profile_path = dir.join(PROFILE_FILE_NAME);
+    let libinfo_map =
+        parse_libinfo_map_from_profile_file(File::open(&profile_path)?, &profile_path)?;
+
+    let binaries_dir = dir.join(BINARIES_DIR_NAME);
+    if copy_binaries {
+        fs::create_dir_all(&binaries_dir)?;
+    }
+    let libraries = library_manifest(&libinfo_map, copy_binaries.then_some(&*binaries_dir))?;
+    write_json(&dir.join(LIBRARIES_FILE_NAME), &libraries)?;
+
+    let command_line: Vec<String> = std::env::args_os()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let manifest = json!({
+        "samplyVersion": env!("CARGO_PKG_VERSION"),
+        "createdAt": humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        "commandLine": command_line,
+        "host": {
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+        },
+        "exitCode": exit_code,
+        "files": {
+            "profile": PROFILE_FILE_NAME,
+            "log": LOG_FILE_NAME,
+            "libraries": LIBRARIES_FILE_NAME,
+            "binaries": copy_binaries.then_some(BINARIES_DIR_NAME),
+        },
+    });
+    write_json(&dir.join(MANIFEST_FILE_NAME), &manifest)
+}
+
+fn write_json(path: &Path, value: &Value) -> io::Result<()> {
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(io::BufWriter::new(file), value)?;
+    Ok(())
+}
+
+/// Lists the libraries, sorted by debug name, and copies their files to
+/// `binaries_dir` if it's given.
+fn library_manifest(
+    libinfo_map: &HashMap<(String, DebugId), LibraryInfo>,
+    binaries_dir: Option<&Path>,
+) -> io::Result<Value> {
+    let mut libs: Vec<_> = libinfo_map.iter().collect();
+    libs.sort_by_key(|(key, _)| *key);
+
+    let mut entries = Vec::with_capacity(libs.len());
+    for ((debug_name, debug_id), lib_info) in libs {
+        let copied_files = match binaries_dir {
+            Some(binaries_dir) => copy_library_files(lib_info, binaries_dir)?,
+            None => vec![],
+        };
+        entries.push(json!({
+            "name": lib_info.name,
+            "path": lib_info.path,
+            "debugName": debug_name,
+            "debugPath": lib_info.debug_path,
+            "breakpadId": debug_id.breakpad().to_string(),
+            "codeId": lib_info.code_id.as_ref().map(ToString::to_string),
+            "arch": lib_info.arch,
+            "copiedFiles": copied_files,
+        }));
+    }
+    Ok(Value::Array(entries))
+}
+
+/// Copies the binary and the debug file of a library to `binaries_dir`, under
+/// the names that `--symbol-dir` looks for. Files which don't exist anymore,
+/// e.g. libraries in the macOS dyld shared cache, are skipped, and so are
+/// files whose name is already taken by another library. Returns the names of
+/// the copied files.
+fn copy_library_files(lib_info: &LibraryInfo, binaries_dir: &Path) -> io::Result<Vec<String>> {
+    let mut copied_files = vec![];
+    let files = [
+        (&lib_info.path, &lib_info.name),
+        (&lib_info.debug_path, &lib_info.debug_name),
+    ];
+    for (path, name) in files {
+        let (Some(path), Some(name)) = (path, name) else {
+            continue;
+        };
+        if copied_files.contains(name) || !Path::new(path).is_file() {
+            continue;
+        }
+        let destination = binaries_dir.join(name);
+        let mut destination_file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&destination)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                log::warn!(
+                    "Not copying {path}, {} already exists",
+                    destination.display()
+                );
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        io::copy(&mut File::open(path)?, &mut destination_file)?;
+        copied_files.push(name.clone());
+    }
+    Ok(copied_files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn copies_library_files_once() {
+        let dir = std::env::temp_dir().join(format!("samply-session-test-{}", std::process::id()));
+        let binaries_dir = dir.join(BINARIES_DIR_NAME);
+        fs::create_dir_all(&binaries_dir).unwrap();
+        let lib_path = dir.join("libfoo.so");
+        fs::write(&lib_path, b"foo").unwrap();
+
+        let debug_id = DebugId::from_breakpad("0123456789ABCDEF0123456789ABCDEF0").unwrap();
+        let lib_info = |path: &Path| LibraryInfo {
+            debug_name: Some("libfoo.so".to_string()),
+            debug_id: Some(debug_id),
+            debug_path: Some(path.to_string_lossy().into_owned()),
+            name: Some("libfoo.so".to_string()),
+            code_id: None,
+            path: Some(path.to_string_lossy().into_owned()),
+            arch: None,
+        };
+        let mut libinfo_map = HashMap::new();
+        libinfo_map.insert(("libfoo.so".to_string(), debug_id), lib_info(&lib_path));
+        libinfo_map.insert(
+            ("libgone.so".to_string(), debug_id),
+            lib_info(&dir.join("libgone.so")),
+        );
+
+        let manifest = library_manifest(&libinfo_map, Some(&binaries_dir)).unwrap();
+        assert_eq!(manifest[0]["debugName"], json!("libfoo.so"));
+        assert_eq!(
+            manifest[0]["breakpadId"],
+            json!("0123456789ABCDEF0123456789ABCDEF0")
+        );
+        assert_eq!(manifest[0]["copiedFiles"], json!(["libfoo.so"]));
+        assert_eq!(manifest[1]["copiedFiles"], json!([]));
+        assert_eq!(fs::read(binaries_dir.join("libfoo.so")).unwrap(), b"foo");
+
+        // A second library with the same name doesn't overwrite the first one.
+        let manifest = library_manifest(&libinfo_map, Some(&binaries_dir)).unwrap();
+        assert_eq!(manifest[0]["copiedFiles"], json!([]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
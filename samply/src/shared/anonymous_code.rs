@@ -0,0 +1,155 @@
+//! A fallback for code in anonymous executable memory which isn't described by
+//! a jitdump or perf map file, e.g. code from a JIT which writes neither. Each
+//! region becomes a pseudo-function of a per-process "unknown JIT" library,
+//! named after the region's start address, so that the samples in a region
+//! are grouped together instead of showing up as scattered raw addresses.
+
+use std::sync::Arc;
+
+use debugid::DebugId;
+use fxprof_processed_profile::{LibMappings, LibraryInfo, Profile, Symbol, SymbolTable};
+
+use super::jit_category_manager::JitCategoryManager;
+use super::lib_mappings::LibMappingInfo;
+
+/// Returns whether a mapping with this path is anonymous memory, as opposed
+/// to a mapped file. Empty paths come from `/proc/<pid>/maps`, "//anon" from
+/// perf's mmap records, and Android names its anonymous mappings "[anon:...]".
+pub fn is_anonymous_mapping_path(path: &[u8]) -> bool {
+    path.is_empty()
+        || path == b"//anon"
+        || path.starts_with(b"[anon")
+        || path.starts_with(b"/memfd:")
+        || path.starts_with(b"/dev/zero")
+}
+
+/// The anonymous executable memory regions of a process.
+///
+/// Regions are not tracked over time: a sample address which falls into a
+/// region at any point of the recording is attributed to that region. JIT code
+/// regions are rarely unmapped, so this is good enough for a fallback.
+#[derive(Debug, Clone, Default)]
+pub struct AnonymousCodeRegions {
+    /// (start_avma, end_avma)
+    regions: Vec<(u64, u64)>,
+}
+
+impl AnonymousCodeRegions {
+    pub fn add_region(&mut self, start_avma: u64, end_avma: u64) {
+        if start_avma < end_avma {
+            self.regions.push((start_avma, end_avma));
+        }
+    }
+
+    /// Returns the regions sorted by address, with overlapping regions merged,
+    /// e.g. when a region was unmapped and then mapped again.
+    fn merged_regions(mut self) -> Vec<(u64, u64)> {
+        self.regions.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(self.regions.len());
+        for (start, end) in self.regions {
+            match merged.last_mut() {
+                Some((_, last_end)) if start < *last_end => *last_end = (*last_end).max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+
+    /// Creates the "unknown JIT" library with a symbol for each region, and
+    /// returns the mappings which translate the regions' addresses into it.
+    pub fn into_lib_mappings(
+        self,
+        pid: u32,
+        profile: &mut Profile,
+        jit_category_manager: &mut JitCategoryManager,
+    ) -> Option<LibMappings<LibMappingInfo>> {
+        let regions = self.merged_regions();
+        if regions.is_empty() {
+            return None;
+        }
+
+        let name = format!("unknown-jit-{pid}");
+        let lib_handle = profile.add_lib(LibraryInfo {
+            debug_name: name.clone(),
+            name: name.clone(),
+            debug_path: name.clone(),
+            path: name,
+            debug_id: DebugId::nil(),
+            code_id: None,
+            arch: None,
+            symbol_table: None,
+        });
+        let category = jit_category_manager.default_category(profile);
+
+        let mut symbols = Vec::new();
+        let mut mappings = LibMappings::new();
+        let mut cumulative_address: u32 = 0;
+        for (start_avma, end_avma) in regions {
+            // Like for perf map files, the regions are laid out consecutively
+            // in the fake library.
+            let Ok(size) = u32::try_from(end_avma - start_avma) else {
+                continue;
+            };
+            let relative_address = cumulative_address;
+            let Some(next_address) = cumulative_address.checked_add(size) else {
+                break;
+            };
+            cumulative_address = next_address;
+            symbols.push(Symbol {
+                address: relative_address,
+                size: Some(size),
+                name: format!("JIT region {start_avma:#x}"),
+            });
+            mappings.add_mapping(
+                start_avma,
+                end_avma,
+                relative_address,
+                LibMappingInfo::new_lib_with_category(lib_handle, category.into()),
+            );
+        }
+
+        profile.set_lib_symbol_table(lib_handle, Arc::new(SymbolTable::new(symbols)));
+        Some(mappings)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{ReferenceTimestamp, SamplingInterval};
+
+    use super::*;
+
+    #[test]
+    fn regions_become_pseudo_symbols() {
+        assert!(is_anonymous_mapping_path(b"//anon"));
+        assert!(is_anonymous_mapping_path(b"[anon:dalvik-jit-code-cache]"));
+        assert!(!is_anonymous_mapping_path(b"/usr/lib/libc.so.6"));
+        assert!(!is_anonymous_mapping_path(b"[vdso]"));
+
+        let mut regions = AnonymousCodeRegions::default();
+        regions.add_region(0x7000_3000, 0x7000_4000);
+        regions.add_region(0x7000_0000, 0x7000_1000);
+        // Overlaps with the first region and extends it.
+        regions.add_region(0x7000_0800, 0x7000_2000);
+
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let mut jit_category_manager = JitCategoryManager::new();
+        let mappings = regions
+            .into_lib_mappings(123, &mut profile, &mut jit_category_manager)
+            .unwrap();
+
+        assert_eq!(
+            mappings.convert_address(0x7000_1800).map(|(addr, _)| addr),
+            Some(0x1800)
+        );
+        assert_eq!(
+            mappings.convert_address(0x7000_3010).map(|(addr, _)| addr),
+            Some(0x2010)
+        );
+        assert!(mappings.convert_address(0x7000_2800).is_none());
+    }
+}
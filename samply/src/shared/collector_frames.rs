@@ -0,0 +1,82 @@
+//! Frames of the profiler itself, which end up at the leaf end of sampled
+//! stacks: signal trampolines of the kernel and the C library, samply's preload
+//! library on macOS, and the kernel's stack walking and event logging code for
+//! ETW stacks on Windows. They're removed when the profile is finished, unless
+//! `--keep-collector-frames` is passed.
+//!
+//! Only frames at the leaf end of a stack are removed, so that e.g. a signal
+//! trampoline under an application's own signal handler stays in the profile.
+//! Like for the runtime categories, frames in libraries are only recognized by
+//! their function name if the library has a symbol table.
+
+use fxprof_processed_profile::Profile;
+use regex::Regex;
+
+/// (library name regex, function name regex)
+const COLLECTOR_RULES: &[(Option<&str>, Option<&str>)] = &[
+    (Some(r"^libsamply_mac_preload\.dylib$"), None),
+    (
+        None,
+        Some(r"^(_sigtramp|__restore_rt|__kernel_rt_sigreturn|__kernel_sigreturn)$"),
+    ),
+    (
+        None,
+        Some(concat!(
+            r"^(EtwpStackWalk\w*|EtwpTraceStackWalk|EtwpLogKernelEvent|EtwTrace\w+",
+            r"|RtlWalkFrameChain|RtlpWalkFrameChain|KeWalkFrameChain)$",
+        )),
+    ),
+];
+
+struct CollectorFrameMatcher {
+    lib_names: Vec<Regex>,
+    function_names: Vec<Regex>,
+}
+
+impl CollectorFrameMatcher {
+    fn new() -> Self {
+        let mut lib_names = Vec::new();
+        let mut function_names = Vec::new();
+        for (lib_name, function_name) in COLLECTOR_RULES {
+            lib_names.extend(lib_name.map(|re| Regex::new(re).unwrap()));
+            function_names.extend(function_name.map(|re| Regex::new(re).unwrap()));
+        }
+        Self {
+            lib_names,
+            function_names,
+        }
+    }
+
+    fn is_collector_frame(&self, lib_name: Option<&str>, function_name: Option<&str>) -> bool {
+        let matches_any = |regexes: &[Regex], name: Option<&str>| {
+            name.is_some_and(|name| regexes.iter().any(|re| re.is_match(name)))
+        };
+        matches_any(&self.lib_names, lib_name) || matches_any(&self.function_names, function_name)
+    }
+}
+
+/// Removes the profiler's own frames from the leaf end of all stacks.
+pub fn remove_collector_frames(profile: &mut Profile) {
+    let matcher = CollectorFrameMatcher::new();
+    profile.remove_leaf_frames(|lib_name, function_name| {
+        matcher.is_collector_frame(lib_name, function_name)
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collector_rules() {
+        let matcher = CollectorFrameMatcher::new();
+        assert!(matcher.is_collector_frame(Some("libsamply_mac_preload.dylib"), None));
+        assert!(matcher.is_collector_frame(Some("libsystem_platform.dylib"), Some("_sigtramp")));
+        assert!(matcher.is_collector_frame(Some("libc.so.6"), Some("__restore_rt")));
+        assert!(matcher.is_collector_frame(Some("ntoskrnl.exe"), Some("RtlWalkFrameChain")));
+        assert!(matcher.is_collector_frame(Some("ntoskrnl.exe"), Some("EtwpStackWalkDpc")));
+        assert!(!matcher.is_collector_frame(Some("ntoskrnl.exe"), Some("KiSwapContext")));
+        assert!(!matcher.is_collector_frame(Some("libc.so.6"), None));
+        assert!(!matcher.is_collector_frame(None, Some("my_sigtramp")));
+    }
+}
@@ -229,6 +229,19 @@ pub struct OffCpuSampleGroup {
     pub sample_count: u64,
 }
 
+impl OffCpuSampleGroup {
+    /// The timestamps of the individual samples of this group, one per off-cpu
+    /// sampling interval, for profiles with a sample in every interval.
+    pub fn sample_timestamps(&self) -> impl Iterator<Item = u64> {
+        let interval = match self.sample_count {
+            0 | 1 => 0,
+            n => (self.end_timestamp - self.begin_timestamp) / (n - 1),
+        };
+        let begin_timestamp = self.begin_timestamp;
+        (0..self.sample_count).map(move |i| begin_timestamp + i * interval)
+    }
+}
+
 #[derive(Default, Clone, Debug, PartialEq, Eq)]
 pub struct ThreadContextSwitchData {
     state: ThreadState,
@@ -317,4 +330,20 @@ mod test {
         assert_eq!(s, None);
         assert_eq!(delta, 10);
     }
+
+    #[test]
+    fn sample_timestamps() {
+        let group = OffCpuSampleGroup {
+            begin_timestamp: 37,
+            end_timestamp: 57,
+            sample_count: 3,
+        };
+        assert_eq!(group.sample_timestamps().collect::<Vec<_>>(), [37, 47, 57]);
+        let group = OffCpuSampleGroup {
+            begin_timestamp: 24,
+            end_timestamp: 24,
+            sample_count: 1,
+        };
+        assert_eq!(group.sample_timestamps().collect::<Vec<_>>(), [24]);
+    }
 }
@@ -0,0 +1,369 @@
+//! CoreCLR event conversions which don't depend on how the events were
+//! recorded: the GC enums and the markers, shared by the ETW path on Windows
+//! and the `.nettrace` importer.
+
+use std::fmt::Display;
+
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+use num_derive::FromPrimitive;
+
+/// The function name of a managed method, as shown in the call tree.
+pub fn method_name(basename: &str, namespace: &str, signature: &str) -> String {
+    format!("{basename} [{namespace}] \u{2329}{signature}\u{232a}")
+}
+
+#[derive(Debug, Clone, FromPrimitive)]
+pub enum GcReason {
+    AllocSmall = 0,
+    Induced,
+    LowMemory,
+    Empty,
+    AllocLarge,
+    OutOfSpaceSmallObjectHeap,
+    OutOfSpaceLargeObjectHeap,
+    InducedNoForce,
+    Stress,
+    InducedLowMemory,
+}
+
+impl Display for GcReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GcReason::AllocSmall => f.write_str("Small object heap allocation"),
+            GcReason::Induced => f.write_str("Induced"),
+            GcReason::LowMemory => f.write_str("Low memory"),
+            GcReason::Empty => f.write_str("Empty"),
+            GcReason::AllocLarge => f.write_str("Large object heap allocation"),
+            GcReason::OutOfSpaceSmallObjectHeap => {
+                f.write_str("Out of space (for small object heap)")
+            }
+            GcReason::OutOfSpaceLargeObjectHeap => {
+                f.write_str("Out of space (for large object heap)")
+            }
+            GcReason::InducedNoForce => f.write_str("Induced but not forced as blocking"),
+            GcReason::Stress => f.write_str("Stress"),
+            GcReason::InducedLowMemory => f.write_str("Induced low memory"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromPrimitive)]
+pub enum GcSuspendEeReason {
+    Other = 0,
+    GC,
+    AppDomainShutdown,
+    CodePitching,
+    Shutdown,
+    Debugger,
+    GcPrep,
+    DebuggerSweep,
+}
+
+impl Display for GcSuspendEeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GcSuspendEeReason::Other => f.write_str("Other"),
+            GcSuspendEeReason::GC => f.write_str("GC"),
+            GcSuspendEeReason::AppDomainShutdown => f.write_str("AppDomain shutdown"),
+            GcSuspendEeReason::CodePitching => f.write_str("Code pitching"),
+            GcSuspendEeReason::Shutdown => f.write_str("Shutdown"),
+            GcSuspendEeReason::Debugger => f.write_str("Debugger"),
+            GcSuspendEeReason::GcPrep => f.write_str("GC prep"),
+            GcSuspendEeReason::DebuggerSweep => f.write_str("Debugger sweep"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromPrimitive)]
+pub enum GcType {
+    Blocking,
+    Background,
+    BlockingDuringBackground,
+}
+
+impl Display for GcType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GcType::Blocking => f.write_str("Blocking GC"),
+            GcType::Background => f.write_str("Background GC"),
+            GcType::BlockingDuringBackground => f.write_str("Blocking GC during background GC"),
+        }
+    }
+}
+
+// String is type name
+#[derive(Debug, Clone)]
+pub struct CoreClrGcAllocMarker(pub StringHandle, pub f64, pub CategoryHandle);
+
+impl StaticSchemaMarker for CoreClrGcAllocMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "GC Alloc";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![
+                MarkerLocation::MarkerChart,
+                MarkerLocation::MarkerTable,
+                MarkerLocation::TimelineMemory,
+            ],
+            chart_label: Some("GC Alloc".into()),
+            tooltip_label: Some(
+                "GC Alloc: {marker.data.clrtype} ({marker.data.size} bytes)".into(),
+            ),
+            table_label: Some("GC Alloc".into()),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "clrtype".into(),
+                    label: "CLR Type".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "size".into(),
+                    label: "Size".into(),
+                    format: MarkerFieldFormat::Bytes,
+                    searchable: false,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "GC Allocation.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("GC Alloc")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.2
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.0
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.1
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CoreClrGcEventMarker(pub StringHandle, pub StringHandle, pub CategoryHandle);
+
+impl StaticSchemaMarker for CoreClrGcEventMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "GC Event";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![
+                MarkerLocation::MarkerChart,
+                MarkerLocation::MarkerTable,
+                MarkerLocation::TimelineMemory,
+            ],
+            chart_label: Some("{marker.data.event}".into()),
+            tooltip_label: Some("{marker.data.event}".into()),
+            table_label: Some("{marker.data.event}".into()),
+            fields: vec![MarkerFieldSchema {
+                key: "event".into(),
+                label: "Event".into(),
+                format: MarkerFieldFormat::String,
+                searchable: true,
+            }],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "Generic GC Event.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.0
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.2
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.1
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CoreClrExceptionThrownMarker(pub StringHandle, pub StringHandle, pub CategoryHandle);
+
+impl StaticSchemaMarker for CoreClrExceptionThrownMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "CLR Exception Thrown";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.clrtype}".into()),
+            tooltip_label: Some(
+                "Exception thrown: {marker.data.clrtype}: {marker.data.message}".into(),
+            ),
+            table_label: Some("{marker.data.clrtype}: {marker.data.message}".into()),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "clrtype".into(),
+                    label: "Exception Type".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "message".into(),
+                    label: "Message".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "A managed exception was thrown.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Exception Thrown")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.2
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.0,
+            1 => self.1,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CoreClrExceptionCaughtMarker(pub StringHandle, pub StringHandle, pub CategoryHandle);
+
+impl StaticSchemaMarker for CoreClrExceptionCaughtMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "CLR Exception Caught";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.clrtype}".into()),
+            tooltip_label: Some(
+                "Exception caught: {marker.data.clrtype} in {marker.data.method}".into(),
+            ),
+            table_label: Some("{marker.data.clrtype} caught in {marker.data.method}".into()),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "clrtype".into(),
+                    label: "Exception Type".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "method".into(),
+                    label: "Catching Method".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "A managed exception was caught.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Exception Caught")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.2
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.0,
+            1 => self.1,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CoreClrAssemblyLoadMarker(pub StringHandle, pub CategoryHandle);
+
+impl StaticSchemaMarker for CoreClrAssemblyLoadMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "CLR Assembly Load";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.assembly}".into()),
+            tooltip_label: Some("Assembly load: {marker.data.assembly}".into()),
+            table_label: Some("Assembly load: {marker.data.assembly}".into()),
+            fields: vec![MarkerFieldSchema {
+                key: "assembly".into(),
+                label: "Assembly".into(),
+                format: MarkerFieldFormat::String,
+                searchable: true,
+            }],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "A managed assembly was loaded.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Assembly Load")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.1
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.0
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayUnknownIfNone<'a, T>(pub &'a Option<T>);
+
+impl<'a, T: Display> Display for DisplayUnknownIfNone<'a, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(value) => value.fmt(f),
+            None => f.write_str("Unknown"),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Names for the kinds of cores on machines with performance and efficiency
+//! cores, for the `cpuCoreTypes` in the profile metadata.
+
+/// Returns the core type name for each CPU. `cpu_classes` has a
+/// `(cpu, class)` pair for each CPU, where a higher class means a faster
+/// core, for example the `EfficiencyClass` on Windows or the `cpu_capacity`
+/// on arm64 Linux. The fastest cores are "Performance", the slowest ones
+/// "Efficiency" and any in between "Mid". If all cores are of the same kind,
+/// nothing is returned.
+pub fn core_type_names(cpu_classes: &[(u32, u32)]) -> Vec<(u32, &'static str)> {
+    let mut classes: Vec<u32> = cpu_classes.iter().map(|(_, class)| *class).collect();
+    classes.sort_unstable();
+    classes.dedup();
+    let (Some(&slowest), Some(&fastest)) = (classes.first(), classes.last()) else {
+        return Vec::new();
+    };
+    if slowest == fastest {
+        return Vec::new();
+    }
+    cpu_classes
+        .iter()
+        .map(|&(cpu, class)| {
+            let name = if class == fastest {
+                "Performance"
+            } else if class == slowest {
+                "Efficiency"
+            } else {
+                "Mid"
+            };
+            (cpu, name)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn core_types_are_ranked_by_class() {
+        assert_eq!(
+            core_type_names(&[(0, 1024), (1, 1024), (2, 512), (3, 160)]),
+            [
+                (0, "Performance"),
+                (1, "Performance"),
+                (2, "Mid"),
+                (3, "Efficiency")
+            ]
+        );
+        assert_eq!(
+            core_type_names(&[(0, 1), (1, 0)]),
+            [(0, "Performance"), (1, "Efficiency")]
+        );
+        assert!(core_type_names(&[(0, 1024), (1, 1024)]).is_empty());
+        assert!(core_type_names(&[]).is_empty());
+    }
+}
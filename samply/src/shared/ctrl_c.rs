@@ -34,7 +34,7 @@ impl CtrlC {
     ///
     /// ### Example 1: Suspend automatic termination for a given scope
     ///
-    /// ```
+    /// ```ignore
     /// let mut ctrl_c_receiver = CtrlC::observe_oneshot();
     ///
     /// // do something
@@ -45,7 +45,7 @@ impl CtrlC {
     ///
     /// ### Example 2: Suspend automatic termination and check if Ctrl+C was pressed
     ///
-    /// ```
+    /// ```ignore
     /// let mut ctrl_c_receiver = CtrlC::observe_oneshot();
     ///
     /// // do something
@@ -71,7 +71,7 @@ impl CtrlC {
     ///
     /// ### Example 3: Keep checking for Ctrl+C in a loop
     ///
-    /// ```
+    /// ```ignore
     /// let mut ctrl_c_receiver = CtrlC::observe_oneshot();
     ///
     /// loop {
@@ -92,7 +92,7 @@ impl CtrlC {
     ///
     /// ### Example 4: Loop on a future and stop early if Ctrl+C is pressed
     ///
-    /// ```
+    /// ```ignore
     /// let mut ctrl_c_receiver = CtrlC::observe_oneshot();
     ///
     /// loop {
@@ -31,13 +31,15 @@ impl JitCategoryManager {
     /// (prefix, name, color, is_js)
     const CATEGORIES: &'static [(&'static str, &'static str, CategoryColor, bool)] = &[
         ("JS:~", "Interpreter", CategoryColor::Magenta, true),
-        ("Script:~", "Interpreter", CategoryColor::Magenta, true),
         ("JS:^", "Baseline", CategoryColor::Blue, true),
         ("JS:+", "Maglev", CategoryColor::Green, true),
         ("JS:*", "Turbofan", CategoryColor::Green, true),
         ("JS:?", "JavaScript", CategoryColor::Blue, true),
         ("Builtin:", "Builtin", CategoryColor::Brown, false),
         ("BytecodeHandler:", "Interpreter", CategoryColor::Red, false),
+        ("Handler:", "IC", CategoryColor::Brown, false),
+        ("Stub:", "Trampoline", CategoryColor::DarkGray, false),
+        ("RegExp:", "RegExp", CategoryColor::Yellow, false),
         ("Interpreter: ", "Interpreter", CategoryColor::Red, true),
         (
             "BaselineThunk: ",
@@ -104,7 +106,6 @@ impl JitCategoryManager {
         }
     }
 
-    #[allow(dead_code)]
     pub fn default_category(&mut self, profile: &mut Profile) -> CategoryHandle {
         self.generic_jit_category.get(profile)
     }
@@ -118,6 +119,10 @@ impl JitCategoryManager {
         name: &str,
         profile: &mut Profile,
     ) -> (CategoryPairHandle, Option<JsFrame>) {
+        if let Some(v8_js_name) = Self::normalize_legacy_v8_js_name(name) {
+            return self.classify_jit_symbol(&v8_js_name, profile);
+        }
+
         if name == "BaselineInterpreter" || name.starts_with("BlinterpOp: ") {
             return (
                 self.baseline_interpreter_category.get(profile).into(),
@@ -203,6 +208,60 @@ impl JitCategoryManager {
         (category.into(), None)
     }
 
+    /// Returns the JIT tier of a function from JIT code, e.g. "Baseline" or
+    /// "Turbofan". This is the name of the category which [`classify_jit_symbol`]
+    /// gives the function.
+    ///
+    /// [`classify_jit_symbol`]: Self::classify_jit_symbol
+    pub fn jit_tier(name: &str) -> &'static str {
+        if let Some(v8_js_name) = Self::normalize_legacy_v8_js_name(name) {
+            return Self::jit_tier(&v8_js_name);
+        }
+        if name == "BaselineInterpreter"
+            || name.starts_with("BlinterpOp: ")
+            || name.starts_with("BaselineInterpreter: ")
+        {
+            return "BaselineInterpreter";
+        }
+        if name.starts_with("IonIC: ") {
+            return "IonIC";
+        }
+        if let Some((_prefix, category_name, _color, _is_js)) = Self::CATEGORIES
+            .iter()
+            .find(|(prefix, ..)| name.starts_with(prefix))
+        {
+            return category_name;
+        }
+        if name.starts_with("JS:") {
+            if name.ends_with("-liftoff") {
+                return "Liftoff (wasm)";
+            }
+            if name.ends_with("-turbofan") {
+                return "Turbofan (wasm)";
+            }
+        }
+        "JIT"
+    }
+
+    /// Converts the names of JS functions from V8 versions before 11 (Node.js
+    /// before 20) into the "JS:" format of newer versions. These older versions
+    /// use the tag of the code creation event instead, for example
+    /// "LazyCompile:~foo /app/index.js:12:34". With --interpreted-frames-native-stack,
+    /// interpreted functions get their own trampoline, named "InterpretedFunction:foo ...".
+    fn normalize_legacy_v8_js_name(name: &str) -> Option<String> {
+        const JS_TAGS: &[&str] = &["LazyCompile:", "Function:", "Eval:", "Script:"];
+
+        if let Some(func_name) = name.strip_prefix("InterpretedFunction:") {
+            return Some(format!("JS:~{func_name}"));
+        }
+        let rest = JS_TAGS.iter().find_map(|tag| name.strip_prefix(tag))?;
+        if rest.starts_with(['~', '^', '+', '*']) {
+            Some(format!("JS:{rest}"))
+        } else {
+            Some(format!("JS:?{rest}"))
+        }
+    }
+
     fn intern_js_name(profile: &mut Profile, func_name: &str) -> JsName {
         if let Some((before, after)) = func_name
             .split_once("[Call")
@@ -280,4 +339,58 @@ mod test {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn jit_tiers() {
+        assert_eq!(
+            JitCategoryManager::jit_tier("JS:^foo app.js:1:1"),
+            "Baseline"
+        );
+        assert_eq!(
+            JitCategoryManager::jit_tier("JS:*foo app.js:1:1"),
+            "Turbofan"
+        );
+        assert_eq!(
+            JitCategoryManager::jit_tier("LazyCompile:~foo node:internal/timers:1"),
+            "Interpreter"
+        );
+        assert_eq!(
+            JitCategoryManager::jit_tier("IonIC: GetProp : foo"),
+            "IonIC"
+        );
+        assert_eq!(
+            JitCategoryManager::jit_tier("JS:wasm-function[12]-12-liftoff"),
+            "Liftoff (wasm)"
+        );
+        assert_eq!(JitCategoryManager::jit_tier("Foo.Bar(int)"), "JIT");
+    }
+
+    #[test]
+    fn legacy_node_names() {
+        let mut manager = JitCategoryManager::new();
+        let mut profile = Profile::new(
+            "",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        for symbol_name in [
+            "LazyCompile:~processTicksAndRejections node:internal/process/task_queues:67",
+            "LazyCompile:*processTicksAndRejections node:internal/process/task_queues:67",
+            "InterpretedFunction:processTicksAndRejections node:internal/process/task_queues:67",
+        ] {
+            let (_category, js_name) = manager.classify_jit_symbol(symbol_name, &mut profile);
+            match js_name {
+                Some(JsFrame::RegularInAdditionToNativeFrame(JsName::NonSelfHosted(s))) => {
+                    assert_eq!(
+                        profile.get_string(s),
+                        "processTicksAndRejections node:internal/process/task_queues:67"
+                    )
+                }
+                _ => panic!("{symbol_name} wasn't recognized as a JS function"),
+            }
+        }
+
+        let (_category, js_name) = manager.classify_jit_symbol("Stub:CEntryStub", &mut profile);
+        assert!(js_name.is_none());
+    }
 }
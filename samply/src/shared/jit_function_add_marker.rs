@@ -4,7 +4,16 @@ use fxprof_processed_profile::{
 };
 
 #[derive(Debug, Clone)]
-pub struct JitFunctionAddMarker(pub StringHandle);
+pub struct JitFunctionAddMarker {
+    pub name: StringHandle,
+    /// The JIT tier, e.g. "Baseline" or "Turbofan", see [`JitCategoryManager::jit_tier`].
+    ///
+    /// [`JitCategoryManager::jit_tier`]: super::jit_category_manager::JitCategoryManager::jit_tier
+    pub tier: StringHandle,
+    /// The library which the JIT code is attributed to, e.g. the jitdump file.
+    pub module: StringHandle,
+    pub code_size: u32,
+}
 
 impl StaticSchemaMarker for JitFunctionAddMarker {
     const UNIQUE_MARKER_TYPE_NAME: &'static str = "JitFunctionAdd";
@@ -14,14 +23,9 @@ impl StaticSchemaMarker for JitFunctionAddMarker {
             type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
             locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
             chart_label: Some("{marker.data.n}".into()),
-            tooltip_label: Some("{marker.data.n}".into()),
-            table_label: Some("{marker.data.n}".into()),
-            fields: vec![MarkerFieldSchema {
-                key: "n".into(),
-                label: "Function".into(),
-                format: MarkerFieldFormat::String,
-                searchable: true,
-            }],
+            tooltip_label: Some("{marker.data.n} ({marker.data.tier})".into()),
+            table_label: Some("{marker.data.n} ({marker.data.tier}, {marker.data.size})".into()),
+            fields: jit_function_fields(),
             static_fields: vec![MarkerStaticField {
                 label: "Description".into(),
                 value: "Emitted when a JIT function is added to the process.".into(),
@@ -37,11 +41,96 @@ impl StaticSchemaMarker for JitFunctionAddMarker {
         CategoryHandle::OTHER
     }
 
-    fn string_field_value(&self, _field_index: u32) -> StringHandle {
-        self.0
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.name,
+            1 => self.tier,
+            2 => self.module,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.code_size.into()
+    }
+}
+
+/// Emitted when the code of a JIT function is freed, e.g. because the runtime
+/// recompiled it at a different tier or because its module was unloaded.
+#[derive(Debug, Clone)]
+pub struct JitFunctionUnloadMarker {
+    pub name: StringHandle,
+    pub tier: StringHandle,
+    pub module: StringHandle,
+    pub code_size: u32,
+}
+
+impl StaticSchemaMarker for JitFunctionUnloadMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "JitFunctionUnload";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.n}".into()),
+            tooltip_label: Some("{marker.data.n} ({marker.data.tier})".into()),
+            table_label: Some("{marker.data.n} ({marker.data.tier}, {marker.data.size})".into()),
+            fields: jit_function_fields(),
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "Emitted when the code of a JIT function is unloaded from the process."
+                    .into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("JitFunctionUnload")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        CategoryHandle::OTHER
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.name,
+            1 => self.tier,
+            2 => self.module,
+            _ => unreachable!(),
+        }
     }
 
     fn number_field_value(&self, _field_index: u32) -> f64 {
-        unreachable!()
+        self.code_size.into()
     }
 }
+
+fn jit_function_fields() -> Vec<MarkerFieldSchema> {
+    vec![
+        MarkerFieldSchema {
+            key: "n".into(),
+            label: "Function".into(),
+            format: MarkerFieldFormat::String,
+            searchable: true,
+        },
+        MarkerFieldSchema {
+            key: "tier".into(),
+            label: "Tier".into(),
+            format: MarkerFieldFormat::String,
+            searchable: true,
+        },
+        MarkerFieldSchema {
+            key: "module".into(),
+            label: "Module".into(),
+            format: MarkerFieldFormat::String,
+            searchable: true,
+        },
+        MarkerFieldSchema {
+            key: "size".into(),
+            label: "Code size".into(),
+            format: MarkerFieldFormat::Bytes,
+            searchable: false,
+        },
+    ]
+}
@@ -2,7 +2,7 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use fxprof_processed_profile::{
-    LibraryHandle, MarkerTiming, Profile, Symbol, SymbolTable, ThreadHandle,
+    LibraryHandle, MarkerTiming, Profile, StringHandle, Symbol, SymbolTable, ThreadHandle,
 };
 use linux_perf_data::jitdump::{JitDumpReader, JitDumpRecord, JitDumpRecordType};
 
@@ -72,8 +72,17 @@ impl JitDumpManager {
                     reader.header(),
                     profile,
                 );
-                self.processors
-                    .push(SingleJitDumpProcessor::new(reader, lib_handle, *thread));
+                let module_name = actual_path
+                    .file_name()
+                    .unwrap_or(actual_path.as_os_str())
+                    .to_string_lossy();
+                let module_name = profile.intern_string(&module_name);
+                self.processors.push(SingleJitDumpProcessor::new(
+                    reader,
+                    lib_handle,
+                    module_name,
+                    *thread,
+                ));
                 false // "Do not retain", i.e. remove from pending_jitdump_paths
             });
 
@@ -107,6 +116,8 @@ struct SingleJitDumpProcessor {
     /// Some() until a JIT_CODE_CLOSE record is encountered.
     reader: Option<JitDumpReader<std::fs::File>>,
     lib_handle: LibraryHandle,
+    /// The file name of the jitdump file, for the markers.
+    module_name: StringHandle,
     lib_mapping_ops: LibMappingOpQueue,
     symbols: Vec<Symbol>,
     thread_handle: ThreadHandle,
@@ -125,11 +136,13 @@ impl SingleJitDumpProcessor {
     pub fn new(
         reader: JitDumpReader<std::fs::File>,
         lib_handle: LibraryHandle,
+        module_name: StringHandle,
         thread_handle: ThreadHandle,
     ) -> Self {
         Self {
             reader: Some(reader),
             lib_handle,
+            module_name,
             lib_mapping_ops: Default::default(),
             symbols: Default::default(),
             thread_handle,
@@ -187,11 +200,16 @@ impl SingleJitDumpProcessor {
                     });
 
                     let timestamp = timestamp_converter.convert_time(raw_jitdump_record.timestamp);
-                    let symbol_name_handle = profile.intern_string(symbol_name);
+                    let marker = JitFunctionAddMarker {
+                        name: profile.intern_string(symbol_name),
+                        tier: profile.intern_string(JitCategoryManager::jit_tier(symbol_name)),
+                        module: self.module_name,
+                        code_size,
+                    };
                     profile.add_marker(
                         self.thread_handle,
                         MarkerTiming::Instant(timestamp),
-                        JitFunctionAddMarker(symbol_name_handle),
+                        marker,
                     );
 
                     let (lib_handle, relative_address_at_start) =
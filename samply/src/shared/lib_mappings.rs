@@ -32,7 +32,6 @@ impl LibMappingInfo {
         }
     }
 
-    #[allow(unused)]
     pub fn new_lib_with_category(lib_handle: LibraryHandle, category: CategoryPairHandle) -> Self {
         Self {
             lib_handle,
@@ -82,6 +81,8 @@ pub struct LibMappingsHierarchy {
     regular_libs: (LibMappings<LibMappingInfo>, LibMappingOpQueueIter),
     jitdumps: Vec<(LibMappings<LibMappingInfo>, LibMappingOpQueueIter)>,
     perf_map: Option<LibMappings<LibMappingInfo>>,
+    /// The fallback for anonymous executable memory which none of the above cover.
+    anonymous_code: Option<LibMappings<LibMappingInfo>>,
 }
 
 impl LibMappingsHierarchy {
@@ -90,6 +91,7 @@ impl LibMappingsHierarchy {
             regular_libs: (LibMappings::default(), regular_lib_mappings_ops.into_iter()),
             jitdumps: Vec::new(),
             perf_map: None,
+            anonymous_code: None,
         }
     }
 
@@ -102,6 +104,10 @@ impl LibMappingsHierarchy {
         self.perf_map = Some(mappings);
     }
 
+    pub fn add_anonymous_code_mappings(&mut self, mappings: LibMappings<LibMappingInfo>) {
+        self.anonymous_code = Some(mappings);
+    }
+
     pub fn process_ops(&mut self, timestamp: u64) {
         while let Some(op) = self.regular_libs.1.next_op_if_at_or_before(timestamp) {
             op.apply_to(&mut self.regular_libs.0);
@@ -127,6 +133,11 @@ impl LibMappingsHierarchy {
                 return Some(x);
             }
         }
+        if let Some(anonymous_code) = &self.anonymous_code {
+            if let Some(x) = anonymous_code.convert_address(address) {
+                return Some(x);
+            }
+        }
         None
     }
 }
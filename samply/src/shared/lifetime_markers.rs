@@ -0,0 +1,210 @@
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, MarkerTiming, Profile, StaticSchemaMarker, StringHandle, Timestamp,
+};
+
+/// The time range during which a process or thread existed, as far as the
+/// recording can tell.
+#[derive(Debug, Clone, Copy)]
+pub struct Lifetime {
+    /// The creation time, or the time at which we learned about the process or
+    /// thread if it already existed when the recording started.
+    start: Timestamp,
+    started_before_recording: bool,
+    end: Option<Timestamp>,
+}
+
+impl Lifetime {
+    pub fn new(start: Timestamp, started_before_recording: bool) -> Self {
+        Self {
+            start,
+            started_before_recording,
+            end: None,
+        }
+    }
+
+    pub fn start(&self) -> Timestamp {
+        self.start
+    }
+
+    pub fn set_end(&mut self, end: Timestamp) {
+        self.end = Some(end);
+    }
+
+    /// The timing for a marker covering this lifetime. Open ends extend the
+    /// marker to the start or the end of the profile.
+    pub fn marker_timing(&self) -> MarkerTiming {
+        match (self.started_before_recording, self.end) {
+            (false, Some(end)) => MarkerTiming::Interval(self.start, end),
+            (true, Some(end)) => MarkerTiming::IntervalEnd(end),
+            (_, None) => MarkerTiming::IntervalStart(self.start),
+        }
+    }
+}
+
+/// Covers the lifetime of a process. Added to the process's main thread.
+#[derive(Debug, Clone)]
+pub struct ProcessLifetimeMarker {
+    pub name: StringHandle,
+    pub pid: u32,
+    pub parent_pid: u32,
+    pub command_line: StringHandle,
+    /// Empty if the process was still running at the end of the recording.
+    pub exit_code: StringHandle,
+}
+
+impl StaticSchemaMarker for ProcessLifetimeMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "ProcessLifetime";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.name} ({marker.data.pid})".into()),
+            tooltip_label: Some("{marker.name} ({marker.data.pid})".into()),
+            table_label: Some(
+                "{marker.name} ({marker.data.pid}), parent {marker.data.parentPid}: {marker.data.commandLine}"
+                    .into(),
+            ),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "pid".into(),
+                    label: "PID".into(),
+                    format: MarkerFieldFormat::Integer,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "parentPid".into(),
+                    label: "Parent PID".into(),
+                    format: MarkerFieldFormat::Integer,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "commandLine".into(),
+                    label: "Command line".into(),
+                    format: MarkerFieldFormat::SanitizedString,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "exitCode".into(),
+                    label: "Exit code".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "The time during which the process was running.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        CategoryHandle::OTHER
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            2 => self.command_line,
+            3 => self.exit_code,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, field_index: u32) -> f64 {
+        match field_index {
+            0 => self.pid.into(),
+            1 => self.parent_pid.into(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Covers the lifetime of a thread. Added to the thread itself.
+#[derive(Debug, Clone)]
+pub struct ThreadLifetimeMarker {
+    pub name: StringHandle,
+    pub pid: u32,
+    pub tid: u32,
+}
+
+impl StaticSchemaMarker for ThreadLifetimeMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "ThreadLifetime";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.name} ({marker.data.tid})".into()),
+            tooltip_label: Some("{marker.name} ({marker.data.tid})".into()),
+            table_label: Some(
+                "{marker.name} (pid {marker.data.pid}, tid {marker.data.tid})".into(),
+            ),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "pid".into(),
+                    label: "PID".into(),
+                    format: MarkerFieldFormat::Integer,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "tid".into(),
+                    label: "TID".into(),
+                    format: MarkerFieldFormat::Integer,
+                    searchable: true,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "The time during which the thread was running.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        CategoryHandle::OTHER
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        unreachable!()
+    }
+
+    fn number_field_value(&self, field_index: u32) -> f64 {
+        match field_index {
+            0 => self.pid.into(),
+            1 => self.tid.into(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lifetime_marker_timing() {
+        let start = Timestamp::from_millis_since_reference(1.0);
+        let end = Timestamp::from_millis_since_reference(5.0);
+
+        let mut lifetime = Lifetime::new(start, false);
+        assert!(matches!(lifetime.marker_timing(), MarkerTiming::IntervalStart(s) if s == start));
+        lifetime.set_end(end);
+        assert!(
+            matches!(lifetime.marker_timing(), MarkerTiming::Interval(s, e) if s == start && e == end)
+        );
+
+        let mut lifetime = Lifetime::new(start, true);
+        assert!(matches!(lifetime.marker_timing(), MarkerTiming::IntervalStart(s) if s == start));
+        lifetime.set_end(end);
+        assert!(matches!(lifetime.marker_timing(), MarkerTiming::IntervalEnd(e) if e == end));
+    }
+}
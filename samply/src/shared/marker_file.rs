@@ -1,8 +1,20 @@
+//! Markers and counters which the profiled app writes to `marker-<pid>-<tid>.txt`
+//! files. Each line is one of:
+//!
+//!  - `<start> <end> <name>`: an interval marker on the thread,
+//!  - `counter <timestamp> <value> <name>`: a sample of the named counter,
+//!    e.g. `counter 1234567 42 queue depth`. Counters belong to the process;
+//!    the values are absolute, and samples of the same name in different
+//!    files of the process go into the same counter.
+//!
+//! Timestamps are raw timestamps, in the same clock as the samples.
+
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Lines};
 use std::path::{Path, PathBuf};
 
-use fxprof_processed_profile::Timestamp;
+use fxprof_processed_profile::{CounterHandle, ProcessHandle, Profile, Timestamp};
 
 use super::timestamp_converter::TimestampConverter;
 use super::utils::open_file_with_fallback;
@@ -14,6 +26,19 @@ pub struct MarkerSpan {
     pub name: String,
 }
 
+#[derive(Debug, Clone)]
+pub struct UserCounterSample {
+    pub timestamp: Timestamp,
+    pub value: f64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum MarkerFileEntry {
+    Span(MarkerSpan),
+    CounterSample(UserCounterSample),
+}
+
 fn process_marker_span_line(
     line: &str,
     timestamp_converter: &TimestampConverter,
@@ -34,6 +59,40 @@ fn process_marker_span_line(
     })
 }
 
+fn process_counter_sample_line(
+    line: &str,
+    timestamp_converter: &TimestampConverter,
+) -> Option<UserCounterSample> {
+    let mut split = line.splitn(3, ' ');
+    let timestamp = split.next()?;
+    let value = split.next()?;
+    let name = split.next()?.to_owned();
+    if name.is_empty() {
+        return None;
+    }
+    let timestamp = timestamp_converter.convert_time(timestamp.parse::<u64>().ok()?);
+    let value = value
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())?;
+    Some(UserCounterSample {
+        timestamp,
+        value,
+        name,
+    })
+}
+
+fn process_marker_file_line(
+    line: &str,
+    timestamp_converter: &TimestampConverter,
+) -> Option<MarkerFileEntry> {
+    match line.strip_prefix("counter ") {
+        Some(rest) => process_counter_sample_line(rest, timestamp_converter)
+            .map(MarkerFileEntry::CounterSample),
+        None => process_marker_span_line(line, timestamp_converter).map(MarkerFileEntry::Span),
+    }
+}
+
 pub struct MarkerFile {
     lines: Lines<BufReader<File>>,
     timestamp_converter: TimestampConverter,
@@ -49,11 +108,11 @@ impl MarkerFile {
 }
 
 impl Iterator for MarkerFile {
-    type Item = MarkerSpan;
+    type Item = MarkerFileEntry;
 
     fn next(&mut self) -> Option<Self::Item> {
         let line = self.lines.next()?.ok()?;
-        process_marker_span_line(&line, &self.timestamp_converter)
+        process_marker_file_line(&line, &self.timestamp_converter)
     }
 }
 
@@ -78,14 +137,106 @@ pub fn parse_marker_file_path(path: &Path) -> MarkerFileInfo {
     MarkerFileInfo { prefix, pid, tid }
 }
 
+/// The marker spans and counter samples from one marker file.
+#[derive(Debug, Clone, Default)]
+pub struct MarkerFileContents {
+    pub spans: Vec<MarkerSpan>,
+    pub counter_samples: Vec<UserCounterSample>,
+}
+
 pub fn get_markers(
     marker_file: &Path,
     lookup_dirs: &[PathBuf],
     timestamp_converter: TimestampConverter,
-) -> Result<Vec<MarkerSpan>, std::io::Error> {
+) -> Result<MarkerFileContents, std::io::Error> {
     let (f, _true_path) = open_file_with_fallback(marker_file, lookup_dirs)?;
     let marker_file = MarkerFile::parse(f, timestamp_converter);
-    let mut marker_spans: Vec<MarkerSpan> = marker_file.collect();
-    marker_spans.sort_by_key(|m| m.start_time);
-    Ok(marker_spans)
+    let mut contents = MarkerFileContents::default();
+    for entry in marker_file {
+        match entry {
+            MarkerFileEntry::Span(span) => contents.spans.push(span),
+            MarkerFileEntry::CounterSample(sample) => contents.counter_samples.push(sample),
+        }
+    }
+    contents.spans.sort_by_key(|m| m.start_time);
+    Ok(contents)
+}
+
+/// Adds a counter to the process for each counter name, with the samples of
+/// that name. The profile stores the change since the previous sample, so the
+/// absolute values are turned into deltas.
+pub fn add_user_counters(
+    profile: &mut Profile,
+    process: ProcessHandle,
+    mut samples: Vec<UserCounterSample>,
+) {
+    samples.sort_by_key(|sample| sample.timestamp);
+    let mut counters: HashMap<String, (CounterHandle, f64)> = HashMap::new();
+    for sample in samples {
+        let (counter, last_value) = counters.entry(sample.name).or_insert_with_key(|name| {
+            let counter =
+                profile.add_counter(process, name, "User", "Emitted by the profiled application");
+            (counter, 0.0)
+        });
+        profile.add_counter_sample(*counter, sample.timestamp, sample.value - *last_value, 1);
+        *last_value = sample.value;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{ReferenceTimestamp, SamplingInterval};
+
+    use super::*;
+
+    #[test]
+    fn counter_lines_become_counter_samples() {
+        let timestamp_converter = TimestampConverter {
+            reference_raw: 1000,
+            raw_to_ns_factor: 1,
+        };
+        let Some(MarkerFileEntry::Span(span)) =
+            process_marker_file_line("2000 3000 my span", &timestamp_converter)
+        else {
+            panic!("expected a marker span");
+        };
+        assert_eq!(span.name, "my span");
+        assert!(process_marker_file_line("counter 2000 abc depth", &timestamp_converter).is_none());
+
+        let samples: Vec<UserCounterSample> = [
+            "counter 3000000 5 queue depth",
+            "counter 1000000 2 queue depth",
+            "counter 2000000 0.5 hit rate",
+            "counter 4000000 1 queue depth",
+        ]
+        .iter()
+        .map(
+            |line| match process_marker_file_line(line, &timestamp_converter) {
+                Some(MarkerFileEntry::CounterSample(sample)) => sample,
+                _ => panic!("expected a counter sample"),
+            },
+        )
+        .collect();
+
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+        add_user_counters(&mut profile, process, samples);
+
+        let json = serde_json::to_value(&profile).unwrap();
+        let counters = json["counters"].as_array().unwrap();
+        assert_eq!(counters.len(), 2);
+        let queue_depth = counters
+            .iter()
+            .find(|counter| counter["name"] == "queue depth")
+            .unwrap();
+        assert_eq!(queue_depth["category"], "User");
+        assert_eq!(
+            queue_depth["samples"]["count"],
+            serde_json::json!([2.0, 3.0, -4.0])
+        );
+    }
 }
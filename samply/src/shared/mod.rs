@@ -1,4 +1,8 @@
+pub mod anonymous_code;
+pub mod collector_frames;
 pub mod context_switch;
+pub mod coreclr;
+pub mod cpu_core_types;
 pub mod ctrl_c;
 pub mod included_processes;
 pub mod jit_category_manager;
@@ -6,19 +10,34 @@ pub mod jit_function_add_marker;
 pub mod jit_function_recycler;
 pub mod jitdump_manager;
 pub mod lib_mappings;
+pub mod lifetime_markers;
 pub mod marker_file;
+pub mod namespace_categories;
 pub mod per_cpu;
 pub mod perf_map;
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+pub mod power_counters;
+pub mod process_groups;
+pub mod process_intervals;
 pub mod process_name;
 pub mod process_sample_data;
+pub mod profile_size_budget;
+pub mod ptr_auth;
+pub mod r2r_map;
+pub mod record_error;
 pub mod recording_props;
+pub mod recording_schedule;
+pub mod recording_summary;
 pub mod recycling;
+pub mod runtime_categories;
 pub mod save_profile;
 pub mod stack_converter;
 pub mod stack_depth_limiting_frame_iter;
 pub mod symbol_precog;
 pub mod symbol_props;
 pub mod synthetic_jit_library;
+pub mod thread_counts;
+pub mod time_zone;
 pub mod timestamp_converter;
 pub mod types;
 pub mod unresolved_samples;
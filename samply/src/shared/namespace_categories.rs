@@ -0,0 +1,275 @@
+//! Categories from function names, for `--categorize-by-namespace`: each frame
+//! gets a category for the Rust crate, C++ namespace or Java package of its
+//! function, so that the category graph shows which parts of the code the time
+//! is spent in.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use fxprof_processed_profile::{CategoryColor, LibraryInfo, Profile, Symbol, SymbolTable};
+
+/// The colors we hand out to namespaces, in order. Gray is left out because it's
+/// the color of the default category.
+const NAMESPACE_COLORS: [CategoryColor; 11] = [
+    CategoryColor::Blue,
+    CategoryColor::Green,
+    CategoryColor::Purple,
+    CategoryColor::Orange,
+    CategoryColor::Yellow,
+    CategoryColor::LightBlue,
+    CategoryColor::Magenta,
+    CategoryColor::Brown,
+    CategoryColor::Red,
+    CategoryColor::LightGreen,
+    CategoryColor::LightRed,
+];
+
+/// A user-specified category for functions with a name prefix, from
+/// `--namespace-category`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamespaceCategoryRule {
+    pub prefix: String,
+    pub category: String,
+}
+
+impl NamespaceCategoryRule {
+    /// Parses `PREFIX=CATEGORY`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let Some((prefix, category)) = s.split_once('=') else {
+            return Err(format!("expected PREFIX=CATEGORY, got {s:?}"));
+        };
+        if prefix.is_empty() || category.is_empty() {
+            return Err(format!("expected PREFIX=CATEGORY, got {s:?}"));
+        }
+        Ok(Self {
+            prefix: prefix.to_string(),
+            category: category.to_string(),
+        })
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match name.strip_prefix(self.prefix.as_str()) {
+            // Don't let "tokio" match "tokio_util::...".
+            Some(rest) => {
+                rest.is_empty()
+                    || self.prefix.ends_with([':', '.', '/'])
+                    || !rest.starts_with(is_path_char)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Assigns the categories for `--categorize-by-namespace`. The first matching
+/// rule wins; functions which don't match a rule get a category named after
+/// their top-level namespace. Each category gets its own color.
+#[derive(Debug, Default)]
+pub struct NamespaceCategorizer {
+    rules: Vec<NamespaceCategoryRule>,
+    colors: HashMap<String, CategoryColor>,
+}
+
+impl NamespaceCategorizer {
+    pub fn new(rules: Vec<NamespaceCategoryRule>) -> Self {
+        Self {
+            rules,
+            colors: HashMap::new(),
+        }
+    }
+
+    /// Returns the name and color of the category for a function, or `None` if
+    /// the function isn't in any namespace, e.g. for C functions.
+    pub fn category_for_function(&mut self, name: &str) -> Option<(String, CategoryColor)> {
+        let name = strip_type_prefix(name);
+        let category = match self.rules.iter().find(|rule| rule.matches(name)) {
+            Some(rule) => rule.category.clone(),
+            None => top_level_namespace(name)?.to_string(),
+        };
+        let color_count = self.colors.len();
+        let color = *self
+            .colors
+            .entry(category.clone())
+            .or_insert(NAMESPACE_COLORS[color_count % NAMESPACE_COLORS.len()]);
+        Some((category, color))
+    }
+}
+
+/// Gives the profile's frames categories by the namespace of their functions.
+///
+/// Native code only has function names in the profile if its library has a
+/// symbol table, so the libraries without one are symbolicated first, with
+/// the symbol files which can be found locally.
+pub fn categorize_profile_by_namespace(profile: &mut Profile, rules: &[NamespaceCategoryRule]) {
+    add_local_symbol_tables(profile);
+    let mut categorizer = NamespaceCategorizer::new(rules.to_vec());
+    profile.categorize_frames_by_name(|name| categorizer.category_for_function(name));
+}
+
+/// Sets a symbol table with the symbols of the used addresses for each library
+/// which doesn't have a symbol table yet.
+fn add_local_symbol_tables(profile: &mut Profile) {
+    let libs: Vec<(LibraryInfo, Vec<u32>)> = profile
+        .lib_used_rva_iter()
+        .filter(|(lib, rvas)| lib.symbol_table.is_none() && !rvas.is_empty())
+        .map(|(lib, rvas)| (lib.clone(), rvas.iter().copied().collect()))
+        .collect();
+    if libs.is_empty() {
+        return;
+    }
+
+    let config = wholesym::SymbolManagerConfig::new()
+        .use_spotlight(true)
+        .respect_nt_symbol_path(true);
+    let mut symbol_manager = wholesym::SymbolManager::with_config(config);
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    for (lib, rvas) in libs {
+        symbol_manager.add_known_library(wholesym::LibraryInfo {
+            name: Some(lib.name.clone()),
+            path: Some(lib.path.clone()),
+            debug_path: Some(lib.debug_path.clone()),
+            debug_id: Some(lib.debug_id),
+            arch: lib.arch.clone(),
+            debug_name: Some(lib.debug_name.clone()),
+            code_id: lib
+                .code_id
+                .as_deref()
+                .and_then(|id| wholesym::CodeId::from_str(id).ok()),
+        });
+        let symbols = rt.block_on(async {
+            let Ok(symbol_map) = symbol_manager
+                .load_symbol_map(&lib.debug_name, lib.debug_id)
+                .await
+            else {
+                return Vec::new();
+            };
+            let mut symbols = Vec::new();
+            for rva in rvas {
+                if let Some(info) = symbol_map
+                    .lookup(wholesym::LookupAddress::Relative(rva))
+                    .await
+                {
+                    symbols.push(Symbol {
+                        address: info.symbol.address,
+                        size: info.symbol.size,
+                        name: info.symbol.name,
+                    });
+                }
+            }
+            symbols
+        });
+        if !symbols.is_empty() {
+            // The library was added before, so this returns its existing handle.
+            let lib_handle = profile.add_lib(lib);
+            profile.set_lib_symbol_table(lib_handle, Arc::new(SymbolTable::new(symbols)));
+        }
+    }
+}
+
+fn is_path_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// Strips the parts in front of the type in names like `<mycrate::Foo as Debug>::fmt`
+/// or `<&dyn mycrate::Trait>::method`.
+fn strip_type_prefix(name: &str) -> &str {
+    let name = name.trim_start_matches(['<', '&', '*']);
+    let name = name.strip_prefix("dyn ").unwrap_or(name);
+    name.strip_prefix("mut ").unwrap_or(name)
+}
+
+/// Returns the top-level namespace of a function name: the crate of a Rust
+/// function, the outermost namespace of a C++ function, or the first two
+/// components of the package of a Java function (e.g. `com.example`), since
+/// the first component is usually just a domain suffix.
+fn top_level_namespace(name: &str) -> Option<&str> {
+    // JVM type descriptors, as in "Lcom/example/Foo;::bar".
+    let name = match name.strip_prefix('L') {
+        Some(descriptor) if descriptor.contains(['/', ';']) => descriptor,
+        _ => name,
+    };
+    let path_len = name
+        .find(|c: char| !is_path_char(c) && c != '.' && c != '/')
+        .unwrap_or(name.len());
+    let (path, rest) = name.split_at(path_len);
+    let has_method_separator = rest.starts_with("::") || rest.starts_with(";::");
+
+    if path.contains(['.', '/']) {
+        // A Java (or .NET) name. The last one or two components are the class and
+        // the method, and the rest is the package.
+        let components: Vec<&str> = path.split(['.', '/']).collect();
+        let class_and_method_len = if has_method_separator { 1 } else { 2 };
+        let package_len = components.len().checked_sub(class_and_method_len)?.min(2);
+        // Names like "memcpy.part.0" are C functions with compiler suffixes.
+        let is_compiler_suffix =
+            |c: &&str| c.is_empty() || c.starts_with(|c: char| c.is_ascii_digit());
+        if package_len == 0 || components.iter().any(is_compiler_suffix) {
+            return None;
+        }
+        let namespace_len = components[..package_len]
+            .iter()
+            .map(|c| c.len())
+            .sum::<usize>()
+            + package_len
+            - 1;
+        return Some(&path[..namespace_len]);
+    }
+
+    if rest.starts_with("::") && !path.is_empty() {
+        return Some(path);
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_top_level_namespace() {
+        let namespace = |name| top_level_namespace(strip_type_prefix(name));
+        assert_eq!(namespace("mycrate::module::function"), Some("mycrate"));
+        assert_eq!(namespace("alloc::vec::Vec<T>::push"), Some("alloc"));
+        assert_eq!(
+            namespace("<mycrate::Foo as core::fmt::Debug>::fmt"),
+            Some("mycrate")
+        );
+        assert_eq!(namespace("std::vector<int>::push_back(int)"), Some("std"));
+        assert_eq!(namespace("java.lang.String.charAt"), Some("java.lang"));
+        assert_eq!(namespace("com.example.app.Main::run"), Some("com.example"));
+        assert_eq!(namespace("Lcom/example/Foo;::bar"), Some("com/example"));
+        assert_eq!(namespace("Foo.bar"), None);
+        assert_eq!(namespace("memcpy"), None);
+        assert_eq!(namespace("memcpy.part.0"), None);
+        assert_eq!(namespace("JS:~foo"), None);
+        assert_eq!(namespace("0x1234"), None);
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut categorizer = NamespaceCategorizer::new(vec![
+            NamespaceCategoryRule::parse("tokio=Async").unwrap(),
+            NamespaceCategoryRule::parse("std::collections::=Collections").unwrap(),
+        ]);
+        assert_eq!(
+            categorizer.category_for_function("tokio::runtime::park"),
+            Some(("Async".to_string(), CategoryColor::Blue))
+        );
+        assert_eq!(
+            categorizer.category_for_function("tokio_util::codec::decode"),
+            Some(("tokio_util".to_string(), CategoryColor::Green))
+        );
+        assert_eq!(
+            categorizer.category_for_function("std::collections::HashMap<K,V>::get"),
+            Some(("Collections".to_string(), CategoryColor::Purple))
+        );
+        assert_eq!(
+            categorizer.category_for_function("tokio::task::spawn"),
+            Some(("Async".to_string(), CategoryColor::Blue))
+        );
+        assert_eq!(categorizer.category_for_function("memcpy"), None);
+        assert!(NamespaceCategoryRule::parse("tokio").is_err());
+        assert!(NamespaceCategoryRule::parse("=Async").is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Power tracks for `--power-counters`. The profiler front-end displays
+//! counters with the "power" category as power tracks; each counter sample
+//! holds the energy which was used since the previous sample, in picowatt-hours.
+
+use fxprof_processed_profile::{CounterHandle, ProcessHandle, Profile, Timestamp};
+
+/// Turns the readings of a cumulative energy meter into the samples of a
+/// power counter.
+#[derive(Debug)]
+pub struct PowerCounter {
+    counter: CounterHandle,
+    previous_energy_pwh: Option<u64>,
+}
+
+impl PowerCounter {
+    pub fn new(
+        profile: &mut Profile,
+        process: ProcessHandle,
+        name: &str,
+        description: &str,
+    ) -> Self {
+        let counter = profile.add_counter(process, name, "power", description);
+        Self {
+            counter,
+            previous_energy_pwh: None,
+        }
+    }
+
+    /// Adds a sample with the energy used since the previous reading. The first
+    /// reading only starts the track. If the meter went backwards, e.g. because
+    /// it was reset, the reading starts over from there.
+    pub fn add_reading(&mut self, profile: &mut Profile, timestamp: Timestamp, energy_pwh: u64) {
+        let delta = match self.previous_energy_pwh.replace(energy_pwh) {
+            Some(previous_energy_pwh) => energy_pwh.saturating_sub(previous_energy_pwh),
+            None => 0,
+        };
+        profile.add_counter_sample(self.counter, timestamp, delta as f64, 1);
+    }
+}
+
+/// One picowatt-hour is 3.6 nanojoules.
+pub fn nanojoules_to_picowatt_hours(nanojoules: u64) -> u64 {
+    (u128::from(nanojoules) * 10 / 36) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{ReferenceTimestamp, SamplingInterval};
+
+    use super::*;
+
+    #[test]
+    fn readings_become_deltas() {
+        assert_eq!(nanojoules_to_picowatt_hours(36_000), 10_000);
+
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let process = profile.add_process("app", 123, Timestamp::from_millis_since_reference(0.0));
+        let mut counter = PowerCounter::new(&mut profile, process, "Power", "Power of the app");
+        for (ms, energy_pwh) in [
+            (0.0, 1000),
+            (1.0, 1500),
+            (2.0, 1800),
+            (3.0, 200),
+            (4.0, 700),
+        ] {
+            let timestamp = Timestamp::from_millis_since_reference(ms);
+            counter.add_reading(&mut profile, timestamp, energy_pwh);
+        }
+
+        let json = serde_json::to_value(&profile).unwrap();
+        let counter = &json["counters"][0];
+        assert_eq!(counter["category"], "power");
+        assert_eq!(
+            counter["samples"]["count"],
+            serde_json::json!([0.0, 500.0, 300.0, 0.0, 500.0])
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Process group CPU tracks for `--process-group-cpu`: for each process which
+//! has child processes in the profile, a counter with the combined CPU usage of
+//! the process and all its descendants. This shows the cost of a whole build or
+//! a multi-process browser at a glance, which is hard to see from the separate
+//! tracks of dozens of short-lived processes.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use fxprof_processed_profile::{ProcessHandle, Profile};
+
+/// The CPU time is summed up into buckets of at least this duration...
+const MIN_BUCKET_DURATION: Duration = Duration::from_millis(10);
+/// ...but long profiles don't get more than this many buckets per group.
+const MAX_BUCKET_COUNT: usize = 2000;
+
+#[derive(Debug, Clone)]
+struct ProcessTreeEntry {
+    pid: u32,
+    parent_pid: Option<u32>,
+    handle: ProcessHandle,
+    name: String,
+}
+
+/// The parent / child relationships of the processes in the profile.
+#[derive(Debug, Default)]
+pub struct ProcessTree {
+    entries: Vec<ProcessTreeEntry>,
+}
+
+/// A process and the profile processes of its descendants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessGroup {
+    pub root: ProcessHandle,
+    pub root_pid: u32,
+    pub root_name: String,
+    /// The root process and its descendants. Processes which were merged into
+    /// the same profile process are only listed once.
+    pub processes: Vec<ProcessHandle>,
+    pub descendant_count: usize,
+}
+
+impl ProcessTree {
+    /// Adds a process. If a pid is added more than once because it was reused,
+    /// the process which was added last is the parent of the children with
+    /// this parent pid.
+    pub fn add_process(
+        &mut self,
+        pid: u32,
+        parent_pid: Option<u32>,
+        handle: ProcessHandle,
+        name: &str,
+    ) {
+        self.entries.push(ProcessTreeEntry {
+            pid,
+            parent_pid,
+            handle,
+            name: name.to_owned(),
+        });
+    }
+
+    /// Returns a group for each process which has descendants and whose parent
+    /// isn't in the profile.
+    pub fn groups(&self) -> Vec<ProcessGroup> {
+        let mut index_by_pid: HashMap<u32, usize> = HashMap::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            index_by_pid.insert(entry.pid, index);
+        }
+        let parent_index = |index: usize| {
+            let parent_pid = self.entries[index].parent_pid?;
+            index_by_pid
+                .get(&parent_pid)
+                .copied()
+                .filter(|&parent_index| parent_index != index)
+        };
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); self.entries.len()];
+        let mut roots = Vec::new();
+        for index in 0..self.entries.len() {
+            match parent_index(index) {
+                Some(parent_index) => children[parent_index].push(index),
+                None => roots.push(index),
+            }
+        }
+
+        let mut groups = Vec::new();
+        for root in roots {
+            // Processes which aren't reachable from a root, i.e. parent pid
+            // cycles, are never visited, so this terminates.
+            let mut descendants = Vec::new();
+            let mut stack = children[root].clone();
+            while let Some(index) = stack.pop() {
+                descendants.push(index);
+                stack.extend_from_slice(&children[index]);
+            }
+            if descendants.is_empty() {
+                continue;
+            }
+            let root_entry = &self.entries[root];
+            let mut processes = vec![root_entry.handle];
+            for index in &descendants {
+                let handle = self.entries[*index].handle;
+                if !processes.contains(&handle) {
+                    processes.push(handle);
+                }
+            }
+            groups.push(ProcessGroup {
+                root: root_entry.handle,
+                root_pid: root_entry.pid,
+                root_name: root_entry.name.clone(),
+                processes,
+                descendant_count: descendants.len(),
+            });
+        }
+        groups
+    }
+
+    /// Adds a "CPU (process group)" counter to the root process of each group.
+    /// Call this after all samples have been added to the profile.
+    pub fn add_process_group_counters(&self, profile: &mut Profile) {
+        for group in self.groups() {
+            let buckets = profile.cpu_time_per_bucket(
+                &group.processes,
+                MIN_BUCKET_DURATION,
+                MAX_BUCKET_COUNT,
+            );
+            if buckets.iter().all(|(_, cpu_time)| cpu_time.is_zero()) {
+                continue;
+            }
+            let description = format!(
+                "CPU time of {} (pid {}) and its {} descendant processes",
+                group.root_name, group.root_pid, group.descendant_count
+            );
+            let counter =
+                profile.add_counter(group.root, "CPU (process group)", "CPU", &description);
+            for (timestamp, cpu_time) in buckets {
+                let cpu_ms = cpu_time.as_secs_f64() * 1000.0;
+                profile.add_counter_sample(counter, timestamp, cpu_ms, 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{ReferenceTimestamp, SamplingInterval, Timestamp};
+
+    use super::*;
+
+    #[test]
+    fn groups() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let mut handle = |name: &str, pid: u32| {
+            profile.add_process(name, pid, Timestamp::from_millis_since_reference(0.0))
+        };
+        let make = handle("make", 10);
+        let cc1 = handle("cc", 11);
+        let ld = handle("ld", 12);
+        let as_ = handle("as", 13);
+        let shell = handle("sh", 20);
+        let cc2 = handle("cc", 11);
+
+        let mut tree = ProcessTree::default();
+        tree.add_process(10, Some(1), make, "make");
+        tree.add_process(11, Some(10), cc1, "cc");
+        tree.add_process(13, Some(11), as_, "as");
+        tree.add_process(12, Some(10), ld, "ld");
+        // A process without children doesn't get a group.
+        tree.add_process(20, None, shell, "sh");
+        // Pid 11 is reused, so pid 13 becomes a child of the second "cc".
+        // Two processes with each other as parents are ignored.
+        tree.add_process(11, Some(10), cc2, "cc");
+        tree.add_process(30, Some(31), shell, "a");
+        tree.add_process(31, Some(30), shell, "b");
+
+        let groups = tree.groups();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].root, make);
+        assert_eq!(groups[0].root_pid, 10);
+        assert_eq!(groups[0].descendant_count, 4);
+        let mut processes = groups[0].processes.clone();
+        processes.sort();
+        assert_eq!(processes, vec![make, cc1, ld, as_, cc2]);
+    }
+}
@@ -0,0 +1,115 @@
+//! Lower sampling rates for some processes, from `--interval-for`.
+//!
+//! The processes are sampled at the main rate, and only every n-th sample of
+//! a matching process is kept, with a weight of n. This way the call tree
+//! totals of all processes stay comparable.
+
+use std::time::Duration;
+
+use fxprof_processed_profile::Profile;
+
+/// For each `--interval-for` entry, the n of "every n-th sample".
+#[derive(Debug, Clone, Default)]
+pub struct ProcessSampleStrides {
+    interval: Duration,
+    strides: Vec<(String, u32)>,
+}
+
+impl ProcessSampleStrides {
+    /// `interval` is the sampling interval of all other processes. The process
+    /// intervals are rounded to a multiple of it.
+    pub fn new(process_intervals: &[(String, Duration)], interval: Duration) -> Self {
+        let strides = process_intervals
+            .iter()
+            .map(|(name_substring, process_interval)| {
+                let stride = process_interval.as_secs_f64() / interval.as_secs_f64();
+                (name_substring.clone(), (stride.round() as u32).max(1))
+            })
+            .collect();
+        Self { interval, strides }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strides.is_empty()
+    }
+
+    /// Returns n if only every n-th sample of the process with the given name
+    /// should be kept. The first entry whose string is contained in the name
+    /// wins.
+    pub fn stride_for_process(&self, process_name: &str) -> u32 {
+        self.strides
+            .iter()
+            .find(|(name_substring, _)| process_name.contains(name_substring.as_str()))
+            .map_or(1, |(_, stride)| *stride)
+    }
+
+    /// Records the effective sampling interval of a process in the profile's
+    /// metadata.
+    pub fn add_meta_info(&self, profile: &mut Profile, process_name: &str, pid: u32, stride: u32) {
+        let interval = self.interval * stride;
+        profile.add_extra_meta_info(
+            "Sampling intervals",
+            &format!("{process_name} (pid {pid})"),
+            &format!("{interval:?}, sample weight {stride}"),
+        );
+    }
+}
+
+/// Picks the samples of a process which are kept.
+#[derive(Debug, Clone, Default)]
+pub struct SampleThinner {
+    sample_count: u64,
+}
+
+impl SampleThinner {
+    /// Returns the weight for the next sample of the process: `stride` for
+    /// every `stride`-th sample, starting with the first one, and `None` for
+    /// the samples in between, which should be dropped.
+    pub fn next_sample(&mut self, stride: u32) -> Option<i32> {
+        let stride = stride.max(1);
+        let index = self.sample_count;
+        self.sample_count += 1;
+        index
+            .is_multiple_of(u64::from(stride))
+            .then_some(stride as i32)
+    }
+
+    /// Whether none of the process's samples have been seen yet.
+    pub fn is_first_sample(&self) -> bool {
+        self.sample_count == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strides_are_rounded_multiples_of_the_interval() {
+        let strides = ProcessSampleStrides::new(
+            &[
+                ("mds".to_string(), Duration::from_millis(100)),
+                ("md".to_string(), Duration::from_micros(2600)),
+                ("fast".to_string(), Duration::from_micros(100)),
+            ],
+            Duration::from_millis(1),
+        );
+        assert_eq!(strides.stride_for_process("mds_stores"), 100);
+        assert_eq!(strides.stride_for_process("mdworker"), 3);
+        assert_eq!(strides.stride_for_process("fastfetch"), 1);
+        assert_eq!(strides.stride_for_process("firefox"), 1);
+    }
+
+    #[test]
+    fn every_nth_sample_is_kept() {
+        let mut thinner = SampleThinner::default();
+        assert!(thinner.is_first_sample());
+        let weights: Vec<_> = (0..7).map(|_| thinner.next_sample(3)).collect();
+        assert_eq!(weights, [Some(3), None, None, Some(3), None, None, Some(3)]);
+        assert!(!thinner.is_first_sample());
+
+        let mut thinner = SampleThinner::default();
+        assert_eq!(thinner.next_sample(1), Some(1));
+        assert_eq!(thinner.next_sample(1), Some(1));
+    }
+}
@@ -5,6 +5,7 @@ use fxprof_processed_profile::{
 };
 
 use super::lib_mappings::{LibMappingInfo, LibMappingOpQueue, LibMappingsHierarchy};
+use super::ptr_auth::PtrAuthStripper;
 use super::stack_converter::StackConverter;
 use super::stack_depth_limiting_frame_iter::StackDepthLimitingFrameIter;
 use super::types::StackFrame;
@@ -34,6 +35,7 @@ pub struct ProcessSampleData {
     regular_lib_mapping_op_queue: LibMappingOpQueue,
     jitdump_lib_mapping_op_queues: Vec<LibMappingOpQueue>,
     perf_map_mappings: Option<LibMappings<LibMappingInfo>>,
+    anonymous_code_mappings: Option<LibMappings<LibMappingInfo>>,
     marker_spans: Vec<MarkerSpanOnThread>,
 }
 
@@ -43,6 +45,7 @@ impl ProcessSampleData {
         regular_lib_mapping_op_queue: LibMappingOpQueue,
         jitdump_lib_mapping_op_queues: Vec<LibMappingOpQueue>,
         perf_map_mappings: Option<LibMappings<LibMappingInfo>>,
+        anonymous_code_mappings: Option<LibMappings<LibMappingInfo>>,
         marker_spans: Vec<MarkerSpanOnThread>,
     ) -> Self {
         Self {
@@ -50,6 +53,7 @@ impl ProcessSampleData {
             regular_lib_mapping_op_queue,
             jitdump_lib_mapping_op_queues,
             perf_map_mappings,
+            anonymous_code_mappings,
             marker_spans,
         }
     }
@@ -58,12 +62,12 @@ impl ProcessSampleData {
         self.unresolved_samples.is_empty()
     }
 
-    #[allow(clippy::too_many_arguments)]
     pub fn flush_samples_to_profile(
         self,
         profile: &mut Profile,
         user_category: CategoryPairHandle,
         kernel_category: CategoryPairHandle,
+        ptr_auth_stripper: PtrAuthStripper,
         stack_frame_scratch_buf: &mut Vec<StackFrame>,
         stacks: &UnresolvedStacks,
     ) {
@@ -72,8 +76,57 @@ impl ProcessSampleData {
             regular_lib_mapping_op_queue,
             jitdump_lib_mapping_op_queues,
             perf_map_mappings,
+            anonymous_code_mappings,
             marker_spans,
         } = self;
+        let mut flusher = ProcessSampleFlusher::new(
+            profile,
+            regular_lib_mapping_op_queue,
+            jitdump_lib_mapping_op_queues,
+            perf_map_mappings,
+            anonymous_code_mappings,
+            user_category,
+            kernel_category,
+            ptr_auth_stripper,
+        );
+        flusher.flush_samples(profile, unresolved_samples, stack_frame_scratch_buf, stacks);
+
+        for marker in marker_spans {
+            let marker_name_string_index = profile.intern_string(&marker.name);
+            profile.add_marker(
+                marker.thread_handle,
+                MarkerTiming::Interval(marker.start_time, marker.end_time),
+                SimpleMarker(marker_name_string_index),
+            );
+        }
+    }
+}
+
+/// Resolves the stacks of a process's samples against its lib mappings and
+/// adds the samples to the profile.
+///
+/// Normally this happens once at the end, but it can also be done in batches
+/// if all lib mappings are known up front, so that the samples don't all
+/// have to be kept in memory until the end.
+#[derive(Debug)]
+pub struct ProcessSampleFlusher {
+    lib_mappings_hierarchy: LibMappingsHierarchy,
+    stack_converter: StackConverter,
+    user_category: CategoryPairHandle,
+}
+
+impl ProcessSampleFlusher {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        profile: &mut Profile,
+        regular_lib_mapping_op_queue: LibMappingOpQueue,
+        jitdump_lib_mapping_op_queues: Vec<LibMappingOpQueue>,
+        perf_map_mappings: Option<LibMappings<LibMappingInfo>>,
+        anonymous_code_mappings: Option<LibMappings<LibMappingInfo>>,
+        user_category: CategoryPairHandle,
+        kernel_category: CategoryPairHandle,
+        ptr_auth_stripper: PtrAuthStripper,
+    ) -> Self {
         let mut lib_mappings_hierarchy = LibMappingsHierarchy::new(regular_lib_mapping_op_queue);
         for jitdump_lib_mapping_ops in jitdump_lib_mapping_op_queues {
             lib_mappings_hierarchy.add_jitdump_lib_mappings_ops(jitdump_lib_mapping_ops);
@@ -81,45 +134,78 @@ impl ProcessSampleData {
         if let Some(perf_map_mappings) = perf_map_mappings {
             lib_mappings_hierarchy.add_perf_map_mappings(perf_map_mappings);
         }
-        let mut stack_converter = StackConverter::new(user_category, kernel_category);
-        let samples = unresolved_samples.into_inner();
-        for sample in samples {
-            lib_mappings_hierarchy.process_ops(sample.timestamp_mono);
+        if let Some(anonymous_code_mappings) = anonymous_code_mappings {
+            lib_mappings_hierarchy.add_anonymous_code_mappings(anonymous_code_mappings);
+        }
+        let stack_converter =
+            StackConverter::new(profile, user_category, kernel_category, ptr_auth_stripper);
+        Self {
+            lib_mappings_hierarchy,
+            stack_converter,
+            user_category,
+        }
+    }
+
+    /// Adds the samples to the profile. Samples must be flushed in timestamp
+    /// order, because the lib mapping ops are only ever applied forwards.
+    pub fn flush_samples(
+        &mut self,
+        profile: &mut Profile,
+        unresolved_samples: UnresolvedSamples,
+        stack_frame_scratch_buf: &mut Vec<StackFrame>,
+        stacks: &UnresolvedStacks,
+    ) {
+        for sample in unresolved_samples.into_samples_and_markers() {
+            self.lib_mappings_hierarchy
+                .process_ops(sample.timestamp_mono);
             let UnresolvedSampleOrMarker {
                 thread_handle,
                 timestamp,
                 stack,
                 sample_or_marker,
                 extra_label_frame,
+                extra_leaf_frame,
                 ..
             } = sample;
 
             stack_frame_scratch_buf.clear();
             stacks.convert_back(stack, stack_frame_scratch_buf);
-            let frames = stack_converter.convert_stack(
-                stack_frame_scratch_buf,
-                &lib_mappings_hierarchy,
-                extra_label_frame,
-            );
-            let frames = StackDepthLimitingFrameIter::new(profile, frames, user_category);
+            let frames = self
+                .stack_converter
+                .convert_stack(
+                    stack_frame_scratch_buf,
+                    &self.lib_mappings_hierarchy,
+                    extra_label_frame,
+                )
+                .chain(extra_leaf_frame);
+            let frames = StackDepthLimitingFrameIter::new(profile, frames, self.user_category);
             match sample_or_marker {
-                SampleOrMarker::Sample(SampleData { cpu_delta, weight }) => {
+                SampleOrMarker::Sample(SampleData {
+                    cpu_delta,
+                    weight,
+                    cpu: None,
+                }) => {
                     profile.add_sample(thread_handle, timestamp, frames, cpu_delta, weight);
                 }
+                SampleOrMarker::Sample(SampleData {
+                    cpu_delta,
+                    weight,
+                    cpu: Some(cpu),
+                }) => {
+                    profile.add_sample_on_cpu(
+                        thread_handle,
+                        timestamp,
+                        frames,
+                        cpu_delta,
+                        weight,
+                        cpu,
+                    );
+                }
                 SampleOrMarker::MarkerHandle(mh) => {
                     profile.set_marker_stack(thread_handle, mh, frames);
                 }
             }
         }
-
-        for marker in marker_spans {
-            let marker_name_string_index = profile.intern_string(&marker.name);
-            profile.add_marker(
-                marker.thread_handle,
-                MarkerTiming::Interval(marker.start_time, marker.end_time),
-                SimpleMarker(marker_name_string_index),
-            );
-        }
     }
 }
 
@@ -401,3 +487,126 @@ impl StaticSchemaMarker for SimpleMarker {
         unreachable!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{
+        CategoryHandle, CpuDelta, ReferenceTimestamp, SamplingInterval,
+    };
+
+    use super::*;
+    use crate::shared::types::StackMode;
+
+    /// Returns the function names of each sample's stack, from the root.
+    fn sample_stacks(thread: &serde_json::Value) -> Vec<Vec<String>> {
+        let stack_table = &thread["stackTable"];
+        let func_of_frame = &thread["frameTable"]["func"];
+        let name_of_func = &thread["funcTable"]["name"];
+        let strings = &thread["stringArray"];
+        let samples = thread["samples"]["stack"].as_array().unwrap();
+        samples
+            .iter()
+            .map(|stack| {
+                let mut names = Vec::new();
+                let mut stack = stack.as_u64();
+                while let Some(index) = stack {
+                    let index = index as usize;
+                    let frame = stack_table["frame"][index].as_u64().unwrap() as usize;
+                    let func = func_of_frame[frame].as_u64().unwrap() as usize;
+                    let name = name_of_func[func].as_u64().unwrap() as usize;
+                    names.push(strings[name].as_str().unwrap().to_string());
+                    stack = stack_table["prefix"][index].as_u64();
+                }
+                names.reverse();
+                names
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stacks_survive_between_batches() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let ts = Timestamp::from_millis_since_reference;
+        let process = profile.add_process("app", 1, ts(0.0));
+        let thread = profile.add_thread(process, 1, ts(0.0), true);
+        let category = CategoryHandle::OTHER.into();
+        let mut flusher = ProcessSampleFlusher::new(
+            &mut profile,
+            LibMappingOpQueue::default(),
+            Vec::new(),
+            None,
+            None,
+            category,
+            category,
+            PtrAuthStripper::NONE,
+        );
+        let frame = |addr| StackFrame::InstructionPointer(addr, StackMode::User);
+        let mut scratch_buf = Vec::new();
+
+        // Each batch has its own stack table, like in the sample pass of a
+        // two-pass import, so both stacks get the same handle.
+        let batches = [(1, vec![frame(0x10), frame(0x11)]), (2, vec![frame(0x20)])];
+        for (timestamp_mono, stack) in batches {
+            let mut stacks = UnresolvedStacks::default();
+            let stack = stacks.convert(stack.into_iter().rev());
+            let mut samples = UnresolvedSamples::default();
+            let timestamp = ts(timestamp_mono as f64);
+            samples.add_sample(
+                thread,
+                timestamp,
+                timestamp_mono,
+                stack,
+                CpuDelta::ZERO,
+                1,
+                None,
+            );
+            flusher.flush_samples(&mut profile, samples, &mut scratch_buf, &stacks);
+        }
+
+        let json = serde_json::to_value(&profile).unwrap();
+        assert_eq!(
+            sample_stacks(&json["threads"][0]),
+            vec![vec!["0x11", "0x10"], vec!["0x20"]]
+        );
+    }
+
+    #[test]
+    fn sample_cpus_reach_the_profile() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let ts = Timestamp::from_millis_since_reference;
+        let process = profile.add_process("app", 1, ts(0.0));
+        let thread = profile.add_thread(process, 1, ts(0.0), true);
+        let category = CategoryHandle::OTHER.into();
+        let mut flusher = ProcessSampleFlusher::new(
+            &mut profile,
+            LibMappingOpQueue::default(),
+            Vec::new(),
+            None,
+            None,
+            category,
+            category,
+            PtrAuthStripper::NONE,
+        );
+        let mut stacks = UnresolvedStacks::default();
+        let stack =
+            stacks.convert([StackFrame::InstructionPointer(0x10, StackMode::User)].into_iter());
+        let mut samples = UnresolvedSamples::default();
+        samples.add_sample_on_cpu(thread, ts(1.0), 1, stack, CpuDelta::ZERO, 1, 3);
+        samples.add_sample(thread, ts(2.0), 2, stack, CpuDelta::ZERO, 1, None);
+        flusher.flush_samples(&mut profile, samples, &mut Vec::new(), &stacks);
+
+        let json = serde_json::to_value(&profile).unwrap();
+        assert_eq!(
+            json["threads"][0]["samples"]["cpu"],
+            serde_json::json!([3, null])
+        );
+    }
+}
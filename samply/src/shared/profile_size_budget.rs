@@ -0,0 +1,86 @@
+use std::io::Write;
+
+use fxprof_processed_profile::Profile;
+
+/// We stop downsampling once we've reached this factor. At this point the
+/// samples are unlikely to be the main contributor to the profile size.
+const MAX_DOWNSAMPLING_FACTOR: usize = 1024;
+
+/// A writer which discards its input and only counts the number of bytes.
+struct ByteCounter(u64);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the size of the profile's JSON serialization, in bytes.
+fn serialized_size(profile: &Profile) -> u64 {
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, profile).expect("Counting bytes should never fail");
+    counter.0
+}
+
+/// Makes sure that the profile JSON (before compression) is no larger than
+/// `max_size` bytes, if possible.
+///
+/// First, marker stacks are dropped, then the marker field values. If the profile is
+/// still too large, the samples are repeatedly downsampled by a factor of two, with
+/// the kept samples absorbing the weight of the dropped samples. If even that isn't
+/// enough, a warning is printed and the profile is kept at the smallest size reached.
+pub fn shrink_profile_to_size_budget(profile: &mut Profile, max_size: u64) {
+    let original_size = serialized_size(profile);
+    if original_size <= max_size {
+        return;
+    }
+
+    let mut dropped = vec!["marker stacks"];
+    profile.clear_marker_stacks();
+    let mut size = serialized_size(profile);
+
+    if size > max_size {
+        profile.clear_marker_fields();
+        size = serialized_size(profile);
+        dropped.push("marker fields");
+    }
+
+    let mut factor = 1;
+    while size > max_size && factor < MAX_DOWNSAMPLING_FACTOR {
+        let sample_count_before = profile.sample_count();
+        profile.downsample(2);
+        if profile.sample_count() == sample_count_before {
+            break;
+        }
+        factor *= 2;
+        size = serialized_size(profile);
+    }
+
+    eprintln!(
+        "The profile was {:.1} MB, which exceeds the size budget of {:.1} MB. Dropped {}{}; the profile is now {:.1} MB.",
+        to_megabytes(original_size),
+        to_megabytes(max_size),
+        dropped.join(" and "),
+        if factor > 1 {
+            format!(" and downsampled the samples by a factor of {factor}")
+        } else {
+            String::new()
+        },
+        to_megabytes(size),
+    );
+    if size > max_size {
+        eprintln!(
+            "Warning: Could not shrink the profile below the size budget of {:.1} MB, even after downsampling by a factor of {factor}.",
+            to_megabytes(max_size),
+        );
+    }
+}
+
+fn to_megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
@@ -0,0 +1,72 @@
+//! Stripping of pointer authentication codes (PACs) from arm64 code addresses.
+//!
+//! With pointer authentication, which Apple Silicon and recent arm64 Linux and
+//! Windows builds use, return addresses are signed before they're pushed to the
+//! stack: a PAC is stored in the bits above the virtual address size. These
+//! bits need to be removed before an address can be classified as a user or
+//! kernel address and looked up in a library, otherwise the frame shows up as
+//! an unsymbolicated address far outside of any library.
+
+/// Removes the PAC from the addresses of one architecture. On architectures
+/// without pointer authentication, addresses are left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtrAuthStripper {
+    /// The bits which are part of the address. All other bits may hold a PAC.
+    address_mask: u64,
+}
+
+impl PtrAuthStripper {
+    /// For architectures without pointer authentication.
+    pub const NONE: Self = Self {
+        address_mask: u64::MAX,
+    };
+
+    /// For arm64 on Linux and Windows, which use 48-bit virtual addresses.
+    pub const ARM64: Self = Self::arm64(48);
+
+    /// For arm64 with the given virtual address size, e.g. 47 bits on macOS.
+    pub const fn arm64(virtual_address_bits: u32) -> Self {
+        Self {
+            address_mask: u64::MAX >> (64 - virtual_address_bits),
+        }
+    }
+
+    /// The bits which are kept from user-space addresses.
+    pub fn address_mask(&self) -> u64 {
+        self.address_mask
+    }
+
+    /// Returns the address without the PAC. Bit 55 selects between the user and
+    /// the kernel half of the address space and is never part of the PAC, so it
+    /// decides whether the PAC bits are cleared (user addresses) or set (kernel
+    /// addresses).
+    pub fn strip(&self, address: u64) -> u64 {
+        if self.address_mask == u64::MAX {
+            address
+        } else if address & (1 << 55) == 0 {
+            address & self.address_mask
+        } else {
+            address | !self.address_mask
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strips_pac_bits() {
+        let stripper = PtrAuthStripper::ARM64;
+        assert_eq!(stripper.strip(0x002d_aaaa_0001_2344), 0x0000_aaaa_0001_2344);
+        assert_eq!(stripper.strip(0xa37f_ffff_8a01_5678), 0x0000_ffff_8a01_5678);
+        assert_eq!(stripper.strip(0xffff_8000_1000_0000), 0xffff_8000_1000_0000);
+        assert_eq!(stripper.strip(0xc0a9_8000_1000_0000), 0xffff_8000_1000_0000);
+
+        let stripper = PtrAuthStripper::arm64(47);
+        assert_eq!(stripper.strip(0x6a54_8001_0000_1234), 0x0000_0001_0000_1234);
+
+        let stripper = PtrAuthStripper::NONE;
+        assert_eq!(stripper.strip(0xa37f_ffff_8a01_5678), 0xa37f_ffff_8a01_5678);
+    }
+}
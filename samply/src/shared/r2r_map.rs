@@ -0,0 +1,179 @@
+//! Symbols for .NET ReadyToRun (R2R) images from the perf maps which crossgen2
+//! writes with `--perfmap` next to the compiled assembly, e.g.
+//! `System.Private.CoreLib.ni.r2rmap` for `System.Private.CoreLib.dll`.
+//!
+//! Each line is `<rva> <size> <name>`, with the RVA and the size in hex. Since
+//! format version 1, the file starts with pseudo entries whose RVA is one of
+//! the tokens below, and whose name is the image's signature, the format
+//! version, or the target OS, architecture and ABI.
+
+use std::path::{Path, PathBuf};
+
+use fxprof_processed_profile::{Symbol, SymbolTable};
+
+const PSEUDO_RVA_SIGNATURE: u32 = 0xffff_ffff;
+const PSEUDO_RVA_VERSION: u32 = 0xffff_fffe;
+/// The target OS, architecture and ABI come after the version, down to this token.
+const PSEUDO_RVA_LAST: u32 = 0xffff_fffb;
+
+/// The highest format version which we understand.
+const MAX_SUPPORTED_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct R2rMap {
+    /// The signature of the image the map was generated for, if the map has one.
+    pub signature: Option<String>,
+    pub symbols: Vec<Symbol>,
+}
+
+impl R2rMap {
+    /// Returns `None` if the map has a format version we don't understand.
+    pub fn parse(content: &str) -> Option<Self> {
+        let mut signature = None;
+        let mut symbols = Vec::new();
+        for line in content.lines() {
+            let mut split = line.trim_end().splitn(3, ' ');
+            let (Some(rva), Some(size), Some(name)) = (split.next(), split.next(), split.next())
+            else {
+                continue;
+            };
+            let (Ok(rva), Ok(size)) = (u32::from_str_radix(rva, 16), u32::from_str_radix(size, 16))
+            else {
+                continue;
+            };
+            match rva {
+                PSEUDO_RVA_SIGNATURE => signature = Some(name.to_owned()),
+                PSEUDO_RVA_VERSION => {
+                    if name.parse::<u32>().ok()? > MAX_SUPPORTED_VERSION {
+                        return None;
+                    }
+                }
+                PSEUDO_RVA_LAST..=u32::MAX => {}
+                _ if name.is_empty() => {}
+                _ => symbols.push(Symbol {
+                    address: rva,
+                    size: Some(size),
+                    name: name.to_owned(),
+                }),
+            }
+        }
+        Some(Self { signature, symbols })
+    }
+
+    pub fn into_symbol_table(self) -> SymbolTable {
+        SymbolTable::new(self.symbols)
+    }
+}
+
+/// The paths where crossgen2 puts the perf map of the image at `image_path`.
+fn r2r_map_paths(image_path: &Path) -> Vec<PathBuf> {
+    let Some(stem) = image_path.file_stem().and_then(|stem| stem.to_str()) else {
+        return Vec::new();
+    };
+    let stem = stem.strip_suffix(".ni").unwrap_or(stem);
+    vec![
+        image_path.with_file_name(format!("{stem}.ni.r2rmap")),
+        image_path.with_file_name(format!("{stem}.r2rmap")),
+    ]
+}
+
+/// Returns the signature which the runtime logged for the image at
+/// `image_path` in `/tmp/perfinfo-<pid>.map`, whose lines look like
+/// `ImageLoad;<path>;<signature>;`.
+pub fn perfinfo_image_signature(pid: u32, image_path: &str) -> Option<String> {
+    let content = std::fs::read_to_string(format!("/tmp/perfinfo-{pid}.map")).ok()?;
+    content.lines().find_map(|line| {
+        let mut split = line.split(';');
+        if split.next()? != "ImageLoad" || split.next()? != image_path {
+            return None;
+        }
+        split.next().map(str::to_owned)
+    })
+}
+
+/// Whether two signatures are the same GUID, ignoring braces, dashes and case.
+pub fn signatures_match(a: &str, b: &str) -> bool {
+    let normalize = |signature: &str| -> String {
+        signature
+            .chars()
+            .filter(|c| !matches!(c, '{' | '}' | '-'))
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Loads the perf map for the R2R image at `image_path`, if there is one.
+pub fn try_load_r2r_map(image_path: &Path) -> Option<R2rMap> {
+    r2r_map_paths(image_path).into_iter().find_map(|path| {
+        let content = std::fs::read_to_string(&path).ok()?;
+        let map = R2rMap::parse(&content);
+        if map.is_none() {
+            eprintln!("Ignoring {path:?}, it has an unsupported format version.");
+        }
+        map
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_r2r_map() {
+        let map = R2rMap::parse(
+            "FFFFFFFF 00 9EBCE3F2E4B64F6F8B1C5B0B9E7E6F5D\n\
+             FFFFFFFE 00 1\n\
+             FFFFFFFD 00 1\n\
+             FFFFFFFC 00 1\n\
+             FFFFFFFB 00 0\n\
+             00011E60 3F [System.Private.CoreLib]System.Object::GetType()\n\
+             00011EA0 1A4 [System.Private.CoreLib]System.String::Concat(string,string)\n\
+             garbage\n",
+        )
+        .unwrap();
+        assert_eq!(
+            map.signature.as_deref(),
+            Some("9EBCE3F2E4B64F6F8B1C5B0B9E7E6F5D")
+        );
+        assert_eq!(
+            map.symbols,
+            vec![
+                Symbol {
+                    address: 0x11e60,
+                    size: Some(0x3f),
+                    name: "[System.Private.CoreLib]System.Object::GetType()".into(),
+                },
+                Symbol {
+                    address: 0x11ea0,
+                    size: Some(0x1a4),
+                    name: "[System.Private.CoreLib]System.String::Concat(string,string)".into(),
+                },
+            ]
+        );
+        assert_eq!(R2rMap::parse("FFFFFFFE 00 2\n"), None);
+    }
+
+    #[test]
+    fn test_signatures_match() {
+        assert!(signatures_match(
+            "{9ebce3f2-e4b6-4f6f-8b1c-5b0b9e7e6f5d}",
+            "9EBCE3F2E4B64F6F8B1C5B0B9E7E6F5D"
+        ));
+        assert!(!signatures_match(
+            "{9ebce3f2-e4b6-4f6f-8b1c-5b0b9e7e6f5d}",
+            "00"
+        ));
+    }
+
+    #[test]
+    fn test_r2r_map_paths() {
+        assert_eq!(
+            r2r_map_paths(Path::new("/app/MyApp.dll")),
+            vec![
+                PathBuf::from("/app/MyApp.ni.r2rmap"),
+                PathBuf::from("/app/MyApp.r2rmap")
+            ]
+        );
+    }
+}
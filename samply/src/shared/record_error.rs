@@ -0,0 +1,40 @@
+use std::ffi::OsStr;
+
+/// Why a recording failed. `samply record` prints these and exits, and
+/// `Recorder::record` returns them.
+#[derive(thiserror::Error, Debug)]
+pub enum RecordError {
+    #[error("Invalid recording options: {0}")]
+    InvalidOptions(String),
+
+    #[error("Could not find an executable with the name {0}.")]
+    CommandNotFound(String),
+
+    #[error("Could not launch child process: {0}")]
+    Launch(std::io::Error),
+
+    #[error("Failed to start profiling: {0}")]
+    StartProfiling(String),
+
+    #[error("Encountered an error during profiling: {0}")]
+    Profiling(String),
+
+    #[error("Could not save the profile: {0}")]
+    Save(std::io::Error),
+}
+
+impl RecordError {
+    /// The error for a command which couldn't be executed.
+    pub fn launch(command_name: &OsStr, err: std::io::Error) -> Self {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            return RecordError::Launch(err);
+        }
+        let command_name = command_name.to_string_lossy();
+        if command_name.starts_with('-') {
+            // The command line parser took an unknown option for the command.
+            RecordError::InvalidOptions(format!("unexpected argument '{command_name}' found"))
+        } else {
+            RecordError::CommandNotFound(command_name.into_owned())
+        }
+    }
+}
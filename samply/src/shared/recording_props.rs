@@ -2,8 +2,12 @@ use std::ffi::OsString;
 use std::path::PathBuf;
 use std::time::Duration;
 
+use fxprof_processed_profile::ThreadOrder;
 use serde_derive::{Deserialize, Serialize};
 
+use super::namespace_categories::NamespaceCategoryRule;
+use super::process_intervals::ProcessSampleStrides;
+
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct CoreClrProfileProps {
     pub enabled: bool,
@@ -23,12 +27,205 @@ impl CoreClrProfileProps {
     }
 }
 
+/// A user-mode ETW provider to enable during recording, from `--provider`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EtwProviderProps {
+    /// The provider's registered name or its GUID, without braces.
+    pub provider: String,
+    pub keywords: Option<u64>,
+    pub level: Option<u8>,
+}
+
+impl EtwProviderProps {
+    /// Parses `<name-or-guid>[:keywords[:level]]`. Keywords can be given in
+    /// hex with a `0x` prefix, or in decimal.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = s.split(':');
+        let provider = parts.next().unwrap_or_default();
+        let keywords = parts.next();
+        let level = parts.next();
+        if parts.next().is_some() {
+            return Err(format!("expected PROVIDER[:KEYWORDS[:LEVEL]], got {s:?}"));
+        }
+
+        let provider = match provider.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+            Some(guid) if !is_guid(guid) => {
+                return Err(format!("invalid provider GUID {provider:?}"))
+            }
+            Some(guid) => guid,
+            None => provider,
+        };
+        if provider.is_empty() {
+            return Err(format!("missing provider name in {s:?}"));
+        }
+
+        // An empty keyword field, as in "Provider::5", means all keywords.
+        let keywords = match keywords.filter(|keywords| !keywords.is_empty()) {
+            Some(keywords) => {
+                let parsed = match keywords
+                    .strip_prefix("0x")
+                    .or_else(|| keywords.strip_prefix("0X"))
+                {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => keywords.parse(),
+                };
+                Some(parsed.map_err(|_| format!("invalid keywords {keywords:?}"))?)
+            }
+            None => None,
+        };
+        let level = match level {
+            Some(level) => Some(
+                level
+                    .parse()
+                    .map_err(|_| format!("invalid level {level:?}, expected 0 to 255"))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            provider: provider.to_string(),
+            keywords,
+            level,
+        })
+    }
+
+    /// Whether `provider` is a GUID rather than a name.
+    pub fn is_guid(&self) -> bool {
+        is_guid(&self.provider)
+    }
+
+    /// The provider in the form that xperf's `-on` takes.
+    pub fn xperf_arg(&self) -> String {
+        match (self.keywords, self.level) {
+            (None, None) => self.provider.clone(),
+            (Some(keywords), None) => format!("{}:{keywords:#x}", self.provider),
+            (keywords, Some(level)) => {
+                format!(
+                    "{}:{:#x}:{level}",
+                    self.provider,
+                    keywords.unwrap_or(u64::MAX)
+                )
+            }
+        }
+    }
+}
+
+/// A kernel event which gets a stack walk when recording on Windows, from
+/// `--stack-walk`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StackWalkEvent {
+    /// The sampled profile interrupt. These stacks are the CPU samples.
+    Profile,
+    /// Context switches. These stacks are used for the samples of the time
+    /// which threads spend blocked.
+    CSwitch,
+    ReadyThread,
+    VirtualAlloc,
+    VirtualFree,
+}
+
+impl StackWalkEvent {
+    /// The name of the event in xperf's `-stackwalk` argument.
+    #[allow(dead_code)]
+    pub fn xperf_name(self) -> &'static str {
+        match self {
+            StackWalkEvent::Profile => "Profile",
+            StackWalkEvent::CSwitch => "CSwitch",
+            StackWalkEvent::ReadyThread => "ReadyThread",
+            StackWalkEvent::VirtualAlloc => "VirtualAlloc",
+            StackWalkEvent::VirtualFree => "VirtualFree",
+        }
+    }
+
+    /// The kernel flag which enables the event itself.
+    #[allow(dead_code)]
+    pub fn kernel_flag(self) -> &'static str {
+        match self {
+            StackWalkEvent::Profile => "PROFILE",
+            StackWalkEvent::CSwitch => "CSWITCH",
+            StackWalkEvent::ReadyThread => "DISPATCHER",
+            StackWalkEvent::VirtualAlloc | StackWalkEvent::VirtualFree => "VIRT_ALLOC",
+        }
+    }
+}
+
+/// The kernel providers which `--etw-provider-alias` accepts by name.
+const KERNEL_PROVIDER_GUIDS: [(&str, &str); 5] = [
+    ("PerfInfo", "ce1dbfb4-137e-4da6-87b0-3f59aa102cbc"),
+    ("StackWalk", "def2fe46-7bd6-4b80-bd94-f57fe20d0ce3"),
+    ("Thread", "3d6fa8d1-fe05-11d0-9dda-00c04fd7ba7c"),
+    ("Process", "3d6fa8d0-fe05-11d0-9dda-00c04fd7ba7c"),
+    ("Image", "2cb15d1d-5fc1-11d2-abe1-00a0c911f518"),
+];
+
+/// An ETW provider whose events are decoded as the events of another provider,
+/// from `--etw-provider-alias`. This is for traces from collectors which log the
+/// kernel's sample, stack and image events under their own provider GUIDs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EtwProviderAlias {
+    /// The GUID of the provider in the trace, without braces.
+    pub provider: String,
+    /// The GUID of the provider whose event schemas apply, without braces.
+    pub canonical_provider: String,
+}
+
+impl EtwProviderAlias {
+    /// Parses `GUID=GUID`. The second GUID can also be the name of a kernel
+    /// provider, e.g. `PerfInfo` or `StackWalk`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let Some((provider, canonical_provider)) = s.split_once('=') else {
+            return Err(format!("expected GUID=GUID, got {s:?}"));
+        };
+        let parse_guid = |guid: &str| {
+            let guid = guid
+                .strip_prefix('{')
+                .and_then(|g| g.strip_suffix('}'))
+                .unwrap_or(guid);
+            match is_guid(guid) {
+                true => Ok(guid.to_string()),
+                false => Err(format!("invalid provider GUID {guid:?}")),
+            }
+        };
+        let canonical_provider = match KERNEL_PROVIDER_GUIDS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(canonical_provider))
+        {
+            Some((_, guid)) => guid.to_string(),
+            None => parse_guid(canonical_provider)?,
+        };
+        Ok(Self {
+            provider: parse_guid(provider)?,
+            canonical_provider,
+        })
+    }
+}
+
+/// Checks for the `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx` form.
+fn is_guid(s: &str) -> bool {
+    s.len() == 36
+        && s.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
 /// Properties which are meaningful both for recording a fresh process
 /// as well as for recording an existing process.
 #[derive(Debug, Clone)]
 pub struct RecordingProps {
     pub output_file: PathBuf,
+    /// How long to record, after the delay.
     pub time_limit: Option<Duration>,
+    /// How long to wait before recording samples, e.g. to skip the startup of
+    /// a launched command.
+    pub delay: Duration,
+    /// Stop recording once the process with this pid has exited.
+    pub until_exit_of: Option<u32>,
+    /// The longest the recording may take, including the delay.
+    pub max_duration: Option<Duration>,
+    /// Stop recording once the backend has recorded this many bytes (Linux
+    /// and Windows only).
+    pub max_recording_size: Option<u64>,
     pub interval: Duration,
     #[allow(dead_code)]
     pub vm_hack: bool,
@@ -38,6 +235,76 @@ pub struct RecordingProps {
     pub browsers: bool,
     #[allow(dead_code)]
     pub keep_etl: bool,
+    /// The names of the CPU performance counters to read with each sample
+    /// (Windows only).
+    #[allow(dead_code)]
+    pub pmc_counters: Vec<String>,
+    /// The CPU performance counter which drives the sampling instead of the
+    /// timer, e.g. "BranchMispredictions" (Windows only).
+    #[allow(dead_code)]
+    pub pmc_sampling_source: Option<String>,
+    /// The number of counter events between two samples, with
+    /// `pmc_sampling_source`.
+    #[allow(dead_code)]
+    pub pmc_sampling_interval: u32,
+    /// Whether to capture Windows Defender and minifilter events, to show
+    /// antivirus scans as markers (Windows only).
+    #[allow(dead_code)]
+    pub antivirus: bool,
+    /// Whether to record with a user-mode ETW session only, which doesn't
+    /// need administrator rights (Windows only).
+    #[allow(dead_code)]
+    pub user_mode_only: bool,
+    /// The clock to use for sample timestamps (Linux only).
+    #[allow(dead_code)]
+    pub clock: TimestampClock,
+    /// Whether to capture LBR call stacks with each sample (Linux only).
+    #[allow(dead_code)]
+    pub lbr_call_stacks: bool,
+    /// Whether to sample with the BPF backend, which counts stacks in the
+    /// kernel (Linux only).
+    #[allow(dead_code)]
+    pub bpf: bool,
+    /// The tracepoints to turn into markers, e.g. `syscalls:sys_enter_openat`
+    /// (Linux only).
+    #[allow(dead_code)]
+    pub tracepoints: Vec<String>,
+    /// Whether to add lock contention markers from futex waits and wakes
+    /// (Linux only).
+    #[allow(dead_code)]
+    pub contention: bool,
+    /// Whether to add thread affinity markers from `sched_setaffinity` calls
+    /// (Linux only).
+    #[allow(dead_code)]
+    pub thread_affinity: bool,
+    /// Sampling intervals for processes whose name contains the given string,
+    /// overriding `interval`.
+    pub process_intervals: Vec<(String, Duration)>,
+    /// Whether to add power tracks from the system's energy meters (Windows
+    /// and macOS only).
+    #[allow(dead_code)]
+    pub power_counters: bool,
+    /// How often to checkpoint the raw events to a file next to the output
+    /// file, so that the recording can be recovered if it doesn't finish
+    /// (Linux only).
+    #[allow(dead_code)]
+    pub checkpoint_interval: Option<Duration>,
+}
+
+impl RecordingProps {
+    /// Which samples of which processes to keep for `process_intervals`.
+    pub fn process_sample_strides(&self) -> ProcessSampleStrides {
+        ProcessSampleStrides::new(&self.process_intervals, self.interval)
+    }
+}
+
+/// The clock from which sample timestamps are taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampClock {
+    /// Doesn't advance while the system is suspended.
+    Monotonic,
+    /// Like `Monotonic`, but includes the time during which the system was suspended.
+    Boottime,
 }
 
 /// Which process(es) to record.
@@ -49,6 +316,13 @@ pub enum RecordingMode {
     Pid(u32),
     /// Launch a process, and record just that process (and its children).
     Launch(ProcessLaunchProps),
+    /// Restart a Windows service, and record its process (and its children).
+    #[cfg(target_os = "windows")]
+    Service(String),
+    /// Launch a packaged (UWP / MSIX) app by its Application User Model ID, and
+    /// record its process (and its children).
+    #[cfg(target_os = "windows")]
+    PackagedApp { app_id: String, args: Vec<OsString> },
 }
 
 impl RecordingMode {
@@ -58,6 +332,11 @@ impl RecordingMode {
             RecordingMode::All => true,
             RecordingMode::Pid(_) => true,
             RecordingMode::Launch(_) => false,
+            #[cfg(target_os = "windows")]
+            RecordingMode::Service(_) => false,
+            // The app may already be running.
+            #[cfg(target_os = "windows")]
+            RecordingMode::PackagedApp { .. } => true,
         }
     }
 }
@@ -73,28 +352,102 @@ pub struct ProfileCreationProps {
     pub main_thread_only: bool,
     /// Merge non-overlapping threads of the same name.
     pub reuse_threads: bool,
+    /// Merge non-overlapping processes of the same name, even if
+    /// `reuse_threads` is off.
+    pub reuse_processes_by_name: bool,
     /// Fold repeated frames at the base of the stack.
     pub fold_recursive_prefix: bool,
     /// Unlink jitdump/marker files
     pub unlink_aux_files: bool,
     /// Create a separate thread for each CPU.
     pub create_per_cpu_threads: bool,
+    /// Give blocked threads a sample in every sampling interval, instead of
+    /// one weighted sample per blocked period.
+    #[allow(dead_code)]
+    pub wall_clock_samples: bool,
     /// Include up to N command line arguments in the process name
     pub arg_count_to_include_in_process_name: usize,
     /// Override system architecture.
-    #[allow(dead_code)]
     pub override_arch: Option<String>,
     /// Dump presymbolication info.
     pub unstable_presymbolicate: bool,
+    /// Don't embed symbol tables in the profile, leave symbolication to the viewer.
+    pub no_presymbolicate: bool,
+    /// Write a search index sidecar file (`<output>.idx`) next to the profile.
+    pub search_index: bool,
+    /// Print a summary of the profile to stderr once it's saved.
+    pub recording_summary: bool,
     /// CoreCLR specific properties.
     #[allow(dead_code)]
     pub coreclr: CoreClrProfileProps,
+    /// Extra user-mode ETW providers to enable when recording. Their events
+    /// become markers, even without `unknown_event_markers` (Windows only).
+    #[allow(dead_code)]
+    pub etw_providers: Vec<EtwProviderProps>,
+    /// Providers in imported traces whose events are decoded with the schemas
+    /// of another provider (Windows only).
+    #[allow(dead_code)]
+    pub etw_provider_aliases: Vec<EtwProviderAlias>,
     /// Create markers for unknown events.
     #[allow(dead_code)]
     pub unknown_event_markers: bool,
+    /// Truncate property values in the text of ETW event markers to this many
+    /// bytes (Windows only).
+    #[allow(dead_code)]
+    pub max_marker_value_size: Option<usize>,
+    /// Leave out the remaining properties once the text of an ETW event marker
+    /// reaches this many bytes (Windows only).
+    #[allow(dead_code)]
+    pub max_marker_payload_size: Option<usize>,
+    /// Include the System process (pid 4) and its kernel threads, even if
+    /// only specific processes are being profiled.
+    #[allow(dead_code)]
+    pub include_system_process: bool,
+    /// Add markers for the running / ready / blocked state of each thread
+    /// (Windows only).
+    #[allow(dead_code)]
+    pub thread_states: bool,
+    /// Add a scheduler latency counter for each process, and markers for the
+    /// wakeups which waited longer than this for a CPU (Windows only).
+    #[allow(dead_code)]
+    pub scheduler_latency_threshold: Option<std::time::Duration>,
+    /// Add a marker for each ReadyThread event, with the thread which readied
+    /// the woken thread (Windows only).
+    #[allow(dead_code)]
+    pub wakeups: bool,
+    /// Add "Hang" markers for the times in which a thread with a message loop
+    /// didn't check its messages for longer than this (Windows only).
+    #[allow(dead_code)]
+    pub hang_threshold: Option<std::time::Duration>,
+    /// The kernel events which get stack walks when recording, or which the
+    /// imported trace has stack walks for (Windows only).
+    #[allow(dead_code)]
+    pub stack_walk_events: Vec<StackWalkEvent>,
     /// Time range to include, relative to start of recording.
     #[allow(dead_code)]
     pub time_range: Option<(std::time::Duration, std::time::Duration)>,
+    /// Maximum size of the profile JSON in bytes, before compression.
+    pub max_profile_size: Option<u64>,
+    /// Truncate stacks with more than this many frames.
+    pub max_stack_depth: Option<usize>,
+    /// Categorize frames by the namespace of their function, with these rules
+    /// taking precedence. `None` keeps the regular categories.
+    pub namespace_category_rules: Option<Vec<NamespaceCategoryRule>>,
+    /// Give the frames of common runtimes, e.g. libc or the Python interpreter,
+    /// their own categories.
+    pub runtime_categories: bool,
+    /// Attribute the kernel code under syscall entry points to the syscall, with
+    /// a subcategory for each syscall (Linux only).
+    pub syscall_categories: bool,
+    /// Remove the profiler's own frames from the leaf end of stacks.
+    pub remove_collector_frames: bool,
+    /// Add a counter with the combined CPU usage of each process and all its
+    /// descendants, for processes with child processes in the profile.
+    pub process_group_cpu: bool,
+    /// The order of the processes and threads in the profile.
+    pub thread_order: ThreadOrder,
+    /// Initially hide the threads which used hardly any CPU.
+    pub hide_idle_threads: bool,
 }
 
 impl ProfileCreationProps {
@@ -103,6 +456,12 @@ impl ProfileCreationProps {
             .as_deref()
             .unwrap_or(&self.fallback_profile_name)
     }
+
+    /// Whether exited processes should be reused for later processes with
+    /// the same name.
+    pub fn reuse_processes(&self) -> bool {
+        self.reuse_threads || self.reuse_processes_by_name
+    }
 }
 
 /// Properties which are meaningful for launching and recording a fresh process.
@@ -113,3 +472,60 @@ pub struct ProcessLaunchProps {
     pub args: Vec<OsString>,
     pub iteration_count: u32,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_etw_provider() {
+        let props = EtwProviderProps::parse("My-Product-Provider").unwrap();
+        assert_eq!(props.xperf_arg(), "My-Product-Provider");
+        assert!(!props.is_guid());
+
+        let props =
+            EtwProviderProps::parse("{1A2B3C4D-0000-1111-2222-333344445555}:0x10:4").unwrap();
+        assert_eq!(props.provider, "1A2B3C4D-0000-1111-2222-333344445555");
+        assert!(props.is_guid());
+        assert_eq!(
+            props.xperf_arg(),
+            "1A2B3C4D-0000-1111-2222-333344445555:0x10:4"
+        );
+
+        let props = EtwProviderProps::parse("My-Product-Provider::5").unwrap();
+        assert_eq!(
+            props.xperf_arg(),
+            "My-Product-Provider:0xffffffffffffffff:5"
+        );
+
+        assert!(EtwProviderProps::parse("").is_err());
+        assert!(EtwProviderProps::parse("{not-a-guid}").is_err());
+        assert!(EtwProviderProps::parse("Provider:zz").is_err());
+        assert!(EtwProviderProps::parse("Provider:1:256").is_err());
+        assert!(EtwProviderProps::parse("Provider:1:2:3").is_err());
+    }
+
+    #[test]
+    fn parse_etw_provider_alias() {
+        let alias =
+            EtwProviderAlias::parse("{1A2B3C4D-0000-1111-2222-333344445555}=perfinfo").unwrap();
+        assert_eq!(alias.provider, "1A2B3C4D-0000-1111-2222-333344445555");
+        assert_eq!(
+            alias.canonical_provider,
+            "ce1dbfb4-137e-4da6-87b0-3f59aa102cbc"
+        );
+
+        let alias = EtwProviderAlias::parse(
+            "1a2b3c4d-0000-1111-2222-333344445555=def2fe46-7bd6-4b80-bd94-f57fe20d0ce3",
+        )
+        .unwrap();
+        assert_eq!(
+            alias.canonical_provider,
+            "def2fe46-7bd6-4b80-bd94-f57fe20d0ce3"
+        );
+
+        assert!(EtwProviderAlias::parse("1a2b3c4d-0000-1111-2222-333344445555").is_err());
+        assert!(EtwProviderAlias::parse("PerfInfo=1a2b3c4d-0000-1111-2222-333344445555").is_err());
+        assert!(EtwProviderAlias::parse("1a2b3c4d-0000-1111-2222-333344445555=Nope").is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! When a recording samples and when it stops, from `--delay`, `--duration`,
+//! `--until-exit-of`, and the `--max-duration` and `--max-recording-size`
+//! safety limits. The recording backends poll the schedule from their sampling
+//! or event loops.
+
+use std::time::{Duration, Instant};
+
+use super::recording_props::RecordingProps;
+
+/// Why a recording was stopped by its schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The `--duration` has passed.
+    Duration,
+    /// The process given to `--until-exit-of` has exited.
+    ProcessExited(u32),
+    /// The `--max-duration` has passed.
+    MaxDuration,
+    /// The recording has reached the `--max-recording-size`.
+    MaxRecordingSize,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordingSchedule {
+    start: Instant,
+    delay: Duration,
+    duration: Option<Duration>,
+    until_exit_of: Option<u32>,
+    max_duration: Option<Duration>,
+    max_recording_size: Option<u64>,
+}
+
+impl RecordingSchedule {
+    /// The schedule starts now, i.e. the delay counts from this call.
+    pub fn new(recording_props: &RecordingProps) -> Self {
+        Self {
+            start: Instant::now(),
+            delay: recording_props.delay,
+            duration: recording_props.time_limit,
+            until_exit_of: recording_props.until_exit_of,
+            max_duration: recording_props.max_duration,
+            max_recording_size: recording_props.max_recording_size,
+        }
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Whether the delay has passed, i.e. whether samples should be recorded.
+    pub fn is_sampling(&self) -> bool {
+        self.start.elapsed() >= self.delay
+    }
+
+    /// Returns the reason to stop the recording now, if there is one. The
+    /// duration counts from the end of the delay.
+    pub fn check_stop(&self) -> Option<StopReason> {
+        if let Some(duration) = self.duration {
+            if self.start.elapsed() >= self.delay + duration {
+                return Some(StopReason::Duration);
+            }
+        }
+        if let Some(pid) = self.until_exit_of {
+            if !process_is_alive(pid) {
+                return Some(StopReason::ProcessExited(pid));
+            }
+        }
+        match self.max_duration {
+            Some(max_duration) if self.start.elapsed() >= max_duration => {
+                eprintln!(
+                    "The recording has reached the maximum duration of {}, stopping.",
+                    humantime::format_duration(max_duration)
+                );
+                Some(StopReason::MaxDuration)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns [`StopReason::MaxRecordingSize`] if the recording has reached the
+    /// `--max-recording-size`. `recorded_size` returns the number of bytes the
+    /// backend has recorded so far, and is only called if there is a limit.
+    pub fn check_recording_size(&self, recorded_size: impl FnOnce() -> u64) -> Option<StopReason> {
+        let max_recording_size = self.max_recording_size?;
+        if recorded_size() < max_recording_size {
+            return None;
+        }
+        eprintln!(
+            "The recording has reached the maximum size of {}, stopping.",
+            format_size(max_recording_size)
+        );
+        Some(StopReason::MaxRecordingSize)
+    }
+
+    /// The schedule and the reason for stopping, for the "Recording" section
+    /// of the profile metadata.
+    pub fn meta_info(&self, stop_reason: Option<StopReason>) -> Vec<(&'static str, String)> {
+        let mut meta_info = Vec::new();
+        if !self.delay.is_zero() {
+            meta_info.push(("Delay", humantime::format_duration(self.delay).to_string()));
+        }
+        if let Some(duration) = self.duration {
+            meta_info.push(("Duration", humantime::format_duration(duration).to_string()));
+        }
+        if let Some(pid) = self.until_exit_of {
+            meta_info.push(("Until exit of", format!("pid {pid}")));
+        }
+        if let Some(max_duration) = self.max_duration {
+            meta_info.push((
+                "Maximum duration",
+                humantime::format_duration(max_duration).to_string(),
+            ));
+        }
+        if let Some(max_recording_size) = self.max_recording_size {
+            meta_info.push(("Maximum recording size", format_size(max_recording_size)));
+        }
+        match stop_reason {
+            Some(StopReason::Duration) => {
+                meta_info.push(("Stopped by", "Duration reached".to_string()));
+            }
+            Some(StopReason::ProcessExited(pid)) => {
+                meta_info.push(("Stopped by", format!("Exit of pid {pid}")));
+            }
+            Some(StopReason::MaxDuration) => {
+                meta_info.push(("Stopped by", "Maximum duration reached".to_string()));
+            }
+            Some(StopReason::MaxRecordingSize) => {
+                meta_info.push(("Stopped by", "Maximum recording size reached".to_string()));
+            }
+            None => {}
+        }
+        meta_info
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / 1_000_000_000.0)
+}
+
+/// Whether the process with this pid exists and hasn't exited yet.
+#[cfg(unix)]
+pub fn process_is_alive(pid: u32) -> bool {
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    // EPERM means that the process exists, but belongs to someone else.
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether the process with this pid exists and hasn't exited yet.
+#[cfg(windows)]
+pub fn process_is_alive(pid: u32) -> bool {
+    use windows::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+    use windows::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    unsafe {
+        let Ok(handle) = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) else {
+            return false;
+        };
+        let mut exit_code = 0;
+        let result = GetExitCodeProcess(handle, &mut exit_code);
+        let _ = CloseHandle(handle);
+        result.is_ok() && exit_code == STILL_ACTIVE.0 as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_meta_info() {
+        let schedule = RecordingSchedule {
+            start: Instant::now(),
+            delay: Duration::from_secs(5),
+            duration: Some(Duration::from_secs(30)),
+            until_exit_of: None,
+            max_duration: None,
+            max_recording_size: None,
+        };
+        assert!(!schedule.is_sampling());
+        assert_eq!(schedule.check_stop(), None);
+        assert_eq!(
+            schedule.meta_info(Some(StopReason::Duration)),
+            vec![
+                ("Delay", "5s".to_string()),
+                ("Duration", "30s".to_string()),
+                ("Stopped by", "Duration reached".to_string()),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_until_exit_of() {
+        let schedule = RecordingSchedule {
+            start: Instant::now(),
+            delay: Duration::ZERO,
+            duration: None,
+            until_exit_of: Some(std::process::id()),
+            max_duration: None,
+            max_recording_size: None,
+        };
+        assert!(schedule.is_sampling());
+        assert_eq!(schedule.check_stop(), None);
+    }
+
+    #[test]
+    fn test_safety_limits() {
+        let schedule = RecordingSchedule {
+            start: Instant::now(),
+            delay: Duration::ZERO,
+            duration: None,
+            until_exit_of: None,
+            max_duration: Some(Duration::ZERO),
+            max_recording_size: Some(2_000_000_000),
+        };
+        assert_eq!(schedule.check_stop(), Some(StopReason::MaxDuration));
+        assert_eq!(schedule.check_recording_size(|| 1_000_000_000), None);
+        assert_eq!(
+            schedule.check_recording_size(|| 2_000_000_000),
+            Some(StopReason::MaxRecordingSize)
+        );
+        assert_eq!(
+            schedule.meta_info(Some(StopReason::MaxRecordingSize)),
+            vec![
+                ("Maximum duration", "0s".to_string()),
+                ("Maximum recording size", "2.0 GB".to_string()),
+                ("Stopped by", "Maximum recording size reached".to_string()),
+            ]
+        );
+    }
+}
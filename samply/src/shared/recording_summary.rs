@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use fxprof_processed_profile::{HotFrame, HotFrameLocation, ProcessSummary, Profile};
+
+/// The number of functions listed in the "Hottest functions" section.
+const HOT_FUNCTION_COUNT: usize = 10;
+
+/// The number of characters in each per-process sparkline.
+const SPARKLINE_WIDTH: usize = 40;
+
+const SPARKLINE_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Prints a short overview of the recorded profile to stderr: the CPU usage of
+/// each process with a sparkline of its activity over time, the hottest
+/// functions, the number of markers, and the size of the profile file.
+///
+/// Addresses of the hottest functions are symbolicated with whatever symbol
+/// files can be found locally, including the symbol caches of
+/// `_NT_SYMBOL_PATH`, without downloading anything from symbol servers.
+pub fn print_recording_summary(profile: &Profile, output_file: &Path) {
+    let summary = profile.summary(HOT_FUNCTION_COUNT, SPARKLINE_WIDTH);
+    if summary.sample_count == 0 {
+        return;
+    }
+
+    eprintln!();
+    eprintln!(
+        "Recorded {} samples over {:.2}s, {} markers. Profile size: {}",
+        summary.sample_count,
+        summary.sampled_duration.as_secs_f64(),
+        summary.marker_count,
+        match std::fs::metadata(output_file) {
+            Ok(metadata) => format_size(metadata.len()),
+            Err(_) => "unknown".to_string(),
+        }
+    );
+
+    eprintln!();
+    eprintln!("CPU usage by process:");
+    for process in &summary.processes {
+        eprintln!(
+            "  {:>14}  {}  {} (pid {})",
+            format_process_usage(process, summary.sampled_duration),
+            sparkline(process),
+            process.name,
+            process.pid
+        );
+    }
+
+    if summary.hottest_frames.is_empty() {
+        return;
+    }
+    let function_names = symbolicate_hot_frames(&summary.hottest_frames);
+    eprintln!();
+    eprintln!("Hottest functions (self time):");
+    for (frame, name) in summary.hottest_frames.iter().zip(function_names) {
+        let percentage = frame.self_weight as f64 / summary.sample_count as f64 * 100.0;
+        eprintln!("  {percentage:>5.1}%  {name}");
+    }
+    eprintln!();
+}
+
+fn format_process_usage(process: &ProcessSummary, sampled_duration: Duration) -> String {
+    if process.cpu_time.is_zero() || sampled_duration.is_zero() {
+        // No CPU deltas were recorded for this process.
+        return format!("{} samples", process.sample_count);
+    }
+    let percentage = process.cpu_time.as_secs_f64() / sampled_duration.as_secs_f64() * 100.0;
+    format!("{percentage:.1}% CPU")
+}
+
+/// Draws the process's activity over time, relative to its busiest time bucket.
+fn sparkline(process: &ProcessSummary) -> String {
+    let values: Vec<u64> = if process.cpu_time.is_zero() {
+        process.samples_per_bucket.clone()
+    } else {
+        process
+            .cpu_time_per_bucket
+            .iter()
+            .map(|cpu_time| cpu_time.as_micros() as u64)
+            .collect()
+    };
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    values
+        .into_iter()
+        .map(|value| {
+            if value == 0 {
+                ' '
+            } else {
+                let level = (value * SPARKLINE_CHARS.len() as u64).div_ceil(max) as usize;
+                SPARKLINE_CHARS[level.clamp(1, SPARKLINE_CHARS.len()) - 1]
+            }
+        })
+        .collect()
+}
+
+/// Returns a display name for each of the hottest frames, symbolicating the
+/// library addresses which don't have a symbol yet.
+fn symbolicate_hot_frames(frames: &[HotFrame]) -> Vec<String> {
+    let config = wholesym::SymbolManagerConfig::new()
+        .use_spotlight(true)
+        .respect_nt_symbol_path(true)
+        .offline(true);
+    let mut symbol_manager = wholesym::SymbolManager::with_config(config);
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut symbol_maps = HashMap::new();
+
+    frames
+        .iter()
+        .map(|frame| match &frame.location {
+            HotFrameLocation::Label(label) => label.clone(),
+            HotFrameLocation::UnknownAddress(address) => format!("0x{address:x}"),
+            HotFrameLocation::AddressInLib {
+                lib,
+                relative_address,
+                symbol_name,
+            } => {
+                let symbol_name = symbol_name.clone().or_else(|| {
+                    let symbol_map = symbol_maps
+                        .entry((lib.debug_name.clone(), lib.debug_id))
+                        .or_insert_with(|| {
+                            symbol_manager.add_known_library(wholesym::LibraryInfo {
+                                name: Some(lib.name.clone()),
+                                path: Some(lib.path.clone()),
+                                debug_path: Some(lib.debug_path.clone()),
+                                debug_id: Some(lib.debug_id),
+                                arch: lib.arch.clone(),
+                                debug_name: Some(lib.debug_name.clone()),
+                                code_id: lib
+                                    .code_id
+                                    .as_deref()
+                                    .and_then(|id| wholesym::CodeId::from_str(id).ok()),
+                            });
+                            rt.block_on(
+                                symbol_manager.load_symbol_map(&lib.debug_name, lib.debug_id),
+                            )
+                            .ok()
+                        })
+                        .as_ref()?;
+                    let address_info = rt.block_on(
+                        symbol_map.lookup(wholesym::LookupAddress::Relative(*relative_address)),
+                    )?;
+                    Some(address_info.symbol.name)
+                });
+                match symbol_name {
+                    Some(symbol_name) => format!("{symbol_name}  ({})", lib.name),
+                    None => format!("0x{relative_address:x}  ({})", lib.name),
+                }
+            }
+        })
+        .collect()
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
@@ -1,7 +1,7 @@
 use std::cmp::Reverse;
 use std::collections::BinaryHeap;
 
-use fxprof_processed_profile::{FrameInfo, ProcessHandle, ThreadHandle};
+use fxprof_processed_profile::{FrameInfo, ProcessHandle, Profile, ThreadHandle};
 
 use crate::shared::jit_function_recycler::JitFunctionRecycler;
 use crate::shared::types::FastHashMap;
@@ -9,7 +9,8 @@ use crate::shared::types::FastHashMap;
 pub struct ProcessRecyclingData {
     pub process_handle: ProcessHandle,
     pub main_thread_recycling_data: (ThreadHandle, FrameInfo),
-    pub thread_recycler: ThreadRecycler,
+    /// `None` if only processes are reused, not threads.
+    pub thread_recycler: Option<ThreadRecycler>,
     pub jit_function_recycler: JitFunctionRecycler,
 }
 
@@ -33,7 +34,55 @@ impl Ord for ProcessRecyclingData {
     }
 }
 
-pub type ProcessRecycler = RecyclerByName<ProcessRecyclingData>;
+/// Reuses the profile process of an exited process for a later process with
+/// the same name, so that a process which is restarted over and over shows up
+/// as a single track.
+pub struct ProcessRecycler {
+    pool: RecyclerByName<ProcessRecyclingData>,
+    /// How many processes have used each recycled process handle so far.
+    run_counts: FastHashMap<ProcessHandle, u32>,
+    reuse_threads: bool,
+}
+
+impl ProcessRecycler {
+    /// If `reuse_threads` is false, each run of a process gets fresh threads
+    /// and only the process itself is shared.
+    pub fn new(reuse_threads: bool) -> Self {
+        Self {
+            pool: RecyclerByName::new(),
+            run_counts: FastHashMap::default(),
+            reuse_threads,
+        }
+    }
+
+    /// The thread recycler for a new process which isn't a recycled one.
+    pub fn new_thread_recycler(&self) -> Option<ThreadRecycler> {
+        if self.reuse_threads {
+            Some(ThreadRecycler::new())
+        } else {
+            None
+        }
+    }
+
+    pub fn add_to_pool(&mut self, name: &str, value: ProcessRecyclingData) {
+        self.pool.add_to_pool(name, value);
+    }
+
+    /// Returns an exited process with the given name, if there is one. The
+    /// process is renamed to include the number of runs it now covers.
+    pub fn recycle_by_name(
+        &mut self,
+        name: &str,
+        profile: &mut Profile,
+    ) -> Option<ProcessRecyclingData> {
+        let data = self.pool.recycle_by_name(name)?;
+        let run_count = self.run_counts.entry(data.process_handle).or_insert(1);
+        *run_count += 1;
+        profile.set_process_name(data.process_handle, &format!("{name} ({run_count} runs)"));
+        Some(data)
+    }
+}
+
 pub type ThreadRecycler = RecyclerByName<(ThreadHandle, FrameInfo)>;
 
 pub struct RecyclerByName<T: Ord>(FastHashMap<String, BinaryHeap<Reverse<T>>>);
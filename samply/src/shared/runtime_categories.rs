@@ -0,0 +1,189 @@
+//! Built-in categories for the code of common runtimes, e.g. the C library or
+//! the Python interpreter, so that profiles show where the time goes without
+//! any configuration. They can be turned off with `--no-runtime-categories`.
+//!
+//! Frames are matched by the name of their library, or by their function name
+//! if it is known, e.g. for JIT frames or for libraries with an embedded symbol
+//! table. Most native frames are only symbolicated later, in the profiler, so
+//! runtimes which get linked into the executable, like tokio or rayon, are only
+//! recognized by name if the profile has symbols for them.
+
+use std::collections::HashMap;
+
+use fxprof_processed_profile::{CategoryColor, Profile};
+use regex::Regex;
+
+/// (category, color, library name regex, function name regex)
+const RUNTIME_RULES: &[(&str, CategoryColor, Option<&str>, Option<&str>)] = &[
+    (
+        "libc",
+        CategoryColor::Orange,
+        Some(concat!(
+            r"^(libc\.so(\.\d+)?|libc-[\d.]+\.so|ld-musl-.*\.so\.\d+|libc\.musl-.*\.so\.\d+",
+            r"|libsystem_(c|malloc|platform|pthread)\.dylib|ucrtbase\.dll|msvcrt\.dll)$",
+        )),
+        None,
+    ),
+    (
+        "C++ standard library",
+        CategoryColor::Yellow,
+        Some(concat!(
+            r"^(libstdc\+\+\.so(\.\d+)*|libc\+\+(abi)?\.(so(\.\d+)*|1\.dylib|dylib)",
+            r"|msvcp\d+\.dll)$",
+        )),
+        Some(r"^(__gnu_cxx|__cxxabiv1)::"),
+    ),
+    ("tokio", CategoryColor::LightBlue, None, Some(r"^<?tokio::")),
+    (
+        "rayon",
+        CategoryColor::LightGreen,
+        None,
+        Some(r"^<?rayon(_core)?::"),
+    ),
+    (
+        "CLR",
+        CategoryColor::Purple,
+        Some(r"^(lib)?(coreclr|clrjit|clr|mscorwks)\.(dll|so|dylib)$"),
+        None,
+    ),
+    (
+        "JVM",
+        CategoryColor::Brown,
+        Some(r"^(lib)?jvm\.(dll|so|dylib)$"),
+        None,
+    ),
+    (
+        "Python interpreter",
+        CategoryColor::Magenta,
+        Some(r"^(lib)?python\d+(\.\d+)?[dmu]*(\.so(\.[\d.]+)?|\.dll|\.dylib|\.exe)?$"),
+        Some(r"^_?Py[A-Z][A-Za-z]*_"),
+    ),
+];
+
+struct RuntimeRule {
+    category: &'static str,
+    color: CategoryColor,
+    lib_name: Option<Regex>,
+    function_name: Option<Regex>,
+}
+
+/// Finds the runtime category of a frame. Function name rules are checked
+/// first, because they can find runtime code inside another library.
+struct RuntimeCategorizer {
+    rules: Vec<RuntimeRule>,
+    /// The index of the matching lib name rule for each library name.
+    lib_rule_cache: HashMap<String, Option<usize>>,
+}
+
+impl RuntimeCategorizer {
+    fn new() -> Self {
+        let rules = RUNTIME_RULES
+            .iter()
+            .map(|(category, color, lib_name, function_name)| RuntimeRule {
+                category,
+                color: *color,
+                lib_name: lib_name.map(|re| Regex::new(re).unwrap()),
+                function_name: function_name.map(|re| Regex::new(re).unwrap()),
+            })
+            .collect();
+        Self {
+            rules,
+            lib_rule_cache: HashMap::new(),
+        }
+    }
+
+    /// Returns the name and color of the frame's category, or `None` if the
+    /// frame isn't in a known runtime.
+    fn category_for_frame(
+        &mut self,
+        lib_name: Option<&str>,
+        function_name: Option<&str>,
+    ) -> Option<(String, CategoryColor)> {
+        let rule_index = function_name
+            .and_then(|name| {
+                self.rules.iter().position(|rule| {
+                    rule.function_name
+                        .as_ref()
+                        .is_some_and(|re| re.is_match(name))
+                })
+            })
+            .or_else(|| {
+                let lib_name = lib_name?;
+                if let Some(rule_index) = self.lib_rule_cache.get(lib_name) {
+                    return *rule_index;
+                }
+                let rule_index = self.rules.iter().position(|rule| {
+                    rule.lib_name
+                        .as_ref()
+                        .is_some_and(|re| re.is_match(lib_name))
+                });
+                self.lib_rule_cache.insert(lib_name.to_owned(), rule_index);
+                rule_index
+            })?;
+        let rule = &self.rules[rule_index];
+        Some((rule.category.to_owned(), rule.color))
+    }
+}
+
+/// Gives the frames of known runtimes their runtime's category.
+pub fn categorize_profile_by_runtime(profile: &mut Profile) {
+    let mut categorizer = RuntimeCategorizer::new();
+    profile.categorize_frames(|lib_name, function_name| {
+        categorizer.category_for_frame(lib_name, function_name)
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_runtime_rules() {
+        let mut categorizer = RuntimeCategorizer::new();
+        let mut category = |lib_name, function_name| {
+            categorizer
+                .category_for_frame(lib_name, function_name)
+                .map(|(category, _color)| category)
+        };
+        assert_eq!(category(Some("libc.so.6"), None).as_deref(), Some("libc"));
+        assert_eq!(
+            category(Some("ld-musl-x86_64.so.1"), None).as_deref(),
+            Some("libc")
+        );
+        assert_eq!(category(Some("libcrypto.so.3"), None), None);
+        assert_eq!(
+            category(Some("libstdc++.so.6"), None).as_deref(),
+            Some("C++ standard library")
+        );
+        assert_eq!(
+            category(Some("myapp"), Some("tokio::runtime::park::Inner::park")).as_deref(),
+            Some("tokio")
+        );
+        assert_eq!(
+            category(Some("myapp"), Some("tokio_util::codec::decode")),
+            None
+        );
+        assert_eq!(
+            category(
+                Some("myapp"),
+                Some("<rayon_core::job::StackJob<L,F,R> as rayon_core::job::Job>::execute")
+            )
+            .as_deref(),
+            Some("rayon")
+        );
+        assert_eq!(
+            category(Some("libcoreclr.so"), None).as_deref(),
+            Some("CLR")
+        );
+        assert_eq!(category(Some("jvm.dll"), None).as_deref(), Some("JVM"));
+        assert_eq!(
+            category(Some("libpython3.12.so.1.0"), None).as_deref(),
+            Some("Python interpreter")
+        );
+        assert_eq!(
+            category(Some("python3.11"), Some("_PyEval_EvalFrameDefault")).as_deref(),
+            Some("Python interpreter")
+        );
+        assert_eq!(category(None, Some("main")), None);
+    }
+}
@@ -1,34 +1,49 @@
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Read, Write};
 use std::path::Path;
 
 use flate2::{Compression, GzBuilder};
-use fxprof_processed_profile::Profile;
+use serde::Serialize;
 
 // Level two has an acceptable trade-off between how long compression
 // takes and how much data it saves on the profile JSONs I tested with.
 const GZIP_COMPRESSION_LEVEL: u32 = 2;
 
-pub fn save_profile_to_file(profile: &Profile, output_path: &Path) -> std::io::Result<()> {
-    let output_file = match File::create(output_path) {
-        Ok(output_file) => output_file,
-        Err(err) => {
-            eprintln!("Couldn't create output file {:?}: {}", output_path, err);
-            std::process::exit(1);
-        }
-    };
+/// Writes a profile, or anything else which serializes to a profile's JSON, to a
+/// file, gzip-compressed if the file name ends in `.gz`.
+pub fn save_profile_to_file(profile: &impl Serialize, output_path: &Path) -> std::io::Result<()> {
+    write_profile_file(output_path, |writer| {
+        serde_json::to_writer(writer, &profile)?;
+        Ok(())
+    })
+}
+
+/// Copies a profile's JSON, e.g. the one which the import helper process
+/// writes to its stdout, to a file, gzip-compressed if the file name ends in
+/// `.gz`.
+pub fn copy_profile_json_to_file(mut json: impl Read, output_path: &Path) -> std::io::Result<()> {
+    write_profile_file(output_path, |writer| {
+        std::io::copy(&mut json, writer)?;
+        writer.flush()
+    })
+}
+
+fn write_profile_file(
+    output_path: &Path,
+    write: impl FnOnce(&mut dyn Write) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let output_file = File::create(output_path)?;
 
-    let writer = BufWriter::new(output_file);
+    let mut writer = BufWriter::new(output_file);
     let is_gz = output_path.extension() == Some(OsStr::new("gz"));
     if is_gz {
         let name_without_gz = output_path.file_stem().unwrap().to_string_lossy();
         let builder = GzBuilder::new().filename(name_without_gz.as_bytes());
         let gz = builder.write(writer, Compression::new(GZIP_COMPRESSION_LEVEL));
-        let gz = BufWriter::new(gz);
-        serde_json::to_writer(gz, &profile)?;
+        let mut gz = BufWriter::new(gz);
+        write(&mut gz)
     } else {
-        serde_json::to_writer(writer, &profile)?;
+        write(&mut writer)
     }
-    Ok(())
 }
@@ -1,22 +1,30 @@
 use std::collections::VecDeque;
 
-use fxprof_processed_profile::{CategoryPairHandle, Frame, FrameFlags, FrameInfo};
+use fxprof_processed_profile::{
+    CategoryPairHandle, Frame, FrameFlags, FrameInfo, Profile, StringHandle,
+};
 
 use super::jit_category_manager::{JsFrame, JsName};
 use super::lib_mappings::{AndroidArtInfo, LibMappingsHierarchy};
+use super::ptr_auth::PtrAuthStripper;
 use super::types::{StackFrame, StackMode};
 
 #[derive(Debug)]
 pub struct StackConverter {
     user_category: CategoryPairHandle,
     kernel_category: CategoryPairHandle,
+    truncation_label: StringHandle,
     libart_frame_buffer: VecDeque<SecondPassFrameInfo>,
+    ptr_auth_stripper: PtrAuthStripper,
 }
 
-struct FirstPassFrameInfo {
-    mode: StackMode,
-    lookup_address: u64,
-    from_ip: bool,
+enum FirstPassFrameInfo {
+    Address {
+        mode: StackMode,
+        lookup_address: u64,
+        from_ip: bool,
+    },
+    TruncatedByDepthLimit,
 }
 
 #[derive(Debug)]
@@ -27,13 +35,14 @@ struct SecondPassFrameInfo {
     art_info: Option<AndroidArtInfo>,
 }
 
-struct FirstPassIter<I: Iterator<Item = StackFrame>>(I);
+struct FirstPassIter<I: Iterator<Item = StackFrame>>(I, PtrAuthStripper);
 
 struct SecondPassIter<'a, I: Iterator<Item = FirstPassFrameInfo>> {
     inner: I,
     lib_mappings: &'a LibMappingsHierarchy,
     user_category: CategoryPairHandle,
     kernel_category: CategoryPairHandle,
+    truncation_label: StringHandle,
 }
 
 struct LibartFilteringIter<'c, I: Iterator<Item = SecondPassFrameInfo>> {
@@ -58,13 +67,21 @@ impl<I: Iterator<Item = StackFrame>> Iterator for FirstPassIter<I> {
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             let frame = self.0.next()?;
+            // Return addresses may be signed, so the PAC has to be removed
+            // before the address is adjusted and looked up.
+            let strip = |addr| self.1.strip(addr);
             let (mode, lookup_address, from_ip) = match frame {
-                StackFrame::InstructionPointer(addr, mode) => (mode, addr, true),
-                StackFrame::ReturnAddress(addr, mode) => (mode, addr.saturating_sub(1), false),
-                StackFrame::AdjustedReturnAddress(addr, mode) => (mode, addr, false),
+                StackFrame::InstructionPointer(addr, mode) => (mode, strip(addr), true),
+                StackFrame::ReturnAddress(addr, mode) => {
+                    (mode, strip(addr).saturating_sub(1), false)
+                }
+                StackFrame::AdjustedReturnAddress(addr, mode) => (mode, strip(addr), false),
                 StackFrame::TruncatedStackMarker => continue,
+                StackFrame::TruncatedByDepthLimit => {
+                    return Some(FirstPassFrameInfo::TruncatedByDepthLimit)
+                }
             };
-            return Some(FirstPassFrameInfo {
+            return Some(FirstPassFrameInfo::Address {
                 mode,
                 lookup_address,
                 from_ip,
@@ -81,11 +98,21 @@ impl<'a, I: Iterator<Item = FirstPassFrameInfo>> Iterator for SecondPassIter<'a,
     }
 
     fn next(&mut self) -> Option<Self::Item> {
-        let FirstPassFrameInfo {
-            mode,
-            lookup_address,
-            from_ip,
-        } = self.inner.next()?;
+        let (mode, lookup_address, from_ip) = match self.inner.next()? {
+            FirstPassFrameInfo::Address {
+                mode,
+                lookup_address,
+                from_ip,
+            } => (mode, lookup_address, from_ip),
+            FirstPassFrameInfo::TruncatedByDepthLimit => {
+                return Some(SecondPassFrameInfo {
+                    location: Frame::Label(self.truncation_label),
+                    category: self.user_category,
+                    js_frame: None,
+                    art_info: None,
+                });
+            }
+        };
         let (location, category, js_frame, art_info) = match mode {
             StackMode::User => match self.lib_mappings.convert_address(lookup_address) {
                 Some((relative_lookup_address, info)) => {
@@ -254,11 +281,18 @@ impl<I: Iterator<Item = SecondPassFrameInfo>> Iterator for ConvertedStackIter<I>
 }
 
 impl StackConverter {
-    pub fn new(user_category: CategoryPairHandle, kernel_category: CategoryPairHandle) -> Self {
+    pub fn new(
+        profile: &mut Profile,
+        user_category: CategoryPairHandle,
+        kernel_category: CategoryPairHandle,
+        ptr_auth_stripper: PtrAuthStripper,
+    ) -> Self {
         Self {
             user_category,
             kernel_category,
+            truncation_label: profile.intern_string("(truncated)"),
             libart_frame_buffer: VecDeque::new(),
+            ptr_auth_stripper,
         }
     }
 
@@ -271,12 +305,13 @@ impl StackConverter {
         lib_mappings: &'a LibMappingsHierarchy,
         extra_first_frame: Option<FrameInfo>,
     ) -> impl Iterator<Item = FrameInfo> + 'a {
-        let pass1 = FirstPassIter(stack.iter().cloned().rev());
+        let pass1 = FirstPassIter(stack.iter().cloned().rev(), self.ptr_auth_stripper);
         let pass2 = SecondPassIter {
             inner: pass1,
             lib_mappings,
             user_category: self.user_category,
             kernel_category: self.kernel_category,
+            truncation_label: self.truncation_label,
         };
         self.libart_frame_buffer.clear();
         let pass3 = LibartFilteringIter {
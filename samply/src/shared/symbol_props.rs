@@ -1,6 +1,8 @@
 use std::path::PathBuf;
 
-#[derive(Debug, Clone)]
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SymbolProps {
     /// Extra directories containing symbol files
     pub symbol_dir: Vec<PathBuf>,
@@ -16,4 +18,63 @@ pub struct SymbolProps {
     pub breakpad_symbol_cache: Option<PathBuf>,
     /// Extra directory containing symbol files, with the directory structure used by simpleperf's scripts
     pub simpleperf_binary_cache: Option<PathBuf>,
+    /// Parse symbol files in a separate, sandboxed process
+    pub sandbox: bool,
+    /// Source path prefixes to replace, as (from, to) pairs, so that the source
+    /// view finds local files for binaries which were built elsewhere
+    pub source_path_map: Vec<(String, String)>,
+    /// HTTP headers for downloading source files, as (URL prefix, name, value)
+    pub source_url_headers: Vec<(String, String, String)>,
+    /// Add the parameter types from DWARF to function names which don't have them
+    pub full_signatures: bool,
+    /// Patterns of library names whose debug files aren't loaded, so that they
+    /// only get the symbols from the binary itself
+    pub skip_symbols_for: Vec<String>,
+    /// Only use local files, without downloading from symbol servers
+    pub offline: bool,
+}
+
+impl SymbolProps {
+    /// Parses a `--source-path-map` argument of the form `FROM=TO`. A leading
+    /// `~` in `TO` is expanded to the home directory.
+    pub fn parse_source_path_map(s: &str) -> Result<(String, String), String> {
+        let Some((from, to)) = s.split_once('=') else {
+            return Err(format!("expected FROM=TO, got {s:?}"));
+        };
+        if from.is_empty() || to.is_empty() {
+            return Err(format!("expected FROM=TO, got {s:?}"));
+        }
+        let to = match (to.strip_prefix('~'), std::env::var("HOME")) {
+            (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+                format!("{home}{rest}")
+            }
+            _ => to.to_string(),
+        };
+        Ok((from.to_string(), to))
+    }
+
+    /// Parses a `--source-url-header` argument of the form `URL_PREFIX=NAME: VALUE`.
+    /// Words of the form `$VAR` in the value are replaced with the environment
+    /// variable `VAR`, so that tokens don't have to be on the command line.
+    pub fn parse_source_url_header(s: &str) -> Result<(String, String, String), String> {
+        let err = || format!("expected URL_PREFIX=NAME: VALUE, got {s:?}");
+        // URLs can contain '=' in the query, but the prefixes which people
+        // specify here are hosts and paths, so the first '=' ends the prefix.
+        let (url_prefix, header) = s.split_once('=').ok_or_else(err)?;
+        let (name, value) = header.split_once(':').ok_or_else(err)?;
+        let (name, value) = (name.trim(), value.trim());
+        if url_prefix.is_empty() || name.is_empty() || value.is_empty() {
+            return Err(err());
+        }
+        let words: Result<Vec<String>, String> = value
+            .split(' ')
+            .map(|word| match word.strip_prefix('$') {
+                Some(var) => {
+                    std::env::var(var).map_err(|_| format!("environment variable {var} is not set"))
+                }
+                None => Ok(word.to_string()),
+            })
+            .collect();
+        Ok((url_prefix.to_string(), name.to_string(), words?.join(" ")))
+    }
 }
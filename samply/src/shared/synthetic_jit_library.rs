@@ -9,6 +9,7 @@ use super::types::FastHashMap;
 
 #[derive(Debug)]
 pub struct SyntheticJitLibrary {
+    name: String,
     lib_handle: LibraryHandle,
     default_category: CategoryPairHandle,
     next_relative_address: u32,
@@ -27,7 +28,7 @@ impl SyntheticJitLibrary {
             name: name.clone(),
             debug_name: name.clone(),
             path: name.clone(),
-            debug_path: name,
+            debug_path: name.clone(),
             debug_id: DebugId::nil(),
             code_id: None,
             arch: None,
@@ -39,6 +40,7 @@ impl SyntheticJitLibrary {
             None
         };
         Self {
+            name,
             lib_handle,
             default_category,
             next_relative_address: 0,
@@ -75,6 +77,10 @@ impl SyntheticJitLibrary {
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn lib_handle(&self) -> LibraryHandle {
         self.lib_handle
     }
@@ -87,4 +93,11 @@ impl SyntheticJitLibrary {
         let symbol_table = Arc::new(SymbolTable::new(self.symbols));
         profile.set_lib_symbol_table(self.lib_handle, symbol_table);
     }
+
+    /// Sets the symbol table for the functions added so far, while allowing
+    /// more functions to be added afterwards.
+    pub fn set_symbol_table(&self, profile: &mut Profile) {
+        let symbol_table = Arc::new(SymbolTable::new(self.symbols.clone()));
+        profile.set_lib_symbol_table(self.lib_handle, symbol_table);
+    }
 }
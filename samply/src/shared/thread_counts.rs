@@ -0,0 +1,94 @@
+//! The "Thread count" counter of each process, so that a process which creates
+//! lots of threads stands out without scrolling through all of its tracks.
+
+use fxprof_processed_profile::{ProcessHandle, Profile, Timestamp};
+
+/// Processes which never had more threads than this don't get a counter.
+const MIN_PEAK_THREAD_COUNT: u32 = 2;
+
+/// Collects the thread starts and ends of one process.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadCounts {
+    /// The change in the thread count at each start or end, in event order.
+    /// Threads whose start we only learn about later, e.g. from their first
+    /// sample, can have an earlier timestamp than the changes before them.
+    changes: Vec<(Timestamp, f64)>,
+    count: u32,
+    peak_count: u32,
+}
+
+impl ThreadCounts {
+    pub fn thread_started(&mut self, timestamp: Timestamp) {
+        self.count += 1;
+        self.peak_count = self.peak_count.max(self.count);
+        self.changes.push((timestamp, 1.0));
+    }
+
+    pub fn thread_ended(&mut self, timestamp: Timestamp) {
+        // Ignore ends of threads whose start we didn't see.
+        if self.count == 0 {
+            return;
+        }
+        self.count -= 1;
+        self.changes.push((timestamp, -1.0));
+    }
+
+    /// Adds the counter to the process, if the process ever had more than one
+    /// thread.
+    pub fn add_counter(&self, profile: &mut Profile, process: ProcessHandle) {
+        if self.peak_count < MIN_PEAK_THREAD_COUNT {
+            return;
+        }
+        let counter = profile.add_counter(
+            process,
+            "Thread count",
+            "Threads",
+            "The number of threads in the process",
+        );
+        let mut changes = self.changes.clone();
+        changes.sort_by_key(|(timestamp, _)| *timestamp);
+        for (timestamp, change) in changes {
+            profile.add_counter_sample(counter, timestamp, change, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{ReferenceTimestamp, SamplingInterval};
+
+    use super::*;
+
+    #[test]
+    fn starts_and_ends_become_changes() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let at = Timestamp::from_millis_since_reference;
+        let single = profile.add_process("single", 1, at(0.0));
+        let mut counts = ThreadCounts::default();
+        counts.thread_started(at(0.0));
+        counts.thread_ended(at(1.0));
+        counts.add_counter(&mut profile, single);
+
+        let multi = profile.add_process("multi", 2, at(0.0));
+        let mut counts = ThreadCounts::default();
+        counts.thread_ended(at(0.0));
+        counts.thread_started(at(1.0));
+        counts.thread_started(at(2.0));
+        counts.thread_ended(at(3.0));
+        counts.thread_started(at(0.5));
+        counts.add_counter(&mut profile, multi);
+
+        let json = serde_json::to_value(&profile).unwrap();
+        let counters = json["counters"].as_array().unwrap();
+        assert_eq!(counters.len(), 1);
+        assert_eq!(counters[0]["name"], "Thread count");
+        assert_eq!(
+            counters[0]["samples"]["count"],
+            serde_json::json!([1.0, 1.0, 1.0, -1.0])
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! The local time zone of the recording machine, so that the absolute
+//! timestamps in a profile can be correlated with local-time logs.
+
+use std::fmt;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeZone {
+    /// Local time minus UTC, in seconds.
+    pub utc_offset_seconds: i64,
+    /// The abbreviation or name of the time zone, e.g. "CEST".
+    pub name: Option<String>,
+}
+
+impl fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.utc_offset_seconds < 0 {
+            '-'
+        } else {
+            '+'
+        };
+        let offset_minutes = self.utc_offset_seconds.unsigned_abs() / 60;
+        write!(
+            f,
+            "UTC{sign}{:02}:{:02}",
+            offset_minutes / 60,
+            offset_minutes % 60
+        )?;
+        if let Some(name) = &self.name {
+            write!(f, " ({name})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the local time zone which is in effect at `time`.
+#[cfg(unix)]
+pub fn local_time_zone(time: SystemTime) -> Option<TimeZone> {
+    let secs = time.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return None;
+    }
+    let name = if tm.tm_zone.is_null() {
+        None
+    } else {
+        let name = unsafe { std::ffi::CStr::from_ptr(tm.tm_zone) };
+        Some(name.to_string_lossy().into_owned())
+    };
+    Some(TimeZone {
+        utc_offset_seconds: tm.tm_gmtoff as i64,
+        name,
+    })
+}
+
+/// Returns the local time zone which is currently in effect. Windows only
+/// tells us about the current time zone, so `time` should be close to now.
+#[cfg(windows)]
+pub fn local_time_zone(_time: SystemTime) -> Option<TimeZone> {
+    use windows::Win32::System::Time::{GetTimeZoneInformation, TIME_ZONE_INFORMATION};
+
+    const TIME_ZONE_ID_DAYLIGHT: u32 = 2;
+    const TIME_ZONE_ID_INVALID: u32 = u32::MAX;
+
+    let mut info = TIME_ZONE_INFORMATION::default();
+    let zone_id = unsafe { GetTimeZoneInformation(&mut info) };
+    if zone_id == TIME_ZONE_ID_INVALID {
+        return None;
+    }
+    // The bias is UTC minus local time, in minutes.
+    let (extra_bias, name) = if zone_id == TIME_ZONE_ID_DAYLIGHT {
+        (info.DaylightBias, &info.DaylightName)
+    } else {
+        (info.StandardBias, &info.StandardName)
+    };
+    let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    let name = String::from_utf16_lossy(&name[..name_len]);
+    Some(TimeZone {
+        utc_offset_seconds: -i64::from(info.Bias + extra_bias) * 60,
+        name: (!name.is_empty()).then_some(name),
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn local_time_zone(_time: SystemTime) -> Option<TimeZone> {
+    None
+}
+
+/// Returns the profile meta info entries for a recording which started at
+/// `start_time`, as (label, value) pairs for the "Recording" section.
+pub fn recording_start_meta_info(start_time: SystemTime) -> Vec<(&'static str, String)> {
+    let mut entries = vec![(
+        "Start time (UTC)",
+        humantime::format_rfc3339_millis(start_time).to_string(),
+    )];
+    if let Some(time_zone) = local_time_zone(start_time) {
+        entries.push(("Time zone", time_zone.to_string()));
+    }
+    entries
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_time_zone() {
+        let time_zone = |utc_offset_seconds, name: Option<&str>| TimeZone {
+            utc_offset_seconds,
+            name: name.map(ToOwned::to_owned),
+        };
+        assert_eq!(
+            time_zone(7200, Some("CEST")).to_string(),
+            "UTC+02:00 (CEST)"
+        );
+        assert_eq!(time_zone(-34200, None).to_string(), "UTC-09:30");
+        assert_eq!(time_zone(0, Some("UTC")).to_string(), "UTC+00:00 (UTC)");
+    }
+}
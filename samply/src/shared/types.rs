@@ -47,6 +47,8 @@ pub enum StackFrame {
     ReturnAddress(u64, StackMode),
     AdjustedReturnAddress(u64, StackMode),
     TruncatedStackMarker,
+    /// Replaces the callee-most frames of a stack which exceeded the maximum stack depth.
+    TruncatedByDepthLimit,
 }
 
 impl StackFrame {
@@ -56,6 +58,7 @@ impl StackFrame {
             StackFrame::ReturnAddress(_, stack_mode) => Some(*stack_mode),
             StackFrame::AdjustedReturnAddress(_, stack_mode) => Some(*stack_mode),
             StackFrame::TruncatedStackMarker => None,
+            StackFrame::TruncatedByDepthLimit => None,
         }
     }
 }
@@ -4,12 +4,47 @@ use fxprof_processed_profile::{CpuDelta, FrameInfo, MarkerHandle, ThreadHandle,
 
 use super::types::{FastHashMap, StackFrame, StackMode};
 
+/// The samples and markers of a process, in the order they were added.
+///
+/// Long recordings can have hundreds of millions of samples, so each entry
+/// is stored in a compact form: threads are referred to by a u32 index, and
+/// the rarely used parts (marker handles and extra label frames) live in a
+/// side table.
 #[derive(Debug, Clone, Default)]
 pub struct UnresolvedSamples {
-    samples_and_markers: Vec<UnresolvedSampleOrMarker>,
+    samples_and_markers: Vec<CompactSampleOrMarker>,
+    side_data: Vec<SideData>,
+    threads: Vec<ThreadHandle>,
+    thread_indexes: FastHashMap<ThreadHandle, u32>,
     prev_sample_info_per_thread: FastHashMap<ThreadHandle, PreviousSampleInfo>,
 }
 
+#[derive(Debug, Clone)]
+struct CompactSampleOrMarker {
+    timestamp: Timestamp,
+    timestamp_mono: u64,
+    /// Zero for markers.
+    cpu_delta: CpuDelta,
+    stack: UnresolvedStackHandle,
+    thread_index: u32,
+    /// Zero for markers.
+    weight: i32,
+    /// Index into `side_data`, or `NO_SIDE_DATA`. Markers always have side data.
+    side_data_index: u32,
+    /// The CPU which the sample was taken on, or `NO_CPU`.
+    cpu: u32,
+}
+
+const NO_SIDE_DATA: u32 = u32::MAX;
+const NO_CPU: u32 = u32::MAX;
+
+#[derive(Debug, Clone)]
+enum SideData {
+    ExtraLabelFrame(FrameInfo),
+    ExtraLeafFrame(FrameInfo),
+    MarkerHandle(MarkerHandle),
+}
+
 #[derive(Debug, Clone)]
 struct PreviousSampleInfo {
     stack: UnresolvedStackHandle,
@@ -17,14 +52,54 @@ struct PreviousSampleInfo {
 }
 
 impl UnresolvedSamples {
-    pub fn into_inner(self) -> Vec<UnresolvedSampleOrMarker> {
-        self.samples_and_markers
+    /// Returns the samples and markers in the order they were added.
+    pub fn into_samples_and_markers(self) -> impl Iterator<Item = UnresolvedSampleOrMarker> {
+        let UnresolvedSamples {
+            samples_and_markers,
+            side_data,
+            threads,
+            ..
+        } = self;
+        samples_and_markers.into_iter().map(move |sample| {
+            let side_data = side_data.get(sample.side_data_index as usize);
+            let extra_label_frame = match side_data {
+                Some(SideData::ExtraLabelFrame(frame)) => Some(frame.clone()),
+                _ => None,
+            };
+            let extra_leaf_frame = match side_data {
+                Some(SideData::ExtraLeafFrame(frame)) => Some(frame.clone()),
+                _ => None,
+            };
+            let sample_or_marker = match side_data {
+                Some(SideData::MarkerHandle(marker_handle)) => {
+                    SampleOrMarker::MarkerHandle(*marker_handle)
+                }
+                _ => SampleOrMarker::Sample(SampleData {
+                    cpu_delta: sample.cpu_delta,
+                    weight: sample.weight,
+                    cpu: (sample.cpu != NO_CPU).then_some(sample.cpu),
+                }),
+            };
+            UnresolvedSampleOrMarker {
+                thread_handle: threads[sample.thread_index as usize],
+                timestamp: sample.timestamp,
+                timestamp_mono: sample.timestamp_mono,
+                stack: sample.stack,
+                extra_label_frame,
+                extra_leaf_frame,
+                sample_or_marker,
+            }
+        })
     }
 
     pub fn is_empty(&self) -> bool {
         self.samples_and_markers.is_empty()
     }
 
+    pub fn len(&self) -> usize {
+        self.samples_and_markers.len()
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn add_sample(
         &mut self,
@@ -36,15 +111,90 @@ impl UnresolvedSamples {
         weight: i32,
         extra_label_frame: Option<FrameInfo>,
     ) {
-        let sample_index = self.samples_and_markers.len();
-        self.samples_and_markers.push(UnresolvedSampleOrMarker {
+        self.add_sample_with_side_data(
             thread_handle,
             timestamp,
             timestamp_mono,
             stack,
-            extra_label_frame,
-            sample_or_marker: SampleOrMarker::Sample(SampleData { weight, cpu_delta }),
-        });
+            cpu_delta,
+            weight,
+            None,
+            extra_label_frame.map(SideData::ExtraLabelFrame),
+        );
+    }
+
+    /// Like [`Self::add_sample`], for a sample whose CPU is known.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_sample_on_cpu(
+        &mut self,
+        thread_handle: ThreadHandle,
+        timestamp: Timestamp,
+        timestamp_mono: u64,
+        stack: UnresolvedStackHandle,
+        cpu_delta: CpuDelta,
+        weight: i32,
+        cpu: u32,
+    ) {
+        self.add_sample_with_side_data(
+            thread_handle,
+            timestamp,
+            timestamp_mono,
+            stack,
+            cpu_delta,
+            weight,
+            Some(cpu),
+            None,
+        );
+    }
+
+    /// Like [`Self::add_sample`], but with a label frame on top of the stack,
+    /// e.g. to put an off-CPU sample into a category which says what the
+    /// thread was waiting for.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_sample_with_leaf_frame(
+        &mut self,
+        thread_handle: ThreadHandle,
+        timestamp: Timestamp,
+        timestamp_mono: u64,
+        stack: UnresolvedStackHandle,
+        cpu_delta: CpuDelta,
+        weight: i32,
+        leaf_frame: FrameInfo,
+    ) {
+        self.add_sample_with_side_data(
+            thread_handle,
+            timestamp,
+            timestamp_mono,
+            stack,
+            cpu_delta,
+            weight,
+            None,
+            Some(SideData::ExtraLeafFrame(leaf_frame)),
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_sample_with_side_data(
+        &mut self,
+        thread_handle: ThreadHandle,
+        timestamp: Timestamp,
+        timestamp_mono: u64,
+        stack: UnresolvedStackHandle,
+        cpu_delta: CpuDelta,
+        weight: i32,
+        cpu: Option<u32>,
+        side_data: Option<SideData>,
+    ) {
+        let sample_index = self.push_sample(
+            thread_handle,
+            timestamp,
+            timestamp_mono,
+            stack,
+            cpu_delta,
+            weight,
+            cpu,
+            side_data,
+        );
         self.prev_sample_info_per_thread.insert(
             thread_handle,
             PreviousSampleInfo {
@@ -64,47 +214,36 @@ impl UnresolvedSamples {
         weight: i32,
         extra_label_frame: Option<FrameInfo>,
     ) {
+        let prev_sample_info = self.prev_sample_info_per_thread.get(&thread_handle);
+        if let Some(&PreviousSampleInfo {
+            prev_sample_index_if_zero_cpu: Some(sample_index),
+            ..
+        }) = prev_sample_info
+        {
+            let sample = &mut self.samples_and_markers[sample_index];
+            sample.timestamp = timestamp;
+            sample.weight += weight;
+            return;
+        }
+        let stack = match prev_sample_info {
+            Some(sample_info) => sample_info.stack,
+            None => UnresolvedStackHandle::EMPTY,
+        };
+        let sample_index = self.push_sample(
+            thread_handle,
+            timestamp,
+            timestamp_mono,
+            stack,
+            CpuDelta::ZERO,
+            weight,
+            None,
+            extra_label_frame.map(SideData::ExtraLabelFrame),
+        );
         match self.prev_sample_info_per_thread.entry(thread_handle) {
             Entry::Occupied(mut entry) => {
-                let sample_info = entry.get_mut();
-                if let Some(sample_index) = sample_info.prev_sample_index_if_zero_cpu {
-                    let sample = &mut self.samples_and_markers[sample_index];
-                    sample.timestamp = timestamp;
-                    let SampleOrMarker::Sample(ref mut data) = &mut sample.sample_or_marker else {
-                        panic!()
-                    };
-                    data.weight += weight;
-                } else {
-                    let stack = sample_info.stack;
-                    let sample_index = self.samples_and_markers.len();
-                    self.samples_and_markers.push(UnresolvedSampleOrMarker {
-                        thread_handle,
-                        timestamp,
-                        timestamp_mono,
-                        stack,
-                        extra_label_frame,
-                        sample_or_marker: SampleOrMarker::Sample(SampleData {
-                            weight,
-                            cpu_delta: CpuDelta::ZERO,
-                        }),
-                    });
-                    sample_info.prev_sample_index_if_zero_cpu = Some(sample_index);
-                }
+                entry.get_mut().prev_sample_index_if_zero_cpu = Some(sample_index);
             }
             Entry::Vacant(entry) => {
-                let stack = UnresolvedStackHandle::EMPTY;
-                let sample_index = self.samples_and_markers.len();
-                self.samples_and_markers.push(UnresolvedSampleOrMarker {
-                    thread_handle,
-                    timestamp,
-                    timestamp_mono,
-                    stack,
-                    extra_label_frame,
-                    sample_or_marker: SampleOrMarker::Sample(SampleData {
-                        weight,
-                        cpu_delta: CpuDelta::ZERO,
-                    }),
-                });
                 entry.insert(PreviousSampleInfo {
                     stack,
                     prev_sample_index_if_zero_cpu: Some(sample_index),
@@ -121,15 +260,66 @@ impl UnresolvedSamples {
         stack: UnresolvedStackHandle,
         marker_handle: MarkerHandle,
     ) {
-        self.samples_and_markers.push(UnresolvedSampleOrMarker {
-            thread_handle,
+        let thread_index = self.thread_index(thread_handle);
+        let side_data_index = self.add_side_data(SideData::MarkerHandle(marker_handle));
+        self.samples_and_markers.push(CompactSampleOrMarker {
             timestamp,
             timestamp_mono,
+            cpu_delta: CpuDelta::ZERO,
             stack,
-            extra_label_frame: None,
-            sample_or_marker: SampleOrMarker::MarkerHandle(marker_handle),
+            thread_index,
+            weight: 0,
+            side_data_index,
+            cpu: NO_CPU,
         });
     }
+
+    /// Appends a sample and returns its index.
+    #[allow(clippy::too_many_arguments)]
+    fn push_sample(
+        &mut self,
+        thread_handle: ThreadHandle,
+        timestamp: Timestamp,
+        timestamp_mono: u64,
+        stack: UnresolvedStackHandle,
+        cpu_delta: CpuDelta,
+        weight: i32,
+        cpu: Option<u32>,
+        side_data: Option<SideData>,
+    ) -> usize {
+        let thread_index = self.thread_index(thread_handle);
+        let side_data_index = match side_data {
+            Some(side_data) => self.add_side_data(side_data),
+            None => NO_SIDE_DATA,
+        };
+        let sample_index = self.samples_and_markers.len();
+        self.samples_and_markers.push(CompactSampleOrMarker {
+            timestamp,
+            timestamp_mono,
+            cpu_delta,
+            stack,
+            thread_index,
+            weight,
+            side_data_index,
+            cpu: cpu.unwrap_or(NO_CPU),
+        });
+        sample_index
+    }
+
+    fn thread_index(&mut self, thread_handle: ThreadHandle) -> u32 {
+        *self.thread_indexes.entry(thread_handle).or_insert_with(|| {
+            let thread_index = self.threads.len() as u32;
+            self.threads.push(thread_handle);
+            thread_index
+        })
+    }
+
+    fn add_side_data(&mut self, side_data: SideData) -> u32 {
+        let index = self.side_data.len() as u32;
+        assert!(index != NO_SIDE_DATA, "too many markers and label frames");
+        self.side_data.push(side_data);
+        index
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -139,6 +329,8 @@ pub struct UnresolvedSampleOrMarker {
     pub timestamp_mono: u64,
     pub stack: UnresolvedStackHandle,
     pub extra_label_frame: Option<FrameInfo>,
+    /// A label frame to add on top of the stack.
+    pub extra_leaf_frame: Option<FrameInfo>,
     pub sample_or_marker: SampleOrMarker,
 }
 
@@ -152,6 +344,7 @@ pub enum SampleOrMarker {
 pub struct SampleData {
     pub cpu_delta: CpuDelta,
     pub weight: i32,
+    pub cpu: Option<u32>,
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
@@ -162,13 +355,33 @@ impl UnresolvedStackHandle {
     pub const EMPTY: Self = Self(u32::MAX);
 }
 
+/// An index into [`UnresolvedStacks::frames`]. Distinct frames are much rarer
+/// than distinct stacks, so the stack nodes only store this index.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+struct FrameIndex(u32);
+
 #[derive(Debug, Clone, Default)]
 pub struct UnresolvedStacks {
-    pub stacks: Vec<(UnresolvedStackHandle, StackFrame)>, // (prefix, frame)
-    pub stack_lookup: FastHashMap<(UnresolvedStackHandle, StackFrame), UnresolvedStackHandle>, // (prefix, frame) -> stack index
+    /// (prefix, frame) for each stack node.
+    stacks: Vec<(UnresolvedStackHandle, FrameIndex)>,
+    /// (prefix, frame) -> stack index
+    stack_lookup: FastHashMap<(UnresolvedStackHandle, FrameIndex), UnresolvedStackHandle>,
+    frames: Vec<StackFrame>,
+    frame_lookup: FastHashMap<StackFrame, FrameIndex>,
+    /// If set, stacks deeper than this are truncated: Only the `max_depth` caller-most
+    /// frames are kept, and the rest is replaced with a single
+    /// [`StackFrame::TruncatedByDepthLimit`] frame.
+    max_depth: Option<usize>,
 }
 
 impl UnresolvedStacks {
+    pub fn with_max_depth(max_depth: Option<usize>) -> Self {
+        Self {
+            max_depth,
+            ..Default::default()
+        }
+    }
+
     /// Get the `UnresolvedStackHandle` for a stack. The stack must be ordered from
     /// caller-most to callee-most ("outside to inside").
     pub fn convert(&mut self, frames: impl Iterator<Item = StackFrame>) -> UnresolvedStackHandle {
@@ -180,14 +393,15 @@ impl UnresolvedStacks {
         mut prefix: UnresolvedStackHandle,
         frames: impl Iterator<Item = StackFrame>,
     ) -> UnresolvedStackHandle {
+        let mut depth = self.depth_if_limited(prefix);
         for frame in frames {
-            let x = (prefix, frame);
-            let node = *self.stack_lookup.entry(x).or_insert_with(|| {
-                let new_index = self.stacks.len() as u32;
-                self.stacks.push(x);
-                UnresolvedStackHandle(new_index)
-            });
-            prefix = node;
+            if let Some(max_depth) = self.max_depth {
+                if depth >= max_depth {
+                    return self.truncate(prefix);
+                }
+                depth += 1;
+            }
+            prefix = self.child(prefix, frame);
         }
         prefix
     }
@@ -198,25 +412,156 @@ impl UnresolvedStacks {
         &mut self,
         frames: impl Iterator<Item = StackFrame>,
     ) -> UnresolvedStackHandle {
-        let mut prefix = UnresolvedStackHandle::EMPTY;
-        for frame in frames.filter(|f| f.stack_mode() != Some(StackMode::Kernel)) {
-            let x = (prefix, frame);
-            let node = *self.stack_lookup.entry(x).or_insert_with(|| {
-                let new_index = self.stacks.len() as u32;
-                self.stacks.push(x);
-                UnresolvedStackHandle(new_index)
-            });
-            prefix = node;
+        self.convert(frames.filter(|f| f.stack_mode() != Some(StackMode::Kernel)))
+    }
+
+    fn child(&mut self, prefix: UnresolvedStackHandle, frame: StackFrame) -> UnresolvedStackHandle {
+        let frame_index = *self.frame_lookup.entry(frame).or_insert_with(|| {
+            let new_index = self.frames.len() as u32;
+            self.frames.push(frame);
+            FrameIndex(new_index)
+        });
+        let x = (prefix, frame_index);
+        *self.stack_lookup.entry(x).or_insert_with(|| {
+            let new_index = self.stacks.len() as u32;
+            assert!(
+                new_index != UnresolvedStackHandle::EMPTY.0,
+                "too many distinct stacks"
+            );
+            self.stacks.push(x);
+            UnresolvedStackHandle(new_index)
+        })
+    }
+
+    fn frame(&self, stack_index: UnresolvedStackHandle) -> StackFrame {
+        let (_prefix, frame_index) = self.stacks[stack_index.0 as usize];
+        self.frames[frame_index.0 as usize]
+    }
+
+    /// Returns the stack for `prefix` followed by the truncation frame, or `prefix`
+    /// itself if it has already been truncated.
+    fn truncate(&mut self, prefix: UnresolvedStackHandle) -> UnresolvedStackHandle {
+        if prefix != UnresolvedStackHandle::EMPTY
+            && self.frame(prefix) == StackFrame::TruncatedByDepthLimit
+        {
+            return prefix;
         }
-        prefix
+        self.child(prefix, StackFrame::TruncatedByDepthLimit)
+    }
+
+    /// Returns the depth of the stack, but only if we have a depth limit. This
+    /// saves us from walking the stack if we don't need to know its depth.
+    fn depth_if_limited(&self, mut stack_index: UnresolvedStackHandle) -> usize {
+        if self.max_depth.is_none() {
+            return 0;
+        }
+        let mut depth = 0;
+        while stack_index != UnresolvedStackHandle::EMPTY {
+            stack_index = self.stacks[stack_index.0 as usize].0;
+            depth += 1;
+        }
+        depth
     }
 
     // Appends the stack to `buf`, starting with the callee-most frame.
     pub fn convert_back(&self, mut stack_index: UnresolvedStackHandle, buf: &mut Vec<StackFrame>) {
         while stack_index != UnresolvedStackHandle::EMPTY {
-            let (prefix, frame) = self.stacks[stack_index.0 as usize];
-            buf.push(frame);
+            let (prefix, frame_index) = self.stacks[stack_index.0 as usize];
+            buf.push(self.frames[frame_index.0 as usize]);
             stack_index = prefix;
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{Profile, ReferenceTimestamp, SamplingInterval};
+
+    use super::*;
+
+    #[test]
+    fn test_compact_samples() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let process = profile.add_process("app", 1, Timestamp::from_millis_since_reference(0.0));
+        let start = Timestamp::from_millis_since_reference(0.0);
+        let thread_a = profile.add_thread(process, 1, start, true);
+        let thread_b = profile.add_thread(process, 2, start, false);
+
+        let mut stacks = UnresolvedStacks::default();
+        let frame = |addr| StackFrame::ReturnAddress(addr, StackMode::User);
+        let stack = stacks.convert([frame(1), frame(2)].into_iter());
+        let ts = Timestamp::from_millis_since_reference;
+
+        let mut samples = UnresolvedSamples::default();
+        samples.add_sample(thread_a, ts(1.0), 1, stack, CpuDelta::ZERO, 1, None);
+        samples.add_sample(
+            thread_b,
+            ts(2.0),
+            2,
+            stack,
+            CpuDelta::from_micros(5),
+            1,
+            None,
+        );
+        samples.add_sample_same_stack_zero_cpu(thread_a, ts(3.0), 3, 2, None);
+        samples.add_sample_same_stack_zero_cpu(thread_b, ts(4.0), 4, 1, None);
+
+        let samples: Vec<_> = samples
+            .into_samples_and_markers()
+            .map(|sample| {
+                let SampleOrMarker::Sample(data) = sample.sample_or_marker else {
+                    panic!("unexpected marker");
+                };
+                (
+                    sample.thread_handle,
+                    sample.timestamp,
+                    sample.stack,
+                    data.weight,
+                )
+            })
+            .collect();
+        assert_eq!(
+            samples,
+            vec![
+                (thread_a, ts(3.0), stack, 3),
+                (thread_b, ts(2.0), stack, 1),
+                (thread_b, ts(4.0), stack, 1),
+            ]
+        );
+
+        let mut buf = Vec::new();
+        stacks.convert_back(stack, &mut buf);
+        assert_eq!(buf, vec![frame(2), frame(1)]);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let frames = |addrs: &[u64]| {
+            addrs
+                .iter()
+                .map(|addr| StackFrame::ReturnAddress(*addr, StackMode::User))
+                .collect::<Vec<_>>()
+        };
+        let mut stacks = UnresolvedStacks::with_max_depth(Some(3));
+        let deep = stacks.convert(frames(&[1, 2, 3, 4, 5]).into_iter());
+        let deeper = stacks.convert(frames(&[1, 2, 3, 6, 7, 8]).into_iter());
+        assert_eq!(deep, deeper);
+        let extended = stacks.convert_with_prefix(deep, frames(&[9]).into_iter());
+        assert_eq!(deep, extended);
+
+        let mut buf = Vec::new();
+        stacks.convert_back(deep, &mut buf);
+        let mut expected = vec![StackFrame::TruncatedByDepthLimit];
+        expected.extend(frames(&[3, 2, 1]));
+        assert_eq!(buf, expected);
+
+        let shallow = stacks.convert(frames(&[1, 2]).into_iter());
+        buf.clear();
+        stacks.convert_back(shallow, &mut buf);
+        assert_eq!(buf, frames(&[2, 1]));
+    }
+}
@@ -0,0 +1,315 @@
+//! Splits a profile into one profile per process, for `samply split
+//! --by-process`. System-wide profiles can be too big for the profiler to
+//! load, and usually only one of their processes is of interest.
+//!
+//! The profile of a process has its threads and counters, and the libraries
+//! which its threads refer to, with the indexes into these tables rewritten.
+//! Everything else, e.g. the categories and the marker schemas, is copied
+//! into each profile.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SplitError {
+    #[error("Unexpected profile format: {0}")]
+    UnexpectedFormat(&'static str),
+}
+
+/// The profile of one process of the split profile.
+#[derive(Debug)]
+pub struct ProcessProfile {
+    pub pid: String,
+    pub process_name: String,
+    pub profile: Value,
+}
+
+/// The threads of one process, with their indexes in the split profile.
+#[derive(Default)]
+struct ProcessThreads {
+    pid: String,
+    threads: Vec<(usize, Value)>,
+    counters: Vec<Value>,
+}
+
+/// Splits the profile, and returns the profiles of the processes in the order
+/// of their first threads.
+pub fn split_by_process(profile: Value) -> Result<Vec<ProcessProfile>, SplitError> {
+    let Value::Object(mut base) = profile else {
+        return Err(SplitError::UnexpectedFormat("the profile is not an object"));
+    };
+    let Some(Value::Array(threads)) = base.remove("threads") else {
+        return Err(SplitError::UnexpectedFormat(
+            "the profile has no threads array",
+        ));
+    };
+    let libs = take_array(&mut base, "libs")?;
+    let counters = take_array(&mut base, "counters")?;
+
+    let mut processes: Vec<ProcessThreads> = Vec::new();
+    let mut process_index_by_pid: HashMap<String, usize> = HashMap::new();
+    // The process of each thread, by thread index.
+    let mut thread_processes = Vec::with_capacity(threads.len());
+    for (thread_index, thread) in threads.into_iter().enumerate() {
+        let pid = id_string(&thread["pid"]);
+        let process_index = *process_index_by_pid.entry(pid.clone()).or_insert_with(|| {
+            processes.push(ProcessThreads {
+                pid,
+                ..Default::default()
+            });
+            processes.len() - 1
+        });
+        thread_processes.push(process_index);
+        processes[process_index]
+            .threads
+            .push((thread_index, thread));
+    }
+
+    for counter in counters {
+        let process_index = counter["mainThreadIndex"]
+            .as_u64()
+            .and_then(|thread_index| thread_processes.get(thread_index as usize).copied())
+            .or_else(|| {
+                let pid = id_string(&counter["pid"]);
+                process_index_by_pid.get(&pid).copied()
+            });
+        if let Some(process_index) = process_index {
+            processes[process_index].counters.push(counter);
+        }
+    }
+
+    let process_profiles = processes
+        .into_iter()
+        .map(|process| process_profile(process, &base, &libs))
+        .collect();
+    Ok(process_profiles)
+}
+
+fn process_profile(
+    process: ProcessThreads,
+    base: &Map<String, Value>,
+    libs: &[Value],
+) -> ProcessProfile {
+    // The new index of each thread of the process, by its old index.
+    let new_thread_indexes: HashMap<u64, u64> = process
+        .threads
+        .iter()
+        .enumerate()
+        .map(|(new_index, (old_index, _))| (*old_index as u64, new_index as u64))
+        .collect();
+    let process_name = process
+        .threads
+        .iter()
+        .find(|(_, thread)| thread["isMainThread"] == Value::Bool(true))
+        .or(process.threads.first())
+        .and_then(|(_, thread)| thread["processName"].as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    let mut process_libs = Vec::new();
+    let mut new_lib_indexes: HashMap<u64, Value> = HashMap::new();
+    let mut map_lib = |index: u64| {
+        new_lib_indexes
+            .entry(index)
+            .or_insert_with(|| match libs.get(index as usize) {
+                Some(lib) => {
+                    process_libs.push(lib.clone());
+                    Value::from(process_libs.len() - 1)
+                }
+                None => Value::Null,
+            })
+            .clone()
+    };
+    let mut threads = Vec::with_capacity(process.threads.len());
+    for (_, mut thread) in process.threads {
+        remap_indexes(&mut thread["resourceTable"]["lib"], &mut map_lib);
+        remap_indexes(&mut thread["nativeSymbols"]["libIndex"], &mut map_lib);
+        threads.push(thread);
+    }
+
+    let mut counters = process.counters;
+    for counter in &mut counters {
+        remap_indexes(&mut counter["mainThreadIndex"], &mut |index| {
+            new_thread_indexes
+                .get(&index)
+                .map_or(Value::Null, |i| (*i).into())
+        });
+    }
+
+    let mut profile = base.clone();
+    if let Some(Value::Object(meta)) = profile.get_mut("meta") {
+        for key in ["initialVisibleThreads", "initialSelectedThreads"] {
+            let Some(Value::Array(indexes)) = meta.get_mut(key) else {
+                continue;
+            };
+            let remapped: Vec<Value> = indexes
+                .iter()
+                .filter_map(|index| new_thread_indexes.get(&index.as_u64()?))
+                .map(|index| (*index).into())
+                .collect();
+            if remapped.is_empty() {
+                meta.remove(key);
+            } else {
+                *indexes = remapped;
+            }
+        }
+    }
+    profile.insert("libs".to_string(), Value::Array(process_libs));
+    profile.insert("threads".to_string(), Value::Array(threads));
+    if !counters.is_empty() {
+        profile.insert("counters".to_string(), Value::Array(counters));
+    }
+
+    ProcessProfile {
+        pid: process.pid,
+        process_name,
+        profile: Value::Object(profile),
+    }
+}
+
+fn take_array(object: &mut Map<String, Value>, key: &str) -> Result<Vec<Value>, SplitError> {
+    match object.remove(key) {
+        Some(Value::Array(array)) => Ok(array),
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(_) => Err(SplitError::UnexpectedFormat("expected an array")),
+    }
+}
+
+/// Replaces an index, or each index in an array, with `f(index)`. Nulls and
+/// missing values are left alone.
+fn remap_indexes(value: &mut Value, f: &mut impl FnMut(u64) -> Value) {
+    match value {
+        Value::Number(index) => {
+            if let Some(index) = index.as_u64() {
+                *value = f(index);
+            }
+        }
+        Value::Array(indexes) => {
+            for index in indexes {
+                remap_indexes(index, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn id_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// The path for the profile of a process, e.g. `profile.1234-firefox.json.gz`
+/// for `profile.json.gz`, in `output_dir`, or next to the split profile.
+pub fn output_path(input: &Path, output_dir: Option<&Path>, process: &ProcessProfile) -> PathBuf {
+    let file_name = input.file_name().unwrap_or_default().to_string_lossy();
+    let (stem, extension) = match file_name.find(".json") {
+        Some(index) => file_name.split_at(index),
+        None => (&*file_name, ".json.gz"),
+    };
+    let process_name: String = process
+        .process_name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    let file_name = if process_name.is_empty() {
+        format!("{stem}.{}{extension}", process.pid)
+    } else {
+        format!("{stem}.{}-{process_name}{extension}", process.pid)
+    };
+    let dir = output_dir.or(input.parent()).unwrap_or(Path::new(""));
+    dir.join(file_name)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn split_by_pid() {
+        let profile = json!({
+            "meta": {
+                "version": 24,
+                "initialVisibleThreads": [0, 1, 2],
+                "initialSelectedThreads": [2],
+            },
+            "libs": [{ "name": "firefox" }, { "name": "libxul.so" }, { "name": "libc.so.6" }],
+            "threads": [
+                {
+                    "pid": "10",
+                    "processName": "firefox",
+                    "isMainThread": true,
+                    "resourceTable": { "lib": [2, null] },
+                    "nativeSymbols": { "libIndex": [2] },
+                },
+                {
+                    "pid": "20",
+                    "processName": "Web Content",
+                    "resourceTable": { "lib": [1, 2] },
+                    "nativeSymbols": { "libIndex": [1] },
+                },
+                { "pid": "10", "processName": "firefox", "resourceTable": { "lib": [0] } },
+            ],
+            "counters": [
+                { "name": "Memory", "pid": "20", "mainThreadIndex": 1 },
+                { "name": "Thread count", "pid": "10", "mainThreadIndex": 2 },
+            ],
+        });
+        let processes = split_by_process(profile).unwrap();
+        assert_eq!(processes.len(), 2);
+
+        let parent = &processes[0];
+        assert_eq!(
+            (parent.pid.as_str(), parent.process_name.as_str()),
+            ("10", "firefox")
+        );
+        assert_eq!(
+            parent.profile["libs"],
+            json!([{ "name": "libc.so.6" }, { "name": "firefox" }])
+        );
+        let threads = &parent.profile["threads"];
+        assert_eq!(threads[0]["resourceTable"]["lib"], json!([0, null]));
+        assert_eq!(threads[0]["nativeSymbols"]["libIndex"], json!([0]));
+        assert_eq!(threads[1]["resourceTable"]["lib"], json!([1]));
+        assert_eq!(
+            parent.profile["counters"],
+            json!([{ "name": "Thread count", "pid": "10", "mainThreadIndex": 1 }])
+        );
+        assert_eq!(
+            parent.profile["meta"]["initialVisibleThreads"],
+            json!([0, 1])
+        );
+        assert_eq!(parent.profile["meta"]["initialSelectedThreads"], json!([1]));
+
+        let content = &processes[1];
+        assert_eq!(
+            content.profile["libs"],
+            json!([{ "name": "libxul.so" }, { "name": "libc.so.6" }])
+        );
+        assert_eq!(
+            content.profile["threads"][0]["resourceTable"]["lib"],
+            json!([0, 1])
+        );
+        assert_eq!(content.profile["counters"][0]["mainThreadIndex"], json!(0));
+        assert_eq!(content.profile["meta"]["initialVisibleThreads"], json!([0]));
+        assert_eq!(content.profile["meta"].get("initialSelectedThreads"), None);
+
+        assert_eq!(
+            output_path(Path::new("out/profile.json.gz"), None, content),
+            Path::new("out/profile.20-Web_Content.json.gz")
+        );
+        assert_eq!(
+            output_path(Path::new("perf.json"), Some(Path::new("split")), parent),
+            Path::new("split/perf.10-firefox.json")
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! `samply stats`: Prints statistics which characterize a saved profile without
+//! loading it in the profiler, as Markdown: the distribution of stack depths, the
+//! sample counts per thread, the marker counts per name, and the sample weight
+//! per library.
+
+use std::io::{self, Write};
+
+use serde_json::Value;
+
+use crate::profile_query::ProfileQuery;
+use crate::report::{markdown_escape, write_markdown_table};
+
+/// The width of the longest bar of the stack depth histogram.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Summarizes a list of values, e.g. the stack depths of all samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Distribution {
+    min: usize,
+    median: usize,
+    p90: usize,
+    p99: usize,
+    max: usize,
+    mean: f64,
+}
+
+impl Distribution {
+    /// Returns `None` if there are no values.
+    fn new(values: &[usize]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_unstable();
+        // Nearest-rank percentiles.
+        let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+        Some(Distribution {
+            min: sorted[0],
+            median: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<usize>() as f64 / sorted.len() as f64,
+        })
+    }
+
+    fn write_markdown_table(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            w,
+            "| Min | Median | 90th percentile | 99th percentile | Max | Mean |"
+        )?;
+        writeln!(w, "| ---: | ---: | ---: | ---: | ---: | ---: |")?;
+        writeln!(
+            w,
+            "| {} | {} | {} | {} | {} | {:.1} |",
+            self.min, self.median, self.p90, self.p99, self.max, self.mean
+        )
+    }
+}
+
+/// Counts the values in power-of-two buckets: 1, 2-3, 4-7, 8-15 and so on.
+/// Returns the first value of each bucket with its count, up to the bucket of
+/// the largest value.
+fn power_of_two_histogram(values: &[usize]) -> Vec<(usize, usize)> {
+    let bucket = |value: usize| (usize::BITS - value.max(1).leading_zeros() - 1) as usize;
+    let Some(max_bucket) = values.iter().map(|&value| bucket(value)).max() else {
+        return Vec::new();
+    };
+    let mut counts = vec![0; max_bucket + 1];
+    for &value in values {
+        counts[bucket(value)] += 1;
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(bucket, count)| (1 << bucket, count))
+        .collect()
+}
+
+/// Writes the statistics of all threads to `w`. The tables of threads, markers
+/// and libraries are cut off after `top` rows.
+pub fn write_stats(w: &mut impl Write, query: &ProfileQuery, top: usize) -> io::Result<()> {
+    let depths = query.sample_stack_depths(None);
+    let sample_counts: Vec<usize> = (0..query.thread_count())
+        .map(|thread| query.sample_count(thread))
+        .collect();
+    let sample_count: usize = sample_counts.iter().sum();
+
+    writeln!(w, "## Samples")?;
+    writeln!(w)?;
+    writeln!(w, "| Threads | Samples | Samples with a stack |")?;
+    writeln!(w, "| ---: | ---: | ---: |")?;
+    writeln!(
+        w,
+        "| {} | {sample_count} | {} |",
+        query.thread_count(),
+        depths.len()
+    )?;
+
+    if let Some(distribution) = Distribution::new(&depths) {
+        writeln!(w)?;
+        writeln!(w, "## Stack depth")?;
+        writeln!(w)?;
+        distribution.write_markdown_table(w)?;
+        writeln!(w)?;
+        let histogram = power_of_two_histogram(&depths);
+        let max_count = histogram.iter().map(|(_, count)| *count).max().unwrap_or(0);
+        writeln!(w, "| Depth | Samples | |")?;
+        writeln!(w, "| ---: | ---: | --- |")?;
+        for (start, count) in histogram {
+            let range = match start {
+                1 => "1".to_string(),
+                _ => format!("{start}-{}", start * 2 - 1),
+            };
+            let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(max_count.max(1)));
+            writeln!(w, "| {range} | {count} | {bar} |")?;
+        }
+    }
+
+    if let Some(distribution) = Distribution::new(&sample_counts) {
+        writeln!(w)?;
+        writeln!(w, "## Samples per thread")?;
+        writeln!(w)?;
+        distribution.write_markdown_table(w)?;
+        writeln!(w)?;
+        let mut threads: Vec<usize> = (0..query.thread_count()).collect();
+        threads.sort_by_key(|&thread| std::cmp::Reverse(sample_counts[thread]));
+        writeln!(w, "| Thread | Name | Process | Pid | Tid | Samples |")?;
+        writeln!(w, "| ---: | --- | --- | ---: | ---: | ---: |")?;
+        for &thread in threads.iter().take(top) {
+            let Some(identity) = query.thread_identity(thread) else {
+                continue;
+            };
+            writeln!(
+                w,
+                "| {thread} | {} | {} | {} | {} | {} |",
+                markdown_escape(identity.name),
+                markdown_escape(identity.process_name.unwrap_or("")),
+                id_string(identity.pid),
+                id_string(identity.tid),
+                sample_counts[thread],
+            )?;
+        }
+        write_omitted_rows(w, threads.len(), top)?;
+    }
+
+    let markers = query.marker_counts(None);
+    if !markers.is_empty() {
+        writeln!(w)?;
+        writeln!(w, "## Markers")?;
+        writeln!(w)?;
+        writeln!(w, "| Marker | Count |")?;
+        writeln!(w, "| --- | ---: |")?;
+        for (name, count) in markers.iter().take(top) {
+            writeln!(w, "| {} | {count} |", markdown_escape(name))?;
+        }
+        write_omitted_rows(w, markers.len(), top)?;
+    }
+
+    let mut libraries = query.library_summary(None);
+    if !libraries.is_empty() {
+        let library_count = libraries.len();
+        libraries.truncate(top);
+        writeln!(w)?;
+        writeln!(w, "## Libraries")?;
+        writeln!(w)?;
+        write_markdown_table(w, "Library", &libraries, query.total_sample_weight(None))?;
+        write_omitted_rows(w, library_count, top)?;
+    }
+    Ok(())
+}
+
+fn write_omitted_rows(w: &mut impl Write, row_count: usize, top: usize) -> io::Result<()> {
+    if row_count > top {
+        writeln!(w)?;
+        writeln!(w, "{} more not shown, see `--top`.", row_count - top)?;
+    }
+    Ok(())
+}
+
+fn id_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::debugid::DebugId;
+    use fxprof_processed_profile::{
+        CategoryHandle, CpuDelta, Frame, FrameFlags, FrameInfo, LibraryInfo, Profile,
+        ReferenceTimestamp, SamplingInterval, Timestamp,
+    };
+
+    use super::*;
+
+    #[test]
+    fn distribution_and_stats() {
+        let distribution = Distribution::new(&[5, 1, 3, 2, 4]).unwrap();
+        assert_eq!(
+            distribution,
+            Distribution {
+                min: 1,
+                median: 3,
+                p90: 5,
+                p99: 5,
+                max: 5,
+                mean: 3.0,
+            }
+        );
+        assert_eq!(Distribution::new(&[]), None);
+        assert_eq!(
+            power_of_two_histogram(&[1, 2, 3, 9]),
+            vec![(1, 1), (2, 2), (4, 0), (8, 1)]
+        );
+
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let start = Timestamp::from_millis_since_reference(0.0);
+        let process = profile.add_process("app", 123, start);
+        let thread = profile.add_thread(process, 123, start, true);
+        let lib = profile.add_lib(LibraryInfo {
+            name: "libc.so.6".to_string(),
+            debug_name: "libc.so.6".to_string(),
+            path: "/usr/lib/libc.so.6".to_string(),
+            debug_path: "/usr/lib/libc.so.6".to_string(),
+            debug_id: DebugId::nil(),
+            code_id: None,
+            arch: None,
+            symbol_table: None,
+        });
+        let label = |profile: &mut Profile, name: &str| Frame::Label(profile.intern_string(name));
+        let stacks = [
+            vec![label(&mut profile, "main"), label(&mut profile, "work")],
+            vec![
+                label(&mut profile, "main"),
+                label(&mut profile, "work"),
+                label(&mut profile, "inner"),
+            ],
+            vec![label(&mut profile, "main")],
+            vec![
+                label(&mut profile, "main"),
+                Frame::RelativeAddressFromInstructionPointer(lib, 0x1234),
+            ],
+        ];
+        for (i, stack) in stacks.into_iter().enumerate() {
+            let frames = stack.into_iter().map(|frame| FrameInfo {
+                frame,
+                category_pair: CategoryHandle::OTHER.into(),
+                flags: FrameFlags::empty(),
+            });
+            let timestamp = Timestamp::from_millis_since_reference(i as f64);
+            profile.add_sample(thread, timestamp, frames, CpuDelta::ZERO, 1);
+        }
+        let json = serde_json::to_vec(&profile).unwrap();
+        let query = ProfileQuery::load(&json[..]).unwrap();
+        assert_eq!(query.sample_stack_depths(None), vec![2, 3, 1, 2]);
+
+        let mut output = Vec::new();
+        write_stats(&mut output, &query, 10).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("| 1 | 4 | 4 |"));
+        assert!(output.contains(&format!("| 2-3 | 3 | {} |", "#".repeat(HISTOGRAM_WIDTH))));
+        assert!(output.contains("| 0 | app | app | 123 | 123 | 4 |"));
+        // Label frames don't belong to a library, so only one sample counts.
+        assert!(output.contains("| libc.so.6 | 1 | 25.0% | 1 | 25.0% |"));
+    }
+}
@@ -0,0 +1,219 @@
+//! `samply symbolicate`: Resolves a list of addresses in one library to their
+//! functions, source files, line numbers and inlined frames, with the same
+//! symbol lookup that the profiler's symbol server uses. This is meant for
+//! build pipelines which need to symbolicate crash or sanitizer output.
+
+use std::io::{self, Write};
+
+use wholesym::{AddressInfo, LookupAddress, SymbolMap};
+
+/// How the input addresses are to be interpreted, see [`LookupAddress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressKind {
+    /// Relative to the image base address, e.g. an RVA on Windows.
+    Relative,
+    /// An address as written down in the binary, as `addr2line` takes it.
+    Svma,
+    /// An offset into the binary file.
+    FileOffset,
+}
+
+impl AddressKind {
+    fn lookup_address(self, address: u64) -> Option<LookupAddress> {
+        Some(match self {
+            AddressKind::Relative => LookupAddress::Relative(address.try_into().ok()?),
+            AddressKind::Svma => LookupAddress::Svma(address),
+            AddressKind::FileOffset => LookupAddress::FileOffset(address),
+        })
+    }
+}
+
+/// Parses one hex address per line, with or without `0x` prefix. Empty lines
+/// and lines starting with `#` are skipped.
+pub fn parse_addresses(text: &str) -> Result<Vec<u64>, String> {
+    let mut addresses = Vec::new();
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let hex = line
+            .strip_prefix("0x")
+            .or_else(|| line.strip_prefix("0X"))
+            .unwrap_or(line);
+        let address = u64::from_str_radix(hex, 16)
+            .map_err(|_| format!("Invalid address {line:?} on line {}", line_index + 1))?;
+        addresses.push(address);
+    }
+    Ok(addresses)
+}
+
+/// The lookup result for one input address.
+#[derive(Debug, Clone)]
+pub struct SymbolicatedAddress {
+    pub address: u64,
+    /// `None` if the address isn't inside a known function.
+    pub info: Option<AddressInfo>,
+    /// The offset of the address from the start of its function. Only known
+    /// for relative addresses, because symbol addresses are relative.
+    pub function_offset: Option<u64>,
+}
+
+/// Looks up each address in the symbol map.
+pub async fn symbolicate_addresses(
+    symbol_map: &SymbolMap,
+    kind: AddressKind,
+    addresses: &[u64],
+) -> Vec<SymbolicatedAddress> {
+    let mut results = Vec::with_capacity(addresses.len());
+    for &address in addresses {
+        let info = match kind.lookup_address(address) {
+            Some(lookup_address) => symbol_map.lookup(lookup_address).await,
+            None => None,
+        };
+        let function_offset = match (&info, kind) {
+            (Some(info), AddressKind::Relative) => address.checked_sub(info.symbol.address.into()),
+            _ => None,
+        };
+        results.push(SymbolicatedAddress {
+            address,
+            info,
+            function_offset,
+        });
+    }
+    results
+}
+
+/// Writes one block per address: the address with its function and the offset
+/// into the function, followed by an indented line for each frame, innermost
+/// inlined frame first.
+///
+/// ```text
+/// 0x1a2b: my_function + 0x1b
+///     inlined_helper at src/helper.rs:12
+///     my_function at src/lib.rs:40
+/// ```
+pub fn write_text(w: &mut impl Write, results: &[SymbolicatedAddress]) -> io::Result<()> {
+    for result in results {
+        let address = result.address;
+        let Some(info) = &result.info else {
+            writeln!(w, "{address:#x}: ??")?;
+            continue;
+        };
+        match result.function_offset {
+            Some(offset) => writeln!(w, "{address:#x}: {} + {offset:#x}", info.symbol.name)?,
+            None => writeln!(w, "{address:#x}: {}", info.symbol.name)?,
+        }
+        for frame in info.frames.iter().flatten() {
+            let function = frame.function.as_deref().unwrap_or("??");
+            match (&frame.file_path, frame.line_number) {
+                (Some(file), Some(line)) => {
+                    writeln!(w, "    {function} at {}:{line}", file.display_path())?
+                }
+                (Some(file), None) => writeln!(w, "    {function} at {}", file.display_path())?,
+                (None, _) => writeln!(w, "    {function}")?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a JSON array with an object per address.
+pub fn write_json(w: &mut impl Write, results: &[SymbolicatedAddress]) -> io::Result<()> {
+    let results: Vec<serde_json::Value> = results
+        .iter()
+        .map(|result| {
+            let address = format!("{:#x}", result.address);
+            let Some(info) = &result.info else {
+                return serde_json::json!({ "address": address, "function": null });
+            };
+            let frames: Vec<serde_json::Value> = info
+                .frames
+                .iter()
+                .flatten()
+                .map(|frame| {
+                    serde_json::json!({
+                        "function": frame.function,
+                        "file": frame.file_path.as_ref().map(|path| path.display_path()),
+                        "line": frame.line_number,
+                    })
+                })
+                .collect();
+            serde_json::json!({
+                "address": address,
+                "function": info.symbol.name,
+                "functionOffset": result.function_offset.map(|offset| format!("{offset:#x}")),
+                "frames": frames,
+            })
+        })
+        .collect();
+    serde_json::to_writer_pretty(&mut *w, &results)?;
+    writeln!(w)
+}
+
+#[cfg(test)]
+mod test {
+    use wholesym::{FrameDebugInfo, SourceFilePath, SymbolInfo};
+
+    use super::*;
+
+    #[test]
+    fn parse_and_write() {
+        assert_eq!(
+            parse_addresses("0x1a2b\n\n# comment\n  3C4d  \n"),
+            Ok(vec![0x1a2b, 0x3c4d])
+        );
+        assert_eq!(
+            parse_addresses("0x10\nxyz\n"),
+            Err("Invalid address \"xyz\" on line 2".to_string())
+        );
+
+        let info = AddressInfo {
+            symbol: SymbolInfo {
+                address: 0x1a10,
+                size: Some(0x40),
+                name: "my_function".to_string(),
+            },
+            frames: Some(vec![
+                FrameDebugInfo {
+                    function: Some("inlined_helper".to_string()),
+                    file_path: Some(SourceFilePath::new("src/helper.rs".to_string(), None)),
+                    line_number: Some(12),
+                },
+                FrameDebugInfo {
+                    function: Some("my_function".to_string()),
+                    file_path: Some(SourceFilePath::new("src/lib.rs".to_string(), None)),
+                    line_number: Some(40),
+                },
+            ]),
+        };
+        let results = vec![
+            SymbolicatedAddress {
+                address: 0x1a2b,
+                info: Some(info),
+                function_offset: Some(0x1b),
+            },
+            SymbolicatedAddress {
+                address: 0x5000,
+                info: None,
+                function_offset: None,
+            },
+        ];
+        let mut out = Vec::new();
+        write_text(&mut out, &results).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0x1a2b: my_function + 0x1b\n\
+             \x20   inlined_helper at src/helper.rs:12\n\
+             \x20   my_function at src/lib.rs:40\n\
+             0x5000: ??\n"
+        );
+
+        let mut out = Vec::new();
+        write_json(&mut out, &results).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json[0]["functionOffset"], "0x1b");
+        assert_eq!(json[0]["frames"][0]["file"], "src/helper.rs");
+        assert_eq!(json[1]["function"], serde_json::Value::Null);
+    }
+}
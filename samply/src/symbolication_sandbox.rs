@@ -0,0 +1,1218 @@
+//! Runs the symbolication in a separate process, so that parsing untrusted
+//! symbol files (PE, ELF, Mach-O, PDB, ...) can't compromise the process
+//! which runs the server. `samply import` converts the imported file in the
+//! same kind of helper process, because the file and the binaries it refers
+//! to are just as untrusted.
+//!
+//! The server sends each symbolication API request to the helper process as
+//! one line of JSON on its stdin, and reads the response as one line of JSON
+//! from its stdout.
+//!
+//! The helper processes restrict themselves before they parse anything:
+//!
+//!  - On Linux, a seccomp filter only lets them make the syscalls they need for
+//!    reading files, using memory and threads, and, if symbol servers are
+//!    configured, downloading. Landlock confines writes to the symbol cache
+//!    directories; without Landlock, nothing can be opened for writing.
+//!  - On macOS, a sandbox profile only allows reading files, writing beneath
+//!    the symbol cache directories, and, if symbol servers are configured,
+//!    outgoing network connections. Starting other processes is not allowed.
+//!  - On Windows, the helpers lower themselves to low integrity, which keeps
+//!    them from writing anywhere but the symbol cache directories, which get a
+//!    low integrity label. A job object keeps them from starting processes and
+//!    from using the desktop and the clipboard. AppContainers are not used,
+//!    because they can't read the user's files unless each file's ACL is
+//!    changed. Low integrity processes can still connect to the network, so if
+//!    symbol servers are configured, a compromised helper could send the
+//!    contents of the files it can read.
+//!
+//! Symbol files are only downloaded if they can be written to the symbol
+//! cache; if writes can't be confined to the cache directories, the helpers
+//! don't get network access either.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Mutex;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::profile_json_preparse::parse_libinfo_map_from_profile_file;
+use crate::server::{create_symbol_manager, symbol_cache_dirs};
+use crate::shared::save_profile::copy_profile_json_to_file;
+use crate::shared::symbol_props::SymbolProps;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HelperRequest {
+    path: String,
+    request_json: String,
+}
+
+/// The server side of the symbolication helper process.
+pub struct SandboxedSymbolicator {
+    helper: Mutex<HelperProcess>,
+}
+
+struct HelperProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl SandboxedSymbolicator {
+    /// Launches the helper process. This runs the current executable with the
+    /// hidden `run-symbolication-helper` subcommand.
+    pub fn spawn(
+        profile_filename: Option<&Path>,
+        symbol_props: &SymbolProps,
+        verbose: bool,
+    ) -> std::io::Result<Self> {
+        let mut command = Command::new(std::env::current_exe()?);
+        command
+            .arg("run-symbolication-helper")
+            .arg("--symbol-props")
+            .arg(serde_json::to_string(symbol_props)?);
+        if let Some(profile_filename) = profile_filename {
+            command.arg("--profile").arg(profile_filename);
+        }
+        if verbose {
+            command.arg("--verbose");
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(Self {
+            helper: Mutex::new(HelperProcess {
+                child,
+                stdin,
+                stdout,
+            }),
+        })
+    }
+
+    /// Forwards a symbolication API request to the helper process. Requests
+    /// are processed one at a time.
+    pub fn query_json_api(&self, path: &str, request_json: &str) -> String {
+        let mut helper = self.helper.lock().unwrap();
+        match helper.query(path, request_json) {
+            Ok(response_json) => response_json,
+            Err(err) => serde_json::json!({
+                "error": format!("The symbolication helper process failed: {err}")
+            })
+            .to_string(),
+        }
+    }
+}
+
+impl HelperProcess {
+    fn query(&mut self, path: &str, request_json: &str) -> std::io::Result<String> {
+        let request = HelperRequest {
+            path: path.to_string(),
+            request_json: request_json.to_string(),
+        };
+        let mut line = serde_json::to_string(&request)?;
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes())?;
+        self.stdin.flush()?;
+
+        let mut response_line = String::new();
+        if self.stdout.read_line(&mut response_line)? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "the helper process exited",
+            ));
+        }
+        Ok(serde_json::from_str(&response_line)?)
+    }
+}
+
+impl Drop for HelperProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+/// The entry point of the helper process.
+pub fn run_symbolication_helper(profile: Option<&Path>, symbol_props_json: &str, verbose: bool) {
+    let mut symbol_props: SymbolProps =
+        serde_json::from_str(symbol_props_json).expect("Invalid symbol props");
+
+    // This needs to happen before any threads are created, because the
+    // restrictions are only inherited by threads created afterwards.
+    let writable_dirs = symbol_cache_dirs(&symbol_props);
+    match restrict_process(needs_network(&symbol_props), &writable_dirs) {
+        Ok(true) => {}
+        Ok(false) => {
+            // Don't try to download anything if the network is off limits.
+            symbol_props.offline = true;
+        }
+        Err(err) => {
+            eprintln!("Could not sandbox the symbolication helper process: {err}");
+            std::process::exit(1);
+        }
+    }
+
+    serve_requests(profile, symbol_props, verbose);
+}
+
+#[tokio::main]
+async fn serve_requests(profile: Option<&Path>, symbol_props: SymbolProps, verbose: bool) {
+    let libinfo_map = match profile {
+        Some(profile) => File::open(profile)
+            .and_then(|file| parse_libinfo_map_from_profile_file(file, profile))
+            .unwrap_or_default(),
+        None => Default::default(),
+    };
+    let symbol_manager = create_symbol_manager(symbol_props, verbose, libinfo_map, profile);
+
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    for line in stdin.lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let response_json = match serde_json::from_str::<HelperRequest>(&line) {
+            Ok(request) => {
+                symbol_manager
+                    .query_json_api(&request.path, &request.request_json)
+                    .await
+            }
+            Err(err) => {
+                serde_json::json!({ "error": format!("Invalid request: {err}") }).to_string()
+            }
+        };
+        let response_line = serde_json::to_string(&response_json).unwrap();
+        if writeln!(stdout, "{response_line}")
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Converts a file for `samply import` in a helper process, which runs the
+/// current executable with the hidden `run-import-helper` subcommand and the
+/// given arguments. The helper writes the profile JSON to its stdout, and
+/// this saves it to `output`.
+pub fn run_import_helper(
+    helper_args: impl IntoIterator<Item = OsString>,
+    output: &Path,
+) -> std::io::Result<()> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg("run-import-helper")
+        .args(helper_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let profile_json = child.stdout.take().unwrap();
+    let result = copy_profile_json_to_file(profile_json, output);
+    let status = child.wait()?;
+    if !status.success() {
+        let _ = std::fs::remove_file(output);
+        return Err(std::io::Error::other(format!(
+            "the import helper process failed ({status})"
+        )));
+    }
+    result
+}
+
+/// Sandboxes the import helper process. It only needs to read files, and
+/// writes the profile to its stdout, which is already open.
+pub fn restrict_import_helper() {
+    if let Err(err) = restrict_process(false, &[]) {
+        eprintln!("Could not sandbox the import helper process: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Whether the helper needs to be able to download symbol files.
+fn needs_network(symbol_props: &SymbolProps) -> bool {
+    !symbol_props.offline
+        && (!symbol_props.windows_symbol_server.is_empty()
+            || !symbol_props.breakpad_symbol_server.is_empty()
+            || std::env::var_os("SAMPLY_USE_DEBUGINFOD").is_some()
+            || std::env::var_os("_NT_SYMBOL_PATH").is_some())
+}
+
+/// Restricts the helper process to reading files, using memory and threads,
+/// writing to the pipes it was given, and, if `allow_network` is set,
+/// connecting to other machines. Files can only be created, changed or
+/// deleted beneath `writable_dirs`, and only if the kernel supports Landlock.
+///
+/// Returns whether the process can use the network. This is only the case if
+/// `allow_network` is set and the process can write to `writable_dirs`,
+/// because downloaded files which can't be cached would be thrown away.
+#[cfg(target_os = "linux")]
+fn restrict_process(allow_network: bool, writable_dirs: &[PathBuf]) -> std::io::Result<bool> {
+    // Both Landlock and unprivileged seccomp filters require this.
+    let no_new_privs: libc::c_ulong = 1;
+    let unused: libc::c_ulong = 0;
+    if unsafe {
+        libc::prctl(
+            libc::PR_SET_NO_NEW_PRIVS,
+            no_new_privs,
+            unused,
+            unused,
+            unused,
+        )
+    } != 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let allow_writes = if writable_dirs.is_empty() {
+        false
+    } else if landlock::restrict_writes_to(writable_dirs)? {
+        true
+    } else {
+        eprintln!(
+            "Note: The kernel doesn't support Landlock, so the symbolication helper process can't write to the symbol cache, and won't download symbol files."
+        );
+        false
+    };
+    let allow_network = allow_network && allow_writes;
+    seccomp::install_filter(allow_network, allow_writes)?;
+    Ok(allow_network)
+}
+
+/// Restricts the helper process with the macOS sandbox, see [`seatbelt`].
+/// Returns whether the process can use the network, like the Linux version.
+#[cfg(target_os = "macos")]
+fn restrict_process(allow_network: bool, writable_dirs: &[PathBuf]) -> std::io::Result<bool> {
+    let writable_dirs = seatbelt::resolve_writable_dirs(writable_dirs);
+    if allow_network && writable_dirs.is_empty() {
+        eprintln!(
+            "Note: The symbol cache directories can't be created, so the symbolication helper process won't download symbol files."
+        );
+    }
+    let allow_network = allow_network && !writable_dirs.is_empty();
+    seatbelt::restrict(allow_network, &writable_dirs)?;
+    Ok(allow_network)
+}
+
+/// Restricts the helper process with a low integrity level and a job object,
+/// see [`low_integrity`]. Returns whether the process should use the network,
+/// like the Linux version. Unlike on Linux and macOS, this isn't enforced.
+#[cfg(windows)]
+fn restrict_process(allow_network: bool, writable_dirs: &[PathBuf]) -> std::io::Result<bool> {
+    let allow_writes = low_integrity::label_writable_dirs(writable_dirs);
+    if allow_network && !allow_writes {
+        eprintln!(
+            "Note: The symbol cache directories can't be made writable for the symbolication helper process, so it won't download symbol files."
+        );
+    }
+    low_integrity::restrict()?;
+    Ok(allow_network && allow_writes)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn restrict_process(allow_network: bool, _writable_dirs: &[PathBuf]) -> std::io::Result<bool> {
+    eprintln!(
+        "Note: Files are parsed in a separate process, but the process is only sandboxed on Linux, macOS and Windows."
+    );
+    Ok(allow_network)
+}
+
+/// Restricts writes with Landlock, see
+/// <https://docs.kernel.org/userspace-api/landlock.html>.
+#[cfg(target_os = "linux")]
+mod landlock {
+    use std::fs::File;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::path::PathBuf;
+
+    const CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    // The access rights from linux/landlock.h which change the file system.
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+    const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+    const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+    const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+    const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+    const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+    const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+    const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+    const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+    /// Since ABI version 2.
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    /// Since ABI version 3.
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: libc::c_int,
+    }
+
+    /// Makes `dirs` the only directories beneath which the process can create,
+    /// change or delete files. Reading stays unrestricted. The directories are
+    /// created if they don't exist. Returns false if the kernel doesn't
+    /// support Landlock.
+    pub fn restrict_writes_to(dirs: &[PathBuf]) -> std::io::Result<bool> {
+        let abi_version = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi_version < 0 {
+            let err = std::io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ENOSYS | libc::EOPNOTSUPP) => Ok(false),
+                _ => Err(err),
+            };
+        }
+
+        let mut handled_access = ACCESS_FS_WRITE_FILE
+            | ACCESS_FS_REMOVE_DIR
+            | ACCESS_FS_REMOVE_FILE
+            | ACCESS_FS_MAKE_CHAR
+            | ACCESS_FS_MAKE_DIR
+            | ACCESS_FS_MAKE_REG
+            | ACCESS_FS_MAKE_SOCK
+            | ACCESS_FS_MAKE_FIFO
+            | ACCESS_FS_MAKE_BLOCK
+            | ACCESS_FS_MAKE_SYM;
+        if abi_version >= 2 {
+            handled_access |= ACCESS_FS_REFER;
+        }
+        if abi_version >= 3 {
+            handled_access |= ACCESS_FS_TRUNCATE;
+        }
+
+        let ruleset_attr = RulesetAttr {
+            handled_access_fs: handled_access,
+        };
+        let ruleset_fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &ruleset_attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if ruleset_fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let ruleset = unsafe { OwnedFd::from_raw_fd(ruleset_fd as libc::c_int) };
+
+        for dir in dirs {
+            // A cache directory which can't be created stays read-only.
+            let Ok(dir) = std::fs::create_dir_all(dir).and_then(|()| File::open(dir)) else {
+                continue;
+            };
+            let path_beneath = PathBeneathAttr {
+                allowed_access: handled_access,
+                parent_fd: dir.as_raw_fd(),
+            };
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    RULE_PATH_BENEATH,
+                    &path_beneath as *const PathBeneathAttr,
+                    0,
+                )
+            };
+            if result != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(true)
+    }
+}
+
+/// The seccomp filter of the helper processes. It only allows the syscalls
+/// which are listed in [`rules`], and makes all others fail with `EPERM`.
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use libc::{c_long, sock_filter};
+
+    // Classic BPF opcodes, from linux/filter.h.
+    const LD_W_ABS: u16 = 0x20; // BPF_LD | BPF_W | BPF_ABS
+    const JMP_JEQ_K: u16 = 0x15; // BPF_JMP | BPF_JEQ | BPF_K
+    #[cfg(target_arch = "x86_64")]
+    const JMP_JGE_K: u16 = 0x35; // BPF_JMP | BPF_JGE | BPF_K
+    const JMP_JSET_K: u16 = 0x45; // BPF_JMP | BPF_JSET | BPF_K
+    const RET_K: u16 = 0x06; // BPF_RET | BPF_K
+
+    // Offsets into struct seccomp_data.
+    const NR_OFFSET: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+    const ARGS_OFFSET: u32 = 16;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    const AUDIT_ARCH: Option<u32> = None;
+
+    const RET_ALLOW: u32 = libc::SECCOMP_RET_ALLOW;
+
+    const fn ret_errno(errno: libc::c_int) -> u32 {
+        libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA)
+    }
+
+    /// What the filter does with a syscall. The argument checks look at the
+    /// lower 32 bits of the argument, which are enough for the flags and
+    /// values that are checked.
+    #[derive(Debug, Clone)]
+    enum Rule {
+        Allow,
+        /// Allowed if none of the `flags` are set in the argument.
+        AllowWithoutFlags {
+            arg: u32,
+            flags: u32,
+        },
+        /// Allowed if one of the `flags` is set in the argument.
+        AllowWithFlag {
+            arg: u32,
+            flags: u32,
+        },
+        /// Allowed if the argument is one of the `values`.
+        AllowValues {
+            arg: u32,
+            values: Vec<u32>,
+        },
+        Fail(libc::c_int),
+    }
+
+    impl Rule {
+        /// The instructions which run after the syscall number matched.
+        /// They always return.
+        fn instructions(&self) -> Vec<sock_filter> {
+            let deny = stmt(RET_K, ret_errno(libc::EPERM));
+            match self {
+                Rule::Allow => vec![stmt(RET_K, RET_ALLOW)],
+                Rule::AllowWithoutFlags { arg, flags } => vec![
+                    load_arg(*arg),
+                    jump(JMP_JSET_K, *flags, 1, 0),
+                    stmt(RET_K, RET_ALLOW),
+                    deny,
+                ],
+                Rule::AllowWithFlag { arg, flags } => vec![
+                    load_arg(*arg),
+                    jump(JMP_JSET_K, *flags, 0, 1),
+                    stmt(RET_K, RET_ALLOW),
+                    deny,
+                ],
+                Rule::AllowValues { arg, values } => {
+                    let mut instructions = vec![load_arg(*arg)];
+                    for (i, value) in values.iter().enumerate() {
+                        // Jump over the remaining comparisons and the deny.
+                        let skip = u8::try_from(values.len() - i).unwrap();
+                        instructions.push(jump(JMP_JEQ_K, *value, skip, 0));
+                    }
+                    instructions.push(deny);
+                    instructions.push(stmt(RET_K, RET_ALLOW));
+                    instructions
+                }
+                Rule::Fail(errno) => vec![stmt(RET_K, ret_errno(*errno))],
+            }
+        }
+    }
+
+    fn stmt(code: u16, k: u32) -> sock_filter {
+        sock_filter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter { code, jt, jf, k }
+    }
+
+    fn load_arg(arg: u32) -> sock_filter {
+        stmt(LD_W_ABS, ARGS_OFFSET + 8 * arg)
+    }
+
+    /// The syscalls which the helper processes may make. `pid` is the
+    /// process's own pid, which is the only one it may send signals to.
+    fn rules(allow_network: bool, allow_writes: bool, pid: u32) -> Vec<(c_long, Rule)> {
+        use libc::*;
+
+        let write_flags = (O_WRONLY | O_RDWR | O_CREAT | O_TRUNC) as u32;
+        let read_only_open = |arg| {
+            if allow_writes {
+                Rule::Allow
+            } else {
+                Rule::AllowWithoutFlags {
+                    arg,
+                    flags: write_flags,
+                }
+            }
+        };
+        let no_exec = Rule::AllowWithoutFlags {
+            arg: 2,
+            flags: PROT_EXEC as u32,
+        };
+        let own_pid = |arg| Rule::AllowValues {
+            arg,
+            values: vec![pid],
+        };
+
+        let mut rules = vec![
+            // Reading files and writing to file descriptors which are already open.
+            (SYS_openat, read_only_open(2)),
+            (SYS_read, Rule::Allow),
+            (SYS_readv, Rule::Allow),
+            (SYS_pread64, Rule::Allow),
+            (SYS_preadv, Rule::Allow),
+            (SYS_write, Rule::Allow),
+            (SYS_writev, Rule::Allow),
+            (SYS_close, Rule::Allow),
+            (SYS_fstat, Rule::Allow),
+            (SYS_newfstatat, Rule::Allow),
+            (SYS_statx, Rule::Allow),
+            (SYS_fstatfs, Rule::Allow),
+            (SYS_statfs, Rule::Allow),
+            (SYS_lseek, Rule::Allow),
+            (SYS_getdents64, Rule::Allow),
+            (SYS_readlinkat, Rule::Allow),
+            (SYS_faccessat, Rule::Allow),
+            (SYS_faccessat2, Rule::Allow),
+            (SYS_getcwd, Rule::Allow),
+            (SYS_fcntl, Rule::Allow),
+            (SYS_dup, Rule::Allow),
+            (SYS_dup3, Rule::Allow),
+            (SYS_pipe2, Rule::Allow),
+            // Only the ioctls for non-blocking and close-on-exec file
+            // descriptors, and for checking whether stderr is a terminal.
+            // This keeps TIOCSTI from typing into the terminal.
+            (
+                SYS_ioctl,
+                Rule::AllowValues {
+                    arg: 1,
+                    values: vec![FIONBIO as u32, FIOCLEX as u32, TCGETS as u32],
+                },
+            ),
+            // Memory, which can't be made executable.
+            (SYS_mmap, no_exec.clone()),
+            (SYS_mprotect, no_exec),
+            (SYS_munmap, Rule::Allow),
+            (SYS_mremap, Rule::Allow),
+            (SYS_madvise, Rule::Allow),
+            (SYS_brk, Rule::Allow),
+            // Threads, but no child processes. clone3 can't be filtered by its
+            // flags, so it fails in a way that makes the C library use clone.
+            (
+                SYS_clone,
+                Rule::AllowWithFlag {
+                    arg: 0,
+                    flags: CLONE_THREAD as u32,
+                },
+            ),
+            (SYS_clone3, Rule::Fail(ENOSYS)),
+            (SYS_futex, Rule::Allow),
+            (SYS_set_robust_list, Rule::Allow),
+            (SYS_rseq, Rule::Allow),
+            (SYS_sched_yield, Rule::Allow),
+            (SYS_sched_getaffinity, Rule::Allow),
+            (
+                SYS_prctl,
+                Rule::AllowValues {
+                    arg: 0,
+                    values: vec![PR_SET_NAME as u32, PR_GET_NAME as u32],
+                },
+            ),
+            (
+                SYS_prlimit64,
+                Rule::AllowValues {
+                    arg: 0,
+                    values: vec![0, pid],
+                },
+            ),
+            (SYS_getrlimit, Rule::Allow),
+            (SYS_exit, Rule::Allow),
+            (SYS_exit_group, Rule::Allow),
+            // Signals, only to the process itself.
+            (SYS_rt_sigaction, Rule::Allow),
+            (SYS_rt_sigprocmask, Rule::Allow),
+            (SYS_rt_sigreturn, Rule::Allow),
+            (SYS_sigaltstack, Rule::Allow),
+            (SYS_restart_syscall, Rule::Allow),
+            (SYS_tgkill, own_pid(0)),
+            // Event loops and timers.
+            (SYS_epoll_create1, Rule::Allow),
+            (SYS_epoll_ctl, Rule::Allow),
+            (SYS_epoll_pwait, Rule::Allow),
+            (SYS_epoll_pwait2, Rule::Allow),
+            (SYS_eventfd2, Rule::Allow),
+            (SYS_ppoll, Rule::Allow),
+            (SYS_clock_gettime, Rule::Allow),
+            (SYS_clock_getres, Rule::Allow),
+            (SYS_clock_nanosleep, Rule::Allow),
+            (SYS_nanosleep, Rule::Allow),
+            (SYS_gettimeofday, Rule::Allow),
+            // Information about the process and the system.
+            (SYS_getpid, Rule::Allow),
+            (SYS_gettid, Rule::Allow),
+            (SYS_getuid, Rule::Allow),
+            (SYS_geteuid, Rule::Allow),
+            (SYS_getgid, Rule::Allow),
+            (SYS_getegid, Rule::Allow),
+            (SYS_getrandom, Rule::Allow),
+            (SYS_uname, Rule::Allow),
+            (SYS_sysinfo, Rule::Allow),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        rules.extend([
+            (SYS_open, read_only_open(1)),
+            (SYS_stat, Rule::Allow),
+            (SYS_lstat, Rule::Allow),
+            (SYS_access, Rule::Allow),
+            (SYS_readlink, Rule::Allow),
+            (SYS_dup2, Rule::Allow),
+            (SYS_pipe, Rule::Allow),
+            (SYS_poll, Rule::Allow),
+            (SYS_epoll_wait, Rule::Allow),
+        ]);
+
+        if allow_writes {
+            // Landlock restricts where these can make changes.
+            rules.extend([
+                (SYS_pwrite64, Rule::Allow),
+                (SYS_ftruncate, Rule::Allow),
+                (SYS_fallocate, Rule::Allow),
+                (SYS_fsync, Rule::Allow),
+                (SYS_fdatasync, Rule::Allow),
+                (SYS_flock, Rule::Allow),
+                (SYS_fchmod, Rule::Allow),
+                (SYS_utimensat, Rule::Allow),
+                (SYS_mkdirat, Rule::Allow),
+                (SYS_unlinkat, Rule::Allow),
+                (SYS_renameat, Rule::Allow),
+                (SYS_renameat2, Rule::Allow),
+            ]);
+            #[cfg(target_arch = "x86_64")]
+            rules.extend([
+                (SYS_mkdir, Rule::Allow),
+                (SYS_rmdir, Rule::Allow),
+                (SYS_unlink, Rule::Allow),
+                (SYS_rename, Rule::Allow),
+            ]);
+        }
+
+        if allow_network {
+            // Internet sockets only, so that local services which listen on
+            // Unix sockets, like SSH agents, can't be reached.
+            rules.extend([
+                (
+                    SYS_socket,
+                    Rule::AllowValues {
+                        arg: 0,
+                        values: vec![AF_INET as u32, AF_INET6 as u32],
+                    },
+                ),
+                (SYS_connect, Rule::Allow),
+                (SYS_getsockname, Rule::Allow),
+                (SYS_getpeername, Rule::Allow),
+                (SYS_getsockopt, Rule::Allow),
+                (SYS_setsockopt, Rule::Allow),
+                (SYS_sendto, Rule::Allow),
+                (SYS_sendmsg, Rule::Allow),
+                (SYS_sendmmsg, Rule::Allow),
+                (SYS_recvfrom, Rule::Allow),
+                (SYS_recvmsg, Rule::Allow),
+                (SYS_shutdown, Rule::Allow),
+            ]);
+        }
+        rules
+    }
+
+    /// Compiles the rules into a seccomp filter program. Syscalls without a
+    /// rule fail with `EPERM`, and syscalls of other ABIs kill the process.
+    fn build_program(audit_arch: u32, rules: &[(c_long, Rule)]) -> Vec<sock_filter> {
+        let mut program = vec![
+            // Kill the process if a syscall is made with a different ABI, which
+            // would use different syscall numbers.
+            stmt(LD_W_ABS, ARCH_OFFSET),
+            jump(JMP_JEQ_K, audit_arch, 1, 0),
+            stmt(RET_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(LD_W_ABS, NR_OFFSET),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        {
+            // Same for the x32 ABI, which uses the same arch value.
+            program.push(jump(JMP_JGE_K, 0x4000_0000, 0, 1));
+            program.push(stmt(RET_K, libc::SECCOMP_RET_KILL_PROCESS));
+        }
+        for (syscall, rule) in rules {
+            let instructions = rule.instructions();
+            let skip = u8::try_from(instructions.len()).unwrap();
+            program.push(jump(JMP_JEQ_K, *syscall as u32, 0, skip));
+            program.extend(instructions);
+        }
+        program.push(stmt(RET_K, ret_errno(libc::EPERM)));
+        program
+    }
+
+    /// Installs the filter. `PR_SET_NO_NEW_PRIVS` must already be set.
+    pub fn install_filter(allow_network: bool, allow_writes: bool) -> std::io::Result<()> {
+        let Some(audit_arch) = AUDIT_ARCH else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "the seccomp filter is not available for this architecture",
+            ));
+        };
+        let rules = rules(allow_network, allow_writes, std::process::id());
+        let mut program = build_program(audit_arch, &rules);
+        let program = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_mut_ptr(),
+        };
+        if unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER as libc::c_ulong,
+                &program as *const libc::sock_fprog,
+            )
+        } != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(all(test, any(target_arch = "x86_64", target_arch = "aarch64")))]
+    mod test {
+        use super::*;
+
+        const PID: u32 = 4242;
+
+        /// Runs a classic BPF program on a seccomp_data for the native arch.
+        fn run(program: &[sock_filter], nr: c_long, args: [u64; 6]) -> u32 {
+            let mut data = Vec::new();
+            data.extend_from_slice(&(nr as u32).to_le_bytes());
+            data.extend_from_slice(&AUDIT_ARCH.unwrap().to_le_bytes());
+            data.extend_from_slice(&0u64.to_le_bytes());
+            for arg in args {
+                data.extend_from_slice(&arg.to_le_bytes());
+            }
+            let mut accumulator = 0;
+            let mut pc = 0;
+            loop {
+                let instruction = program[pc];
+                pc += 1;
+                let k = instruction.k;
+                let taken = match instruction.code {
+                    LD_W_ABS => {
+                        let offset = k as usize;
+                        accumulator =
+                            u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+                        continue;
+                    }
+                    RET_K => return k,
+                    JMP_JEQ_K => accumulator == k,
+                    JMP_JSET_K => accumulator & k != 0,
+                    #[cfg(target_arch = "x86_64")]
+                    JMP_JGE_K => accumulator >= k,
+                    code => panic!("unexpected opcode {code:#x}"),
+                };
+                pc += usize::from(if taken {
+                    instruction.jt
+                } else {
+                    instruction.jf
+                });
+            }
+        }
+
+        fn filter(allow_network: bool, allow_writes: bool) -> Vec<sock_filter> {
+            build_program(
+                AUDIT_ARCH.unwrap(),
+                &rules(allow_network, allow_writes, PID),
+            )
+        }
+
+        fn call(program: &[sock_filter], nr: c_long, args: &[u64]) -> u32 {
+            let mut all_args = [0; 6];
+            all_args[..args.len()].copy_from_slice(args);
+            run(program, nr, all_args)
+        }
+
+        const EPERM: u32 = ret_errno(libc::EPERM);
+
+        #[test]
+        fn files_are_read_only() {
+            let program = filter(false, false);
+            let openat =
+                |flags: libc::c_int| call(&program, libc::SYS_openat, &[0, 0, flags as u64]);
+            assert_eq!(openat(libc::O_RDONLY | libc::O_CLOEXEC), RET_ALLOW);
+            assert_eq!(openat(libc::O_WRONLY), EPERM);
+            assert_eq!(openat(libc::O_RDWR), EPERM);
+            assert_eq!(openat(libc::O_CREAT), EPERM);
+            assert_eq!(openat(libc::O_RDONLY | libc::O_TRUNC), EPERM);
+            assert_eq!(call(&program, libc::SYS_unlinkat, &[]), EPERM);
+            assert_eq!(call(&program, libc::SYS_renameat2, &[]), EPERM);
+
+            let program = filter(false, true);
+            let openat =
+                |flags: libc::c_int| call(&program, libc::SYS_openat, &[0, 0, flags as u64]);
+            assert_eq!(openat(libc::O_WRONLY | libc::O_CREAT), RET_ALLOW);
+            assert_eq!(call(&program, libc::SYS_unlinkat, &[]), RET_ALLOW);
+        }
+
+        #[test]
+        fn no_processes_or_executable_memory() {
+            let program = filter(true, true);
+            assert_eq!(call(&program, libc::SYS_execve, &[]), EPERM);
+            assert_eq!(call(&program, libc::SYS_execveat, &[]), EPERM);
+            assert_eq!(call(&program, libc::SYS_ptrace, &[]), EPERM);
+            assert_eq!(call(&program, libc::SYS_process_vm_writev, &[]), EPERM);
+            assert_eq!(call(&program, libc::SYS_io_uring_setup, &[]), EPERM);
+            assert_eq!(call(&program, libc::SYS_kill, &[]), EPERM);
+            assert_eq!(
+                call(&program, libc::SYS_tgkill, &[PID as u64, 1]),
+                RET_ALLOW
+            );
+            assert_eq!(call(&program, libc::SYS_tgkill, &[1, 1]), EPERM);
+
+            let thread_flags = (libc::CLONE_VM | libc::CLONE_THREAD) as u64;
+            assert_eq!(call(&program, libc::SYS_clone, &[thread_flags]), RET_ALLOW);
+            assert_eq!(
+                call(&program, libc::SYS_clone, &[libc::SIGCHLD as u64]),
+                EPERM
+            );
+            assert_eq!(
+                call(&program, libc::SYS_clone3, &[]),
+                ret_errno(libc::ENOSYS)
+            );
+
+            let read_write = (libc::PROT_READ | libc::PROT_WRITE) as u64;
+            let read_exec = (libc::PROT_READ | libc::PROT_EXEC) as u64;
+            assert_eq!(
+                call(&program, libc::SYS_mmap, &[0, 4096, read_write]),
+                RET_ALLOW
+            );
+            assert_eq!(call(&program, libc::SYS_mmap, &[0, 4096, read_exec]), EPERM);
+            assert_eq!(
+                call(&program, libc::SYS_mprotect, &[0, 4096, read_exec]),
+                EPERM
+            );
+
+            let ioctl = |request: u32| call(&program, libc::SYS_ioctl, &[2, u64::from(request)]);
+            assert_eq!(ioctl(libc::FIONBIO as u32), RET_ALLOW);
+            assert_eq!(ioctl(libc::TIOCSTI as u32), EPERM);
+        }
+
+        #[test]
+        fn network_only_if_needed() {
+            let inet = [libc::AF_INET as u64, libc::SOCK_STREAM as u64];
+            let unix = [libc::AF_UNIX as u64, libc::SOCK_STREAM as u64];
+            let program = filter(false, false);
+            assert_eq!(call(&program, libc::SYS_socket, &inet), EPERM);
+            assert_eq!(call(&program, libc::SYS_connect, &[]), EPERM);
+
+            let program = filter(true, false);
+            assert_eq!(call(&program, libc::SYS_socket, &inet), RET_ALLOW);
+            assert_eq!(call(&program, libc::SYS_socket, &unix), EPERM);
+            assert_eq!(call(&program, libc::SYS_connect, &[]), RET_ALLOW);
+        }
+
+        #[test]
+        fn other_abis_are_killed() {
+            let program = filter(false, false);
+            // Check a different arch value by changing the expected one.
+            let mut other_arch = program.clone();
+            other_arch[1].k ^= 1;
+            assert_eq!(
+                call(&other_arch, libc::SYS_read, &[]),
+                libc::SECCOMP_RET_KILL_PROCESS
+            );
+            #[cfg(target_arch = "x86_64")]
+            assert_eq!(
+                call(&program, 0x4000_0000 | libc::SYS_read, &[]),
+                libc::SECCOMP_RET_KILL_PROCESS
+            );
+        }
+    }
+}
+
+/// Restricts the process with the macOS sandbox ("Seatbelt"). The sandbox
+/// profile is written in SBPL, the language of the profiles in
+/// /System/Library/Sandbox/Profiles.
+#[cfg(target_os = "macos")]
+mod seatbelt {
+    use std::ffi::{c_char, c_int, CStr, CString};
+    use std::fmt::Write;
+    use std::path::PathBuf;
+
+    extern "C" {
+        fn sandbox_init(profile: *const c_char, flags: u64, errorbuf: *mut *mut c_char) -> c_int;
+        fn sandbox_free_error(errorbuf: *mut c_char);
+    }
+
+    /// The Mach services for resolving host names and for reading the proxy
+    /// settings.
+    const NETWORK_MACH_SERVICES: &[&str] = &[
+        "com.apple.dnssd.service",
+        "com.apple.system.opendirectoryd.libinfo",
+        "com.apple.SystemConfiguration.configd",
+    ];
+
+    /// Creates the directories and resolves their paths, because the sandbox
+    /// compares resolved paths, for example /private/tmp instead of /tmp.
+    /// Directories which can't be created are left out, so they stay read-only.
+    pub fn resolve_writable_dirs(dirs: &[PathBuf]) -> Vec<String> {
+        dirs.iter()
+            .filter_map(|dir| {
+                let dir = std::fs::create_dir_all(dir)
+                    .and_then(|()| dir.canonicalize())
+                    .ok()?;
+                dir.into_os_string().into_string().ok()
+            })
+            .collect()
+    }
+
+    /// The sandbox profile. Everything which isn't allowed is denied.
+    fn profile(allow_network: bool, writable_dirs: &[String]) -> String {
+        let mut profile = String::from(
+            "(version 1)\n\
+             (deny default)\n\
+             (allow file-read*)\n\
+             (allow sysctl-read)\n\
+             (allow process-info* (target self))\n\
+             (allow signal (target self))\n",
+        );
+        for dir in writable_dirs {
+            writeln!(profile, "(allow file-write* (subpath {}))", quote(dir)).unwrap();
+        }
+        if allow_network {
+            profile.push_str("(allow system-socket)\n(allow network-outbound (remote ip))\n");
+            for service in NETWORK_MACH_SERVICES {
+                writeln!(
+                    profile,
+                    "(allow mach-lookup (global-name {}))",
+                    quote(service)
+                )
+                .unwrap();
+            }
+        }
+        profile
+    }
+
+    /// Quotes a string for SBPL.
+    fn quote(s: &str) -> String {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+
+    /// Applies the sandbox profile to the process. This can't be undone.
+    pub fn restrict(allow_network: bool, writable_dirs: &[String]) -> std::io::Result<()> {
+        let profile = CString::new(profile(allow_network, writable_dirs))?;
+        let mut error = std::ptr::null_mut();
+        if unsafe { sandbox_init(profile.as_ptr(), 0, &mut error) } != 0 {
+            let message = if error.is_null() {
+                "unknown error".to_string()
+            } else {
+                let message = unsafe { CStr::from_ptr(error) }
+                    .to_string_lossy()
+                    .into_owned();
+                unsafe { sandbox_free_error(error) };
+                message
+            };
+            return Err(std::io::Error::other(format!(
+                "sandbox_init failed: {message}"
+            )));
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn network_only_if_needed() {
+            let dirs = ["/Users/me/Library/Caches/samply/symbols".to_string()];
+            let offline = profile(false, &dirs);
+            assert!(offline.starts_with("(version 1)\n(deny default)\n"));
+            assert!(offline.contains(
+                "(allow file-write* (subpath \"/Users/me/Library/Caches/samply/symbols\"))"
+            ));
+            assert!(!offline.contains("network-outbound"));
+            assert!(!offline.contains("mach-lookup"));
+
+            let online = profile(true, &dirs);
+            assert!(online.contains("(allow network-outbound (remote ip))"));
+            assert!(
+                online.contains("(allow mach-lookup (global-name \"com.apple.dnssd.service\"))")
+            );
+        }
+
+        #[test]
+        fn paths_are_quoted() {
+            assert_eq!(quote(r#"/a "b"\c"#), r#""/a \"b\"\\c""#);
+        }
+    }
+}
+
+/// Lowers the process to low integrity, see
+/// <https://learn.microsoft.com/en-us/windows/win32/secauthz/mandatory-integrity-control>,
+/// and puts it into a job object which doesn't allow any other processes.
+#[cfg(windows)]
+mod low_integrity {
+    use std::ffi::c_void;
+    use std::path::{Path, PathBuf};
+
+    use windows::core::{w, HSTRING, PCWSTR};
+    use windows::Win32::Foundation::{CloseHandle, LocalFree, BOOL, HANDLE, HLOCAL};
+    use windows::Win32::Security::Authorization::{
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, SetNamedSecurityInfoW,
+        SDDL_REVISION_1, SE_FILE_OBJECT,
+    };
+    use windows::Win32::Security::{
+        CreateWellKnownSid, GetSecurityDescriptorSacl, SetTokenInformation, TokenIntegrityLevel,
+        WinLowLabelSid, ACL, LABEL_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, PSID,
+        SECURITY_MAX_SID_SIZE, SID_AND_ATTRIBUTES, TOKEN_ADJUST_DEFAULT, TOKEN_MANDATORY_LABEL,
+    };
+    use windows::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectBasicUIRestrictions,
+        JobObjectExtendedLimitInformation, SetInformationJobObject,
+        JOBOBJECT_BASIC_UI_RESTRICTIONS, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_ACTIVE_PROCESS, JOB_OBJECT_LIMIT_DIE_ON_UNHANDLED_EXCEPTION,
+        JOB_OBJECT_UILIMIT_DESKTOP, JOB_OBJECT_UILIMIT_DISPLAYSETTINGS,
+        JOB_OBJECT_UILIMIT_EXITWINDOWS, JOB_OBJECT_UILIMIT_GLOBALATOMS, JOB_OBJECT_UILIMIT_HANDLES,
+        JOB_OBJECT_UILIMIT_READCLIPBOARD, JOB_OBJECT_UILIMIT_SYSTEMPARAMETERS,
+        JOB_OBJECT_UILIMIT_WRITECLIPBOARD,
+    };
+    use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    /// From winnt.h.
+    const SE_GROUP_INTEGRITY: u32 = 0x20;
+
+    /// A low integrity label which is inherited by everything beneath the
+    /// directory, and which lets low integrity processes write there.
+    const LOW_INTEGRITY_LABEL: PCWSTR = w!("S:(ML;OICI;NW;;;LW)");
+
+    /// Gives the directories a low integrity label, so that the process can
+    /// still write beneath them after [`restrict`]. The directories are
+    /// created if they don't exist. Returns whether any of them is writable.
+    pub fn label_writable_dirs(dirs: &[PathBuf]) -> bool {
+        let mut any_writable = false;
+        for dir in dirs {
+            // A cache directory which can't be labeled stays read-only.
+            if std::fs::create_dir_all(dir).is_ok() && label_low_integrity(dir).is_ok() {
+                any_writable = true;
+            }
+        }
+        any_writable
+    }
+
+    fn label_low_integrity(dir: &Path) -> windows::core::Result<()> {
+        unsafe {
+            let mut descriptor = PSECURITY_DESCRIPTOR::default();
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                LOW_INTEGRITY_LABEL,
+                SDDL_REVISION_1,
+                &mut descriptor,
+                None,
+            )?;
+            let mut sacl_present = BOOL::default();
+            let mut sacl_defaulted = BOOL::default();
+            let mut sacl: *mut ACL = std::ptr::null_mut();
+            let result = GetSecurityDescriptorSacl(
+                descriptor,
+                &mut sacl_present,
+                &mut sacl,
+                &mut sacl_defaulted,
+            )
+            .and_then(|()| {
+                SetNamedSecurityInfoW(
+                    &HSTRING::from(dir),
+                    SE_FILE_OBJECT,
+                    LABEL_SECURITY_INFORMATION,
+                    PSID::default(),
+                    PSID::default(),
+                    None,
+                    Some(sacl),
+                )
+                .to_hresult()
+                .ok()
+            });
+            LocalFree(HLOCAL(descriptor.0));
+            result
+        }
+    }
+
+    /// Puts the process into a job object and lowers its integrity level.
+    /// Handles which are already open, like the pipes to the server, keep
+    /// working.
+    pub fn restrict() -> std::io::Result<()> {
+        unsafe {
+            let job = CreateJobObjectW(None, PCWSTR::null())?;
+            let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+            limits.BasicLimitInformation.LimitFlags =
+                JOB_OBJECT_LIMIT_ACTIVE_PROCESS | JOB_OBJECT_LIMIT_DIE_ON_UNHANDLED_EXCEPTION;
+            limits.BasicLimitInformation.ActiveProcessLimit = 1;
+            SetInformationJobObject(
+                job,
+                JobObjectExtendedLimitInformation,
+                &limits as *const _ as *const c_void,
+                std::mem::size_of_val(&limits) as u32,
+            )?;
+            let ui_restrictions = JOBOBJECT_BASIC_UI_RESTRICTIONS {
+                UIRestrictionsClass: JOB_OBJECT_UILIMIT_DESKTOP
+                    | JOB_OBJECT_UILIMIT_DISPLAYSETTINGS
+                    | JOB_OBJECT_UILIMIT_EXITWINDOWS
+                    | JOB_OBJECT_UILIMIT_GLOBALATOMS
+                    | JOB_OBJECT_UILIMIT_HANDLES
+                    | JOB_OBJECT_UILIMIT_READCLIPBOARD
+                    | JOB_OBJECT_UILIMIT_SYSTEMPARAMETERS
+                    | JOB_OBJECT_UILIMIT_WRITECLIPBOARD,
+            };
+            SetInformationJobObject(
+                job,
+                JobObjectBasicUIRestrictions,
+                &ui_restrictions as *const _ as *const c_void,
+                std::mem::size_of_val(&ui_restrictions) as u32,
+            )?;
+            // The job handle stays open until the process exits.
+            AssignProcessToJobObject(job, GetCurrentProcess())?;
+
+            let mut sid_buffer = [0u32; SECURITY_MAX_SID_SIZE as usize / 4];
+            let sid = PSID(sid_buffer.as_mut_ptr().cast());
+            let mut sid_size = SECURITY_MAX_SID_SIZE;
+            CreateWellKnownSid(WinLowLabelSid, PSID::default(), sid, &mut sid_size)?;
+            let label = TOKEN_MANDATORY_LABEL {
+                Label: SID_AND_ATTRIBUTES {
+                    Sid: sid,
+                    Attributes: SE_GROUP_INTEGRITY,
+                },
+            };
+            let mut token = HANDLE::default();
+            OpenProcessToken(GetCurrentProcess(), TOKEN_ADJUST_DEFAULT, &mut token)?;
+            let result = SetTokenInformation(
+                token,
+                TokenIntegrityLevel,
+                &label as *const _ as *const c_void,
+                std::mem::size_of::<TOKEN_MANDATORY_LABEL>() as u32 + sid_size,
+            );
+            let _ = CloseHandle(token);
+            result?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,484 @@
+//! Adds the syscalls from a `strace` or `dtruss` log to an existing profile, as
+//! interval markers on the threads which made them, for
+//! `samply annotate-profile --syscall-log`.
+//!
+//! strace needs to be run with `-ttt`, so that each line has a wall-clock
+//! timestamp, and ideally with `-f` and `-T`, so that each line has the thread
+//! id and the duration of the syscall. dtruss only logs times relative to its
+//! own start, so `--syscall-log-start` says when that was; `dtruss -a` gives
+//! the thread ids and the relative and elapsed times.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::annotate::{self, AnnotateError, AnnotationTime};
+
+const SYSCALL_MARKER_TYPE: &str = "Syscall";
+const SYSCALL_CATEGORY: &str = "Syscall";
+
+#[derive(thiserror::Error, Debug)]
+pub enum SyscallLogError {
+    #[error("Unrecognized syscall log format, expected the output of strace -ttt or dtruss")]
+    UnknownFormat,
+
+    #[error("The dtruss log has no RELATIVE column, run dtruss with -d or -a")]
+    NoRelativeTime,
+
+    #[error("{0}")]
+    Annotate(#[from] AnnotateError),
+}
+
+/// What the times in a [`SyscallLog`] are relative to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBase {
+    /// Seconds since the Unix epoch, from strace.
+    UnixEpoch,
+    /// Seconds since the start of the log, from dtruss.
+    LogStart,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyscallEvent {
+    pub pid: Option<u32>,
+    pub tid: Option<u32>,
+    pub name: String,
+    pub args: String,
+    pub result: String,
+    /// In seconds, see [`TimeBase`].
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyscallLog {
+    pub time_base: TimeBase,
+    pub events: Vec<SyscallEvent>,
+}
+
+/// Parses the output of `strace -ttt` or `dtruss`. dtruss logs are recognized
+/// by their header line.
+pub fn parse_syscall_log(text: &str) -> Result<SyscallLog, SyscallLogError> {
+    match text.lines().position(|line| line.contains("SYSCALL(args)")) {
+        Some(header_index) => parse_dtruss(text, header_index),
+        None => parse_strace(text),
+    }
+}
+
+fn parse_strace(text: &str) -> Result<SyscallLog, SyscallLogError> {
+    let mut events = Vec::new();
+    // Syscalls which were interrupted by another thread's syscall, keyed by
+    // (tid, syscall name), waiting for their "resumed" line.
+    let mut unfinished: HashMap<(Option<u32>, String), SyscallEvent> = HashMap::new();
+    let mut recognized_any = false;
+
+    for line in text.lines() {
+        let Some((tid, timestamp, rest)) = split_strace_prefix(line) else {
+            continue;
+        };
+        recognized_any = true;
+        if rest.starts_with("---") || rest.starts_with("+++") {
+            // Signals and exits.
+            continue;
+        }
+        if let Some(resumed) = rest.strip_prefix("<... ") {
+            let Some((name, rest)) = resumed.split_once(" resumed>") else {
+                continue;
+            };
+            let Some(mut event) = unfinished.remove(&(tid, name.to_string())) else {
+                continue;
+            };
+            let (args, result, duration) = split_strace_result(rest);
+            event.args.push_str(args);
+            event.result = result.to_string();
+            event.end = duration.map_or(timestamp, |duration| event.start + duration);
+            events.push(event);
+            continue;
+        }
+        let Some((name, rest)) = rest.split_once('(') else {
+            continue;
+        };
+        if let Some(args) = rest.strip_suffix(" <unfinished ...>") {
+            let event = SyscallEvent {
+                pid: None,
+                tid,
+                name: name.to_string(),
+                args: args.to_string(),
+                result: "?".to_string(),
+                start: timestamp,
+                end: timestamp,
+            };
+            unfinished.insert((tid, name.to_string()), event);
+            continue;
+        }
+        let (args, result, duration) = split_strace_result(rest);
+        events.push(SyscallEvent {
+            pid: None,
+            tid,
+            name: name.to_string(),
+            args: args.to_string(),
+            result: result.to_string(),
+            start: timestamp,
+            end: timestamp + duration.unwrap_or(0.0),
+        });
+    }
+
+    if !recognized_any {
+        return Err(SyscallLogError::UnknownFormat);
+    }
+    // Syscalls which never returned, e.g. because the process exited.
+    events.extend(unfinished.into_values());
+    events.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(SyscallLog {
+        time_base: TimeBase::UnixEpoch,
+        events,
+    })
+}
+
+/// Splits off the `[pid N]` or `N` prefix which strace -f adds, and the -ttt
+/// timestamp, from a line.
+fn split_strace_prefix(line: &str) -> Option<(Option<u32>, f64, &str)> {
+    let mut rest = line.trim_start();
+    let mut tid = None;
+    if let Some(pid_prefix) = rest.strip_prefix("[pid ") {
+        let (pid, after) = pid_prefix.split_once(']')?;
+        tid = Some(pid.trim().parse().ok()?);
+        rest = after.trim_start();
+    }
+    let (first, after) = rest.split_once(' ')?;
+    if tid.is_none() && !first.contains('.') {
+        if let Ok(pid) = first.parse() {
+            tid = Some(pid);
+            rest = after.trim_start();
+        }
+    }
+    let (timestamp, rest) = rest.split_once(' ')?;
+    if !timestamp.contains('.') {
+        return None;
+    }
+    Some((tid, timestamp.parse().ok()?, rest.trim_start()))
+}
+
+/// Splits `args) = result <duration>` into the arguments, the result and the
+/// duration in seconds, which is only there with strace -T.
+fn split_strace_result(rest: &str) -> (&str, &str, Option<f64>) {
+    let Some((args, result)) = rest.rsplit_once(") = ") else {
+        return (rest.trim_end_matches(')'), "?", None);
+    };
+    let result = result.trim_end();
+    if let Some((result, duration)) = result.rsplit_once(" <") {
+        if let Some(duration) = duration.strip_suffix('>').and_then(|d| d.parse().ok()) {
+            return (args, result, Some(duration));
+        }
+    }
+    (args, result, None)
+}
+
+fn parse_dtruss(text: &str, header_index: usize) -> Result<SyscallLog, SyscallLogError> {
+    let mut lines = text.lines().skip(header_index);
+    let header = lines.next().unwrap_or_default();
+    let columns: Vec<&str> = header
+        .split("SYSCALL(args)")
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect();
+    let column = |name: &str| columns.iter().position(|column| *column == name);
+    let relative_column = column("RELATIVE").ok_or(SyscallLogError::NoRelativeTime)?;
+    let elapsed_column = column("ELAPSD");
+    let pid_column = columns.iter().position(|column| column.starts_with("PID"));
+
+    let mut events = Vec::new();
+    for line in lines {
+        let mut rest = line.trim_start();
+        let mut values = Vec::with_capacity(columns.len());
+        for _ in 0..columns.len() {
+            let Some((value, after)) = rest.split_once(char::is_whitespace) else {
+                break;
+            };
+            values.push(value);
+            rest = after.trim_start();
+        }
+        if values.len() != columns.len() {
+            continue;
+        }
+        let Some(start) = values[relative_column].parse::<f64>().ok() else {
+            continue;
+        };
+        let elapsed = elapsed_column
+            .and_then(|column| values[column].parse::<f64>().ok())
+            .unwrap_or(0.0);
+        let (pid, tid) = match pid_column {
+            Some(column) => parse_dtruss_pid_thread(values[column]),
+            None => (None, None),
+        };
+        let Some((name, rest)) = rest.split_once('(') else {
+            continue;
+        };
+        let Some((args, result)) = rest.rsplit_once(" = ") else {
+            continue;
+        };
+        let args = args.trim_end().strip_suffix(')').unwrap_or(args);
+        // The result is the return value followed by errno.
+        let result = match result.split_whitespace().collect::<Vec<_>>()[..] {
+            [value, "0"] => value.to_string(),
+            [value, errno] => format!("{value} (errno {errno})"),
+            _ => result.trim().to_string(),
+        };
+        // dtruss times are in microseconds.
+        events.push(SyscallEvent {
+            pid,
+            tid,
+            name: name.to_string(),
+            args: args.to_string(),
+            result,
+            start: start / 1_000_000.0,
+            end: (start + elapsed) / 1_000_000.0,
+        });
+    }
+
+    Ok(SyscallLog {
+        time_base: TimeBase::LogStart,
+        events,
+    })
+}
+
+/// Parses `1234/0x5678:` into the pid and the thread id.
+fn parse_dtruss_pid_thread(s: &str) -> (Option<u32>, Option<u32>) {
+    let s = s.trim_end_matches(':');
+    let (pid, thread) = match s.split_once('/') {
+        Some((pid, thread)) => (pid, Some(thread)),
+        None => (s, None),
+    };
+    let tid = thread.and_then(|thread| match thread.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => thread.parse().ok(),
+    });
+    (pid.parse().ok(), tid)
+}
+
+/// Reads a pid or tid from the profile, where they're strings or numbers.
+fn json_id(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Finds the thread for a syscall: the thread with its tid, or else the main
+/// thread of its process. Syscalls without any ids go to the main thread of
+/// the first process, like the markers from `--marker`.
+fn find_thread(threads: &[Value], pid: Option<u32>, tid: Option<u32>) -> Option<usize> {
+    let pid = pid.map(u64::from);
+    let tid = tid.map(u64::from);
+    let matches_pid = |thread: &Value| pid.is_none() || json_id(&thread["pid"]) == pid;
+    if let Some(tid) = tid {
+        if let Some(index) = threads
+            .iter()
+            .position(|thread| json_id(&thread["tid"]) == Some(tid) && matches_pid(thread))
+        {
+            return Some(index);
+        }
+    }
+    // strace only logs the tid, and the tid of the main thread is the pid.
+    match pid.or(tid) {
+        Some(pid) => threads.iter().position(|thread| {
+            json_id(&thread["pid"]) == Some(pid) && thread["isMainThread"] == json!(true)
+        }),
+        None => Some(
+            threads
+                .iter()
+                .position(|thread| thread["isMainThread"] == json!(true))
+                .unwrap_or(0),
+        ),
+    }
+}
+
+/// Adds the syscalls as interval markers. `log_start` is when a dtruss log
+/// started, and is ignored for strace logs, which have wall-clock times.
+/// Returns the number of added markers and the number of syscalls for which no
+/// thread was found.
+pub fn add_syscall_markers(
+    profile: &mut Value,
+    log: &SyscallLog,
+    log_start: &AnnotationTime,
+) -> Result<(usize, usize), SyscallLogError> {
+    let start_time_ms = profile["meta"]["startTime"]
+        .as_f64()
+        .ok_or(AnnotateError::UnexpectedFormat("missing meta.startTime"))?;
+    let offset_ms = match log.time_base {
+        TimeBase::UnixEpoch => -start_time_ms,
+        TimeBase::LogStart => log_start.resolve(start_time_ms)?,
+    };
+    annotate::add_marker_schema(
+        profile,
+        json!({
+            "name": SYSCALL_MARKER_TYPE,
+            "display": ["marker-chart", "marker-table"],
+            "chartLabel": "{marker.name}",
+            "tooltipLabel": "{marker.name}({marker.data.args}) = {marker.data.result}",
+            "tableLabel": "{marker.name}({marker.data.args}) = {marker.data.result}",
+            "data": [
+                {
+                    "key": "args",
+                    "label": "Arguments",
+                    "format": "unique-string",
+                    "searchable": true,
+                },
+                {
+                    "key": "result",
+                    "label": "Result",
+                    "format": "unique-string",
+                    "searchable": true,
+                },
+            ],
+        }),
+    )?;
+    let category = annotate::find_or_add_category(profile, SYSCALL_CATEGORY, "red")?;
+
+    let threads = profile
+        .get_mut("threads")
+        .and_then(Value::as_array_mut)
+        .filter(|threads| !threads.is_empty())
+        .ok_or(AnnotateError::UnexpectedFormat(
+            "the profile has no threads",
+        ))?;
+    let mut added = 0;
+    let mut unmatched = 0;
+    for event in &log.events {
+        let Some(thread_index) = find_thread(threads, event.pid, event.tid) else {
+            unmatched += 1;
+            continue;
+        };
+        let thread = &mut threads[thread_index];
+        let name_index = annotate::intern_string(thread, &event.name)?;
+        let data = json!({
+            "type": SYSCALL_MARKER_TYPE,
+            "args": annotate::intern_string(thread, &event.args)?,
+            "result": annotate::intern_string(thread, &event.result)?,
+        });
+        let start = event.start * 1000.0 + offset_ms;
+        let end = event.end * 1000.0 + offset_ms;
+        annotate::add_marker(thread, name_index, category, start, Some(end), data)?;
+        added += 1;
+    }
+    Ok((added, unmatched))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use fxprof_processed_profile::{Profile, ReferenceTimestamp, SamplingInterval, Timestamp};
+
+    use super::*;
+
+    #[test]
+    fn parses_strace_logs() {
+        let log = parse_syscall_log(
+            "42 1714564800.000100 openat(AT_FDCWD, \"/etc/hosts\", O_RDONLY) = 3 <0.000020>\n\
+             [pid    43] 1714564800.000200 futex(0x7f10, FUTEX_WAKE, 1 <unfinished ...>\n\
+             42 1714564800.000300 read(3, \"\", 4096) = -1 EAGAIN (Try again) <0.000005>\n\
+             [pid    43] 1714564800.100200 <... futex resumed>) = 0 <0.100000>\n\
+             42 1714564800.200000 --- SIGCHLD {si_signo=SIGCHLD} ---\n\
+             42 1714564800.300000 exit_group(0) = ?\n\
+             42 1714564800.300100 +++ exited with 0 +++\n",
+        )
+        .unwrap();
+        assert_eq!(log.time_base, TimeBase::UnixEpoch);
+        let names: Vec<_> = log.events.iter().map(|event| event.name.as_str()).collect();
+        assert_eq!(names, ["openat", "futex", "read", "exit_group"]);
+        let futex = &log.events[1];
+        assert_eq!(futex.tid, Some(43));
+        assert_eq!(futex.args, "0x7f10, FUTEX_WAKE, 1");
+        assert_eq!(futex.result, "0");
+        assert!((futex.end - futex.start - 0.1).abs() < 1e-6);
+        assert_eq!(log.events[2].result, "-1 EAGAIN (Try again)");
+        assert_eq!(log.events[3].end, log.events[3].start);
+
+        let log = parse_syscall_log("1714564800.5 write(1, \"hi\\n\", 3) = 3\n").unwrap();
+        assert_eq!(log.events[0].tid, None);
+        assert_eq!(log.events[0].args, "1, \"hi\\n\", 3");
+        assert!(parse_syscall_log("hello\nworld\n").is_err());
+    }
+
+    #[test]
+    fn parses_dtruss_logs() {
+        let log = parse_syscall_log(
+            "\tPID/THRD  RELATIVE  ELAPSD    CPU SYSCALL(args) \t\t = return\n\
+             1234/0x5678:      1500      20      8 open(\"/etc/hosts\\0\", 0x0, 0x0)\t\t = 3 0\n\
+             1234/0x5679:      2000       5      2 read(0x3, \"\\0\", 0x1000)\t\t = -1 35\n",
+        )
+        .unwrap();
+        assert_eq!(log.time_base, TimeBase::LogStart);
+        assert_eq!(log.events.len(), 2);
+        let open = &log.events[0];
+        assert_eq!((open.pid, open.tid), (Some(1234), Some(0x5678)));
+        assert_eq!(open.name, "open");
+        assert_eq!(open.args, "\"/etc/hosts\\0\", 0x0, 0x0");
+        assert_eq!(open.result, "3");
+        assert_eq!((open.start, open.end), (0.0015, 0.00152));
+        assert_eq!(log.events[1].result, "-1 (errno 35)");
+
+        assert!(matches!(
+            parse_syscall_log("PID/THRD  SYSCALL(args) \t\t = return\n"),
+            Err(SyscallLogError::NoRelativeTime)
+        ));
+    }
+
+    #[test]
+    fn adds_syscall_markers_to_threads() {
+        // 2024-05-01T12:00:00Z
+        let start_time_ms = 1_714_564_800_000.0;
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(start_time_ms),
+            SamplingInterval::from_millis(1),
+        );
+        let process = profile.add_process("test", 42, Timestamp::from_millis_since_reference(0.0));
+        profile.add_thread(
+            process,
+            42,
+            Timestamp::from_millis_since_reference(0.0),
+            true,
+        );
+        profile.add_thread(
+            process,
+            43,
+            Timestamp::from_millis_since_reference(0.0),
+            false,
+        );
+        let mut profile = serde_json::to_value(&profile).unwrap();
+
+        let log = parse_syscall_log(
+            "42 1714564800.010000 close(3) = 0 <0.001000>\n\
+             43 1714564800.020000 close(4) = 0 <0.002000>\n\
+             44 1714564800.030000 close(5) = 0 <0.003000>\n",
+        )
+        .unwrap();
+        let log_start = AnnotationTime::Relative(Duration::ZERO);
+        let (added, unmatched) = add_syscall_markers(&mut profile, &log, &log_start).unwrap();
+        assert_eq!((added, unmatched), (2, 1));
+
+        let threads = profile["threads"].as_array().unwrap();
+        for (thread, tid, start) in [(&threads[0], 42, 10.0), (&threads[1], 43, 20.0)] {
+            assert_eq!(json_id(&thread["tid"]), Some(tid));
+            let markers = &thread["markers"];
+            assert_eq!(markers["length"], json!(1));
+            assert_eq!(markers["phase"], json!([1]));
+            let marker_start = markers["startTime"][0].as_f64().unwrap();
+            assert!((marker_start - start).abs() < 1e-3);
+            let args_index = markers["data"][0]["args"].as_u64().unwrap() as usize;
+            assert!(thread["stringArray"][args_index]
+                .as_str()
+                .unwrap()
+                .starts_with(char::is_numeric));
+        }
+        assert!(profile["meta"]["markerSchema"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|schema| schema["name"] == json!(SYSCALL_MARKER_TYPE)));
+    }
+}
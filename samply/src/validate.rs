@@ -0,0 +1,561 @@
+//! `samply validate`: Checks the structural invariants of a profile in the
+//! processed profile format which the profiler relies on without checking
+//! them, e.g. for profiles written by third-party exporters: table columns of
+//! the same length, string and table indexes in range, stack prefixes before
+//! their stacks, samples in time order, and valid thread indexes. Most of the
+//! problems can be repaired, by sorting the samples or by dropping the broken
+//! references.
+
+use std::fmt;
+
+use serde_json::{json, Value};
+
+/// The string which replaces invalid string indexes when repairing.
+const INVALID_STRING: &str = "<invalid string>";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ValidateError {
+    #[error("Unexpected profile format: {0}")]
+    UnexpectedFormat(&'static str),
+}
+
+/// A broken invariant, with the number of rows of the column which break it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// Where the problem is, e.g. `threads[2].stackTable.prefix`.
+    pub location: String,
+    /// Describes the first row with the problem.
+    pub message: String,
+    pub count: usize,
+    pub repaired: bool,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)?;
+        if self.count > 1 {
+            write!(f, " ({} rows)", self.count)?;
+        }
+        if self.repaired {
+            write!(f, " [repaired]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks `profile`, and repairs what can be repaired if `repair` is true.
+/// Returns all problems which were found, including the repaired ones.
+pub fn validate_profile(profile: &mut Value, repair: bool) -> Result<Vec<Problem>, ValidateError> {
+    if profile["meta"]["preprocessedProfileVersion"].is_null() {
+        return Err(ValidateError::UnexpectedFormat(
+            "not a processed profile, use `samply import` for other formats",
+        ));
+    }
+    let mut validator = Validator {
+        repair,
+        problems: Vec::new(),
+        needs_invalid_string: false,
+    };
+    let category_count = profile["meta"]["categories"].as_array().map_or(0, Vec::len);
+    let lib_count = profile["libs"].as_array().map_or(0, Vec::len);
+    let shared_string_count = profile["shared"]["stringArray"].as_array().map(Vec::len);
+
+    let Some(threads) = profile.get_mut("threads").and_then(Value::as_array_mut) else {
+        return Err(ValidateError::UnexpectedFormat("threads is not an array"));
+    };
+    let thread_count = threads.len();
+    for (index, thread) in threads.iter_mut().enumerate() {
+        let sizes = ProfileSizes {
+            category_count,
+            lib_count,
+            shared_string_count,
+        };
+        validator.validate_thread(thread, &format!("threads[{index}]"), &sizes);
+    }
+    if shared_string_count.is_some() && validator.needs_invalid_string {
+        if let Some(strings) = profile["shared"]["stringArray"].as_array_mut() {
+            strings.push(json!(INVALID_STRING));
+        }
+    }
+
+    if let Some(counters) = profile.get_mut("counters").and_then(Value::as_array_mut) {
+        for (index, counter) in counters.iter_mut().enumerate() {
+            let location = format!("counters[{index}]");
+            validator.check_column(
+                Some(&mut *counter),
+                &location,
+                "mainThreadIndex",
+                |_, value| is_index_below(value, thread_count),
+                |_, value| {
+                    format!("refers to thread {value}, but there are {thread_count} threads")
+                },
+                (thread_count > 0).then(|| json!(0)),
+            );
+            if let Some(samples) = counter.get_mut("samples") {
+                let samples_location = format!("{location}.samples");
+                validator.check_table_length(samples, &samples_location);
+                validator.check_sample_order(samples, &samples_location);
+            }
+        }
+    }
+
+    for key in ["initialVisibleThreads", "initialSelectedThreads"] {
+        let Some(indexes) = profile["meta"].get_mut(key).and_then(Value::as_array_mut) else {
+            continue;
+        };
+        let invalid: Vec<&Value> = indexes
+            .iter()
+            .filter(|value| !is_index_below(value, thread_count))
+            .collect();
+        if let Some(first) = invalid.first() {
+            let message = format!("refers to thread {first}, but there are {thread_count} threads");
+            let count = invalid.len();
+            if repair {
+                indexes.retain(|value| is_index_below(value, thread_count));
+            }
+            validator.report(format!("meta.{key}"), message, count, repair);
+        }
+    }
+
+    Ok(validator.problems)
+}
+
+struct ProfileSizes {
+    category_count: usize,
+    lib_count: usize,
+    /// The length of `shared.stringArray`, for profiles whose threads share one
+    /// string table.
+    shared_string_count: Option<usize>,
+}
+
+struct Validator {
+    repair: bool,
+    problems: Vec<Problem>,
+    /// Whether an invalid index into the shared string table was replaced.
+    needs_invalid_string: bool,
+}
+
+impl Validator {
+    fn report(&mut self, location: String, message: String, count: usize, repaired: bool) {
+        self.problems.push(Problem {
+            location,
+            message,
+            count,
+            repaired,
+        });
+    }
+
+    fn validate_thread(&mut self, thread: &mut Value, location: &str, sizes: &ProfileSizes) {
+        if !thread.is_object() {
+            self.report(
+                location.to_string(),
+                "is not an object".to_string(),
+                1,
+                false,
+            );
+            return;
+        }
+        let mut table_length = |validator: &mut Self, name: &str, required: bool| {
+            let table_location = format!("{location}.{name}");
+            if !thread[name].is_null() {
+                Some(validator.check_table_length(&mut thread[name], &table_location))
+            } else if required {
+                validator.report(table_location, "is missing".to_string(), 1, false);
+                None
+            } else {
+                Some(0)
+            }
+        };
+        let (Some(_), Some(stack_count), Some(frame_count), Some(func_count)) = (
+            table_length(self, "samples", true),
+            table_length(self, "stackTable", true),
+            table_length(self, "frameTable", true),
+            table_length(self, "funcTable", true),
+        ) else {
+            return;
+        };
+        let resource_count = table_length(self, "resourceTable", false).unwrap_or(0);
+        let native_symbol_count = table_length(self, "nativeSymbols", false).unwrap_or(0);
+        table_length(self, "markers", false);
+
+        let string_count = match (thread["stringArray"].as_array(), sizes.shared_string_count) {
+            (Some(strings), _) => strings.len(),
+            (None, Some(shared_string_count)) => shared_string_count,
+            (None, None) => {
+                let message = "is missing".to_string();
+                self.report(format!("{location}.stringArray"), message, 1, false);
+                return;
+            }
+        };
+
+        // Invalid string indexes are replaced with the index of a new string,
+        // which is added to the string table afterwards.
+        let mut invalid_string_count = 0;
+        for (table, column, nullable) in [
+            ("funcTable", "name", false),
+            ("funcTable", "fileName", true),
+            ("resourceTable", "name", false),
+            ("resourceTable", "host", true),
+            ("nativeSymbols", "name", false),
+            ("markers", "name", false),
+        ] {
+            invalid_string_count += self.check_column(
+                thread.get_mut(table),
+                &format!("{location}.{table}"),
+                column,
+                |_, value| (nullable && value.is_null()) || is_index_below(value, string_count),
+                |_, value| {
+                    format!("refers to string {value}, but there are {string_count} strings")
+                },
+                Some(json!(string_count)),
+            );
+        }
+        if invalid_string_count != 0 && self.repair {
+            match thread.get_mut("stringArray").and_then(Value::as_array_mut) {
+                Some(strings) => strings.push(json!(INVALID_STRING)),
+                None => self.needs_invalid_string = true,
+            }
+        }
+
+        let samples_location = format!("{location}.samples");
+        self.check_column(
+            thread.get_mut("samples"),
+            &samples_location,
+            "stack",
+            |_, value| value.is_null() || is_index_below(value, stack_count),
+            |_, value| format!("refers to stack {value}, but there are {stack_count} stacks"),
+            Some(Value::Null),
+        );
+        self.check_sample_order(&mut thread["samples"], &samples_location);
+
+        let stacks_location = format!("{location}.stackTable");
+        self.check_column(
+            thread.get_mut("stackTable"),
+            &stacks_location,
+            "prefix",
+            |row, value| value.is_null() || is_index_below(value, row),
+            |_, value| format!("refers to stack {value}, which doesn't come before it"),
+            Some(Value::Null),
+        );
+        self.check_column(
+            thread.get_mut("stackTable"),
+            &stacks_location,
+            "frame",
+            |_, value| is_index_below(value, frame_count),
+            |_, value| format!("refers to frame {value}, but there are {frame_count} frames"),
+            None,
+        );
+        self.check_category_column(thread.get_mut("stackTable"), &stacks_location, false, sizes);
+
+        let frames_location = format!("{location}.frameTable");
+        self.check_column(
+            thread.get_mut("frameTable"),
+            &frames_location,
+            "func",
+            |_, value| is_index_below(value, func_count),
+            |_, value| format!("refers to function {value}, but there are {func_count} functions"),
+            None,
+        );
+        self.check_column(
+            thread.get_mut("frameTable"),
+            &frames_location,
+            "nativeSymbol",
+            |_, value| value.is_null() || is_index_below(value, native_symbol_count),
+            |_, value| {
+                format!("refers to native symbol {value}, but there are {native_symbol_count}")
+            },
+            Some(Value::Null),
+        );
+        self.check_category_column(thread.get_mut("frameTable"), &frames_location, true, sizes);
+
+        self.check_column(
+            thread.get_mut("funcTable"),
+            &format!("{location}.funcTable"),
+            "resource",
+            |_, value| value.as_i64() == Some(-1) || is_index_below(value, resource_count),
+            |_, value| format!("refers to resource {value}, but there are {resource_count}"),
+            Some(json!(-1)),
+        );
+
+        let lib_count = sizes.lib_count;
+        self.check_column(
+            thread.get_mut("resourceTable"),
+            &format!("{location}.resourceTable"),
+            "lib",
+            |_, value| value.is_null() || is_index_below(value, lib_count),
+            |_, value| format!("refers to library {value}, but there are {lib_count} libraries"),
+            Some(Value::Null),
+        );
+        self.check_column(
+            thread.get_mut("nativeSymbols"),
+            &format!("{location}.nativeSymbols"),
+            "libIndex",
+            |_, value| is_index_below(value, lib_count),
+            |_, value| format!("refers to library {value}, but there are {lib_count} libraries"),
+            None,
+        );
+
+        self.check_category_column(
+            thread.get_mut("markers"),
+            &format!("{location}.markers"),
+            false,
+            sizes,
+        );
+    }
+
+    /// Checks that all columns of the table have as many rows as its `length`
+    /// field says. Repairs by cutting all columns to the shortest one. Returns
+    /// the number of rows which can be accessed in all columns.
+    fn check_table_length(&mut self, table: &mut Value, location: &str) -> usize {
+        let Some(object) = table.as_object_mut() else {
+            self.report(
+                location.to_string(),
+                "is not an object".to_string(),
+                1,
+                false,
+            );
+            return 0;
+        };
+        let length = object.get("length").and_then(Value::as_u64);
+        let mut column_lengths: Vec<(&String, usize)> = object
+            .iter()
+            .filter_map(|(key, value)| Some((key, value.as_array()?.len())))
+            .collect();
+        column_lengths.sort();
+        let min_length = column_lengths.iter().map(|(_, len)| *len).min();
+        let max_length = column_lengths.iter().map(|(_, len)| *len).max();
+        let (row_count, message) = match (min_length, max_length, length) {
+            (None, _, Some(length)) => return length as usize,
+            (None, _, None) => return 0,
+            (Some(min), Some(max), _) if min != max => {
+                let lengths: Vec<String> = column_lengths
+                    .iter()
+                    .map(|(key, len)| format!("{key}: {len}"))
+                    .collect();
+                let message = format!("has columns of different lengths ({})", lengths.join(", "));
+                (min, message)
+            }
+            (Some(min), _, Some(length)) if length as usize == min => return min,
+            (Some(min), _, Some(length)) => (min, format!("has length {length}, but {min} rows")),
+            (Some(min), _, None) => (min, format!("has no length, but {min} rows")),
+        };
+        if self.repair {
+            for value in object.values_mut() {
+                if let Some(column) = value.as_array_mut() {
+                    column.truncate(row_count);
+                }
+            }
+            object.insert("length".to_string(), json!(row_count));
+        }
+        self.report(location.to_string(), message, 1, self.repair);
+        row_count
+    }
+
+    /// Checks the values of the column `column` of `table` with `is_valid`,
+    /// which gets the row and the value. Returns the number of invalid values.
+    /// If repairing, these are replaced with `replacement`; without a
+    /// replacement, the problem can't be repaired. `describe` makes the message
+    /// for the first invalid value. Missing tables and columns aren't checked.
+    fn check_column(
+        &mut self,
+        table: Option<&mut Value>,
+        location: &str,
+        column: &str,
+        is_valid: impl Fn(usize, &Value) -> bool,
+        describe: impl Fn(usize, &Value) -> String,
+        replacement: Option<Value>,
+    ) -> usize {
+        let location = format!("{location}.{column}");
+        let values = match table.and_then(|table| table.get_mut(column)) {
+            None | Some(Value::Null) => return 0,
+            Some(Value::Array(values)) => values,
+            // Single values, e.g. `mainThreadIndex` of a counter.
+            Some(value) => std::slice::from_mut(value),
+        };
+        let mut invalid_rows = values
+            .iter()
+            .enumerate()
+            .filter(|(row, value)| !is_valid(*row, value))
+            .map(|(row, _)| row);
+        let Some(first_row) = invalid_rows.next() else {
+            return 0;
+        };
+        let count = 1 + invalid_rows.count();
+        let mut message = describe(first_row, &values[first_row]);
+        if values.len() > 1 {
+            message = format!("row {first_row} {message}");
+        }
+        let repaired = match &replacement {
+            Some(replacement) if self.repair => {
+                for (row, value) in values.iter_mut().enumerate() {
+                    if !is_valid(row, value) {
+                        *value = replacement.clone();
+                    }
+                }
+                true
+            }
+            _ => false,
+        };
+        self.report(location, message, count, repaired);
+        count
+    }
+
+    /// Checks the `category` column of a table. Invalid categories are replaced
+    /// with null if the column is `nullable`, and with the first category
+    /// otherwise.
+    fn check_category_column(
+        &mut self,
+        table: Option<&mut Value>,
+        location: &str,
+        nullable: bool,
+        sizes: &ProfileSizes,
+    ) {
+        let category_count = sizes.category_count;
+        let replacement = match nullable {
+            true => Some(Value::Null),
+            false => (category_count > 0).then(|| json!(0)),
+        };
+        self.check_column(
+            table,
+            location,
+            "category",
+            |_, value| (nullable && value.is_null()) || is_index_below(value, category_count),
+            |_, value| format!("refers to category {value}, but there are {category_count}"),
+            replacement,
+        );
+    }
+
+    /// Checks that the samples are sorted by time. They have either a `time`
+    /// column or a `timeDeltas` column. Repairs by sorting all columns.
+    fn check_sample_order(&mut self, samples: &mut Value, location: &str) {
+        let (column, is_delta) = match (&samples["time"], &samples["timeDeltas"]) {
+            (Value::Array(_), _) => ("time", false),
+            (_, Value::Array(_)) => ("timeDeltas", true),
+            _ => return,
+        };
+        let location = format!("{location}.{column}");
+        let Some(values) = samples[column]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(Value::as_f64)
+            .collect::<Option<Vec<f64>>>()
+        else {
+            let message = "has values which aren't numbers".to_string();
+            self.report(location, message, 1, false);
+            return;
+        };
+        let times: Vec<f64> = match is_delta {
+            true => values
+                .iter()
+                .scan(0.0, |time, delta| {
+                    *time += delta;
+                    Some(*time)
+                })
+                .collect(),
+            false => values,
+        };
+        let mut out_of_order_rows = (1..times.len()).filter(|&row| times[row] < times[row - 1]);
+        let Some(first_row) = out_of_order_rows.next() else {
+            return;
+        };
+        let count = 1 + out_of_order_rows.count();
+        let message = format!(
+            "row {first_row} has time {}, which is before the time {} of the previous sample",
+            times[first_row],
+            times[first_row - 1]
+        );
+        if self.repair {
+            let mut order: Vec<usize> = (0..times.len()).collect();
+            order.sort_by(|&a, &b| times[a].total_cmp(&times[b]));
+            if let Some(object) = samples.as_object_mut() {
+                for values in object.values_mut().filter_map(Value::as_array_mut) {
+                    if values.len() == order.len() {
+                        *values = order.iter().map(|&row| values[row].take()).collect();
+                    }
+                }
+            }
+            if is_delta {
+                let sorted_times = order.iter().map(|&row| times[row]);
+                let deltas: Vec<Value> = sorted_times
+                    .clone()
+                    .zip(std::iter::once(0.0).chain(sorted_times))
+                    .map(|(time, previous)| json!(time - previous))
+                    .collect();
+                samples[column] = Value::Array(deltas);
+            }
+        }
+        self.report(location, message, count, self.repair);
+    }
+}
+
+fn is_index_below(value: &Value, len: usize) -> bool {
+    value.as_u64().is_some_and(|index| (index as usize) < len)
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{
+        CategoryHandle, CpuDelta, Frame, FrameFlags, FrameInfo, Profile, ReferenceTimestamp,
+        SamplingInterval, Timestamp,
+    };
+
+    use super::*;
+
+    #[test]
+    fn finds_and_repairs_problems() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let start = Timestamp::from_millis_since_reference(0.0);
+        let process = profile.add_process("app", 123, start);
+        let thread = profile.add_thread(process, 123, start, true);
+        for (i, name) in ["main", "work", "main"].into_iter().enumerate() {
+            let frame = FrameInfo {
+                frame: Frame::Label(profile.intern_string(name)),
+                category_pair: CategoryHandle::OTHER.into(),
+                flags: FrameFlags::empty(),
+            };
+            let timestamp = Timestamp::from_millis_since_reference(i as f64);
+            profile.add_sample(thread, timestamp, [frame].into_iter(), CpuDelta::ZERO, 1);
+        }
+        let mut json = serde_json::to_value(&profile).unwrap();
+        assert_eq!(validate_profile(&mut json, false).unwrap(), vec![]);
+
+        let thread = &mut json["threads"][0];
+        thread["samples"]["time"][0] = json!(5.0);
+        thread["samples"]["stack"][1] = json!(17);
+        thread["stackTable"]["prefix"][0] = json!(1);
+        thread["funcTable"]["name"][0] = json!(1000);
+        thread["frameTable"]["func"].as_array_mut().unwrap().pop();
+        let problems = validate_profile(&mut json, true).unwrap();
+        let problems: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            problems,
+            vec![
+                "threads[0].frameTable: has columns of different lengths (address: 2, \
+                 category: 2, column: 2, func: 1, implementation: 2, inlineDepth: 2, \
+                 innerWindowID: 2, line: 2, nativeSymbol: 2, subcategory: 2) [repaired]",
+                "threads[0].funcTable.name: row 0 refers to string 1000, but there are 2 \
+                 strings [repaired]",
+                "threads[0].samples.stack: row 1 refers to stack 17, but there are 2 stacks \
+                 [repaired]",
+                "threads[0].samples.time: row 1 has time 1, which is before the time 5 of the \
+                 previous sample [repaired]",
+                "threads[0].stackTable.prefix: row 0 refers to stack 1, which doesn't come \
+                 before it [repaired]",
+                "threads[0].stackTable.frame: row 1 refers to frame 1, but there are 1 frames",
+            ]
+        );
+        let thread = &json["threads"][0];
+        assert_eq!(thread["samples"]["time"], json!([1.0, 2.0, 5.0]));
+        assert_eq!(thread["samples"]["stack"], json!([null, 0, 0]));
+        assert_eq!(thread["stringArray"][2], json!(INVALID_STRING));
+        assert_eq!(thread["funcTable"]["name"][0], json!(2));
+
+        // Only the problem which can't be repaired is left.
+        assert_eq!(validate_profile(&mut json, true).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,319 @@
+//! `samply export wakegraph`: Builds a graph of which threads wake which from
+//! the "Wakeup" markers of a profile (`samply record --wakeups`), and writes
+//! it as a Graphviz DOT file or as JSON. Following the edges back from a slow
+//! thread shows the chain of threads, across processes, that it waited on.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use serde_json::{json, Value};
+
+use crate::profile_query::ProfileQuery;
+
+pub const WAKEUP_MARKER_NAME: &str = "Wakeup";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeGraphFormat {
+    Dot,
+    Json,
+}
+
+/// A thread in the graph. Wakers don't need to be in the profile, e.g. when
+/// only one process was profiled, so threads are identified by pid and tid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct ThreadKey {
+    pid: String,
+    tid: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ThreadNode {
+    name: String,
+    process_name: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct WakeGraph {
+    nodes: BTreeMap<ThreadKey, ThreadNode>,
+    /// The number of wakeups per (waker, woken) pair.
+    edges: HashMap<(ThreadKey, ThreadKey), u64>,
+}
+
+impl WakeGraph {
+    pub fn from_profile(query: &ProfileQuery) -> Self {
+        let profile_threads: HashMap<ThreadKey, ThreadNode> = (0..query.thread_count())
+            .filter_map(|thread| query.thread_identity(thread))
+            .map(|identity| {
+                let key = ThreadKey {
+                    pid: id_string(identity.pid),
+                    tid: id_string(identity.tid),
+                };
+                let node = ThreadNode {
+                    name: identity.name.to_string(),
+                    process_name: identity.process_name.map(ToString::to_string),
+                };
+                (key, node)
+            })
+            .collect();
+
+        let mut graph = WakeGraph::default();
+        query.for_each_marker(WAKEUP_MARKER_NAME, |thread, data| {
+            let Some(identity) = query.thread_identity(thread) else {
+                return;
+            };
+            let woken = ThreadKey {
+                pid: id_string(identity.pid),
+                tid: id_string(identity.tid),
+            };
+            let waker = ThreadKey {
+                pid: id_string(&data["wakerPid"]),
+                tid: id_string(&data["wakerTid"]),
+            };
+            for key in [&woken, &waker] {
+                if graph.nodes.contains_key(key) {
+                    continue;
+                }
+                // Wakers which aren't in the profile only have the name from
+                // the marker.
+                let node = profile_threads.get(key).cloned().unwrap_or_else(|| {
+                    let waker_name = data["waker"]
+                        .as_u64()
+                        .and_then(|index| query.thread_string(thread, index as usize));
+                    ThreadNode {
+                        name: waker_name.unwrap_or("Unknown").to_string(),
+                        process_name: None,
+                    }
+                });
+                graph.nodes.insert(key.clone(), node);
+            }
+            *graph.edges.entry((waker, woken)).or_default() += 1;
+        });
+        graph
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+
+    /// The edges with the most wakeups first.
+    fn sorted_edges(&self) -> Vec<(&ThreadKey, &ThreadKey, u64)> {
+        let mut edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|((waker, woken), count)| (waker, woken, *count))
+            .collect();
+        edges.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| (a.0, a.1).cmp(&(b.0, b.1))));
+        edges
+    }
+
+    pub fn write(&self, writer: &mut impl Write, format: WakeGraphFormat) -> io::Result<()> {
+        match format {
+            WakeGraphFormat::Dot => self.write_dot(writer),
+            WakeGraphFormat::Json => self.write_json(writer),
+        }
+    }
+
+    fn write_dot(&self, writer: &mut impl Write) -> io::Result<()> {
+        let node_ids: HashMap<&ThreadKey, usize> = self
+            .nodes
+            .keys()
+            .enumerate()
+            .map(|(i, key)| (key, i))
+            .collect();
+        writeln!(writer, "digraph wakeups {{")?;
+        writeln!(writer, "  node [shape=box];")?;
+        for (i, (key, node)) in self.nodes.iter().enumerate() {
+            let process = match &node.process_name {
+                Some(process_name) => format!("{process_name}, "),
+                None => String::new(),
+            };
+            let label = format!(
+                "{}\\n{process}pid {}, tid {}",
+                dot_escape(&node.name),
+                key.pid,
+                key.tid
+            );
+            writeln!(writer, "  t{i} [label=\"{label}\"];")?;
+        }
+        for (waker, woken, count) in self.sorted_edges() {
+            writeln!(
+                writer,
+                "  t{} -> t{} [label=\"{count}\"];",
+                node_ids[waker], node_ids[woken]
+            )?;
+        }
+        writeln!(writer, "}}")
+    }
+
+    fn write_json(&self, writer: &mut impl Write) -> io::Result<()> {
+        let node_ids: HashMap<&ThreadKey, usize> = self
+            .nodes
+            .keys()
+            .enumerate()
+            .map(|(i, key)| (key, i))
+            .collect();
+        let nodes: Vec<Value> = self
+            .nodes
+            .iter()
+            .map(|(key, node)| {
+                json!({
+                    "pid": key.pid,
+                    "tid": key.tid,
+                    "name": node.name,
+                    "processName": node.process_name,
+                })
+            })
+            .collect();
+        let edges: Vec<Value> = self
+            .sorted_edges()
+            .into_iter()
+            .map(|(waker, woken, count)| {
+                json!({
+                    "waker": node_ids[waker],
+                    "woken": node_ids[woken],
+                    "count": count,
+                })
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut *writer, &json!({ "nodes": nodes, "edges": edges }))?;
+        writeln!(writer)
+    }
+}
+
+/// Pids and tids are numbers or strings in the profile, and numbers in the
+/// marker data, where they're stored as floats.
+fn id_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if f.fract() == 0.0 => format!("{}", f as i64),
+            _ => n.to_string(),
+        },
+        _ => String::new(),
+    }
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{
+        CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+        MarkerTiming, Profile, ReferenceTimestamp, SamplingInterval, StaticSchemaMarker,
+        StringHandle, Timestamp,
+    };
+
+    use super::*;
+
+    /// The same schema as the Windows `WakeupMarker`.
+    struct TestWakeupMarker {
+        waker_pid: u32,
+        waker_tid: u32,
+        waker_name: StringHandle,
+    }
+
+    impl StaticSchemaMarker for TestWakeupMarker {
+        const UNIQUE_MARKER_TYPE_NAME: &'static str = "Wakeup";
+
+        fn schema() -> MarkerSchema {
+            let field = |key: &str, format| MarkerFieldSchema {
+                key: key.into(),
+                label: key.into(),
+                format,
+                searchable: false,
+            };
+            MarkerSchema {
+                type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+                locations: vec![MarkerLocation::MarkerTable],
+                chart_label: None,
+                tooltip_label: None,
+                table_label: None,
+                fields: vec![
+                    field("waker", MarkerFieldFormat::String),
+                    field("wakerPid", MarkerFieldFormat::Integer),
+                    field("wakerTid", MarkerFieldFormat::Integer),
+                ],
+                static_fields: vec![],
+            }
+        }
+
+        fn name(&self, profile: &mut Profile) -> StringHandle {
+            profile.intern_string(WAKEUP_MARKER_NAME)
+        }
+
+        fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+            CategoryHandle::OTHER
+        }
+
+        fn string_field_value(&self, _field_index: u32) -> StringHandle {
+            self.waker_name
+        }
+
+        fn number_field_value(&self, field_index: u32) -> f64 {
+            match field_index {
+                1 => self.waker_pid.into(),
+                _ => self.waker_tid.into(),
+            }
+        }
+    }
+
+    #[test]
+    fn wake_graph() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let start = Timestamp::from_millis_since_reference(0.0);
+        let process = profile.add_process("app.exe", 10, start);
+        let main_thread = profile.add_thread(process, 11, start, true);
+        let worker = profile.add_thread(process, 12, start, false);
+        profile.set_thread_name(worker, "Worker");
+
+        let mut add_wakeup = |thread, waker_pid, waker_tid, waker_name: &str| {
+            let waker_name = profile.intern_string(waker_name);
+            profile.add_marker(
+                thread,
+                MarkerTiming::Instant(start),
+                TestWakeupMarker {
+                    waker_pid,
+                    waker_tid,
+                    waker_name,
+                },
+            );
+        };
+        add_wakeup(main_thread, 10, 12, "Worker");
+        add_wakeup(main_thread, 10, 12, "Worker");
+        add_wakeup(worker, 20, 21, "server.exe");
+
+        let json = serde_json::to_vec(&profile).unwrap();
+        let query = ProfileQuery::load(&json[..]).unwrap();
+        let graph = WakeGraph::from_profile(&query);
+
+        let mut dot = Vec::new();
+        graph.write(&mut dot, WakeGraphFormat::Dot).unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            r#"digraph wakeups {
+  node [shape=box];
+  t0 [label="app.exe\napp.exe, pid 10, tid 11"];
+  t1 [label="Worker\napp.exe, pid 10, tid 12"];
+  t2 [label="server.exe\npid 20, tid 21"];
+  t1 -> t0 [label="2"];
+  t2 -> t1 [label="1"];
+}
+"#
+        );
+
+        let mut json = Vec::new();
+        graph.write(&mut json, WakeGraphFormat::Json).unwrap();
+        let json: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["nodes"][2]["name"], "server.exe");
+        assert_eq!(
+            json["edges"][0],
+            json!({ "waker": 1, "woken": 0, "count": 2 })
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use super::elevated_helper::ElevatedRecordingProps;
+
+pub const DEFENDER_PROVIDER_PREFIX: &str = "Microsoft-Windows-Windows Defender/";
+
+/// The kernel events for minifilter pre- and post-operation callbacks. All
+/// on-access antivirus scanners, including Windows Defender, sit in the file
+/// system stack as minifilters, so these show the time they add to file I/O.
+const MINIFILTER_KERNEL_FLAGS: &str = "FLT_IO_INIT+FLT_IO+FLT_FASTIO+FLT_IO_FAILURE";
+
+/// There's a minifilter event for every callback of every filter on every
+/// file operation. Only the slow ones are interesting, and showing all of
+/// them would drown out everything else.
+pub const MIN_MINIFILTER_MARKER_DURATION_NANOS: u64 = 1_000_000;
+
+pub fn antivirus_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
+    if !props.antivirus {
+        return vec![];
+    }
+    vec!["Microsoft-Windows-Windows Defender".to_string()]
+}
+
+/// Extra flags for the kernel session, if any.
+pub fn antivirus_kernel_flags(props: &ElevatedRecordingProps) -> Option<&'static str> {
+    if props.antivirus {
+        Some(MINIFILTER_KERNEL_FLAGS)
+    } else {
+        None
+    }
+}
+
+/// The kernel's minifilter events are "MSNT_SystemTrace/FileIo/Flt*". The
+/// ones for completed callbacks have an "InitialTime" field with the time at
+/// which the callback was entered.
+pub const MINIFILTER_EVENT_PREFIX: &str = "MSNT_SystemTrace/FileIo/Flt";
@@ -70,10 +70,10 @@ pub fn chrome_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
     // JIT symbols
     providers.push("Microsoft-JScript:0x3".to_string());
 
-    // UserTiming trace events
+    // UserTiming trace events, with stacks so that we can show where they came from
     let enabled_keywords = KeywordNames::blink_user_timing;
     providers.push(format!(
-        "{}:{:#x}",
+        "{}:{:#x}:5:'stack'",
         CHROME_PROVIDER_GUID,
         enabled_keywords.bits()
     ));
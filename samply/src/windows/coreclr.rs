@@ -1,16 +1,21 @@
-use std::{collections::HashMap, convert::TryInto, fmt::Display};
+use std::{collections::HashMap, convert::TryInto};
 
 use bitflags::bitflags;
 use fxprof_processed_profile::*;
-use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
 use etw_reader::{self, schema::TypedEvent};
 use etw_reader::{
-    event_properties_to_string,
+    event_properties_to_string_with_limits,
     parser::{Parser, TryParse},
+    PropertyStringLimits,
 };
 
+use crate::shared::coreclr::{
+    method_name, CoreClrAssemblyLoadMarker, CoreClrExceptionCaughtMarker,
+    CoreClrExceptionThrownMarker, CoreClrGcAllocMarker, CoreClrGcEventMarker, DisplayUnknownIfNone,
+    GcReason, GcSuspendEeReason, GcType,
+};
 use crate::shared::recording_props::{CoreClrProfileProps, ProfileCreationProps};
 use crate::windows::profile_context::{KnownCategory, ProfileContext};
 
@@ -26,7 +31,9 @@ pub struct CoreClrContext {
     props: CoreClrProfileProps,
     last_marker_on_thread: HashMap<u32, (ThreadHandle, MarkerHandle)>,
     gc_markers_on_thread: HashMap<u32, HashMap<&'static str, SavedMarkerInfo>>,
+    last_exception_type_on_thread: HashMap<u32, StringHandle>,
     unknown_event_markers: bool,
+    payload_limits: PropertyStringLimits,
 }
 
 impl CoreClrContext {
@@ -35,7 +42,12 @@ impl CoreClrContext {
             props: profile_creation_props.coreclr,
             last_marker_on_thread: HashMap::new(),
             gc_markers_on_thread: HashMap::new(),
+            last_exception_type_on_thread: HashMap::new(),
             unknown_event_markers: profile_creation_props.unknown_event_markers,
+            payload_limits: PropertyStringLimits {
+                max_value_len: profile_creation_props.max_marker_value_size,
+                max_total_len: profile_creation_props.max_marker_payload_size,
+            },
         }
     }
 
@@ -129,201 +141,6 @@ mod constants {
     pub const CORECLR_TYPE_DIAGNOSTIC_KEYWORD: u64 = 0x8000000000;
 }
 
-#[derive(Debug, Clone, FromPrimitive)]
-enum GcReason {
-    AllocSmall = 0,
-    Induced,
-    LowMemory,
-    Empty,
-    AllocLarge,
-    OutOfSpaceSmallObjectHeap,
-    OutOfSpaceLargeObjectHeap,
-    InducedNoForce,
-    Stress,
-    InducedLowMemory,
-}
-
-impl Display for GcReason {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            GcReason::AllocSmall => f.write_str("Small object heap allocation"),
-            GcReason::Induced => f.write_str("Induced"),
-            GcReason::LowMemory => f.write_str("Low memory"),
-            GcReason::Empty => f.write_str("Empty"),
-            GcReason::AllocLarge => f.write_str("Large object heap allocation"),
-            GcReason::OutOfSpaceSmallObjectHeap => {
-                f.write_str("Out of space (for small object heap)")
-            }
-            GcReason::OutOfSpaceLargeObjectHeap => {
-                f.write_str("Out of space (for large object heap)")
-            }
-            GcReason::InducedNoForce => f.write_str("Induced but not forced as blocking"),
-            GcReason::Stress => f.write_str("Stress"),
-            GcReason::InducedLowMemory => f.write_str("Induced low memory"),
-        }
-    }
-}
-
-#[derive(Debug, Clone, FromPrimitive)]
-enum GcSuspendEeReason {
-    Other = 0,
-    GC,
-    AppDomainShutdown,
-    CodePitching,
-    Shutdown,
-    Debugger,
-    GcPrep,
-    DebuggerSweep,
-}
-
-impl Display for GcSuspendEeReason {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            GcSuspendEeReason::Other => f.write_str("Other"),
-            GcSuspendEeReason::GC => f.write_str("GC"),
-            GcSuspendEeReason::AppDomainShutdown => f.write_str("AppDomain shutdown"),
-            GcSuspendEeReason::CodePitching => f.write_str("Code pitching"),
-            GcSuspendEeReason::Shutdown => f.write_str("Shutdown"),
-            GcSuspendEeReason::Debugger => f.write_str("Debugger"),
-            GcSuspendEeReason::GcPrep => f.write_str("GC prep"),
-            GcSuspendEeReason::DebuggerSweep => f.write_str("Debugger sweep"),
-        }
-    }
-}
-
-#[derive(Debug, Clone, FromPrimitive)]
-enum GcType {
-    Blocking,
-    Background,
-    BlockingDuringBackground,
-}
-
-impl Display for GcType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            GcType::Blocking => f.write_str("Blocking GC"),
-            GcType::Background => f.write_str("Background GC"),
-            GcType::BlockingDuringBackground => f.write_str("Blocking GC during background GC"),
-        }
-    }
-}
-// String is type name
-#[derive(Debug, Clone)]
-pub struct CoreClrGcAllocMarker(StringHandle, f64, CategoryHandle);
-
-impl StaticSchemaMarker for CoreClrGcAllocMarker {
-    const UNIQUE_MARKER_TYPE_NAME: &'static str = "GC Alloc";
-
-    fn schema() -> MarkerSchema {
-        MarkerSchema {
-            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
-            locations: vec![
-                MarkerLocation::MarkerChart,
-                MarkerLocation::MarkerTable,
-                MarkerLocation::TimelineMemory,
-            ],
-            chart_label: Some("GC Alloc".into()),
-            tooltip_label: Some(
-                "GC Alloc: {marker.data.clrtype} ({marker.data.size} bytes)".into(),
-            ),
-            table_label: Some("GC Alloc".into()),
-            fields: vec![
-                MarkerFieldSchema {
-                    key: "clrtype".into(),
-                    label: "CLR Type".into(),
-                    format: MarkerFieldFormat::String,
-                    searchable: true,
-                },
-                MarkerFieldSchema {
-                    key: "size".into(),
-                    label: "Size".into(),
-                    format: MarkerFieldFormat::Bytes,
-                    searchable: false,
-                },
-            ],
-            static_fields: vec![MarkerStaticField {
-                label: "Description".into(),
-                value: "GC Allocation.".into(),
-            }],
-        }
-    }
-
-    fn name(&self, profile: &mut Profile) -> StringHandle {
-        profile.intern_string("GC Alloc")
-    }
-
-    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
-        self.2
-    }
-
-    fn string_field_value(&self, _field_index: u32) -> StringHandle {
-        self.0
-    }
-
-    fn number_field_value(&self, _field_index: u32) -> f64 {
-        self.1
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct CoreClrGcEventMarker(StringHandle, StringHandle, CategoryHandle);
-
-impl StaticSchemaMarker for CoreClrGcEventMarker {
-    const UNIQUE_MARKER_TYPE_NAME: &'static str = "GC Event";
-
-    fn schema() -> MarkerSchema {
-        MarkerSchema {
-            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
-            locations: vec![
-                MarkerLocation::MarkerChart,
-                MarkerLocation::MarkerTable,
-                MarkerLocation::TimelineMemory,
-            ],
-            chart_label: Some("{marker.data.event}".into()),
-            tooltip_label: Some("{marker.data.event}".into()),
-            table_label: Some("{marker.data.event}".into()),
-            fields: vec![MarkerFieldSchema {
-                key: "event".into(),
-                label: "Event".into(),
-                format: MarkerFieldFormat::String,
-                searchable: true,
-            }],
-            static_fields: vec![MarkerStaticField {
-                label: "Description".into(),
-                value: "Generic GC Event.".into(),
-            }],
-        }
-    }
-
-    fn name(&self, _profile: &mut Profile) -> StringHandle {
-        self.0
-    }
-
-    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
-        self.2
-    }
-
-    fn string_field_value(&self, _field_index: u32) -> StringHandle {
-        self.1
-    }
-
-    fn number_field_value(&self, _field_index: u32) -> f64 {
-        unreachable!()
-    }
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct DisplayUnknownIfNone<'a, T>(pub &'a Option<T>);
-
-impl<'a, T: Display> Display for DisplayUnknownIfNone<'a, T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.0 {
-            Some(value) => value.fmt(f),
-            None => f.write_str("Unknown"),
-        }
-    }
-}
-
 pub fn coreclr_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
     let mut providers = vec![];
 
@@ -339,7 +156,9 @@ pub fn coreclr_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
     // which is only useful if we're tracing an already running process.
     // if STACK is enabled, then every CoreCLR event will also generate a stack event right afterwards
     use constants::*;
-    let mut info_keywords = CORECLR_LOADER_KEYWORD;
+    // Exception events are cheap (they only fire when an exception is thrown or
+    // caught) and often explain latency spikes, so we always request them.
+    let mut info_keywords = CORECLR_LOADER_KEYWORD | CORECLR_EXCEPTION_KEYWORD;
     if props.coreclr.event_stacks {
         info_keywords |= CORECLR_STACK_KEYWORD;
     }
@@ -474,11 +293,21 @@ pub fn handle_coreclr_event(
                 // there's some stuff in MethodFlags -- might be tiered JIT info?
                 // also ClrInstanceID -- we probably won't have more than one runtime, but maybe.
 
-                let method_name = format!("{method_basename} [{method_namespace}] \u{2329}{method_signature}\u{232a}");
+                let method_name = method_name(&method_basename, &method_namespace, &method_signature);
 
                 context.handle_coreclr_method_load(timestamp_raw, pid, method_name, method_start_address, method_size);
                 handled = true;
             }
+            "MethodUnloadVerbose" => {
+                let method_basename: String = parser.parse("MethodName");
+                let method_namespace: String = parser.parse("MethodNamespace");
+                let method_signature: String = parser.parse("MethodSignature");
+                let method_size: u32 = parser.parse("MethodSize");
+                let method_name = method_name(&method_basename, &method_namespace, &method_signature);
+
+                context.handle_coreclr_method_unload(timestamp_raw, pid, &method_name, method_size);
+                handled = true;
+            }
             "ModuleLoad" | "ModuleDCStart" |
             "ModuleUnload" | "ModuleDCEnd" => {
                 // do we need this for ReadyToRun code?
@@ -552,7 +381,7 @@ pub fn handle_coreclr_event(
                 .chain(parser.buffer.chunks_exact(8))
                 .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()));
 
-            context.handle_coreclr_stack(timestamp_raw, tid, address_iter, marker);
+            context.handle_marker_stack(timestamp_raw, tid, address_iter, marker);
             handled = true;
         }
         ("GarbageCollection", gc_event) => {
@@ -731,15 +560,82 @@ pub fn handle_coreclr_event(
         ("CLRRuntimeInformation", _) => {
             handled = true;
         }
-        ("CLRLoader", _) => {
+        ("CLRLoader", loader_event) => {
             // AppDomain, Assembly, Module Load/Unload
+            if loader_event == "AssemblyLoad" && is_in_time_range {
+                let assembly_name: String = parser.parse("FullyQualifiedAssemblyName");
+
+                let category = context.known_category(KnownCategory::CoreClrLoader);
+                let assembly_name = context.intern_profile_string(&assembly_name);
+                let mh = context.add_thread_instant_marker(
+                    timestamp_raw,
+                    tid,
+                    CoreClrAssemblyLoadMarker(assembly_name, category),
+                );
+                coreclr_context.set_last_event_for_thread(tid, mh);
+            }
+            handled = true;
+        }
+        ("Exception", "win:Start") => {
+            // ExceptionThrown_V1
+            if !is_in_time_range {
+                return;
+            }
+
+            let exception_type: String = parser.parse("ExceptionType");
+            let exception_message: String = parser.parse("ExceptionMessage");
+
+            let category = context.known_category(KnownCategory::CoreClrException);
+            let exception_type = context.intern_profile_string(&exception_type);
+            let exception_message = context.intern_profile_string(&exception_message);
+            let mh = context.add_thread_instant_marker(
+                timestamp_raw,
+                tid,
+                CoreClrExceptionThrownMarker(exception_type, exception_message, category),
+            );
+            coreclr_context
+                .last_exception_type_on_thread
+                .insert(tid, exception_type);
+            coreclr_context.set_last_event_for_thread(tid, mh);
+            handled = true;
+        }
+        ("ExceptionCatch", "win:Start") => {
+            // ExceptionCatch_V1. This event doesn't carry the exception type, so we
+            // use the type of the most recent exception thrown on this thread.
+            if !is_in_time_range {
+                return;
+            }
+
+            let method_name: String = parser.parse("MethodName");
+
+            let category = context.known_category(KnownCategory::CoreClrException);
+            let exception_type = match coreclr_context.last_exception_type_on_thread.remove(&tid) {
+                Some(exception_type) => exception_type,
+                None => context.intern_profile_string("Unknown"),
+            };
+            let method_name = context.intern_profile_string(&method_name);
+            let mh = context.add_thread_instant_marker(
+                timestamp_raw,
+                tid,
+                CoreClrExceptionCaughtMarker(exception_type, method_name, category),
+            );
+            coreclr_context.set_last_event_for_thread(tid, mh);
+            handled = true;
+        }
+        ("Exception" | "ExceptionCatch" | "ExceptionFilter" | "ExceptionFinally", _) => {
+            // don't care about the other exception handling stages
             handled = true;
         }
         _ => {}
     }
 
     if !handled && coreclr_context.unknown_event_markers {
-        let text = event_properties_to_string(s, parser, None);
+        let text = event_properties_to_string_with_limits(
+            s,
+            parser,
+            None,
+            &coreclr_context.payload_limits,
+        );
         let name = context.intern_profile_string(s.name().split_once('/').unwrap().1);
         let description = context.intern_profile_string(&text);
         let marker_handle = context.add_thread_instant_marker(
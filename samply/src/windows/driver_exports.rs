@@ -0,0 +1,158 @@
+//! Symbols from the export table of kernel-mode drivers whose PDBs aren't
+//! available, such as GPU drivers. Their vendors don't publish the PDBs, so
+//! without this, the driver frames in kernel stacks are bare addresses.
+//!
+//! The exports only cover a small part of a driver, and each address gets the
+//! name of the closest export before it, so these names are only a hint of
+//! where in the driver the time went.
+
+use std::path::Path;
+
+use fxprof_processed_profile::{Symbol, SymbolTable};
+use object::read::pe::{ImageNtHeaders, ImageOptionalHeader, PeFile, PeFile32, PeFile64};
+use object::Object;
+
+/// Whether the symbols of the driver at `path` should come from its exports:
+/// drivers without debug info, and drivers from third-party driver packages,
+/// which are installed in the DriverStore, whose PDB isn't on this machine.
+/// The PDBs of the drivers which come with Windows are on the Microsoft
+/// symbol server.
+pub fn needs_export_symbols(path: &str, pdb_path: Option<&str>) -> bool {
+    let path_lower = path.to_lowercase();
+    if !path_lower.ends_with(".sys") {
+        return false;
+    }
+    match pdb_path {
+        None => true,
+        Some(pdb_path) => path_lower.contains("\\driverstore\\") && !Path::new(pdb_path).exists(),
+    }
+}
+
+/// Reads the export table of the driver at `path`, if the file is the image
+/// with this size and checksum.
+pub fn load_export_symbol_table(
+    path: &Path,
+    image_size: u32,
+    image_checksum: u32,
+) -> Option<SymbolTable> {
+    let file = std::fs::File::open(path).ok()?;
+    let mmap = unsafe { memmap2::Mmap::map(&file).ok()? };
+    let symbols = match object::FileKind::parse(&mmap[..]).ok()? {
+        object::FileKind::Pe32 => export_symbols(
+            &PeFile32::parse(&mmap[..]).ok()?,
+            image_size,
+            image_checksum,
+        )?,
+        object::FileKind::Pe64 => export_symbols(
+            &PeFile64::parse(&mmap[..]).ok()?,
+            image_size,
+            image_checksum,
+        )?,
+        _ => return None,
+    };
+    if symbols.is_empty() {
+        return None;
+    }
+    Some(SymbolTable::new(symbols))
+}
+
+fn export_symbols<'a, Pe: ImageNtHeaders, R: object::ReadRef<'a>>(
+    pe: &PeFile<'a, Pe, R>,
+    image_size: u32,
+    image_checksum: u32,
+) -> Option<Vec<Symbol>> {
+    let optional_header = pe.nt_headers().optional_header();
+    if optional_header.size_of_image() != image_size
+        || optional_header.check_sum() != image_checksum
+    {
+        // This is a different build of the driver than the one in the trace.
+        return None;
+    }
+    let image_base = pe.relative_address_base();
+    let exports: Vec<(u32, String)> = pe
+        .exports()
+        .ok()?
+        .into_iter()
+        .filter_map(|export| {
+            let address = u32::try_from(export.address().checked_sub(image_base)?).ok()?;
+            let name = std::str::from_utf8(export.name()).ok()?;
+            Some((address, name.to_owned()))
+        })
+        .collect();
+    Some(symbols_from_exports(exports))
+}
+
+/// Makes symbols from (relative address, name) pairs. Each symbol ends where
+/// the next one starts. Of several exports at the same address, the first by
+/// name is kept.
+fn symbols_from_exports(mut exports: Vec<(u32, String)>) -> Vec<Symbol> {
+    exports.sort();
+    exports.dedup_by_key(|(address, _)| *address);
+    let next_addresses: Vec<Option<u32>> = exports
+        .iter()
+        .skip(1)
+        .map(|(address, _)| Some(*address))
+        .chain(std::iter::once(None))
+        .collect();
+    exports
+        .iter()
+        .zip(next_addresses)
+        .map(|((address, name), next_address)| Symbol {
+            address: *address,
+            size: next_address.map(|next_address| next_address - address),
+            name: name.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_needs_export_symbols() {
+        let driver_store =
+            r"C:\Windows\System32\DriverStore\FileRepository\nv.inf_amd64_1\nvlddmkm.sys";
+        assert!(needs_export_symbols(
+            driver_store,
+            Some(r"X:\build\nonexistent\nvlddmkm.pdb")
+        ));
+        assert!(needs_export_symbols(
+            r"C:\Windows\System32\drivers\foo.SYS",
+            None
+        ));
+        assert!(!needs_export_symbols(
+            r"C:\Windows\System32\drivers\ntfs.sys",
+            Some("ntfs.pdb")
+        ));
+        assert!(!needs_export_symbols(
+            r"C:\Windows\System32\ntdll.dll",
+            None
+        ));
+    }
+
+    #[test]
+    fn test_symbols_from_exports() {
+        let exports = vec![
+            (0x2000, "C".to_string()),
+            (0x1000, "A".to_string()),
+            (0x2000, "B".to_string()),
+        ];
+        let symbols = symbols_from_exports(exports);
+        assert_eq!(
+            symbols,
+            vec![
+                Symbol {
+                    address: 0x1000,
+                    size: Some(0x1000),
+                    name: "A".to_string()
+                },
+                Symbol {
+                    address: 0x2000,
+                    size: None,
+                    name: "B".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -4,7 +4,8 @@ use std::path::{Path, PathBuf};
 use serde_derive::{Deserialize, Serialize};
 
 use crate::shared::recording_props::{
-    CoreClrProfileProps, ProfileCreationProps, RecordingMode, RecordingProps,
+    CoreClrProfileProps, EtwProviderProps, ProfileCreationProps, RecordingMode, RecordingProps,
+    StackWalkEvent,
 };
 
 use super::utility_process::{
@@ -29,6 +30,16 @@ pub struct ElevatedRecordingProps {
     pub is_attach: bool,
     pub gfx: bool,
     pub browsers: bool,
+    pub pmc_counters: Vec<String>,
+    pub pmc_sampling_source: Option<String>,
+    pub pmc_sampling_interval: u32,
+    pub antivirus: bool,
+    pub thread_states: bool,
+    pub scheduler_latency: bool,
+    pub wakeups: bool,
+    pub hangs: bool,
+    pub stack_walk_events: Vec<StackWalkEvent>,
+    pub etw_providers: Vec<EtwProviderProps>,
 }
 
 impl ElevatedRecordingProps {
@@ -45,6 +56,16 @@ impl ElevatedRecordingProps {
             is_attach: recording_mode.is_attach_mode(),
             gfx: recording_props.gfx,
             browsers: recording_props.browsers,
+            pmc_counters: recording_props.pmc_counters.clone(),
+            pmc_sampling_source: recording_props.pmc_sampling_source.clone(),
+            pmc_sampling_interval: recording_props.pmc_sampling_interval,
+            antivirus: recording_props.antivirus,
+            thread_states: profile_creation_props.thread_states,
+            scheduler_latency: profile_creation_props.scheduler_latency_threshold.is_some(),
+            wakeups: profile_creation_props.wakeups,
+            hangs: profile_creation_props.hang_threshold.is_some(),
+            stack_walk_events: profile_creation_props.stack_walk_events.clone(),
+            etw_providers: profile_creation_props.etw_providers.clone(),
         }
     }
 }
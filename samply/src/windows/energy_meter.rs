@@ -0,0 +1,308 @@
+//! Reads the system's energy meters through the Energy Meter Interface (EMI)
+//! for `--power-counters`. An EMI device reports the absolute energy of each of
+//! its channels, e.g. "CPU Cores" or "GPU", in picowatt-hours. We poll the
+//! devices on a separate thread while the ETW sessions are recording, and turn
+//! the readings into power tracks once the trace has been processed.
+
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use windows::core::PCWSTR;
+use windows::Win32::Devices::DeviceAndDriverInstallation::{
+    SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInterfaces, SetupDiGetClassDevsW,
+    SetupDiGetDeviceInterfaceDetailW, DIGCF_DEVICEINTERFACE, DIGCF_PRESENT,
+    SP_DEVICE_INTERFACE_DATA, SP_DEVICE_INTERFACE_DETAIL_DATA_W,
+};
+use windows::Win32::Foundation::{CloseHandle, GENERIC_READ, HANDLE};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+};
+use windows::Win32::System::Performance::QueryPerformanceCounter;
+use windows::Win32::System::Power::{
+    EMI_CHANNEL_MEASUREMENT_DATA, EMI_METADATA_SIZE, EMI_VERSION, EMI_VERSION_V1, EMI_VERSION_V2,
+    GUID_DEVICE_ENERGY_METER, IOCTL_EMI_GET_MEASUREMENT, IOCTL_EMI_GET_METADATA,
+    IOCTL_EMI_GET_METADATA_SIZE, IOCTL_EMI_GET_VERSION,
+};
+use windows::Win32::System::IO::DeviceIoControl;
+
+/// How often the energy meters are read. The meters themselves usually
+/// update less often than this.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The readings of one energy meter channel.
+#[derive(Debug, Clone)]
+pub struct EnergyChannelReadings {
+    pub name: String,
+    /// The QPC timestamp of each reading and the channel's absolute energy at
+    /// that time, in picowatt-hours.
+    pub readings: Vec<(u64, u64)>,
+}
+
+/// Polls all energy meters of the system on a background thread.
+pub struct EnergyMeterRecorder {
+    stop_flag: Arc<AtomicBool>,
+    thread: JoinHandle<Vec<EnergyChannelReadings>>,
+}
+
+impl EnergyMeterRecorder {
+    /// Starts polling. Returns `None` if the system doesn't have any energy
+    /// meters which we can read.
+    pub fn start() -> Option<Self> {
+        let meters: Vec<EnergyMeter> = find_energy_meter_paths()
+            .iter()
+            .filter_map(|path| EnergyMeter::open(path))
+            .collect();
+        if meters.is_empty() {
+            return None;
+        }
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let stop_flag = stop_flag.clone();
+            move || poll_energy_meters(meters, &stop_flag)
+        });
+        Some(Self { stop_flag, thread })
+    }
+
+    /// Stops polling and returns the readings of all channels.
+    pub fn stop(self) -> Vec<EnergyChannelReadings> {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+fn poll_energy_meters(
+    meters: Vec<EnergyMeter>,
+    stop_flag: &AtomicBool,
+) -> Vec<EnergyChannelReadings> {
+    let mut channels: Vec<Vec<EnergyChannelReadings>> = meters
+        .iter()
+        .map(|meter| {
+            meter
+                .channel_names
+                .iter()
+                .map(|name| EnergyChannelReadings {
+                    name: name.clone(),
+                    readings: Vec::new(),
+                })
+                .collect()
+        })
+        .collect();
+    while !stop_flag.load(Ordering::Relaxed) {
+        for (meter, channels) in meters.iter().zip(channels.iter_mut()) {
+            let Some(measurements) = meter.read() else {
+                continue;
+            };
+            let timestamp_raw = query_performance_counter();
+            for (channel, measurement) in channels.iter_mut().zip(measurements) {
+                channel
+                    .readings
+                    .push((timestamp_raw, measurement.AbsoluteEnergy));
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    channels.into_iter().flatten().collect()
+}
+
+fn query_performance_counter() -> u64 {
+    let mut count = 0;
+    let _ = unsafe { QueryPerformanceCounter(&mut count) };
+    count as u64
+}
+
+/// Returns the device paths of the energy meters, as nul-terminated UTF-16.
+fn find_energy_meter_paths() -> Vec<Vec<u16>> {
+    let mut paths = Vec::new();
+    unsafe {
+        let Ok(device_info_set) = SetupDiGetClassDevsW(
+            Some(&GUID_DEVICE_ENERGY_METER),
+            PCWSTR::null(),
+            None,
+            DIGCF_PRESENT | DIGCF_DEVICEINTERFACE,
+        ) else {
+            return paths;
+        };
+        for index in 0.. {
+            let mut interface_data = SP_DEVICE_INTERFACE_DATA {
+                cbSize: size_of::<SP_DEVICE_INTERFACE_DATA>() as u32,
+                ..Default::default()
+            };
+            if SetupDiEnumDeviceInterfaces(
+                device_info_set,
+                None,
+                &GUID_DEVICE_ENERGY_METER,
+                index,
+                &mut interface_data,
+            )
+            .is_err()
+            {
+                break;
+            }
+            let mut required_size = 0;
+            let _ = SetupDiGetDeviceInterfaceDetailW(
+                device_info_set,
+                &interface_data,
+                None,
+                0,
+                Some(&mut required_size),
+                None,
+            );
+            if (required_size as usize) < size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() {
+                continue;
+            }
+            // Use a u32 buffer so that the detail data is sufficiently aligned.
+            let mut buffer = vec![0u32; (required_size as usize).div_ceil(4)];
+            let detail_data = buffer.as_mut_ptr() as *mut SP_DEVICE_INTERFACE_DETAIL_DATA_W;
+            (*detail_data).cbSize = size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() as u32;
+            if SetupDiGetDeviceInterfaceDetailW(
+                device_info_set,
+                &interface_data,
+                Some(detail_data),
+                required_size,
+                None,
+                None,
+            )
+            .is_err()
+            {
+                continue;
+            }
+            let path_ptr = std::ptr::addr_of!((*detail_data).DevicePath) as *const u16;
+            let path_len = PCWSTR(path_ptr).len();
+            let mut path = std::slice::from_raw_parts(path_ptr, path_len).to_vec();
+            path.push(0);
+            paths.push(path);
+        }
+        let _ = SetupDiDestroyDeviceInfoList(device_info_set);
+    }
+    paths
+}
+
+/// An open EMI device.
+struct EnergyMeter {
+    handle: HANDLE,
+    channel_names: Vec<String>,
+}
+
+// The handle is only used from the polling thread once the meter has been opened.
+unsafe impl Send for EnergyMeter {}
+
+impl EnergyMeter {
+    fn open(path: &[u16]) -> Option<Self> {
+        let handle = unsafe {
+            CreateFileW(
+                PCWSTR(path.as_ptr()),
+                GENERIC_READ.0,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                None,
+                OPEN_EXISTING,
+                FILE_ATTRIBUTE_NORMAL,
+                None,
+            )
+        }
+        .ok()?;
+        let mut meter = EnergyMeter {
+            handle,
+            channel_names: Vec::new(),
+        };
+        let mut version = EMI_VERSION::default();
+        meter.ioctl(IOCTL_EMI_GET_VERSION, &mut version)?;
+        let mut metadata_size = EMI_METADATA_SIZE::default();
+        meter.ioctl(IOCTL_EMI_GET_METADATA_SIZE, &mut metadata_size)?;
+        let mut metadata = vec![0u8; metadata_size.MetadataSize as usize];
+        meter.ioctl_buffer(IOCTL_EMI_GET_METADATA, &mut metadata)?;
+        meter.channel_names = match u32::from(version.EmiVersion) {
+            EMI_VERSION_V1 => vec![parse_metadata_v1_name(&metadata)?],
+            EMI_VERSION_V2 => parse_metadata_v2_channel_names(&metadata)?,
+            _ => return None,
+        };
+        if meter.channel_names.is_empty() {
+            return None;
+        }
+        Some(meter)
+    }
+
+    /// Returns the current measurement of each channel.
+    fn read(&self) -> Option<Vec<EMI_CHANNEL_MEASUREMENT_DATA>> {
+        let mut measurements =
+            vec![EMI_CHANNEL_MEASUREMENT_DATA::default(); self.channel_names.len()];
+        let size = measurements.len() * size_of::<EMI_CHANNEL_MEASUREMENT_DATA>();
+        let buffer =
+            unsafe { std::slice::from_raw_parts_mut(measurements.as_mut_ptr() as *mut u8, size) };
+        self.ioctl_buffer(IOCTL_EMI_GET_MEASUREMENT, buffer)?;
+        Some(measurements)
+    }
+
+    fn ioctl<T>(&self, code: u32, output: &mut T) -> Option<()> {
+        let buffer =
+            unsafe { std::slice::from_raw_parts_mut(output as *mut T as *mut u8, size_of::<T>()) };
+        self.ioctl_buffer(code, buffer)
+    }
+
+    fn ioctl_buffer(&self, code: u32, output: &mut [u8]) -> Option<()> {
+        let mut bytes_returned = 0;
+        unsafe {
+            DeviceIoControl(
+                self.handle,
+                code,
+                None,
+                0,
+                Some(output.as_mut_ptr() as *mut _),
+                output.len() as u32,
+                Some(&mut bytes_returned),
+                None,
+            )
+        }
+        .ok()?;
+        (bytes_returned as usize == output.len()).then_some(())
+    }
+}
+
+impl Drop for EnergyMeter {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.handle) };
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_utf16_string(bytes: &[u8], offset: usize, size: usize) -> Option<String> {
+    let units: Vec<u16> = bytes
+        .get(offset..offset + size)?
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect();
+    Some(String::from_utf16_lossy(&units))
+}
+
+/// Returns the name of the metered hardware from an `EMI_METADATA_V1`, which
+/// describes a single channel.
+fn parse_metadata_v1_name(metadata: &[u8]) -> Option<String> {
+    // MeasurementUnit: u32, HardwareOEM: [u16; 16], HardwareModel: [u16; 16],
+    // HardwareRevision: u16, MeteredHardwareNameSize: u16, MeteredHardwareName
+    let name_size = read_u16(metadata, 70)?;
+    read_utf16_string(metadata, 72, name_size.into())
+}
+
+/// Returns the channel names from an `EMI_METADATA_V2`.
+fn parse_metadata_v2_channel_names(metadata: &[u8]) -> Option<Vec<String>> {
+    // HardwareOEM: [u16; 16], HardwareModel: [u16; 16], HardwareRevision: u16,
+    // ChannelCount: u16, followed by the variable-size EMI_CHANNEL_V2 entries:
+    // MeasurementUnit: u32, ChannelNameSize: u16, ChannelName
+    let channel_count = read_u16(metadata, 66)?;
+    let mut offset = 68;
+    let mut names = Vec::with_capacity(channel_count.into());
+    for _ in 0..channel_count {
+        let name_size = usize::from(read_u16(metadata, offset + 4)?);
+        names.push(read_utf16_string(metadata, offset + 6, name_size)?);
+        offset += 6 + name_size;
+    }
+    Some(names)
+}
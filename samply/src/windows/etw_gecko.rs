@@ -6,49 +6,80 @@ use debugid::DebugId;
 use etw_reader::parser::{Address, Parser, TryParse};
 use etw_reader::schema::SchemaLocator;
 use etw_reader::{
-    add_custom_schemas, event_properties_to_string, open_trace, print_property, GUID,
+    add_custom_schemas, event_properties_to_string_with_limits, open_trace, print_property,
+    PropertyStringLimits, GUID,
 };
 use fxprof_processed_profile::debugid;
 use uuid::Uuid;
 
 use super::coreclr::CoreClrContext;
 use super::profile_context::ProfileContext;
-use crate::windows::coreclr;
+use super::regions_of_interest::RegionEvent;
+use super::syscall_markers::SyscallKind;
 use crate::windows::profile_context::{KnownCategory, PeInfo};
+use crate::windows::{antivirus, coreclr, hangs, kernel_process, syscall_markers};
 
+/// Which events of a trace `process_trace` handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EventSelection {
+    All,
+    /// The first pass of a two-pass import: processes, threads, images and JIT
+    /// functions.
+    Metadata,
+    /// The second pass of a two-pass import: everything else.
+    Samples,
+}
+
+/// Processes the ETL files into `context`.
+///
+/// With `two_pass`, the files are read twice. The first pass collects the
+/// processes, threads, images and JIT functions. The second pass then adds
+/// the samples to the profile in batches as it goes, rather than keeping all
+/// of them in memory until the end, which bounds the memory use for very
+/// large traces. Stacks which CoreCLR attaches to its own markers are not
+/// supported in this mode; they are dropped with a warning.
 pub fn process_etl_files(
     context: &mut ProfileContext,
     etl_file: &Path,
     extra_etl_filenames: &[PathBuf],
+    two_pass: bool,
 ) {
     let mut schema_locator = SchemaLocator::new();
     add_custom_schemas(&mut schema_locator);
+    for alias in &context.creation_props().etw_provider_aliases {
+        schema_locator.add_provider_alias(
+            GUID::from(alias.provider.as_str()),
+            GUID::from(alias.canonical_provider.as_str()),
+        );
+    }
 
     let processing_start_timestamp = Instant::now();
 
     let mut core_clr_context = CoreClrContext::new(context.creation_props());
 
-    let result = process_trace(
-        etl_file,
-        context,
-        &mut schema_locator,
-        &mut core_clr_context,
-    );
-    if result.is_err() {
-        dbg!(&result);
-        std::process::exit(1);
-    }
-
-    for extra_etl_file in extra_etl_filenames {
-        let result = process_trace(
-            extra_etl_file,
-            context,
-            &mut schema_locator,
-            &mut core_clr_context,
-        );
-        if result.is_err() {
-            dbg!(&result);
-            std::process::exit(1);
+    let passes: &[EventSelection] = if two_pass {
+        &[EventSelection::Metadata, EventSelection::Samples]
+    } else {
+        &[EventSelection::All]
+    };
+    for &selection in passes {
+        if selection == EventSelection::Samples {
+            context.begin_sample_pass();
+        }
+        let etl_files =
+            std::iter::once(etl_file).chain(extra_etl_filenames.iter().map(PathBuf::as_path));
+        for etl_file in etl_files {
+            let result = process_trace(
+                etl_file,
+                context,
+                &mut schema_locator,
+                &mut core_clr_context,
+                selection,
+            );
+            if result.is_err() {
+                dbg!(&result);
+                std::process::exit(1);
+            }
         }
     }
 
@@ -58,24 +89,111 @@ pub fn process_etl_files(
     );
 }
 
+/// Which pass of a two-pass import handles the event with this name. `All`
+/// means both passes: the trace header and the sampling interval are needed
+/// in both.
+fn pass_for_event(name: &str) -> EventSelection {
+    match name {
+        "MSNT_SystemTrace/EventTrace/Header"
+        | "MSNT_SystemTrace/PerfInfo/CollectionStart"
+        | "MSNT_SystemTrace/PerfInfo/SetInterval" => EventSelection::All,
+        "MSNT_SystemTrace/Thread/CSwitch" | "MSNT_SystemTrace/Thread/ReadyThread" => {
+            EventSelection::Samples
+        }
+        _ if name.starts_with("MSNT_SystemTrace/Process/")
+            || name.starts_with("MSNT_SystemTrace/Thread/")
+            || name.starts_with("MSNT_SystemTrace/Image/")
+            || name.starts_with("KernelTraceControl/ImageID/")
+            || name.starts_with("V8.js/")
+            || name.starts_with("Microsoft-JScript/")
+            || name.starts_with("Microsoft-Windows-DotNETRuntime") =>
+        {
+            EventSelection::Metadata
+        }
+        _ => EventSelection::Samples,
+    }
+}
+
+/// How many events to process in the sample pass before the collected
+/// samples are added to the profile.
+const SAMPLE_PASS_FLUSH_INTERVAL: usize = 1_000_000;
+
 fn process_trace(
     etl_file: &Path,
     context: &mut ProfileContext,
     schema_locator: &mut SchemaLocator,
     core_clr_context: &mut CoreClrContext,
+    selection: EventSelection,
 ) -> Result<(), std::io::Error> {
     let is_arm64 = context.is_arm64();
     let demand_zero_faults = false; //pargs.contains("--demand-zero-faults");
     let mut pending_image_info: Option<((u32, u64), PeInfo)> = None;
+    let mut events_since_flush = 0;
+
+    // The events of providers which were enabled with --provider always become
+    // markers, as if --unknown-event-markers had been given for them.
+    let creation_props = context.creation_props();
+    let detect_hangs = creation_props.hang_threshold.is_some();
+    let (requested_guids, requested_names): (Vec<_>, Vec<_>) = creation_props
+        .etw_providers
+        .into_iter()
+        .partition(|provider| provider.is_guid());
+    let payload_limits = PropertyStringLimits {
+        max_value_len: creation_props.max_marker_value_size,
+        max_total_len: creation_props.max_marker_payload_size,
+    };
+    let requested_guids: Vec<GUID> = requested_guids
+        .iter()
+        .map(|provider| GUID::from(provider.provider.as_str()))
+        .collect();
 
     open_trace(etl_file, |e| {
         let Ok(s) = schema_locator.event_schema(e) else {
             return;
         };
 
+        // Regions can start and stop at any kind of event, so they're checked
+        // before the events are split up between the passes.
+        if selection != EventSelection::Metadata && context.has_regions_of_interest() {
+            let descriptor = &e.EventHeader.EventDescriptor;
+            let name = s.name();
+            context.handle_region_of_interest_event(&RegionEvent {
+                provider: e.EventHeader.ProviderId.to_u128(),
+                id: descriptor.Id,
+                version: descriptor.Version,
+                opcode: descriptor.Opcode,
+                name: name.split_once('/').map_or(name, |(_, name)| name),
+                pid: e.EventHeader.ProcessId,
+                tid: e.EventHeader.ThreadId,
+                timestamp_raw: e.EventHeader.TimeStamp as u64,
+            });
+        }
+
+        if selection != EventSelection::All {
+            let event_pass = pass_for_event(s.name());
+            if event_pass != EventSelection::All && event_pass != selection {
+                return;
+            }
+        }
+        if selection == EventSelection::Samples {
+            events_since_flush += 1;
+            if events_since_flush >= SAMPLE_PASS_FLUSH_INTERVAL {
+                context.flush_streamed_samples();
+                events_since_flush = 0;
+            }
+        }
+
         let mut parser = Parser::create(&s);
         let timestamp_raw = e.EventHeader.TimeStamp as u64;
 
+        if !s.name().starts_with("MSNT_SystemTrace/") {
+            context.ensure_synthetic_process_and_thread(
+                timestamp_raw,
+                e.EventHeader.ProcessId,
+                e.EventHeader.ThreadId,
+            );
+        }
+
         //eprintln!("{}", s.name());
         match s.name() {
             "MSNT_SystemTrace/EventTrace/Header" => {
@@ -96,9 +214,11 @@ fn process_trace(
                     }
                 }
             }
-            "MSNT_SystemTrace/PerfInfo/CollectionStart" => {
+            "MSNT_SystemTrace/PerfInfo/CollectionStart"
+            | "MSNT_SystemTrace/PerfInfo/SetInterval" => {
+                let source: u32 = parser.try_parse("Source").unwrap_or(0);
                 let interval_raw: u32 = parser.parse("NewInterval");
-                context.handle_collection_start(interval_raw);
+                context.handle_sampling_interval(timestamp_raw, source, interval_raw);
             }
             "MSNT_SystemTrace/Thread/SetName" => {
                 let pid: u32 = parser.parse("ProcessId");
@@ -110,13 +230,27 @@ fn process_trace(
                 let tid: u32 = parser.parse("TThreadId");
                 let pid: u32 = parser.parse("ProcessId");
                 let thread_name: Option<String> = parser.try_parse("ThreadName").ok();
-                context.handle_thread_dcstart(timestamp_raw, tid, pid, thread_name)
+                let start_address: Option<Address> = parser.try_parse("Win32StartAddr").ok();
+                context.handle_thread_dcstart(
+                    timestamp_raw,
+                    tid,
+                    pid,
+                    thread_name,
+                    start_address.map(|address| address.as_u64()),
+                );
             }
             "MSNT_SystemTrace/Thread/Start" => {
                 let tid: u32 = parser.parse("TThreadId");
                 let pid: u32 = parser.parse("ProcessId");
                 let thread_name: Option<String> = parser.try_parse("ThreadName").ok();
-                context.handle_thread_start(timestamp_raw, tid, pid, thread_name);
+                let start_address: Option<Address> = parser.try_parse("Win32StartAddr").ok();
+                context.handle_thread_start(
+                    timestamp_raw,
+                    tid,
+                    pid,
+                    thread_name,
+                    start_address.map(|address| address.as_u64()),
+                );
             }
             "MSNT_SystemTrace/Thread/End" => {
                 let tid: u32 = parser.parse("TThreadId");
@@ -161,7 +295,8 @@ fn process_trace(
             }
             "MSNT_SystemTrace/Process/End" => {
                 let pid: u32 = parser.parse("ProcessId");
-                context.handle_process_end(timestamp_raw, pid);
+                let exit_code: Option<u32> = parser.try_parse("ExitStatus").ok();
+                context.handle_process_end(timestamp_raw, pid, exit_code);
             }
             "MSNT_SystemTrace/Process/DCEnd" => {
                 let pid: u32 = parser.parse("ProcessId");
@@ -208,7 +343,39 @@ fn process_trace(
                 }
                 let tid: u32 = parser.parse("ThreadId");
                 let cpu = u32::from(unsafe { e.BufferContext.Anonymous.ProcessorIndex });
-                context.handle_sample(timestamp_raw, tid, cpu);
+                let gap_nanos = context.check_sample_gap(timestamp_raw, cpu);
+                context.handle_sample(timestamp_raw, tid, cpu, gap_nanos);
+            }
+            pmc_event_name
+                if pmc_event_name.starts_with("MSNT_SystemTrace/PerfInfo/")
+                    && pmc_event_name.to_ascii_lowercase().contains("pmcinterrupt") =>
+            {
+                // Logged instead of SampleProf when the samples are driven by
+                // a PMC profile source (`--pmc-sampling`).
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                let tid: u32 = parser.parse("ThreadId");
+                let source: Option<u32> = parser.try_parse("ProfileSource").ok();
+                context.handle_pmc_sample(timestamp_raw, tid, source);
+            }
+            pmc_event_name
+                if pmc_event_name.starts_with("MSNT_SystemTrace/PerfInfo/")
+                    && pmc_event_name.to_ascii_lowercase().contains("pmccounter") =>
+            {
+                // Logged right after each SampleProf event on the same CPU if
+                // PMCs were configured with `xperf -pmc <counters> PROFILE`.
+                // The payload is the list of raw counter values.
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                let cpu = u32::from(unsafe { e.BufferContext.Anonymous.ProcessorIndex });
+                let counter_values: Vec<u64> = parser
+                    .buffer
+                    .chunks_exact(8)
+                    .map(|a| u64::from_ne_bytes(a.try_into().unwrap()))
+                    .collect();
+                context.handle_pmc_counters(timestamp_raw, cpu, &counter_values);
             }
             "MSNT_SystemTrace/PageFault/DemandZeroFault" => {
                 if !context.is_in_time_range(timestamp_raw) {
@@ -220,7 +387,7 @@ fn process_trace(
 
                 let tid: u32 = s.thread_id();
                 let cpu = u32::from(unsafe { e.BufferContext.Anonymous.ProcessorIndex });
-                context.handle_sample(timestamp_raw, tid, cpu);
+                context.handle_sample(timestamp_raw, tid, cpu, None);
             }
             "MSNT_SystemTrace/PageFault/VirtualAlloc"
             | "MSNT_SystemTrace/PageFault/VirtualFree" => {
@@ -231,7 +398,8 @@ fn process_trace(
                 let pid = e.EventHeader.ProcessId;
                 let tid = e.EventHeader.ThreadId;
                 let region_size: u64 = parser.parse("RegionSize");
-                let text = event_properties_to_string(&s, &mut parser, None);
+                let text =
+                    event_properties_to_string_with_limits(&s, &mut parser, None, &payload_limits);
                 context.handle_virtual_alloc_free(
                     timestamp_raw,
                     is_free,
@@ -304,7 +472,9 @@ fn process_trace(
                 context.handle_image_load(timestamp_raw, pid, image_base, path, info);
             }
             "MSNT_SystemTrace/Image/UnLoad" => {
-                // nothing, but we don't want a marker for it
+                let pid = parser.try_parse("ProcessId").unwrap();
+                let image_base: u64 = parser.try_parse("ImageBase").unwrap();
+                context.handle_image_unload(timestamp_raw, pid, image_base);
             }
             "Microsoft-Windows-DxgKrnl/VSyncDPC/Info " => {
                 if !context.is_in_time_range(timestamp_raw) {
@@ -319,15 +489,29 @@ fn process_trace(
                 let old_tid: u32 = parser.parse("OldThreadId");
                 let new_tid: u32 = parser.parse("NewThreadId");
                 let cpu = u32::from(unsafe { e.BufferContext.Anonymous.ProcessorIndex });
+                let old_thread_state: i8 = parser.parse("OldThreadState");
                 let wait_reason: i8 = parser.parse("OldThreadWaitReason");
-                context.handle_cswitch(timestamp_raw, old_tid, new_tid, cpu, wait_reason);
+                context.handle_cswitch(
+                    timestamp_raw,
+                    old_tid,
+                    new_tid,
+                    cpu,
+                    old_thread_state,
+                    wait_reason,
+                );
             }
             "MSNT_SystemTrace/Thread/ReadyThread" => {
                 if !context.is_in_time_range(timestamp_raw) {
                     return;
                 }
-                // these events can give us the unblocking stack
-                let _thread_id: u32 = parser.parse("TThreadId");
+                // these events can also give us the unblocking stack
+                let thread_id: u32 = parser.parse("TThreadId");
+                context.handle_ready_thread(
+                    timestamp_raw,
+                    thread_id,
+                    s.process_id(),
+                    e.EventHeader.ThreadId,
+                );
             }
             "V8.js/SourceLoad/Start"
             | "Microsoft-JScript/ScriptContextRuntime/SourceLoad"
@@ -364,6 +548,20 @@ fn process_trace(
                     column,
                 );
             }
+            "Microsoft-JScript/MethodRuntime/MethodUnload" => {
+                let pid = s.process_id();
+                if !context.has_process_at_time(pid, timestamp_raw) {
+                    return;
+                }
+                let method_name: String = parser.parse("MethodName");
+                let method_size: u64 = parser.parse("MethodSize");
+                context.handle_js_method_unload(
+                    timestamp_raw,
+                    pid,
+                    &method_name,
+                    method_size as u32,
+                );
+            }
             "Microsoft-Windows-Direct3D11/ID3D11VideoContext_SubmitDecoderBuffers/win:Start" => {
                 if !context.is_in_time_range(timestamp_raw) {
                     return;
@@ -372,7 +570,8 @@ fn process_trace(
                 if !context.has_thread_at_time(tid, timestamp_raw) {
                     return;
                 }
-                let text = event_properties_to_string(&s, &mut parser, None);
+                let text =
+                    event_properties_to_string_with_limits(&s, &mut parser, None, &payload_limits);
                 context.handle_freeform_marker_start(
                     timestamp_raw,
                     tid,
@@ -388,7 +587,8 @@ fn process_trace(
                 if !context.has_thread_at_time(tid, timestamp_raw) {
                     return;
                 }
-                let text = event_properties_to_string(&s, &mut parser, None);
+                let text =
+                    event_properties_to_string_with_limits(&s, &mut parser, None, &payload_limits);
                 context.handle_freeform_marker_end(
                     timestamp_raw,
                     tid,
@@ -418,7 +618,7 @@ fn process_trace(
                 let maybe_user_timing_name: Option<String> = parser.try_parse("name").ok();
                 let maybe_explicit_marker_name: Option<String> =
                     parser.try_parse("MarkerName").ok();
-                let text = event_properties_to_string(
+                let text = event_properties_to_string_with_limits(
                     &s,
                     &mut parser,
                     Some(&[
@@ -429,6 +629,7 @@ fn process_trace(
                         "InnerWindowId",
                         "CategoryPair",
                     ]),
+                    &payload_limits,
                 );
                 context.handle_firefox_marker(
                     tid,
@@ -478,10 +679,11 @@ fn process_trace(
                 };
                 let phase: String = parser.try_parse("Phase").unwrap();
                 let keyword_bitfield = e.EventHeader.EventDescriptor.Keyword; // a bitfield of keywords
-                let text = event_properties_to_string(
+                let text = event_properties_to_string_with_limits(
                     &s,
                     &mut parser,
                     Some(&["Timestamp", "Phase", "Duration"]),
+                    &payload_limits,
                 );
                 context.handle_chrome_marker(
                     tid,
@@ -493,6 +695,81 @@ fn process_trace(
                     text,
                 );
             }
+            kernel_process_event
+                if kernel_process_event.starts_with("Microsoft-Windows-Kernel-Process/") =>
+            {
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                let task_and_op = s.name().split_once('/').unwrap().1;
+                let Some(marker_name) = kernel_process::throttling_marker_name(task_and_op) else {
+                    return;
+                };
+                // The event header describes the thread which made the change. The
+                // affected process and thread, if any, are in the payload.
+                let pid: u32 = parser
+                    .try_parse("ProcessID")
+                    .unwrap_or(e.EventHeader.ProcessId);
+                let tid: Option<u32> = parser.try_parse("ThreadID").ok();
+                let text =
+                    event_properties_to_string_with_limits(&s, &mut parser, None, &payload_limits);
+                context.handle_process_throttling_event(timestamp_raw, pid, tid, marker_name, text);
+            }
+            minifilter_event
+                if minifilter_event.starts_with(antivirus::MINIFILTER_EVENT_PREFIX) =>
+            {
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                // Only the events for completed callbacks know when the callback started.
+                let initial_time: Option<u64> = parser.try_parse("InitialTime").ok();
+                let Some(start_timestamp_raw) = initial_time else {
+                    return;
+                };
+                let tid = e.EventHeader.ThreadId;
+                let text = event_properties_to_string_with_limits(
+                    &s,
+                    &mut parser,
+                    Some(&["InitialTime"]),
+                    &payload_limits,
+                );
+                context.handle_minifilter_completion(start_timestamp_raw, timestamp_raw, tid, text);
+            }
+            defender_event if defender_event.starts_with(antivirus::DEFENDER_PROVIDER_PREFIX) => {
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                let tid = e.EventHeader.ThreadId;
+                if !context.has_thread_at_time(tid, timestamp_raw) {
+                    return;
+                }
+                let text =
+                    event_properties_to_string_with_limits(&s, &mut parser, None, &payload_limits);
+                if let Some(name) = defender_event.strip_suffix("/win:Start") {
+                    context.handle_freeform_marker_start(timestamp_raw, tid, name, text);
+                } else if let Some(name) = defender_event.strip_suffix("/win:Stop") {
+                    context.handle_freeform_marker_end(
+                        timestamp_raw,
+                        tid,
+                        name,
+                        text,
+                        KnownCategory::Antivirus,
+                    );
+                } else {
+                    let task_and_op = defender_event.split_once('/').unwrap().1;
+                    context.handle_defender_event(timestamp_raw, tid, task_and_op, text);
+                }
+            }
+            win32k_event if detect_hangs && hangs::is_message_check_delay_event(win32k_event) => {
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                let Some(delay_ms) = hangs::message_check_delay_ms(&s, &mut parser) else {
+                    return;
+                };
+                let tid = e.EventHeader.ThreadId;
+                context.handle_message_check_delay(timestamp_raw, tid, delay_ms);
+            }
             dotnet_event if dotnet_event.starts_with("Microsoft-Windows-DotNETRuntime") => {
                 let pid = s.process_id();
                 if !context.has_process_at_time(pid, timestamp_raw) {
@@ -517,9 +794,40 @@ fn process_trace(
                     return;
                 }
 
-                let task_and_op = s.name().split_once('/').unwrap().1;
-                let text = event_properties_to_string(&s, &mut parser, None);
-                context.handle_unknown_event(timestamp_raw, tid, task_and_op, text);
+                let is_requested_provider = requested_guids.contains(&e.EventHeader.ProviderId)
+                    || (!requested_names.is_empty() && {
+                        let provider_name = s.provider_name();
+                        requested_names
+                            .iter()
+                            .any(|p| p.provider.eq_ignore_ascii_case(&provider_name))
+                    });
+                // Keep the provider name for requested providers, so that the
+                // events of different products can be told apart.
+                let marker_name = if is_requested_provider {
+                    s.name()
+                } else {
+                    s.name().split_once('/').unwrap().1
+                };
+                if let Some(kind) = SyscallKind::from_event_name(s.name()) {
+                    let args = syscall_markers::parse_syscall_args(kind, &mut parser);
+                    context.handle_syscall_event(
+                        timestamp_raw,
+                        tid,
+                        kind,
+                        args,
+                        is_requested_provider,
+                    );
+                    return;
+                }
+                let text =
+                    event_properties_to_string_with_limits(&s, &mut parser, None, &payload_limits);
+                context.handle_unknown_event(
+                    timestamp_raw,
+                    tid,
+                    marker_name,
+                    text,
+                    is_requested_provider,
+                );
             }
         }
     })
@@ -35,9 +35,9 @@ pub fn firefox_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
     // JIT symbols
     providers.push("Microsoft-JScript:0x3".to_string());
 
-    // UserTiming + GC markers
+    // UserTiming + GC markers, with stacks so that we can show where they came from
     let bits = (EtwMarkerGroup::UserMarkers | EtwMarkerGroup::Memory).bits();
-    providers.push(format!("{}:{:#x}", FIREFOX_PROVIDER_GUID, bits));
+    providers.push(format!("{}:{:#x}:5:'stack'", FIREFOX_PROVIDER_GUID, bits));
 
     providers
 }
@@ -0,0 +1,217 @@
+//! Hang detection for `--hangs`: periods in which a thread with a message
+//! loop didn't check for window messages, so its windows were frozen.
+//!
+//! There are two sources. Win32k logs a MessageCheckDelay event when a thread
+//! checks its messages again after a long time, with the delay. And without
+//! those events, the gaps between a thread's message waits show the same:
+//! GetMessage, WaitMessage and MsgWaitForMultipleObjects block with the
+//! WrUserRequest wait reason, so a thread which has been seen waiting for
+//! messages hangs from the time it's switched back in until its next such wait.
+
+use etw_reader::parser::{Parser, TryParse};
+use etw_reader::schema::TypedEvent;
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+
+use super::elevated_helper::ElevatedRecordingProps;
+
+/// The CSwitch wait reason of threads which wait in win32k, i.e. for messages.
+const WR_USER_REQUEST: i8 = 13;
+const WR_TERMINATED: i8 = 22;
+
+const WIN32K_PROVIDER_NAME: &str = "Microsoft-Windows-Win32k";
+
+pub fn hangs_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
+    if !props.hangs {
+        return vec![];
+    }
+    // The MessageCheckDelay events don't have a documented keyword, so this
+    // enables all of them.
+    vec![WIN32K_PROVIDER_NAME.to_string()]
+}
+
+/// Whether this is Win32k's event for a late message check. `event_name` is
+/// "<provider>/<task>/<opcode>".
+pub fn is_message_check_delay_event(event_name: &str) -> bool {
+    event_name
+        .strip_prefix(WIN32K_PROVIDER_NAME)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .is_some_and(|rest| {
+            rest.starts_with("MessageCheckDelay") || rest.starts_with("MsgCheckDelay")
+        })
+}
+
+/// The delay in milliseconds from a MessageCheckDelay event. Its payload
+/// isn't documented, so this takes the first field whose name says that it's
+/// in milliseconds.
+pub fn message_check_delay_ms(s: &TypedEvent, parser: &mut Parser) -> Option<u64> {
+    (0..s.property_count()).find_map(|i| {
+        let name = s.property(i).name;
+        if !name.ends_with("Ms") && !name.ends_with("MS") {
+            return None;
+        }
+        let value: Result<u32, _> = parser.try_parse(&name);
+        match value {
+            Ok(value) => Some(u64::from(value)),
+            Err(_) => parser.try_parse(&name).ok(),
+        }
+    })
+}
+
+/// Tracks the message waits of one thread.
+#[derive(Debug, Default)]
+pub struct HangDetector {
+    waiting_for_messages: bool,
+    /// When the thread last came back from waiting for messages. `None` for
+    /// threads which haven't been seen waiting for messages, and while the
+    /// thread is waiting.
+    last_message_check_raw: Option<u64>,
+}
+
+impl HangDetector {
+    /// The thread was switched out. If it's now waiting for messages, returns
+    /// the raw timestamp of its previous message check, which is where the
+    /// gap since then started. A thread which exits doesn't hang.
+    pub fn notify_switch_out(&mut self, wait_reason: i8) -> Option<u64> {
+        match wait_reason {
+            WR_USER_REQUEST => {
+                self.waiting_for_messages = true;
+                self.last_message_check_raw.take()
+            }
+            WR_TERMINATED => {
+                self.last_message_check_raw = None;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// The thread was switched in. If it was waiting for messages, it's now
+    /// handling one.
+    pub fn notify_switch_in(&mut self, timestamp_raw: u64) {
+        if std::mem::take(&mut self.waiting_for_messages) {
+            self.last_message_check_raw = Some(timestamp_raw);
+        }
+    }
+
+    /// Returns the start of the gap which is still open at the end of the
+    /// profile, if any.
+    pub fn finish(&mut self) -> Option<u64> {
+        self.last_message_check_raw.take()
+    }
+}
+
+/// A hang of a thread, in raw timestamps. Hangs are turned into markers at
+/// the end, once the samples are in the profile, so that the markers can get
+/// the most common stack of the hang.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hang {
+    pub start_raw: u64,
+    pub end_raw: u64,
+}
+
+/// Merges overlapping hangs, e.g. where the MessageCheckDelay event and the
+/// message wait gaps found the same hang, and sorts them by start.
+pub fn merge_overlapping_hangs(mut hangs: Vec<Hang>) -> Vec<Hang> {
+    hangs.sort_by_key(|hang| hang.start_raw);
+    let mut merged: Vec<Hang> = Vec::with_capacity(hangs.len());
+    for hang in hangs {
+        match merged.last_mut() {
+            Some(last) if hang.start_raw <= last.end_raw => {
+                last.end_raw = last.end_raw.max(hang.end_raw);
+            }
+            _ => merged.push(hang),
+        }
+    }
+    merged
+}
+
+/// An interval marker for a hang, with the most common stack of the thread
+/// during the hang.
+#[derive(Debug, Clone)]
+pub struct HangMarker {
+    pub duration_ms: f64,
+    pub category: CategoryHandle,
+}
+
+impl StaticSchemaMarker for HangMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Hang";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![
+                MarkerLocation::MarkerChart,
+                MarkerLocation::MarkerTable,
+                MarkerLocation::TimelineOverview,
+            ],
+            chart_label: Some("Hang {marker.data.duration}".into()),
+            tooltip_label: Some("Hang for {marker.data.duration}".into()),
+            table_label: Some("Hang for {marker.data.duration}".into()),
+            fields: vec![MarkerFieldSchema {
+                key: "duration".into(),
+                label: "Duration".into(),
+                format: MarkerFieldFormat::Milliseconds,
+                searchable: false,
+            }],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "The thread didn't check for window messages, so its windows didn't respond to input. The marker's stack is the most common stack during the hang.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Hang")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.category
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        unreachable!()
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.duration_ms
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hang_detector() {
+        const WR_QUEUE: i8 = 15;
+        let mut detector = HangDetector::default();
+        // Not a UI thread yet.
+        assert_eq!(detector.notify_switch_out(WR_QUEUE), None);
+        detector.notify_switch_in(100);
+        assert_eq!(detector.notify_switch_out(WR_USER_REQUEST), None);
+        detector.notify_switch_in(200);
+        // Blocked on something else in between, which is part of the gap.
+        assert_eq!(detector.notify_switch_out(WR_QUEUE), None);
+        detector.notify_switch_in(300);
+        assert_eq!(detector.notify_switch_out(WR_USER_REQUEST), Some(200));
+        detector.notify_switch_in(400);
+        assert_eq!(detector.finish(), Some(400));
+
+        detector.notify_switch_out(WR_USER_REQUEST);
+        detector.notify_switch_in(500);
+        assert_eq!(detector.notify_switch_out(WR_TERMINATED), None);
+        assert_eq!(detector.finish(), None);
+    }
+
+    #[test]
+    fn test_merge_overlapping_hangs() {
+        let hang = |start_raw, end_raw| Hang { start_raw, end_raw };
+        assert_eq!(
+            merge_overlapping_hangs(vec![hang(50, 60), hang(10, 30), hang(20, 40)]),
+            vec![hang(10, 40), hang(50, 60)]
+        );
+    }
+}
@@ -3,18 +3,19 @@ use std::path::{Path, PathBuf};
 use fxprof_processed_profile::{Profile, ReferenceTimestamp, SamplingInterval};
 
 use super::etw_gecko;
+use super::regions_of_interest::RegionsOfInterest;
 use crate::shared::included_processes::IncludedProcesses;
 use crate::shared::recording_props::ProfileCreationProps;
-use crate::shared::save_profile::save_profile_to_file;
 use crate::windows::profile_context::ProfileContext;
 
 pub fn convert_etl_file_to_profile(
     filename: &Path,
     extra_etl_filenames: &[PathBuf],
-    output_file: &Path,
     profile_creation_props: ProfileCreationProps,
     included_processes: Option<IncludedProcesses>,
-) {
+    two_pass: bool,
+    regions_of_interest_file: Option<&Path>,
+) -> Profile {
     let timebase = std::time::SystemTime::now();
     let timebase = ReferenceTimestamp::from_system_time(timebase);
 
@@ -31,11 +32,19 @@ pub fn convert_etl_file_to_profile(
 
     let mut context =
         ProfileContext::new(profile, arch, included_processes, profile_creation_props);
-
-    etw_gecko::process_etl_files(&mut context, filename, extra_etl_filenames);
-
-    let profile = context.finish();
-    save_profile_to_file(&profile, output_file).expect("Couldn't write JSON");
+    if let Some(regions_of_interest_file) = regions_of_interest_file {
+        match RegionsOfInterest::load(regions_of_interest_file) {
+            Ok(regions_of_interest) => context.set_regions_of_interest(regions_of_interest),
+            Err(err) => {
+                eprintln!("Error: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    etw_gecko::process_etl_files(&mut context, filename, extra_etl_filenames, two_pass);
+
+    context.finish()
 }
 
 #[cfg(target_arch = "x86")]
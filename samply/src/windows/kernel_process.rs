@@ -0,0 +1,42 @@
+use super::elevated_helper::ElevatedRecordingProps;
+
+// From `logman query providers Microsoft-Windows-Kernel-Process`.
+const KERNEL_PROCESS_CPU_PRIORITY_KEYWORD: u64 = 0x80;
+const KERNEL_PROCESS_OTHER_PRIORITY_KEYWORD: u64 = 0x100;
+const KERNEL_PROCESS_JOB_KEYWORD: u64 = 0x400;
+
+/// Priority changes and job object events are rare, so we always ask for
+/// them. They explain why a process got less CPU time than expected, e.g.
+/// when Windows puts a background app into a CPU-rate-limited job.
+pub fn kernel_process_xperf_args(_props: &ElevatedRecordingProps) -> Vec<String> {
+    let keywords = KERNEL_PROCESS_CPU_PRIORITY_KEYWORD
+        | KERNEL_PROCESS_OTHER_PRIORITY_KEYWORD
+        | KERNEL_PROCESS_JOB_KEYWORD;
+    vec![format!(
+        "Microsoft-Windows-Kernel-Process:0x{:x}:4",
+        keywords
+    )]
+}
+
+/// Returns the marker name for a Microsoft-Windows-Kernel-Process event, given
+/// its "Task/Opcode" name, if it's one of the events we show as markers.
+pub fn throttling_marker_name(task_and_op: &str) -> Option<&'static str> {
+    let task = task_and_op.split('/').next().unwrap_or(task_and_op);
+    if task.contains("CpuRate") {
+        Some("Job CPU rate limit")
+    } else if task.starts_with("Job") {
+        Some("Job object")
+    } else if task.contains("PriorityClass") {
+        Some("Priority class change")
+    } else if task.contains("BasePriority") {
+        Some("Base priority change")
+    } else if task.contains("IoPriority") {
+        Some("I/O priority change")
+    } else if task.contains("PagePriority") {
+        Some("Page priority change")
+    } else if task.contains("Priority") {
+        Some("Priority change")
+    } else {
+        None
+    }
+}
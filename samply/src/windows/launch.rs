@@ -0,0 +1,137 @@
+//! Launching processes which samply can't spawn itself: services are started
+//! by services.exe, and packaged (UWP / MSIX) apps are started by the activation
+//! manager on behalf of sihost. In both cases we get the pid of the new process
+//! from the system, so that it and its children can be included in the profile.
+
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::ERROR_SERVICE_NOT_ACTIVE;
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CLSCTX_LOCAL_SERVER, COINIT_APARTMENTTHREADED,
+};
+use windows::Win32::System::Services::{
+    CloseServiceHandle, ControlService, OpenSCManagerW, OpenServiceW, QueryServiceStatusEx,
+    StartServiceW, SC_HANDLE, SC_MANAGER_CONNECT, SC_STATUS_PROCESS_INFO, SERVICE_CONTROL_STOP,
+    SERVICE_QUERY_STATUS, SERVICE_RUNNING, SERVICE_START, SERVICE_STATUS,
+    SERVICE_STATUS_CURRENT_STATE, SERVICE_STATUS_PROCESS, SERVICE_STOP, SERVICE_STOPPED,
+};
+use windows::Win32::UI::Shell::{
+    ApplicationActivationManager, IApplicationActivationManager, AO_NONE,
+};
+
+use super::winutils::is_elevated;
+
+/// How long we wait for a service to stop or to start.
+const SERVICE_STATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Closes the service handle when dropped.
+struct ServiceHandle(SC_HANDLE);
+
+impl Drop for ServiceHandle {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseServiceHandle(self.0);
+        }
+    }
+}
+
+/// Stops the service if it's running and starts it again, so that the profile
+/// covers the service's startup. Returns the pid of the service process.
+pub fn restart_service(name: &str) -> Result<u32, String> {
+    if !is_elevated() {
+        return Err("Restarting a service requires administrator privileges. \
+            Run samply from an elevated prompt, or with 'sudo' on recent Windows."
+            .to_string());
+    }
+
+    unsafe {
+        let manager = OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), SC_MANAGER_CONNECT)
+            .map_err(|e| format!("Couldn't connect to the service control manager: {e}"))?;
+        let manager = ServiceHandle(manager);
+        let service = OpenServiceW(
+            manager.0,
+            &HSTRING::from(name),
+            SERVICE_QUERY_STATUS | SERVICE_START | SERVICE_STOP,
+        )
+        .map_err(|e| format!("Couldn't open service {name:?}: {e}"))?;
+        let service = ServiceHandle(service);
+
+        let mut status = SERVICE_STATUS::default();
+        match ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) {
+            Ok(()) => {
+                eprintln!("Stopping service {name}...");
+                wait_for_service_state(&service, SERVICE_STOPPED)
+                    .map_err(|e| format!("Service {name:?} didn't stop: {e}"))?;
+            }
+            Err(e) if e.code() == ERROR_SERVICE_NOT_ACTIVE.to_hresult() => {}
+            Err(e) => return Err(format!("Couldn't stop service {name:?}: {e}")),
+        }
+
+        eprintln!("Starting service {name}...");
+        StartServiceW(service.0, None)
+            .map_err(|e| format!("Couldn't start service {name:?}: {e}"))?;
+        let status = wait_for_service_state(&service, SERVICE_RUNNING)
+            .map_err(|e| format!("Service {name:?} didn't start: {e}"))?;
+        Ok(status.dwProcessId)
+    }
+}
+
+/// Polls the service's status until it reaches `state`.
+fn wait_for_service_state(
+    service: &ServiceHandle,
+    state: SERVICE_STATUS_CURRENT_STATE,
+) -> Result<SERVICE_STATUS_PROCESS, String> {
+    let start = Instant::now();
+    loop {
+        let status = query_service_status(service).map_err(|e| e.to_string())?;
+        if status.dwCurrentState == state {
+            return Ok(status);
+        }
+        if status.dwCurrentState == SERVICE_STOPPED && state == SERVICE_RUNNING {
+            return Err(format!("it exited with code {}", status.dwWin32ExitCode));
+        }
+        if start.elapsed() > SERVICE_STATE_TIMEOUT {
+            return Err(format!("timed out after {SERVICE_STATE_TIMEOUT:?}"));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn query_service_status(service: &ServiceHandle) -> windows::core::Result<SERVICE_STATUS_PROCESS> {
+    let mut status = SERVICE_STATUS_PROCESS::default();
+    let mut bytes_needed = 0;
+    unsafe {
+        let buffer = std::slice::from_raw_parts_mut(
+            &mut status as *mut SERVICE_STATUS_PROCESS as *mut u8,
+            size_of::<SERVICE_STATUS_PROCESS>(),
+        );
+        QueryServiceStatusEx(
+            service.0,
+            SC_STATUS_PROCESS_INFO,
+            Some(buffer),
+            &mut bytes_needed,
+        )?;
+    }
+    Ok(status)
+}
+
+/// Launches the packaged app with the given Application User Model ID, e.g.
+/// `Microsoft.WindowsCalculator_8wekyb3d8bbwe!App`, and returns the pid of the
+/// app process. If the app is already running, this is the pid of the running
+/// instance.
+pub fn activate_packaged_app(app_id: &str, args: &str) -> Result<u32, String> {
+    unsafe {
+        // This returns S_FALSE if COM is already initialized on this thread, which is fine.
+        CoInitializeEx(None, COINIT_APARTMENTTHREADED)
+            .ok()
+            .map_err(|e| format!("Couldn't initialize COM: {e}"))?;
+        let manager: IApplicationActivationManager =
+            CoCreateInstance(&ApplicationActivationManager, None, CLSCTX_LOCAL_SERVER)
+                .map_err(|e| format!("Couldn't create the application activation manager: {e}"))?;
+        manager
+            .ActivateApplication(&HSTRING::from(app_id), &HSTRING::from(args), AO_NONE)
+            .map_err(|e| format!("Couldn't launch app {app_id:?}: {e}"))
+    }
+}
@@ -1,13 +1,26 @@
+mod antivirus;
 mod chrome;
 mod coreclr;
+mod driver_exports;
 mod elevated_helper;
+mod energy_meter;
 mod etw_gecko;
 mod firefox;
 mod gfx;
+mod hangs;
 pub mod import;
+mod kernel_process;
+mod launch;
+mod package_paths;
 mod profile_context;
 pub mod profiler;
+mod regions_of_interest;
+mod sample_gaps;
+mod scheduler_latency;
+mod syscall_markers;
+mod thread_states;
 mod utility_process;
+mod wakeups;
 mod winutils;
 mod xperf;
 
@@ -0,0 +1,114 @@
+//! Heuristics for the images of packaged (UWP / MSIX) apps, which are installed
+//! in `C:\Program Files\WindowsApps\<package full name>\`.
+//!
+//! The PDB path in these images is the path on the build machine, which rarely
+//! exists here. Packages built for sideloading often contain their PDBs though,
+//! next to the image or at the root of the package, so we look there before
+//! giving up on the local PDB.
+
+use std::path::Path;
+
+/// The directory which contains all installed packages.
+const WINDOWS_APPS_DIR: &str = "\\windowsapps\\";
+
+/// Packages of shared runtimes which store apps depend on, such as the C++
+/// runtime. Their images are categorized like the system libraries.
+const FRAMEWORK_PACKAGE_PREFIXES: &[&str] = &[
+    "microsoft.vclibs.",
+    "microsoft.ui.xaml.",
+    "microsoft.net.native.",
+    "microsoft.windowsappruntime.",
+];
+
+/// The installation directory of the package which contains the image at
+/// `path`, e.g. `C:\Program Files\WindowsApps\Microsoft.WindowsCalculator_11.2307.4.0_x64__8wekyb3d8bbwe`.
+pub fn package_dir(path: &str) -> Option<&str> {
+    let start = path.to_ascii_lowercase().find(WINDOWS_APPS_DIR)? + WINDOWS_APPS_DIR.len();
+    let len = path[start..].find('\\')?;
+    Some(&path[..start + len])
+}
+
+/// The name of the package which contains the image at `path`, i.e. the
+/// package full name without the version, architecture and publisher.
+pub fn package_name(path: &str) -> Option<&str> {
+    let package_dir = package_dir(path)?;
+    let full_name = &package_dir[package_dir.rfind('\\')? + 1..];
+    full_name.split('_').next()
+}
+
+/// Whether the image at `path` is part of a framework package.
+pub fn is_framework_package_image(path: &str) -> bool {
+    let Some(package_name) = package_name(path) else {
+        return false;
+    };
+    let package_name = package_name.to_ascii_lowercase();
+    FRAMEWORK_PACKAGE_PREFIXES
+        .iter()
+        .any(|prefix| package_name.starts_with(prefix))
+}
+
+/// Finds the PDB of a packaged image in its package, if the PDB isn't at the
+/// path which is recorded in the image. Returns `None` for images which aren't
+/// in a package, or if the package doesn't contain the PDB either.
+pub fn find_pdb_in_package(path: &str, pdb_path: &str) -> Option<String> {
+    find_pdb_in_package_with(path, pdb_path, |path| Path::new(path).exists())
+}
+
+fn find_pdb_in_package_with(
+    path: &str,
+    pdb_path: &str,
+    exists: impl Fn(&str) -> bool,
+) -> Option<String> {
+    let package_dir = package_dir(path)?;
+    if exists(pdb_path) {
+        return None;
+    }
+    let pdb_name = &pdb_path[pdb_path.rfind(['\\', '/']).map_or(0, |i| i + 1)..];
+    let image_dir = &path[..path.rfind('\\')?];
+    [image_dir, package_dir]
+        .into_iter()
+        .map(|dir| format!("{dir}\\{pdb_name}"))
+        .find(|candidate| exists(candidate))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CALC_EXE: &str = r"C:\Program Files\WindowsApps\Microsoft.WindowsCalculator_11.2307.4.0_x64__8wekyb3d8bbwe\bin\CalculatorApp.exe";
+
+    #[test]
+    fn test_package_paths() {
+        assert_eq!(
+            package_dir(CALC_EXE),
+            Some(
+                r"C:\Program Files\WindowsApps\Microsoft.WindowsCalculator_11.2307.4.0_x64__8wekyb3d8bbwe"
+            )
+        );
+        assert_eq!(package_name(CALC_EXE), Some("Microsoft.WindowsCalculator"));
+        assert_eq!(package_dir(r"C:\Windows\System32\ntdll.dll"), None);
+        assert!(!is_framework_package_image(CALC_EXE));
+        assert!(is_framework_package_image(
+            r"C:\Program Files\WindowsApps\Microsoft.VCLibs.140.00_14.0.33519.0_x64__8wekyb3d8bbwe\vcruntime140_app.dll"
+        ));
+    }
+
+    #[test]
+    fn test_find_pdb_in_package() {
+        let package_pdb = r"C:\Program Files\WindowsApps\Microsoft.WindowsCalculator_11.2307.4.0_x64__8wekyb3d8bbwe\CalculatorApp.pdb";
+        let exists = |path: &str| path == package_pdb;
+        assert_eq!(
+            find_pdb_in_package_with(CALC_EXE, r"D:\a\_work\1\s\CalculatorApp.pdb", exists),
+            Some(package_pdb.to_string())
+        );
+        // The PDB from the image exists, so we keep it.
+        assert_eq!(
+            find_pdb_in_package_with(CALC_EXE, package_pdb, exists),
+            None
+        );
+        assert_eq!(
+            find_pdb_in_package_with(r"C:\Windows\System32\ntdll.dll", "ntdll.pdb", exists),
+            None
+        );
+    }
+}
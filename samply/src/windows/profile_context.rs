@@ -1,5 +1,7 @@
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use debugid::DebugId;
 use fxprof_processed_profile::{
@@ -11,22 +13,53 @@ use fxprof_processed_profile::{
 use shlex::Shlex;
 use wholesym::PeCodeId;
 
+use super::antivirus::MIN_MINIFILTER_MARKER_DURATION_NANOS;
 use super::chrome::KeywordNames;
+use super::driver_exports::{load_export_symbol_table, needs_export_symbols};
+use super::energy_meter::EnergyChannelReadings;
+use super::hangs::{merge_overlapping_hangs, Hang, HangDetector, HangMarker};
+use super::package_paths::{find_pdb_in_package, is_framework_package_image};
+use super::regions_of_interest::{RegionEvent, RegionOfInterestMarker, RegionsOfInterest};
+use super::sample_gaps::{sample_weight_after_gap, SampleGapDetector, SampleGapMarker};
+use super::scheduler_latency::{
+    LatencyDistribution, SchedulerLatencyMarker, SchedulerLatencyTracker,
+};
+use super::syscall_markers::{SyscallArgs, SyscallKind, SyscallMarker};
+use super::thread_states::{
+    wait_reason_name, ThreadState, ThreadStateInterval, ThreadStateMarker, ThreadStateTracker,
+};
+use super::wakeups::{WakeupMarker, IDLE_WAKER_NAME};
 use super::winutils;
+use crate::shared::collector_frames::remove_collector_frames;
 use crate::shared::context_switch::{
     ContextSwitchHandler, OffCpuSampleGroup, ThreadContextSwitchData,
 };
+use crate::shared::cpu_core_types::core_type_names;
 use crate::shared::included_processes::IncludedProcesses;
 use crate::shared::jit_category_manager::{JitCategoryManager, JsFrame};
-use crate::shared::jit_function_add_marker::JitFunctionAddMarker;
+use crate::shared::jit_function_add_marker::{JitFunctionAddMarker, JitFunctionUnloadMarker};
 use crate::shared::jit_function_recycler::JitFunctionRecycler;
-use crate::shared::lib_mappings::{LibMappingAdd, LibMappingInfo, LibMappingOp, LibMappingOpQueue};
+use crate::shared::lib_mappings::{
+    LibMappingAdd, LibMappingInfo, LibMappingOp, LibMappingOpQueue, LibMappingRemove,
+};
+use crate::shared::lifetime_markers::{Lifetime, ProcessLifetimeMarker, ThreadLifetimeMarker};
+use crate::shared::namespace_categories::categorize_profile_by_namespace;
 use crate::shared::per_cpu::Cpus;
+use crate::shared::power_counters::PowerCounter;
+use crate::shared::process_groups::ProcessTree;
+use crate::shared::process_intervals::{ProcessSampleStrides, SampleThinner};
 use crate::shared::process_name::make_process_name;
-use crate::shared::process_sample_data::{ProcessSampleData, UserTimingMarker};
-use crate::shared::recording_props::ProfileCreationProps;
+use crate::shared::process_sample_data::{
+    ProcessSampleData, ProcessSampleFlusher, UserTimingMarker,
+};
+use crate::shared::profile_size_budget::shrink_profile_to_size_budget;
+use crate::shared::ptr_auth::PtrAuthStripper;
+use crate::shared::r2r_map::try_load_r2r_map;
+use crate::shared::recording_props::{ProfileCreationProps, StackWalkEvent};
 use crate::shared::recycling::{ProcessRecycler, ProcessRecyclingData, ThreadRecycler};
+use crate::shared::runtime_categories::categorize_profile_by_runtime;
 use crate::shared::synthetic_jit_library::SyntheticJitLibrary;
+use crate::shared::thread_counts::ThreadCounts;
 use crate::shared::timestamp_converter::TimestampConverter;
 use crate::shared::types::{StackFrame, StackMode};
 use crate::shared::unresolved_samples::{
@@ -36,6 +69,9 @@ use crate::windows::firefox::{
     PHASE_INSTANT, PHASE_INTERVAL, PHASE_INTERVAL_END, PHASE_INTERVAL_START,
 };
 
+/// The pid of the "System" process, which owns the kernel's own threads.
+const SYSTEM_PROCESS_PID: u32 = 4;
+
 /// An on- or off-cpu-sample for which the user stack is not known yet.
 /// Consumed once the user stack arrives.
 #[derive(Debug, Clone)]
@@ -47,7 +83,12 @@ pub struct SampleWithPendingStack {
     pub off_cpu_sample_group: Option<OffCpuSampleGroup>,
     pub cpu_delta: CpuDelta,
     pub has_on_cpu_sample: bool,
+    /// The weight of the on-cpu sample. This is more than 1 if the sample
+    /// stands in for the samples which are missing in a gap before it.
+    pub weight: i32,
     pub per_cpu_stuff: Option<(ThreadHandle, CpuDelta)>,
+    /// The CPU which the on-cpu sample was taken on.
+    pub cpu: Option<u32>,
 }
 
 #[derive(Debug)]
@@ -67,6 +108,8 @@ pub struct Threads {
     threads: Vec<Thread>,
     threads_by_tid: HashMap<u32, usize>,
     threads_by_tid_and_start_time: BTreeMap<(u32, u64), usize>,
+    /// Set once all threads of the trace have been added, see `get_at_time`.
+    all_known: bool,
 }
 
 impl Threads {
@@ -75,6 +118,7 @@ impl Threads {
             threads: Vec::new(),
             threads_by_tid: HashMap::new(),
             threads_by_tid_and_start_time: BTreeMap::new(),
+            all_known: false,
         }
     }
 
@@ -98,6 +142,29 @@ impl Threads {
         Some(&mut self.threads[index])
     }
 
+    /// Returns the thread with this tid at the time of an event. While the
+    /// threads are still being added in event order, that's the most recent
+    /// thread with this tid.
+    pub fn get_at_time(&mut self, tid: u32, timestamp_raw: u64) -> Option<&mut Thread> {
+        if self.all_known {
+            self.get_by_tid_and_timestamp(tid, timestamp_raw)
+        } else {
+            self.get_by_tid(tid)
+        }
+    }
+
+    pub fn set_all_known(&mut self) {
+        self.all_known = true;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Thread> {
+        self.threads.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Thread> {
+        self.threads.iter_mut()
+    }
+
     fn get_index_by_tid_and_timestamp(&self, tid: u32, timestamp_raw: u64) -> Option<usize> {
         let lookup_key = (tid, timestamp_raw);
         let (found_key, last_entry_at_or_before_key) = self
@@ -138,21 +205,23 @@ impl Threads {
 #[derive(Debug)]
 pub struct Thread {
     pub name: Option<String>,
-    #[allow(dead_code)]
     pub is_main_thread: bool,
     pub handle: ThreadHandle,
     pub label_frame: FrameInfo,
+    pub lifetime: Lifetime,
     pub samples_with_pending_stacks: VecDeque<SampleWithPendingStack>,
     pub context_switch_data: ThreadContextSwitchData,
-    #[allow(dead_code)]
     pub thread_id: u32,
     pub tid_reused_timestamp_raw: Option<u64>,
-    #[allow(dead_code)]
     pub process_id: u32,
     pub pending_markers: HashMap<String, PendingMarker>,
+    pub thread_state: ThreadStateTracker,
+    pub scheduler_latency: SchedulerLatencyTracker,
+    pub hang_detector: HangDetector,
 }
 
 impl Thread {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         name: Option<String>,
         is_main_thread: bool,
@@ -160,18 +229,23 @@ impl Thread {
         label_frame: FrameInfo,
         pid: u32,
         tid: u32,
+        lifetime: Lifetime,
     ) -> Self {
         Thread {
             name,
             is_main_thread,
             handle,
             label_frame,
+            lifetime,
             samples_with_pending_stacks: VecDeque::new(),
             context_switch_data: Default::default(),
             pending_markers: HashMap::new(),
             thread_id: tid,
             tid_reused_timestamp_raw: None,
             process_id: pid,
+            thread_state: ThreadStateTracker::default(),
+            scheduler_latency: SchedulerLatencyTracker::default(),
+            hang_detector: HangDetector::default(),
         }
     }
 
@@ -187,6 +261,8 @@ pub struct Processes {
     processes: Vec<Process>,
     processes_by_pid: HashMap<u32, usize>,
     processes_by_pid_and_start_time: BTreeMap<(u32, u64), usize>,
+    /// Set once all processes of the trace have been added, see `get_at_time`.
+    all_known: bool,
 }
 
 impl Processes {
@@ -195,6 +271,7 @@ impl Processes {
             processes: Vec::new(),
             processes_by_pid: HashMap::new(),
             processes_by_pid_and_start_time: BTreeMap::new(),
+            all_known: false,
         }
     }
 
@@ -221,6 +298,7 @@ impl Processes {
                     process.regular_lib_mapping_ops,
                     jitdump_lib_mapping_op_queues,
                     None,
+                    None,
                     Vec::new(),
                 )
             })
@@ -243,6 +321,29 @@ impl Processes {
         Some(&mut self.processes[index])
     }
 
+    /// Returns the process with this pid at the time of an event. While the
+    /// processes are still being added in event order, that's the most recent
+    /// process with this pid.
+    pub fn get_at_time(&mut self, pid: u32, timestamp_raw: u64) -> Option<&mut Process> {
+        if self.all_known {
+            self.get_by_pid_and_timestamp(pid, timestamp_raw)
+        } else {
+            self.get_by_pid(pid)
+        }
+    }
+
+    pub fn set_all_known(&mut self) {
+        self.all_known = true;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Process> {
+        self.processes.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Process> {
+        self.processes.iter_mut()
+    }
+
     fn get_index_by_pid_and_timestamp(&self, pid: u32, timestamp_raw: u64) -> Option<usize> {
         let lookup_key = (pid, timestamp_raw);
         let (found_key, last_entry_at_or_before_key) = self
@@ -290,13 +391,23 @@ pub struct Process {
     pub main_thread_handle: ThreadHandle,
     pub main_thread_label_frame: FrameInfo,
     pub memory_usage: Option<MemoryUsage>,
+    pub pmc_counters: Vec<CounterHandle>,
+    pub scheduler_latency_counter: Option<CounterHandle>,
     pub process_id: u32,
     pub pid_reused_timestamp_raw: Option<u64>,
-    #[allow(dead_code)]
     pub parent_id: u32,
+    pub command_line: String,
+    pub lifetime: Lifetime,
+    pub exit_code: Option<u32>,
     pub thread_recycler: Option<ThreadRecycler>,
     pub jit_function_recycler: Option<JitFunctionRecycler>,
     pub js_sources: HashMap<u64, String>,
+    pub thread_counts: ThreadCounts,
+    /// Set during the sample pass of a two-pass import, once the process's lib
+    /// mapping ops are complete.
+    pub sample_flusher: Option<ProcessSampleFlusher>,
+    /// Drops samples if the process has a lower rate from `--interval-for`.
+    pub sample_thinner: SampleThinner,
 }
 
 impl Process {
@@ -305,6 +416,8 @@ impl Process {
         name: String,
         process_id: u32,
         parent_id: u32,
+        command_line: String,
+        lifetime: Lifetime,
         handle: ProcessHandle,
         main_thread_handle: ThreadHandle,
         main_thread_label_frame: FrameInfo,
@@ -321,18 +434,26 @@ impl Process {
             main_thread_handle,
             main_thread_label_frame,
             memory_usage: None,
+            pmc_counters: Vec::new(),
+            scheduler_latency_counter: None,
             process_id,
             pid_reused_timestamp_raw: None,
             parent_id,
+            command_line,
+            lifetime,
+            exit_code: None,
             thread_recycler,
             jit_function_recycler,
             js_sources: HashMap::new(),
+            thread_counts: ThreadCounts::default(),
+            sample_flusher: None,
+            sample_thinner: SampleThinner::default(),
         }
     }
 
     pub fn take_recycling_data(&mut self) -> Option<ProcessRecyclingData> {
         let jit_function_recycler = self.jit_function_recycler.take()?;
-        let thread_recycler = self.thread_recycler.take()?;
+        let thread_recycler = self.thread_recycler.take();
 
         Some(ProcessRecyclingData {
             process_handle: self.handle,
@@ -384,6 +505,39 @@ impl Process {
         });
         memory_usage.counter
     }
+
+    /// Returns the counter for the PMC at `index`, creating the counters up
+    /// to that index if needed.
+    pub fn get_pmc_counter(
+        &mut self,
+        index: usize,
+        counter_names: &[String],
+        profile: &mut Profile,
+    ) -> CounterHandle {
+        while self.pmc_counters.len() <= index {
+            let counter_index = self.pmc_counters.len();
+            let name = match counter_names.get(counter_index) {
+                Some(name) => name.clone(),
+                None => format!("PMC {counter_index}"),
+            };
+            let description = format!("CPU performance counter {name}");
+            let counter = profile.add_counter(self.handle, &name, "CPU", &description);
+            self.pmc_counters.push(counter);
+        }
+        self.pmc_counters[index]
+    }
+
+    pub fn get_scheduler_latency_counter(&mut self, profile: &mut Profile) -> CounterHandle {
+        let process_handle = self.handle;
+        *self.scheduler_latency_counter.get_or_insert_with(|| {
+            profile.add_counter(
+                process_handle,
+                "Scheduler latency",
+                "Scheduling",
+                "Time in milliseconds which threads waited for a CPU after they became ready to run",
+            )
+        })
+    }
 }
 
 // Known profiler categories, lazy-created
@@ -397,6 +551,17 @@ pub enum KnownCategory {
     CoreClrR2r,
     CoreClrJit,
     CoreClrGc,
+    CoreClrException,
+    CoreClrLoader,
+    KernelThread,
+    KernelWorkerThread,
+    KernelMemoryManager,
+    KernelCacheManager,
+    Scheduling,
+    Antivirus,
+    RegionOfInterest,
+    Hang,
+    NetworkWait,
     Unknown,
 }
 
@@ -416,6 +581,17 @@ impl KnownCategories {
         (KnownCategory::CoreClrR2r, "CoreCLR R2R", CategoryColor::Blue),
         (KnownCategory::CoreClrJit, "CoreCLR JIT", CategoryColor::Purple),
         (KnownCategory::CoreClrGc, "CoreCLR GC", CategoryColor::Red),
+        (KnownCategory::CoreClrException, "CoreCLR Exception", CategoryColor::Orange),
+        (KnownCategory::CoreClrLoader, "CoreCLR Loader", CategoryColor::Green),
+        (KnownCategory::KernelThread, "Kernel Thread", CategoryColor::LightRed),
+        (KnownCategory::KernelWorkerThread, "Kernel Worker Thread", CategoryColor::LightRed),
+        (KnownCategory::KernelMemoryManager, "Kernel Memory Manager", CategoryColor::Red),
+        (KnownCategory::KernelCacheManager, "Kernel Cache Manager", CategoryColor::Red),
+        (KnownCategory::Scheduling, "Scheduling", CategoryColor::Magenta),
+        (KnownCategory::Antivirus, "Antivirus", CategoryColor::Brown),
+        (KnownCategory::RegionOfInterest, "Region of Interest", CategoryColor::Blue),
+        (KnownCategory::Hang, "Hang", CategoryColor::Red),
+        (KnownCategory::NetworkWait, "Network wait", CategoryColor::LightBlue),
         (KnownCategory::Unknown, "Other", CategoryColor::DarkGray),
     ];
 
@@ -427,24 +603,31 @@ impl KnownCategories {
         };
 
         *self.0.entry(category).or_insert_with(|| {
-            let (category_name, color) = Self::CATEGORIES
-                .iter()
-                .find(|(c, _, _)| *c == category)
-                .map(|(_, name, color)| (*name, *color))
-                .unwrap();
+            let (category_name, color) = Self::name_and_color(category);
             profile.add_category(category_name, color)
         })
     }
+
+    fn name_and_color(category: KnownCategory) -> (&'static str, CategoryColor) {
+        Self::CATEGORIES
+            .iter()
+            .find(|(c, _, _)| *c == category)
+            .map(|(_, name, color)| (*name, *color))
+            .unwrap()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 struct AddressClassifier {
     kernel_min: u64,
+    /// Signed arm64 user-space return addresses can have high bits set, so the
+    /// PAC needs to be removed before comparing against `kernel_min`.
+    ptr_auth_stripper: PtrAuthStripper,
 }
 
 impl AddressClassifier {
     pub fn get_stack_mode(&self, address: u64) -> StackMode {
-        if address >= self.kernel_min {
+        if self.ptr_auth_stripper.strip(address) >= self.kernel_min {
             StackMode::Kernel
         } else {
             StackMode::User
@@ -494,6 +677,18 @@ pub struct ProfileContext {
 
     address_classifier: AddressClassifier,
 
+    /// The address ranges of afd.sys, the kernel driver behind Winsock. Off-CPU
+    /// samples whose kernel stack goes through it are waits on a socket.
+    afd_address_ranges: Vec<(u64, u64)>,
+
+    /// The kernel image (ntoskrnl.exe), and the address range it's loaded at.
+    /// The start addresses of the System process's threads are looked up in it.
+    ntoskrnl_lib: Option<LibraryInfo>,
+    ntoskrnl_address_range: Option<(u64, u64)>,
+
+    /// The start addresses of the System process's threads, keyed by tid.
+    kernel_thread_start_addresses: HashMap<u32, u64>,
+
     // architecture to record in the trace. will be the system architecture for now.
     // TODO no idea how to handle "I'm on aarch64 windows but I'm recording a win64 process".
     // I have no idea how stack traces work in that case anyway, so this is probably moot.
@@ -514,6 +709,82 @@ pub struct ProfileContext {
     time_range: Option<(Timestamp, Timestamp)>,
 
     cpus: Option<Cpus>,
+
+    /// The last marker from a user provider event on each thread, keyed by tid,
+    /// with the raw event timestamp. If the event was logged with a stack walk,
+    /// the stack arrives afterwards with the same timestamp.
+    markers_with_pending_stacks: HashMap<u32, (u64, ThreadHandle, MarkerHandle)>,
+
+    /// Thread names from SetName events for threads which we haven't seen a
+    /// DCStart event for yet, keyed by tid. The thread name rundown at the
+    /// start of the trace can come before the thread rundown.
+    pending_thread_names: HashMap<u32, String>,
+
+    /// The names of the PMCs configured for the recording, if known.
+    pmc_counter_names: Vec<String>,
+
+    /// The name of the PMC profile source which drives the samples instead of
+    /// the timer, for `--pmc-sampling`.
+    pmc_sampling_source: Option<String>,
+
+    /// The intervals of the PMC profile sources, in events, keyed by source id.
+    pmc_sample_intervals: BTreeMap<u32, u32>,
+
+    /// The ids of the profile sources of the PMC samples we've seen.
+    pmc_sample_source_ids: BTreeSet<u32>,
+
+    /// The interval of the timer profile source, once known.
+    sampling_interval_nanos: Option<u64>,
+
+    /// The interval that was set on the profile, the shortest one seen so far.
+    profile_interval_nanos: u64,
+
+    /// When the timer interval changed during the trace, after the initial
+    /// CollectionStart, as raw timestamps with the new interval.
+    sampling_interval_changes: Vec<(u64, u64)>,
+
+    /// The timestamp of the most recent CSwitch or ReadyThread event, where
+    /// the open thread state intervals end at the end of the profile.
+    last_thread_state_timestamp_raw: u64,
+
+    /// The tid of the most recent sample on each CPU, keyed by CPU index, so
+    /// that the PMC values which follow a sample can be attributed to it.
+    last_sample_tid_per_cpu: HashMap<u32, u32>,
+
+    /// Finds the CPUs which had no samples for a while.
+    sample_gaps: SampleGapDetector,
+
+    /// The thread for the sampling gap markers if there are no per-CPU threads.
+    sample_gap_thread_handle: Option<ThreadHandle>,
+
+    /// The hangs of each thread, for `--hangs`. The markers are added at the
+    /// end, when the samples for their stacks are in the profile.
+    hangs: HashMap<ThreadHandle, Vec<Hang>>,
+
+    /// The time of the most recent CSwitch event, where the hangs which are
+    /// still going on at the end of the profile end.
+    last_cswitch_timestamp_raw: u64,
+
+    /// The region definitions from `--regions-of-interest`, which turn pairs of
+    /// events into interval markers.
+    regions_of_interest: Option<RegionsOfInterest>,
+
+    /// The raw PMC values of the previous PMC event on each CPU. The counters
+    /// count per CPU, so the delta since the previous event on the same CPU
+    /// is what the sampled thread contributed.
+    last_pmc_values_per_cpu: HashMap<u32, Vec<u64>>,
+
+    /// Which samples to keep of the processes from `--interval-for`.
+    process_sample_strides: ProcessSampleStrides,
+
+    /// Whether we're in the second pass of a two-pass import, see
+    /// `begin_sample_pass`.
+    is_sample_pass: bool,
+
+    /// Some() if processes and threads are created when we first see one of
+    /// their events, with the names of the processes we know, keyed by pid.
+    /// User-mode-only traces don't have the kernel's process and thread events.
+    synthetic_process_names: Option<HashMap<u32, String>>,
 }
 
 impl ProfileContext {
@@ -531,13 +802,23 @@ impl ProfileContext {
         } else {
             0xF000_0000_0000_0000
         };
-        let address_classifier = AddressClassifier { kernel_min };
-        let process_recycler = if profile_creation_props.reuse_threads {
-            Some(ProcessRecycler::new())
+        let ptr_auth_stripper = match arch {
+            "arm64" => PtrAuthStripper::ARM64,
+            _ => PtrAuthStripper::NONE,
+        };
+        let address_classifier = AddressClassifier {
+            kernel_min,
+            ptr_auth_stripper,
+        };
+        let process_recycler = if profile_creation_props.reuse_processes() {
+            Some(ProcessRecycler::new(profile_creation_props.reuse_threads))
         } else {
             None
         };
         let main_thread_only = profile_creation_props.main_thread_only;
+        let max_stack_depth = profile_creation_props.max_stack_depth;
+        profile.set_thread_order(profile_creation_props.thread_order);
+        profile.set_hide_idle_threads(profile_creation_props.hide_idle_threads);
         let time_range = profile_creation_props.time_range.map(|(start, end)| {
             (
                 Timestamp::from_nanos_since_reference(start.as_nanos() as u64),
@@ -548,7 +829,7 @@ impl ProfileContext {
         let mut categories = KnownCategories::new();
         let mut js_category_manager = JitCategoryManager::new();
         let default_js_jit_category = js_category_manager.default_category(&mut profile);
-        let allow_jit_function_recycling = profile_creation_props.reuse_threads;
+        let allow_jit_function_recycling = profile_creation_props.reuse_processes();
         let js_jit_lib = SyntheticJitLibrary::new(
             "JS JIT".to_string(),
             default_js_jit_category.into(),
@@ -578,7 +859,7 @@ impl ProfileContext {
             processes: Processes::new(),
             threads: Threads::new(),
             thread_handles: BTreeMap::new(),
-            unresolved_stacks: UnresolvedStacks::default(),
+            unresolved_stacks: UnresolvedStacks::with_max_depth(max_stack_depth),
             process_recycler,
             gpu_thread_handle: None,
             included_processes,
@@ -591,6 +872,10 @@ impl ProfileContext {
             device_mappings: winutils::get_dos_device_mappings(),
             kernel_min,
             address_classifier,
+            afd_address_ranges: Vec::new(),
+            ntoskrnl_lib: None,
+            ntoskrnl_address_range: None,
+            kernel_thread_start_addresses: HashMap::new(),
             arch: arch.to_string(),
             sample_count: 0,
             stack_sample_count: 0,
@@ -605,6 +890,117 @@ impl ProfileContext {
             main_thread_only,
             time_range,
             cpus,
+            markers_with_pending_stacks: HashMap::new(),
+            pending_thread_names: HashMap::new(),
+            pmc_counter_names: Vec::new(),
+            pmc_sampling_source: None,
+            pmc_sample_intervals: BTreeMap::new(),
+            pmc_sample_source_ids: BTreeSet::new(),
+            sampling_interval_nanos: None,
+            profile_interval_nanos: 0,
+            sampling_interval_changes: Vec::new(),
+            last_thread_state_timestamp_raw: 0,
+            last_sample_tid_per_cpu: HashMap::new(),
+            sample_gaps: SampleGapDetector::default(),
+            sample_gap_thread_handle: None,
+            hangs: HashMap::new(),
+            last_cswitch_timestamp_raw: 0,
+            regions_of_interest: None,
+            last_pmc_values_per_cpu: HashMap::new(),
+            process_sample_strides: ProcessSampleStrides::default(),
+            is_sample_pass: false,
+            synthetic_process_names: None,
+        }
+    }
+
+    /// Sets the names of the PMCs which were passed to `xperf -pmc`. Without
+    /// names, the counters are called "PMC 0", "PMC 1" etc.
+    pub fn set_pmc_counter_names(&mut self, pmc_counter_names: Vec<String>) {
+        self.pmc_counter_names = pmc_counter_names;
+    }
+
+    /// Sets the name of the profile source which was passed to `xperf -PmcProfile`,
+    /// if the samples are taken on PMC overflow instead of on a timer.
+    pub fn set_pmc_sampling_source(&mut self, pmc_sampling_source: Option<String>) {
+        self.pmc_sampling_source = pmc_sampling_source;
+    }
+
+    /// Only keeps every n-th sample of the processes which should be sampled
+    /// at a lower rate, with a weight of n.
+    pub fn set_process_sample_strides(&mut self, process_sample_strides: ProcessSampleStrides) {
+        self.process_sample_strides = process_sample_strides;
+    }
+
+    /// Creates processes and threads when we first see one of their events, for
+    /// traces from a user-mode-only session. `process_names` has the names of
+    /// the processes we know about, e.g. the ones we launched; other processes
+    /// are named after their pid.
+    pub fn enable_synthetic_processes(&mut self, process_names: HashMap<u32, String>) {
+        self.synthetic_process_names = Some(process_names);
+    }
+
+    /// Adds a power track for each energy meter channel which was read during
+    /// the recording. The meters measure the whole system, so the tracks are
+    /// put on the first process in the profile. Call this after the ETL files
+    /// have been processed, so that the QPC readings can be converted.
+    pub fn add_energy_meter_readings(&mut self, channels: Vec<EnergyChannelReadings>) {
+        if channels.is_empty() {
+            return;
+        }
+        if !self.event_timestamps_are_qpc {
+            log::warn!("Dropping the energy meter readings because the trace doesn't use QPC");
+            return;
+        }
+        let Some(process_handle) = self.processes.iter().next().map(|p| p.handle) else {
+            return;
+        };
+        for channel in channels {
+            let description = format!("Power of the \"{}\" energy meter channel", channel.name);
+            let mut counter = PowerCounter::new(
+                &mut self.profile,
+                process_handle,
+                &channel.name,
+                &description,
+            );
+            for (timestamp_raw, energy_pwh) in channel.readings {
+                let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+                counter.add_reading(&mut self.profile, timestamp, energy_pwh);
+            }
+        }
+    }
+
+    /// Makes sure that the process and the thread of an event exist, if
+    /// `enable_synthetic_processes` was called.
+    pub fn ensure_synthetic_process_and_thread(&mut self, timestamp_raw: u64, pid: u32, tid: u32) {
+        let Some(process_names) = &self.synthetic_process_names else {
+            return;
+        };
+        if pid == 0 || pid == u32::MAX {
+            return;
+        }
+        if !self.processes.has_process_at_time(pid, timestamp_raw) {
+            let name = match process_names.get(&pid) {
+                Some(name) => name.clone(),
+                None => format!("pid {pid}"),
+            };
+            self.handle_process_dcstart(timestamp_raw, pid, 0, name, String::new());
+        }
+        if self.processes.has_process_at_time(pid, timestamp_raw)
+            && !self.threads.has_thread_at_time(tid, timestamp_raw)
+        {
+            self.handle_thread_dcstart(timestamp_raw, tid, pid, None, None);
+        }
+    }
+
+    /// Remembers where a thread of the System process started, so that it can
+    /// be categorized by its start routine at the end.
+    fn note_kernel_thread_start_address(&mut self, pid: u32, tid: u32, start_address: Option<u64>) {
+        match start_address {
+            Some(start_address) if pid == SYSTEM_PROCESS_PID && start_address != 0 => {
+                self.kernel_thread_start_addresses
+                    .insert(tid, start_address);
+            }
+            _ => {}
         }
     }
 
@@ -629,6 +1025,10 @@ impl ProfileContext {
             return false;
         }
 
+        if pid == SYSTEM_PROCESS_PID && self.profile_creation_props.include_system_process {
+            return true;
+        }
+
         // already tracking this process or its parent?
         if self.processes.has(pid) || ppid.is_some_and(|k| self.processes.has(k)) {
             return true;
@@ -695,7 +1095,7 @@ impl ProfileContext {
         let start_timestamp = self.timestamp_converter.convert_time(start_timestamp_raw);
         let end_timestamp = self.timestamp_converter.convert_time(end_timestamp_raw);
         let timing = MarkerTiming::Interval(start_timestamp, end_timestamp);
-        let thread = self.threads.get_by_tid(tid).unwrap();
+        let thread = self.threads.get_at_time(tid, start_timestamp_raw).unwrap();
         self.profile.add_marker(thread.handle, timing, marker)
     }
 
@@ -730,12 +1130,54 @@ impl ProfileContext {
         }
     }
 
-    pub fn handle_collection_start(&mut self, interval_raw: u32) {
+    /// A CollectionStart or SetInterval event for the sampled profile source
+    /// `source`. Only the timer source determines the sampling interval; the
+    /// intervals of the PMC sources are event counts. The timer interval can
+    /// change during the trace, e.g. when another session asks for a
+    /// different rate.
+    pub fn handle_sampling_interval(&mut self, timestamp_raw: u64, source: u32, interval_raw: u32) {
+        const PROFILE_TIME_SOURCE: u32 = 0;
+        if source != PROFILE_TIME_SOURCE && interval_raw != 0 {
+            self.pmc_sample_intervals.insert(source, interval_raw);
+        }
+        if source != PROFILE_TIME_SOURCE || interval_raw == 0 {
+            return;
+        }
         let interval_nanos = interval_raw as u64 * 100;
-        let interval = SamplingInterval::from_nanos(interval_nanos);
+        self.context_switch_handler = ContextSwitchHandler::new(interval_raw as u64);
+
+        let profile_interval_nanos = match self.sampling_interval_nanos.replace(interval_nanos) {
+            None => interval_nanos,
+            Some(previous) if previous == interval_nanos => return,
+            Some(_) => {
+                // The metadata pass of a two-pass import has already seen this change.
+                if !self.is_sample_pass {
+                    self.sampling_interval_changes
+                        .push((timestamp_raw, interval_nanos));
+                }
+                // The profile can only have one interval. Use the shortest one, so
+                // that the graphs have enough resolution for all parts.
+                interval_nanos.min(self.profile_interval_nanos)
+            }
+        };
+        self.profile_interval_nanos = profile_interval_nanos;
+        let interval = SamplingInterval::from_nanos(profile_interval_nanos);
         log::info!("Sample rate {}ms", interval.as_secs_f64() * 1000.);
         self.profile.set_interval(interval);
-        self.context_switch_handler = ContextSwitchHandler::new(interval_raw as u64);
+    }
+
+    /// Records the times at which the sampling interval changed in the profile's metadata.
+    fn add_sampling_interval_change_meta_info(&mut self) {
+        for (timestamp_raw, interval_nanos) in &self.sampling_interval_changes {
+            let converter = &self.timestamp_converter;
+            let since_start_nanos =
+                timestamp_raw.saturating_sub(converter.reference_raw) * converter.raw_to_ns_factor;
+            self.profile.add_extra_meta_info(
+                "Sampling interval changes",
+                &format!("{:.3}ms", since_start_nanos as f64 / 1_000_000.0),
+                &format!("{:?}", std::time::Duration::from_nanos(*interval_nanos)),
+            );
+        }
     }
 
     pub fn make_process_name(&self, image_file_name: &str, cmdline: &str) -> String {
@@ -762,25 +1204,34 @@ impl ProfileContext {
         }
 
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let lifetime = Lifetime::new(timestamp, true);
         let name = self.make_process_name(&image_file_name, &cmdline);
         let process_handle = self.profile.add_process(&name, pid, timestamp);
         let main_thread_handle = self
             .profile
             .add_thread(process_handle, pid, timestamp, true);
-        let main_thread_label_frame =
-            make_thread_label_frame(&mut self.profile, Some(&name), pid, pid);
-        let (thread_recycler, jit_function_recycler) = if self.process_recycler.is_some() {
-            (
-                Some(ThreadRecycler::new()),
-                Some(JitFunctionRecycler::default()),
-            )
-        } else {
-            (None, None)
-        };
+        let main_thread_label_frame = make_thread_label_frame(
+            &mut self.profile,
+            &mut self.categories,
+            Some(&name),
+            pid,
+            pid,
+        );
+        let (thread_recycler, jit_function_recycler) =
+            if let Some(process_recycler) = &self.process_recycler {
+                (
+                    process_recycler.new_thread_recycler(),
+                    Some(JitFunctionRecycler::default()),
+                )
+            } else {
+                (None, None)
+            };
         let process = Process::new(
             name,
             pid,
             parent_pid,
+            cmdline,
+            lifetime,
             process_handle,
             main_thread_handle,
             main_thread_label_frame,
@@ -790,6 +1241,19 @@ impl ProfileContext {
         self.processes.add(pid, timestamp_raw, process);
     }
 
+    /// The System process doesn't always get a Process/DCStart event, e.g. if
+    /// the rundown was incomplete. Create it on demand so that the activity on
+    /// kernel threads isn't dropped.
+    fn add_synthetic_system_process(&mut self, timestamp_raw: u64) {
+        self.handle_process_dcstart(
+            timestamp_raw,
+            SYSTEM_PROCESS_PID,
+            0,
+            "System".to_string(),
+            String::new(),
+        );
+    }
+
     pub fn handle_process_start(
         &mut self,
         timestamp_raw: u64,
@@ -805,12 +1269,13 @@ impl ProfileContext {
         }
 
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let lifetime = Lifetime::new(timestamp, false);
 
         let name = self.make_process_name(&image_file_name, &cmdline);
         let recycling_data = self
             .process_recycler
             .as_mut()
-            .and_then(|pr| pr.recycle_by_name(&name));
+            .and_then(|pr| pr.recycle_by_name(&name, &mut self.profile));
 
         let (process_handle, main_thread_handle, main_thread_label_frame) =
             if let Some(recycling_data) = &recycling_data {
@@ -827,20 +1292,25 @@ impl ProfileContext {
                 let main_thread_handle =
                     self.profile
                         .add_thread(process_handle, pid, timestamp, true);
-                let main_thread_label_frame =
-                    make_thread_label_frame(&mut self.profile, Some(&name), pid, pid);
+                let main_thread_label_frame = make_thread_label_frame(
+                    &mut self.profile,
+                    &mut self.categories,
+                    Some(&name),
+                    pid,
+                    pid,
+                );
                 (process_handle, main_thread_handle, main_thread_label_frame)
             };
 
         let (thread_recycler, jit_function_recycler) = if let Some(recycling_data) = recycling_data
         {
             (
-                Some(recycling_data.thread_recycler),
+                recycling_data.thread_recycler,
                 Some(recycling_data.jit_function_recycler),
             )
-        } else if self.process_recycler.is_some() {
+        } else if let Some(process_recycler) = &self.process_recycler {
             (
-                Some(ThreadRecycler::new()),
+                process_recycler.new_thread_recycler(),
                 Some(JitFunctionRecycler::default()),
             )
         } else {
@@ -851,6 +1321,8 @@ impl ProfileContext {
             name,
             pid,
             parent_pid,
+            cmdline,
+            lifetime,
             process_handle,
             main_thread_handle,
             main_thread_label_frame,
@@ -860,13 +1332,15 @@ impl ProfileContext {
         self.processes.add(pid, timestamp_raw, process);
     }
 
-    pub fn handle_process_end(&mut self, timestamp_raw: u64, pid: u32) {
+    pub fn handle_process_end(&mut self, timestamp_raw: u64, pid: u32, exit_code: Option<u32>) {
         let Some(process) = self.processes.get_by_pid(pid) else {
             return;
         };
 
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
         self.profile.set_process_end_time(process.handle, timestamp);
+        process.lifetime.set_end(timestamp);
+        process.exit_code = exit_code;
 
         if let Some(process_recycler) = self.process_recycler.as_mut() {
             if let Some(process_recycling_data) = process.take_recycling_data() {
@@ -892,30 +1366,54 @@ impl ProfileContext {
         tid: u32,
         pid: u32,
         mut name: Option<String>,
+        start_address: Option<u64>,
     ) {
         if !self.is_interesting_process(pid, None, None) {
             return;
         }
+        self.note_kernel_thread_start_address(pid, tid, start_address);
 
         if name.as_deref().is_some_and(|name| name.is_empty()) {
             name = None;
         }
+        if let Some(pending_name) = self.pending_thread_names.remove(&tid) {
+            name = name.or(Some(pending_name));
+        }
 
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let lifetime = Lifetime::new(timestamp, true);
+
+        if pid == SYSTEM_PROCESS_PID && !self.processes.has(pid) {
+            self.add_synthetic_system_process(timestamp_raw);
+        }
 
         let Some(process) = self.processes.get_by_pid(pid) else {
             log::warn!("Adding thread {tid} for unknown pid {pid}");
             return;
         };
+        process.thread_counts.thread_started(timestamp);
         if !process.seen_main_thread_start {
             process.seen_main_thread_start = true;
             let thread_handle = process.main_thread_handle;
             let thread_name = name.as_deref().unwrap_or(&process.name);
-            let thread_label_frame =
-                make_thread_label_frame(&mut self.profile, Some(thread_name), pid, tid);
+            let thread_label_frame = make_thread_label_frame(
+                &mut self.profile,
+                &mut self.categories,
+                Some(thread_name),
+                pid,
+                tid,
+            );
             process.main_thread_label_frame = thread_label_frame.clone();
             self.profile.set_thread_tid(thread_handle, tid);
-            let thread = Thread::new(name, true, thread_handle, thread_label_frame, pid, tid);
+            let thread = Thread::new(
+                name,
+                true,
+                thread_handle,
+                thread_label_frame,
+                pid,
+                tid,
+                lifetime,
+            );
             self.threads.add(tid, timestamp_raw, thread);
             self.thread_handles
                 .insert((tid, timestamp_raw), thread_handle);
@@ -930,15 +1428,28 @@ impl ProfileContext {
         let thread_handle = self
             .profile
             .add_thread(process.handle, tid, timestamp, false);
-        let thread_label_frame =
-            make_thread_label_frame(&mut self.profile, name.as_deref(), pid, tid);
+        let thread_label_frame = make_thread_label_frame(
+            &mut self.profile,
+            &mut self.categories,
+            name.as_deref(),
+            pid,
+            tid,
+        );
         if let Some(name) = name.as_deref() {
             if !name.is_empty() {
                 self.profile.set_thread_name(thread_handle, name);
             }
         }
 
-        let thread = Thread::new(name, false, thread_handle, thread_label_frame, pid, tid);
+        let thread = Thread::new(
+            name,
+            false,
+            thread_handle,
+            thread_label_frame,
+            pid,
+            tid,
+            lifetime,
+        );
         self.threads.add(tid, timestamp_raw, thread);
         self.thread_handles
             .insert((tid, timestamp_raw), thread_handle);
@@ -950,28 +1461,52 @@ impl ProfileContext {
         tid: u32,
         pid: u32,
         name: Option<String>,
+        start_address: Option<u64>,
     ) {
         self.threads.notify_thread_created(tid, timestamp_raw);
+        // Names for this tid from before the thread was created belong to a
+        // different thread.
+        self.pending_thread_names.remove(&tid);
 
         if !self.is_interesting_process(pid, None, None) {
             return;
         }
+        self.note_kernel_thread_start_address(pid, tid, start_address);
 
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let lifetime = Lifetime::new(timestamp, false);
+
+        if pid == SYSTEM_PROCESS_PID && !self.processes.has(pid) {
+            self.add_synthetic_system_process(timestamp_raw);
+        }
 
         let Some(process) = self.processes.get_by_pid(pid) else {
             log::warn!("Adding thread {tid} for unknown pid {pid}");
             return;
         };
+        process.thread_counts.thread_started(timestamp);
         if !process.seen_main_thread_start {
             process.seen_main_thread_start = true;
             let thread_handle = process.main_thread_handle;
             let thread_name = name.as_deref().unwrap_or(&process.name);
-            let thread_label_frame =
-                make_thread_label_frame(&mut self.profile, Some(thread_name), pid, tid);
+            let thread_label_frame = make_thread_label_frame(
+                &mut self.profile,
+                &mut self.categories,
+                Some(thread_name),
+                pid,
+                tid,
+            );
             process.main_thread_label_frame = thread_label_frame.clone();
             self.profile.set_thread_tid(thread_handle, tid);
-            let thread = Thread::new(name, true, thread_handle, thread_label_frame, pid, tid);
+            let thread = Thread::new(
+                name,
+                true,
+                thread_handle,
+                thread_label_frame,
+                pid,
+                tid,
+                lifetime,
+            );
             self.threads.add(tid, timestamp_raw, thread);
             self.thread_handles
                 .insert((tid, timestamp_raw), thread_handle);
@@ -989,7 +1524,15 @@ impl ProfileContext {
             if let Some((thread_handle, thread_label_frame)) =
                 thread_recycler.recycle_by_name(thread_name)
             {
-                let thread = Thread::new(name, false, thread_handle, thread_label_frame, pid, tid);
+                let thread = Thread::new(
+                    name,
+                    false,
+                    thread_handle,
+                    thread_label_frame,
+                    pid,
+                    tid,
+                    lifetime,
+                );
                 self.threads.add(tid, timestamp_raw, thread);
                 self.thread_handles
                     .insert((tid, timestamp_raw), thread_handle);
@@ -1000,15 +1543,28 @@ impl ProfileContext {
         let thread_handle = self
             .profile
             .add_thread(process.handle, tid, timestamp, false);
-        let thread_label_frame =
-            make_thread_label_frame(&mut self.profile, name.as_deref(), pid, tid);
+        let thread_label_frame = make_thread_label_frame(
+            &mut self.profile,
+            &mut self.categories,
+            name.as_deref(),
+            pid,
+            tid,
+        );
         if let Some(name) = name.as_deref() {
             if !name.is_empty() {
                 self.profile.set_thread_name(thread_handle, name);
             }
         }
 
-        let thread = Thread::new(name, false, thread_handle, thread_label_frame, pid, tid);
+        let thread = Thread::new(
+            name,
+            false,
+            thread_handle,
+            thread_label_frame,
+            pid,
+            tid,
+            lifetime,
+        );
         self.threads.add(tid, timestamp_raw, thread);
         self.thread_handles
             .insert((tid, timestamp_raw), thread_handle);
@@ -1046,6 +1602,11 @@ impl ProfileContext {
             return;
         }
         let Some(thread) = self.threads.get_by_tid(tid) else {
+            // This is probably the name rundown for a thread which we'll see a
+            // DCStart event for later. Keep the name until then.
+            if self.is_interesting_process(pid, None, None) {
+                self.pending_thread_names.insert(tid, name);
+            }
             return;
         };
         let Some(process) = self.processes.get_by_pid(pid) else {
@@ -1068,17 +1629,29 @@ impl ProfileContext {
                 return;
             }
         }
-        thread.label_frame = make_thread_label_frame(&mut self.profile, Some(&name), pid, tid);
+        thread.label_frame = make_thread_label_frame(
+            &mut self.profile,
+            &mut self.categories,
+            Some(&name),
+            pid,
+            tid,
+        );
         self.profile.set_thread_name(thread.handle, &name);
         thread.name = Some(name);
     }
 
     pub fn handle_thread_end(&mut self, timestamp_raw: u64, pid: u32, tid: u32) {
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        // Threads which don't get a track, e.g. with --main-thread-only, are
+        // counted too.
+        if let Some(process) = self.processes.get_by_pid(pid) {
+            process.thread_counts.thread_ended(timestamp);
+        }
         let Some(thread) = self.threads.get_by_tid(tid) else {
             return;
         };
-        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
         self.profile.set_thread_end_time(thread.handle, timestamp);
+        thread.lifetime.set_end(timestamp);
 
         let Some(process) = self.processes.get_by_pid(pid) else {
             return;
@@ -1097,8 +1670,9 @@ impl ProfileContext {
 
     /// Attach a stack to an existing marker.
     ///
-    /// CoreCLR emits these stacks after the corresponding marker.
-    pub fn handle_coreclr_stack(
+    /// CoreCLR emits these stacks after the corresponding marker. The stack
+    /// walk events for user provider events also come after the event.
+    pub fn handle_marker_stack(
         &mut self,
         timestamp_raw: u64,
         pid: u32,
@@ -1135,19 +1709,63 @@ impl ProfileContext {
         tid: u32,
         stack_address_iter: impl Iterator<Item = u64>,
     ) {
-        let Some(process) = self.processes.get_by_pid(pid) else {
+        if let Some(thread_marker_handle) = self.take_marker_with_pending_stack(tid, timestamp_raw)
+        {
+            self.handle_marker_stack(timestamp_raw, pid, stack_address_iter, thread_marker_handle);
             return;
-        };
-        let Some(thread) = self.threads.get_by_tid(tid) else {
+        }
+
+        let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) else {
             return;
         };
 
         // On ARM64, this seems to be simpler -- stacks come in with full kernel and user frames.
         // At least, I've never seen a kernel stack come in separately.
         // TODO -- is this because I can't use PROFILE events in the VM?
-
         let stack: Vec<StackFrame> = to_stack_frames(stack_address_iter, self.address_classifier);
 
+        if thread
+            .samples_with_pending_stacks
+            .iter()
+            .any(|s| s.timestamp == timestamp_raw)
+        {
+            // The stack of a CPU sample, or of a context switch which ended a
+            // blocked period. Earlier samples which didn't get a stack of their
+            // own get this one too, like for the user stacks on x86.
+            let stack_index = self.unresolved_stacks.convert(stack.into_iter().rev());
+            let num_samples = thread
+                .samples_with_pending_stacks
+                .iter()
+                .take_while(|s| s.timestamp <= timestamp_raw)
+                .count();
+            let samples: VecDeque<_> = thread
+                .samples_with_pending_stacks
+                .drain(..num_samples)
+                .collect();
+            let thread_handle = thread.handle;
+            let thread_label_frame = thread.label_frame.clone();
+            for sample_info in samples {
+                self.consume_sample(
+                    pid,
+                    sample_info,
+                    stack_index,
+                    thread_handle,
+                    thread_label_frame.clone(),
+                );
+            }
+            return;
+        }
+
+        if self.sample_count != 0 {
+            // The stack of an event we don't make samples for.
+            return;
+        }
+
+        // Without sampled profile events (e.g. with --vm-hack), the stacks of
+        // the other events are the only samples.
+        let Some(process) = self.processes.get_at_time(pid, timestamp_raw) else {
+            return;
+        };
         let cpu_delta_raw = self
             .context_switch_handler
             .consume_cpu_delta(&mut thread.context_switch_data);
@@ -1174,6 +1792,12 @@ impl ProfileContext {
         stack_len: usize,
         stack_address_iter: impl Iterator<Item = u64>,
     ) {
+        if let Some(thread_marker_handle) = self.take_marker_with_pending_stack(tid, timestamp_raw)
+        {
+            self.handle_marker_stack(timestamp_raw, pid, stack_address_iter, thread_marker_handle);
+            return;
+        }
+
         let mut stack: Vec<StackFrame> = Vec::with_capacity(stack_len);
         let mut address_iter = stack_address_iter;
         let Some(first_frame_address) = address_iter.next() else {
@@ -1195,6 +1819,21 @@ impl ProfileContext {
         }
     }
 
+    /// Returns the marker which a stack walk event with this timestamp on this
+    /// thread belongs to, if any.
+    fn take_marker_with_pending_stack(
+        &mut self,
+        tid: u32,
+        timestamp_raw: u64,
+    ) -> Option<(ThreadHandle, MarkerHandle)> {
+        let (marker_timestamp_raw, _, _) = self.markers_with_pending_stacks.get(&tid)?;
+        if *marker_timestamp_raw != timestamp_raw {
+            return None;
+        }
+        let (_, thread_handle, marker_handle) = self.markers_with_pending_stacks.remove(&tid)?;
+        Some((thread_handle, marker_handle))
+    }
+
     fn handle_kernel_stack(
         &mut self,
         timestamp_raw: u64,
@@ -1202,7 +1841,7 @@ impl ProfileContext {
         tid: u32,
         stack: Vec<StackFrame>,
     ) {
-        let Some(thread) = self.threads.get_by_tid(tid) else {
+        let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) else {
             return;
         };
         let Some(index) = thread
@@ -1220,7 +1859,7 @@ impl ProfileContext {
             sample_info.kernel_stack = Some(stack);
         }
 
-        if pid == 4 {
+        if pid == SYSTEM_PROCESS_PID {
             // No user stack will arrive. Consume the sample now.
             let sample_info = thread.samples_with_pending_stacks.remove(index).unwrap();
             let thread_handle = thread.handle;
@@ -1242,9 +1881,18 @@ impl ProfileContext {
         tid: u32,
         user_stack: Vec<StackFrame>,
     ) {
-        let Some(thread) = self.threads.get_by_tid(tid) else {
+        let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) else {
             return;
         };
+        if !thread
+            .samples_with_pending_stacks
+            .front()
+            .is_some_and(|s| s.timestamp <= timestamp_raw)
+        {
+            // The stack of an event we don't make samples for, e.g. with
+            // `--stack-walk virtual-alloc`.
+            return;
+        }
 
         // User stacks always come last. Consume any samples with pending stacks with matching timestamp.
         let user_stack_index = self.unresolved_stacks.convert(user_stack.into_iter().rev());
@@ -1284,7 +1932,16 @@ impl ProfileContext {
         thread_handle: ThreadHandle,
         thread_label_frame: FrameInfo,
     ) {
-        let Some(process) = self.processes.get_by_pid(pid) else {
+        // The frame which says what a blocked thread waited for.
+        let wait_frame = match &sample_info.off_cpu_sample_group {
+            Some(_)
+                if self.is_network_wait(sample_info.kernel_stack.as_deref(), user_stack_index) =>
+            {
+                Some(self.network_wait_frame())
+            }
+            _ => None,
+        };
+        let Some(process) = self.processes.get_at_time(pid, sample_info.timestamp) else {
             return;
         };
         let SampleWithPendingStack {
@@ -1293,44 +1950,67 @@ impl ProfileContext {
             off_cpu_sample_group,
             mut cpu_delta,
             has_on_cpu_sample,
+            weight,
             per_cpu_stuff,
+            cpu,
         } = sample_info;
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
 
         if let Some(off_cpu_sample_group) = off_cpu_sample_group {
-            let OffCpuSampleGroup {
-                begin_timestamp: begin_timestamp_raw,
-                end_timestamp: end_timestamp_raw,
-                sample_count,
-            } = off_cpu_sample_group;
-
-            // Add a sample at the beginning of the paused range.
-            // This "first sample" will carry any leftover accumulated running time ("cpu delta").
-            let begin_timestamp = self.timestamp_converter.convert_time(begin_timestamp_raw);
-            process.unresolved_samples.add_sample(
-                thread_handle,
-                begin_timestamp,
-                begin_timestamp_raw,
-                user_stack_index,
-                cpu_delta,
-                1,
-                None,
-            );
-            cpu_delta = CpuDelta::ZERO;
-
-            if sample_count > 1 {
-                // Emit a "rest sample" with a CPU delta of zero covering the rest of the paused range.
-                let weight = i32::try_from(sample_count - 1).unwrap_or(0);
-                let end_timestamp = self.timestamp_converter.convert_time(end_timestamp_raw);
-                process.unresolved_samples.add_sample(
+            if self.profile_creation_props.wall_clock_samples {
+                // One sample per interval. The first one carries any leftover accumulated
+                // running time ("cpu delta").
+                for timestamp_raw in off_cpu_sample_group.sample_timestamps() {
+                    let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+                    add_off_cpu_sample(
+                        &mut process.unresolved_samples,
+                        thread_handle,
+                        timestamp,
+                        timestamp_raw,
+                        user_stack_index,
+                        std::mem::replace(&mut cpu_delta, CpuDelta::ZERO),
+                        1,
+                        wait_frame.as_ref(),
+                    );
+                }
+            } else {
+                let OffCpuSampleGroup {
+                    begin_timestamp: begin_timestamp_raw,
+                    end_timestamp: end_timestamp_raw,
+                    sample_count,
+                } = off_cpu_sample_group;
+
+                // Add a sample at the beginning of the paused range. This "first sample"
+                // will carry any leftover accumulated running time ("cpu delta").
+                let begin_timestamp = self.timestamp_converter.convert_time(begin_timestamp_raw);
+                add_off_cpu_sample(
+                    &mut process.unresolved_samples,
                     thread_handle,
-                    end_timestamp,
-                    end_timestamp_raw,
+                    begin_timestamp,
+                    begin_timestamp_raw,
                     user_stack_index,
-                    CpuDelta::ZERO,
-                    weight,
-                    None,
+                    cpu_delta,
+                    1,
+                    wait_frame.as_ref(),
                 );
+                cpu_delta = CpuDelta::ZERO;
+
+                if sample_count > 1 {
+                    // Emit a "rest sample" with a CPU delta of zero covering the rest of the
+                    // paused range.
+                    let weight = i32::try_from(sample_count - 1).unwrap_or(0);
+                    let end_timestamp = self.timestamp_converter.convert_time(end_timestamp_raw);
+                    add_off_cpu_sample(
+                        &mut process.unresolved_samples,
+                        thread_handle,
+                        end_timestamp,
+                        end_timestamp_raw,
+                        user_stack_index,
+                        CpuDelta::ZERO,
+                        weight,
+                        wait_frame.as_ref(),
+                    );
+                }
             }
         }
 
@@ -1344,33 +2024,44 @@ impl ProfileContext {
         } else {
             user_stack_index
         };
-        process.unresolved_samples.add_sample(
-            thread_handle,
-            timestamp,
-            timestamp_raw,
-            stack_index,
-            cpu_delta,
-            1,
-            None,
-        );
-
-        if let Some((cpu_thread_handle, cpu_delta)) = per_cpu_stuff {
-            process.unresolved_samples.add_sample(
-                cpu_thread_handle,
+        match cpu {
+            Some(cpu) => process.unresolved_samples.add_sample_on_cpu(
+                thread_handle,
                 timestamp,
                 timestamp_raw,
                 stack_index,
                 cpu_delta,
-                1,
-                Some(thread_label_frame.clone()),
-            );
+                weight,
+                cpu,
+            ),
+            None => process.unresolved_samples.add_sample(
+                thread_handle,
+                timestamp,
+                timestamp_raw,
+                stack_index,
+                cpu_delta,
+                weight,
+                None,
+            ),
+        }
+
+        if let Some((cpu_thread_handle, cpu_delta)) = per_cpu_stuff {
+            process.unresolved_samples.add_sample(
+                cpu_thread_handle,
+                timestamp,
+                timestamp_raw,
+                stack_index,
+                cpu_delta,
+                weight,
+                Some(thread_label_frame.clone()),
+            );
             process.unresolved_samples.add_sample(
                 self.cpus.as_ref().unwrap().combined_thread_handle(),
                 timestamp,
                 timestamp_raw,
                 stack_index,
                 CpuDelta::ZERO,
-                1,
+                weight,
                 Some(thread_label_frame.clone()),
             );
         }
@@ -1378,18 +2069,121 @@ impl ProfileContext {
         self.stack_sample_count += 1;
     }
 
-    pub fn handle_sample(&mut self, timestamp_raw: u64, tid: u32, cpu_index: u32) {
-        let Some(thread) = self.threads.get_by_tid(tid) else {
+    /// Whether a blocked thread waited in afd.sys, i.e. on a socket. The kernel
+    /// frames are in `kernel_stack` on x86, and part of the stack on arm64.
+    fn is_network_wait(
+        &self,
+        kernel_stack: Option<&[StackFrame]>,
+        stack_index: UnresolvedStackHandle,
+    ) -> bool {
+        if self.afd_address_ranges.is_empty() {
+            return false;
+        }
+        let is_afd_frame = |frame: &StackFrame| match *frame {
+            StackFrame::InstructionPointer(address, StackMode::Kernel)
+            | StackFrame::ReturnAddress(address, StackMode::Kernel)
+            | StackFrame::AdjustedReturnAddress(address, StackMode::Kernel) => {
+                let address = self.address_classifier.ptr_auth_stripper.strip(address);
+                self.afd_address_ranges
+                    .iter()
+                    .any(|&(start, end)| (start..end).contains(&address))
+            }
+            _ => false,
+        };
+        match kernel_stack {
+            Some(kernel_stack) => kernel_stack.iter().any(is_afd_frame),
+            None => {
+                let mut frames = Vec::new();
+                self.unresolved_stacks
+                    .convert_back(stack_index, &mut frames);
+                frames.iter().any(is_afd_frame)
+            }
+        }
+    }
+
+    fn network_wait_frame(&mut self) -> FrameInfo {
+        let category = self
+            .categories
+            .get(KnownCategory::NetworkWait, &mut self.profile);
+        FrameInfo {
+            frame: Frame::Label(self.profile.intern_string("Network wait")),
+            category_pair: category.into(),
+            flags: FrameFlags::empty(),
+        }
+    }
+
+    /// `gap_nanos` is the length of the gap in the CPU's samples before this
+    /// one, if [`Self::check_sample_gap`] found one.
+    pub fn handle_sample(
+        &mut self,
+        timestamp_raw: u64,
+        tid: u32,
+        cpu_index: u32,
+        gap_nanos: Option<u64>,
+    ) {
+        self.last_sample_tid_per_cpu.insert(cpu_index, tid);
+        let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) else {
             return;
         };
 
         let off_cpu_sample_group = self
             .context_switch_handler
             .handle_on_cpu_sample(timestamp_raw, &mut thread.context_switch_data);
+
+        let stride_weight = match self.processes.get_at_time(thread.process_id, timestamp_raw) {
+            Some(process) if !self.process_sample_strides.is_empty() => {
+                let stride = self
+                    .process_sample_strides
+                    .stride_for_process(&process.name);
+                if stride > 1 && process.sample_thinner.is_first_sample() {
+                    self.process_sample_strides.add_meta_info(
+                        &mut self.profile,
+                        &process.name,
+                        process.process_id,
+                        stride,
+                    );
+                }
+                process.sample_thinner.next_sample(stride)
+            }
+            _ => Some(1),
+        };
+        let Some(stride_weight) = stride_weight else {
+            // Drop the sample, but keep its off-CPU samples. The running time
+            // goes to the next kept sample.
+            if let Some(off_cpu_sample_group) = off_cpu_sample_group {
+                thread
+                    .samples_with_pending_stacks
+                    .push_back(SampleWithPendingStack {
+                        timestamp: timestamp_raw,
+                        kernel_stack: None,
+                        off_cpu_sample_group: Some(off_cpu_sample_group),
+                        cpu_delta: CpuDelta::ZERO,
+                        has_on_cpu_sample: false,
+                        weight: 1,
+                        per_cpu_stuff: None,
+                        cpu: None,
+                    });
+            }
+            return;
+        };
+
         let delta = self
             .context_switch_handler
             .consume_cpu_delta(&mut thread.context_switch_data);
         let cpu_delta = self.timestamp_converter.convert_cpu_delta(delta);
+        // If the samples during the gap were lost while this thread was running,
+        // this sample makes up for them, so that the thread's sample count still
+        // matches its running time. A kept sample of a process with a lower
+        // rate already stands for several intervals.
+        let weight = match (gap_nanos, self.sampling_interval_nanos) {
+            (Some(gap_nanos), Some(sampling_interval_nanos)) => sample_weight_after_gap(
+                delta * self.timestamp_converter.raw_to_ns_factor,
+                gap_nanos,
+                sampling_interval_nanos,
+            )
+            .max(stride_weight),
+            _ => stride_weight,
+        };
 
         let per_cpu_stuff = if let Some(cpus) = &mut self.cpus {
             let cpu = cpus.get_mut(cpu_index as usize, &mut self.profile);
@@ -1422,12 +2216,171 @@ impl ProfileContext {
                 off_cpu_sample_group,
                 cpu_delta,
                 has_on_cpu_sample: true,
+                weight,
                 per_cpu_stuff,
+                cpu: Some(cpu_index),
             });
 
         self.sample_count += 1;
     }
 
+    /// A PmcInterrupt event, for `--pmc-sampling`: the counter of the profile
+    /// source overflowed on this thread. These samples show where the counted
+    /// events, e.g. branch mispredictions, happen rather than where the time
+    /// went, so they carry no CPU delta and no off-CPU samples are added.
+    pub fn handle_pmc_sample(&mut self, timestamp_raw: u64, tid: u32, source: Option<u32>) {
+        if let Some(source) = source {
+            self.pmc_sample_source_ids.insert(source);
+        }
+        let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) else {
+            return;
+        };
+        thread
+            .samples_with_pending_stacks
+            .push_back(SampleWithPendingStack {
+                timestamp: timestamp_raw,
+                kernel_stack: None,
+                off_cpu_sample_group: None,
+                cpu_delta: CpuDelta::ZERO,
+                has_on_cpu_sample: true,
+                weight: 1,
+                per_cpu_stuff: None,
+                cpu: None,
+            });
+        self.sample_count += 1;
+    }
+
+    /// Records which profile source the PMC samples came from in the profile's
+    /// metadata, with its interval in events.
+    fn add_pmc_sampling_meta_info(&mut self) {
+        for source in &self.pmc_sample_source_ids {
+            let name = match &self.pmc_sampling_source {
+                Some(name) => name.clone(),
+                None => format!("PMC source {source}"),
+            };
+            let value = match self.pmc_sample_intervals.get(source) {
+                Some(interval) => format!("{name}, every {interval} events"),
+                None => name,
+            };
+            self.profile
+                .add_extra_meta_info("Sampling", "Source", &value);
+        }
+    }
+
+    /// Adds a marker if the CPU had no SampleProf events for much longer than
+    /// the sampling interval before this one, and returns the length of the gap.
+    /// The marker goes on the CPU's track if there are per-CPU threads.
+    pub fn check_sample_gap(&mut self, timestamp_raw: u64, cpu_index: u32) -> Option<u64> {
+        let sampling_interval_nanos = self.sampling_interval_nanos?;
+        let raw_to_ns_factor = self.timestamp_converter.raw_to_ns_factor;
+        let Some(gap_start_raw) = self.sample_gaps.notify_sample(
+            cpu_index,
+            timestamp_raw,
+            raw_to_ns_factor,
+            sampling_interval_nanos,
+        )?;
+
+        let (thread_handle, cpu_name) = match &mut self.cpus {
+            Some(cpus) => {
+                let cpu = cpus.get_mut(cpu_index as usize, &mut self.profile);
+                (cpu.thread_handle, cpu.name)
+            }
+            None => {
+                let thread_handle = *self.sample_gap_thread_handle.get_or_insert_with(|| {
+                    let start_timestamp = Timestamp::from_nanos_since_reference(0);
+                    let process = self.profile.add_process("CPU", 0, start_timestamp);
+                    self.profile.add_thread(process, 0, start_timestamp, true)
+                });
+                let cpu_name = self.profile.intern_string(&format!("CPU {cpu_index}"));
+                (thread_handle, cpu_name)
+            }
+        };
+        let gap_nanos = (timestamp_raw - gap_start_raw) * raw_to_ns_factor;
+        let start = self.timestamp_converter.convert_time(gap_start_raw);
+        let end = self.timestamp_converter.convert_time(timestamp_raw);
+        self.profile.add_marker(
+            thread_handle,
+            MarkerTiming::Interval(start, end),
+            SampleGapMarker {
+                cpu_name,
+                gap_ms: gap_nanos as f64 / 1_000_000.0,
+            },
+        );
+        Some(gap_nanos)
+    }
+
+    pub fn set_regions_of_interest(&mut self, regions_of_interest: RegionsOfInterest) {
+        self.regions_of_interest = Some(regions_of_interest);
+    }
+
+    pub fn has_regions_of_interest(&self) -> bool {
+        self.regions_of_interest.is_some()
+    }
+
+    /// Adds a marker for each region of interest which this event stops.
+    pub fn handle_region_of_interest_event(&mut self, event: &RegionEvent) {
+        let Some(regions_of_interest) = &mut self.regions_of_interest else {
+            return;
+        };
+        let mut completed_regions = Vec::new();
+        regions_of_interest.handle_event(event, |region| {
+            completed_regions.push((
+                region.name.to_string(),
+                region.root_name.to_string(),
+                region.tid,
+                region.start_raw,
+                region.end_raw,
+            ));
+        });
+        for (name, root_name, tid, start_raw, end_raw) in completed_regions {
+            if !self.is_in_time_range(start_raw) && !self.is_in_time_range(end_raw) {
+                continue;
+            }
+            let Some(thread_handle) = self.thread_handle_at_time(tid, start_raw) else {
+                continue;
+            };
+            let category = self
+                .categories
+                .get(KnownCategory::RegionOfInterest, &mut self.profile);
+            let start = self.timestamp_converter.convert_time(start_raw);
+            let end = self.timestamp_converter.convert_time(end_raw);
+            let marker = RegionOfInterestMarker {
+                name: self.profile.intern_string(&name),
+                root_name: self.profile.intern_string(&root_name),
+                category,
+            };
+            self.profile
+                .add_marker(thread_handle, MarkerTiming::Interval(start, end), marker);
+        }
+    }
+
+    pub fn handle_pmc_counters(&mut self, timestamp_raw: u64, cpu_index: u32, values: &[u64]) {
+        let previous_values = self
+            .last_pmc_values_per_cpu
+            .insert(cpu_index, values.to_vec());
+        let Some(previous_values) = previous_values else {
+            return;
+        };
+        let Some(&tid) = self.last_sample_tid_per_cpu.get(&cpu_index) else {
+            return;
+        };
+        let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) else {
+            return;
+        };
+        let Some(process) = self.processes.get_at_time(thread.process_id, timestamp_raw) else {
+            return;
+        };
+
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        for (index, (value, previous_value)) in values.iter().zip(previous_values).enumerate() {
+            let counter =
+                process.get_pmc_counter(index, &self.pmc_counter_names, &mut self.profile);
+            let delta = value.wrapping_sub(previous_value);
+            self.profile
+                .add_counter_sample(counter, timestamp, delta as f64, 1);
+        }
+    }
+
     pub fn handle_virtual_alloc_free(
         &mut self,
         timestamp_raw: u64,
@@ -1437,7 +2390,7 @@ impl ProfileContext {
         region_size: u64,
         _stringified_properties: String,
     ) {
-        let Some(process) = self.processes.get_by_pid(pid) else {
+        let Some(process) = self.processes.get_at_time(pid, timestamp_raw) else {
             return;
         };
 
@@ -1465,6 +2418,7 @@ impl ProfileContext {
         &mut self,
         device_path: String,
         mut image_info: PeInfo,
+        is_kernel_image: bool,
     ) -> (LibraryHandle, KnownCategory) {
         let key = (
             device_path,
@@ -1480,13 +2434,29 @@ impl ProfileContext {
 
         let code_id = image_info.code_id();
         let debug_id = image_info.debug_id.unwrap_or_default();
+        // Drivers whose PDB we won't find get the names of their exports.
+        let driver_symbol_table =
+            if is_kernel_image && needs_export_symbols(&path, image_info.pdb_path.as_deref()) {
+                load_export_symbol_table(
+                    Path::new(&path),
+                    image_info.image_size,
+                    image_info.image_checksum,
+                )
+            } else {
+                None
+            };
         let pdb_path = image_info.pdb_path.unwrap_or_else(|| path.clone());
+        let pdb_path = find_pdb_in_package(&path, &pdb_path).unwrap_or(pdb_path);
         let path_lower = path.to_lowercase();
         let pdb_path_lower = pdb_path.to_lowercase();
         let name = extract_filename(&path).to_string();
         let pdb_name = extract_filename(&pdb_path).to_string();
+        // ReadyToRun images compiled with `crossgen2 --perfmap` have their
+        // symbols in a perf map next to them.
+        let r2r_map = try_load_r2r_map(Path::new(&path));
+        let is_r2r = r2r_map.is_some();
 
-        let lib_handle = self.profile.add_lib(LibraryInfo {
+        let lib_info = LibraryInfo {
             name,
             path,
             debug_name: pdb_name,
@@ -1494,13 +2464,22 @@ impl ProfileContext {
             debug_id,
             code_id: code_id.map(|ci| ci.to_string()),
             arch: Some(self.arch.to_owned()),
-            symbol_table: None,
-        });
+            symbol_table: r2r_map
+                .map(|map| map.into_symbol_table())
+                .or(driver_symbol_table)
+                .map(Arc::new),
+        };
+        if is_kernel_image && is_ntoskrnl(&lib_info.name) {
+            self.ntoskrnl_lib = Some(lib_info.clone());
+        }
+        let lib_handle = self.profile.add_lib(lib_info);
 
         // attempt to categorize the library based on the path
-        let known_category = if pdb_path_lower.contains(".ni.pdb") {
+        let known_category = if is_r2r || pdb_path_lower.contains(".ni.pdb") {
             KnownCategory::CoreClrR2r
-        } else if path_lower.contains("windows\\system32") || path_lower.contains("windows\\winsxs")
+        } else if path_lower.contains("windows\\system32")
+            || path_lower.contains("windows\\winsxs")
+            || is_framework_package_image(&path)
         {
             KnownCategory::System
         } else {
@@ -1524,12 +2503,21 @@ impl ProfileContext {
         }
 
         let image_size = image_info.image_size as u64;
+        let is_kernel_image = pid == 0 || image_base >= self.kernel_min;
+        let is_afd = extract_filename(&device_path).eq_ignore_ascii_case("afd.sys");
+        let is_ntoskrnl = is_ntoskrnl(extract_filename(&device_path));
         let (lib_handle, known_category) =
-            self.lib_handle_and_category_for_image(device_path, image_info);
+            self.lib_handle_and_category_for_image(device_path, image_info, is_kernel_image);
 
         let start_avma = image_base;
         let end_avma = image_base + image_size;
-        if pid == 0 || start_avma >= self.kernel_min {
+        if is_kernel_image {
+            if is_afd {
+                self.afd_address_ranges.push((start_avma, end_avma));
+            }
+            if is_ntoskrnl {
+                self.ntoskrnl_address_range = Some((start_avma, end_avma));
+            }
             self.profile
                 .add_kernel_lib_mapping(lib_handle, start_avma, end_avma, 0);
             return;
@@ -1556,6 +2544,28 @@ impl ProfileContext {
         );
     }
 
+    /// An Image/UnLoad event: the image is no longer mapped at `image_base`.
+    /// Removing the mapping at the time of the unload keeps later samples from
+    /// being attributed to this image if the address range is reused, e.g.
+    /// when the same DLL is loaded again at a different base address.
+    ///
+    /// Kernel images keep their mapping: the kernel mappings apply to the whole
+    /// profile, so removing them would lose the symbols of earlier samples.
+    pub fn handle_image_unload(&mut self, timestamp_raw: u64, pid: u32, image_base: u64) {
+        if pid == 0 || image_base >= self.kernel_min {
+            return;
+        }
+        let Some(process) = self.processes.get_by_pid(pid) else {
+            return;
+        };
+        process.regular_lib_mapping_ops.push(
+            timestamp_raw,
+            LibMappingOp::Remove(LibMappingRemove {
+                start_avma: image_base,
+            }),
+        );
+    }
+
     pub fn handle_vsync(&mut self, timestamp_raw: u64) {
         #[derive(Debug, Clone)]
         pub struct VSyncMarker;
@@ -1612,15 +2622,37 @@ impl ProfileContext {
         old_tid: u32,
         new_tid: u32,
         cpu_index: u32,
+        old_thread_state: i8,
         wait_reason: i8,
     ) {
+        if let Some(threshold) = self.profile_creation_props.scheduler_latency_threshold {
+            self.handle_scheduler_latency(timestamp_raw, old_tid, new_tid, threshold);
+        }
+        if let Some(threshold) = self.profile_creation_props.hang_threshold {
+            self.detect_hangs(timestamp_raw, old_tid, new_tid, wait_reason, threshold);
+        }
+        if self.profile_creation_props.thread_states {
+            const WR_TERMINATED: i8 = 22;
+            if wait_reason == WR_TERMINATED {
+                self.end_thread_state(old_tid, timestamp_raw);
+            } else {
+                let state = ThreadState::from_switched_out_state(old_thread_state);
+                let wait_reason = match state {
+                    ThreadState::Blocked => Some(wait_reason_name(wait_reason)),
+                    _ => None,
+                };
+                self.set_thread_state(old_tid, state, wait_reason, timestamp_raw);
+            }
+            self.set_thread_state(new_tid, ThreadState::Running, None, timestamp_raw);
+        }
+
         // CSwitch events may or may not have stacks.
         // If they have stacks, the stack will be the stack of new_tid.
         // In other words, if a thread sleeps, the sleeping stack is delivered to us at the end of the sleep,
         // once the CPU starts executing the switched-to thread.
         // (That's different to e.g. Linux with sched_switch samples, which deliver the stack at the start of the sleep, i.e. just before the switch-out.)
 
-        if let Some(old_thread) = self.threads.get_by_tid(old_tid) {
+        if let Some(old_thread) = self.threads.get_at_time(old_tid, timestamp_raw) {
             self.context_switch_handler
                 .handle_switch_out(timestamp_raw, &mut old_thread.context_switch_data);
 
@@ -1645,7 +2677,7 @@ impl ProfileContext {
             }
         }
 
-        if let Some(new_thread) = self.threads.get_by_tid(new_tid) {
+        if let Some(new_thread) = self.threads.get_at_time(new_tid, timestamp_raw) {
             let off_cpu_sample_group = self
                 .context_switch_handler
                 .handle_switch_in(timestamp_raw, &mut new_thread.context_switch_data);
@@ -1653,7 +2685,17 @@ impl ProfileContext {
                 .context_switch_handler
                 .consume_cpu_delta(&mut new_thread.context_switch_data);
             let cpu_delta = self.timestamp_converter.convert_cpu_delta(cpu_delta_raw);
-            if let Some(off_cpu_sample_group) = off_cpu_sample_group {
+            // Without CSwitch stack walks, no stack will arrive for the time
+            // the thread was blocked, so there can't be samples for it.
+            // With PMC sampling, the samples count events rather than time, so
+            // off-CPU samples would skew them.
+            let has_cswitch_stacks = self
+                .profile_creation_props
+                .stack_walk_events
+                .contains(&StackWalkEvent::CSwitch)
+                && self.pmc_sampling_source.is_none();
+            if let Some(off_cpu_sample_group) = off_cpu_sample_group.filter(|_| has_cswitch_stacks)
+            {
                 new_thread
                     .samples_with_pending_stacks
                     .push_back(SampleWithPendingStack {
@@ -1662,7 +2704,9 @@ impl ProfileContext {
                         off_cpu_sample_group: Some(off_cpu_sample_group),
                         cpu_delta,
                         has_on_cpu_sample: false,
+                        weight: 1,
                         per_cpu_stuff: None,
+                        cpu: None,
                     });
             }
             if let Some(cpus) = &mut self.cpus {
@@ -1717,6 +2761,311 @@ impl ProfileContext {
         }
     }
 
+    /// A ReadyThread event: The thread was unblocked and waits for a CPU.
+    /// `waker_pid` and `waker_tid` are from the event header, i.e. they're the
+    /// thread which unblocked it.
+    pub fn handle_ready_thread(
+        &mut self,
+        timestamp_raw: u64,
+        tid: u32,
+        waker_pid: u32,
+        waker_tid: u32,
+    ) {
+        if self.profile_creation_props.wakeups {
+            self.add_wakeup_marker(timestamp_raw, tid, waker_pid, waker_tid);
+        }
+        if self
+            .profile_creation_props
+            .scheduler_latency_threshold
+            .is_some()
+        {
+            if let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) {
+                thread.scheduler_latency.notify_ready(timestamp_raw);
+            }
+        }
+        if !self.profile_creation_props.thread_states {
+            return;
+        }
+        let is_running = self
+            .threads
+            .get_at_time(tid, timestamp_raw)
+            .is_some_and(|thread| thread.thread_state.state() == Some(ThreadState::Running));
+        if !is_running {
+            self.set_thread_state(tid, ThreadState::Ready, None, timestamp_raw);
+        }
+    }
+
+    fn add_wakeup_marker(&mut self, timestamp_raw: u64, tid: u32, waker_pid: u32, waker_tid: u32) {
+        let (waker_pid, waker_name) = if waker_tid == 0 {
+            (0, IDLE_WAKER_NAME.to_string())
+        } else {
+            let waker_thread = self.threads.get_at_time(waker_tid, timestamp_raw);
+            let waker_thread_name = waker_thread.as_ref().and_then(|t| t.name.clone());
+            let waker_pid = waker_thread.map_or(waker_pid, |t| t.process_id);
+            let waker_name = waker_thread_name.or_else(|| {
+                let process = self.processes.get_at_time(waker_pid, timestamp_raw)?;
+                Some(process.name.clone())
+            });
+            (
+                waker_pid,
+                waker_name.unwrap_or_else(|| "Unknown".to_string()),
+            )
+        };
+        let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) else {
+            return;
+        };
+        let thread_handle = thread.handle;
+        let category = self
+            .categories
+            .get(KnownCategory::Scheduling, &mut self.profile);
+        let waker_name = self.profile.intern_string(&waker_name);
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        self.profile.add_marker(
+            thread_handle,
+            MarkerTiming::Instant(timestamp),
+            WakeupMarker {
+                waker_pid,
+                waker_tid,
+                waker_name,
+                category,
+            },
+        );
+    }
+
+    fn set_thread_state(
+        &mut self,
+        tid: u32,
+        state: ThreadState,
+        wait_reason: Option<&'static str>,
+        timestamp_raw: u64,
+    ) {
+        self.last_thread_state_timestamp_raw = timestamp_raw;
+        let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) else {
+            return;
+        };
+        let thread_handle = thread.handle;
+        if let Some(interval) = thread
+            .thread_state
+            .transition(state, wait_reason, timestamp_raw)
+        {
+            self.add_thread_state_marker(thread_handle, interval);
+        }
+    }
+
+    fn end_thread_state(&mut self, tid: u32, timestamp_raw: u64) {
+        self.last_thread_state_timestamp_raw = timestamp_raw;
+        let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) else {
+            return;
+        };
+        let thread_handle = thread.handle;
+        if let Some(interval) = thread.thread_state.finish(timestamp_raw) {
+            self.add_thread_state_marker(thread_handle, interval);
+        }
+    }
+
+    fn add_thread_state_marker(
+        &mut self,
+        thread_handle: ThreadHandle,
+        interval: ThreadStateInterval,
+    ) {
+        let start = self.timestamp_converter.convert_time(interval.start_raw);
+        let end = self.timestamp_converter.convert_time(interval.end_raw);
+        let category = self
+            .categories
+            .get(KnownCategory::Scheduling, &mut self.profile);
+        let wait_reason = self
+            .profile
+            .intern_string(interval.wait_reason.unwrap_or(""));
+        self.profile.add_marker(
+            thread_handle,
+            MarkerTiming::Interval(start, end),
+            ThreadStateMarker {
+                state: interval.state,
+                wait_reason,
+                category,
+            },
+        );
+    }
+
+    /// Measures how long `new_tid` waited for a CPU since its ReadyThread event,
+    /// for the process's scheduler latency counter, and adds a marker if that's
+    /// longer than `threshold`.
+    fn handle_scheduler_latency(
+        &mut self,
+        timestamp_raw: u64,
+        old_tid: u32,
+        new_tid: u32,
+        threshold: std::time::Duration,
+    ) {
+        if let Some(old_thread) = self.threads.get_at_time(old_tid, timestamp_raw) {
+            old_thread.scheduler_latency.notify_switch_out();
+        }
+        let Some(new_thread) = self.threads.get_at_time(new_tid, timestamp_raw) else {
+            return;
+        };
+        let Some(ready_raw) = new_thread.scheduler_latency.notify_switch_in() else {
+            return;
+        };
+        let latency_nanos =
+            timestamp_raw.saturating_sub(ready_raw) * self.timestamp_converter.raw_to_ns_factor;
+        new_thread.scheduler_latency.add_latency(latency_nanos);
+        let thread_handle = new_thread.handle;
+        let pid = new_thread.process_id;
+
+        let latency_ms = latency_nanos as f64 / 1_000_000.0;
+        let end = self.timestamp_converter.convert_time(timestamp_raw);
+        if let Some(process) = self.processes.get_by_pid_and_timestamp(pid, timestamp_raw) {
+            let counter = process.get_scheduler_latency_counter(&mut self.profile);
+            self.profile.add_counter_sample(counter, end, latency_ms, 1);
+        }
+        if u128::from(latency_nanos) >= threshold.as_nanos() {
+            let start = self.timestamp_converter.convert_time(ready_raw);
+            let category = self
+                .categories
+                .get(KnownCategory::Scheduling, &mut self.profile);
+            self.profile.add_marker(
+                thread_handle,
+                MarkerTiming::Interval(start, end),
+                SchedulerLatencyMarker {
+                    latency_ms,
+                    category,
+                },
+            );
+        }
+    }
+
+    /// Finds the gaps between the message waits of `old_tid`, see [`HangDetector`].
+    fn detect_hangs(
+        &mut self,
+        timestamp_raw: u64,
+        old_tid: u32,
+        new_tid: u32,
+        wait_reason: i8,
+        threshold: std::time::Duration,
+    ) {
+        self.last_cswitch_timestamp_raw = timestamp_raw;
+        if let Some(old_thread) = self.threads.get_at_time(old_tid, timestamp_raw) {
+            if let Some(start_raw) = old_thread.hang_detector.notify_switch_out(wait_reason) {
+                let thread_handle = old_thread.handle;
+                self.add_hang_if_long_enough(thread_handle, start_raw, timestamp_raw, threshold);
+            }
+        }
+        if let Some(new_thread) = self.threads.get_at_time(new_tid, timestamp_raw) {
+            new_thread.hang_detector.notify_switch_in(timestamp_raw);
+        }
+    }
+
+    /// Win32k's MessageCheckDelay event, which is logged on a thread which
+    /// checked its messages `delay_ms` milliseconds after it last did.
+    pub fn handle_message_check_delay(&mut self, timestamp_raw: u64, tid: u32, delay_ms: u64) {
+        let Some(threshold) = self.profile_creation_props.hang_threshold else {
+            return;
+        };
+        let Some(thread) = self.threads.get_at_time(tid, timestamp_raw) else {
+            return;
+        };
+        let thread_handle = thread.handle;
+        let delay_raw = delay_ms * 1_000_000 / self.timestamp_converter.raw_to_ns_factor;
+        let start_raw = timestamp_raw.saturating_sub(delay_raw);
+        self.add_hang_if_long_enough(thread_handle, start_raw, timestamp_raw, threshold);
+    }
+
+    fn add_hang_if_long_enough(
+        &mut self,
+        thread_handle: ThreadHandle,
+        start_raw: u64,
+        end_raw: u64,
+        threshold: std::time::Duration,
+    ) {
+        let duration_nanos =
+            end_raw.saturating_sub(start_raw) * self.timestamp_converter.raw_to_ns_factor;
+        if u128::from(duration_nanos) >= threshold.as_nanos() {
+            self.hangs
+                .entry(thread_handle)
+                .or_default()
+                .push(Hang { start_raw, end_raw });
+        }
+    }
+
+    /// Adds the markers for the hangs, including the ones which are still going
+    /// on at the end of the profile. Must be called after the samples have been
+    /// added to the profile, because each marker gets the most common stack of
+    /// its hang.
+    fn add_hang_markers(&mut self, threshold: std::time::Duration) {
+        let end_raw = self.last_cswitch_timestamp_raw;
+        let open_hangs: Vec<_> = self
+            .threads
+            .iter_mut()
+            .filter_map(|thread| Some((thread.handle, thread.hang_detector.finish()?)))
+            .collect();
+        for (thread_handle, start_raw) in open_hangs {
+            self.add_hang_if_long_enough(thread_handle, start_raw, end_raw, threshold);
+        }
+
+        let category = self.categories.get(KnownCategory::Hang, &mut self.profile);
+        for (thread_handle, hangs) in std::mem::take(&mut self.hangs) {
+            for hang in merge_overlapping_hangs(hangs) {
+                let start = self.timestamp_converter.convert_time(hang.start_raw);
+                let end = self.timestamp_converter.convert_time(hang.end_raw);
+                let duration_nanos =
+                    (hang.end_raw - hang.start_raw) * self.timestamp_converter.raw_to_ns_factor;
+                let marker = self.profile.add_marker(
+                    thread_handle,
+                    MarkerTiming::Interval(start, end),
+                    HangMarker {
+                        duration_ms: duration_nanos as f64 / 1_000_000.0,
+                        category,
+                    },
+                );
+                self.profile
+                    .set_marker_stack_from_samples(thread_handle, marker, start, end);
+            }
+        }
+    }
+
+    /// Adds the distribution of the scheduler latencies of all threads, and of the
+    /// threads with the highest 99th percentiles, to the profile's metadata.
+    fn add_scheduler_latency_meta_info(&mut self) {
+        const WORST_THREAD_COUNT: usize = 5;
+        let mut all_latencies = Vec::new();
+        let mut thread_distributions = Vec::new();
+        for thread in self.threads.iter_mut() {
+            let latencies = thread.scheduler_latency.latencies_nanos();
+            let Some(distribution) = LatencyDistribution::from_latencies(latencies) else {
+                continue;
+            };
+            all_latencies.extend_from_slice(latencies);
+            let name = thread.name.as_deref().unwrap_or("Thread");
+            let label = format!("{name} ({}:{})", thread.process_id, thread.thread_id);
+            thread_distributions.push((label, distribution));
+        }
+        let Some(overall) = LatencyDistribution::from_latencies(&all_latencies) else {
+            return;
+        };
+        self.profile
+            .add_extra_meta_info("Scheduler latency", "All threads", &overall.summary());
+        thread_distributions.sort_by_key(|(_, distribution)| {
+            std::cmp::Reverse((distribution.p99_nanos, distribution.max_nanos))
+        });
+        for (label, distribution) in thread_distributions.iter().take(WORST_THREAD_COUNT) {
+            self.profile
+                .add_extra_meta_info("Scheduler latency", label, &distribution.summary());
+        }
+    }
+
+    /// Ends the thread state intervals which are still open at the end of the profile.
+    fn add_final_thread_state_markers(&mut self) {
+        let end_raw = self.last_thread_state_timestamp_raw;
+        let intervals: Vec<_> = self
+            .threads
+            .iter_mut()
+            .filter_map(|thread| Some((thread.handle, thread.thread_state.finish(end_raw)?)))
+            .collect();
+        for (thread_handle, interval) in intervals {
+            self.add_thread_state_marker(thread_handle, interval);
+        }
+    }
+
     pub fn handle_js_source_load(
         &mut self,
         timestamp_raw: u64,
@@ -1777,46 +3126,135 @@ impl ProfileContext {
         let lib = &mut self.js_jit_lib;
         let info = LibMappingInfo::new_jit_function(lib.lib_handle(), category, js_frame);
 
-        let name_handle = self.profile.intern_string(&method_name);
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let marker = JitFunctionAddMarker {
+            name: self.profile.intern_string(&method_name),
+            tier: self
+                .profile
+                .intern_string(JitCategoryManager::jit_tier(&method_name)),
+            module: self.profile.intern_string(lib.name()),
+            code_size: method_size,
+        };
+        self.profile.add_marker(
+            process.main_thread_handle,
+            MarkerTiming::Instant(timestamp),
+            marker,
+        );
+
+        process.add_jit_function(
+            timestamp_raw,
+            lib,
+            method_name,
+            method_start_address,
+            method_size,
+            info,
+        );
+    }
+
+    pub fn handle_coreclr_method_load(
+        &mut self,
+        timestamp_raw: u64,
+        pid: u32,
+        method_name: String,
+        method_start_address: u64,
+        method_size: u32,
+    ) {
+        let Some(process) = self.processes.get_by_pid_and_timestamp(pid, timestamp_raw) else {
+            return;
+        };
+
+        let lib = &mut self.coreclr_jit_lib;
+        let info = LibMappingInfo::new_jit_function(lib.lib_handle(), lib.default_category(), None);
+
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let marker = JitFunctionAddMarker {
+            name: self.profile.intern_string(&method_name),
+            tier: self
+                .profile
+                .intern_string(JitCategoryManager::jit_tier(&method_name)),
+            module: self.profile.intern_string(lib.name()),
+            code_size: method_size,
+        };
         self.profile.add_marker(
             process.main_thread_handle,
             MarkerTiming::Instant(timestamp),
-            JitFunctionAddMarker(name_handle),
+            marker,
+        );
+
+        process.add_jit_function(
+            timestamp_raw,
+            lib,
+            method_name,
+            method_start_address,
+            method_size,
+            info,
+        );
+    }
+
+    /// A MethodUnload event: The JIT code of a JS function was freed.
+    pub fn handle_js_method_unload(
+        &mut self,
+        timestamp_raw: u64,
+        pid: u32,
+        method_name: &str,
+        method_size: u32,
+    ) {
+        let tier = JitCategoryManager::jit_tier(method_name);
+        let module = self.js_jit_lib.name().to_owned();
+        self.add_jit_function_unload_marker(
+            timestamp_raw,
+            pid,
+            method_name,
+            tier,
+            &module,
+            method_size,
         );
+    }
 
-        process.add_jit_function(
+    /// A MethodUnloadVerbose event: The JIT code of a .NET method was freed, e.g.
+    /// because the method was rejitted at a higher tier.
+    pub fn handle_coreclr_method_unload(
+        &mut self,
+        timestamp_raw: u64,
+        pid: u32,
+        method_name: &str,
+        method_size: u32,
+    ) {
+        let tier = JitCategoryManager::jit_tier(method_name);
+        let module = self.coreclr_jit_lib.name().to_owned();
+        self.add_jit_function_unload_marker(
             timestamp_raw,
-            lib,
+            pid,
             method_name,
-            method_start_address,
+            tier,
+            &module,
             method_size,
-            info,
         );
     }
 
-    pub fn handle_coreclr_method_load(
+    fn add_jit_function_unload_marker(
         &mut self,
         timestamp_raw: u64,
         pid: u32,
-        method_name: String,
-        method_start_address: u64,
+        method_name: &str,
+        tier: &str,
+        module: &str,
         method_size: u32,
     ) {
         let Some(process) = self.processes.get_by_pid_and_timestamp(pid, timestamp_raw) else {
             return;
         };
-
-        let lib = &mut self.coreclr_jit_lib;
-        let info = LibMappingInfo::new_jit_function(lib.lib_handle(), lib.default_category(), None);
-
-        process.add_jit_function(
-            timestamp_raw,
-            lib,
-            method_name,
-            method_start_address,
-            method_size,
-            info,
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let marker = JitFunctionUnloadMarker {
+            name: self.profile.intern_string(method_name),
+            tier: self.profile.intern_string(tier),
+            module: self.profile.intern_string(module),
+            code_size: method_size,
+        };
+        self.profile.add_marker(
+            process.main_thread_handle,
+            MarkerTiming::Instant(timestamp),
+            marker,
         );
     }
 
@@ -1932,10 +3370,10 @@ impl ProfileContext {
             _ => panic!("Unexpected marker phase {phase}"),
         };
 
-        if marker_name == "UserTiming" {
+        let marker_handle = if marker_name == "UserTiming" {
             let name = self.profile.intern_string(&maybe_user_timing_name.unwrap());
             self.profile
-                .add_marker(thread_handle, timing, UserTimingMarker(name));
+                .add_marker(thread_handle, timing, UserTimingMarker(name))
         } else if marker_name == "SimpleMarker" || marker_name == "Text" || marker_name == "tracing"
         {
             let marker_name = self
@@ -1946,7 +3384,7 @@ impl ProfileContext {
                 thread_handle,
                 timing,
                 FreeformMarker(marker_name, description, CategoryHandle::OTHER),
-            );
+            )
         } else {
             let marker_name = self.profile.intern_string(marker_name);
             let description = self.profile.intern_string(&text);
@@ -1954,8 +3392,10 @@ impl ProfileContext {
                 thread_handle,
                 timing,
                 FreeformMarker(marker_name, description, CategoryHandle::OTHER),
-            );
-        }
+            )
+        };
+        self.markers_with_pending_stacks
+            .insert(tid, (timestamp_raw, thread_handle, marker_handle));
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -1981,10 +3421,10 @@ impl ProfileContext {
             _ => MarkerTiming::Instant(timestamp),
         };
         let keyword = KeywordNames::from_bits(keyword_bitfield).unwrap();
-        if keyword == KeywordNames::blink_user_timing {
+        let marker_handle = if keyword == KeywordNames::blink_user_timing {
             let name = self.profile.intern_string(marker_name);
             self.profile
-                .add_marker(thread_handle, timing, UserTimingMarker(name));
+                .add_marker(thread_handle, timing, UserTimingMarker(name))
         } else {
             let marker_name = self.profile.intern_string(marker_name);
             let description = self.profile.intern_string(&text);
@@ -1992,18 +3432,112 @@ impl ProfileContext {
                 thread_handle,
                 timing,
                 FreeformMarker(marker_name, description, CategoryHandle::OTHER),
-            );
+            )
+        };
+        self.markers_with_pending_stacks
+            .insert(tid, (timestamp_raw, thread_handle, marker_handle));
+    }
+
+    /// Adds a marker for a priority change or a job object event. The marker goes on
+    /// the affected thread if we know it, otherwise on the main thread of the
+    /// affected process.
+    pub fn handle_process_throttling_event(
+        &mut self,
+        timestamp_raw: u64,
+        pid: u32,
+        tid: Option<u32>,
+        marker_name: &str,
+        stringified_properties: String,
+    ) {
+        let thread_handle = match tid.and_then(|tid| self.thread_handle_at_time(tid, timestamp_raw))
+        {
+            Some(thread_handle) => thread_handle,
+            None => match self.processes.get_by_pid_and_timestamp(pid, timestamp_raw) {
+                Some(process) => process.main_thread_handle,
+                None => return,
+            },
+        };
+
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let category = self
+            .categories
+            .get(KnownCategory::Scheduling, &mut self.profile);
+        let marker_name = self.profile.intern_string(marker_name);
+        let description = self.profile.intern_string(&stringified_properties);
+        self.profile.add_marker(
+            thread_handle,
+            MarkerTiming::Instant(timestamp),
+            FreeformMarker(marker_name, description, category),
+        );
+    }
+
+    /// A minifilter callback which has completed. Usually this is an antivirus
+    /// scanner inspecting a file before it lets the I/O proceed.
+    pub fn handle_minifilter_completion(
+        &mut self,
+        start_timestamp_raw: u64,
+        end_timestamp_raw: u64,
+        tid: u32,
+        stringified_properties: String,
+    ) {
+        let duration_nanos = end_timestamp_raw.saturating_sub(start_timestamp_raw)
+            * self.timestamp_converter.raw_to_ns_factor;
+        if duration_nanos < MIN_MINIFILTER_MARKER_DURATION_NANOS {
+            return;
         }
+        let Some(thread_handle) = self.thread_handle_at_time(tid, end_timestamp_raw) else {
+            return;
+        };
+
+        let start = self.timestamp_converter.convert_time(start_timestamp_raw);
+        let end = self.timestamp_converter.convert_time(end_timestamp_raw);
+        let category = self
+            .categories
+            .get(KnownCategory::Antivirus, &mut self.profile);
+        let marker_name = self.profile.intern_string("Minifilter");
+        let description = self.profile.intern_string(&stringified_properties);
+        self.profile.add_marker(
+            thread_handle,
+            MarkerTiming::Interval(start, end),
+            FreeformMarker(marker_name, description, category),
+        );
     }
 
-    pub fn handle_unknown_event(
+    /// An event from the Windows Defender provider which isn't part of a
+    /// start / stop pair.
+    pub fn handle_defender_event(
         &mut self,
         timestamp_raw: u64,
         tid: u32,
         task_and_op: &str,
         stringified_properties: String,
     ) {
-        if !self.profile_creation_props.unknown_event_markers {
+        let Some(thread_handle) = self.thread_handle_at_time(tid, timestamp_raw) else {
+            return;
+        };
+
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let category = self
+            .categories
+            .get(KnownCategory::Antivirus, &mut self.profile);
+        let marker_name = self.profile.intern_string(task_and_op);
+        let description = self.profile.intern_string(&stringified_properties);
+        self.profile.add_marker(
+            thread_handle,
+            MarkerTiming::Instant(timestamp),
+            FreeformMarker(marker_name, description, category),
+        );
+    }
+
+    pub fn handle_unknown_event(
+        &mut self,
+        timestamp_raw: u64,
+        tid: u32,
+        marker_name: &str,
+        stringified_properties: String,
+        is_requested_provider: bool,
+    ) {
+        if !self.profile_creation_props.unknown_event_markers && !is_requested_provider {
             return;
         }
 
@@ -2017,16 +3551,63 @@ impl ProfileContext {
         let category = self
             .categories
             .get(KnownCategory::Unknown, &mut self.profile);
-        let marker_name = self.profile.intern_string(task_and_op);
+        let marker_name = self.profile.intern_string(marker_name);
         let description = self.profile.intern_string(&stringified_properties);
-        self.profile.add_marker(
+        let marker_handle = self.profile.add_marker(
             thread_handle,
             timing,
             FreeformMarker(marker_name, description, category),
         );
+        self.markers_with_pending_stacks
+            .insert(tid, (timestamp_raw, thread_handle, marker_handle));
         //println!("unhandled {}", s.name())
     }
 
+    /// A file create or registry key open, which gets its own marker type
+    /// with the decoded arguments, instead of the stringified properties.
+    pub fn handle_syscall_event(
+        &mut self,
+        timestamp_raw: u64,
+        tid: u32,
+        kind: SyscallKind,
+        args: SyscallArgs,
+        is_requested_provider: bool,
+    ) {
+        if !self.profile_creation_props.unknown_event_markers && !is_requested_provider {
+            return;
+        }
+
+        let Some(thread_handle) = self.thread_handle_at_time(tid, timestamp_raw) else {
+            return;
+        };
+
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let timing = match args
+            .start_timestamp_raw
+            .filter(|start_timestamp_raw| *start_timestamp_raw <= timestamp_raw)
+        {
+            Some(start_timestamp_raw) => MarkerTiming::Interval(
+                self.timestamp_converter.convert_time(start_timestamp_raw),
+                timestamp,
+            ),
+            None => MarkerTiming::Instant(timestamp),
+        };
+        let category = self
+            .categories
+            .get(KnownCategory::Unknown, &mut self.profile);
+        let marker = SyscallMarker {
+            name: self.profile.intern_string(kind.marker_name()),
+            path: self.profile.intern_string(&args.path),
+            access: self.profile.intern_string(&args.access),
+            disposition: self.profile.intern_string(&args.disposition),
+            status: self.profile.intern_string(&args.status),
+            category,
+        };
+        let marker_handle = self.profile.add_marker(thread_handle, timing, marker);
+        self.markers_with_pending_stacks
+            .insert(tid, (timestamp_raw, thread_handle, marker_handle));
+    }
+
     pub fn is_in_time_range(&self, ts_raw: u64) -> bool {
         let Some((tstart, tstop)) = self.time_range else {
             return true;
@@ -2040,6 +3621,181 @@ impl ProfileContext {
         self.profile.set_os_name(os_name);
     }
 
+    /// Names the kind of core of each CPU in the profile metadata, see
+    /// [`core_type_names`].
+    pub fn set_cpu_core_types(&mut self, cpu_classes: &[(u32, u32)]) {
+        for (cpu, core_type) in core_type_names(cpu_classes) {
+            self.profile.set_cpu_core_type(cpu, core_type);
+        }
+    }
+
+    /// Called between the two passes of a two-pass import. The first pass has
+    /// seen all processes, threads, images and JIT functions, so from now on
+    /// samples can be added to the profile as they come in, instead of being
+    /// kept in memory until `finish`.
+    pub fn begin_sample_pass(&mut self) {
+        self.js_jit_lib.set_symbol_table(&mut self.profile);
+        self.coreclr_jit_lib.set_symbol_table(&mut self.profile);
+
+        let user_category = self.categories.get(KnownCategory::User, &mut self.profile);
+        let kernel_category = self
+            .categories
+            .get(KnownCategory::Kernel, &mut self.profile);
+        for process in self.processes.iter_mut() {
+            let regular_lib_mapping_ops = std::mem::take(&mut process.regular_lib_mapping_ops);
+            let jit_lib_mapping_ops = std::mem::take(&mut process.jit_lib_mapping_ops);
+            let jitdump_lib_mapping_op_queues = if !jit_lib_mapping_ops.is_empty() {
+                vec![jit_lib_mapping_ops]
+            } else {
+                Vec::new()
+            };
+            process.sample_flusher = Some(ProcessSampleFlusher::new(
+                &mut self.profile,
+                regular_lib_mapping_ops,
+                jitdump_lib_mapping_op_queues,
+                None,
+                None,
+                user_category.into(),
+                kernel_category.into(),
+                self.address_classifier.ptr_auth_stripper,
+            ));
+        }
+        self.processes.set_all_known();
+        self.threads.set_all_known();
+
+        // The stacks which CoreCLR attached to its markers during the metadata
+        // pass can't be flushed in timestamp order with the samples, and the
+        // stack walk events for the still pending markers only come in the
+        // sample pass. Drop them, and say so.
+        let mut dropped_marker_stack_count = self.markers_with_pending_stacks.len();
+        for process in self.processes.iter_mut() {
+            let unresolved_samples = std::mem::take(&mut process.unresolved_samples);
+            dropped_marker_stack_count += unresolved_samples.len();
+        }
+        if dropped_marker_stack_count > 0 {
+            eprintln!(
+                "Warning: {dropped_marker_stack_count} marker stacks were dropped by the two-pass import. Import without --two-pass to keep them."
+            );
+        }
+        self.unresolved_stacks =
+            UnresolvedStacks::with_max_depth(self.profile_creation_props.max_stack_depth);
+        self.markers_with_pending_stacks.clear();
+        self.pending_thread_names.clear();
+        self.is_sample_pass = true;
+    }
+
+    /// During the sample pass of a two-pass import, adds the samples which
+    /// have been collected so far to the profile, and starts over with an
+    /// empty stack table.
+    pub fn flush_streamed_samples(&mut self) {
+        if !self.is_sample_pass {
+            return;
+        }
+        let mut stack_frame_scratch_buf = Vec::new();
+        for process in self.processes.iter_mut() {
+            let Some(sample_flusher) = process.sample_flusher.as_mut() else {
+                continue;
+            };
+            sample_flusher.flush_samples(
+                &mut self.profile,
+                std::mem::take(&mut process.unresolved_samples),
+                &mut stack_frame_scratch_buf,
+                &self.unresolved_stacks,
+            );
+        }
+        self.unresolved_stacks =
+            UnresolvedStacks::with_max_depth(self.profile_creation_props.max_stack_depth);
+    }
+
+    /// Gives the threads of the System process the category of the kernel
+    /// component their start routine belongs to, e.g. "Kernel Memory Manager"
+    /// for a thread which was started at MiZeroPageThread. The start routines
+    /// are looked up in the symbols of ntoskrnl.exe, if they're available
+    /// locally; threads which were started in drivers keep the "Kernel Thread"
+    /// category.
+    fn categorize_kernel_threads(&mut self) {
+        let (Some(lib), Some((start_avma, end_avma))) =
+            (&self.ntoskrnl_lib, self.ntoskrnl_address_range)
+        else {
+            return;
+        };
+        let rvas_by_tid: HashMap<u32, u32> = self
+            .kernel_thread_start_addresses
+            .iter()
+            .filter(|(_, address)| (start_avma..end_avma).contains(*address))
+            .map(|(tid, address)| (*tid, (address - start_avma) as u32))
+            .collect();
+        if rvas_by_tid.is_empty() {
+            return;
+        }
+        let mut rvas: Vec<u32> = rvas_by_tid.values().copied().collect();
+        rvas.sort_unstable();
+        rvas.dedup();
+        let routine_names = lookup_function_names(lib, &rvas);
+        let categories_by_tid: HashMap<u32, KnownCategory> = rvas_by_tid
+            .into_iter()
+            .filter_map(|(tid, rva)| {
+                let routine_name = routine_names.get(&rva)?;
+                Some((tid, kernel_thread_category(routine_name)))
+            })
+            .filter(|(_, category)| *category != KnownCategory::KernelThread)
+            .collect();
+        if categories_by_tid.is_empty() {
+            return;
+        }
+        // Only the thread label frames have a name and no library.
+        self.profile.categorize_frames(|lib_name, function_name| {
+            if lib_name.is_some() {
+                return None;
+            }
+            let tid = kernel_thread_tid_from_label(function_name?)?;
+            let (name, color) = KnownCategories::name_and_color(*categories_by_tid.get(&tid)?);
+            Some((name.to_string(), color))
+        });
+    }
+
+    /// Adds a marker covering the lifetime of each process and of each
+    /// non-main thread, so that they show up in the marker chart and table.
+    fn add_lifetime_markers(&mut self) {
+        let empty_string = self.profile.intern_string("");
+        for process in self.processes.iter() {
+            let exit_code = match process.exit_code {
+                Some(exit_code) => self.profile.intern_string(&format_exit_code(exit_code)),
+                None => empty_string,
+            };
+            let marker = ProcessLifetimeMarker {
+                name: self.profile.intern_string(&process.name),
+                pid: process.process_id,
+                parent_pid: process.parent_id,
+                command_line: self.profile.intern_string(&process.command_line),
+                exit_code,
+            };
+            self.profile.add_marker(
+                process.main_thread_handle,
+                process.lifetime.marker_timing(),
+                marker,
+            );
+        }
+        for thread in self.threads.iter() {
+            if thread.is_main_thread {
+                continue;
+            }
+            let name = match &thread.name {
+                Some(name) => self.profile.intern_string(name),
+                None => self
+                    .profile
+                    .intern_string(&format!("Thread {}", thread.thread_id)),
+            };
+            let marker = ThreadLifetimeMarker {
+                name,
+                pid: thread.process_id,
+                tid: thread.thread_id,
+            };
+            self.profile
+                .add_marker(thread.handle, thread.lifetime.marker_timing(), marker);
+        }
+    }
+
     pub fn finish(mut self) -> Profile {
         // Push queued samples into the profile.
         // We queue them so that we can get symbolicated JIT function names. To get symbolicated JIT function names,
@@ -2048,11 +3804,37 @@ impl ProfileContext {
         // (This is a rather weak justification. The better justification is that this is consistent with what
         // samply does on Linux and macOS, where the queued samples also want to respect JIT function names from
         // a /tmp/perf-1234.map file, and this file may not exist until the profiled process finishes.)
+        self.flush_streamed_samples();
         let mut stack_frame_scratch_buf = Vec::new();
         self.js_jit_lib
             .finish_and_set_symbol_table(&mut self.profile);
         self.coreclr_jit_lib
             .finish_and_set_symbol_table(&mut self.profile);
+        self.add_lifetime_markers();
+        self.add_final_thread_state_markers();
+        if self
+            .profile_creation_props
+            .scheduler_latency_threshold
+            .is_some()
+        {
+            self.add_scheduler_latency_meta_info();
+        }
+        self.add_sampling_interval_change_meta_info();
+        self.add_pmc_sampling_meta_info();
+        let mut process_tree = ProcessTree::default();
+        for process in self.processes.iter() {
+            process_tree.add_process(
+                process.process_id,
+                Some(process.parent_id),
+                process.handle,
+                &process.name,
+            );
+        }
+        for process in self.processes.iter() {
+            process
+                .thread_counts
+                .add_counter(&mut self.profile, process.handle);
+        }
         let process_sample_datas = self.processes.finish();
 
         let user_category = self.categories.get(KnownCategory::User, &mut self.profile);
@@ -2065,10 +3847,18 @@ impl ProfileContext {
                 &mut self.profile,
                 user_category.into(),
                 kernel_category.into(),
+                self.address_classifier.ptr_auth_stripper,
                 &mut stack_frame_scratch_buf,
                 &self.unresolved_stacks,
             )
         }
+        if self.profile_creation_props.process_group_cpu {
+            process_tree.add_process_group_counters(&mut self.profile);
+        }
+        if let Some(threshold) = self.profile_creation_props.hang_threshold {
+            self.add_hang_markers(threshold);
+        }
+        self.categorize_kernel_threads();
 
         log::info!(
             "{} events, {} samples, {} stack-samples",
@@ -2077,6 +3867,19 @@ impl ProfileContext {
             self.stack_sample_count
         );
 
+        if self.profile_creation_props.remove_collector_frames {
+            remove_collector_frames(&mut self.profile);
+        }
+        if self.profile_creation_props.runtime_categories {
+            categorize_profile_by_runtime(&mut self.profile);
+        }
+        if let Some(rules) = &self.profile_creation_props.namespace_category_rules {
+            categorize_profile_by_namespace(&mut self.profile, rules);
+        }
+        if let Some(max_profile_size) = self.profile_creation_props.max_profile_size {
+            shrink_profile_to_size_budget(&mut self.profile, max_profile_size);
+        }
+
         self.profile
     }
 }
@@ -2208,8 +4011,19 @@ fn to_stack_frames(
     frames
 }
 
-pub fn make_thread_label_frame(
+/// Process exit codes on Windows are often NTSTATUS values, which are easier
+/// to recognize in hex.
+fn format_exit_code(exit_code: u32) -> String {
+    if exit_code > 0xffff {
+        format!("0x{exit_code:08X}")
+    } else {
+        exit_code.to_string()
+    }
+}
+
+fn make_thread_label_frame(
     profile: &mut Profile,
+    categories: &mut KnownCategories,
     name: Option<&str>,
     pid: u32,
     tid: u32,
@@ -2219,13 +4033,101 @@ pub fn make_thread_label_frame(
         None => format!("Thread {tid} (pid: {pid}, tid: {tid})"),
     };
     let thread_label = profile.intern_string(&s);
+    // The threads of the System process get a more specific category once
+    // their start routine is known, in `categorize_kernel_threads`.
+    let category = if pid == SYSTEM_PROCESS_PID {
+        categories.get(KnownCategory::KernelThread, profile)
+    } else {
+        CategoryHandle::OTHER
+    };
     FrameInfo {
         frame: Frame::Label(thread_label),
-        category_pair: CategoryHandle::OTHER.into(),
+        category_pair: category.into(),
         flags: FrameFlags::empty(),
     }
 }
 
+/// Picks a category for a thread in the System process, based on the ntoskrnl
+/// routine the thread was started at, e.g. "MiZeroPageThread". The prefix of
+/// the routine name is the kernel component it belongs to.
+fn kernel_thread_category(start_routine: &str) -> KnownCategory {
+    let component = |prefix| start_routine.starts_with(prefix);
+    if component("Mi") || component("Mm") {
+        KnownCategory::KernelMemoryManager
+    } else if component("Cc") {
+        KnownCategory::KernelCacheManager
+    } else if component("Ex") || component("Io") {
+        KnownCategory::KernelWorkerThread
+    } else {
+        KnownCategory::KernelThread
+    }
+}
+
+/// Whether this is the file name of the NT kernel image. Multiprocessor and
+/// PAE builds of the kernel have different names on older systems.
+fn is_ntoskrnl(file_name: &str) -> bool {
+    [
+        "ntoskrnl.exe",
+        "ntkrnlmp.exe",
+        "ntkrnlpa.exe",
+        "ntkrpamp.exe",
+    ]
+    .iter()
+    .any(|name| file_name.eq_ignore_ascii_case(name))
+}
+
+/// Returns the tid of a System process thread from the label of its thread
+/// label frame, see `make_thread_label_frame`.
+fn kernel_thread_tid_from_label(label: &str) -> Option<u32> {
+    let (_, rest) = label.rsplit_once(" (pid: ")?;
+    let (pid, tid) = rest.strip_suffix(')')?.split_once(", tid: ")?;
+    if pid.parse::<u32>().ok()? != SYSTEM_PROCESS_PID {
+        return None;
+    }
+    tid.parse().ok()
+}
+
+/// Looks up the names of the functions at these relative addresses in `lib`,
+/// with the symbol files which can be found locally.
+fn lookup_function_names(lib: &LibraryInfo, rvas: &[u32]) -> HashMap<u32, String> {
+    let config = wholesym::SymbolManagerConfig::new()
+        .use_spotlight(true)
+        .respect_nt_symbol_path(true)
+        .offline(true);
+    let mut symbol_manager = wholesym::SymbolManager::with_config(config);
+    symbol_manager.add_known_library(wholesym::LibraryInfo {
+        name: Some(lib.name.clone()),
+        path: Some(lib.path.clone()),
+        debug_path: Some(lib.debug_path.clone()),
+        debug_id: Some(lib.debug_id),
+        arch: lib.arch.clone(),
+        debug_name: Some(lib.debug_name.clone()),
+        code_id: lib
+            .code_id
+            .as_deref()
+            .and_then(|id| wholesym::CodeId::from_str(id).ok()),
+    });
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut names = HashMap::new();
+        let Ok(symbol_map) = symbol_manager
+            .load_symbol_map(&lib.debug_name, lib.debug_id)
+            .await
+        else {
+            return names;
+        };
+        for &rva in rvas {
+            if let Some(info) = symbol_map
+                .lookup(wholesym::LookupAddress::Relative(rva))
+                .await
+            {
+                names.insert(rva, info.symbol.name);
+            }
+        }
+        names
+    })
+}
+
 #[derive(Debug, Clone)]
 pub struct FreeformMarker(StringHandle, StringHandle, CategoryHandle);
 
@@ -2266,9 +4168,97 @@ impl StaticSchemaMarker for FreeformMarker {
     }
 }
 
+/// Adds an off-CPU sample, with the frame which says what the thread waited for
+/// on top of its stack, if known.
+#[allow(clippy::too_many_arguments)]
+fn add_off_cpu_sample(
+    samples: &mut UnresolvedSamples,
+    thread_handle: ThreadHandle,
+    timestamp: Timestamp,
+    timestamp_raw: u64,
+    stack: UnresolvedStackHandle,
+    cpu_delta: CpuDelta,
+    weight: i32,
+    wait_frame: Option<&FrameInfo>,
+) {
+    match wait_frame {
+        Some(wait_frame) => samples.add_sample_with_leaf_frame(
+            thread_handle,
+            timestamp,
+            timestamp_raw,
+            stack,
+            cpu_delta,
+            weight,
+            wait_frame.clone(),
+        ),
+        None => samples.add_sample(
+            thread_handle,
+            timestamp,
+            timestamp_raw,
+            stack,
+            cpu_delta,
+            weight,
+            None,
+        ),
+    }
+}
+
 fn extract_filename(path: &str) -> &str {
     match path.rsplit_once(['/', '\\']) {
         Some((_base, file_name)) => file_name,
         None => path,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kernel_thread_category() {
+        assert_eq!(
+            kernel_thread_category("MiZeroPageThread"),
+            KnownCategory::KernelMemoryManager
+        );
+        assert_eq!(
+            kernel_thread_category("MmZeroPageThread"),
+            KnownCategory::KernelMemoryManager
+        );
+        assert_eq!(
+            kernel_thread_category("CcQueueLazyWriteScanThread"),
+            KnownCategory::KernelCacheManager
+        );
+        assert_eq!(
+            kernel_thread_category("ExpWorkerThread"),
+            KnownCategory::KernelWorkerThread
+        );
+        assert_eq!(
+            kernel_thread_category("KeBalanceSetManager"),
+            KnownCategory::KernelThread
+        );
+    }
+
+    #[test]
+    fn test_kernel_thread_tid_from_label() {
+        assert_eq!(
+            kernel_thread_tid_from_label("ExpWorkerThread (pid: 4, tid: 132)"),
+            Some(132)
+        );
+        assert_eq!(
+            kernel_thread_tid_from_label("Thread 88 (pid: 4, tid: 88)"),
+            Some(88)
+        );
+        assert_eq!(
+            kernel_thread_tid_from_label("main (pid: 1234, tid: 5678)"),
+            None
+        );
+        assert_eq!(kernel_thread_tid_from_label("ExpWorkerThread"), None);
+    }
+
+    #[test]
+    fn test_is_ntoskrnl() {
+        assert!(is_ntoskrnl("ntoskrnl.exe"));
+        assert!(is_ntoskrnl("NTKRNLMP.EXE"));
+        assert!(!is_ntoskrnl("ntdll.dll"));
+    }
+}
@@ -1,18 +1,89 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::os::windows::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
 use std::process::ExitStatus;
+use std::time::Duration;
 
 use fxprof_processed_profile::{Profile, ReferenceTimestamp, SamplingInterval};
+use tokio::sync::oneshot;
 
+use super::energy_meter::EnergyMeterRecorder;
 use super::etw_gecko;
 use super::profile_context::ProfileContext;
+use super::winutils;
 use crate::server::{start_server_main, ServerProps};
 use crate::shared::ctrl_c::CtrlC;
 use crate::shared::included_processes::IncludedProcesses;
+use crate::shared::record_error::RecordError;
 use crate::shared::recording_props::{ProfileCreationProps, RecordingMode, RecordingProps};
+use crate::shared::recording_schedule::{process_is_alive, RecordingSchedule, StopReason};
+use crate::shared::recording_summary::print_recording_summary;
 use crate::shared::save_profile::save_profile_to_file;
 use crate::shared::symbol_props::SymbolProps;
-use crate::windows::elevated_helper::ElevatedHelperSession;
+use crate::shared::time_zone::recording_start_meta_info;
+use crate::windows::elevated_helper::{ElevatedHelperSession, ElevatedRecordingProps};
+use crate::windows::launch::{activate_packaged_app, restart_service};
+use crate::windows::xperf::{recorded_etl_size, Xperf};
+
+/// The ETW sessions we record with.
+enum EtwSessions {
+    /// Kernel and user-mode sessions, controlled by the elevated helper process.
+    Elevated(ElevatedHelperSession),
+    /// Only a user-mode session, controlled from this process.
+    UserModeOnly(Xperf),
+}
+
+impl EtwSessions {
+    fn start(
+        recording_props: &RecordingProps,
+        profile_creation_props: &ProfileCreationProps,
+        recording_mode: &RecordingMode,
+    ) -> Self {
+        if recording_props.user_mode_only {
+            let props = ElevatedRecordingProps::from_recording_props(
+                recording_props,
+                profile_creation_props,
+                recording_mode,
+            );
+            let mut xperf = Xperf::new();
+            if let Err(err) = xperf.start_user_session(&recording_props.output_file, &props) {
+                eprintln!("Error: {err}");
+                std::process::exit(1);
+            }
+            return EtwSessions::UserModeOnly(xperf);
+        }
+
+        let mut elevated_helper = ElevatedHelperSession::new(recording_props.output_file.clone())
+            .unwrap_or_else(|e| panic!("Couldn't start elevated helper process: {e:?}"));
+        elevated_helper
+            .start_xperf(recording_props, profile_creation_props, recording_mode)
+            .unwrap();
+        EtwSessions::Elevated(elevated_helper)
+    }
+
+    /// Stops the sessions and returns the ETL files, the one with the kernel
+    /// events (if any) first.
+    fn stop(self) -> Vec<PathBuf> {
+        match self {
+            EtwSessions::Elevated(mut elevated_helper) => {
+                let (kernel_output_file, user_output_file) = elevated_helper
+                    .stop_xperf()
+                    .expect("Should have produced a merged ETL file");
+                elevated_helper.shutdown();
+                std::iter::once(kernel_output_file)
+                    .chain(user_output_file)
+                    .collect()
+            }
+            EtwSessions::UserModeOnly(mut xperf) => {
+                let user_output_file = xperf
+                    .stop_user_session()
+                    .expect("Should have produced an ETL file");
+                vec![user_output_file]
+            }
+        }
+    }
+}
 
 // Hello intrepid explorer! You may be in this code because you'd like to extend something,
 // or are trying to figure out how various ETW things work. It's not the easiest API!
@@ -43,34 +114,49 @@ use crate::windows::elevated_helper::ElevatedHelperSession;
 pub fn start_recording(
     recording_mode: RecordingMode,
     recording_props: RecordingProps,
-    profile_creation_props: ProfileCreationProps,
+    mut profile_creation_props: ProfileCreationProps,
     symbol_props: SymbolProps,
     server_props: Option<ServerProps>,
-) -> Result<ExitStatus, i32> {
-    let timebase = std::time::SystemTime::now();
-    let timebase = ReferenceTimestamp::from_system_time(timebase);
+) -> Result<(ExitStatus, Profile), RecordError> {
+    let start_time = std::time::SystemTime::now();
+    let schedule = RecordingSchedule::new(&recording_props);
+    let timebase = ReferenceTimestamp::from_system_time(start_time);
 
-    let profile = Profile::new(
+    let mut profile = Profile::new(
         profile_creation_props.profile_name(),
         timebase,
         SamplingInterval::from_nanos(1000000), // will be replaced with correct interval from file later
     );
+    for (label, value) in recording_start_meta_info(start_time) {
+        profile.add_extra_meta_info("Recording", label, &value);
+    }
 
     // Start xperf.
-    let mut elevated_helper = ElevatedHelperSession::new(recording_props.output_file.clone())
-        .unwrap_or_else(|e| panic!("Couldn't start elevated helper process: {e:?}"));
-    elevated_helper
-        .start_xperf(&recording_props, &profile_creation_props, &recording_mode)
-        .unwrap();
+    let etw_sessions =
+        EtwSessions::start(&recording_props, &profile_creation_props, &recording_mode);
+    let energy_meter_recorder = if recording_props.power_counters {
+        let recorder = EnergyMeterRecorder::start();
+        if recorder.is_none() {
+            eprintln!("Warning: No energy meters found, recording without power counters.");
+        }
+        recorder
+    } else {
+        None
+    };
+    let mut launched_process_names = HashMap::new();
+    let mut stop_reason = None;
 
     let included_processes = match recording_mode {
         RecordingMode::All => {
             let ctrl_c_receiver = CtrlC::observe_oneshot();
             eprintln!("Profiling all processes...");
             eprintln!("Press Ctrl+C to stop.");
-            // TODO: Respect recording_props.time_limit, if specified
-            // Wait for Ctrl+C.
-            let _ = ctrl_c_receiver.blocking_recv();
+            stop_reason = wait_for_ctrl_c_or_stop(
+                ctrl_c_receiver,
+                &schedule,
+                &recording_props.output_file,
+                None,
+            );
             None
         }
         RecordingMode::Pid(pid) => {
@@ -78,9 +164,75 @@ pub fn start_recording(
             // TODO: check that process with this pid exists
             eprintln!("Profiling process with pid {pid}...");
             eprintln!("Press Ctrl+C to stop.");
-            // TODO: Respect recording_props.time_limit, if specified
-            // Wait for Ctrl+C.
-            let _ = ctrl_c_receiver.blocking_recv();
+            stop_reason = wait_for_ctrl_c_or_stop(
+                ctrl_c_receiver,
+                &schedule,
+                &recording_props.output_file,
+                None,
+            );
+            Some(IncludedProcesses {
+                name_substrings: Vec::new(),
+                pids: vec![pid],
+            })
+        }
+        RecordingMode::Service(service_name) => {
+            let ctrl_c_receiver = CtrlC::observe_oneshot();
+            // The service process is started by services.exe, so we can only find
+            // out its pid from the service control manager.
+            let pid = match restart_service(&service_name) {
+                Ok(pid) => pid,
+                Err(err) => {
+                    eprintln!("Error: {err}");
+                    discard_etl_files(etw_sessions.stop());
+                    std::process::exit(1);
+                }
+            };
+            eprintln!("Profiling service {service_name} (pid {pid})...");
+            eprintln!("Press Ctrl+C to stop.");
+            stop_reason = wait_for_ctrl_c_or_stop(
+                ctrl_c_receiver,
+                &schedule,
+                &recording_props.output_file,
+                None,
+            );
+            Some(IncludedProcesses {
+                name_substrings: Vec::new(),
+                pids: vec![pid],
+            })
+        }
+        RecordingMode::PackagedApp { app_id, args } => {
+            let ctrl_c_receiver = CtrlC::observe_oneshot();
+            let args = args
+                .iter()
+                .map(|arg| {
+                    let arg = arg.to_string_lossy();
+                    if arg.contains(' ') {
+                        format!("\"{arg}\"")
+                    } else {
+                        arg.into_owned()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            // Packaged apps are started by the activation manager, not by us, so
+            // we get the pid of the app process from there.
+            let pid = match activate_packaged_app(&app_id, &args) {
+                Ok(pid) => pid,
+                Err(err) => {
+                    eprintln!("Error: {err}");
+                    discard_etl_files(etw_sessions.stop());
+                    std::process::exit(1);
+                }
+            };
+            launched_process_names.insert(pid, app_id.clone());
+            eprintln!("Profiling app {app_id} (pid {pid})...");
+            eprintln!("Press Ctrl+C to stop, or close the app.");
+            stop_reason = wait_for_ctrl_c_or_stop(
+                ctrl_c_receiver,
+                &schedule,
+                &recording_props.output_file,
+                Some(pid),
+            );
             Some(IncludedProcesses {
                 name_substrings: Vec::new(),
                 pids: vec![pid],
@@ -92,13 +244,18 @@ pub fn start_recording(
             let mut ctrl_c_receiver = CtrlC::observe_oneshot();
 
             let mut pids = Vec::new();
-            for _ in 0..process_launch_props.iteration_count {
+            'iterations: for _ in 0..process_launch_props.iteration_count {
                 let mut child = std::process::Command::new(&process_launch_props.command_name);
                 child.args(&process_launch_props.args);
                 child.envs(process_launch_props.env_vars.iter().map(|(k, v)| (k, v)));
                 let mut child = child.spawn().unwrap();
 
                 pids.push(child.id());
+                let command_name = std::path::Path::new(&process_launch_props.command_name);
+                if let Some(file_name) = command_name.file_name() {
+                    launched_process_names
+                        .insert(child.id(), file_name.to_string_lossy().into_owned());
+                }
 
                 // Wait for the child to exit.
                 //
@@ -108,7 +265,20 @@ pub fn start_recording(
                 // longer we take to handle Ctrl+C, the higher the chance that the user might
                 // press Ctrl+C again, which would immediately terminate this process and not
                 // give us a chance to stop xperf.
-                let exit_status = child.wait().unwrap();
+                //
+                // We poll so that the recording can stop before the child exits, if the
+                // --duration passes or the --until-exit-of process goes away. The child
+                // keeps running in that case.
+                let exit_status = loop {
+                    if let Some(exit_status) = child.try_wait().unwrap() {
+                        break exit_status;
+                    }
+                    stop_reason = check_stop(&schedule, &recording_props.output_file);
+                    if stop_reason.is_some() {
+                        break 'iterations;
+                    }
+                    std::thread::sleep(SCHEDULE_POLL_INTERVAL);
+                };
                 if !exit_status.success() {
                     eprintln!("Child process exited with {:?}", exit_status);
                 }
@@ -124,13 +294,22 @@ pub fn start_recording(
         }
     };
 
-    eprintln!("Stopping xperf...");
+    for (label, value) in schedule.meta_info(stop_reason) {
+        profile.add_extra_meta_info("Recording", label, &value);
+    }
+    if !schedule.delay().is_zero() && profile_creation_props.time_range.is_none() {
+        // ETW keeps recording during the delay, so we drop the delay's events when
+        // processing the trace.
+        profile_creation_props.time_range =
+            Some((schedule.delay(), Duration::from_nanos(u64::MAX)));
+    }
 
-    let (kernel_output_file, user_output_file) = elevated_helper
-        .stop_xperf()
-        .expect("Should have produced a merged ETL file");
+    eprintln!("Stopping xperf...");
 
-    elevated_helper.shutdown();
+    let etl_files = etw_sessions.stop();
+    let energy_meter_readings = energy_meter_recorder
+        .map(EnergyMeterRecorder::stop)
+        .unwrap_or_default();
 
     eprintln!("Processing ETL trace...");
 
@@ -142,43 +321,39 @@ pub fn start_recording(
         .unwrap_or(get_native_arch().to_string());
 
     let unstable_presymbolicate = profile_creation_props.unstable_presymbolicate;
+    let search_index = profile_creation_props.search_index;
+    let recording_summary = profile_creation_props.recording_summary;
     let mut context =
         ProfileContext::new(profile, &arch, included_processes, profile_creation_props);
-    let extra_etls = match &user_output_file {
-        Some(user_etl) => vec![user_etl.clone()],
-        None => Vec::new(),
-    };
-    etw_gecko::process_etl_files(&mut context, &kernel_output_file, &extra_etls);
+    context.set_pmc_counter_names(recording_props.pmc_counters.clone());
+    context.set_pmc_sampling_source(recording_props.pmc_sampling_source.clone());
+    context.set_process_sample_strides(recording_props.process_sample_strides());
+    if recording_props.user_mode_only {
+        context.enable_synthetic_processes(launched_process_names);
+    }
+    etw_gecko::process_etl_files(&mut context, &etl_files[0], &etl_files[1..], false);
+    context.add_energy_meter_readings(energy_meter_readings);
 
     if let Some(win_version) = winver::WindowsVersion::detect() {
         context.set_os_name(&format!("Windows {win_version}"))
     }
+    context.set_cpu_core_types(&winutils::cpu_efficiency_classes());
 
     let profile = context.finish();
 
     if !recording_props.keep_etl {
-        std::fs::remove_file(&kernel_output_file).unwrap_or_else(|_| {
-            panic!(
-                "Failed to delete ETL file {:?}",
-                kernel_output_file.to_str().unwrap()
-            )
-        });
-        if let Some(user_output_file) = &user_output_file {
-            std::fs::remove_file(user_output_file).unwrap_or_else(|_| {
-                panic!(
-                    "Failed to delete ETL file {:?}",
-                    user_output_file.to_str().unwrap()
-                )
+        for etl_file in &etl_files {
+            std::fs::remove_file(etl_file).unwrap_or_else(|_| {
+                panic!("Failed to delete ETL file {:?}", etl_file.to_str().unwrap())
             });
         }
     } else {
-        eprintln!("ETL path: {}", kernel_output_file.to_str().unwrap());
-        if let Some(user_output_file) = &user_output_file {
-            eprintln!("User ETL path: {}", user_output_file.to_str().unwrap());
+        for etl_file in &etl_files {
+            eprintln!("ETL path: {}", etl_file.to_str().unwrap());
         }
     }
 
-    save_profile_to_file(&profile, &output_file).expect("Couldn't write JSON");
+    save_profile_to_file(&profile, &output_file).map_err(RecordError::Save)?;
 
     if unstable_presymbolicate {
         crate::shared::symbol_precog::presymbolicate(
@@ -187,6 +362,14 @@ pub fn start_recording(
         );
     }
 
+    if search_index {
+        crate::search_index::write_search_index_for_profile(&output_file);
+    }
+
+    if recording_summary {
+        print_recording_summary(&profile, &output_file);
+    }
+
     // then fire up the server for the profiler front end, if not save-only
     if let Some(server_props) = server_props {
         let libinfo_map = crate::profile_json_preparse::parse_libinfo_map_from_profile_file(
@@ -198,7 +381,49 @@ pub fn start_recording(
         start_server_main(&output_file, server_props, symbol_props, libinfo_map);
     }
 
-    Ok(ExitStatus::from_raw(0))
+    Ok((ExitStatus::from_raw(0), profile))
+}
+
+fn discard_etl_files(etl_files: Vec<PathBuf>) {
+    for etl_file in etl_files {
+        let _ = std::fs::remove_file(etl_file);
+    }
+}
+
+/// How often we check the recording schedule while waiting for the recording to end.
+const SCHEDULE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Checks the schedule, and the size of the ETL files for `--max-recording-size`.
+fn check_stop(schedule: &RecordingSchedule, output_file: &Path) -> Option<StopReason> {
+    schedule
+        .check_stop()
+        .or_else(|| schedule.check_recording_size(|| recorded_etl_size(output_file)))
+}
+
+/// Waits until the user presses Ctrl+C, the schedule says to stop, or the
+/// process `exit_of` exits. Returns the schedule's reason for stopping, if it
+/// was the schedule.
+fn wait_for_ctrl_c_or_stop(
+    mut ctrl_c_receiver: oneshot::Receiver<()>,
+    schedule: &RecordingSchedule,
+    output_file: &Path,
+    exit_of: Option<u32>,
+) -> Option<StopReason> {
+    loop {
+        if exit_of.is_some_and(|pid| !process_is_alive(pid)) {
+            return None;
+        }
+        if !matches!(
+            ctrl_c_receiver.try_recv(),
+            Err(oneshot::error::TryRecvError::Empty)
+        ) {
+            return None;
+        }
+        if let Some(stop_reason) = check_stop(schedule, output_file) {
+            return Some(stop_reason);
+        }
+        std::thread::sleep(SCHEDULE_POLL_INTERVAL);
+    }
 }
 
 #[cfg(target_arch = "x86")]
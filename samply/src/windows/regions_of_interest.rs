@@ -0,0 +1,402 @@
+//! Regions of interest from a WPA regions file, for `samply import --regions-of-interest`.
+//!
+//! WPA defines a region by the event which starts it and the event which stops
+//! it, e.g.:
+//!
+//! ```xml
+//! <InstrumentationManifest>
+//!   <Instrumentation>
+//!     <Regions>
+//!       <RegionRoot Guid="{...}" Name="MyApp">
+//!         <Region Guid="{...}" Name="Load" FriendlyName="Document load">
+//!           <Start><Event Provider="{...}" Id="1" Version="0"/></Start>
+//!           <Stop><Event Provider="{...}" Id="2" Version="0"/></Stop>
+//!           <Match><Event PID="true" TID="true"/></Match>
+//!         </Region>
+//!       </RegionRoot>
+//!     </Regions>
+//!   </Instrumentation>
+//! </InstrumentationManifest>
+//! ```
+//!
+//! Each pair of matching start and stop events becomes an interval marker on
+//! the thread of the start event. Events are matched by provider and by any of
+//! `Id`, `Version`, `Opcode` and `Name` which the definition has. Matching on
+//! payload fields, on parent regions and payload-based naming are not supported.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+
+/// The properties of an event which a region's start or stop can match.
+#[derive(Debug, Clone)]
+pub struct RegionEvent<'a> {
+    pub provider: u128,
+    pub id: u16,
+    pub version: u8,
+    pub opcode: u8,
+    /// The event name, without the provider name.
+    pub name: &'a str,
+    pub pid: u32,
+    pub tid: u32,
+    pub timestamp_raw: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EventFilter {
+    provider: u128,
+    id: Option<u16>,
+    version: Option<u8>,
+    opcode: Option<u8>,
+    name: Option<String>,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &RegionEvent) -> bool {
+        self.provider == event.provider
+            && matches_if_set(self.id, event.id)
+            && matches_if_set(self.version, event.version)
+            && matches_if_set(self.opcode, event.opcode)
+            && matches_if_set(self.name.as_deref(), event.name)
+    }
+}
+
+/// Attributes which a definition leaves out match any value.
+fn matches_if_set<T: PartialEq>(expected: Option<T>, value: T) -> bool {
+    match expected {
+        Some(expected) => expected == value,
+        None => true,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RegionDefinition {
+    name: String,
+    /// The name of the region's RegionRoot.
+    root_name: String,
+    start: Vec<EventFilter>,
+    stop: Vec<EventFilter>,
+    same_pid: bool,
+    same_tid: bool,
+}
+
+/// A started region which hasn't been stopped yet.
+#[derive(Debug, Clone, Copy)]
+struct OpenRegion {
+    pid: u32,
+    tid: u32,
+    start_raw: u64,
+}
+
+/// A region with its start and stop event.
+#[derive(Debug, Clone)]
+pub struct CompletedRegion<'a> {
+    pub name: &'a str,
+    pub root_name: &'a str,
+    pub pid: u32,
+    /// The thread of the start event.
+    pub tid: u32,
+    pub start_raw: u64,
+    pub end_raw: u64,
+}
+
+/// The region definitions, and the regions which have been started so far.
+#[derive(Debug)]
+pub struct RegionsOfInterest {
+    regions: Vec<RegionDefinition>,
+    /// The indexes of the regions which have a start or stop event of each provider.
+    regions_by_provider: HashMap<u128, Vec<usize>>,
+    /// The open regions of each region definition, oldest first.
+    open_regions: Vec<Vec<OpenRegion>>,
+}
+
+impl RegionsOfInterest {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let xml = std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read {path:?}: {err}"))?;
+        Self::parse(&xml).map_err(|err| format!("Could not parse {path:?}: {err}"))
+    }
+
+    fn parse(xml: &str) -> Result<Self, String> {
+        let document = roxmltree::Document::parse(xml).map_err(|err| err.to_string())?;
+        let mut regions = Vec::new();
+        for root in document
+            .descendants()
+            .filter(|node| node.has_tag_name("RegionRoot"))
+        {
+            let root_name = display_name(root).unwrap_or_default();
+            for region in root
+                .descendants()
+                .filter(|node| node.has_tag_name("Region"))
+            {
+                regions.push(parse_region(region, &root_name)?);
+            }
+        }
+        if regions.is_empty() {
+            return Err("no Region elements found".into());
+        }
+
+        let mut regions_by_provider: HashMap<u128, Vec<usize>> = HashMap::new();
+        for (index, region) in regions.iter().enumerate() {
+            for filter in region.start.iter().chain(&region.stop) {
+                let indexes = regions_by_provider.entry(filter.provider).or_default();
+                if indexes.last() != Some(&index) {
+                    indexes.push(index);
+                }
+            }
+        }
+        let open_regions = vec![Vec::new(); regions.len()];
+        Ok(Self {
+            regions,
+            regions_by_provider,
+            open_regions,
+        })
+    }
+
+    /// Checks the event against the start and stop events of all regions, and
+    /// calls `f` for each region which the event stops.
+    pub fn handle_event(&mut self, event: &RegionEvent, mut f: impl FnMut(CompletedRegion)) {
+        let Some(region_indexes) = self.regions_by_provider.get(&event.provider) else {
+            return;
+        };
+        for &index in region_indexes {
+            let region = &self.regions[index];
+            let open_regions = &mut self.open_regions[index];
+            if region.stop.iter().any(|filter| filter.matches(event)) {
+                // Stop the most recent region which this event can stop.
+                let position = open_regions.iter().rposition(|open| {
+                    (!region.same_pid || open.pid == event.pid)
+                        && (!region.same_tid || open.tid == event.tid)
+                });
+                if let Some(position) = position {
+                    let open = open_regions.remove(position);
+                    f(CompletedRegion {
+                        name: &region.name,
+                        root_name: &region.root_name,
+                        pid: open.pid,
+                        tid: open.tid,
+                        start_raw: open.start_raw,
+                        end_raw: event.timestamp_raw,
+                    });
+                    continue;
+                }
+            }
+            if region.start.iter().any(|filter| filter.matches(event)) {
+                open_regions.push(OpenRegion {
+                    pid: event.pid,
+                    tid: event.tid,
+                    start_raw: event.timestamp_raw,
+                });
+            }
+        }
+    }
+}
+
+/// The FriendlyName of the element, or its Name if it doesn't have one.
+fn display_name(node: roxmltree::Node) -> Option<String> {
+    node.attribute("FriendlyName")
+        .or_else(|| node.attribute("Name"))
+        .map(str::to_string)
+}
+
+fn parse_region(region: roxmltree::Node, root_name: &str) -> Result<RegionDefinition, String> {
+    let name = display_name(region).ok_or("a Region has no Name")?;
+    let child = |tag_name: &str| region.children().find(|node| node.has_tag_name(tag_name));
+    let event_filters = |tag_name: &str| -> Result<Vec<EventFilter>, String> {
+        let filters = child(tag_name)
+            .into_iter()
+            .flat_map(|node| node.children().filter(|node| node.has_tag_name("Event")))
+            .map(parse_event_filter)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("in the {tag_name} of region {name:?}: {err}"))?;
+        match filters.is_empty() {
+            true => Err(format!("region {name:?} has no {tag_name} event")),
+            false => Ok(filters),
+        }
+    };
+    let start = event_filters("Start")?;
+    let stop = event_filters("Stop")?;
+    let match_event =
+        child("Match").and_then(|node| node.children().find(|node| node.has_tag_name("Event")));
+    let match_attribute = |name: &str| {
+        match_event
+            .and_then(|node| node.attribute(name))
+            .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+    };
+    Ok(RegionDefinition {
+        same_pid: match_attribute("PID"),
+        same_tid: match_attribute("TID"),
+        name,
+        root_name: root_name.to_string(),
+        start,
+        stop,
+    })
+}
+
+fn parse_event_filter(event: roxmltree::Node) -> Result<EventFilter, String> {
+    let provider = event
+        .attribute("Provider")
+        .ok_or("an Event has no Provider")?;
+    let provider =
+        parse_guid(provider).ok_or_else(|| format!("invalid provider GUID {provider:?}"))?;
+    fn number<T: std::str::FromStr>(
+        event: roxmltree::Node,
+        name: &str,
+    ) -> Result<Option<T>, String> {
+        event
+            .attribute(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid {name} {value:?}"))
+            })
+            .transpose()
+    }
+    Ok(EventFilter {
+        provider,
+        id: number(event, "Id")?,
+        version: number(event, "Version")?,
+        opcode: number(event, "Opcode")?,
+        name: event.attribute("Name").map(str::to_string),
+    })
+}
+
+/// Parses a GUID like `{9e814aad-3204-11d2-9a82-006008a86939}`, with or
+/// without braces, into the same number as `GUID::to_u128`.
+fn parse_guid(guid: &str) -> Option<u128> {
+    let guid = guid
+        .strip_prefix('{')
+        .and_then(|guid| guid.strip_suffix('}'))
+        .unwrap_or(guid);
+    let groups: Vec<&str> = guid.split('-').collect();
+    let lengths: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+    if lengths != [8, 4, 4, 4, 12] {
+        return None;
+    }
+    u128::from_str_radix(&groups.concat(), 16).ok()
+}
+
+/// An interval marker for a region of interest.
+#[derive(Debug, Clone)]
+pub struct RegionOfInterestMarker {
+    pub name: StringHandle,
+    pub root_name: StringHandle,
+    pub category: CategoryHandle,
+}
+
+impl StaticSchemaMarker for RegionOfInterestMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "RegionOfInterest";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![
+                MarkerLocation::MarkerChart,
+                MarkerLocation::MarkerTable,
+                MarkerLocation::TimelineOverview,
+            ],
+            chart_label: Some("{marker.name}".into()),
+            tooltip_label: Some("{marker.name}".into()),
+            table_label: Some("{marker.data.root}: {marker.name}".into()),
+            fields: vec![MarkerFieldSchema {
+                key: "root".into(),
+                label: "Region root".into(),
+                format: MarkerFieldFormat::String,
+                searchable: true,
+            }],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "A region from the WPA regions of interest file.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.category
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.root_name
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROVIDER: &str = "{9e814aad-3204-11d2-9a82-006008a86939}";
+
+    fn event(id: u16, pid: u32, tid: u32, timestamp_raw: u64) -> RegionEvent<'static> {
+        RegionEvent {
+            provider: parse_guid(PROVIDER).unwrap(),
+            id,
+            version: 0,
+            opcode: 0,
+            name: "",
+            pid,
+            tid,
+            timestamp_raw,
+        }
+    }
+
+    #[test]
+    fn regions_are_matched_by_thread() {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+            <InstrumentationManifest>
+              <Instrumentation>
+                <Regions>
+                  <RegionRoot Guid="{{d8d639a0-cf4c-45fb-976a-0000deadbeef}}" Name="App">
+                    <Region Guid="{{d8d639a1-cf4c-45fb-976a-0000deadbeef}}" Name="Load"
+                            FriendlyName="Document load">
+                      <Start><Event Provider="{PROVIDER}" Id="1" Version="0"/></Start>
+                      <Stop><Event Provider="{PROVIDER}" Id="2" Version="0"/></Stop>
+                      <Match><Event PID="true" TID="true"/></Match>
+                    </Region>
+                  </RegionRoot>
+                </Regions>
+              </Instrumentation>
+            </InstrumentationManifest>"#
+        );
+        let mut regions = RegionsOfInterest::parse(&xml).unwrap();
+        let mut completed = Vec::new();
+        for event in [
+            event(1, 10, 11, 100),
+            event(1, 10, 12, 150),
+            event(2, 10, 11, 200),
+            event(2, 10, 13, 250),
+            event(2, 10, 12, 300),
+        ] {
+            regions.handle_event(&event, |region| {
+                assert_eq!(region.name, "Document load");
+                assert_eq!(region.root_name, "App");
+                completed.push((region.tid, region.start_raw, region.end_raw));
+            });
+        }
+        assert_eq!(completed, vec![(11, 100, 200), (12, 150, 300)]);
+    }
+
+    #[test]
+    fn invalid_definitions_are_errors() {
+        assert!(RegionsOfInterest::parse("<Regions/>").is_err());
+        let missing_stop = format!(
+            r#"<Regions><RegionRoot Name="App"><Region Name="Load">
+                 <Start><Event Provider="{PROVIDER}" Id="1"/></Start>
+               </Region></RegionRoot></Regions>"#
+        );
+        assert!(RegionsOfInterest::parse(&missing_stop).is_err());
+        assert_eq!(parse_guid("not a guid"), None);
+    }
+}
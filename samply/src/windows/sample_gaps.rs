@@ -0,0 +1,133 @@
+//! Gaps in the SampledProfile events of each CPU. Every CPU normally gets a
+//! SampleProf event per sampling interval, even while it's idle. If a CPU has
+//! no samples for much longer than that, e.g. because it was in a deep C-state
+//! or because the trace lost events, the missing samples would look like low
+//! CPU usage, so we add a marker for the gap. And if a thread was running
+//! during the gap, its next sample gets the weight of the missing samples.
+
+use std::collections::HashMap;
+
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+
+/// A CPU counts as frozen if it had no samples for this many sampling intervals.
+const GAP_INTERVAL_COUNT: u64 = 10;
+
+/// The time of the most recent sample on each CPU.
+#[derive(Debug, Default)]
+pub struct SampleGapDetector {
+    last_sample_raw_per_cpu: HashMap<u32, u64>,
+}
+
+impl SampleGapDetector {
+    /// Notes a SampleProf event on the CPU. If the CPU had no samples for more
+    /// than [`GAP_INTERVAL_COUNT`] sampling intervals, returns the raw timestamp
+    /// of its previous sample, where the gap started.
+    pub fn notify_sample(
+        &mut self,
+        cpu_index: u32,
+        timestamp_raw: u64,
+        raw_to_ns_factor: u64,
+        sampling_interval_nanos: u64,
+    ) -> Option<u64> {
+        let previous_raw = self
+            .last_sample_raw_per_cpu
+            .insert(cpu_index, timestamp_raw)?;
+        let gap_nanos = timestamp_raw.saturating_sub(previous_raw) * raw_to_ns_factor;
+        (gap_nanos > sampling_interval_nanos * GAP_INTERVAL_COUNT).then_some(previous_raw)
+    }
+}
+
+/// The weight for the first sample after a gap on a CPU: the number of
+/// sampling intervals for which the sampled thread ran since its previous
+/// sample, up to the length of the gap. So a thread which kept running while
+/// the samples were lost still gets about as many samples as it would have
+/// gotten without the gap, and a thread which just woke up, e.g. after the CPU
+/// was idle, gets the usual weight of 1.
+pub fn sample_weight_after_gap(
+    on_cpu_nanos: u64,
+    gap_nanos: u64,
+    sampling_interval_nanos: u64,
+) -> i32 {
+    if sampling_interval_nanos == 0 {
+        return 1;
+    }
+    let running_nanos = on_cpu_nanos.min(gap_nanos);
+    let intervals = (running_nanos + sampling_interval_nanos / 2) / sampling_interval_nanos;
+    i32::try_from(intervals).unwrap_or(i32::MAX).max(1)
+}
+
+/// An interval marker for a time in which a CPU had no samples.
+#[derive(Debug, Clone)]
+pub struct SampleGapMarker {
+    pub cpu_name: StringHandle,
+    pub gap_ms: f64,
+}
+
+impl StaticSchemaMarker for SampleGapMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "SampleGap";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.cpu}".into()),
+            tooltip_label: Some("No samples on {marker.data.cpu} for {marker.data.gap}".into()),
+            table_label: Some("No samples on {marker.data.cpu} for {marker.data.gap}".into()),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "cpu".into(),
+                    label: "CPU".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "gap".into(),
+                    label: "Gap".into(),
+                    format: MarkerFieldFormat::Milliseconds,
+                    searchable: false,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "The CPU had no samples for much longer than the sampling interval, e.g. because it was in a deep sleep state or because events were lost. Its CPU usage during this time is unknown.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Sampling gap")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        CategoryHandle::OTHER
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.cpu_name
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.gap_ms
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_weight_after_gap() {
+        const MS: u64 = 1_000_000;
+        // Ran through the whole 50ms gap.
+        assert_eq!(sample_weight_after_gap(50 * MS, 50 * MS, MS), 50);
+        // Ran for a part of it.
+        assert_eq!(sample_weight_after_gap(20 * MS + MS / 3, 50 * MS, MS), 20);
+        // Running time from before the gap doesn't count.
+        assert_eq!(sample_weight_after_gap(80 * MS, 50 * MS, MS), 50);
+        // Just woke up.
+        assert_eq!(sample_weight_after_gap(MS / 10, 50 * MS, MS), 1);
+    }
+}
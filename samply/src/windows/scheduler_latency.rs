@@ -0,0 +1,131 @@
+//! Scheduler latency for `--scheduler-latency`: the time between a ReadyThread
+//! event, which makes a thread runnable, and the CSwitch event which puts it
+//! onto a CPU. Long latencies show CPU contention and priority starvation.
+
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+
+/// The ready-to-running latencies of one thread.
+#[derive(Debug, Default)]
+pub struct SchedulerLatencyTracker {
+    ready_since_raw: Option<u64>,
+    latencies_nanos: Vec<u64>,
+}
+
+impl SchedulerLatencyTracker {
+    /// The thread was made ready to run. If it was already waiting for a CPU,
+    /// the wait began at the earlier ReadyThread event.
+    pub fn notify_ready(&mut self, timestamp_raw: u64) {
+        self.ready_since_raw.get_or_insert(timestamp_raw);
+    }
+
+    /// The thread was switched out. A pending ReadyThread event doesn't count
+    /// if the thread was still running when it came.
+    pub fn notify_switch_out(&mut self) {
+        self.ready_since_raw = None;
+    }
+
+    /// The thread was switched in. Returns the raw timestamp at which the
+    /// thread became ready, if it was made ready by a ReadyThread event.
+    pub fn notify_switch_in(&mut self) -> Option<u64> {
+        self.ready_since_raw.take()
+    }
+
+    pub fn add_latency(&mut self, latency_nanos: u64) {
+        self.latencies_nanos.push(latency_nanos);
+    }
+
+    pub fn latencies_nanos(&self) -> &[u64] {
+        &self.latencies_nanos
+    }
+}
+
+/// Summary statistics of a set of latencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyDistribution {
+    pub count: usize,
+    pub median_nanos: u64,
+    pub p90_nanos: u64,
+    pub p99_nanos: u64,
+    pub max_nanos: u64,
+}
+
+impl LatencyDistribution {
+    /// Returns `None` if there are no latencies.
+    pub fn from_latencies(latencies_nanos: &[u64]) -> Option<Self> {
+        let mut sorted = latencies_nanos.to_vec();
+        sorted.sort_unstable();
+        let max_nanos = *sorted.last()?;
+        let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+        Some(Self {
+            count: sorted.len(),
+            median_nanos: percentile(50),
+            p90_nanos: percentile(90),
+            p99_nanos: percentile(99),
+            max_nanos,
+        })
+    }
+
+    /// For the profile metadata, e.g. "1234 wakeups, median 0.05ms, p90 0.4ms, ...".
+    pub fn summary(&self) -> String {
+        let ms = |nanos: u64| nanos as f64 / 1_000_000.0;
+        format!(
+            "{} wakeups, median {:.3}ms, p90 {:.3}ms, p99 {:.3}ms, max {:.3}ms",
+            self.count,
+            ms(self.median_nanos),
+            ms(self.p90_nanos),
+            ms(self.p99_nanos),
+            ms(self.max_nanos)
+        )
+    }
+}
+
+/// An interval marker from the ReadyThread event to the switch-in, for
+/// latencies above the threshold.
+#[derive(Debug, Clone)]
+pub struct SchedulerLatencyMarker {
+    pub latency_ms: f64,
+    pub category: CategoryHandle,
+}
+
+impl StaticSchemaMarker for SchedulerLatencyMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "SchedulerLatency";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.latency}".into()),
+            tooltip_label: Some("Waited {marker.data.latency} for a CPU".into()),
+            table_label: Some("Waited {marker.data.latency} for a CPU".into()),
+            fields: vec![MarkerFieldSchema {
+                key: "latency".into(),
+                label: "Latency".into(),
+                format: MarkerFieldFormat::Milliseconds,
+                searchable: false,
+            }],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "The thread was ready to run, but waited longer than the threshold before it was scheduled onto a CPU.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Scheduler latency")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.category
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        unreachable!()
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.latency_ms
+    }
+}
@@ -0,0 +1,383 @@
+//! Markers for file creates and registry key opens, with their arguments
+//! decoded into separate marker fields, so that the marker table can be
+//! filtered by path. Other events only get the stringified properties.
+//!
+//! Both the kernel logger ("MSNT_SystemTrace") and the manifest providers
+//! log these calls, with different field names for the same arguments.
+
+use etw_reader::parser::{Parser, TryParse};
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+
+/// The events which are decoded, by event name prefix.
+const SYSCALL_EVENT_PREFIXES: &[(&str, SyscallKind)] = &[
+    ("MSNT_SystemTrace/FileIo/Create", SyscallKind::FileCreate),
+    (
+        "Microsoft-Windows-Kernel-File/Create/",
+        SyscallKind::FileCreate,
+    ),
+    ("MSNT_SystemTrace/Registry/Open", SyscallKind::RegistryOpen),
+    (
+        "Microsoft-Windows-Kernel-Registry/OpenKey/",
+        SyscallKind::RegistryOpen,
+    ),
+    (
+        "MSNT_SystemTrace/Registry/Create",
+        SyscallKind::RegistryCreate,
+    ),
+    (
+        "Microsoft-Windows-Kernel-Registry/CreateKey/",
+        SyscallKind::RegistryCreate,
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallKind {
+    FileCreate,
+    RegistryOpen,
+    RegistryCreate,
+}
+
+impl SyscallKind {
+    pub fn from_event_name(name: &str) -> Option<Self> {
+        SYSCALL_EVENT_PREFIXES
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix))
+            .map(|(_, kind)| *kind)
+    }
+
+    pub fn marker_name(self) -> &'static str {
+        match self {
+            SyscallKind::FileCreate => "CreateFile",
+            SyscallKind::RegistryOpen => "RegOpenKey",
+            SyscallKind::RegistryCreate => "RegCreateKey",
+        }
+    }
+}
+
+/// The decoded arguments of one call. Arguments which the event doesn't have
+/// are empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyscallArgs {
+    pub path: String,
+    pub access: String,
+    pub disposition: String,
+    pub status: String,
+    /// The raw timestamp at which the call started, for the kernel registry
+    /// events, which are logged when the call returns.
+    pub start_timestamp_raw: Option<u64>,
+}
+
+pub fn parse_syscall_args(kind: SyscallKind, parser: &mut Parser) -> SyscallArgs {
+    let mut string_field = |names: &[&str]| -> String {
+        names
+            .iter()
+            .find_map(|name| TryParse::<String>::try_parse(parser, name).ok())
+            .unwrap_or_default()
+    };
+    match kind {
+        SyscallKind::FileCreate => {
+            let path = string_field(&["OpenPath", "FileName"]);
+            let create_options: Option<u32> = parser.try_parse("CreateOptions").ok();
+            let share_access: Option<u32> = parser.try_parse("ShareAccess").ok();
+            let desired_access: Option<u32> = parser.try_parse("DesiredAccess").ok();
+            let access = match (desired_access, share_access) {
+                (Some(desired), Some(share)) => {
+                    format!(
+                        "{}, {}",
+                        file_access_string(desired),
+                        share_access_string(share)
+                    )
+                }
+                (Some(desired), None) => file_access_string(desired),
+                (None, Some(share)) => share_access_string(share),
+                (None, None) => String::new(),
+            };
+            SyscallArgs {
+                path,
+                access,
+                disposition: create_options
+                    .map(|options| file_disposition(options).to_string())
+                    .unwrap_or_default(),
+                ..Default::default()
+            }
+        }
+        SyscallKind::RegistryOpen | SyscallKind::RegistryCreate => {
+            let mut path = string_field(&["KeyName"]);
+            if path.is_empty() {
+                let base_name = string_field(&["BaseName"]);
+                let relative_name = string_field(&["RelativeName"]);
+                path = match (base_name.is_empty(), relative_name.is_empty()) {
+                    (false, false) => format!("{base_name}\\{relative_name}"),
+                    (false, true) => base_name,
+                    _ => relative_name,
+                };
+            }
+            let desired_access: Option<u32> = parser.try_parse("DesiredAccess").ok();
+            let disposition: Option<u32> = parser.try_parse("Disposition").ok();
+            // NTSTATUS, which some event versions declare as signed.
+            let status: Option<u32> = parser.try_parse("Status").ok().or_else(|| {
+                let status: i32 = parser.try_parse("Status").ok()?;
+                Some(status as u32)
+            });
+            let initial_time: Option<u64> = parser.try_parse("InitialTime").ok();
+            SyscallArgs {
+                path,
+                access: desired_access
+                    .map(registry_access_string)
+                    .unwrap_or_default(),
+                disposition: disposition
+                    .and_then(registry_disposition)
+                    .unwrap_or_default()
+                    .to_string(),
+                status: status.map(status_string).unwrap_or_default(),
+                start_timestamp_raw: initial_time.filter(|time| *time != 0),
+            }
+        }
+    }
+}
+
+/// The create disposition, which is in the top byte of the `CreateOptions`
+/// of the file create events, as the `FILE_*` name without the prefix.
+fn file_disposition(create_options: u32) -> &'static str {
+    match create_options >> 24 {
+        0 => "supersede",
+        1 => "open",
+        2 => "create",
+        3 => "open_if",
+        4 => "overwrite",
+        5 => "overwrite_if",
+        _ => "unknown",
+    }
+}
+
+fn share_access_string(share_access: u32) -> String {
+    let mut modes = Vec::new();
+    for (bit, name) in [(0x1, "read"), (0x2, "write"), (0x4, "delete")] {
+        if share_access & bit != 0 {
+            modes.push(name);
+        }
+    }
+    if modes.is_empty() {
+        "share none".to_string()
+    } else {
+        format!("share {}", modes.join("|"))
+    }
+}
+
+/// The access rights which every object type has.
+const STANDARD_RIGHTS: &[(u32, &str)] = &[
+    (0x0001_0000, "DELETE"),
+    (0x0002_0000, "READ_CONTROL"),
+    (0x0004_0000, "WRITE_DAC"),
+    (0x0008_0000, "WRITE_OWNER"),
+    (0x0010_0000, "SYNCHRONIZE"),
+    (0x0100_0000, "ACCESS_SYSTEM_SECURITY"),
+    (0x0200_0000, "MAXIMUM_ALLOWED"),
+    (0x1000_0000, "GENERIC_ALL"),
+    (0x2000_0000, "GENERIC_EXECUTE"),
+    (0x4000_0000, "GENERIC_WRITE"),
+    (0x8000_0000, "GENERIC_READ"),
+];
+
+const FILE_RIGHTS: &[(u32, &str)] = &[
+    (0x001f_01ff, "FILE_ALL_ACCESS"),
+    (0x0012_0089, "FILE_GENERIC_READ"),
+    (0x0012_0116, "FILE_GENERIC_WRITE"),
+    (0x0012_00a0, "FILE_GENERIC_EXECUTE"),
+    (0x0001, "FILE_READ_DATA"),
+    (0x0002, "FILE_WRITE_DATA"),
+    (0x0004, "FILE_APPEND_DATA"),
+    (0x0008, "FILE_READ_EA"),
+    (0x0010, "FILE_WRITE_EA"),
+    (0x0020, "FILE_EXECUTE"),
+    (0x0040, "FILE_DELETE_CHILD"),
+    (0x0080, "FILE_READ_ATTRIBUTES"),
+    (0x0100, "FILE_WRITE_ATTRIBUTES"),
+];
+
+const REGISTRY_RIGHTS: &[(u32, &str)] = &[
+    (0x000f_003f, "KEY_ALL_ACCESS"),
+    (0x0002_0019, "KEY_READ"),
+    (0x0002_0006, "KEY_WRITE"),
+    (0x0001, "KEY_QUERY_VALUE"),
+    (0x0002, "KEY_SET_VALUE"),
+    (0x0004, "KEY_CREATE_SUB_KEY"),
+    (0x0008, "KEY_ENUMERATE_SUB_KEYS"),
+    (0x0010, "KEY_NOTIFY"),
+    (0x0020, "KEY_CREATE_LINK"),
+    (0x0100, "KEY_WOW64_64KEY"),
+    (0x0200, "KEY_WOW64_32KEY"),
+];
+
+fn file_access_string(access_mask: u32) -> String {
+    access_mask_string(access_mask, FILE_RIGHTS)
+}
+
+fn registry_access_string(access_mask: u32) -> String {
+    access_mask_string(access_mask, REGISTRY_RIGHTS)
+}
+
+/// Formats an access mask as `|`-separated right names, e.g.
+/// "KEY_READ|KEY_SET_VALUE". The combined rights come first in the tables,
+/// so they're used instead of their individual bits where they match; they
+/// can overlap, e.g. KEY_READ and KEY_WRITE both include READ_CONTROL.
+/// Unknown bits are appended in hex.
+fn access_mask_string(access_mask: u32, specific_rights: &[(u32, &str)]) -> String {
+    let mut names = Vec::new();
+    let mut remaining = access_mask;
+    for (rights, name) in specific_rights.iter().chain(STANDARD_RIGHTS) {
+        if access_mask & rights == *rights && remaining & rights != 0 {
+            names.push(name.to_string());
+            remaining &= !rights;
+        }
+    }
+    if remaining != 0 {
+        names.push(format!("{remaining:#x}"));
+    }
+    if names.is_empty() {
+        "0".to_string()
+    } else {
+        names.join("|")
+    }
+}
+
+fn registry_disposition(disposition: u32) -> Option<&'static str> {
+    match disposition {
+        1 => Some("created"),
+        2 => Some("opened"),
+        _ => None,
+    }
+}
+
+/// The NTSTATUS of a call, in hex, or empty for STATUS_SUCCESS.
+fn status_string(status: u32) -> String {
+    match status {
+        0 => String::new(),
+        0xc000_0034 => "STATUS_OBJECT_NAME_NOT_FOUND".to_string(),
+        0xc000_0022 => "STATUS_ACCESS_DENIED".to_string(),
+        status => format!("{status:#010x}"),
+    }
+}
+
+/// A marker for a file create or registry key open, on the calling thread.
+#[derive(Debug, Clone)]
+pub struct SyscallMarker {
+    pub name: StringHandle,
+    pub path: StringHandle,
+    pub access: StringHandle,
+    pub disposition: StringHandle,
+    pub status: StringHandle,
+    pub category: CategoryHandle,
+}
+
+impl StaticSchemaMarker for SyscallMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Syscall";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.path}".into()),
+            tooltip_label: Some("{marker.name} {marker.data.path}".into()),
+            table_label: Some(
+                "{marker.data.path} ({marker.data.access}) {marker.data.disposition} {marker.data.status}"
+                    .into(),
+            ),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "path".into(),
+                    label: "Path".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "access".into(),
+                    label: "Access".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "disposition".into(),
+                    label: "Disposition".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "status".into(),
+                    label: "Status".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "A file create or registry key open. The stack is where the call was made, if the trace has stacks for these events.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.category
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.path,
+            1 => self.access,
+            2 => self.disposition,
+            3 => self.status,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decodes_event_names_and_arguments() {
+        assert_eq!(
+            SyscallKind::from_event_name("MSNT_SystemTrace/FileIo/Create"),
+            Some(SyscallKind::FileCreate)
+        );
+        assert_eq!(
+            SyscallKind::from_event_name("Microsoft-Windows-Kernel-Registry/OpenKey/win:Info"),
+            Some(SyscallKind::RegistryOpen)
+        );
+        assert_eq!(
+            SyscallKind::from_event_name("MSNT_SystemTrace/FileIo/FltPreOpCompletion"),
+            None
+        );
+
+        // FILE_OPEN_IF | FILE_NON_DIRECTORY_FILE
+        assert_eq!(file_disposition(0x0300_0040), "open_if");
+        assert_eq!(share_access_string(0x3), "share read|write");
+        assert_eq!(share_access_string(0), "share none");
+
+        assert_eq!(registry_access_string(0x2_0019), "KEY_READ");
+        assert_eq!(
+            registry_access_string(0x2_0019 | 0x2 | 0x100),
+            "KEY_READ|KEY_SET_VALUE|KEY_WOW64_64KEY"
+        );
+        assert_eq!(registry_access_string(0x2_001f), "KEY_READ|KEY_WRITE");
+        assert_eq!(registry_access_string(0x8000_0000), "GENERIC_READ");
+        assert_eq!(
+            file_access_string(0x0012_0089 | 0x0400_0000),
+            "FILE_GENERIC_READ|0x4000000"
+        );
+        assert_eq!(status_string(0), "");
+        assert_eq!(status_string(0xc000_000d), "0xc000000d");
+    }
+}
@@ -0,0 +1,190 @@
+//! Scheduling state timelines for `--thread-states`: Each thread gets a marker
+//! for every interval in which it was running, ready to run but waiting for a
+//! CPU, or blocked. The transitions come from CSwitch and ReadyThread events.
+
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    Running,
+    /// Runnable, but waiting to be scheduled onto a CPU.
+    Ready,
+    /// Waiting for something, e.g. for I/O, a lock or a timer.
+    Blocked,
+}
+
+impl ThreadState {
+    pub fn name(self) -> &'static str {
+        match self {
+            ThreadState::Running => "Running",
+            ThreadState::Ready => "Ready",
+            ThreadState::Blocked => "Blocked",
+        }
+    }
+
+    /// The state of a thread which was switched out, from the `OldThreadState`
+    /// field of a CSwitch event. This is a `KTHREAD_STATE` value.
+    pub fn from_switched_out_state(kthread_state: i8) -> Self {
+        match kthread_state {
+            // Ready | Standby | DeferredReady
+            1 | 3 | 7 => ThreadState::Ready,
+            _ => ThreadState::Blocked,
+        }
+    }
+}
+
+/// A finished interval during which a thread was in one state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadStateInterval {
+    pub state: ThreadState,
+    pub wait_reason: Option<&'static str>,
+    pub start_raw: u64,
+    pub end_raw: u64,
+}
+
+/// The current state of one thread.
+#[derive(Debug, Default)]
+pub struct ThreadStateTracker {
+    current: Option<(ThreadState, Option<&'static str>, u64)>,
+}
+
+impl ThreadStateTracker {
+    /// Switches to `state` at `timestamp_raw`, and returns the interval of the
+    /// previous state. The first transition of a thread doesn't have a
+    /// previous interval, because we don't know when that state began.
+    pub fn transition(
+        &mut self,
+        state: ThreadState,
+        wait_reason: Option<&'static str>,
+        timestamp_raw: u64,
+    ) -> Option<ThreadStateInterval> {
+        let previous = self.current.replace((state, wait_reason, timestamp_raw));
+        let (previous_state, previous_wait_reason, start_raw) = previous?;
+        if previous_state == state && previous_wait_reason == wait_reason {
+            // Nothing changed, keep the interval going.
+            self.current = Some((previous_state, previous_wait_reason, start_raw));
+            return None;
+        }
+        Some(ThreadStateInterval {
+            state: previous_state,
+            wait_reason: previous_wait_reason,
+            start_raw,
+            end_raw: timestamp_raw,
+        })
+    }
+
+    pub fn state(&self) -> Option<ThreadState> {
+        self.current.map(|(state, _, _)| state)
+    }
+
+    /// Ends the current interval, at the end of the thread or of the profile.
+    pub fn finish(&mut self, end_raw: u64) -> Option<ThreadStateInterval> {
+        let (state, wait_reason, start_raw) = self.current.take()?;
+        Some(ThreadStateInterval {
+            state,
+            wait_reason,
+            start_raw,
+            end_raw: end_raw.max(start_raw),
+        })
+    }
+}
+
+/// The name of a `KWAIT_REASON` value, from the `OldThreadWaitReason` field
+/// of a CSwitch event.
+pub fn wait_reason_name(wait_reason: i8) -> &'static str {
+    const NAMES: [&str; 38] = [
+        "Executive",
+        "FreePage",
+        "PageIn",
+        "PoolAllocation",
+        "DelayExecution",
+        "Suspended",
+        "UserRequest",
+        "WrExecutive",
+        "WrFreePage",
+        "WrPageIn",
+        "WrPoolAllocation",
+        "WrDelayExecution",
+        "WrSuspended",
+        "WrUserRequest",
+        "WrEventPair",
+        "WrQueue",
+        "WrLpcReceive",
+        "WrLpcReply",
+        "WrVirtualMemory",
+        "WrPageOut",
+        "WrRendezvous",
+        "WrKeyedEvent",
+        "WrTerminated",
+        "WrProcessInSwap",
+        "WrCpuRateControl",
+        "WrCalloutStack",
+        "WrKernel",
+        "WrResource",
+        "WrPushLock",
+        "WrMutex",
+        "WrQuantumEnd",
+        "WrDispatchInt",
+        "WrPreempted",
+        "WrYieldExecution",
+        "WrFastMutex",
+        "WrGuardedMutex",
+        "WrRundown",
+        "WrAlertByThreadId",
+    ];
+    usize::try_from(wait_reason)
+        .ok()
+        .and_then(|index| NAMES.get(index))
+        .copied()
+        .unwrap_or("Unknown")
+}
+
+#[derive(Debug, Clone)]
+pub struct ThreadStateMarker {
+    pub state: ThreadState,
+    pub wait_reason: StringHandle,
+    pub category: CategoryHandle,
+}
+
+impl StaticSchemaMarker for ThreadStateMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "ThreadState";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.name}".into()),
+            tooltip_label: Some("{marker.name} {marker.data.waitReason}".into()),
+            table_label: Some("{marker.name} {marker.data.waitReason}".into()),
+            fields: vec![MarkerFieldSchema {
+                key: "waitReason".into(),
+                label: "Wait reason".into(),
+                format: MarkerFieldFormat::String,
+                searchable: true,
+            }],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "Whether the thread was running, ready to run but waiting for a CPU, or blocked.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string(self.state.name())
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.category
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.wait_reason
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
@@ -0,0 +1,84 @@
+//! Wakeup markers for `--wakeups`: which thread readied which. A ReadyThread
+//! event is logged on the thread which unblocked another thread, e.g. by
+//! releasing a lock or by completing an I/O request, so its header has the
+//! waker and its payload has the woken thread. `samply export wakegraph` turns
+//! these markers into a graph of the wakeups between threads.
+
+use fxprof_processed_profile::{
+    CategoryHandle, MarkerFieldFormat, MarkerFieldSchema, MarkerLocation, MarkerSchema,
+    MarkerStaticField, Profile, StaticSchemaMarker, StringHandle,
+};
+
+/// The name of the waker when a thread is readied by an interrupt or a DPC on
+/// an idle CPU, e.g. when a timer expires.
+pub const IDLE_WAKER_NAME: &str = "Idle (interrupt or DPC)";
+
+/// An instant marker on the woken thread, at the time at which it was readied.
+#[derive(Debug, Clone)]
+pub struct WakeupMarker {
+    pub waker_pid: u32,
+    pub waker_tid: u32,
+    pub waker_name: StringHandle,
+    pub category: CategoryHandle,
+}
+
+impl StaticSchemaMarker for WakeupMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Wakeup";
+
+    fn schema() -> MarkerSchema {
+        MarkerSchema {
+            type_name: Self::UNIQUE_MARKER_TYPE_NAME.into(),
+            locations: vec![MarkerLocation::MarkerChart, MarkerLocation::MarkerTable],
+            chart_label: Some("{marker.data.waker}".into()),
+            tooltip_label: Some("Woken by {marker.data.waker}".into()),
+            table_label: Some(
+                "Woken by {marker.data.waker} (pid {marker.data.wakerPid}, tid {marker.data.wakerTid})"
+                    .into(),
+            ),
+            fields: vec![
+                MarkerFieldSchema {
+                    key: "waker".into(),
+                    label: "Waker".into(),
+                    format: MarkerFieldFormat::String,
+                    searchable: true,
+                },
+                MarkerFieldSchema {
+                    key: "wakerPid".into(),
+                    label: "Waker pid".into(),
+                    format: MarkerFieldFormat::Integer,
+                    searchable: false,
+                },
+                MarkerFieldSchema {
+                    key: "wakerTid".into(),
+                    label: "Waker tid".into(),
+                    format: MarkerFieldFormat::Integer,
+                    searchable: false,
+                },
+            ],
+            static_fields: vec![MarkerStaticField {
+                label: "Description".into(),
+                value: "Another thread made this thread ready to run, e.g. by releasing a lock or completing an I/O request.".into(),
+            }],
+        }
+    }
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.intern_string("Wakeup")
+    }
+
+    fn category(&self, _profile: &mut Profile) -> CategoryHandle {
+        self.category
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.waker_name
+    }
+
+    fn number_field_value(&self, field_index: u32) -> f64 {
+        match field_index {
+            1 => self.waker_pid.into(),
+            2 => self.waker_tid.into(),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -12,7 +12,9 @@ use windows::Win32::Security::{
 };
 use windows::Win32::Storage::FileSystem::QueryDosDeviceW;
 use windows::Win32::System::ProcessStatus::{EnumDeviceDrivers, GetDeviceDriverFileNameW};
-use windows::Win32::System::SystemInformation::GetSystemDirectoryW;
+use windows::Win32::System::SystemInformation::{
+    CpuSetInformation, GetSystemCpuSetInformation, GetSystemDirectoryW, SYSTEM_CPU_SET_INFORMATION,
+};
 use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
 
 pub fn is_elevated() -> bool {
@@ -207,3 +209,52 @@ pub fn iter_kernel_drivers() -> impl Iterator<Item = (String, u64, u64)> {
         })
     }
 }
+
+/// Returns the `EfficiencyClass` of each logical processor, by the system-wide
+/// processor index which ETW events use. A higher class means a faster core.
+/// The index counts the processors of all processor groups in order.
+pub fn cpu_efficiency_classes() -> Vec<(u32, u32)> {
+    unsafe {
+        let mut len = 0;
+        let _ = GetSystemCpuSetInformation(None, 0, &mut len, HANDLE::default(), 0);
+        // The entries contain u64 fields, so the buffer needs to be aligned for them.
+        let mut buffer = vec![0u64; (len as usize).div_ceil(size_of::<u64>())];
+        if len == 0
+            || !GetSystemCpuSetInformation(
+                Some(buffer.as_mut_ptr().cast::<SYSTEM_CPU_SET_INFORMATION>()),
+                len,
+                &mut len,
+                HANDLE::default(),
+                0,
+            )
+            .as_bool()
+        {
+            return Vec::new();
+        }
+
+        let mut processors = Vec::new();
+        let mut offset = 0;
+        while offset + size_of::<SYSTEM_CPU_SET_INFORMATION>() <= len as usize {
+            let info = &*buffer
+                .as_ptr()
+                .cast::<u8>()
+                .add(offset)
+                .cast::<SYSTEM_CPU_SET_INFORMATION>();
+            if info.Size == 0 {
+                break;
+            }
+            if info.Type == CpuSetInformation {
+                let cpu_set = info.Anonymous.CpuSet;
+                let processor = (cpu_set.Group, cpu_set.LogicalProcessorIndex);
+                processors.push((processor, u32::from(cpu_set.EfficiencyClass)));
+            }
+            offset += info.Size as usize;
+        }
+        processors.sort_unstable();
+        processors
+            .into_iter()
+            .enumerate()
+            .map(|(index, (_processor, class))| (index as u32, class))
+            .collect()
+    }
+}
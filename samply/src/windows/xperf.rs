@@ -3,6 +3,7 @@ use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
 use super::elevated_helper::ElevatedRecordingProps;
+use crate::shared::recording_props::StackWalkEvent;
 
 const XPERF_NOT_FOUND_ERROR_MSG: &str = "\
 Could not find an xperf installation.\n\
@@ -19,6 +20,7 @@ enum XperfState {
     Stopped,
     RecordingKernelToFile(PathBuf),
     RecordingKernelAndUserToFile(PathBuf, PathBuf),
+    RecordingUserToFile(PathBuf),
 }
 
 impl Xperf {
@@ -32,7 +34,9 @@ impl Xperf {
     pub fn is_running(&self) -> bool {
         matches!(
             &self.state,
-            XperfState::RecordingKernelToFile(_) | XperfState::RecordingKernelAndUserToFile(_, _)
+            XperfState::RecordingKernelToFile(_)
+                | XperfState::RecordingKernelAndUserToFile(_, _)
+                | XperfState::RecordingUserToFile(_)
         )
     }
 
@@ -51,28 +55,15 @@ impl Xperf {
         props: &ElevatedRecordingProps,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         if self.is_running() {
-            let _ = self.stop_xperf();
+            let _ = self.stop();
         }
 
-        // All the user providers need to be specified in a single `-on` argument
-        // with "+" in between.
-        let mut user_providers = vec![];
-
-        user_providers.append(&mut super::coreclr::coreclr_xperf_args(props));
-        user_providers.append(&mut super::gfx::gfx_xperf_args(props));
-        user_providers.append(&mut super::firefox::firefox_xperf_args(props));
-        user_providers.append(&mut super::chrome::chrome_xperf_args(props));
-        user_providers.sort_unstable();
-        user_providers.dedup();
+        let user_providers = user_providers(props);
 
         let xperf_path = self.get_xperf_path()?;
         // start xperf.exe, logging to the same location as the output file, just with a .etl
         // extension.
-        let mut kernel_etl_file = output_path.to_owned();
-        if kernel_etl_file.extension() == Some(OsStr::new("gz")) {
-            kernel_etl_file.set_extension("");
-        }
-        kernel_etl_file.set_extension("kernel.etl");
+        let kernel_etl_file = etl_file_path(output_path, "kernel.etl");
 
         const MIN_INTERVAL_NANOS: u64 = 122100; // 8192 kHz
         let interval_nanos = props.interval_nanos.clamp(MIN_INTERVAL_NANOS, u64::MAX);
@@ -82,14 +73,62 @@ impl Xperf {
         let mut xperf = std::process::Command::new(xperf_path);
         xperf.arg("-SetProfInt");
         xperf.arg(interval_ticks.to_string());
+        if let Some(source) = &props.pmc_sampling_source {
+            xperf.arg("-SetProfInt");
+            xperf.arg(source);
+            xperf.arg(props.pmc_sampling_interval.to_string());
+        }
+
+        // With --pmc-sampling, the samples are PmcInterrupt events instead of
+        // the timer's SampleProf events.
+        let kernel_flag_and_stack_walk_name = |event: StackWalkEvent| match event {
+            StackWalkEvent::Profile if props.pmc_sampling_source.is_some() => {
+                ("PMC_PROFILE", "PmcInterrupt")
+            }
+            event => (event.kernel_flag(), event.xperf_name()),
+        };
 
         // Virtualised ARM64 Windows crashes out on PROFILE tracing, so this hidden
         // hack argument lets things still continue to run for development of samply.
         xperf.arg("-on");
         if !props.vm_hack {
-            xperf.arg("PROC_THREAD+LOADER+PROFILE+CSWITCH");
-            xperf.arg("-stackwalk");
-            xperf.arg("PROFILE+CSWITCH");
+            let (sample_flag, _) = kernel_flag_and_stack_walk_name(StackWalkEvent::Profile);
+            let mut kernel_flags = format!("PROC_THREAD+LOADER+{sample_flag}+CSWITCH");
+            if let Some(antivirus_flags) = super::antivirus::antivirus_kernel_flags(props) {
+                kernel_flags.push('+');
+                kernel_flags.push_str(antivirus_flags);
+            }
+            if props.thread_states || props.scheduler_latency || props.wakeups {
+                // For ReadyThread events.
+                kernel_flags.push_str("+DISPATCHER");
+            }
+            for event in &props.stack_walk_events {
+                let (flag, _) = kernel_flag_and_stack_walk_name(*event);
+                if !kernel_flags.split('+').any(|f| f == flag) {
+                    kernel_flags.push('+');
+                    kernel_flags.push_str(flag);
+                }
+            }
+            xperf.arg(kernel_flags);
+            if !props.stack_walk_events.is_empty() {
+                let stack_walk_events: Vec<&str> = props
+                    .stack_walk_events
+                    .iter()
+                    .map(|event| kernel_flag_and_stack_walk_name(*event).1)
+                    .collect();
+                xperf.arg("-stackwalk");
+                xperf.arg(stack_walk_events.join("+"));
+            }
+            if !props.pmc_counters.is_empty() {
+                // Read the counters whenever a PROFILE event is logged.
+                xperf.arg("-pmc");
+                xperf.arg(props.pmc_counters.join(","));
+                xperf.arg("PROFILE");
+            }
+            if let Some(source) = &props.pmc_sampling_source {
+                xperf.arg("-PmcProfile");
+                xperf.arg(source);
+            }
         } else {
             // virtualized arm64 hack, to give us enough interesting events
             xperf.arg("PROC_THREAD+LOADER+CSWITCH+SYSCALL+VIRT_ALLOC+OB_HANDLE");
@@ -100,11 +139,7 @@ impl Xperf {
         xperf.arg(&kernel_etl_file);
 
         let user_etl_file = if !user_providers.is_empty() {
-            let mut user_etl_file = output_path.to_owned();
-            if user_etl_file.extension() == Some(OsStr::new("gz")) {
-                user_etl_file.set_extension("");
-            }
-            user_etl_file.set_extension("user.etl");
+            let user_etl_file = etl_file_path(output_path, "user.etl");
 
             xperf.arg("-start");
             xperf.arg("SamplySession");
@@ -134,19 +169,80 @@ impl Xperf {
         Ok(())
     }
 
+    /// Starts only the session for the user-mode providers, without the kernel
+    /// logger. Unlike `start_xperf`, this doesn't need administrator rights, just
+    /// membership in the "Performance Log Users" group.
+    pub fn start_user_session(
+        &mut self,
+        output_path: &Path,
+        props: &ElevatedRecordingProps,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.is_running() {
+            let _ = self.stop();
+        }
+
+        let user_providers = user_providers(props);
+        if user_providers.is_empty() {
+            return Err("No user-mode providers are enabled. Use --coreclr, --browsers, --gfx, --antivirus or --provider to enable some.".into());
+        }
+
+        let xperf_path = self.get_xperf_path()?;
+        let user_etl_file = etl_file_path(output_path, "user.etl");
+
+        let mut xperf = std::process::Command::new(xperf_path);
+        xperf.arg("-start");
+        xperf.arg("SamplySession");
+        xperf.arg("-on");
+        xperf.arg(user_providers.join("+"));
+        xperf.arg("-f");
+        xperf.arg(&user_etl_file);
+
+        let status = xperf.status().expect("failed to execute xperf");
+        if !status.success() {
+            return Err(format!(
+                "xperf could not start the user-mode session ({status}). Is the current user a member of the \"Performance Log Users\" group?"
+            )
+            .into());
+        }
+
+        eprintln!("xperf user-mode session running...");
+        self.state = XperfState::RecordingUserToFile(user_etl_file);
+
+        Ok(())
+    }
+
+    /// Stops the session started by `start_user_session` and returns the path
+    /// of the ETL file.
+    pub fn stop_user_session(&mut self) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+        match self.stop()? {
+            (None, Some(user_etl)) => Ok(user_etl),
+            _ => Err("xperf was running a kernel session, not a user-mode session".into()),
+        }
+    }
+
     pub fn stop_xperf(
         &mut self,
     ) -> Result<(PathBuf, Option<PathBuf>), Box<dyn Error + Send + Sync>> {
+        match self.stop()? {
+            (Some(kernel_etl), user_etl) => Ok((kernel_etl, user_etl)),
+            _ => Err("xperf was running a user-mode session, not a kernel session".into()),
+        }
+    }
+
+    fn stop(&mut self) -> Result<(Option<PathBuf>, Option<PathBuf>), Box<dyn Error + Send + Sync>> {
         let prev_state = std::mem::replace(&mut self.state, XperfState::Stopped);
         let (kernel_etl, user_etl) = match prev_state {
             XperfState::Stopped => return Err("xperf wasn't running, can't stop it".into()),
-            XperfState::RecordingKernelToFile(kpath) => (kpath, None),
-            XperfState::RecordingKernelAndUserToFile(kpath, upath) => (kpath, Some(upath)),
+            XperfState::RecordingKernelToFile(kpath) => (Some(kpath), None),
+            XperfState::RecordingKernelAndUserToFile(kpath, upath) => (Some(kpath), Some(upath)),
+            XperfState::RecordingUserToFile(upath) => (None, Some(upath)),
         };
 
         let xperf_path = self.get_xperf_path()?;
         let mut xperf = std::process::Command::new(xperf_path);
-        xperf.arg("-stop");
+        if kernel_etl.is_some() {
+            xperf.arg("-stop");
+        }
 
         if user_etl.is_some() {
             xperf.arg("-stop");
@@ -166,6 +262,45 @@ impl Xperf {
 impl Drop for Xperf {
     fn drop(&mut self) {
         // we should probably xperf -cancel here instead of doing the merge on drop...
-        let _ = self.stop_xperf();
+        let _ = self.stop();
+    }
+}
+
+/// All the user providers need to be specified in a single `-on` argument
+/// with "+" in between.
+fn user_providers(props: &ElevatedRecordingProps) -> Vec<String> {
+    let mut user_providers = vec![];
+
+    user_providers.append(&mut super::coreclr::coreclr_xperf_args(props));
+    user_providers.append(&mut super::gfx::gfx_xperf_args(props));
+    user_providers.append(&mut super::firefox::firefox_xperf_args(props));
+    user_providers.append(&mut super::chrome::chrome_xperf_args(props));
+    user_providers.append(&mut super::kernel_process::kernel_process_xperf_args(props));
+    user_providers.append(&mut super::antivirus::antivirus_xperf_args(props));
+    user_providers.append(&mut super::hangs::hangs_xperf_args(props));
+    user_providers.extend(props.etw_providers.iter().map(|p| p.xperf_arg()));
+    user_providers.sort_unstable();
+    user_providers.dedup();
+    user_providers
+}
+
+/// The combined size of the ETL files which are being written for the output
+/// file at `output_path`.
+pub fn recorded_etl_size(output_path: &Path) -> u64 {
+    ["kernel.etl", "user.etl"]
+        .into_iter()
+        .filter_map(|extension| std::fs::metadata(etl_file_path(output_path, extension)).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Returns the output path with a .etl extension, e.g. "profile.kernel.etl"
+/// for "profile.json.gz".
+fn etl_file_path(output_path: &Path, extension: &str) -> PathBuf {
+    let mut etl_file = output_path.to_owned();
+    if etl_file.extension() == Some(OsStr::new("gz")) {
+        etl_file.set_extension("");
     }
+    etl_file.set_extension(extension);
+    etl_file
 }
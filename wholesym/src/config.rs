@@ -1,7 +1,8 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-use symsrv::{parse_nt_symbol_path, NtSymbolPathEntry};
+use samply_symbols::SourcePathSubstitution;
+use symsrv::{parse_nt_symbol_path, CachePath, NtSymbolPathEntry};
 
 /// The configuration of a [`SymbolManager`](crate::SymbolManager).
 ///
@@ -22,6 +23,12 @@ pub struct SymbolManagerConfig {
     pub(crate) debuginfod_servers: Vec<(String, PathBuf)>,
     pub(crate) extra_symbol_directories: Vec<PathBuf>,
     pub(crate) simpleperf_binary_cache_directories: Vec<PathBuf>,
+    pub(crate) source_path_substitutions: Vec<SourcePathSubstitution>,
+    pub(crate) source_url_headers: Vec<(String, String, String)>,
+    pub(crate) strip_function_parameters: bool,
+    pub(crate) full_function_signatures: bool,
+    pub(crate) skip_debug_files_patterns: Vec<String>,
+    pub(crate) offline: bool,
 }
 
 impl SymbolManagerConfig {
@@ -191,4 +198,111 @@ impl SymbolManagerConfig {
         self.simpleperf_binary_cache_directories.push(dir.into());
         self
     }
+
+    /// Replace the `from` prefix in the paths of source files with `to`, for
+    /// binaries which were built on a different machine. This makes the source
+    /// view find the files in a local checkout. Rules are tried in the order in
+    /// which they were added.
+    pub fn source_path_substitution(
+        mut self,
+        from: impl Into<String>,
+        to: impl Into<String>,
+    ) -> Self {
+        self.source_path_substitutions
+            .push(SourcePathSubstitution::new(from, to));
+        self
+    }
+
+    /// Send the HTTP header `name: value` when downloading source files whose
+    /// URL starts with `url_prefix`, e.g. to authenticate to a private GitHub
+    /// or Azure DevOps repository with `Authorization: Bearer <token>`. Source
+    /// files are downloaded from the URLs in the srcsrv or SourceLink information
+    /// of PDBs, when the files aren't found locally.
+    pub fn source_url_header(
+        mut self,
+        url_prefix: impl Into<String>,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.source_url_headers
+            .push((url_prefix.into(), name.into(), value.into()));
+        self
+    }
+
+    /// Whether to remove the parameter lists from function names, e.g. to
+    /// turn `Foo::Bar(int) const` into `Foo::Bar`. Off by default; the full
+    /// signatures distinguish C++ overloads.
+    pub fn strip_function_parameters(mut self, strip: bool) -> Self {
+        self.strip_function_parameters = strip;
+        self
+    }
+
+    /// Whether to add the parameter types from DWARF debug info to function
+    /// names which don't have them, e.g. to turn the C function `add` into
+    /// `add(int, long)`. Names from mangled symbols and from PDBs already have
+    /// the parameter types. Off by default.
+    pub fn full_function_signatures(mut self, full_signatures: bool) -> Self {
+        self.full_function_signatures = full_signatures;
+        self
+    }
+
+    /// Don't look for debug files, i.e. PDBs, dSYMs, separate debug files and
+    /// files on symbol servers, for libraries whose file name or debug file
+    /// name contains `pattern`, ignoring case. These libraries only get the
+    /// symbols from the binary itself, e.g. from the export table of a DLL.
+    /// This trades detail for speed for huge libraries with multi-gigabyte
+    /// debug files.
+    pub fn skip_debug_files_for(mut self, pattern: impl Into<String>) -> Self {
+        self.skip_debug_files_patterns
+            .push(pattern.into().to_lowercase());
+        self
+    }
+
+    /// Don't make any network requests. Symbol servers, debuginfod servers and
+    /// source file URLs are skipped; only local files are used, including the
+    /// files which are already in the caches of the configured servers. Skipped
+    /// requests fail with an error which says what was skipped.
+    ///
+    /// With the `offline` crate feature, this is always on, for builds which
+    /// must not access the network at all.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// The directories which a symbol manager with this config writes to: the
+    /// caches of the symbol servers, including the ones from the Windows symbol
+    /// path, and the breakpad symindex cache. A sandbox can restrict writes to
+    /// these directories.
+    pub fn cache_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        for entry in self.effective_nt_symbol_path().unwrap_or_default() {
+            if let NtSymbolPathEntry::Chain { cache_paths, .. } = entry {
+                for cache_path in cache_paths {
+                    match cache_path {
+                        CachePath::Path(path) => dirs.push(path),
+                        CachePath::DefaultDownstreamStore => {
+                            dirs.extend(symsrv::get_home_sym_dir())
+                        }
+                    }
+                }
+            }
+        }
+        let servers = self.breakpad_servers.iter().chain(&self.debuginfod_servers);
+        dirs.extend(servers.map(|(_, cache_dir)| cache_dir.clone()));
+        dirs.extend(self.breakpad_symindex_cache_dir.clone());
+        dirs.extend(self.debuginfod_cache_dir_if_not_installed.clone());
+        dirs
+    }
+
+    pub(crate) fn is_offline(&self) -> bool {
+        cfg!(feature = "offline") || self.offline
+    }
+
+    pub(crate) fn skips_debug_files_for(&self, file_name: &str) -> bool {
+        let file_name = file_name.to_lowercase();
+        self.skip_debug_files_patterns
+            .iter()
+            .any(|pattern| file_name.contains(pattern.as_str()))
+    }
 }
@@ -38,7 +38,6 @@ impl DebuginfodSymbolCache {
         }
     }
 
-    #[allow(unused)]
     pub async fn get_file_only_cached(&self, buildid: &str, file_type: &str) -> Option<PathBuf> {
         match &self.0 {
             DebuginfodSymbolCacheInner::Official(official) => {
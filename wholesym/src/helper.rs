@@ -8,7 +8,7 @@ use debugid::DebugId;
 use samply_symbols::{
     BreakpadIndex, BreakpadIndexParser, CandidatePathInfo, CodeId, ElfBuildId, FileAndPathHelper,
     FileAndPathHelperResult, FileLocation, LibraryInfo, OptionallySendFuture, PeCodeId,
-    SymbolMapTrait,
+    SourcePathSubstitution, SymbolMapTrait,
 };
 use symsrv::{SymsrvDownloader, SymsrvObserver};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -125,6 +125,19 @@ impl FileLocation for WholesymFileLocation {
         }
     }
 
+    fn location_for_source_url(&self, url: &str) -> Option<Self> {
+        // These URLs come from the srcsrv or SourceLink information of the debug
+        // file, and point to the exact revision of the file that was compiled.
+        // This also applies to debug files from symbol servers, which the
+        // Firefox Profiler already fetches these URLs for. Only HTTPS is allowed,
+        // because the request can carry the headers from `source_url_header`.
+        if url.starts_with("https://") {
+            Some(Self::UrlForSourceFile(url.to_owned()))
+        } else {
+            None
+        }
+    }
+
     fn location_for_breakpad_symindex(&self) -> Option<Self> {
         match self {
             Self::BreakpadSymbolServerFile(rel_path) | Self::LocalBreakpadFile(_, rel_path) => {
@@ -325,12 +338,30 @@ impl Helper {
         precog_symbol_data.insert(debug_id, symbol_map);
     }
 
+    /// Whether the library matches one of the patterns from
+    /// [`SymbolManagerConfig::skip_debug_files_for`].
+    fn skips_debug_files(&self, info: &LibraryInfo) -> bool {
+        [&info.name, &info.debug_name]
+            .into_iter()
+            .flatten()
+            .any(|name| self.config.skips_debug_files_for(name))
+    }
+
     async fn load_file_impl(
         &self,
         location: WholesymFileLocation,
     ) -> FileAndPathHelperResult<WholesymFileContents> {
         match location {
             WholesymFileLocation::LocalFile(path) => {
+                // PDBs are also opened from the path in the binary, without
+                // going through get_candidate_paths_for_debug_file.
+                let is_pdb = path
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("pdb"));
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                if is_pdb && self.config.skips_debug_files_for(&file_name) {
+                    return Err(format!("Skipped debug file {path:?}").into());
+                }
                 if self.config.verbose {
                     eprintln!("Opening file {:?}", path.to_string_lossy());
                 }
@@ -367,10 +398,19 @@ impl Helper {
                 }))
             }
             WholesymFileLocation::UrlForSourceFile(url) => {
+                if self.config.is_offline() {
+                    return Err(format!("Offline: skipped downloading source file {url}").into());
+                }
                 if self.config.verbose {
                     eprintln!("Trying to get file {url} from a URL");
                 }
-                let bytes = reqwest::get(&url).await?.bytes().await?;
+                let mut request = reqwest::Client::new().get(&url);
+                for (url_prefix, name, value) in &self.config.source_url_headers {
+                    if url.starts_with(url_prefix.as_str()) {
+                        request = request.header(name.as_str(), value.as_str());
+                    }
+                }
+                let bytes = request.send().await?.error_for_status()?.bytes().await?;
                 Ok(WholesymFileContents::Bytes(bytes))
             }
             WholesymFileLocation::SymsrvFile(filename, hash) => {
@@ -379,17 +419,32 @@ impl Helper {
                         "Trying to get file {filename} {hash} from symbol cache (download allowed)"
                     );
                 }
-                let file_path = self
-                    .symsrv_downloader
-                    .as_ref()
-                    .unwrap()
-                    .get_file(&filename, &hash)
-                    .await?;
+                let downloader = self.symsrv_downloader.as_ref().unwrap();
+                let file_path = if self.config.is_offline() {
+                    downloader
+                        .get_file_no_download(&filename, &hash)
+                        .await
+                        .map_err(|e| {
+                            format!(
+                                "Offline: skipped downloading {filename} {hash} \
+                                 from a symbol server, and it's not in the cache: {e}"
+                            )
+                        })?
+                } else {
+                    downloader.get_file(&filename, &hash).await?
+                };
                 Ok(WholesymFileContents::Mmap(unsafe {
                     memmap2::MmapOptions::new().map(&File::open(file_path)?)?
                 }))
             }
             WholesymFileLocation::BreakpadSymbolServerFile(path) => {
+                if self.config.is_offline() {
+                    // The cached copy, if any, is a LocalBreakpadFile candidate.
+                    return Err(format!(
+                        "Offline: skipped downloading {path} from a breakpad symbol server"
+                    )
+                    .into());
+                }
                 if self.config.verbose {
                     eprintln!("Trying to get file {path:?} from breakpad symbol server");
                 }
@@ -409,26 +464,14 @@ impl Helper {
                 }
             }
             WholesymFileLocation::DebuginfodDebugFile(build_id) => {
-                let file_path = self
-                    .debuginfod_symbol_cache
-                    .as_ref()
-                    .unwrap()
-                    .get_file(&build_id.to_string(), "debuginfo")
-                    .await
-                    .ok_or("Debuginfod could not find debuginfo")?;
+                let file_path = self.get_debuginfod_file(&build_id).await?;
 
                 Ok(WholesymFileContents::Mmap(unsafe {
                     memmap2::MmapOptions::new().map(&File::open(file_path)?)?
                 }))
             }
             WholesymFileLocation::DebuginfodExecutable(build_id) => {
-                let file_path = self
-                    .debuginfod_symbol_cache
-                    .as_ref()
-                    .unwrap()
-                    .get_file(&build_id.to_string(), "debuginfo")
-                    .await
-                    .ok_or("Debuginfod could not find debuginfo")?;
+                let file_path = self.get_debuginfod_file(&build_id).await?;
 
                 Ok(WholesymFileContents::Mmap(unsafe {
                     memmap2::MmapOptions::new().map(&File::open(file_path)?)?
@@ -450,6 +493,27 @@ impl Helper {
         }
     }
 
+    async fn get_debuginfod_file(&self, build_id: &ElfBuildId) -> FileAndPathHelperResult<PathBuf> {
+        let debuginfod_symbol_cache = self.debuginfod_symbol_cache.as_ref().unwrap();
+        let build_id = build_id.to_string();
+        if self.config.is_offline() {
+            return debuginfod_symbol_cache
+                .get_file_only_cached(&build_id, "debuginfo")
+                .await
+                .ok_or_else(|| {
+                    format!(
+                        "Offline: skipped downloading debuginfo for {build_id} \
+                         from debuginfod, and it's not in the cache"
+                    )
+                    .into()
+                });
+        }
+        Ok(debuginfod_symbol_cache
+            .get_file(&build_id, "debuginfo")
+            .await
+            .ok_or("Debuginfod could not find debuginfo")?)
+    }
+
     async fn get_bp_sym_file(
         &self,
         rel_path: &str,
@@ -621,6 +685,17 @@ impl FileAndPathHelper for Helper {
         let mut info = library_info.clone();
         self.fill_in_library_info_details(&mut info);
 
+        if self.skips_debug_files(&info) {
+            // Only get symbols from the binary itself.
+            return Ok(info
+                .path
+                .iter()
+                .map(|path| {
+                    CandidatePathInfo::SingleFile(WholesymFileLocation::LocalFile(path.into()))
+                })
+                .collect());
+        }
+
         let mut got_dsym = false;
 
         if let (Some(debug_path), Some(debug_name)) = (&info.debug_path, &info.debug_name) {
@@ -854,6 +929,12 @@ impl FileAndPathHelper for Helper {
             }
             _ => return Err("Only local files have a .gnu_debuglink".into()),
         };
+        if let WholesymFileLocation::LocalFile(path) = original_file_location {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            if self.config.skips_debug_files_for(&file_name) {
+                return Err(format!("Skipped debug files for {path:?}").into());
+            }
+        }
 
         // https://www-zeuthen.desy.de/unix/unixguide/infohtml/gdb/Separate-Debug-Files.html
         let mut candidates = vec![
@@ -1024,6 +1105,18 @@ impl FileAndPathHelper for Helper {
         );
         Some((location, symbol_map.clone()))
     }
+
+    fn source_path_substitutions(&self) -> &[SourcePathSubstitution] {
+        &self.config.source_path_substitutions
+    }
+
+    fn strip_function_parameters(&self) -> bool {
+        self.config.strip_function_parameters
+    }
+
+    fn full_function_signatures(&self) -> bool {
+        self.config.full_function_signatures
+    }
 }
 
 /// Return a Vec containing the potential paths where a dyld shared cache